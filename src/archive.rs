@@ -0,0 +1,304 @@
+//! Archive module: package a completed size as a single zstd-compressed tar
+//!
+//! A finished size is a few hundred files (lists, state, history); moving it to
+//! cold storage is easier as one file that can be checked on the way back.
+//!
+//! Key features:
+//! - Bundles the size files, the global state and its history into
+//!   nsl_{size:02}_archive.tar.zst
+//! - Embedded manifest.json (first entry): list count, byte size and SHA-256 of
+//!   every file, and the total number of lists
+//! - --unarchive restores the files, checking every SHA-256 against the manifest
+//!   (files are extracted as .tmp and only renamed once their hash matches)
+//! - Existing files are never overwritten by a restore
+//!
+//! Used by --archive and --unarchive modes
+
+use std::io::{Read, Write};
+use std::path::Path;
+use separator::Separatable;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::file_info::GlobalFileState;
+use crate::utils::*;
+
+/// Name of the manifest inside the archive
+const MANIFEST_NAME: &str = "manifest.json";
+
+/// zstd compression level of the archives
+const ARCHIVE_ZSTD_LEVEL: i32 = 3;
+
+/// One archived file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub filename: String,
+    pub nb_lists: Option<u64>,  // None for state and history files
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+/// Manifest embedded in an archive
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveManifest {
+    pub size: u8,
+    pub created_at: String,
+    pub funny_version: String,
+    pub total_lists: u64,
+    pub files: Vec<ManifestEntry>,
+}
+
+/// Result of an archive or a restore
+#[derive(Debug, Clone)]
+pub struct ArchiveReport {
+    pub size: u8,
+    pub archive_path: String,
+    pub files: u64,
+    pub total_lists: u64,
+    pub bytes: u64,          // uncompressed bytes of the archived files
+    pub archive_bytes: u64,  // bytes of the .tar.zst
+}
+
+/// Archive filename of a size
+pub fn archive_filename(size: u8) -> String {
+    format!("nsl_{:02}_archive.tar.zst", size)
+}
+
+/// SHA-256 (hex) and byte size of a file
+pub(crate) fn hash_file(path: &Path) -> std::io::Result<(String, u64)> {
+    let mut file = std::fs::File::open(crate::storage::resolve_path(&path.to_string_lossy()))?;
+    let mut hasher = Sha256::new();
+    let bytes = std::io::copy(&mut file, &mut hasher)?;
+    Ok((format!("{:x}", hasher.finalize()), bytes))
+}
+
+/// Files of `size` in `dir`: list files first (sorted), then state and history
+fn size_files(dir: &str, size: u8) -> std::io::Result<Vec<String>> {
+    let state_prefix = format!("nsl_{:02}_global_", size);
+    let mut lists = Vec::new();
+    let mut state = Vec::new();
+    for entry in std::fs::read_dir(dir)?.flatten() {
+        if !entry.file_type().map(|t| t.is_file()).unwrap_or(false) {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if crate::filenames::parse_filename(&name).is_some_and(|p| p.target_size == size) {
+            lists.push(name);
+        } else if name.starts_with(&state_prefix) && !name.ends_with(".old") && !name.ends_with(".tmp") {
+            state.push(name);
+        }
+    }
+    lists.sort();
+    state.sort();
+    lists.extend(state);
+    Ok(lists)
+}
+
+/// Bundle the files of `size` in `input_dir` into `output_dir`/nsl_{size:02}_archive.tar.zst
+pub fn archive_size(input_dir: &str, output_dir: &str, size: u8) -> std::io::Result<ArchiveReport> {
+    test_print(&format!("\nARCHIVE MODE: Packaging size {:02}...", size));
+    test_print(&format!("   Input directory:  {}", input_dir));
+    test_print(&format!("   Output directory: {}", output_dir));
+
+    let filenames = size_files(input_dir, size)?;
+    if !filenames.iter().any(|f| crate::filenames::parse_filename(f).is_some()) {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound,
+            format!("No size {:02} files found in {}", size, input_dir)));
+    }
+
+    // Counts from the state when it has them, read from the file otherwise
+    let state = match GlobalFileState::from_sources(input_dir, size) {
+        Err(e) if crate::file_info::is_state_locked(&e) => return Err(e),
+        loaded => loaded.ok(),
+    };
+    let recorded_count = |filename: &str| state.as_ref().and_then(|s| {
+        s.entries().values().find(|e| e.filename == filename).map(|e| e.nb_lists_in_file)
+    });
+
+    // Manifest first: every file is hashed before the archive is written
+    let mut manifest = ArchiveManifest {
+        size,
+        created_at: chrono::Local::now().to_rfc3339(),
+        funny_version: env!("CARGO_PKG_VERSION").to_string(),
+        total_lists: 0,
+        files: Vec::new(),
+    };
+    for filename in filenames.iter() {
+        let path = Path::new(input_dir).join(filename);
+        let nb_lists = if crate::filenames::parse_filename(filename).is_some() {
+            Some(match recorded_count(filename) {
+                Some(count) => count,
+                None => crate::io_helpers::count_lists_in_file(&path.to_string_lossy())?,
+            })
+        } else {
+            None
+        };
+        let (sha256, bytes) = hash_file(&path)?;
+        manifest.total_lists += nb_lists.unwrap_or(0);
+        manifest.files.push(ManifestEntry { filename: filename.clone(), nb_lists, bytes, sha256 });
+    }
+    let manifest_json = serde_json::to_vec_pretty(&manifest)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+
+    std::fs::create_dir_all(output_dir)?;
+    let archive_path = Path::new(output_dir).join(archive_filename(size));
+    let tmp_path = archive_path.with_extension("zst.tmp");
+    {
+        let file = std::fs::File::create(&tmp_path)?;
+        let encoder = zstd::Encoder::new(std::io::BufWriter::new(file), ARCHIVE_ZSTD_LEVEL)?;
+        let mut builder = tar::Builder::new(encoder);
+
+        let mut header = tar::Header::new_gnu();
+        header.set_size(manifest_json.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(chrono::Utc::now().timestamp() as u64);
+        header.set_cksum();
+        builder.append_data(&mut header, MANIFEST_NAME, manifest_json.as_slice())?;
+
+        for entry in manifest.files.iter() {
+            let path = crate::storage::resolve_path(&Path::new(input_dir).join(&entry.filename).to_string_lossy());
+            builder.append_path_with_name(path, &entry.filename)?;
+            debug_print(&format!("   ... archived {}", entry.filename));
+        }
+        let encoder = builder.into_inner()?;
+        let mut writer = encoder.finish()?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+    }
+    std::fs::rename(&tmp_path, &archive_path)?;
+
+    let report = ArchiveReport {
+        size,
+        archive_path: archive_path.to_string_lossy().into_owned(),
+        files: manifest.files.len() as u64,
+        total_lists: manifest.total_lists,
+        bytes: manifest.files.iter().map(|e| e.bytes).sum(),
+        archive_bytes: std::fs::metadata(&archive_path)?.len(),
+    };
+    test_print(&format!("   ... {} files, {} lists, {:.2} GB compressed to {:.2} GB",
+        report.files, report.total_lists.separated_string(),
+        report.bytes as f64 / 1_073_741_824.0, report.archive_bytes as f64 / 1_073_741_824.0));
+    test_print(&format!("   Archive: {}", report.archive_path));
+    Ok(report)
+}
+
+/// Read the manifest at the head of an archive
+pub fn read_manifest(archive_path: &str) -> std::io::Result<ArchiveManifest> {
+    let decoder = zstd::Decoder::new(std::fs::File::open(archive_path)?)?;
+    let mut archive = tar::Archive::new(decoder);
+    let mut entries = archive.entries()?;
+    let mut first = entries.next().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData,
+        format!("{} is an empty archive", archive_path)))??;
+    if first.path()?.to_string_lossy() != MANIFEST_NAME {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+            format!("{} does not start with {}: not a funny archive", archive_path, MANIFEST_NAME)));
+    }
+    let mut json = Vec::new();
+    first.read_to_end(&mut json)?;
+    serde_json::from_slice(&json).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// Restore the files of an archive into `output_dir`, checking every SHA-256
+pub fn unarchive(archive_path: &str, output_dir: &str) -> std::io::Result<ArchiveReport> {
+    test_print(&format!("\nUNARCHIVE MODE: Restoring {}...", archive_path));
+    test_print(&format!("   Output directory: {}", output_dir));
+
+    let manifest = read_manifest(archive_path)?;
+    std::fs::create_dir_all(output_dir)?;
+    let existing: Vec<&str> = manifest.files.iter()
+        .filter(|e| Path::new(output_dir).join(&e.filename).exists())
+        .map(|e| e.filename.as_str())
+        .collect();
+    if !existing.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists,
+            format!("{} files of the archive already exist in {} (first: {}): nothing restored",
+                existing.len(), output_dir, existing[0])));
+    }
+
+    let decoder = zstd::Decoder::new(std::fs::File::open(archive_path)?)?;
+    let mut archive = tar::Archive::new(decoder);
+    let mut restored = 0u64;
+    for entry in archive.entries()?.skip(1) {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let expected = manifest.files.iter().find(|e| e.filename == name).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{} is not in the manifest", name))
+        })?;
+
+        let target = Path::new(output_dir).join(&expected.filename);
+        let tmp_path = Path::new(output_dir).join(format!("{}.tmp", expected.filename));
+        let mut hasher = Sha256::new();
+        {
+            let mut file = std::fs::File::create(&tmp_path)?;
+            let mut buffer = vec![0u8; 1 << 20];
+            loop {
+                let read = entry.read(&mut buffer)?;
+                if read == 0 {
+                    break;
+                }
+                hasher.update(&buffer[..read]);
+                file.write_all(&buffer[..read])?;
+            }
+            file.sync_all()?;
+        }
+        let sha256 = format!("{:x}", hasher.finalize());
+        if sha256 != expected.sha256 {
+            std::fs::remove_file(&tmp_path)?;
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+                format!("SHA-256 mismatch for {} ({} in the manifest, {} extracted)", name, expected.sha256, sha256)));
+        }
+        crate::io_helpers::commit_atomic_write(&tmp_path.to_string_lossy(), &target.to_string_lossy())?;
+        debug_print(&format!("   ... restored {}", name));
+        restored += 1;
+    }
+    if restored != manifest.files.len() as u64 {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+            format!("{} files restored, {} in the manifest: the archive is truncated", restored, manifest.files.len())));
+    }
+
+    let report = ArchiveReport {
+        size: manifest.size,
+        archive_path: archive_path.to_string(),
+        files: restored,
+        total_lists: manifest.total_lists,
+        bytes: manifest.files.iter().map(|e| e.bytes).sum(),
+        archive_bytes: std::fs::metadata(archive_path)?.len(),
+    };
+    test_print(&format!("   ... {} files of size {:02} restored and verified ({} lists)",
+        report.files, report.size, report.total_lists.separated_string()));
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::no_set_list::NoSetListSerialized;
+
+    #[test]
+    fn archive_round_trip_restores_identical_files() {
+        let dir = crate::test_dir::TestDir::new("archive");
+        let source = dir.join("source");
+        let restored = dir.join("restored");
+        std::fs::create_dir_all(&source).expect("create dir");
+        let source_str = source.to_string_lossy().into_owned();
+        let restored_str = restored.to_string_lossy().into_owned();
+
+        let list = NoSetListSerialized { n: 4, max_card: 5, no_set_list: vec![0, 1, 3, 5], remaining_cards_list: vec![6] };
+        let file = crate::filenames::output_filename(&source_str, 3, 0, 4, 0);
+        assert!(crate::io_helpers::save_to_file_serialized(&vec![list.clone(), list], &file));
+        let mut state = GlobalFileState::new(&source_str, 4);
+        state.register_file("nsl_03_batch_000000_to_04_batch_000000.rkyv", 0, 0, 2, false, None, None);
+        state.flush().expect("flush");
+
+        let report = archive_size(&source_str, &dir.to_string_lossy(), 4).expect("archive");
+        assert_eq!(report.total_lists, 2);
+        let restore = unarchive(&report.archive_path, &restored_str).expect("unarchive");
+        assert_eq!(restore.files, report.files);
+        let original = std::fs::read(&file).expect("read");
+        let copy = std::fs::read(restored.join("nsl_03_batch_000000_to_04_batch_000000.rkyv")).expect("read");
+        assert_eq!(original, copy);
+
+        // A second restore would overwrite: refused
+        assert!(unarchive(&report.archive_path, &restored_str).is_err());
+    }
+}
@@ -0,0 +1,156 @@
+//! Benchmark module: time a fixed workload to compare machines and code changes
+//!
+//! The workload is always the same: the seed lists (size 3) expanded size by size
+//! up to a maximum size (6 by default), with a fixed batch size, in a scratch
+//! directory deleted afterwards.
+//!
+//! Key features:
+//! - Per size: lists created, elapsed time, computation / file I/O / conversion time
+//!   (the breakdown measured by ListOfNSL), throughputs in lists/s and written MB/s
+//! - Overall throughput, version, encoding and engine (--engine) recorded for
+//!   regression tracking
+//! - Result emitted as JSON (printed on stdout by the command line) and saved as
+//!   benchmark_result.json in the output directory
+//!
+//! Used by --benchmark mode
+
+use std::path::Path;
+use separator::Separatable;
+use serde::Serialize;
+
+use crate::utils::*;
+
+/// Lists per output file of the benchmark workload (several files per size from size 5)
+pub const BENCHMARK_LISTS_PER_FILE: u64 = 2_000_000;
+
+/// Measures of one expansion step (size - 1 -> size)
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchmarkStep {
+    pub size: u8,
+    pub lists_created: u64,
+    pub bytes_written: u64,
+    pub elapsed_secs: f64,
+    pub computation_secs: f64,
+    pub file_io_secs: f64,
+    pub conversion_secs: f64,
+    pub lists_per_sec: f64,        // lists created per second of computation
+    pub conversion_lists_per_sec: f64, // lists created per second of conversion
+    pub io_mb_per_sec: f64,        // MB written or read per second of file I/O
+}
+
+/// Result of a benchmark run
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchmarkResult {
+    pub version: String,
+    pub timestamp: String,
+    pub max_size: u8,
+    pub lists_per_file: u64,
+    pub encoding: String,
+    pub engine: String,
+    pub steps: Vec<BenchmarkStep>,
+    pub total_lists: u64,
+    pub total_secs: f64,
+    pub lists_per_sec: f64,
+}
+
+fn rate(amount: f64, secs: f64) -> f64 {
+    if secs > 0.0 { amount / secs } else { 0.0 }
+}
+
+/// Total size of the files holding lists of `size` in `dir`
+fn bytes_of_size(dir: &str, size: u8) -> u64 {
+    crate::filenames::list_input_files(dir, size).iter()
+        .filter_map(|f| std::fs::metadata(&f.path).ok())
+        .map(|m| m.len())
+        .sum()
+}
+
+/// Run the workload in a scratch directory under `work_parent` (removed afterwards)
+pub fn run_benchmark(work_parent: &str, max_size: u8) -> std::io::Result<BenchmarkResult> {
+    test_print(&format!("\nBENCHMARK MODE: seed lists expanded up to size {:02} ({} lists per file)",
+        max_size, BENCHMARK_LISTS_PER_FILE.separated_string()));
+    let work_dir = Path::new(work_parent).join(format!("funny_benchmark_{}", std::process::id()));
+    if work_dir.exists() {
+        std::fs::remove_dir_all(&work_dir)?;
+    }
+    std::fs::create_dir_all(&work_dir)?;
+    let work = work_dir.to_string_lossy().into_owned();
+    test_print(&format!("   Scratch directory: {}", work));
+
+    let run_start = std::time::Instant::now();
+    let mut no_set_lists = crate::engine::create_engine(&work, &work, Default::default());
+    no_set_lists.create_seed_lists();
+
+    let mut steps = Vec::new();
+    for size in 3..max_size {
+        let step_start = std::time::Instant::now();
+        let created = no_set_lists.process_all_files_of_current_size_n(size, &BENCHMARK_LISTS_PER_FILE, None);
+        let elapsed_secs = step_start.elapsed().as_secs_f64();
+        let timings = no_set_lists.timings();
+        // Files of the input size are read once, those of the output size written once
+        let bytes_written = bytes_of_size(&work, size + 1);
+        let bytes_moved = bytes_written + bytes_of_size(&work, size);
+        steps.push(BenchmarkStep {
+            size: size + 1,
+            lists_created: created,
+            bytes_written,
+            elapsed_secs,
+            computation_secs: timings.computation_secs,
+            file_io_secs: timings.file_io_secs,
+            conversion_secs: timings.conversion_secs,
+            lists_per_sec: rate(created as f64, timings.computation_secs),
+            conversion_lists_per_sec: rate(created as f64, timings.conversion_secs),
+            io_mb_per_sec: rate(bytes_moved as f64 / 1_048_576.0, timings.file_io_secs),
+        });
+    }
+    let total_secs = run_start.elapsed().as_secs_f64();
+    std::fs::remove_dir_all(&work_dir)?;
+
+    let total_lists: u64 = steps.iter().map(|s| s.lists_created).sum();
+    let encoding = crate::io_helpers::output_encoding().name();
+    Ok(BenchmarkResult {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        timestamp: chrono::Local::now().to_rfc3339(),
+        max_size,
+        lists_per_file: BENCHMARK_LISTS_PER_FILE,
+        encoding: encoding.to_string(),
+        engine: no_set_lists.name().to_string(),
+        steps,
+        total_lists,
+        total_secs,
+        lists_per_sec: rate(total_lists as f64, total_secs),
+    })
+}
+
+/// Emit the result as JSON (see events::result) and save it as benchmark_result.json in `output_dir`
+pub fn save_benchmark_result(output_dir: &str, result: &BenchmarkResult) -> std::io::Result<()> {
+    let json = serde_json::to_string_pretty(result)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    crate::events::result(crate::events::ResultReady { mode: "benchmark".to_string(), json: json.clone() });
+    let path = Path::new(output_dir).join("benchmark_result.json");
+    std::fs::write(&path, json)?;
+    test_print(&format!("   Benchmark result saved: {}", path.display()));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn workload_is_measured_and_its_scratch_directory_removed() {
+        let dir = crate::test_dir::TestDir::new("benchmark");
+        let dir_str = dir.to_string_lossy().into_owned();
+
+        let result = run_benchmark(&dir_str, 4).expect("benchmark");
+        assert_eq!(result.steps.len(), 1);
+        assert_eq!(result.steps[0].size, 4);
+        assert_eq!(result.total_lists, result.steps[0].lists_created);
+        assert!(result.steps[0].lists_created > 0 && result.steps[0].bytes_written > 0);
+        assert_eq!(std::fs::read_dir(&*dir).unwrap().count(), 0, "scratch directory removed");
+
+        save_benchmark_result(&dir_str, &result).expect("save");
+        let saved: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(dir.join("benchmark_result.json")).unwrap()).unwrap();
+        assert_eq!(saved["total_lists"].as_u64(), Some(result.total_lists));
+    }
+}
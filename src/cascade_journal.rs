@@ -0,0 +1,172 @@
+//! Cascade-journal module: steps completed by the cascades of a root
+//!
+//! Without it, a cascade resumes each size from the highest source batch found
+//! in the state or the file names of the outputs, which compaction renumbering
+//! can mislead. The journal records what was done instead: each input batch
+//! processed, and each size completed.
+//!
+//! Key features:
+//! - ROOT/cascade_journal.jsonl, appended one line per event and flushed at once
+//!   (an interrupted write loses one line at most; unreadable lines are skipped)
+//! - Events: input batch processed (size, batch), size completed (size, range of
+//!   the input batches processed by the step)
+//! - Resume: a completed size is skipped, unless an earlier size of the run had
+//!   work (its outputs are then new inputs); another size resumes after the last
+//!   input batch journaled for it; sizes absent from the journal fall back on the
+//!   state of their outputs
+//! - Deleting the journal forgets it (back to the states of the outputs)
+//!
+//! Used by --cascade mode
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::file_info::unix_now;
+use crate::utils::*;
+
+/// Journal file, in the cascade root
+pub const CASCADE_JOURNAL_FILE: &str = "cascade_journal.jsonl";
+
+/// One line of the journal
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum JournalRecord {
+    BatchDone { size: u8, batch: u32, at: i64 },
+    SizeCompleted { size: u8, first_batch: Option<u32>, last_batch: Option<u32>, at: i64 },
+}
+
+/// What the journal tells of one output size
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SizeProgress {
+    pub last_batch: Option<u32>, // highest input batch processed
+    pub completed: bool,
+}
+
+/// Journal of a cascade root, replayed
+#[derive(Debug)]
+pub struct CascadeJournal {
+    path: PathBuf,
+    sizes: BTreeMap<u8, SizeProgress>,
+    step_first: Option<u32>, // first input batch of the running step
+    current_size: Option<u8>,
+    torn: bool,              // last line cut short: the next one starts on a new line
+}
+
+impl CascadeJournal {
+    /// Replay the journal of `root` (empty if there is none)
+    pub fn open(root: &str) -> Self {
+        let path = Path::new(root).join(CASCADE_JOURNAL_FILE);
+        let mut sizes: BTreeMap<u8, SizeProgress> = BTreeMap::new();
+        let text = std::fs::read_to_string(&path).unwrap_or_default();
+        for line in text.lines().filter(|l| !l.trim().is_empty()) {
+            match serde_json::from_str::<JournalRecord>(line) {
+                Ok(JournalRecord::BatchDone { size, batch, .. }) => {
+                    let progress = sizes.entry(size).or_default();
+                    progress.last_batch = progress.last_batch.max(Some(batch));
+                }
+                Ok(JournalRecord::SizeCompleted { size, .. }) => sizes.entry(size).or_default().completed = true,
+                Err(_) => debug_print(&format!("   ... skipping unreadable line of {}: {}", path.display(), line)),
+            }
+        }
+        let torn = !text.is_empty() && !text.ends_with('\n');
+        Self { path, sizes, step_first: None, current_size: None, torn }
+    }
+
+    pub fn progress(&self, size: u8) -> Option<&SizeProgress> {
+        self.sizes.get(&size)
+    }
+
+    fn append(&mut self, record: &JournalRecord) -> std::io::Result<()> {
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(&self.path)?;
+        let line = serde_json::to_string(record).map_err(std::io::Error::other)?;
+        writeln!(file, "{}{}", if self.torn { "\n" } else { "" }, line)?;
+        self.torn = false;
+        file.sync_data()
+    }
+
+    /// A step of `size` starts: its batches are journaled from now on
+    pub fn start_size(&mut self, size: u8) {
+        self.current_size = Some(size);
+        self.step_first = None;
+        // A size run again is no more complete until the step says so
+        if let Some(progress) = self.sizes.get_mut(&size) {
+            progress.completed = false;
+        }
+    }
+
+    /// Input `batch` of the running step processed
+    pub fn record_batch(&mut self, batch: u32) -> std::io::Result<()> {
+        let Some(size) = self.current_size else { return Ok(()) };
+        self.append(&JournalRecord::BatchDone { size, batch, at: unix_now() })?;
+        self.step_first.get_or_insert(batch);
+        let progress = self.sizes.entry(size).or_default();
+        progress.last_batch = progress.last_batch.max(Some(batch));
+        Ok(())
+    }
+
+    /// The running step completed its size
+    pub fn complete_size(&mut self) -> std::io::Result<()> {
+        let Some(size) = self.current_size.take() else { return Ok(()) };
+        let progress = self.sizes.entry(size).or_default();
+        progress.completed = true;
+        let record = JournalRecord::SizeCompleted { size, first_batch: self.step_first, last_batch: progress.last_batch, at: unix_now() };
+        self.append(&record)
+    }
+}
+
+// Journal of the cascade running in this process
+static JOURNAL: Mutex<Option<CascadeJournal>> = Mutex::new(None);
+
+/// Make `journal` the one of the running cascade (None: no more journaling)
+pub fn set_active(journal: Option<CascadeJournal>) -> Option<CascadeJournal> {
+    std::mem::replace(&mut *JOURNAL.lock().unwrap_or_else(|e| e.into_inner()), journal)
+}
+
+/// Run `f` on the journal of the running cascade, if any
+pub fn with_active<T>(f: impl FnOnce(&mut CascadeJournal) -> T) -> Option<T> {
+    JOURNAL.lock().unwrap_or_else(|e| e.into_inner()).as_mut().map(f)
+}
+
+/// Input `batch` processed: journaled if a cascade is running
+pub fn batch_done(batch: u32) {
+    if let Some(Err(e)) = with_active(|journal| journal.record_batch(batch)) {
+        test_print(&format!("   ... Warning: could not write to the cascade journal: {}", e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn journal_replays_batches_and_completed_sizes() {
+        let root = crate::test_dir::TestDir::new("cascade_journal");
+        let root_str = root.to_string_lossy().into_owned();
+
+        let mut journal = CascadeJournal::open(&root_str);
+        assert_eq!(journal.progress(14), None);
+        journal.start_size(14);
+        for batch in [3, 4, 5] {
+            journal.record_batch(batch).expect("record");
+        }
+        journal.complete_size().expect("complete");
+        journal.start_size(15);
+        journal.record_batch(0).expect("record");
+        journal.record_batch(1).expect("record");
+        // Interrupted in the middle of a line
+        let mut file = std::fs::OpenOptions::new().append(true).open(root.join(CASCADE_JOURNAL_FILE)).expect("open");
+        write!(file, "{{\"event\":\"batch_do").expect("write");
+
+        let mut journal = CascadeJournal::open(&root_str);
+        assert_eq!(journal.progress(14), Some(&SizeProgress { last_batch: Some(5), completed: true }));
+        assert_eq!(journal.progress(15), Some(&SizeProgress { last_batch: Some(1), completed: false }));
+        journal.start_size(15);
+        journal.record_batch(2).expect("record");
+        journal.complete_size().expect("complete");
+        let journal = CascadeJournal::open(&root_str);
+        assert_eq!(journal.progress(15), Some(&SizeProgress { last_batch: Some(2), completed: true }));
+    }
+}
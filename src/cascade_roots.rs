@@ -0,0 +1,141 @@
+//! Cascade-roots module: where each size of a cascade lives
+//!
+//! The directories of a cascade outgrow one drive: the big sizes end up on
+//! drives of their own. The cascade finds each size directory ({N-1}_to_{N},
+//! 12_to_13c, {N-1}c_to_{N}c) among several roots instead of under -i alone.
+//!
+//! Key features:
+//! - Main root (-i): holds the journal and the status of the cascade, and the
+//!   size directories not found elsewhere
+//! - Mapping (--cascade-root SIZE=ROOT, repeatable): the directory of the size
+//!   SIZE lists is under ROOT, created there if needed
+//! - Search list (--cascade-roots ROOT,ROOT...): roots searched after the main
+//!   one; a size directory is used on the first root holding it, and a missing
+//!   one is created on the first root with the free space kept by
+//!   --cascade-min-free-gb (else on the main root)
+//! - The input directory of a size is the output directory of the previous one,
+//!   wherever that is; find_max_source_batch reads it there
+//! - --flat: every size in the main root (no mapping, no search)
+//!
+//! Used by --cascade mode
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::filenames::cascade_directory_name;
+
+/// Roots of a cascade
+#[derive(Debug, Clone, Default)]
+pub struct CascadeRoots {
+    roots: Vec<String>,             // main root first, then the search list
+    by_size: BTreeMap<u8, String>,  // --cascade-root SIZE=ROOT
+    flat: bool,
+}
+
+impl CascadeRoots {
+    /// Roots from the main root, the search list and the SIZE=ROOT mappings
+    pub fn new(main_root: &str, search: &[String], mappings: &[String], flat: bool) -> Result<Self, String> {
+        let mut by_size = BTreeMap::new();
+        for mapping in mappings {
+            let (size, root) = mapping.split_once('=')
+                .ok_or_else(|| format!("Invalid --cascade-root '{}' (expected SIZE=ROOT)", mapping))?;
+            let size: u8 = size.trim().parse()
+                .map_err(|_| format!("Invalid size in --cascade-root '{}'", mapping))?;
+            if !(3..=20).contains(&size) || root.trim().is_empty() {
+                return Err(format!("Invalid --cascade-root '{}' (size 3-20, non-empty ROOT)", mapping));
+            }
+            by_size.insert(size, root.trim().to_string());
+        }
+        if flat && (!by_size.is_empty() || !search.is_empty()) {
+            return Err("--cascade-root and --cascade-roots need the size subdirectories (not --flat)".to_string());
+        }
+        let mut roots = vec![main_root.to_string()];
+        roots.extend(search.iter().map(|root| root.trim().to_string()).filter(|root| !root.is_empty()));
+        Ok(Self { roots, by_size, flat })
+    }
+
+    /// Main root: journal, status, and the sizes found nowhere else
+    pub fn main(&self) -> &str {
+        &self.roots[0]
+    }
+
+    /// Directory of the lists of `size`: mapped root, else the first root holding
+    /// it, else the first root with room for it
+    pub fn size_directory(&self, size: u8) -> String {
+        if self.flat {
+            return self.main().to_string();
+        }
+        let name = cascade_directory_name(size);
+        let in_root = |root: &String| Path::new(root).join(&name).to_string_lossy().into_owned();
+        if let Some(root) = self.by_size.get(&size) {
+            return in_root(root);
+        }
+        self.roots.iter().map(in_root).find(|dir| Path::new(dir).exists())
+            .or_else(|| self.roots.iter().find(|root| crate::disk_guard::has_free_space(root)).map(in_root))
+            .unwrap_or_else(|| in_root(&self.roots[0]))
+    }
+
+    /// (input directory, output directory) of the step reading `input_size`
+    pub fn directories(&self, input_size: u8) -> (String, String) {
+        (self.size_directory(input_size), self.size_directory(input_size + 1))
+    }
+
+    /// Root options to give a cascade resuming this one
+    pub fn resume_args(&self) -> String {
+        let mut args = format!("-i \"{}\"", self.main());
+        if self.flat {
+            args.push_str(" --flat");
+        }
+        if self.roots.len() > 1 {
+            args.push_str(&format!(" --cascade-roots \"{}\"", self.roots[1..].join(",")));
+        }
+        for (size, root) in &self.by_size {
+            args.push_str(&format!(" --cascade-root \"{}={}\"", size, root));
+        }
+        args
+    }
+
+    /// One line for the console
+    pub fn describe(&self) -> String {
+        let mut text = self.main().to_string();
+        if self.flat {
+            text.push_str(" (flat layout)");
+        }
+        if self.roots.len() > 1 {
+            text.push_str(&format!(", then {}", self.roots[1..].join(", ")));
+        }
+        for (size, root) in &self.by_size {
+            text.push_str(&format!("; size {} under {}", size, root));
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_directories_are_found_on_their_root() {
+        let base = crate::test_dir::TestDir::new("cascade_roots");
+        let root = |name: &str| base.join(name).to_string_lossy().into_owned();
+        for drive in ["c", "d", "e"] {
+            std::fs::create_dir_all(base.join(drive)).expect("create root");
+        }
+        std::fs::create_dir_all(base.join("d").join("13c_to_14c")).expect("create size dir");
+
+        let roots = CascadeRoots::new(&root("c"), &[root("d")], &["16=".to_string() + &root("e")], false).expect("roots");
+        let dir = |size| roots.size_directory(size).replace('\\', "/");
+        // Found on the second root, mapped, and missing (main root)
+        assert!(dir(14).ends_with("/d/13c_to_14c"));
+        assert!(dir(16).ends_with("/e/15c_to_16c"));
+        assert!(dir(15).ends_with("/c/14c_to_15c"));
+        assert_eq!(roots.directories(14).0, roots.size_directory(14));
+        assert!(roots.resume_args().contains("--cascade-root \"16="));
+
+        assert!(CascadeRoots::new(&root("c"), &[], &["16".to_string()], false).is_err());
+        assert!(CascadeRoots::new(&root("c"), &[root("d")], &[], true).is_err());
+        let flat = CascadeRoots::new(&root("c"), &[], &[], true).expect("flat");
+        assert_eq!(flat.directories(14), (root("c"), root("c")));
+    }
+}
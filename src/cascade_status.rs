@@ -0,0 +1,194 @@
+//! Cascade-status module: position of a running cascade, in a file
+//!
+//! A cascade runs for a week; its console is on one machine. The cascade keeps
+//! cascade_status.json up to date in its root directory, for dashboards and for
+//! --overview to read.
+//!
+//! Key features:
+//! - Written when the cascade starts, at each size and after each input batch
+//!   (atomic write, so a reader never sees half a file)
+//! - Cascade: planned output sizes, sizes completed and failed, state
+//!   (running, completed, stopped, failed), PID and host, start and update times
+//! - Current size: input batch, input files done and total, lists produced,
+//!   start time and ETA (from the pace of the size so far)
+//! - Nothing is written outside of --cascade
+//!
+//! Used by --cascade and --overview modes
+
+use std::path::Path;
+use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::file_info::{eta_secs, format_duration, unix_now};
+use crate::utils::*;
+
+/// Status file, in the cascade root
+pub const CASCADE_STATUS_FILE: &str = "cascade_status.json";
+
+/// Position of a cascade
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CascadeStatus {
+    pub state: String,              // running, completed, stopped, failed
+    pub pid: u32,
+    pub host: String,
+    pub started_at: i64,            // unix seconds
+    pub updated_at: i64,
+    pub sizes_planned: Vec<u8>,     // output sizes
+    pub sizes_completed: Vec<u8>,
+    pub sizes_failed: Vec<u8>,
+    pub current_size: Option<u8>,
+    pub size_started_at: Option<i64>,
+    pub current_input_batch: Option<u32>,
+    pub inputs_done: u64,           // input files of the current size processed by this run
+    pub inputs_total: u64,          // input files of the current size to process in this run
+    pub lists_produced: u64,        // lists of the current size created by this run
+    pub eta_secs: Option<f64>,      // of the current size
+}
+
+impl CascadeStatus {
+    /// Status of a cascade starting now over the output sizes `sizes`
+    pub fn new(sizes: &[u8]) -> Self {
+        let now = unix_now();
+        Self {
+            state: "running".to_string(),
+            pid: std::process::id(),
+            host: crate::file_info::hostname(),
+            started_at: now,
+            updated_at: now,
+            sizes_planned: sizes.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    pub fn start_size(&mut self, size: u8) {
+        self.current_size = Some(size);
+        self.size_started_at = Some(unix_now());
+        (self.current_input_batch, self.inputs_done, self.inputs_total, self.lists_produced, self.eta_secs) =
+            (None, 0, 0, 0, None);
+    }
+
+    /// Input `batch` processed, the current size holding `lists_produced` new lists
+    pub fn record_batch(&mut self, batch: u32, lists_produced: u64) {
+        self.current_input_batch = Some(batch);
+        self.inputs_done += 1;
+        self.lists_produced = lists_produced;
+        let elapsed = (unix_now() - self.size_started_at.unwrap_or(self.updated_at)).max(0) as f64;
+        self.eta_secs = eta_secs(self.inputs_done, self.inputs_total.saturating_sub(self.inputs_done), elapsed);
+    }
+
+    pub fn end_size(&mut self, size: u8, completed: bool) {
+        if completed {
+            self.sizes_completed.push(size);
+        } else {
+            self.sizes_failed.push(size);
+        }
+        self.current_size = None;
+        self.eta_secs = None;
+    }
+
+    /// One line for the console: "running size 15, input batch 000123 (12/40 inputs, ...), ETA 2h13m (...)"
+    pub fn describe(&self) -> String {
+        let updated = chrono::DateTime::from_timestamp(self.updated_at, 0)
+            .map(|t| t.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_default();
+        match self.current_size {
+            Some(size) if self.state == "running" => format!("running size {}, input batch {} ({}/{} inputs, {} lists), ETA {} (updated {})",
+                size, self.current_input_batch.map_or("-".to_string(), |b| format!("{:06}", b)),
+                self.inputs_done, self.inputs_total, self.lists_produced,
+                self.eta_secs.map_or("unknown".to_string(), format_duration), updated),
+            _ => format!("{}, sizes completed {:?} of {:?} (updated {})", self.state, self.sizes_completed, self.sizes_planned, updated),
+        }
+    }
+}
+
+// Status of the cascade running in this process, with its file
+static STATUS: Mutex<Option<(String, CascadeStatus)>> = Mutex::new(None);
+
+fn write(path: &str, status: &CascadeStatus) {
+    let result = serde_json::to_string_pretty(status).map_err(std::io::Error::other)
+        .and_then(|json| crate::io_helpers::write_file_atomic(path, json.as_bytes()));
+    if let Err(e) = result {
+        debug_print(&format!("   ... could not write {}: {}", path, e));
+    }
+}
+
+/// Update the status of the running cascade, if any, and write it
+fn update(change: impl FnOnce(&mut CascadeStatus)) {
+    let mut current = STATUS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((path, status)) = current.as_mut() {
+        change(status);
+        status.updated_at = unix_now();
+        write(path, status);
+    }
+}
+
+/// Start the status of a cascade of `root` over the output sizes `sizes`
+pub fn start(root: &str, sizes: &[u8]) {
+    let status = CascadeStatus::new(sizes);
+    let path = Path::new(root).join(CASCADE_STATUS_FILE).to_string_lossy().into_owned();
+    write(&path, &status);
+    *STATUS.lock().unwrap_or_else(|e| e.into_inner()) = Some((path, status));
+}
+
+/// A size starts
+pub fn size_started(size: u8) {
+    update(|status| status.start_size(size));
+}
+
+/// The input files of the current size are planned
+pub fn inputs_planned(inputs_total: u64) {
+    update(|status| status.inputs_total = inputs_total);
+}
+
+/// An input batch of the current size is processed, the size holding `lists_produced` new lists
+pub fn batch_done(batch: u32, lists_produced: u64) {
+    update(|status| status.record_batch(batch, lists_produced));
+}
+
+/// The current size ended (`completed`: else it failed)
+pub fn size_ended(size: u8, completed: bool) {
+    update(|status| status.end_size(size, completed));
+}
+
+/// The cascade ended in `state` (completed, stopped, failed); the status is no more updated
+pub fn finish(state: &str) {
+    update(|status| status.state = state.to_string());
+    *STATUS.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Status file of the cascade of `root`, if any
+pub fn read(root: &str) -> Option<CascadeStatus> {
+    let json = std::fs::read_to_string(Path::new(root).join(CASCADE_STATUS_FILE)).ok()?;
+    serde_json::from_str(&json).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_follows_the_cascade() {
+        let root = crate::test_dir::TestDir::new("cascade_status");
+        let root_str = root.to_string_lossy().into_owned();
+        let path = root.join(CASCADE_STATUS_FILE).to_string_lossy().into_owned();
+
+        // The running status is global: the test works on its own copy
+        let mut status = CascadeStatus::new(&[14, 15]);
+        status.start_size(14);
+        status.inputs_total = 4;
+        status.record_batch(0, 1000);
+        status.record_batch(1, 2500);
+        write(&path, &status);
+        let read_back = read(&root_str).expect("status");
+        assert_eq!(read_back, status);
+        assert_eq!((read_back.current_size, read_back.current_input_batch), (Some(14), Some(1)));
+        assert_eq!((read_back.inputs_done, read_back.inputs_total, read_back.lists_produced), (2, 4, 2500));
+        assert!(read_back.describe().contains("running size 14"));
+
+        status.end_size(14, true);
+        status.start_size(15);
+        assert_eq!((status.inputs_done, status.current_input_batch), (0, None));
+        status.end_size(15, false);
+        assert_eq!((status.sizes_completed, status.sizes_failed, status.current_size), (vec![14], vec![15], None));
+    }
+}
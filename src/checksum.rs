@@ -0,0 +1,124 @@
+//! Checksum module: fast integrity scan of the list files of a size
+//!
+//! Every list file written ends with a footer holding its list count and the CRC32
+//! of its bytes (see io_helpers). This scan checks the footers of all the files of
+//! a size without decoding a single list, so silent disk corruption is found at
+//! read speed rather than when a later run fails to validate a file.
+//!
+//! Key features:
+//! - Files whose CRC32 does not match their footer reported as corrupted (the run fails)
+//! - Footer list count compared with the count recorded in the global state
+//! - Files written before footers existed reported as unchecked (re-write them with
+//!   --reencode to add one)
+//!
+//! Used by --checksum mode
+
+use std::collections::HashMap;
+use std::path::Path;
+use separator::Separatable;
+
+use crate::file_info::GlobalFileState;
+use crate::utils::*;
+
+/// Result of the checksum scan of one size
+#[derive(Debug, Clone, Default)]
+pub struct ChecksumReport {
+    pub size: u8,
+    pub files_checked: u64,
+    pub files_verified: u64,
+    pub unchecked: Vec<String>,               // written without footer
+    pub corrupted: Vec<(String, String)>,     // (filename, problem)
+    pub lists: u64,
+    pub bytes: u64,
+}
+
+/// Check the footer of every file of `size` in `base_path`
+pub fn checksum_size_files(base_path: &str, size: u8) -> std::io::Result<ChecksumReport> {
+    test_print(&format!("\nCHECKSUM MODE: Verifying size {:02} files...", size));
+    test_print(&format!("   Directory: {}", base_path));
+    let start_time = std::time::Instant::now();
+
+    let files = crate::filenames::list_input_files(base_path, size);
+    if files.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound,
+            format!("No size {:02} files found in {}", size, base_path)));
+    }
+    // List counts recorded in the state, when there is one
+    let recorded: HashMap<String, u64> = match GlobalFileState::from_sources(base_path, size) {
+        Ok(state) => state.entries().values().map(|e| (e.filename.clone(), e.nb_lists_in_file)).collect(),
+        Err(e) if crate::file_info::is_state_locked(&e) => return Err(e),
+        Err(_) => HashMap::new(),
+    };
+
+    let mut report = ChecksumReport { size, ..Default::default() };
+    for (i, file) in files.iter().enumerate() {
+        if (i + 1) % 100 == 0 {
+            progress_print(&format!("   ... {} of {} files checked", i + 1, files.len()));
+        }
+        let name = Path::new(&file.path).file_name().unwrap_or_default().to_string_lossy().into_owned();
+        report.files_checked += 1;
+        report.bytes += std::fs::metadata(crate::storage::resolve_path(&file.path)).map(|m| m.len()).unwrap_or(0);
+        match crate::io_helpers::verify_file_checksum(&file.path) {
+            Ok(Some(footer)) => match recorded.get(&name) {
+                Some(&nb_lists) if nb_lists != footer.nb_lists => {
+                    report.corrupted.push((name, format!("footer records {} lists, the state {}", footer.nb_lists, nb_lists)));
+                }
+                _ => {
+                    report.files_verified += 1;
+                    report.lists += footer.nb_lists;
+                }
+            },
+            Ok(None) => report.unchecked.push(name),
+            Err(e) => report.corrupted.push((name, e.to_string())),
+        }
+    }
+
+    for name in report.unchecked.iter() {
+        debug_print(&format!("   ... {} has no checksum (written by an older version)", name));
+    }
+    for (name, problem) in report.corrupted.iter() {
+        test_print(&format!("   [!!] {}: {}", name, problem));
+    }
+    if !report.unchecked.is_empty() {
+        test_print(&format!("   ... {} files without checksum (rewrite them with --reencode {} to add one)",
+            report.unchecked.len(), size));
+    }
+    test_print(&format!("   ... {} of {} files verified ({} lists, {} bytes) in {:.2}s",
+        report.files_verified, report.files_checked, report.lists.separated_string(),
+        report.bytes.separated_string(), start_time.elapsed().as_secs_f64()));
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::no_set_list::NoSetListSerialized;
+
+    #[test]
+    fn corrupted_and_unchecked_files_are_reported() {
+        let dir = crate::test_dir::TestDir::new("checksum");
+        let dir_str = dir.to_string_lossy().into_owned();
+
+        let lists = vec![
+            NoSetListSerialized { n: 3, max_card: 3, no_set_list: vec![0, 1, 3], remaining_cards_list: vec![4, 80] },
+            NoSetListSerialized { n: 3, max_card: 9, no_set_list: vec![0, 1, 9], remaining_cards_list: vec![] },
+        ];
+        let sound = crate::filenames::output_filename(&dir_str, 2, 0, 3, 0);
+        let corrupted = crate::filenames::output_filename(&dir_str, 2, 1, 3, 1);
+        let legacy = crate::filenames::output_filename(&dir_str, 2, 2, 3, 2);
+        assert!(crate::io_helpers::save_to_file_serialized(&lists, &sound));
+        assert!(crate::io_helpers::save_to_file_serialized(&lists, &corrupted));
+        let mut bytes = std::fs::read(&corrupted).expect("read");
+        bytes[10] ^= 0x01;
+        std::fs::write(&corrupted, &bytes).expect("write");
+        let plain = crate::io_helpers::encode_lists(&lists, crate::io_helpers::ListEncoding::Plain).expect("encode");
+        std::fs::write(&legacy, plain).expect("write");
+
+        let report = checksum_size_files(&dir_str, 3).expect("scan");
+        assert_eq!((report.files_checked, report.files_verified, report.lists), (3, 1, 2));
+        assert_eq!(report.unchecked, vec!["nsl_02_batch_000002_to_03_batch_000002.rkyv"]);
+        assert_eq!(report.corrupted.len(), 1);
+        assert!(report.corrupted[0].1.contains("Checksum mismatch"));
+        assert!(crate::io_helpers::load_lists_from_file(&corrupted).is_err());
+    }
+}
@@ -6,21 +6,352 @@
 //! Key features:
 //! - In-place compaction using GlobalFileState for crash safety
 //! - Incremental processing with state persistence after each compacted file
+//! - Streaming: origin files read once, one frame at a time, into the compacted
+//!   file and the rewrite of a partially consumed origin (bounded memory)
 //! - Support for partial compaction with max_batch parameter
+//! - Compacted file size (--compact-size) and minimum number of fragments that
+//!   triggers compaction (--compact-min-files) configurable
 //! - Automatic cleanup of consumed source files
+//! - Free space checked before each compacted file (--compact-min-free-gb): below
+//!   the threshold the run stops cleanly between two files, or waits for space
+//!   (--compact-low-space pause); out of place, --delete-early deletes each source
+//!   once the compacted files holding its lists are verified
+//! - Compaction journal (nsl_XX_compaction.journal): the intent of each in-place
+//!   compaction (compacted file, origins and lists taken from each) is written
+//!   before anything is touched; the next compaction finishes an interrupted one
+//!   whose compacted file is complete, or rolls it back otherwise
+//! - Background variant (--background-compact): while a size 13+ is processed, a
+//!   worker thread writes full compacted files from the outputs already written;
+//!   the processing thread plans and applies them on its GlobalFileState, where the
+//!   files being compacted are leased
+//! - Lineage: the lists each compacted file takes from every source batch are
+//!   recorded in the state (partially consumed compacted files split theirs), and
+//!   with --sources-sidecar in a <compacted file>.sources.json next to it
+//! - Dry run (--dry-run): the plan replayed on the state counts, with the files it
+//!   would create and the fragmentation (files and lists by magnitude of list count)
+//!   before and after
+//! - Rebalance (--rebalance): compacted files of uneven sizes rewritten into files
+//!   of the compacted file size, numbered on continuously, staged and journaled
+//! - Out-of-place variant (-o another directory): sources only read, the written
+//!   files re-read and checked (count, checksum) before the sources are deleted
 //!
 //! Used by --compact mode and automatically by --size mode for sizes 13+
 
+use std::collections::{BTreeMap, HashSet};
 use std::path::Path;
-use memmap2::Mmap;
-use rkyv::check_archived_root;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use rkyv::ser::{serializers::AllocSerializer, Serializer};
-use rkyv::Deserialize;
+use serde::{Deserialize, Serialize};
 use separator::Separatable;
 
 use crate::no_set_list::NoSetListSerialized;
 use crate::utils::*;
-use crate::file_info::GlobalFileState;
+use crate::file_info::{eta_secs, format_duration, push_source, split_sources, FileInfo, GlobalFileState, JournalOp,
+    RemovalReason, SourceContribution};
+use crate::dry_run::{DryRunPlan, Operation};
+
+// Lists per compacted file (--compact-size; 0 = the lists per output file)
+static COMPACT_SIZE: AtomicU64 = AtomicU64::new(0);
+
+// Non-compacted files needed to (keep) compacting (--compact-min-files)
+static COMPACT_MIN_FILES: AtomicU64 = AtomicU64::new(2);
+
+// Compact the outputs while a size 13+ is processed (--background-compact)
+static BACKGROUND_COMPACT: AtomicBool = AtomicBool::new(false);
+
+/// Compact the outputs of sizes 13+ while they are processed
+pub fn set_background_compact(enabled: bool) {
+    BACKGROUND_COMPACT.store(enabled, Ordering::Relaxed);
+}
+
+pub fn background_compact() -> bool {
+    BACKGROUND_COMPACT.load(Ordering::Relaxed)
+}
+
+// Drop the lists whose card set was already compacted by the run (--dedupe)
+static DEDUPE: AtomicBool = AtomicBool::new(false);
+
+/// Skip duplicate card sets while compacting in place
+pub fn set_dedupe(enabled: bool) {
+    DEDUPE.store(enabled, Ordering::Relaxed);
+}
+
+pub fn dedupe() -> bool {
+    DEDUPE.load(Ordering::Relaxed)
+}
+
+/// Canonical form of the card set of `list`: one bit per card (cards < 81), so
+/// the same cards in any order give the same key and different sets never collide
+fn card_set_key(list: &NoSetListSerialized) -> u128 {
+    list.no_set_list.iter().fold(0u128, |key, &card| key | 1u128 << card)
+}
+
+// Free space kept on the volume being compacted, in bytes (--compact-min-free-gb; 0: no check)
+static MIN_FREE_BYTES: AtomicU64 = AtomicU64::new(0);
+// Wait for free space instead of stopping when it runs low (--compact-low-space pause)
+static PAUSE_ON_LOW_SPACE: AtomicBool = AtomicBool::new(false);
+
+/// Seconds between two free-space checks while a compaction waits for space
+const LOW_SPACE_POLL_SECS: u64 = 60;
+
+/// Keep `min_free_gb` GB free while compacting; below it, pause or stop
+pub fn set_min_free_space(min_free_gb: Option<f64>, pause: bool) {
+    MIN_FREE_BYTES.store(min_free_gb.map_or(0, |gb| (gb * (1u64 << 30) as f64) as u64), Ordering::Relaxed);
+    PAUSE_ON_LOW_SPACE.store(pause, Ordering::Relaxed);
+}
+
+/// True if writing `needed` bytes leaves at least `min_free` of the `free` bytes
+/// (unknown free space counts as enough)
+fn room_for(free: Option<u64>, needed: u64, min_free: u64) -> bool {
+    free.is_none_or(|free| free >= needed.saturating_add(min_free))
+}
+
+/// True if `dir` can take `needed` more bytes above --compact-min-free-gb. Below it,
+/// with --compact-low-space pause the check is repeated every LOW_SPACE_POLL_SECS
+/// until space is freed; otherwise false, for the caller to stop between two files.
+fn has_room_for(dir: &str, needed: u64) -> bool {
+    let min_free = MIN_FREE_BYTES.load(Ordering::Relaxed);
+    if min_free == 0 {
+        return true;
+    }
+    let free = || crate::storage::free_space(Path::new(dir));
+    let mut notified = false;
+    loop {
+        let available = free();
+        if room_for(available, needed, min_free) {
+            return true;
+        }
+        let message = format!("Low disk space in {}: {} bytes free, {} needed plus {} kept free",
+            dir, available.unwrap_or(0).separated_string(), needed.separated_string(), min_free.separated_string());
+        test_print(&format!("   {}", message));
+        if !notified {
+            crate::notify::notify("low_disk", &message, None);
+            notified = true;
+        }
+        if !PAUSE_ON_LOW_SPACE.load(Ordering::Relaxed) {
+            return false;
+        }
+        test_print(&format!("   ... compaction paused; checking again in {}s (free some space or stop the run)", LOW_SPACE_POLL_SECS));
+        std::thread::sleep(std::time::Duration::from_secs(LOW_SPACE_POLL_SECS));
+    }
+}
+
+// Out of place, delete each source once the files holding its lists are verified (--delete-early)
+static DELETE_EARLY: AtomicBool = AtomicBool::new(false);
+
+pub fn set_delete_early(enabled: bool) {
+    DELETE_EARLY.store(enabled, Ordering::Relaxed);
+}
+
+// Write the sources of each compacted file next to it (--sources-sidecar)
+static SOURCES_SIDECAR: AtomicBool = AtomicBool::new(false);
+
+/// Write a <compacted file>.sources.json sidecar next to each compacted file
+pub fn set_sources_sidecar(enabled: bool) {
+    SOURCES_SIDECAR.store(enabled, Ordering::Relaxed);
+}
+
+/// Sidecar holding the sources of the compacted file `filename` of `dir`
+pub fn sources_sidecar_path(dir: &str, filename: &str) -> std::path::PathBuf {
+    Path::new(dir).join(format!("{}.sources.json", filename.strip_suffix(".rkyv").unwrap_or(filename)))
+}
+
+/// Record the sources of the compacted file `filename` in `state` and, with
+/// --sources-sidecar, in its sidecar
+fn record_sources(state: &mut GlobalFileState, dir: &str, filename: &str, sources: Vec<SourceContribution>) -> std::io::Result<()> {
+    if SOURCES_SIDECAR.load(Ordering::Relaxed) {
+        let sidecar = serde_json::json!({ "filename": filename, "sources": &sources });
+        let text = serde_json::to_string_pretty(&sidecar).map_err(std::io::Error::other)?;
+        std::fs::write(sources_sidecar_path(dir, filename), text)?;
+    }
+    state.set_compacted_sources(filename, sources);
+    Ok(())
+}
+
+/// Delete the sidecar of the compacted file `filename` of `dir`, if any
+fn remove_sources_sidecar(dir: &str, filename: &str) {
+    let _ = std::fs::remove_file(sources_sidecar_path(dir, filename));
+}
+
+/// Origin file of an in-place compaction, as planned
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IntentOrigin {
+    filename: String,
+    source_batch: u32,
+    target_batch: u32,
+    taken: u64, // first lists moved into the compacted file
+    total: u64,
+    rest_sources: Option<Vec<SourceContribution>>, // recorded sources of the remaining lists
+    #[serde(default)]
+    kept: Option<u64>, // lists left in the rewrite once deduplicated (None: total - taken)
+    #[serde(default)]
+    kept_sources: Option<Vec<SourceContribution>>, // their sources (when rest_sources are recorded)
+}
+
+/// One in-place compaction, journaled before the compacted file is written and
+/// deleted once the origins are rewritten and the state flushed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CompactionIntent {
+    compacted: String,
+    source_batch: u32,
+    target_batch: u32,
+    nb_lists: u64,
+    is_full: bool,
+    sources: Vec<SourceContribution>,
+    origins: Vec<IntentOrigin>,
+}
+
+/// Compaction journal of size `size` in `dir`
+pub fn compaction_journal_path(dir: &str, size: u8) -> std::path::PathBuf {
+    Path::new(dir).join(format!("nsl_{:02}_compaction.journal", size))
+}
+
+impl CompactionIntent {
+    fn save(&self, dir: &str, size: u8) -> std::io::Result<()> {
+        let text = serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+        crate::io_helpers::write_file_atomic(&compaction_journal_path(dir, size).to_string_lossy(), text.as_bytes())
+    }
+
+    fn load(dir: &str, size: u8) -> std::io::Result<Option<Self>> {
+        let path = compaction_journal_path(dir, size);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let text = std::fs::read_to_string(&path)?;
+        serde_json::from_str(&text).map(Some).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData,
+            format!("Unreadable compaction journal {}: {}", path.display(), e)))
+    }
+
+    fn clear(dir: &str, size: u8) -> std::io::Result<()> {
+        let path = compaction_journal_path(dir, size);
+        if path.exists() { std::fs::remove_file(&path) } else { Ok(()) }
+    }
+}
+
+/// Delete the rewrites of `filename` left in `dir` by an interrupted run
+fn remove_stale_rewrites(dir: &str, filename: &str) {
+    let prefix = format!("{}.tmp.", filename);
+    for entry in std::fs::read_dir(dir).into_iter().flatten().flatten() {
+        if entry.file_name().to_string_lossy().starts_with(&prefix) {
+            let _ = crate::storage::remove_list_file(&entry.path().to_string_lossy());
+        }
+    }
+}
+
+/// True if the compacted file of `intent` was written completely
+fn compacted_file_complete(dir: &str, intent: &CompactionIntent) -> bool {
+    let path = format!("{}/{}", dir, intent.compacted);
+    crate::storage::list_file_exists(&path)
+        && crate::io_helpers::verify_file_checksum(&path).is_ok()
+        && crate::io_helpers::count_lists_in_file(&path).ok() == Some(intent.nb_lists)
+}
+
+/// Finish or roll back the compaction journaled in `dir` by a run that stopped
+/// before completing it: with the compacted file complete, the origins are deleted
+/// or shrunk as planned; otherwise the compacted file is deleted (the origins are
+/// only modified once it is complete). Returns true if a compaction was recovered.
+fn recover_interrupted_compaction(dir: &str, size: u8, state: &mut GlobalFileState) -> std::io::Result<bool> {
+    let Some(intent) = CompactionIntent::load(dir, size)? else {
+        return Ok(false);
+    };
+    test_print(&format!("   Interrupted compaction found ({}): {}", compaction_journal_path(dir, size).display(), intent.compacted));
+    for origin in intent.origins.iter() {
+        remove_stale_rewrites(dir, &origin.filename);
+    }
+    let compacted_path = format!("{}/{}", dir, intent.compacted);
+    if !compacted_file_complete(dir, &intent) {
+        test_print(&format!("   ... compacted file {} incomplete; rolling back (origins untouched)", intent.compacted));
+        if state.has_entry(&intent.compacted, intent.source_batch, intent.target_batch) {
+            state.remove_file(&intent.compacted, intent.source_batch, intent.target_batch, RemovalReason::Manual);
+        }
+        if crate::storage::list_file_exists(&compacted_path) {
+            crate::storage::remove_list_file(&compacted_path)?;
+        }
+        remove_sources_sidecar(dir, &intent.compacted);
+        state.flush()?;
+        CompactionIntent::clear(dir, size)?;
+        return Ok(true);
+    }
+
+    test_print(&format!("   ... compacted file {} complete ({} lists); finishing the compaction",
+        intent.compacted, intent.nb_lists.separated_string()));
+    if !state.has_entry(&intent.compacted, intent.source_batch, intent.target_batch) {
+        let metadata = crate::storage::file_metadata(&compacted_path);
+        state.register_file(&intent.compacted, intent.source_batch, intent.target_batch, intent.nb_lists, intent.is_full,
+            metadata.map(|(bytes, _)| bytes), metadata.and_then(|(_, modified)| modified));
+    } else {
+        state.update_count(&intent.compacted, intent.source_batch, intent.target_batch, intent.nb_lists);
+    }
+    record_sources(state, dir, &intent.compacted, intent.sources.clone())?;
+    for origin in intent.origins.iter() {
+        let path = format!("{}/{}", dir, origin.filename);
+        let exists = crate::storage::list_file_exists(&path);
+        if origin.taken >= origin.total {
+            if exists {
+                test_print(&format!("   ... deleting the consumed origin {}", origin.filename));
+                crate::storage::remove_list_file(&path)?;
+                crate::io_helpers::invalidate_cached_batch(&path);
+            }
+            remove_sources_sidecar(dir, &origin.filename);
+            if state.has_entry(&origin.filename, origin.source_batch, origin.target_batch) {
+                state.remove_file(&origin.filename, origin.source_batch, origin.target_batch, RemovalReason::CompactedAway);
+            }
+            continue;
+        }
+        let (mut remaining, mut rest_sources) = match origin.kept {
+            Some(kept) => (kept, origin.kept_sources.clone()),
+            None => (origin.total - origin.taken, origin.rest_sources.clone()),
+        };
+        let current = if exists { crate::io_helpers::count_lists_in_file(&path)? } else { 0 };
+        if current == origin.total {
+            // The rewrite (deduplicated or not) was lost: keep all the remaining lists
+            (remaining, rest_sources) = (origin.total - origin.taken, origin.rest_sources.clone());
+            test_print(&format!("   ... shrinking the origin {} to its {} remaining lists", origin.filename,
+                remaining.separated_string()));
+            let lists = crate::io_helpers::load_lists_range(&path, origin.taken as usize, remaining as usize)?;
+            let mut rest = FrameBuffer::new(crate::io_helpers::ListFileWriter::create(&path)?);
+            for list in lists {
+                rest.push(list)?;
+            }
+            rest.finish()?;
+            crate::io_helpers::invalidate_cached_batch(&path);
+        } else if current != remaining {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!(
+                "Origin {} holds {} lists, expected {} (before) or {} (after the compaction); fix it by hand, then delete {}",
+                origin.filename, current, origin.total, remaining, compaction_journal_path(dir, size).display())));
+        }
+        state.update_count(&origin.filename, origin.source_batch, origin.target_batch, remaining);
+        if let Some(rest_sources) = rest_sources {
+            record_sources(state, dir, &origin.filename, rest_sources)?;
+        }
+    }
+    state.flush()?;
+    CompactionIntent::clear(dir, size)?;
+    Ok(true)
+}
+
+/// Write compacted files of `lists` lists instead of the lists per output file
+pub fn set_compact_size(lists: Option<u64>) {
+    COMPACT_SIZE.store(lists.unwrap_or(0), Ordering::Relaxed);
+}
+
+/// Lists per compacted file: --compact-size, else `lists_per_file`
+pub fn compact_batch_size(lists_per_file: u64) -> u64 {
+    match COMPACT_SIZE.load(Ordering::Relaxed) {
+        0 => lists_per_file,
+        lists => lists,
+    }
+}
+
+/// Compact only while at least `files` non-compacted files remain (at least 2)
+pub fn set_compact_min_files(files: u64) {
+    COMPACT_MIN_FILES.store(files.max(2), Ordering::Relaxed);
+}
+
+/// Non-compacted files needed to (keep) compacting
+pub fn compact_min_files() -> u64 {
+    COMPACT_MIN_FILES.load(Ordering::Relaxed)
+}
 
 /// Legacy: Save compacted batch atomically (no longer used - kept for reference)
 #[allow(dead_code)]
@@ -29,50 +360,436 @@ fn save_compacted_batch_atomic(filepath: &str, lists: &[NoSetListSerialized]) ->
     let lists_vec: Vec<NoSetListSerialized> = lists.to_vec();
     let mut serializer = AllocSerializer::<4096>::default();
     serializer.serialize_value(&lists_vec)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Serialization error: {:?}", e)))?;
+        .map_err(|e| std::io::Error::other(format!("Serialization error: {:?}", e)))?;
     let bytes = serializer.into_serializer().into_inner();
 
-    let path = Path::new(filepath);
-    // Add PID suffix to tmp extension to avoid collisions
-    let pid = std::process::id();
-    let tmp = path.with_extension(format!("tmp.{}", pid));
-    // Write tmp
-    std::fs::write(&tmp, &bytes)?;
-    // fsync
-    let f = std::fs::File::open(&tmp)?;
-    f.sync_all()?;
-
-    // Try atomic rename with retries (backoff) to handle transient Windows/SMB locks
-    let mut renamed = false;
-    for attempt in 0..5 {
-        // Remove existing target (Windows requires remove before rename)
-        if path.exists() { let _ = std::fs::remove_file(path); }
-        match std::fs::rename(&tmp, path) {
-            Ok(()) => { renamed = true; break; }
-            Err(e) => {
-                debug_print(&format!("atomic rename attempt {} failed for {}: {:?}", attempt + 1, filepath, e));
-                std::thread::sleep(std::time::Duration::from_millis(200 * (attempt + 1)));
+    // tmp file, fsync, rename (with retries on Windows)
+    crate::io_helpers::write_file_atomic(filepath, &bytes)
+}
+
+/// Part of an origin file going into a compacted file
+struct Contribution {
+    path: String,
+    filename: String,
+    src_batch: u32,
+    tgt_batch: u32,
+    taken: u64, // first lists of the file copied into the compacted file
+    total: u64,
+    sources: Vec<SourceContribution>, // source batches of the lists of the file, in order
+}
+
+/// Lists written to a list file one frame (FRAME_LISTS lists) at a time
+struct FrameBuffer {
+    writer: crate::io_helpers::ListFileWriter,
+    frame: Vec<NoSetListSerialized>,
+}
+
+impl FrameBuffer {
+    fn new(writer: crate::io_helpers::ListFileWriter) -> Self {
+        Self { writer, frame: Vec::with_capacity(crate::io_helpers::FRAME_LISTS) }
+    }
+
+    fn push(&mut self, list: NoSetListSerialized) -> std::io::Result<()> {
+        self.frame.push(list);
+        if self.frame.len() >= crate::io_helpers::FRAME_LISTS {
+            self.writer.write_frame(&self.frame)?;
+            self.frame.clear();
+        }
+        Ok(())
+    }
+
+    fn flush_frame(&mut self) -> std::io::Result<()> {
+        if !self.frame.is_empty() {
+            self.writer.write_frame(&self.frame)?;
+            self.frame.clear();
+        }
+        Ok(())
+    }
+
+    /// Write the last frame and finish the file; returns the number of lists written
+    fn finish(mut self) -> std::io::Result<u64> {
+        self.flush_frame()?;
+        self.writer.finish()
+    }
+
+    /// `finish`, the file kept under its tmp name until committed
+    fn finish_pending(mut self) -> std::io::Result<crate::io_helpers::PendingListFile> {
+        self.flush_frame()?;
+        self.writer.finish_pending()
+    }
+}
+
+/// Path of a file written by compaction (only full files are tagged _compacted)
+fn compacted_output_filename(dir: &str, source_size: u8, from_src: u32, target_size: u8, idx: u32, is_full: bool) -> String {
+    if is_full {
+        format!("{}/nsl_{:02}_batch_{:06}_to_{:02}_batch_{:06}_compacted.rkyv", dir, source_size, from_src, target_size, idx)
+    } else {
+        format!("{}/nsl_{:02}_batch_{:06}_to_{:02}_batch_{:06}.rkyv", dir, source_size, from_src, target_size, idx)
+    }
+}
+
+/// One compacted file to write in place: its intent and the origins it takes lists from
+struct CompactionJob {
+    dir: String,
+    size: u8,
+    output_filename: String, // path of the compacted file
+    planned_lists: u64,      // non-compacted lists of the plan (progress)
+    intent: CompactionIntent,
+    contribs: Vec<Contribution>,
+}
+
+/// Plan the next compacted file of `dir` from the non-compacted entries of `state`
+/// (those not leased by a running compaction), in (target batch, source batch)
+/// order. With `full_only` (background compaction) a job is only planned when it
+/// fills a whole compacted file, and nothing is printed otherwise.
+fn plan_job(state: &GlobalFileState, dir: &str, target_size: u8, batch_size: u64, max_batch: Option<u32>,
+    full_only: bool) -> std::io::Result<Option<CompactionJob>> {
+    let say = |message: &str| if !full_only { test_print(message) };
+    let mut plan: Vec<(String, u64, u32, u32)> = Vec::new(); // (filename, count, src_batch, tgt_batch)
+    for ((src, tgt, _), info) in state.entries().iter() {
+        if !info.compacted && !state.is_leased(&info.filename) && max_batch.is_none_or(|max| *tgt <= max) {
+            plan.push((info.filename.clone(), info.nb_lists_in_file, *src, *tgt));
+        }
+    }
+
+    // If no non-compacted files left, we're done
+    if plan.is_empty() {
+        say("   No more non-compacted files to compact.");
+        return Ok(None);
+    }
+
+    // Too few non-compacted files remain (one by default, nothing to merge it with) - stop here
+    if (plan.len() as u64) < compact_min_files() {
+        say(&format!("   Only {} non-compacted files remain (--compact-min-files {}); nothing to compact.",
+            plan.len(), compact_min_files()));
+        return Ok(None);
+    }
+    let planned_lists: u64 = plan.iter().map(|p| p.1).sum();
+    if full_only && planned_lists < batch_size {
+        return Ok(None);
+    }
+
+    // Order by target_batch then source_batch (ascending)
+    plan.sort_by(|a, b| match a.3.cmp(&b.3) { std::cmp::Ordering::Equal => a.2.cmp(&b.2), other => other });
+
+    // Determine next compacted batch index from state (more reliable than disk scan)
+    let mut next_compact_idx: u32 = 0;
+    for ((_, tgt, _), info) in state.entries().iter() {
+        if info.compacted {
+            next_compact_idx = next_compact_idx.max(tgt + 1);
+        }
+    }
+    say(&format!("   Next compacted index (from state): {:06}", next_compact_idx));
+
+    // Plan the contributions up to batch_size from the file counts (only the
+    // last contributor can be partially consumed)
+    let mut contribs: Vec<Contribution> = Vec::new();
+    let mut filled: u64 = 0;
+    let source_size = target_size - 1;
+    for (fname, _count, src_batch, tgt_batch) in plan.iter() {
+        if filled >= batch_size { break; }
+        let path = format!("{}/{}", dir, fname);
+        let total = crate::io_helpers::count_lists_in_file(&path)?;
+        let taken = total.min(batch_size - filled);
+        filled += taken;
+        let sources = match state.compacted_sources().get(fname) {
+            Some(recorded) if recorded.iter().map(|s| s.nb_lists).sum::<u64>() == total => recorded.clone(),
+            _ => vec![SourceContribution { source_batch: *src_batch, nb_lists: total }],
+        };
+        contribs.push(Contribution { path, filename: fname.clone(), src_batch: *src_batch, tgt_batch: *tgt_batch, taken, total, sources });
+    }
+
+    if filled == 0 {
+        say("   Nothing to compact in this iteration (no more files or batch_size met).");
+        return Ok(None);
+    }
+    let is_full = filled >= batch_size;
+    if full_only && !is_full {
+        return Ok(None); // the recorded counts were ahead of the files
+    }
+
+    // Determine output filename using the last contributor src batch
+    let from_src = contribs.iter().rev().find(|c| c.taken > 0).map(|c| c.src_batch).unwrap_or(0);
+
+    // Find first available index if calculated one already exists
+    let mut final_compact_idx = next_compact_idx;
+    let mut output_filename = compacted_output_filename(dir, source_size, from_src, target_size, final_compact_idx, is_full);
+    
+    // Find first available index (idempotent: skip existing files)
+    const MAX_INDEX_SEARCH: u32 = 1000;
+    while crate::storage::list_file_exists(&output_filename) && final_compact_idx < next_compact_idx + MAX_INDEX_SEARCH {
+        test_print(&format!("   Compacted file {} already exists, trying next index", output_filename));
+        final_compact_idx += 1;
+        output_filename = compacted_output_filename(dir, source_size, from_src, target_size, final_compact_idx, is_full);
+    }
+    
+    if crate::storage::list_file_exists(&output_filename) {
+        test_print(&format!("   Could not find available index after {} tries, stopping", MAX_INDEX_SEARCH));
+        return Ok(None);
+    }
+
+    let mut sources: Vec<SourceContribution> = Vec::new();
+    for c in contribs.iter() {
+        for s in split_sources(&c.sources, c.taken).0 {
+            push_source(&mut sources, s.source_batch, s.nb_lists);
+        }
+    }
+    let intent = CompactionIntent {
+        compacted: Path::new(&output_filename).file_name().unwrap().to_string_lossy().into_owned(),
+        source_batch: from_src,
+        target_batch: final_compact_idx,
+        nb_lists: filled,
+        is_full,
+        sources,
+        origins: contribs.iter().map(|c| IntentOrigin {
+            filename: c.filename.clone(),
+            source_batch: c.src_batch,
+            target_batch: c.tgt_batch,
+            taken: c.taken,
+            total: c.total,
+            rest_sources: state.compacted_sources().contains_key(&c.filename)
+                .then(|| split_sources(&c.sources, c.taken).1),
+            kept: None,
+            kept_sources: None,
+        }).collect(),
+    };
+    Ok(Some(CompactionJob { dir: dir.to_string(), size: target_size, output_filename, planned_lists, intent, contribs }))
+}
+
+/// Write the compacted file of `job`: the lists are streamed into it, and the rest
+/// of a partially consumed origin into its rewrite (renamed into place by
+/// apply_job once the compacted file is recorded); each origin is read once,
+/// FRAME_LISTS lists at a time. Touches no state: runs on the background thread too.
+/// With `seen` (--dedupe), lists whose card set is already in it are dropped and the
+/// intent of `job` is updated with the lists kept; returns the rewrite and the
+/// number of duplicates dropped.
+fn write_job(job: &mut CompactionJob, mut seen: Option<&mut HashSet<u128>>)
+    -> std::io::Result<(Option<(usize, crate::io_helpers::PendingListFile)>, u64)> {
+    let filled = job.intent.nb_lists;
+    test_print(&format!("   Writing compacted file {} ({} lists)", job.output_filename, filled.separated_string()));
+    let mut compacted = FrameBuffer::new(crate::io_helpers::ListFileWriter::create(&job.output_filename)?);
+    let mut rewrite: Option<(usize, crate::io_helpers::PendingListFile)> = None;
+    let mut sources: Vec<SourceContribution> = Vec::new(); // of the lists kept (dedupe)
+    let mut rest_sources: Vec<SourceContribution> = Vec::new();
+    let (mut duplicates, mut rest_duplicates) = (0u64, 0u64);
+    for (i, c) in job.contribs.iter().enumerate() {
+        let mut rest = (c.taken < c.total)
+            .then(|| crate::io_helpers::ListFileWriter::create(&c.path).map(FrameBuffer::new))
+            .transpose()?;
+        let mut batches = c.sources.iter().flat_map(|s| std::iter::repeat_n(s.source_batch, s.nb_lists as usize));
+        let mut position: u64 = 0;
+        crate::io_helpers::load_lists_in_chunks(&c.path, crate::io_helpers::FRAME_LISTS, |lists| {
+            for list in lists {
+                let batch = batches.next().unwrap_or(c.src_batch);
+                let taken = position < c.taken;
+                position += 1;
+                if let Some(seen) = seen.as_deref_mut() && !seen.insert(card_set_key(&list)) {
+                    duplicates += 1;
+                    rest_duplicates += u64::from(!taken);
+                    continue;
+                }
+                match (&mut rest, taken) {
+                    (Some(rest), false) => {
+                        push_source(&mut rest_sources, batch, 1);
+                        rest.push(list)?
+                    }
+                    _ => {
+                        push_source(&mut sources, batch, 1);
+                        compacted.push(list)?
+                    }
+                }
             }
+            Ok(())
+        })?;
+        crate::events::file_processed(crate::events::FileProcessed::new("compact", job.size,
+            crate::events::FileAction::Read, c.filename.as_str(), Some(c.total)));
+        if c.taken > 0 {
+            test_print(&format!("   Copied {:>10} lists from {}", c.taken.separated_string(), c.filename));
+        }
+        if let Some(rest) = rest {
+            rewrite = Some((i, rest.finish_pending()?));
+        }
+    }
+    let written = compacted.finish()?;
+    if written != filled - (duplicates - rest_duplicates) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!(
+            "Compacted file {} holds {} lists, {} planned", job.output_filename, written, filled)));
+    }
+    crate::events::file_processed(crate::events::FileProcessed::new("compact", job.size, crate::events::FileAction::Written,
+        Path::new(&job.output_filename).file_name().unwrap_or_default().to_string_lossy(), Some(written)));
+    if duplicates > 0 {
+        test_print(&format!("   Dropped {} duplicate lists", duplicates.separated_string()));
+        job.intent.nb_lists = written;
+        job.intent.sources = sources;
+        if let Some((i, pending)) = &rewrite {
+            let origin = &mut job.intent.origins[*i];
+            origin.kept = Some(pending.nb_lists);
+            origin.kept_sources = origin.rest_sources.is_some().then_some(rest_sources);
         }
     }
+    Ok((rewrite, duplicates))
+}
 
-    if renamed {
-        return Ok(());
+/// Record the compacted file written by write_job in `state`, then delete or shrink
+/// its origins (flushing before and after), and clear the compaction journal
+fn apply_job(state: &mut GlobalFileState, job: &CompactionJob, rewrite: Option<(usize, crate::io_helpers::PendingListFile)>) -> std::io::Result<()> {
+    let (intent, dir) = (&job.intent, job.dir.as_str());
+
+    // Register the new compacted file in state IMMEDIATELY after writing
+    let metadata = crate::storage::file_metadata(&job.output_filename);
+    let file_size = metadata.map(|(bytes, _)| bytes);
+    let mtime = metadata.and_then(|(_, modified)| modified);
+    
+    // Only mark as "compacted" if file is full (>= batch_size lists)
+    // Partial files are NOT marked as compacted so they can be merged with future files
+    if !intent.is_full {
+        test_print(&format!("   Note: File has {} lists (< a full compacted file); NOT marking as compacted for future merging",
+            intent.nb_lists.separated_string()));
     }
+    
+    state.register_file(
+        &intent.compacted,
+        intent.source_batch,
+        intent.target_batch,
+        intent.nb_lists,
+        intent.is_full,  // Only full files are marked as compacted
+        file_size,
+        mtime,
+    );
+    record_sources(state, dir, &intent.compacted, intent.sources.clone())?;
+    test_print(&format!("   Registered file in state (compacted={})", intent.is_full));
 
-    // If we reach here, rename failed persistently. Try fallback: write final file via serializer,
-    // then remove the tmp file if fallback succeeded.
-    debug_print(&format!("atomic rename failed persistently for {}; attempting fallback write", filepath));
-    if crate::io_helpers::save_to_file_serialized(&lists_vec, filepath) {
-        // fallback succeeded; remove tmp and return Ok
-        let _ = std::fs::remove_file(&tmp);
-        test_print(&format!("   Wrote compacted file (fallback) {}", filepath));
-        return Ok(());
+    // Flush state IMMEDIATELY (crash-safe checkpoint before modifying original files)
+    state.flush()
+        .map_err(|e| std::io::Error::other(format!("Failed to flush state after compacted file: {}", e)))?;
+    test_print("   Flushed state to rkyv (compacted file recorded)");
+
+    // Now safe to modify original files (if crash happens here, compacted file is already in state)
+    let mut rewrite = rewrite;
+    for (i, c) in job.contribs.iter().enumerate() {
+        if c.taken >= c.total {
+            test_print(&format!("   Origin file {} fully consumed; deleting", c.path));
+            state.journal(&JournalOp::Remove { filename: c.filename.clone(), source_batch: c.src_batch, target_batch: c.tgt_batch })?;
+            crate::storage::remove_list_file(&c.path)?;
+            crate::io_helpers::invalidate_cached_batch(&c.path);
+            remove_sources_sidecar(dir, &c.filename);
+            
+            // Remove from state using proper API
+            state.remove_file(&c.filename, c.src_batch, c.tgt_batch, RemovalReason::CompactedAway);
+        } else if let Some((_, pending)) = rewrite.take_if(|(j, _)| *j == i) {
+            let remaining_count = intent.origins[i].kept.unwrap_or(c.total - c.taken);
+            test_print(&format!("   Origin file {} partially consumed; keeping its {} remaining lists", c.path, remaining_count.separated_string()));
+            state.journal(&JournalOp::Update { filename: c.filename.clone(), source_batch: c.src_batch,
+                target_batch: c.tgt_batch, nb_lists: remaining_count })?;
+            if pending.nb_lists != remaining_count {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!(
+                    "Rewrite of {} holds {} lists, {} expected", c.path, pending.nb_lists, remaining_count)));
+            }
+            pending.commit()?;
+            
+            // Update state with new count using proper API
+            state.update_count(&c.filename, c.src_batch, c.tgt_batch, remaining_count);
+            let origin = &intent.origins[i];
+            if let Some(rest_sources) = origin.kept.map_or(origin.rest_sources.clone(), |_| origin.kept_sources.clone()) {
+                record_sources(state, dir, &c.filename, rest_sources)?;
+            }
+        }
+    }
+
+    // Final flush to record all file modifications (deletions/shrinks) for this iteration
+    state.flush()
+        .map_err(|e| std::io::Error::other(format!("Failed to flush state after file modifications: {}", e)))?;
+    CompactionIntent::clear(dir, job.size)?;
+    test_print("   Flushed state to rkyv (file modifications recorded)");
+    Ok(())
+}
+
+/// Compacted file written by the background thread, with its origins' rewrite
+type BackgroundResult = (CompactionJob, std::io::Result<(Option<(usize, crate::io_helpers::PendingListFile)>, u64)>);
+
+/// Compaction of the outputs of a size while it is processed (--background-compact).
+/// The processing thread calls `poll` after each output file: a finished job is
+/// applied to its state, then the next full compacted file is planned from the
+/// outputs already written (leased in the state) and written by a worker thread.
+/// The compaction journal covers a run stopped while a job is in flight.
+pub struct BackgroundCompactor {
+    dir: String,
+    size: u8,
+    batch_size: u64,
+    running: Option<std::thread::JoinHandle<BackgroundResult>>,
+    stopped: bool,
+    pub files_created: u32,
+    pub lists_compacted: u64,
+}
+
+impl BackgroundCompactor {
+    /// Compactor of the size `size` outputs of `dir`, `batch_size` lists per
+    /// compacted file (see --compact-size)
+    pub fn new(dir: &str, size: u8, batch_size: u64) -> Self {
+        Self { dir: dir.to_string(), size, batch_size: compact_batch_size(batch_size), running: None, stopped: false,
+            files_created: 0, lists_compacted: 0 }
     }
 
-    // Fallback also failed; leave tmp for inspection and return error
-    debug_print(&format!("fallback write also failed for {} — leaving tmp file {} for inspection", filepath, tmp.display()));
-    Err(std::io::Error::new(std::io::ErrorKind::Other, "Atomic rename and fallback write both failed"))
+    /// Apply the job finished since the last call, and start the next one if the
+    /// outputs not compacted yet fill a compacted file. Errors stop the background
+    /// compaction (the compaction after processing picks up what is left).
+    pub fn poll(&mut self, state: &mut GlobalFileState) {
+        if self.stopped || self.running.as_ref().is_some_and(|handle| !handle.is_finished()) {
+            return;
+        }
+        if let Err(e) = self.apply_finished(state).and_then(|_| self.start_next(state)) {
+            test_print(&format!("   ... WARNING: background compaction stopped: {}", e));
+            self.stopped = true;
+        }
+    }
+
+    /// Wait for the running job and apply it (end of processing)
+    pub fn finish(&mut self, state: &mut GlobalFileState) {
+        if let Err(e) = self.apply_finished(state) {
+            test_print(&format!("   ... WARNING: background compaction stopped: {}", e));
+        }
+        self.stopped = true;
+    }
+
+    fn apply_finished(&mut self, state: &mut GlobalFileState) -> std::io::Result<()> {
+        let Some(handle) = self.running.take() else {
+            return Ok(());
+        };
+        let (job, written) = handle.join().map_err(|_| std::io::Error::other("background compaction thread panicked"))?;
+        state.release_files(job.contribs.iter().map(|c| c.filename.as_str()));
+        match written {
+            Ok((rewrite, _)) => {
+                apply_job(state, &job, rewrite)?;
+                self.files_created += 1;
+                self.lists_compacted += job.intent.nb_lists;
+                test_print(&format!("   ... background compaction: {} created ({} compacted files, {} lists so far)",
+                    job.intent.compacted, self.files_created, self.lists_compacted.separated_string()));
+                Ok(())
+            }
+            Err(e) => {
+                test_print(&format!("   ... background compaction of {} failed: {}; rolling back", job.intent.compacted, e));
+                recover_interrupted_compaction(&self.dir, self.size, state)?;
+                Err(e)
+            }
+        }
+    }
+
+    fn start_next(&mut self, state: &mut GlobalFileState) -> std::io::Result<()> {
+        recover_interrupted_compaction(&self.dir, self.size, state)?;
+        let Some(job) = plan_job(state, &self.dir, self.size, self.batch_size, None, true)? else {
+            return Ok(());
+        };
+        job.intent.save(&self.dir, self.size)?;
+        state.lease_files(job.contribs.iter().map(|c| c.filename.clone()));
+        test_print(&format!("   ... background compaction: writing {} from {} outputs",
+            job.intent.compacted, job.contribs.len()));
+        self.running = Some(std::thread::spawn(move || {
+            let mut job = job;
+            let written = write_job(&mut job, None);
+            (job, written)
+        }));
+        Ok(())
+    }
 }
 
 /// Compact multiple batches in-place using GlobalFileState.
@@ -82,6 +799,7 @@ fn save_compacted_batch_atomic(filepath: &str, lists: &[NoSetListSerialized]) ->
 /// - After EACH compacted file: deletes/shrinks consumed files and flushes state.
 /// - Crash-safe: state persisted after each compacted file creation.
 pub fn compact_size_files(input_dir: &str, output_dir: &str, target_size: u8, batch_size: u64, max_batch: Option<u32>) -> std::io::Result<()> {
+    let batch_size = compact_batch_size(batch_size);
     test_print(&format!("\nCompacting files for size {:02} (multiple batches)...", target_size));
     test_print(&format!("Target batch size: {} lists per file", batch_size.separated_string()));
     if let Some(max) = max_batch {
@@ -96,227 +814,746 @@ pub fn compact_size_files(input_dir: &str, output_dir: &str, target_size: u8, ba
 
     // Load GlobalFileState from JSON/TXT/intermediary/rkyv scan
     let mut state = GlobalFileState::from_sources(input_dir, target_size)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to load state: {}", e)))?;
+        .map_err(|e| std::io::Error::other(format!("Failed to load state: {}", e)))?;
+    state.set_max_lists_per_file(Some(batch_size));
 
     // Run the compaction logic in a closure so we can always export at the end
     let result = (|| -> std::io::Result<u32> {
+    recover_interrupted_compaction(input_dir, target_size, &mut state)?;
     let mut total_compacted_files = 0;
     let mut iteration = 0;
+    let started = std::time::Instant::now();
+    let mut lists_to_compact: Option<u64> = None; // non-compacted lists at the first iteration
+    let mut lists_compacted: u64 = 0;
+    let mut seen: Option<HashSet<u128>> = dedupe().then(HashSet::new); // card sets compacted so far (--dedupe)
+    let mut duplicates_dropped: u64 = 0;
 
     // Loop to create multiple compacted files until nothing left to compact
     loop {
         iteration += 1;
         test_print(&format!("\n--- Compaction iteration {} ---", iteration));
+        let Some(mut job) = plan_job(&state, input_dir, target_size, batch_size, max_batch, false)? else {
+            break;
+        };
+        let lists_to_compact = *lists_to_compact.get_or_insert(job.planned_lists);
+        let planned = job.intent.nb_lists;
+        let is_full = job.intent.is_full;
 
-        // Rebuild plan from current state (may have changed after previous iteration)
-        let mut plan: Vec<(String, u64, u32, u32)> = Vec::new(); // (filename, count, src_batch, tgt_batch)
-        for ((src, tgt, _), info) in state.entries().iter() {
-            if !info.compacted {
-                // If max_batch is specified, only include files with tgt_batch <= max_batch
-                if let Some(max) = max_batch {
-                    if *tgt <= max {
-                        plan.push((info.filename.clone(), info.nb_lists_in_file, *src, *tgt));
-                    }
-                } else {
-                    plan.push((info.filename.clone(), info.nb_lists_in_file, *src, *tgt));
-                }
-            }
+        // The compacted file and the rewrite of a partial origin take about the size of the origins
+        let needed: u64 = job.contribs.iter().filter_map(|c| crate::storage::file_metadata(&c.path)).map(|(bytes, _)| bytes).sum();
+        if !has_room_for(input_dir, needed) {
+            test_print("   Stopping compaction: not enough disk space for the next compacted file (state saved)");
+            break;
         }
 
-        // If no non-compacted files left, we're done
-        if plan.is_empty() {
-            test_print("   No more non-compacted files to compact.");
-            break;
+        // Journal the intent before touching anything (see recover_interrupted_compaction)
+        job.intent.save(input_dir, target_size)?;
+        let (rewrite, duplicates) = write_job(&mut job, seen.as_mut())?;
+        if duplicates > 0 {
+            job.intent.save(input_dir, target_size)?; // with the lists kept
+            duplicates_dropped += duplicates;
         }
+        let filled = job.intent.nb_lists;
+        state.journal(&JournalOp::Register { filename: job.intent.compacted.clone(), source_batch: job.intent.source_batch,
+            target_batch: job.intent.target_batch, nb_lists: filled, compacted: is_full })?;
+        apply_job(&mut state, &job, rewrite)?;
 
-        // If only ONE non-compacted file remains, there's nothing to compact - stop here
-        if plan.len() == 1 {
-            test_print(&format!("   Only one non-compacted file remains ({}); nothing to compact.", plan[0].0));
+        total_compacted_files += 1;
+        test_print(&format!("   Compacted file #{} created: {}", total_compacted_files, job.output_filename));
+        test_print(&format!("   Lists in compacted file: {}", filled.separated_string()));
+        lists_compacted += planned;
+        let elapsed = started.elapsed().as_secs_f64();
+        let eta = eta_secs(lists_compacted, lists_to_compact.saturating_sub(lists_compacted), elapsed)
+            .map(format_duration).unwrap_or_else(|| "unknown".to_string());
+        test_print(&format!("   ... progress: {:.1}% ({} of {} lists), ETA {}",
+            lists_compacted as f64 * 100.0 / lists_to_compact.max(1) as f64,
+            lists_compacted.separated_string(), lists_to_compact.separated_string(), eta));
+        
+        // If we created a partial file and max_batch is set, stop here
+        // The partial file will be picked up in the next compaction wave
+        if !is_full && max_batch.is_some() {
+            test_print("   Stopping compaction: created partial file at max_batch limit");
             break;
         }
+    }
 
-        // Order by target_batch then source_batch (ascending)
-        plan.sort_by(|a, b| match a.3.cmp(&b.3) { std::cmp::Ordering::Equal => a.2.cmp(&b.2), other => other });
+    if seen.is_some() {
+        test_print(&format!("   Deduplication: {} duplicate lists dropped (state counts adjusted)",
+            duplicates_dropped.separated_string()));
+    }
+    Ok(total_compacted_files)
+    })(); // End of compaction closure
 
-        // Determine next compacted batch index from state (more reliable than disk scan)
-        let mut next_compact_idx: u32 = 0;
-        for ((_, tgt, _), info) in state.entries().iter() {
-            if info.compacted {
-                next_compact_idx = next_compact_idx.max(tgt + 1);
-            }
+    let elapsed = start_time.elapsed().as_secs_f64();
+    
+    // Always export human-readable state files (JSON and TXT) regardless of success/failure
+    test_print(&format!("\nExporting global state files for size {:02}...", target_size));
+    match state.export_human_readable() {
+        Ok(_) => test_print(&format!("Exported: {}/nsl_{:02}_global_info.json and .txt", output_dir, target_size)),
+        Err(e) => crate::findings::warn("export", format!("Failed to export JSON/TXT: {}", e)),
+    }
+
+    // Now check the result of the compaction
+    match result {
+        Ok(total_compacted_files) => {
+            test_print(&format!("\nCompaction completed in {:.2} seconds", elapsed));
+            test_print(&format!("   Total compacted files created: {}", total_compacted_files));
+            Ok(())
+        },
+        Err(e) => {
+            test_print(&format!("\nCompaction encountered error after {:.2} seconds", elapsed));
+            Err(e)
         }
-        test_print(&format!("   Next compacted index (from state): {:06}", next_compact_idx));
-
-        // Accumulate lists up to batch_size
-        let mut buffer: Vec<NoSetListSerialized> = Vec::new();
-        let mut contribs: Vec<(u32, u64)> = Vec::new();
-        let mut touched_files: Vec<(String, usize, usize, u32)> = Vec::new(); // (path, consumed, total, src_batch)
-        let source_size = target_size - 1;
-        const READ_CHUNK_SIZE: usize = 2_000_000;
-
-    for (fname, _count, src_batch, _tgt_batch) in plan.iter() {
-        if buffer.len() as u64 >= batch_size { break; }
-        let path = format!("{}/{}", input_dir, fname);
-        let all_lists = crate::io_helpers::load_lists_from_file(&path)?;
-        let total = all_lists.len();
-        let mut consumed = 0usize;
-
-        while consumed < total && (buffer.len() as u64) < batch_size {
-            let take = std::cmp::min(READ_CHUNK_SIZE, total - consumed);
-            let chunk = &all_lists[consumed..consumed + take];
-            let space_left = (batch_size as usize) - buffer.len();
-            let take_now = std::cmp::min(space_left, chunk.len());
-            buffer.extend_from_slice(&chunk[..take_now]);
-            consumed += take_now;
-
-            // track contribs
-            if let Some(entry) = contribs.iter_mut().find(|e| e.0 == *src_batch) {
-                entry.1 += take_now as u64;
-            } else {
-                contribs.push((*src_batch, take_now as u64));
+    }
+}
+
+/// Result of an out-of-place compaction
+#[derive(Debug, Clone)]
+pub struct CompactToReport {
+    pub size: u8,
+    pub sources: usize,
+    pub lists: u64,
+    pub outputs: Vec<String>,
+    pub crc32: u32,
+    pub originals_deleted: bool,
+    pub stopped_low_space: bool, // sources left for the next run (--compact-min-free-gb)
+}
+
+/// Lists decoded at once when the written files are re-read for verification
+const VERIFY_CHUNK_LISTS: usize = 1_000_000;
+
+/// Feed one list into a checksum of a sequence of lists
+fn hash_list(hasher: &mut crc32fast::Hasher, list: &NoSetListSerialized) {
+    hasher.update(&[list.n]);
+    hasher.update(&(list.max_card as u64).to_le_bytes());
+    for card in list.no_set_list.iter().chain(list.remaining_cards_list.iter()) {
+        hasher.update(&[*card as u8]);
+    }
+}
+
+/// Check the checksum of the written file `output`, then feed its lists into `hasher`;
+/// returns the number of lists read
+fn read_back(output: &str, hasher: &mut crc32fast::Hasher) -> Result<u64, String> {
+    crate::io_helpers::verify_file_checksum(output).map_err(|e| format!("{}: {}", output, e))?;
+    crate::io_helpers::load_lists_in_chunks(output, VERIFY_CHUNK_LISTS, |lists| {
+        lists.iter().for_each(|list| hash_list(hasher, list));
+        Ok(())
+    }).map_err(|e| format!("{}: {}", output, e))
+}
+
+/// Remove the written files `outputs` from `state` and delete them
+fn discard_outputs(outputs: &[String], output_dir: &str, state: &mut GlobalFileState) {
+    for output in outputs.iter() {
+        let name = Path::new(output).file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let keys: Vec<(u32, u32)> = state.entries().keys()
+            .filter(|(_, _, f)| *f == name).map(|(src, tgt, _)| (*src, *tgt)).collect();
+        for (src, tgt) in keys {
+            state.remove_file(&name, src, tgt, RemovalReason::Manual);
+        }
+        let _ = crate::storage::remove_list_file(output);
+        remove_sources_sidecar(output_dir, &name);
+    }
+}
+
+/// Delete the compacted source `info` of `input_dir` and remove it from `state`
+fn delete_original(input_dir: &str, info: &FileInfo, state: &mut GlobalFileState) -> std::io::Result<()> {
+    let path = info.path_in(input_dir).to_string_lossy().into_owned();
+    state.journal(&JournalOp::Remove { filename: info.filename.clone(), source_batch: info.source_batch,
+        target_batch: info.target_batch })?;
+    crate::storage::remove_list_file(&path)?;
+    remove_sources_sidecar(input_dir, &info.filename);
+    state.remove_file(&info.filename, info.source_batch, info.target_batch, RemovalReason::CompactedAway);
+    Ok(())
+}
+
+/// --delete-early: check the file `output` just written against the lists put in it
+/// (`expected`: count, crc32), then delete the sources `copied` (all their lists in
+/// files verified by now). On a mismatch the file is discarded.
+fn verify_and_delete_early(input_dir: &str, output_dir: &str, output: &str, expected: (u64, u32), copied: &[FileInfo],
+    source_state: &mut GlobalFileState, target_state: &mut GlobalFileState) -> std::io::Result<()> {
+    let mut hasher = crc32fast::Hasher::new();
+    let problem = match read_back(output, &mut hasher) {
+        Ok(count) if (count, hasher.clone().finalize()) == expected => None,
+        Ok(count) => Some(format!("{}: {} lists (crc32 {:08x}) read back, {} lists (crc32 {:08x}) written", output,
+            count.separated_string(), hasher.finalize(), expected.0.separated_string(), expected.1)),
+        Err(problem) => Some(problem),
+    };
+    if let Some(problem) = problem {
+        test_print(&format!("   Verification FAILED: {}; deleting it", problem));
+        discard_outputs(&[output.to_string()], output_dir, target_state);
+        target_state.flush()?;
+        target_state.export_human_readable()?;
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!(
+            "Compaction verification failed: {} (sources deleted so far stay deleted; the source being copied may \
+            have lists in the previous compacted file)", problem)));
+    }
+    for info in copied.iter() {
+        delete_original(input_dir, info, source_state)?;
+    }
+    source_state.flush()
+}
+
+/// Where out-of-place compaction writes its files: directory, size, lists per
+/// file, and the index of the next compacted file
+struct CompactedDestination<'a> {
+    dir: &'a str,
+    target_size: u8,
+    batch_size: u64,
+    next_idx: u32,
+}
+
+impl CompactedDestination<'_> {
+    /// Write `buffer` as the next compacted file and register it, with the source
+    /// batches of its lists (`sources`), in `state`
+    fn write(&mut self, from_src: u32, buffer: &mut Vec<NoSetListSerialized>, sources: &mut Vec<SourceContribution>,
+        state: &mut GlobalFileState) -> std::io::Result<String> {
+        let (output_dir, target_size) = (self.dir, self.target_size);
+        let is_full = (buffer.len() as u64) >= self.batch_size;
+        let mut output_filename = compacted_output_filename(output_dir, target_size - 1, from_src, target_size, self.next_idx, is_full);
+        while crate::storage::list_file_exists(&output_filename) {
+            test_print(&format!("   Compacted file {} already exists, trying next index", output_filename));
+            self.next_idx += 1;
+            output_filename = compacted_output_filename(output_dir, target_size - 1, from_src, target_size, self.next_idx, is_full);
+        }
+        test_print(&format!("   Writing compacted file {} ({} lists)", output_filename, buffer.len().separated_string()));
+        let basename = Path::new(&output_filename).file_name().unwrap().to_string_lossy().into_owned();
+        state.journal(&JournalOp::Register { filename: basename.clone(), source_batch: from_src,
+            target_batch: self.next_idx, nb_lists: buffer.len() as u64, compacted: is_full })?;
+        if !crate::io_helpers::save_to_file_serialized(buffer, &output_filename) {
+            return Err(std::io::Error::other(format!("Failed to write compacted file {}", output_filename)));
+        }
+        crate::events::file_processed(crate::events::FileProcessed::new("compact", target_size,
+            crate::events::FileAction::Written, basename.as_str(), Some(buffer.len() as u64)));
+        let metadata = crate::storage::file_metadata(&output_filename);
+        state.register_file(&basename, from_src, self.next_idx, buffer.len() as u64, is_full,
+            metadata.map(|(bytes, _)| bytes), metadata.and_then(|(_, modified)| modified));
+        record_sources(state, output_dir, &basename, std::mem::take(sources))?;
+        state.flush()?;
+        self.next_idx += 1;
+        buffer.clear();
+        Ok(output_filename)
+    }
+}
+
+/// Out-of-place compaction: compact the non-compacted size `target_size` files of
+/// `input_dir` into `output_dir`, then re-read the written files and check their
+/// list count and checksum against the sources. Only then, if `delete_originals`,
+/// the sources are deleted and removed from the input state; if the check fails the
+/// written files are removed from the output state and deleted, the sources untouched.
+pub fn compact_size_files_to(input_dir: &str, output_dir: &str, target_size: u8, batch_size: u64,
+    max_batch: Option<u32>, delete_originals: bool) -> std::io::Result<CompactToReport> {
+    let batch_size = compact_batch_size(batch_size);
+    test_print(&format!("\nCompacting files for size {:02} out of place...", target_size));
+    test_print(&format!("   From: {}", input_dir));
+    test_print(&format!("   To:   {}", output_dir));
+    test_print(&format!("Target batch size: {} lists per file", batch_size.separated_string()));
+    std::fs::create_dir_all(output_dir)?;
+
+    let mut source_state = GlobalFileState::from_sources(input_dir, target_size)?;
+    let mut target_state = GlobalFileState::from_sources(output_dir, target_size)?;
+    let mut plan: Vec<FileInfo> = source_state.entries().values()
+        .filter(|info| !info.compacted && max_batch.is_none_or(|max| info.target_batch <= max))
+        .cloned()
+        .collect();
+    plan.sort_by(|a, b| a.target_batch.cmp(&b.target_batch).then(a.source_batch.cmp(&b.source_batch)));
+    let mut report = CompactToReport { size: target_size, sources: plan.len(), lists: 0, outputs: Vec::new(),
+        crc32: 0, originals_deleted: false, stopped_low_space: false };
+    if (plan.len() as u64) < compact_min_files() {
+        test_print(&format!("   Only {} non-compacted files (--compact-min-files {}); nothing to compact.",
+            plan.len(), compact_min_files()));
+        return Ok(report);
+    }
+
+    // Copy: the sources are only read
+    let started = std::time::Instant::now();
+    let lists_to_compact: u64 = plan.iter().map(|info| info.nb_lists_in_file).sum();
+    let next_idx = target_state.entries().values()
+        .filter(|info| info.compacted)
+        .map(|info| info.target_batch + 1)
+        .max()
+        .unwrap_or(0);
+    let mut dest = CompactedDestination { dir: output_dir, target_size, batch_size, next_idx };
+    let mut source_hash = crc32fast::Hasher::new();
+    let mut buffer: Vec<NoSetListSerialized> = Vec::new();
+    let mut buffer_sources: Vec<SourceContribution> = Vec::new();
+    let early = delete_originals && DELETE_EARLY.load(Ordering::Relaxed);
+    let mut buffer_hash = crc32fast::Hasher::new(); // lists of the buffer (--delete-early)
+    let mut read: Vec<FileInfo> = Vec::new(); // sources fully copied, not deleted yet (--delete-early)
+    let mut copied = 0;
+    for (i, info) in plan.iter().enumerate() {
+        let path = info.path_in(input_dir).to_string_lossy().into_owned();
+        // The source and the buffered lists may all be written before the next check
+        let bytes = crate::storage::file_metadata(&path).map_or(0, |(bytes, _)| bytes);
+        if !has_room_for(output_dir, bytes + bytes * buffer.len() as u64 / info.nb_lists_in_file.max(1)) {
+            test_print(&format!("   Stopping: not enough disk space in {}; {} sources left for the next run",
+                output_dir, plan.len() - i));
+            report.stopped_low_space = true;
+            break;
+        }
+        let lists = crate::io_helpers::load_lists_from_file(&path)?;
+        if lists.len() as u64 != info.nb_lists_in_file {
+            test_print(&format!("   Warning: {} holds {} lists, the state records {}", info.filename,
+                lists.len().separated_string(), info.nb_lists_in_file.separated_string()));
+        }
+        crate::events::file_processed(crate::events::FileProcessed::new("compact", target_size,
+            crate::events::FileAction::Read, info.filename.as_str(), Some(lists.len() as u64)));
+        test_print(&format!("   Copied {:>10} lists from {}", lists.len().separated_string(), info.filename));
+        let file_sources = source_state.sources_of(info);
+        let mut batches = file_sources.iter().flat_map(|s| std::iter::repeat_n(s.source_batch, s.nb_lists as usize));
+        for list in lists {
+            hash_list(&mut source_hash, &list);
+            if early {
+                hash_list(&mut buffer_hash, &list);
             }
+            report.lists += 1;
+            buffer.push(list);
+            push_source(&mut buffer_sources, batches.next().unwrap_or(info.source_batch), 1);
+            if buffer.len() as u64 >= batch_size {
+                let expected = (buffer.len() as u64, std::mem::take(&mut buffer_hash).finalize());
+                let output = dest.write(info.source_batch, &mut buffer, &mut buffer_sources, &mut target_state)?;
+                if early {
+                    verify_and_delete_early(input_dir, output_dir, &output, expected, &std::mem::take(&mut read),
+                        &mut source_state, &mut target_state)?;
+                }
+                report.outputs.push(output);
+                let eta = eta_secs(report.lists, lists_to_compact.saturating_sub(report.lists), started.elapsed().as_secs_f64())
+                    .map(format_duration).unwrap_or_else(|| "unknown".to_string());
+                test_print(&format!("   ... progress: {} of {} lists, ETA {}", report.lists.separated_string(),
+                    lists_to_compact.separated_string(), eta));
+            }
+        }
+        copied = i + 1;
+        if early {
+            read.push(info.clone());
+        }
+    }
+    plan.truncate(copied);
+    report.sources = plan.len();
+    if !buffer.is_empty() {
+        let from_src = plan.last().map(|info| info.source_batch).unwrap_or(0);
+        let expected = (buffer.len() as u64, std::mem::take(&mut buffer_hash).finalize());
+        let output = dest.write(from_src, &mut buffer, &mut buffer_sources, &mut target_state)?;
+        if early {
+            verify_and_delete_early(input_dir, output_dir, &output, expected, &std::mem::take(&mut read),
+                &mut source_state, &mut target_state)?;
         }
+        report.outputs.push(output);
+    }
+    report.crc32 = source_hash.finalize();
+    if early {
+        source_state.export_human_readable()?;
+        target_state.export_human_readable()?;
+        report.originals_deleted = true;
+        test_print(&format!("   ... verified {} compacted files one by one; deleted the {} originals from {}",
+            report.outputs.len(), plan.len(), input_dir));
+        return Ok(report);
+    }
 
-        touched_files.push((path, consumed, total, *src_batch));
-            if consumed > 0 {
-                test_print(&format!("   Copied {:>10} lists from {}", consumed.separated_string(), fname));
+    // Verify: re-read what was written
+    test_print(&format!("   Verifying {} compacted files against {} sources...", report.outputs.len(), plan.len()));
+    let mut written_hash = crc32fast::Hasher::new();
+    let mut written_lists = 0u64;
+    let mut problem: Option<String> = None;
+    for output in report.outputs.iter() {
+        match read_back(output, &mut written_hash) {
+            Ok(count) => written_lists += count,
+            Err(e) => {
+                problem = Some(e);
+                break;
             }
         }
+    }
+    let written_crc = written_hash.finalize();
+    if problem.is_none() && (written_lists, written_crc) != (report.lists, report.crc32) {
+        problem = Some(format!("{} lists (crc32 {:08x}) written, {} lists (crc32 {:08x}) read from the sources",
+            written_lists.separated_string(), written_crc, report.lists.separated_string(), report.crc32));
+    }
+    if let Some(problem) = problem {
+        test_print(&format!("   Verification FAILED: {}; deleting the compacted files, sources untouched", problem));
+        discard_outputs(&report.outputs, output_dir, &mut target_state);
+        target_state.flush()?;
+        target_state.export_human_readable()?;
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Compaction verification failed: {}", problem)));
+    }
+    test_print(&format!("   ... verified: {} lists, crc32 {:08x}", report.lists.separated_string(), report.crc32));
 
-        if buffer.is_empty() {
-            test_print("   Nothing to compact in this iteration (no more files or batch_size met).");
-            break; // Exit the loop if no more files to compact
+    if delete_originals {
+        for info in plan.iter() {
+            delete_original(input_dir, info, &mut source_state)?;
         }
+        source_state.flush()?;
+        source_state.export_human_readable()?;
+        report.originals_deleted = true;
+        test_print(&format!("   ... deleted the {} originals from {}", plan.len(), input_dir));
+    } else {
+        test_print("   ... originals kept (--delete-originals deletes them once verified); their lists are now in both directories");
+    }
+    target_state.export_human_readable()?;
+    Ok(report)
+}
 
-        // Determine output filename using the last contributor src batch
-        let from_src = contribs.last().map(|c| c.0).unwrap_or(0);
-        let is_full = (buffer.len() as u64) >= batch_size;
+/// Files of a size by magnitude of their list count (fragmentation statistics)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Fragmentation {
+    pub files: u64,
+    pub compacted_files: u64,
+    pub lists: u64,
+    pub by_magnitude: BTreeMap<u32, (u64, u64)>, // digits of the list count -> (files, lists)
+}
 
-        // Find first available index if calculated one already exists
-        let mut final_compact_idx = next_compact_idx;
-        let mut output_filename = if is_full {
-            format!("{}/nsl_{:02}_batch_{:06}_to_{:02}_batch_{:06}_compacted.rkyv", output_dir, source_size, from_src, target_size, final_compact_idx)
-        } else {
-            format!("{}/nsl_{:02}_batch_{:06}_to_{:02}_batch_{:06}.rkyv", output_dir, source_size, from_src, target_size, final_compact_idx)
-        };
-        
-        // Find first available index (idempotent: skip existing files)
-        const MAX_INDEX_SEARCH: u32 = 1000;
-        while Path::new(&output_filename).exists() && final_compact_idx < next_compact_idx + MAX_INDEX_SEARCH {
-            test_print(&format!("   Compacted file {} already exists, trying next index", output_filename));
-            final_compact_idx += 1;
-            output_filename = if is_full {
-                format!("{}/nsl_{:02}_batch_{:06}_to_{:02}_batch_{:06}_compacted.rkyv", output_dir, source_size, from_src, target_size, final_compact_idx)
-            } else {
-                format!("{}/nsl_{:02}_batch_{:06}_to_{:02}_batch_{:06}.rkyv", output_dir, source_size, from_src, target_size, final_compact_idx)
-            };
+impl Fragmentation {
+    pub fn of(state: &GlobalFileState) -> Self {
+        let mut fragmentation = Fragmentation::default();
+        for info in state.entries().values() {
+            let lists = info.nb_lists_in_file;
+            fragmentation.files += 1;
+            fragmentation.compacted_files += u64::from(info.compacted);
+            fragmentation.lists += lists;
+            let bucket = fragmentation.by_magnitude.entry(lists.max(1).ilog10() + 1).or_insert((0, 0));
+            bucket.0 += 1;
+            bucket.1 += lists;
         }
-        
-        if Path::new(&output_filename).exists() {
-            test_print(&format!("   Could not find available index after {} tries, stopping", MAX_INDEX_SEARCH));
+        fragmentation
+    }
+
+    pub fn print(&self, label: &str) {
+        test_print(&format!("   {}: {} files ({} compacted), {} lists, {} lists per file on average", label,
+            self.files.separated_string(), self.compacted_files.separated_string(), self.lists.separated_string(),
+            self.lists.checked_div(self.files).unwrap_or(0).separated_string()));
+        let percent = |part: u64, whole: u64| if whole == 0 { 0.0 } else { 100.0 * part as f64 / whole as f64 };
+        for (digits, (files, lists)) in self.by_magnitude.iter() {
+            let low = if *digits == 1 { 0 } else { 10u64.pow(digits - 1) };
+            let range = format!("{}-{}", low.separated_string(), (10u64.pow(*digits) - 1).separated_string());
+            test_print(&format!("      {:>21} lists per file: {:>9} files ({:5.1}%), {:>15} lists ({:5.1}%)",
+                range, files.separated_string(), percent(*files, self.files),
+                lists.separated_string(), percent(*lists, self.lists)));
+        }
+    }
+}
+
+/// Outcome of a planned compaction (--compact --dry-run)
+#[derive(Debug, Clone, Default)]
+pub struct CompactionEfficiency {
+    pub full_files: u64,    // compacted files created
+    pub partial_files: u64, // partial files created (not marked compacted)
+    pub deleted: u64,       // origins fully consumed
+    pub rewritten: u64,     // origins partially consumed
+    pub before: Fragmentation,
+    pub after: Fragmentation,
+}
+
+impl CompactionEfficiency {
+    pub fn print(&self) {
+        test_print(&format!("
+Compaction efficiency: {} compacted files would be created ({} full, {} partial); {} origins deleted, {} rewritten",
+            (self.full_files + self.partial_files).separated_string(), self.full_files.separated_string(),
+            self.partial_files.separated_string(), self.deleted.separated_string(), self.rewritten.separated_string()));
+        self.before.print("before");
+        self.after.print("after ");
+    }
+}
+
+/// Plan of `compact_size_files` for `dir` (--dry-run): the compaction loop replayed
+/// on the state counts, without reading or writing any list file
+pub fn plan_compaction(dir: &str, target_size: u8, batch_size: u64, max_batch: Option<u32>) -> std::io::Result<DryRunPlan> {
+    plan_compaction_report(dir, target_size, batch_size, max_batch).map(|(plan, _)| plan)
+}
+
+/// plan_compaction with the files it would create and the fragmentation of the
+/// size before and after (--compact --dry-run)
+pub fn plan_compaction_report(dir: &str, target_size: u8, batch_size: u64, max_batch: Option<u32>)
+    -> std::io::Result<(DryRunPlan, CompactionEfficiency)> {
+    let batch_size = compact_batch_size(batch_size);
+    let mut plan = DryRunPlan::new(&format!("compaction of size {:02} in {}", target_size, dir));
+    let mut state = crate::dry_run::load_state_readonly(dir, target_size)?;
+    let source_size = target_size - 1;
+    let mut planned_names: Vec<String> = Vec::new();
+    let mut efficiency = CompactionEfficiency { before: Fragmentation::of(&state), ..Default::default() };
+
+    loop {
+        let mut candidates: Vec<(String, u64, u32, u32)> = state.entries().iter()
+            .filter(|((_, tgt, _), info)| !info.compacted && max_batch.is_none_or(|max| *tgt <= max))
+            .map(|((src, tgt, _), info)| (info.filename.clone(), info.nb_lists_in_file, *src, *tgt))
+            .collect();
+        if (candidates.len() as u64) < compact_min_files() {
             break;
         }
+        candidates.sort_by(|a, b| a.3.cmp(&b.3).then(a.2.cmp(&b.2)));
+        let next_compact_idx = state.entries().iter()
+            .filter(|(_, info)| info.compacted)
+            .map(|((_, tgt, _), _)| tgt + 1)
+            .max()
+            .unwrap_or(0);
 
-        test_print(&format!("   Writing compacted file {} ({} lists)", output_filename, buffer.len().separated_string()));
-        if !crate::io_helpers::save_to_file_serialized(&buffer, &output_filename) {
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, "Failed to write compacted file"));
+        // Same accumulation as the real loop, on counts
+        let mut filled = 0u64;
+        let mut touched: Vec<(String, u64, u64, u32, u32)> = Vec::new(); // (filename, consumed, total, src, tgt)
+        for (filename, count, src, tgt) in candidates.iter() {
+            if filled >= batch_size { break; }
+            let take = (*count).min(batch_size - filled);
+            filled += take;
+            touched.push((filename.clone(), take, *count, *src, *tgt));
+        }
+        if filled == 0 {
+            break;
+        }
+        let from_src = touched.iter().rev().find(|t| t.1 > 0).map_or(0, |t| t.3);
+        let is_full = filled >= batch_size;
+        let mut idx = next_compact_idx;
+        let mut output = compacted_output_filename(dir, source_size, from_src, target_size, idx, is_full);
+        while crate::storage::list_file_exists(&output) || planned_names.contains(&output) {
+            idx += 1;
+            output = compacted_output_filename(dir, source_size, from_src, target_size, idx, is_full);
         }
 
-        // Register the new compacted file in state IMMEDIATELY after writing
-        let compact_basename = Path::new(&output_filename).file_name().unwrap().to_string_lossy().into_owned();
-        let file_size = std::fs::metadata(&output_filename).ok().map(|m| m.len());
-        let mtime = std::fs::metadata(&output_filename).ok().and_then(|m| m.modified().ok()).and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok()).map(|d| d.as_secs() as i64);
-        
-        // Only mark as "compacted" if file is full (>= 10M lists)
-        // Partial files are NOT marked as compacted so they can be merged with future files
-        if !is_full {
-            test_print(&format!("   Note: File has {} lists (< 10M); NOT marking as compacted for future merging", buffer.len().separated_string()));
+        for (filename, consumed, _, _, _) in touched.iter().filter(|t| t.1 > 0) {
+            plan.add(Operation::Read, Path::new(dir).join(filename), format!("{} lists taken", consumed.separated_string()));
         }
-        
-        state.register_file(
-            &compact_basename,
-            from_src,
-            final_compact_idx,
-            buffer.len() as u64,
-            is_full,  // Only full files are marked as compacted
-            file_size,
-            mtime,
-        );
-        test_print(&format!("   Registered file in state (compacted={})", is_full));
-
-        // Flush state IMMEDIATELY (crash-safe checkpoint before modifying original files)
-        state.flush()
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to flush state after compacted file: {}", e)))?;
-        test_print("   Flushed state to rkyv (compacted file recorded)");
-
-        // Now safe to modify original files (if crash happens here, compacted file is already in state)
-        for (path, consumed, total, src_batch) in touched_files.iter() {
-            let basename = Path::new(path).file_name().unwrap().to_string_lossy().into_owned();
-            
-            // Extract target batch from the file for state management
-            let tgt_batch = plan.iter().find(|(fname, _, _, _)| fname == &basename).map(|(_, _, _, t)| *t).unwrap_or(0);
-            
-            if *consumed >= *total {
-                test_print(&format!("   Origin file {} fully consumed; deleting", path));
-                std::fs::remove_file(path)?;
-                
-                // Remove from state using proper API
-                state.remove_file(&basename, *src_batch, tgt_batch);
+        let grouped: Vec<_> = touched.iter().filter(|t| t.1 > 0).collect();
+        let first_batch = grouped.iter().map(|t| t.4).min().unwrap_or(0);
+        plan.add(Operation::Write, &output, format!("{} lists from {} files (batches {:06}-{:06}){}",
+            filled.separated_string(), grouped.len(), first_batch, grouped.iter().map(|t| t.4).max().unwrap_or(0),
+            if is_full { "" } else { " (partial: not marked compacted)" }));
+        if is_full { efficiency.full_files += 1 } else { efficiency.partial_files += 1 }
+        let basename = Path::new(&output).file_name().unwrap_or_default().to_string_lossy().into_owned();
+        state.register_file(&basename, from_src, idx, filled, is_full, None, None);
+        planned_names.push(output);
+
+        for (filename, consumed, total, src, tgt) in touched.iter() {
+            let path = Path::new(dir).join(filename);
+            if consumed >= total {
+                plan.add(Operation::Delete, path, "fully consumed");
+                efficiency.deleted += 1;
+                state.remove_file(filename, *src, *tgt, RemovalReason::CompactedAway);
             } else {
-                let remaining_count = *total - *consumed;
-                test_print(&format!("   Origin file {} partially consumed; rewriting {} remaining lists", path, remaining_count.separated_string()));
-                let remaining_slice = &crate::io_helpers::load_lists_from_file(path)?; // reload to avoid moved ownership
-                let remaining: Vec<NoSetListSerialized> = remaining_slice[*consumed..].to_vec();
-                if !crate::io_helpers::save_to_file_serialized(&remaining, path) {
-                    return Err(std::io::Error::new(std::io::ErrorKind::Other, "Failed to rewrite origin file"));
-                }
-                
-                // Update state with new count using proper API
-                state.update_count(&basename, *src_batch, tgt_batch, remaining_count as u64);
+                plan.add(Operation::Rewrite, path, format!("{} lists left", (total - consumed).separated_string()));
+                efficiency.rewritten += 1;
+                state.update_count(filename, *src, *tgt, total - consumed);
             }
         }
 
-        // Final flush to record all file modifications (deletions/shrinks) for this iteration
-        state.flush()
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to flush state after file modifications: {}", e)))?;
-        test_print("   Flushed state to rkyv (file modifications recorded)");
-
-        total_compacted_files += 1;
-        test_print(&format!("   Compacted file #{} created: {}", total_compacted_files, output_filename));
-        test_print(&format!("   Lists in compacted file: {}", buffer.len().separated_string()));
-        
-        // If we created a partial file and max_batch is set, stop here
-        // The partial file will be picked up in the next compaction wave
         if !is_full && max_batch.is_some() {
-            test_print(&format!("   Stopping compaction: created partial file at max_batch limit"));
             break;
         }
     }
+    if plan.operations.is_empty() {
+        plan.note("nothing to compact");
+    } else {
+        plan.note("the state is flushed after every compacted file");
+    }
+    crate::dry_run::add_state_writes(&mut plan, dir, target_size, false);
+    efficiency.after = Fragmentation::of(&state);
+    Ok((plan, efficiency))
+}
 
-    Ok(total_compacted_files)
-    })(); // End of compaction closure
+/// Lists per file of a rebalance (--rebalance)
+#[derive(Debug, Clone)]
+pub struct RebalanceReport {
+    pub size: u8,
+    pub target_lists: u64,
+    pub files_kept: usize,      // leading compacted files already at the target
+    pub files_rewritten: usize, // compacted files rewritten
+    pub files_created: usize,   // files replacing them (the last one partial if short)
+    pub lists: u64,             // lists rewritten
+}
 
-    let elapsed = start_time.elapsed().as_secs_f64();
-    
-    // Always export human-readable state files (JSON and TXT) regardless of success/failure
-    test_print(&format!("\nExporting global state files for size {:02}...", target_size));
-    match state.export_human_readable() {
-        Ok(_) => test_print(&format!("Exported: {}/nsl_{:02}_global_info.json and .txt", output_dir, target_size)),
-        Err(e) => test_print(&format!("Warning: Failed to export JSON/TXT: {}", e)),
+/// File written by a rebalance: staged under a temporary name, then renamed to `filename`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RebalancedFile {
+    staged: String,
+    filename: String,
+    source_batch: u32,
+    target_batch: u32,
+    nb_lists: u64,
+    is_full: bool,
+    sources: Vec<SourceContribution>,
+}
+
+/// One rebalance, journaled once every new file is staged and deleted once the
+/// staged files are renamed, the rewritten files deleted and the state flushed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RebalanceIntent {
+    files: Vec<RebalancedFile>,
+    origins: Vec<(String, u32, u32)>, // rewritten compacted files (filename, source batch, target batch)
+}
+
+/// Rebalance journal of size `size` in `dir`
+pub fn rebalance_journal_path(dir: &str, size: u8) -> std::path::PathBuf {
+    Path::new(dir).join(format!("nsl_{:02}_rebalance.journal", size))
+}
+
+/// Temporary name of the `index`-th file written by a rebalance of `size` in `dir`
+fn rebalance_staged_filename(dir: &str, size: u8, index: usize) -> String {
+    format!("{}/nsl_{:02}_rebalance_{:06}.staged", dir, size, index)
+}
+
+/// Rename the staged files of `intent` into place, then delete the rewritten files
+/// they do not replace and update `state`. Idempotent: also finishes a rebalance
+/// interrupted after its journal was written.
+fn finish_rebalance(dir: &str, size: u8, intent: &RebalanceIntent, state: &mut GlobalFileState) -> std::io::Result<()> {
+    for file in intent.files.iter() {
+        let path = format!("{}/{}", dir, file.filename);
+        if crate::storage::list_file_exists(&file.staged) {
+            crate::io_helpers::commit_atomic_write(&file.staged, &path)?;
+            crate::io_helpers::invalidate_cached_batch(&path);
+        } else if !crate::storage::list_file_exists(&path) {
+            return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!(
+                "Rebalanced file {} and its staged copy {} are both missing; fix it by hand, then delete {}",
+                file.filename, file.staged, rebalance_journal_path(dir, size).display())));
+        }
     }
+    for (filename, src, tgt) in intent.origins.iter() {
+        if !intent.files.iter().any(|f| f.filename == *filename) {
+            let path = format!("{}/{}", dir, filename);
+            if crate::storage::list_file_exists(&path) {
+                crate::storage::remove_list_file(&path)?;
+                crate::io_helpers::invalidate_cached_batch(&path);
+            }
+            remove_sources_sidecar(dir, filename);
+        }
+        if state.has_entry(filename, *src, *tgt) {
+            state.remove_file(filename, *src, *tgt, RemovalReason::CompactedAway);
+        }
+        state.set_compacted_sources(filename, Vec::new());
+    }
+    for file in intent.files.iter() {
+        let metadata = crate::storage::file_metadata(&format!("{}/{}", dir, file.filename));
+        state.register_file(&file.filename, file.source_batch, file.target_batch, file.nb_lists, file.is_full,
+            metadata.map(|(bytes, _)| bytes), metadata.and_then(|(_, modified)| modified));
+        record_sources(state, dir, &file.filename, file.sources.clone())?;
+    }
+    state.flush()?;
+    let path = rebalance_journal_path(dir, size);
+    if path.exists() { std::fs::remove_file(&path) } else { Ok(()) }
+}
 
-    // Now check the result of the compaction
-    match result {
-        Ok(total_compacted_files) => {
-            test_print(&format!("\nCompaction completed in {:.2} seconds", elapsed));
-            test_print(&format!("   Total compacted files created: {}", total_compacted_files));
+/// Finish the rebalance journaled in `dir`, or drop the files staged by one that
+/// stopped before its journal was written (the compacted files are then untouched)
+fn recover_interrupted_rebalance(dir: &str, size: u8, state: &mut GlobalFileState) -> std::io::Result<()> {
+    let path = rebalance_journal_path(dir, size);
+    if path.exists() {
+        let text = std::fs::read_to_string(&path)?;
+        let intent: RebalanceIntent = serde_json::from_str(&text).map_err(|e| std::io::Error::new(
+            std::io::ErrorKind::InvalidData, format!("Unreadable rebalance journal {}: {}", path.display(), e)))?;
+        test_print(&format!("   Interrupted rebalance found ({}): finishing it ({} files)", path.display(), intent.files.len()));
+        return finish_rebalance(dir, size, &intent, state);
+    }
+    let prefix = format!("nsl_{:02}_rebalance_", size);
+    for entry in std::fs::read_dir(dir).into_iter().flatten().flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with(&prefix) && name.ends_with(".staged") {
+            test_print(&format!("   ... deleting {} (staged by an interrupted rebalance)", name));
+            let _ = crate::storage::remove_list_file(&entry.path().to_string_lossy());
+        }
+    }
+    Ok(())
+}
+
+/// Rewrite the compacted files of `target_size` in `dir` into files of exactly
+/// `batch_size` lists (--compact-size, else the lists per output file): the leading
+/// files already at that size are kept, the others are streamed in target batch
+/// order into new files numbered on from the first one rewritten. A short last file
+/// is written as a partial file (not marked compacted), for the next --compact.
+pub fn rebalance_compacted_files(dir: &str, target_size: u8, batch_size: u64) -> std::io::Result<RebalanceReport> {
+    let batch_size = compact_batch_size(batch_size);
+    test_print(&format!("\nREBALANCE MODE: Rewriting the size {:02} compacted files into files of {} lists...",
+        target_size, batch_size.separated_string()));
+    test_print(&format!("   Directory: {}", dir));
+    let start_time = std::time::Instant::now();
+    if CompactionIntent::load(dir, target_size)?.is_some() {
+        return Err(std::io::Error::other(format!("An interrupted compaction is journaled in {}: run --compact {} first",
+            compaction_journal_path(dir, target_size).display(), target_size)));
+    }
+
+    let mut state = GlobalFileState::from_sources(dir, target_size)?;
+    recover_interrupted_rebalance(dir, target_size, &mut state)?;
+    let mut compacted: Vec<FileInfo> = state.entries().values().filter(|info| info.compacted).cloned().collect();
+    compacted.sort_by_key(|info| (info.target_batch, info.source_batch));
+    let files_kept = compacted.iter().take_while(|info| info.nb_lists_in_file == batch_size).count();
+    let rewritten = compacted.split_off(files_kept);
+    let lists: u64 = rewritten.iter().map(|info| info.nb_lists_in_file).sum();
+    let mut report = RebalanceReport { size: target_size, target_lists: batch_size, files_kept,
+        files_rewritten: 0, files_created: 0, lists: 0 };
+    if rewritten.len() < 2 && lists <= batch_size {
+        test_print(&format!("   ... {} compacted files, already balanced", files_kept + rewritten.len()));
+        return Ok(report);
+    }
+    let first_batch = rewritten[0].target_batch;
+    test_print(&format!("   ... keeping {} compacted files; rewriting {} ({} lists) from batch {:06}",
+        files_kept, rewritten.len(), lists.separated_string(), first_batch));
+
+    // Stage the new files (nothing else is touched until the journal is written)
+    let mut files: Vec<RebalancedFile> = Vec::new();
+    let mut current: Option<(FrameBuffer, Vec<SourceContribution>, u64)> = None; // file being written, its sources and lists
+    let close = |(buffer, sources, _): (FrameBuffer, Vec<SourceContribution>, u64), files: &mut Vec<RebalancedFile>| -> std::io::Result<()> {
+        let nb_lists = buffer.finish()?;
+        let source_batch = sources.last().map_or(0, |s| s.source_batch);
+        files.push(RebalancedFile { staged: rebalance_staged_filename(dir, target_size, files.len()), filename: String::new(),
+            source_batch, target_batch: 0, nb_lists, is_full: nb_lists >= batch_size, sources });
+        Ok(())
+    };
+    for info in rewritten.iter() {
+        let path = format!("{}/{}", dir, info.filename);
+        let sources = state.sources_of(info);
+        let mut batches = sources.iter().flat_map(|s| std::iter::repeat_n(s.source_batch, s.nb_lists as usize));
+        crate::io_helpers::load_lists_in_chunks(&path, crate::io_helpers::FRAME_LISTS, |lists| {
+            for list in lists {
+                let (buffer, sources, pushed) = match current.as_mut() {
+                    Some(current) => current,
+                    None => current.insert((FrameBuffer::new(crate::io_helpers::ListFileWriter::create(
+                        &rebalance_staged_filename(dir, target_size, files.len()))?), Vec::new(), 0)),
+                };
+                buffer.push(list)?;
+                push_source(sources, batches.next().unwrap_or(info.source_batch), 1);
+                *pushed += 1;
+                if *pushed >= batch_size {
+                    close(current.take().expect("file being written"), &mut files)?;
+                }
+            }
             Ok(())
-        },
-        Err(e) => {
-            test_print(&format!("\nCompaction encountered error after {:.2} seconds", elapsed));
-            Err(e)
+        })?;
+    }
+    if let Some(last) = current.take() {
+        close(last, &mut files)?;
+    }
+    let written: u64 = files.iter().map(|f| f.nb_lists).sum();
+    if written != lists {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!(
+            "Rebalance wrote {} lists, {} recorded in the compacted files; nothing changed", written, lists)));
+    }
+
+    // Continuous numbering from the first batch rewritten (a partial file skips the names in use)
+    let origins: HashSet<&str> = rewritten.iter().map(|info| info.filename.as_str()).collect();
+    let mut idx = first_batch;
+    for file in files.iter_mut() {
+        let mut path = compacted_output_filename(dir, target_size - 1, file.source_batch, target_size, idx, file.is_full);
+        while !file.is_full && crate::storage::list_file_exists(&path)
+            && !origins.contains(Path::new(&path).file_name().unwrap_or_default().to_string_lossy().as_ref()) {
+            idx += 1;
+            path = compacted_output_filename(dir, target_size - 1, file.source_batch, target_size, idx, file.is_full);
         }
+        file.filename = Path::new(&path).file_name().unwrap_or_default().to_string_lossy().into_owned();
+        file.target_batch = idx;
+        idx += 1;
+        test_print(&format!("   ... {} ({} lists{})", file.filename, file.nb_lists.separated_string(),
+            if file.is_full { "" } else { ", partial" }));
     }
+
+    let intent = RebalanceIntent {
+        files,
+        origins: rewritten.iter().map(|info| (info.filename.clone(), info.source_batch, info.target_batch)).collect(),
+    };
+    let text = serde_json::to_string_pretty(&intent).map_err(std::io::Error::other)?;
+    crate::io_helpers::write_file_atomic(&rebalance_journal_path(dir, target_size).to_string_lossy(), text.as_bytes())?;
+    finish_rebalance(dir, target_size, &intent, &mut state)?;
+    state.export_human_readable()?;
+
+    report.files_rewritten = rewritten.len();
+    report.files_created = intent.files.len();
+    report.lists = lists;
+    test_print(&format!("   ... {} compacted files rewritten into {} files of up to {} lists in {:.2}s",
+        report.files_rewritten, report.files_created, batch_size.separated_string(), start_time.elapsed().as_secs_f64()));
+    Ok(report)
 }
 
 /// Legacy: Compact a single non-compacted input file (no longer used - kept for reference)
@@ -327,27 +1564,21 @@ pub fn compact_one_file_inplace(dir: &str, target_size: u8, batch_size: u64) ->
 
     // Find non-compacted input files and sort ascending by target_batch then source_batch
     let mut candidates: Vec<(String, u32, u32)> = Vec::new(); // (filename, src_batch, tgt_batch)
-    let entries = match std::fs::read_dir(dir) {
-        Ok(e) => e,
-        Err(e) => return Err(e),
-    };
+    let entries = std::fs::read_dir(dir)?;
     let pattern = format!("_to_{:02}_batch_", target_size);
     for entry in entries.flatten() {
-        if let Some(name) = entry.file_name().to_str() {
-            if name.starts_with("nsl_") && name.contains(&pattern) && !name.contains("_compacted.rkyv") && name.ends_with(".rkyv") {
-                if let Some(to_pos) = name.find("_to_") {
-                    let before_to = &name[..to_pos];
-                    let after_to = &name[to_pos + 4..];
-                    if let Some(src_batch_pos) = before_to.rfind("_batch_") {
-                        let src_batch_str = &before_to[src_batch_pos + 7..];
-                        if let Ok(srcb) = src_batch_str.parse::<u32>() {
-                            if let Some(tgt_batch_pos) = after_to.rfind("_batch_") {
-                                let tgt_batch_str = &after_to[tgt_batch_pos + 7..after_to.len() - 5];
-                                if let Ok(tgtb) = tgt_batch_str.parse::<u32>() {
-                                    candidates.push((name.to_string(), srcb, tgtb));
-                                }
-                            }
-                        }
+        if let Some(name) = entry.file_name().to_str()
+            && name.starts_with("nsl_") && name.contains(&pattern) && !name.contains("_compacted.rkyv") && name.ends_with(".rkyv")
+            && let Some(to_pos) = name.find("_to_") {
+            let before_to = &name[..to_pos];
+            let after_to = &name[to_pos + 4..];
+            if let Some(src_batch_pos) = before_to.rfind("_batch_") {
+                let src_batch_str = &before_to[src_batch_pos + 7..];
+                if let Ok(srcb) = src_batch_str.parse::<u32>()
+                    && let Some(tgt_batch_pos) = after_to.rfind("_batch_") {
+                    let tgt_batch_str = &after_to[tgt_batch_pos + 7..after_to.len() - 5];
+                    if let Ok(tgtb) = tgt_batch_str.parse::<u32>() {
+                        candidates.push((name.to_string(), srcb, tgtb));
                     }
                 }
             }
@@ -367,19 +1598,17 @@ pub fn compact_one_file_inplace(dir: &str, target_size: u8, batch_size: u64) ->
     if let Ok(entries) = std::fs::read_dir(dir) {
         let mut max_idx: Option<u32> = None;
         for entry in entries.flatten() {
-            if let Some(n) = entry.file_name().to_str() {
-                if n.ends_with("_compacted.rkyv") && n.contains(&pattern) {
-                    if let Some(to_pos) = n.find("_to_") {
-                        let after_to = &n[to_pos + 4..];
-                        if let Some(batch_pos) = after_to.rfind("_batch_") {
-                            let start = batch_pos + 7;
-                            let end = after_to.len() - "_compacted.rkyv".len();
-                            if end > start && end <= after_to.len() {
-                                let batch_str = &after_to[start..end];
-                                if let Ok(num) = batch_str.parse::<u32>() {
-                                    max_idx = Some(max_idx.map_or(num, |m| m.max(num)));
-                                }
-                            }
+            if let Some(n) = entry.file_name().to_str()
+                && n.ends_with("_compacted.rkyv") && n.contains(&pattern)
+                && let Some(to_pos) = n.find("_to_") {
+                let after_to = &n[to_pos + 4..];
+                if let Some(batch_pos) = after_to.rfind("_batch_") {
+                    let start = batch_pos + 7;
+                    let end = after_to.len() - "_compacted.rkyv".len();
+                    if end > start && end <= after_to.len() {
+                        let batch_str = &after_to[start..end];
+                        if let Ok(num) = batch_str.parse::<u32>() {
+                            max_idx = Some(max_idx.map_or(num, |m| m.max(num)));
                         }
                     }
                 }
@@ -391,31 +1620,15 @@ pub fn compact_one_file_inplace(dir: &str, target_size: u8, batch_size: u64) ->
 
     // Load lists from first file
     let filepath = format!("{}/{}", dir, first_name);
-    let file = std::fs::File::open(&filepath)?;
-    let mmap = unsafe { Mmap::map(&file)? };
-    let archived = check_archived_root::<Vec<NoSetListSerialized>>(&mmap[..])
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "Archive validation failed"))?;
-
-    let total = archived.len();
+    let mut all_lists = crate::io_helpers::load_lists_from_file(&filepath)?;
+    let total = all_lists.len();
     test_print(&format!("   Source file contains {} lists", total.separated_string()));
 
-    // Deserialize all (ok for single-file method)
-    let mut all_lists: Vec<NoSetListSerialized> = Vec::with_capacity(total);
-    for i in 0..total {
-        let archived_elem = archived.get(i).expect("index in range");
-        let des: NoSetListSerialized = archived_elem.deserialize(&mut rkyv::Infallible).expect("deserialization");
-        all_lists.push(des);
-    }
-
     // Split into compacted chunk and remaining
     let take = std::cmp::min(all_lists.len(), batch_size as usize);
     let compact_chunk: Vec<NoSetListSerialized> = all_lists.drain(0..take).collect();
     let remaining: Vec<NoSetListSerialized> = all_lists; // moved remaining
 
-    // Ensure mmap and file are dropped so Windows allows rewrite/rename of the origin file
-    drop(mmap);
-    drop(file);
-
     let source_size = target_size - 1;
     // Determine compacted filename: use last source batch = first_src here
     let is_full = (compact_chunk.len() as u64) >= batch_size;
@@ -428,18 +1641,18 @@ pub fn compact_one_file_inplace(dir: &str, target_size: u8, batch_size: u64) ->
     test_print(&format!("   Writing compacted file {} ({} lists)", compact_name, compact_chunk.len().separated_string()));
     // Use the simpler save helper here to avoid platform rename permission issues during tests.
     if !crate::io_helpers::save_to_file_serialized(&compact_chunk, &compact_name) {
-        return Err(std::io::Error::new(std::io::ErrorKind::Other, "Failed to save compacted file"));
+        return Err(std::io::Error::other("Failed to save compacted file"));
     }
 
     // Now rewrite or delete the origin file with remaining lists
     if remaining.is_empty() {
         test_print(&format!("   Origin file {} emptied; deleting", filepath));
-        let _ = std::fs::remove_file(&filepath);
+        let _ = crate::storage::remove_list_file(&filepath);
     } else {
         test_print(&format!("   Origin file {} shrunk to {} lists; rewriting", filepath, remaining.len().separated_string()));
         // Use simpler save helper to rewrite origin (avoid Windows locking/permission race in tests)
         if !crate::io_helpers::save_to_file_serialized(&remaining, &filepath) {
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, "Failed to save rewritten origin file"));
+            return Err(std::io::Error::other("Failed to save rewritten origin file"));
         }
     }
 
@@ -487,11 +1700,11 @@ mod tests {
         let compacted_path = format!("{}/nsl_{:02}_batch_{:06}_to_{:02}_batch_{:06}_compacted.rkyv", dir, 14u8, 0u32, 15u8, 0u32);
         assert!(Path::new(&compacted_path).exists(), "compacted file missing");
 
-        let compacted = io_helpers::read_from_file_serialized(&compacted_path).expect("read compacted");
+        let compacted = io_helpers::load_lists_from_file(&compacted_path).expect("read compacted");
         assert_eq!(compacted.len(), 3);
 
         // Origin should have remaining 2 lists
-        let origin = io_helpers::read_from_file_serialized(&filename).expect("read origin");
+        let origin = io_helpers::load_lists_from_file(&filename).expect("read origin");
         assert_eq!(origin.len(), 2);
 
         // Combine and verify all original lists are present exactly once
@@ -511,4 +1724,301 @@ mod tests {
         // Cleanup
         let _ = fs::remove_dir_all(&dir);
     }
+
+    #[test]
+    fn journal_replays_a_compaction_stopped_before_flush() {
+        let dir_dir = crate::test_dir::TestDir::new("journal");
+        let dir = dir_dir.str();
+        let lists: Vec<NoSetListSerialized> = (0..4).map(|i| NoSetListSerialized {
+            n: 3, max_card: i, no_set_list: vec![i, i + 1, i + 2], remaining_cards_list: vec![],
+        }).collect();
+        let origin = "nsl_14_batch_000000_to_15_batch_000000.rkyv";
+        let compacted = "nsl_14_batch_000000_to_15_batch_000000_compacted.rkyv";
+        assert!(io_helpers::save_to_file_serialized(&lists, &format!("{}/{}", dir, origin)));
+        let mut state = GlobalFileState::new(&dir, 15);
+        state.register_file(origin, 0, 0, 4, false, None, None);
+        state.flush().expect("flush");
+
+        // Journaled operations done on disk, state never flushed
+        state.journal(&JournalOp::Register { filename: compacted.to_string(), source_batch: 0, target_batch: 0,
+            nb_lists: 4, compacted: true }).expect("journal");
+        assert!(io_helpers::save_to_file_serialized(&lists, &format!("{}/{}", dir, compacted)));
+        state.journal(&JournalOp::Remove { filename: origin.to_string(), source_batch: 0, target_batch: 0 }).expect("journal");
+        fs::remove_file(format!("{}/{}", dir, origin)).expect("remove");
+        // Journaled but never written: not replayed
+        state.journal(&JournalOp::Register { filename: "nsl_14_batch_000001_to_15_batch_000001.rkyv".to_string(),
+            source_batch: 1, target_batch: 1, nb_lists: 9, compacted: false }).expect("journal");
+        drop(state);
+
+        let mut replayed = GlobalFileState::from_sources(&dir, 15).expect("load");
+        let names: Vec<&str> = replayed.entries().values().map(|e| e.filename.as_str()).collect();
+        assert_eq!(names, vec![compacted]);
+        assert_eq!(replayed.entries().values().next().map(|e| (e.nb_lists_in_file, e.compacted)), Some((4, true)));
+        replayed.flush().expect("flush");
+        assert!(!crate::file_info::journal_path(&dir, 15).exists(), "flush clears the journal");
+    }
+
+    #[test]
+    fn out_of_place_compaction_verifies_then_deletes_sources() {
+        let source_dir = crate::test_dir::TestDir::new("outofplace_src");
+        let source = source_dir.str();
+        let target_dir = crate::test_dir::TestDir::new("outofplace_dst");
+        let target = target_dir.str();
+        let mut state = GlobalFileState::new(&source, 15);
+        for batch in 0..3usize {
+            let lists: Vec<NoSetListSerialized> = (0..2).map(|i| NoSetListSerialized {
+                n: 3, max_card: batch * 2 + i, no_set_list: vec![batch, i, 9], remaining_cards_list: vec![],
+            }).collect();
+            let name = format!("nsl_14_batch_000000_to_15_batch_{:06}.rkyv", batch);
+            assert!(io_helpers::save_to_file_serialized(&lists, &format!("{}/{}", source, name)));
+            state.register_file(&name, 0, batch as u32, 2, false, None, None);
+        }
+        state.flush().expect("flush");
+        drop(state);
+
+        let report = compact_size_files_to(&source, &target, 15, 3, None, true).expect("compaction");
+        assert_eq!((report.sources, report.lists, report.outputs.len()), (3, 6, 2));
+        assert!(report.originals_deleted);
+        assert!(GlobalFileState::from_sources(&source, 15).expect("source").entries().is_empty());
+        let compacted = GlobalFileState::from_sources(&target, 15).expect("target");
+        assert!(compacted.entries().values().all(|e| e.compacted && e.nb_lists_in_file == 3));
+    }
+    #[test]
+    fn early_deletion_verifies_each_written_file() {
+        let source_dir = crate::test_dir::TestDir::new("early_src");
+        let source = source_dir.str();
+        let target_dir = crate::test_dir::TestDir::new("early_dst");
+        let target = target_dir.str();
+        let lists: Vec<NoSetListSerialized> = (0..2).map(|i| NoSetListSerialized {
+            n: 3, max_card: 9, no_set_list: vec![i, 5, 9], remaining_cards_list: vec![],
+        }).collect();
+        let mut source_state = GlobalFileState::new(&source, 15);
+        for batch in 0..2u32 {
+            let name = format!("nsl_14_batch_000000_to_15_batch_{:06}.rkyv", batch);
+            assert!(io_helpers::save_to_file_serialized(&lists, &format!("{}/{}", source, name)));
+            source_state.register_file(&name, 0, batch, 2, false, None, None);
+        }
+        let plan: Vec<FileInfo> = source_state.entries().values().cloned().collect();
+        let mut target_state = GlobalFileState::new(&target, 15);
+        let mut hasher = crc32fast::Hasher::new();
+        lists.iter().for_each(|list| hash_list(&mut hasher, list));
+        let crc = hasher.finalize();
+
+        // A file that does not read back as written is discarded, the sources kept
+        let output = format!("{}/nsl_14_batch_000000_to_15_batch_000000_compacted.rkyv", target);
+        assert!(io_helpers::save_to_file_serialized(&lists, &output));
+        assert!(verify_and_delete_early(&source, &target, &output, (2, crc ^ 1), &plan[..1],
+            &mut source_state, &mut target_state).is_err());
+        assert!(!Path::new(&output).exists());
+        assert_eq!(source_state.entries().len(), 2);
+
+        // Verified: only the sources fully copied are deleted
+        assert!(io_helpers::save_to_file_serialized(&lists, &output));
+        verify_and_delete_early(&source, &target, &output, (2, crc), &plan[..1],
+            &mut source_state, &mut target_state).expect("verified");
+        assert!(!Path::new(&source).join(&plan[0].filename).exists());
+        assert!(Path::new(&source).join(&plan[1].filename).exists());
+        assert_eq!(source_state.entries().len(), 1);
+    }
+
+    #[test]
+    fn free_space_check_keeps_the_margin() {
+        assert!(room_for(Some(100), 60, 40));
+        assert!(!room_for(Some(100), 61, 40));
+        assert!(room_for(None, u64::MAX, 40));
+    }
+
+    #[test]
+    fn compacted_files_record_their_source_batches() {
+        let dir_dir = crate::test_dir::TestDir::new("lineage");
+        let dir = dir_dir.str();
+        let mut state = GlobalFileState::new(&dir, 15);
+        for batch in 0..3usize {
+            let lists: Vec<NoSetListSerialized> = (0..5).map(|i| NoSetListSerialized {
+                n: 3, max_card: batch * 5 + i, no_set_list: vec![batch, i, 9], remaining_cards_list: vec![],
+            }).collect();
+            let name = format!("nsl_14_batch_{:06}_to_15_batch_{:06}.rkyv", batch, batch);
+            assert!(io_helpers::save_to_file_serialized(&lists, &format!("{}/{}", dir, name)));
+            state.register_file(&name, batch as u32, batch as u32, 5, false, None, None);
+        }
+        state.flush().expect("flush");
+        drop(state);
+
+        // 8 lists per compacted file: batches 0 and 1 (3 of 5) fill the first one,
+        // the last 2 of batch 1 and batch 2 go into a partial one
+        compact_size_files(&dir, &dir, 15, 8, None).expect("compaction");
+        let state = GlobalFileState::from_sources(&dir, 15).expect("load");
+        let lineage: Vec<(String, Vec<(u32, u64)>)> = state.compacted_sources().iter()
+            .map(|(f, s)| (f.clone(), s.iter().map(|c| (c.source_batch, c.nb_lists)).collect()))
+            .collect();
+        assert_eq!(lineage, vec![
+            ("nsl_14_batch_000001_to_15_batch_000000_compacted.rkyv".to_string(), vec![(0, 5), (1, 3)]),
+            ("nsl_14_batch_000002_to_15_batch_000001.rkyv".to_string(), vec![(1, 2), (2, 5)]),
+        ]);
+    }
+
+    #[test]
+    fn interrupted_compaction_is_finished_by_the_next_run() {
+        let dir_dir = crate::test_dir::TestDir::new("resume_compaction");
+        let dir = dir_dir.str();
+        let lists: Vec<NoSetListSerialized> = (0..10).map(|i| NoSetListSerialized {
+            n: 3, max_card: i, no_set_list: vec![i, i + 1, 9], remaining_cards_list: vec![],
+        }).collect();
+        let origins = ["nsl_14_batch_000000_to_15_batch_000000.rkyv", "nsl_14_batch_000001_to_15_batch_000001.rkyv"];
+        let compacted = "nsl_14_batch_000001_to_15_batch_000000_compacted.rkyv";
+        let mut state = GlobalFileState::new(&dir, 15);
+        for (batch, name) in origins.iter().enumerate() {
+            assert!(io_helpers::save_to_file_serialized(&lists[batch * 5..batch * 5 + 5].to_vec(), &format!("{}/{}", dir, name)));
+            state.register_file(name, batch as u32, batch as u32, 5, false, None, None);
+        }
+
+        // Stopped after recording the compacted file, before touching the origins
+        let intent = CompactionIntent {
+            compacted: compacted.to_string(), source_batch: 1, target_batch: 0, nb_lists: 8, is_full: true,
+            sources: vec![SourceContribution { source_batch: 0, nb_lists: 5 }, SourceContribution { source_batch: 1, nb_lists: 3 }],
+            origins: origins.iter().enumerate().map(|(batch, name)| IntentOrigin { filename: name.to_string(),
+                source_batch: batch as u32, target_batch: batch as u32, taken: [5, 3][batch], total: 5, rest_sources: None,
+                kept: None, kept_sources: None }).collect(),
+        };
+        intent.save(&dir, 15).expect("intent");
+        assert!(io_helpers::save_to_file_serialized(&lists[..8].to_vec(), &format!("{}/{}", dir, compacted)));
+        state.register_file(compacted, 1, 0, 8, true, None, None);
+        state.flush().expect("flush");
+        drop(state);
+        fs::write(format!("{}/{}.tmp.99999", dir, origins[1]), b"partial rewrite").expect("stale rewrite");
+
+        compact_size_files(&dir, &dir, 15, 8, None).expect("compaction");
+        assert!(!compaction_journal_path(&dir, 15).exists(), "journal cleared");
+        assert!(!Path::new(&format!("{}/{}", dir, origins[0])).exists(), "consumed origin deleted");
+        assert!(!Path::new(&format!("{}/{}.tmp.99999", dir, origins[1])).exists(), "stale rewrite deleted");
+        let rest = io_helpers::load_lists_from_file(&format!("{}/{}", dir, origins[1])).expect("rest");
+        assert!(rest.len() == 2 && rest.iter().zip(&lists[8..]).all(|(a, b)| eq_nsl(a, b)));
+        let state = GlobalFileState::from_sources(&dir, 15).expect("load");
+        let counts: Vec<(&str, u64)> = state.entries().values().map(|e| (e.filename.as_str(), e.nb_lists_in_file)).collect();
+        assert_eq!(counts, vec![(compacted, 8), (origins[1], 2)]);
+    }
+
+    #[test]
+    fn dedupe_drops_the_card_sets_already_compacted() {
+        let dir_dir = crate::test_dir::TestDir::new("dedupe");
+        let dir = dir_dir.str();
+        let list = |cards: [usize; 3]| NoSetListSerialized { n: 3, max_card: cards[2], no_set_list: cards.to_vec(), remaining_cards_list: vec![] };
+        // Batch 1 repeats two card sets of batch 0 (one in another order), one of them in its rest
+        let batches = [
+            vec![list([0, 1, 3]), list([0, 2, 4]), list([1, 2, 5]), list([0, 1, 3])],
+            vec![list([4, 2, 0]), list([3, 5, 7]), list([2, 6, 8]), list([1, 2, 5]), list([6, 7, 9])],
+        ];
+        let mut state = GlobalFileState::new(&dir, 15);
+        for (batch, lists) in batches.iter().enumerate() {
+            let name = format!("nsl_14_batch_{:06}_to_15_batch_{:06}.rkyv", batch, batch);
+            assert!(io_helpers::save_to_file_serialized(lists, &format!("{}/{}", dir, name)));
+            state.register_file(&name, batch as u32, batch as u32, lists.len() as u64, false, None, None);
+        }
+
+        let mut job = plan_job(&state, &dir, 15, 7, None, false).expect("plan").expect("job");
+        let mut seen = HashSet::new();
+        let (rewrite, duplicates) = write_job(&mut job, Some(&mut seen)).expect("write");
+        assert_eq!(duplicates, 3);
+        apply_job(&mut state, &job, rewrite).expect("apply");
+
+        let compacted = io_helpers::load_lists_from_file(&job.output_filename).expect("compacted");
+        let rest = io_helpers::load_lists_from_file(&format!("{}/nsl_14_batch_000001_to_15_batch_000001.rkyv", dir)).expect("rest");
+        assert_eq!((compacted.len(), rest.len()), (5, 1));
+        assert_eq!(rest[0].no_set_list, vec![6, 7, 9]);
+        let counts: Vec<u64> = state.entries().values().map(|e| e.nb_lists_in_file).collect();
+        assert_eq!(counts, vec![5, 1]);
+        let sources: Vec<(u32, u64)> = state.sources_of(state.entries().values().next().unwrap())
+            .iter().map(|s| (s.source_batch, s.nb_lists)).collect();
+        assert_eq!(sources, vec![(0, 3), (1, 2)]);
+    }
+
+    #[test]
+    fn dry_run_reports_the_files_created_and_the_fragmentation() {
+        let dir_dir = crate::test_dir::TestDir::new("dry_run_report");
+        let dir = dir_dir.str();
+        let mut state = GlobalFileState::new(&dir, 15);
+        for batch in 0..3u32 {
+            let name = format!("nsl_14_batch_{:06}_to_15_batch_{:06}.rkyv", batch, batch);
+            state.register_file(&name, batch, batch, 4, false, None, None);
+        }
+        state.flush().expect("flush");
+
+        // 4+4+4 lists by 5: two full files, the third origin keeps 2 lists
+        let (plan, efficiency) = plan_compaction_report(&dir, 15, 5, None).expect("plan");
+        assert_eq!((efficiency.full_files, efficiency.partial_files), (2, 0));
+        assert_eq!((efficiency.deleted, efficiency.rewritten), (2, 2));
+        assert_eq!(plan.count(Operation::Delete), 2);
+        assert!(plan.operations.iter().any(|o| o.detail.starts_with("5 lists from 2 files (batches 000000-000001)")));
+        assert_eq!((efficiency.before.files, efficiency.before.compacted_files, efficiency.before.lists), (3, 0, 12));
+        assert_eq!((efficiency.after.files, efficiency.after.compacted_files, efficiency.after.lists), (3, 2, 12));
+        assert_eq!(efficiency.before.by_magnitude.get(&1), Some(&(3, 12)));
+    }
+
+    #[test]
+    fn rebalance_rewrites_uneven_compacted_files() {
+        let dir_dir = crate::test_dir::TestDir::new("rebalance");
+        let dir = dir_dir.str();
+        let list = |first: usize| NoSetListSerialized { n: 3, max_card: first + 2, no_set_list: vec![first, first + 1, first + 2], remaining_cards_list: vec![] };
+        // Compacted files of 4, 2 and 5 lists (source batches 3, 7 and 9) rebalanced into files of 4
+        let mut state = GlobalFileState::new(&dir, 15);
+        let mut first = 0;
+        for (idx, (src, count)) in [(3u32, 4usize), (7, 2), (9, 5)].into_iter().enumerate() {
+            let path = compacted_output_filename(&dir, 14, src, 15, idx as u32, true);
+            assert!(io_helpers::save_to_file_serialized(&(first..first + count).map(list).collect(), &path));
+            let name = Path::new(&path).file_name().unwrap().to_string_lossy().into_owned();
+            state.register_file(&name, src, idx as u32, count as u64, true, None, None);
+            first += count;
+        }
+        state.flush().expect("flush");
+
+        let report = rebalance_compacted_files(&dir, 15, 4).expect("rebalance");
+        assert_eq!((report.files_kept, report.files_rewritten, report.files_created, report.lists), (1, 2, 2, 7));
+        let state = GlobalFileState::from_sources(&dir, 15).expect("load");
+        let mut entries: Vec<(u32, u32, u64, bool)> = state.entries().values()
+            .map(|e| (e.source_batch, e.target_batch, e.nb_lists_in_file, e.compacted)).collect();
+        entries.sort_by_key(|e| e.1);
+        assert_eq!(entries, vec![(3, 0, 4, true), (9, 1, 4, true), (9, 2, 3, false)]);
+        let rebalanced = io_helpers::load_lists_from_file(&compacted_output_filename(&dir, 14, 9, 15, 1, true)).expect("full");
+        assert_eq!(rebalanced.iter().map(|l| l.no_set_list[0]).collect::<Vec<_>>(), vec![4, 5, 6, 7]);
+        let partial = io_helpers::load_lists_from_file(&compacted_output_filename(&dir, 14, 9, 15, 2, false)).expect("partial");
+        assert_eq!(partial.len(), 3);
+        let info = state.entries().values().find(|e| e.target_batch == 1).unwrap();
+        let sources: Vec<(u32, u64)> = state.sources_of(info).iter().map(|s| (s.source_batch, s.nb_lists)).collect();
+        assert_eq!(sources, vec![(7, 2), (9, 2)]);
+        assert!(!crate::storage::list_file_exists(&compacted_output_filename(&dir, 14, 7, 15, 1, true)));
+        assert!(!crate::storage::list_file_exists(&compacted_output_filename(&dir, 14, 9, 15, 2, true)));
+        assert!(!rebalance_journal_path(&dir, 15).exists());
+    }
+
+    #[test]
+    fn background_compactor_compacts_the_written_outputs() {
+        let dir_dir = crate::test_dir::TestDir::new("background");
+        let dir = dir_dir.str();
+        let mut state = GlobalFileState::new(&dir, 15);
+        let mut compactor = BackgroundCompactor::new(&dir, 15, 8);
+        for batch in 0..3usize {
+            let lists: Vec<NoSetListSerialized> = (0..5).map(|i| NoSetListSerialized {
+                n: 3, max_card: batch * 5 + i, no_set_list: vec![batch, i, 9], remaining_cards_list: vec![],
+            }).collect();
+            let name = format!("nsl_14_batch_{:06}_to_15_batch_{:06}.rkyv", batch, batch);
+            assert!(io_helpers::save_to_file_serialized(&lists, &format!("{}/{}", dir, name)));
+            state.register_file(&name, batch as u32, batch as u32, 5, false, None, None);
+            compactor.poll(&mut state);
+            if batch == 1 {
+                assert!(state.is_leased("nsl_14_batch_000000_to_15_batch_000000.rkyv"), "job started at 10 lists");
+            }
+        }
+        compactor.finish(&mut state);
+
+        // One full compacted file (batch 0, 3 lists of batch 1); the rest never fills one
+        assert_eq!((compactor.files_created, compactor.lists_compacted), (1, 8));
+        assert!(!compaction_journal_path(&dir, 15).exists());
+        let counts: Vec<(String, u64, bool)> = GlobalFileState::from_sources(&dir, 15).expect("load").entries().values()
+            .map(|e| (e.filename.clone(), e.nb_lists_in_file, e.compacted)).collect();
+        assert_eq!(counts, vec![
+            ("nsl_14_batch_000001_to_15_batch_000000_compacted.rkyv".to_string(), 8, true),
+            ("nsl_14_batch_000001_to_15_batch_000001.rkyv".to_string(), 2, false),
+            ("nsl_14_batch_000002_to_15_batch_000002.rkyv".to_string(), 5, false),
+        ]);
+    }
 }
@@ -0,0 +1,275 @@
+//! Conversion module: export the stored lists of a size to standard formats
+//!
+//! One row per list, with the columns:
+//!   batch (target batch of the file holding the list), n, max_card,
+//!   cards (the no-set-list), remaining (the remaining cards)
+//!
+//! Key features:
+//! - CSV (cards as space-separated numbers), JSONL (one JSON object per line)
+//!   and Parquet (list columns, one row group per chunk; needs the `parquet` feature)
+//! - Files streamed chunk by chunk: multi-gigabyte inputs never sit in memory at once
+//! - Saved as nsl_{size:02}_lists.{csv,jsonl,parquet} (written as .tmp, then renamed)
+//! - Dataset format: one Parquet file per list file with bitmask columns, in
+//!   nsl_{size:02}_dataset/ (see the dataset module)
+//!
+//! Used by --convert mode
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use separator::Separatable;
+use serde::Serialize;
+
+use crate::no_set_list::NoSetListSerialized;
+use crate::utils::*;
+
+/// Lists decoded and written at once
+const CONVERT_CHUNK_LISTS: usize = 1_000_000;
+
+/// Output format of --convert
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConvertFormat {
+    Csv,
+    Jsonl,
+    Parquet,
+    Dataset,
+}
+
+impl ConvertFormat {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text.to_ascii_lowercase().as_str() {
+            "csv" => Ok(ConvertFormat::Csv),
+            "jsonl" => Ok(ConvertFormat::Jsonl),
+            "parquet" => Ok(ConvertFormat::Parquet),
+            "dataset" => Ok(ConvertFormat::Dataset),
+            other => Err(format!("Unknown conversion format '{}' (expected parquet, dataset, csv or jsonl)", other)),
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ConvertFormat::Csv => "csv",
+            ConvertFormat::Jsonl => "jsonl",
+            ConvertFormat::Parquet | ConvertFormat::Dataset => "parquet",
+        }
+    }
+}
+
+/// Result of converting one size
+#[derive(Debug, Clone)]
+pub struct ConvertReport {
+    pub size: u8,
+    pub files: u64,
+    pub lists: u64,
+    pub output: String,
+}
+
+/// One JSONL row
+#[derive(Serialize)]
+struct JsonRow<'a> {
+    batch: u32,
+    n: u8,
+    max_card: usize,
+    cards: &'a [usize],
+    remaining: &'a [usize],
+}
+
+/// Space-separated card numbers (a CSV field needing no quoting)
+fn join_cards(cards: &[usize]) -> String {
+    cards.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(" ")
+}
+
+/// Destination of the rows, one variant per format
+enum RowWriter {
+    Csv(BufWriter<File>),
+    Jsonl(BufWriter<File>),
+    #[cfg(feature = "parquet")]
+    Parquet(Box<parquet_backend::ParquetRowWriter>),
+}
+
+impl RowWriter {
+    fn create(path: &str, format: ConvertFormat) -> std::io::Result<Self> {
+        let file = File::create(path)?;
+        match format {
+            ConvertFormat::Csv => {
+                let mut w = BufWriter::new(file);
+                writeln!(w, "batch,n,max_card,cards,remaining")?;
+                Ok(RowWriter::Csv(w))
+            }
+            ConvertFormat::Jsonl => Ok(RowWriter::Jsonl(BufWriter::new(file))),
+            #[cfg(feature = "parquet")]
+            ConvertFormat::Parquet => Ok(RowWriter::Parquet(Box::new(parquet_backend::ParquetRowWriter::new(file)?))),
+            ConvertFormat::Dataset => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                "The dataset format is written by dataset::export_dataset")),
+            #[cfg(not(feature = "parquet"))]
+            ConvertFormat::Parquet => Err(std::io::Error::new(std::io::ErrorKind::Unsupported,
+                "Parquet output needs a build with the `parquet` feature (cargo build --release --features parquet)")),
+        }
+    }
+
+    fn write_chunk(&mut self, batch: u32, lists: &[NoSetListSerialized]) -> std::io::Result<()> {
+        match self {
+            RowWriter::Csv(w) => {
+                for l in lists {
+                    writeln!(w, "{},{},{},{},{}", batch, l.n, l.max_card,
+                        join_cards(&l.no_set_list), join_cards(&l.remaining_cards_list))?;
+                }
+                Ok(())
+            }
+            RowWriter::Jsonl(w) => {
+                for l in lists {
+                    let row = JsonRow {
+                        batch,
+                        n: l.n,
+                        max_card: l.max_card,
+                        cards: &l.no_set_list,
+                        remaining: &l.remaining_cards_list,
+                    };
+                    serde_json::to_writer(&mut *w, &row)
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                    w.write_all(b"\n")?;
+                }
+                Ok(())
+            }
+            #[cfg(feature = "parquet")]
+            RowWriter::Parquet(w) => w.write_chunk(batch, lists),
+        }
+    }
+
+    fn finish(self) -> std::io::Result<()> {
+        match self {
+            RowWriter::Csv(mut w) | RowWriter::Jsonl(mut w) => w.flush(),
+            #[cfg(feature = "parquet")]
+            RowWriter::Parquet(w) => w.finish(),
+        }
+    }
+}
+
+/// Stream every stored list of `size` in `input_dir` to nsl_{size:02}_lists.{ext} in `output_dir`
+/// (or to the dataset nsl_{size:02}_dataset/ in `output_dir`)
+pub fn convert_size_files(input_dir: &str, output_dir: &str, size: u8, format: ConvertFormat) -> std::io::Result<ConvertReport> {
+    if format == ConvertFormat::Dataset {
+        return crate::dataset::export_dataset(input_dir, output_dir, size);
+    }
+    test_print(&format!("\nCONVERT MODE: Exporting size {:02} lists to {}...", size, format.extension()));
+    test_print(&format!("   Input directory:  {}", input_dir));
+    let start_time = std::time::Instant::now();
+
+    let files = crate::filenames::list_input_files(input_dir, size);
+    if files.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound,
+            format!("No size {:02} files found in {}", size, input_dir)));
+    }
+
+    std::fs::create_dir_all(output_dir)?;
+    let output = Path::new(output_dir).join(format!("nsl_{:02}_lists.{}", size, format.extension()))
+        .to_string_lossy().into_owned();
+    let tmp = format!("{}.tmp", output);
+    let mut writer = RowWriter::create(&tmp, format)?;
+
+    let mut report = ConvertReport { size, files: 0, lists: 0, output: output.clone() };
+    for file in files.iter() {
+        let nb = crate::io_helpers::load_lists_in_chunks(&file.path, CONVERT_CHUNK_LISTS,
+            |chunk| writer.write_chunk(file.batch, &chunk))?;
+        report.files += 1;
+        report.lists += nb;
+        test_print(&format!("   ... {:>10} lists from {}", nb.separated_string(),
+            Path::new(&file.path).file_name().unwrap_or_default().to_string_lossy()));
+    }
+    writer.finish()?;
+    std::fs::rename(&tmp, &output)?;
+
+    test_print(&format!("   ... {} lists written to {} in {:.2}s",
+        report.lists.separated_string(), output, start_time.elapsed().as_secs_f64()));
+    Ok(report)
+}
+
+/// Parquet writer (arrow record batches, one row group per chunk)
+#[cfg(feature = "parquet")]
+mod parquet_backend {
+    use std::fs::File;
+    use std::sync::Arc;
+    use arrow_array::builder::{ListBuilder, UInt8Builder};
+    use arrow_array::{ArrayRef, RecordBatch, UInt32Array, UInt8Array};
+    use arrow_schema::{DataType, Field, Schema, SchemaRef};
+    use parquet::arrow::ArrowWriter;
+
+    use crate::no_set_list::NoSetListSerialized;
+
+    fn io_error<E: std::fmt::Display>(e: E) -> std::io::Error {
+        std::io::Error::other(e.to_string())
+    }
+
+    pub struct ParquetRowWriter {
+        schema: SchemaRef,
+        writer: ArrowWriter<File>,
+    }
+
+    impl ParquetRowWriter {
+        pub fn new(file: File) -> std::io::Result<Self> {
+            let card_list = DataType::List(Arc::new(Field::new("item", DataType::UInt8, true)));
+            let schema = Arc::new(Schema::new(vec![
+                Field::new("batch", DataType::UInt32, false),
+                Field::new("n", DataType::UInt8, false),
+                Field::new("max_card", DataType::UInt8, false),
+                Field::new("cards", card_list.clone(), false),
+                Field::new("remaining", card_list, false),
+            ]));
+            let writer = ArrowWriter::try_new(file, schema.clone(), None).map_err(io_error)?;
+            Ok(ParquetRowWriter { schema, writer })
+        }
+
+        pub fn write_chunk(&mut self, batch: u32, lists: &[NoSetListSerialized]) -> std::io::Result<()> {
+            let mut cards = ListBuilder::new(UInt8Builder::new());
+            let mut remaining = ListBuilder::new(UInt8Builder::new());
+            for l in lists {
+                cards.values().append_slice(&l.no_set_list.iter().map(|&c| c as u8).collect::<Vec<u8>>());
+                cards.append(true);
+                remaining.values().append_slice(&l.remaining_cards_list.iter().map(|&c| c as u8).collect::<Vec<u8>>());
+                remaining.append(true);
+            }
+            let columns: Vec<ArrayRef> = vec![
+                Arc::new(UInt32Array::from(vec![batch; lists.len()])),
+                Arc::new(UInt8Array::from(lists.iter().map(|l| l.n).collect::<Vec<u8>>())),
+                Arc::new(UInt8Array::from(lists.iter().map(|l| l.max_card as u8).collect::<Vec<u8>>())),
+                Arc::new(cards.finish()),
+                Arc::new(remaining.finish()),
+            ];
+            let record_batch = RecordBatch::try_new(self.schema.clone(), columns).map_err(io_error)?;
+            self.writer.write(&record_batch).map_err(io_error)?;
+            self.writer.flush().map_err(io_error)
+        }
+
+        pub fn finish(self) -> std::io::Result<()> {
+            self.writer.close().map_err(io_error)?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_and_jsonl_rows() {
+        let dir = crate::test_dir::TestDir::new("convert");
+        let dir_str = dir.to_string_lossy().into_owned();
+
+        let lists = vec![
+            NoSetListSerialized { n: 3, max_card: 3, no_set_list: vec![0, 1, 3], remaining_cards_list: vec![4, 5] },
+            NoSetListSerialized { n: 3, max_card: 4, no_set_list: vec![0, 1, 4], remaining_cards_list: vec![] },
+        ];
+        let input = crate::filenames::output_filename(&dir_str, 0, 0, 3, 7);
+        assert!(crate::io_helpers::save_to_file_serialized(&lists, &input));
+
+        let csv = convert_size_files(&dir_str, &dir_str, 3, ConvertFormat::Csv).expect("csv");
+        assert_eq!(csv.lists, 2);
+        let text = std::fs::read_to_string(&csv.output).expect("read csv");
+        assert_eq!(text, "batch,n,max_card,cards,remaining\n7,3,3,0 1 3,4 5\n7,3,4,0 1 4,\n");
+
+        let jsonl = convert_size_files(&dir_str, &dir_str, 3, ConvertFormat::Jsonl).expect("jsonl");
+        let text = std::fs::read_to_string(&jsonl.output).expect("read jsonl");
+        assert_eq!(text.lines().next(), Some(r#"{"batch":7,"n":3,"max_card":3,"cards":[0,1,3],"remaining":[4,5]}"#));
+    }
+}
@@ -0,0 +1,166 @@
+//! Count-follow module: live view of a size being computed elsewhere
+//!
+//! A size run lasts days, often on another machine writing to a shared directory.
+//! Following it re-runs --count every few minutes: only the files not in the state
+//! yet are counted and registered, so each poll costs the deltas alone.
+//!
+//! Key features:
+//! - One --count per poll (new files registered, state saved)
+//! - Rolling throughput over the last FOLLOW_WINDOW polls: lists/hour and input
+//!   batches/hour
+//! - Projected completion from the input batches consumed over the batches of the
+//!   previous size (found next to the directory, as --overview does); no
+//!   projection when the previous size is not found
+//! - Stopped cleanly by creating funny_watch.stop in the directory
+//!
+//! Used by --count --follow mode
+
+use std::collections::{BTreeSet, VecDeque};
+use separator::Separatable;
+
+use crate::dry_run::load_state_readonly;
+use crate::file_info::{format_duration, InputKey};
+use crate::utils::*;
+use crate::watch::{stop_requested, WATCH_STOP_FILE};
+
+/// Polls the rolling throughput is computed over
+pub const FOLLOW_WINDOW: usize = 6;
+
+/// Progress of the size at one poll
+#[derive(Debug, Clone, Copy)]
+pub struct FollowSample {
+    pub at_secs: f64,     // since the start of the follow
+    pub lists: u64,
+    pub inputs_done: u64,
+}
+
+/// Samples of the last polls
+#[derive(Debug, Clone, Default)]
+pub struct Throughput {
+    samples: VecDeque<FollowSample>,
+}
+
+impl Throughput {
+    pub fn push(&mut self, sample: FollowSample) {
+        if self.samples.len() == FOLLOW_WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    /// Hours covered by the window (None before two polls)
+    fn hours(&self) -> Option<f64> {
+        let (first, last) = (self.samples.front()?, self.samples.back()?);
+        let secs = last.at_secs - first.at_secs;
+        (secs > 0.0).then_some(secs / 3600.0)
+    }
+
+    fn delta(&self, field: fn(&FollowSample) -> u64) -> Option<u64> {
+        let (first, last) = (self.samples.front()?, self.samples.back()?);
+        Some(field(last).saturating_sub(field(first)))
+    }
+
+    pub fn lists_per_hour(&self) -> Option<f64> {
+        Some(self.delta(|s| s.lists)? as f64 / self.hours()?)
+    }
+
+    pub fn inputs_per_hour(&self) -> Option<f64> {
+        Some(self.delta(|s| s.inputs_done)? as f64 / self.hours()?)
+    }
+
+    /// Seconds until the `inputs_total` input batches are consumed at the current pace
+    /// (None without progress over the window)
+    pub fn projected_secs(&self, inputs_total: u64) -> Option<f64> {
+        let remaining = inputs_total.saturating_sub(self.samples.back()?.inputs_done);
+        if remaining == 0 {
+            return Some(0.0);
+        }
+        let rate = self.inputs_per_hour()?;
+        (rate > 0.0).then(|| remaining as f64 / rate * 3600.0)
+    }
+}
+
+/// Lists and input batches consumed of `size` in `dir` (its state, read-only)
+fn sample(dir: &str, size: u8, inputs: &BTreeSet<InputKey>, at_secs: f64) -> std::io::Result<FollowSample> {
+    let state = load_state_readonly(dir, size)?;
+    Ok(FollowSample {
+        at_secs,
+        lists: state.entries().values().map(|e| e.nb_lists_in_file).sum(),
+        inputs_done: state.consumed_among(inputs, &BTreeSet::new()).len() as u64,
+    })
+}
+
+/// Count the files of `size` in `dir` every `interval_minutes`, printing the rolling
+/// throughput and the projected completion, until funny_watch.stop appears in `dir`
+pub fn follow_count(dir: &str, size: u8, interval_minutes: u64) -> std::io::Result<()> {
+    test_print(&format!("\nCOUNT FOLLOW MODE: counting the new files of size {:02} every {} min", size, interval_minutes));
+    test_print(&format!("   Directory: {}; stop by creating {}/{}", dir, dir, WATCH_STOP_FILE));
+    let input_dir = crate::overview::input_dir_of(dir, size);
+    match &input_dir {
+        Some(input_dir) => test_print(&format!("   Size {:02} batches (projection): {}", size - 1, input_dir)),
+        None => test_print(&format!("   [!!] Size {:02} files not found: no projected completion", size - 1)),
+    }
+
+    let start = std::time::Instant::now();
+    let mut throughput = Throughput::default();
+    loop {
+        crate::list_of_nsl::count_size_files(dir, size, false, false)?;
+        // The previous size may still grow (cascade): its batches are read at every poll
+        let inputs = match &input_dir {
+            Some(input_dir) => crate::overview::size_inputs(input_dir, size - 1)?,
+            None => BTreeSet::new(),
+        };
+        let now = sample(dir, size, &inputs, start.elapsed().as_secs_f64())?;
+        throughput.push(now);
+
+        let rate = throughput.lists_per_hour()
+            .map_or("n/a (first poll)".to_string(), |r| format!("{} lists/hour", (r.round() as u64).separated_string()));
+        test_print(&format!("\n[follow {}] {} lists, {}", format_duration(now.at_secs), now.lists.separated_string(), rate));
+        if !inputs.is_empty() {
+            let eta = throughput.projected_secs(inputs.len() as u64).map_or("unknown".to_string(), |secs| {
+                let at = chrono::Local::now() + chrono::Duration::seconds(secs as i64);
+                format!("in {} ({})", format_duration(secs), at.format("%Y-%m-%d %H:%M"))
+            });
+            test_print(&format!("   ... input batches {}/{} ({:.1}%), projected completion {}",
+                now.inputs_done, inputs.len(), 100.0 * now.inputs_done as f64 / inputs.len() as f64, eta));
+        }
+
+        // Sleep by steps, so that the stop file is seen within a few seconds
+        let wake = std::time::Instant::now() + std::time::Duration::from_secs(interval_minutes * 60);
+        while std::time::Instant::now() < wake {
+            if stop_requested(dir) {
+                return Ok(());
+            }
+            std::thread::sleep(std::time::Duration::from_secs(5).min(wake - std::time::Instant::now()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn throughput_rolls_over_the_last_polls() {
+        let mut throughput = Throughput::default();
+        throughput.push(FollowSample { at_secs: 0.0, lists: 0, inputs_done: 0 });
+        assert_eq!(throughput.lists_per_hour(), None);
+
+        // 1000 lists and 2 input batches per hour for the first polls...
+        for hour in 1..FOLLOW_WINDOW as u64 {
+            throughput.push(FollowSample { at_secs: hour as f64 * 3600.0, lists: 1000 * hour, inputs_done: 2 * hour });
+        }
+        assert_eq!(throughput.lists_per_hour(), Some(1000.0));
+        assert_eq!(throughput.projected_secs(20), Some(5.0 * 3600.0));
+
+        // ...then the pace doubles: the window forgets the first polls
+        let last = FOLLOW_WINDOW as u64 - 1;
+        for hour in 1..FOLLOW_WINDOW as u64 {
+            throughput.push(FollowSample {
+                at_secs: (last + hour) as f64 * 3600.0, lists: 1000 * last + 2000 * hour, inputs_done: 2 * last + 4 * hour });
+        }
+        assert_eq!(throughput.lists_per_hour(), Some(2000.0));
+        assert_eq!(throughput.inputs_per_hour(), Some(4.0));
+        assert_eq!(throughput.projected_secs(2 * last + 4 * last), Some(0.0));
+    }
+}
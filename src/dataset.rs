@@ -0,0 +1,231 @@
+//! Dataset module: per-size Parquet datasets for analytics (DuckDB, pandas, polars)
+//!
+//! A dataset is the directory nsl_{size:02}_dataset/ holding one Parquet file per
+//! list file (same name, .parquet extension), with the columns:
+//!   size (u8), max_card (u8), cards and remaining (81-bit masks, 11 bytes: card c
+//!   is bit c % 8 of byte c / 8, as in the packed encoding), source_batch (u32)
+//! so that `SELECT * FROM 'nsl_12_dataset/*.parquet'` reads the whole size.
+//!
+//! Key features:
+//! - Written during processing with --also-parquet (size, unitary, cascade, watch),
+//!   frame by frame alongside each list file
+//! - Built from existing files by --convert SIZE --to dataset
+//! - Parts written as .tmp, then renamed; needs the `parquet` feature
+//!
+//! Note: files rewritten later (compaction, split, repair) do not update their
+//! part; rebuild the dataset with --convert once the size is final.
+//!
+//! Used by --also-parquet and --convert (dataset format)
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use separator::Separatable;
+
+use crate::convert::ConvertReport;
+use crate::no_set_list::NoSetListSerialized;
+use crate::utils::*;
+
+// Write a dataset part next to every list file written (--also-parquet)
+static ALSO_PARQUET: AtomicBool = AtomicBool::new(false);
+
+/// Write a dataset part alongside the list files written from now on
+pub fn set_also_parquet(enabled: bool) {
+    ALSO_PARQUET.store(enabled, Ordering::Relaxed);
+}
+
+/// True if list files are written with their dataset part
+pub fn also_parquet() -> bool {
+    ALSO_PARQUET.load(Ordering::Relaxed)
+}
+
+/// Dataset directory of `size` in `dir`
+pub fn dataset_dir(dir: &str, size: u8) -> PathBuf {
+    Path::new(dir).join(format!("nsl_{:02}_dataset", size))
+}
+
+/// Streaming writer of the dataset part of one list file
+pub struct DatasetPartWriter {
+    path: String,
+    tmp: String,
+    #[cfg(feature = "parquet")]
+    writer: parquet_backend::MaskRowWriter,
+}
+
+impl DatasetPartWriter {
+    /// Create the part of the list file `list_file` (in the dataset directory next to it)
+    pub fn create(list_file: &str) -> std::io::Result<Self> {
+        let list_path = Path::new(list_file);
+        let name = list_path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let parsed = crate::filenames::parse_filename(&name).ok_or_else(|| std::io::Error::new(
+            std::io::ErrorKind::InvalidInput, format!("Cannot parse the list filename {}", name)))?;
+        let dir = list_path.parent().map(|p| p.to_string_lossy().into_owned()).unwrap_or_default();
+        let dir = if dir.is_empty() { ".".to_string() } else { dir };
+        let dataset = dataset_dir(&dir, parsed.target_size);
+        std::fs::create_dir_all(&dataset)?;
+        let path = dataset.join(name.replace(".rkyv", ".parquet")).to_string_lossy().into_owned();
+        let tmp = format!("{}.tmp", path);
+        Self::open(path, tmp)
+    }
+
+    #[cfg(feature = "parquet")]
+    fn open(path: String, tmp: String) -> std::io::Result<Self> {
+        let writer = parquet_backend::MaskRowWriter::new(std::fs::File::create(&tmp)?)?;
+        Ok(DatasetPartWriter { path, tmp, writer })
+    }
+
+    #[cfg(not(feature = "parquet"))]
+    fn open(_path: String, _tmp: String) -> std::io::Result<Self> {
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported,
+            "Parquet datasets need a build with the `parquet` feature (cargo build --release --features parquet)"))
+    }
+
+    /// Append the lists of `source_batch` (one row group)
+    #[cfg_attr(not(feature = "parquet"), allow(unused_variables))]
+    pub fn write_chunk(&mut self, source_batch: u32, lists: &[NoSetListSerialized]) -> std::io::Result<()> {
+        #[cfg(feature = "parquet")]
+        self.writer.write_chunk(source_batch, lists)?;
+        Ok(())
+    }
+
+    /// Close the part and move it into place; returns its path
+    pub fn finish(self) -> std::io::Result<String> {
+        #[cfg(feature = "parquet")]
+        self.writer.finish()?;
+        std::fs::rename(&self.tmp, &self.path)?;
+        Ok(self.path)
+    }
+}
+
+/// Write the dataset of `size` from the list files of `input_dir` (parts in `output_dir`)
+pub fn export_dataset(input_dir: &str, output_dir: &str, size: u8) -> std::io::Result<ConvertReport> {
+    test_print(&format!("\nCONVERT MODE: Exporting size {:02} lists to a Parquet dataset...", size));
+    test_print(&format!("   Input directory:  {}", input_dir));
+    let start_time = std::time::Instant::now();
+
+    let files = crate::filenames::list_input_files(input_dir, size);
+    if files.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound,
+            format!("No size {:02} files found in {}", size, input_dir)));
+    }
+
+    let output = dataset_dir(output_dir, size).to_string_lossy().into_owned();
+    let mut report = ConvertReport { size, files: 0, lists: 0, output: output.clone() };
+    for file in files.iter() {
+        let name = Path::new(&file.path).file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let source_batch = crate::filenames::parse_filename(&name).map(|p| p.source_batch).unwrap_or(0);
+        let mut part = DatasetPartWriter::create(&Path::new(output_dir).join(&name).to_string_lossy())?;
+        let nb = crate::io_helpers::load_lists_in_chunks(&file.path, crate::io_helpers::FRAME_LISTS,
+            |chunk| part.write_chunk(source_batch, &chunk))?;
+        part.finish()?;
+        report.files += 1;
+        report.lists += nb;
+        test_print(&format!("   ... {:>10} lists from {}", nb.separated_string(), name));
+    }
+
+    test_print(&format!("   ... {} lists written to {} in {:.2}s",
+        report.lists.separated_string(), output, start_time.elapsed().as_secs_f64()));
+    Ok(report)
+}
+
+/// Parquet writer of the dataset columns (one row group per chunk)
+#[cfg(feature = "parquet")]
+mod parquet_backend {
+    use std::fs::File;
+    use std::sync::Arc;
+    use arrow_array::{ArrayRef, FixedSizeBinaryArray, RecordBatch, UInt32Array, UInt8Array};
+    use arrow_schema::{DataType, Field, Schema, SchemaRef};
+    use parquet::arrow::ArrowWriter;
+
+    use crate::no_set_list::{NoSetListPacked, NoSetListSerialized, MASK_BYTES};
+
+    fn io_error<E: std::fmt::Display>(e: E) -> std::io::Error {
+        std::io::Error::other(e.to_string())
+    }
+
+    pub struct MaskRowWriter {
+        schema: SchemaRef,
+        writer: ArrowWriter<File>,
+    }
+
+    impl MaskRowWriter {
+        pub fn new(file: File) -> std::io::Result<Self> {
+            let mask = DataType::FixedSizeBinary(MASK_BYTES as i32);
+            let schema = Arc::new(Schema::new(vec![
+                Field::new("size", DataType::UInt8, false),
+                Field::new("max_card", DataType::UInt8, false),
+                Field::new("cards", mask.clone(), false),
+                Field::new("remaining", mask, false),
+                Field::new("source_batch", DataType::UInt32, false),
+            ]));
+            let writer = ArrowWriter::try_new(file, schema.clone(), None).map_err(io_error)?;
+            Ok(MaskRowWriter { schema, writer })
+        }
+
+        pub fn write_chunk(&mut self, source_batch: u32, lists: &[NoSetListSerialized]) -> std::io::Result<()> {
+            let packed: Vec<NoSetListPacked> = lists.iter().map(NoSetListPacked::from_serialized).collect();
+            let mask_column = |mask: fn(&NoSetListPacked) -> &[u8]| {
+                FixedSizeBinaryArray::try_from_sparse_iter_with_size(packed.iter().map(|p| Some(mask(p))), MASK_BYTES as i32)
+                    .map_err(io_error)
+            };
+            let cards = mask_column(NoSetListPacked::cards_mask)?;
+            let remaining = mask_column(NoSetListPacked::remaining_mask)?;
+            let columns: Vec<ArrayRef> = vec![
+                Arc::new(UInt8Array::from(lists.iter().map(|l| l.n).collect::<Vec<u8>>())),
+                Arc::new(UInt8Array::from(lists.iter().map(|l| l.max_card as u8).collect::<Vec<u8>>())),
+                Arc::new(cards),
+                Arc::new(remaining),
+                Arc::new(UInt32Array::from(vec![source_batch; lists.len()])),
+            ];
+            let record_batch = RecordBatch::try_new(self.schema.clone(), columns).map_err(io_error)?;
+            self.writer.write(&record_batch).map_err(io_error)?;
+            self.writer.flush().map_err(io_error)
+        }
+
+        pub fn finish(self) -> std::io::Result<()> {
+            self.writer.close().map_err(io_error)?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dataset_parts_hold_one_row_per_list() {
+        let dir = crate::test_dir::TestDir::new("dataset");
+        let dir_str = dir.to_string_lossy().into_owned();
+
+        let lists = vec![
+            NoSetListSerialized { n: 3, max_card: 3, no_set_list: vec![0, 1, 3], remaining_cards_list: vec![4, 80] },
+            NoSetListSerialized { n: 3, max_card: 9, no_set_list: vec![0, 1, 9], remaining_cards_list: vec![] },
+        ];
+        let input = crate::filenames::output_filename(&dir_str, 0, 5, 3, 7);
+        assert!(crate::io_helpers::save_to_file_serialized(&lists, &input));
+        let result = export_dataset(&dir_str, &dir_str, 3);
+
+        #[cfg(not(feature = "parquet"))]
+        assert_eq!(result.expect_err("no parquet support").kind(), std::io::ErrorKind::Unsupported);
+
+        #[cfg(feature = "parquet")]
+        {
+            use arrow_array::{Array, FixedSizeBinaryArray, UInt32Array};
+            use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+            let report = result.expect("dataset");
+            assert_eq!((report.files, report.lists), (1, 2));
+            let part = dataset_dir(&dir_str, 3).join("nsl_00_batch_000005_to_03_batch_000007.parquet");
+            let batch = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(part).expect("part"))
+                .and_then(|b| b.build()).expect("reader")
+                .next().expect("one batch").expect("read");
+            assert_eq!(batch.num_rows(), 2);
+            let cards = batch.column_by_name("cards").unwrap().as_any().downcast_ref::<FixedSizeBinaryArray>().unwrap();
+            assert_eq!(cards.value(0), &[0b1011, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            let remaining = batch.column_by_name("remaining").unwrap().as_any().downcast_ref::<FixedSizeBinaryArray>().unwrap();
+            assert_eq!(remaining.value(0)[10], 1); // card 80
+            let sources = batch.column_by_name("source_batch").unwrap().as_any().downcast_ref::<UInt32Array>().unwrap();
+            assert_eq!(sources.value(1), 5);
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! Disk-guard module: free space kept by a cascade
+//!
+//! A cascade left alone writes until the volume is full and the next write
+//! fails, in the middle of an output file. The guard checks the free space of
+//! the output directory before each size and before each input batch, and acts
+//! before that happens.
+//!
+//! Key features:
+//! - Threshold in GB (--cascade-min-free-gb; no check without it)
+//! - Below it (--cascade-low-space): stop cleanly between two input batches, with
+//!   the command resuming the cascade (default); pause until space is freed
+//!   (checked every LOW_SPACE_POLL_SECS); or prune the earlier sizes (inputs
+//!   consumed by the next size, outputs verified first, see prune), then stop if
+//!   that is not enough
+//! - Unknown free space counts as enough
+//!
+//! Used by --cascade mode
+
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use separator::Separatable;
+
+use crate::utils::*;
+
+/// Seconds between two free-space checks while the cascade waits for space
+pub const LOW_SPACE_POLL_SECS: u64 = 60;
+
+/// What the cascade does below the threshold
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LowSpaceAction {
+    Stop,
+    Pause,
+    Prune,
+}
+
+impl LowSpaceAction {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "stop" => Ok(LowSpaceAction::Stop),
+            "pause" => Ok(LowSpaceAction::Pause),
+            "prune" => Ok(LowSpaceAction::Prune),
+            other => Err(format!("Unknown low-space action '{}' (stop, pause or prune)", other)),
+        }
+    }
+}
+
+// Free space kept, in bytes (0: no check)
+static MIN_FREE_BYTES: AtomicU64 = AtomicU64::new(0);
+// LowSpaceAction as u8 (see set_min_free_space)
+static LOW_SPACE_ACTION: AtomicU8 = AtomicU8::new(0);
+// Set once the processing stopped on low space: the cascade stops after the size
+static STOPPED: AtomicBool = AtomicBool::new(false);
+
+/// Keep `min_free_gb` GB free in the cascade directories, doing `action` below it
+pub fn set_min_free_space(min_free_gb: Option<f64>, action: LowSpaceAction) {
+    MIN_FREE_BYTES.store(min_free_gb.map_or(0, |gb| (gb * (1u64 << 30) as f64) as u64), Ordering::Relaxed);
+    LOW_SPACE_ACTION.store(action as u8, Ordering::Relaxed);
+}
+
+fn action() -> LowSpaceAction {
+    match LOW_SPACE_ACTION.load(Ordering::Relaxed) {
+        1 => LowSpaceAction::Pause,
+        2 => LowSpaceAction::Prune,
+        _ => LowSpaceAction::Stop,
+    }
+}
+
+/// True once a check stopped the processing
+pub fn stopped() -> bool {
+    STOPPED.load(Ordering::Relaxed)
+}
+
+/// True if `free` bytes (None: unknown) keep at least `min_free` bytes free
+fn enough(free: Option<u64>, min_free: u64) -> bool {
+    free.is_none_or(|free| free >= min_free)
+}
+
+/// True if `dir` has more free space than the threshold (no check, no pause)
+pub fn has_free_space(dir: &str) -> bool {
+    enough(crate::storage::free_space(Path::new(dir)), MIN_FREE_BYTES.load(Ordering::Relaxed))
+}
+
+/// True if `dir` has more free space than the threshold; below it, pauses until it
+/// has (pause), or records the stop (stop, prune: nothing is pruned while a size runs)
+pub fn has_room(dir: &str) -> bool {
+    let min_free = MIN_FREE_BYTES.load(Ordering::Relaxed);
+    if min_free == 0 {
+        return true;
+    }
+    let mut notified = false;
+    loop {
+        let free = crate::storage::free_space(Path::new(dir));
+        if enough(free, min_free) {
+            return true;
+        }
+        let message = format!("Low disk space in {}: {} bytes free, {} kept free",
+            dir, free.unwrap_or(0).separated_string(), min_free.separated_string());
+        test_print(&format!("   {}", message));
+        if !notified {
+            crate::notify::notify("low_disk", &message, None);
+            notified = true;
+        }
+        if action() != LowSpaceAction::Pause {
+            STOPPED.store(true, Ordering::Relaxed);
+            return false;
+        }
+        test_print(&format!("   ... paused; checking again in {}s (free some space or stop the run)", LOW_SPACE_POLL_SECS));
+        std::thread::sleep(std::time::Duration::from_secs(LOW_SPACE_POLL_SECS));
+    }
+}
+
+/// Check `dir` before a size; with prune, prune the sizes of `earlier` (pairs of
+/// input and output directories, with the input size) until there is room
+pub fn room_before_size(dir: &str, earlier: &[(String, String, u8)]) -> bool {
+    let min_free = MIN_FREE_BYTES.load(Ordering::Relaxed);
+    if min_free == 0 || enough(crate::storage::free_space(Path::new(dir)), min_free) {
+        return true;
+    }
+    if action() == LowSpaceAction::Prune {
+        for (input_dir, output_dir, size) in earlier {
+            test_print(&format!("   Low disk space in {}: pruning the size {} inputs consumed by size {}", dir, size, size + 1));
+            match crate::prune::prune_consumed_inputs(input_dir, output_dir, *size, None, false) {
+                Ok(report) => test_print(&format!("   ... {} size {} files pruned", report.files_pruned, size)),
+                Err(e) => test_print(&format!("   ... size {} not pruned: {}", size, e)),
+            }
+            if enough(crate::storage::free_space(Path::new(dir)), min_free) {
+                return true;
+            }
+        }
+    }
+    has_room(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn low_space_actions_are_parsed() {
+        assert_eq!(LowSpaceAction::parse("pause"), Ok(LowSpaceAction::Pause));
+        assert_eq!(LowSpaceAction::parse("prune").map(|a| a as u8), Ok(2));
+        assert!(LowSpaceAction::parse("wait").is_err());
+        assert!(enough(None, 1 << 30));
+        assert!(enough(Some(2 << 30), 1 << 30));
+        assert!(!enough(Some(1 << 29), 1 << 30));
+    }
+}
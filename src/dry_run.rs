@@ -0,0 +1,199 @@
+//! Dry-run module: plan of the file operations of a destructive mode
+//!
+//! With --dry-run, size, compact, prune, repair and cascade print the files they
+//! would read, write, rewrite, delete or rename, and touch nothing. The plan is
+//! derived from the global states and the file headers; no list is decoded.
+//!
+//! Key features:
+//! - One line per file operation, in execution order
+//! - Compaction simulated on the state counts (same plan as the real loop)
+//! - Size mode: input compaction (sizes 13+), inputs left to process, output
+//!   naming, state and history files
+//! - Outputs whose number depends on the computation are reported as a pattern
+//!
+//! Used by --dry-run (size, compact, prune, repair and cascade modes)
+
+use std::path::Path;
+use separator::Separatable;
+
+use crate::file_info::GlobalFileState;
+use crate::utils::*;
+
+/// Kind of file operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Read,
+    Write,
+    Rewrite,
+    Delete,
+    Rename,
+}
+
+impl Operation {
+    fn label(&self) -> &'static str {
+        match self {
+            Operation::Read => "read",
+            Operation::Write => "write",
+            Operation::Rewrite => "rewrite",
+            Operation::Delete => "delete",
+            Operation::Rename => "rename",
+        }
+    }
+}
+
+/// One planned file operation
+#[derive(Debug, Clone)]
+pub struct PlannedOperation {
+    pub operation: Operation,
+    pub path: String,
+    pub detail: String,
+}
+
+/// File operations a mode would perform
+#[derive(Debug, Clone, Default)]
+pub struct DryRunPlan {
+    pub title: String,
+    pub operations: Vec<PlannedOperation>,
+    pub notes: Vec<String>,
+}
+
+impl DryRunPlan {
+    pub fn new(title: &str) -> Self {
+        Self { title: title.to_string(), ..Default::default() }
+    }
+
+    pub fn add(&mut self, operation: Operation, path: impl AsRef<Path>, detail: impl Into<String>) {
+        self.operations.push(PlannedOperation {
+            operation,
+            path: path.as_ref().to_string_lossy().into_owned(),
+            detail: detail.into(),
+        });
+    }
+
+    pub fn note(&mut self, note: impl Into<String>) {
+        self.notes.push(note.into());
+    }
+
+    /// Append the operations and notes of another plan
+    pub fn extend(&mut self, other: DryRunPlan) {
+        self.operations.extend(other.operations);
+        self.notes.extend(other.notes);
+    }
+
+    pub fn count(&self, operation: Operation) -> usize {
+        self.operations.iter().filter(|o| o.operation == operation).count()
+    }
+
+    pub fn print(&self) {
+        test_print(&format!("\nDRY RUN: {} (nothing is modified)", self.title));
+        for op in self.operations.iter() {
+            if op.detail.is_empty() {
+                test_print(&format!("   {:<7} | {}", op.operation.label(), op.path));
+            } else {
+                test_print(&format!("   {:<7} | {} | {}", op.operation.label(), op.path, op.detail));
+            }
+        }
+        for note in self.notes.iter() {
+            test_print(&format!("   note: {}", note));
+        }
+        test_print(&format!("   ... {} reads, {} writes, {} rewrites, {} deletes, {} renames",
+            self.count(Operation::Read), self.count(Operation::Write), self.count(Operation::Rewrite),
+            self.count(Operation::Delete), self.count(Operation::Rename)));
+    }
+}
+
+/// Global state of `size` in `dir`, without the legacy rebuild paths (which may save)
+pub fn load_state_readonly(dir: &str, size: u8) -> std::io::Result<GlobalFileState> {
+    let has_state = ["rkyv", "json", "sqlite"].iter()
+        .any(|ext| Path::new(dir).join(format!("nsl_{:02}_global_info.{}", size, ext)).exists());
+    if has_state {
+        GlobalFileState::from_sources(dir, size)
+    } else {
+        Ok(GlobalFileState::new(dir, size))
+    }
+}
+
+/// State files written by a flush and an export
+pub fn add_state_writes(plan: &mut DryRunPlan, dir: &str, size: u8, history: bool) {
+    let stem = if history { "global_info_history" } else { "global_info" };
+    let sqlite = !history && crate::file_info::state_backend() == crate::file_info::StateBackend::Sqlite;
+    for ext in [if sqlite { "sqlite" } else { "rkyv" }, "json", "txt"] {
+        plan.add(Operation::Write, Path::new(dir).join(format!("nsl_{:02}_{}.{}", size, stem, ext)),
+            if history { "history" } else { "state" });
+    }
+}
+
+/// Plan of `--size output_size [start_batch]` reading `input_dir` and writing `output_dir`
+pub fn plan_size(input_dir: &str, output_dir: &str, output_size: u8, start_batch: Option<u32>,
+    max_lists_per_file: u64, force_recount: bool) -> std::io::Result<DryRunPlan> {
+    let mut plan = DryRunPlan::new(&format!("size {:02} from {} into {}", output_size, input_dir, output_dir));
+    if output_size == 3 {
+        plan.add(Operation::Write, crate::filenames::output_filename(output_dir, 0, 0, 3, 0), "seed lists");
+        return Ok(plan);
+    }
+    if output_size == 4 && start_batch.is_none() {
+        plan.add(Operation::Write, crate::filenames::output_filename(input_dir, 0, 0, 3, 0), "seed lists");
+    }
+    if start_batch.is_some() && force_recount {
+        plan.add(Operation::Write, Path::new(output_dir).join(format!("nsl_{:02}_global_count.txt", output_size)),
+            "--force recount");
+    }
+
+    let source_size = output_size - 1;
+    let state = load_state_readonly(output_dir, output_size)?;
+    let last_done = state.last_consumed_batch();
+
+    // Input compaction (fresh runs of sizes 13+): the inputs read are then the compacted ones
+    if source_size >= 13 && last_done.is_none() {
+        let compaction = crate::compaction::plan_compaction(input_dir, source_size, max_lists_per_file, None)?;
+        if !compaction.operations.is_empty() {
+            plan.note(format!("the input files are compacted first; the reads below are the files on disk now (size {:02})", source_size));
+        }
+        plan.extend(compaction);
+    }
+
+    let inputs = crate::filenames::list_input_files_with_legacy(input_dir, source_size);
+    let (done, files) = state.split_consumed_inputs(inputs, None, start_batch);
+    if !done.is_empty() {
+        plan.note(format!("{} input files already processed (from state){}", done.len(),
+            if start_batch.is_some() { " or before the restart batch" } else { "" }));
+    }
+    let first_output = crate::filenames::get_next_output_batch_from_files(output_dir, output_size, u32::MAX);
+    for file in files.iter() {
+        let count = crate::io_helpers::count_lists_in_file(&file.path).unwrap_or(0);
+        plan.add(Operation::Read, &file.path, format!("{} lists", count.separated_string()));
+        plan.add(Operation::Write, Path::new(output_dir).join(format!("nsl_{:02}_batch_{:06}_to_{:02}_batch_*.rkyv",
+            source_size, file.batch, output_size)), format!("up to {} lists per file", max_lists_per_file.separated_string()));
+        plan.add(Operation::Write, Path::new(output_dir).join(format!("nsl_{:02}_intermediate_count_from_{:02}_{:06}.txt",
+            output_size, source_size, file.batch)), "output counts of the batch");
+    }
+    if files.is_empty() {
+        plan.note("no input files left to process");
+    } else {
+        plan.note(format!("{} input files; outputs numbered from batch {:06}, their number depends on the computation (see --estimate)",
+            files.len(), first_output));
+    }
+    add_state_writes(&mut plan, output_dir, output_size, false);
+    if output_size >= 13 {
+        plan.note(format!("the size {:02} outputs are compacted afterwards (plan with --compact {} --dry-run once written)",
+            output_size, output_size));
+    }
+    add_state_writes(&mut plan, output_dir, output_size, true);
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operations_are_counted_by_kind() {
+        let mut plan = DryRunPlan::new("test");
+        plan.add(Operation::Read, "a.rkyv", "");
+        plan.add(Operation::Delete, "a.rkyv", "fully consumed");
+        add_state_writes(&mut plan, "dir", 14, false);
+        assert_eq!(plan.count(Operation::Read), 1);
+        assert_eq!(plan.count(Operation::Write), 3);
+        assert_eq!(plan.operations[2].path, Path::new("dir").join("nsl_14_global_info.rkyv").to_string_lossy());
+    }
+}
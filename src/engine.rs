@@ -0,0 +1,187 @@
+//! Engine module: the expansion engines behind one trait
+//!
+//! The modes expanding lists (size, unitary, default, benchmark) drive an
+//! ExpansionEngine instead of ListOfNSL itself, so that another engine can be run
+//! against the same inputs, state and outputs, and benchmarked (--engine).
+//!
+//! Key features:
+//! - ExpansionEngine: seed lists, expansion of a size, of a set of input files or
+//!   of one input batch, the background compactor, and the time breakdown
+//! - current: the engine of this version (ListOfNSL)
+//! - The earlier engines (list_of_nlists, list_of_nsl_hybrid, n_list) are not in
+//!   this tree any more: a variant engine is added as a new EngineKind
+//!
+//! Used by --size, --unitary, the default mode and --benchmark (--engine)
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::compaction::BackgroundCompactor;
+use crate::file_info::GlobalFileState;
+use crate::filenames::{InputFile, Shard};
+use crate::list_of_nsl::ListOfNSL;
+
+/// Options of the expansion, as given on the command line
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EngineOptions {
+    pub strong_prune: bool,
+    pub isomorph_cache: bool,
+    pub shard: Option<Shard>,
+}
+
+/// Seconds spent in each part of the expansion since the engine was created
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EngineTimings {
+    pub computation_secs: f64,
+    pub file_io_secs: f64,
+    pub conversion_secs: f64,
+}
+
+/// An engine expanding the lists of a size into those of the next one: it reads
+/// the input files, writes the output files and records them in the state given
+pub trait ExpansionEngine {
+    /// Name of the engine, as given to --engine
+    fn name(&self) -> &'static str;
+
+    /// Write the seed lists (size 3) in the output directory
+    fn create_seed_lists(&mut self);
+
+    /// Expand every input file of `current_size`; returns the lists created
+    fn process_all_files_of_current_size_n(&mut self, current_size: u8, max: &u64, state: Option<&mut GlobalFileState>) -> u64;
+
+    /// Expand the input files `files` of `current_size`, numbering the outputs from
+    /// `output_reference_batch`; returns the lists created
+    fn process_input_files(&mut self, current_size: u8, files: &[InputFile], output_reference_batch: u32, max: &u64,
+        state: Option<&mut GlobalFileState>) -> u64;
+
+    /// Expand the input batch `input_batch` of `input_size`; returns the lists created
+    fn process_single_batch(&mut self, input_size: u8, input_batch: u32, max: &u64, state: Option<&mut GlobalFileState>) -> u64;
+
+    /// Expand the input file `input` of `input_size` (loaded by path, whatever the
+    /// other files of its batch number); returns the lists created
+    fn process_single_input(&mut self, input_size: u8, input: &InputFile, max: &u64, state: Option<&mut GlobalFileState>) -> u64;
+
+    /// Compactor of the outputs running while the engine expands (--background-compact)
+    fn compactor(&mut self) -> &mut Option<BackgroundCompactor>;
+
+    /// Time breakdown of the expansion so far
+    fn timings(&self) -> EngineTimings;
+}
+
+impl ExpansionEngine for ListOfNSL {
+    fn name(&self) -> &'static str {
+        EngineKind::Current.name()
+    }
+
+    fn create_seed_lists(&mut self) {
+        ListOfNSL::create_seed_lists(self)
+    }
+
+    fn process_all_files_of_current_size_n(&mut self, current_size: u8, max: &u64, state: Option<&mut GlobalFileState>) -> u64 {
+        ListOfNSL::process_all_files_of_current_size_n(self, current_size, max, state)
+    }
+
+    fn process_input_files(&mut self, current_size: u8, files: &[InputFile], output_reference_batch: u32, max: &u64,
+        state: Option<&mut GlobalFileState>) -> u64 {
+        ListOfNSL::process_input_files(self, current_size, files, output_reference_batch, max, state)
+    }
+
+    fn process_single_batch(&mut self, input_size: u8, input_batch: u32, max: &u64, state: Option<&mut GlobalFileState>) -> u64 {
+        ListOfNSL::process_single_batch(self, input_size, input_batch, max, state)
+    }
+
+    fn process_single_input(&mut self, input_size: u8, input: &InputFile, max: &u64, state: Option<&mut GlobalFileState>) -> u64 {
+        ListOfNSL::process_single_input(self, input_size, input, max, state)
+    }
+
+    fn compactor(&mut self) -> &mut Option<BackgroundCompactor> {
+        &mut self.compactor
+    }
+
+    fn timings(&self) -> EngineTimings {
+        EngineTimings {
+            computation_secs: self.computation_time,
+            file_io_secs: self.file_io_time,
+            conversion_secs: self.conversion_time,
+        }
+    }
+}
+
+/// Engines selectable with --engine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EngineKind {
+    Current,
+}
+
+impl EngineKind {
+    /// Every engine, in --engine order
+    pub const ALL: [EngineKind; 1] = [EngineKind::Current];
+
+    /// Parse an --engine value
+    pub fn parse(text: &str) -> Result<Self, String> {
+        Self::ALL.into_iter().find(|kind| kind.name() == text).ok_or_else(|| {
+            let names: Vec<&str> = Self::ALL.iter().map(|kind| kind.name()).collect();
+            format!("Unknown engine '{}' (expected {})", text, names.join(", "))
+        })
+    }
+
+    /// Name of the engine, as given to --engine
+    pub fn name(&self) -> &'static str {
+        match self {
+            EngineKind::Current => "current",
+        }
+    }
+
+    /// An engine reading its inputs in `input_path` and writing its outputs in `output_path`
+    pub fn create(&self, input_path: &str, output_path: &str, options: EngineOptions) -> Box<dyn ExpansionEngine> {
+        match self {
+            EngineKind::Current => {
+                let mut engine = ListOfNSL::with_paths(input_path, output_path);
+                engine.strong_prune = options.strong_prune;
+                engine.isomorph_cache = options.isomorph_cache;
+                engine.shard = options.shard;
+                Box::new(engine)
+            }
+        }
+    }
+}
+
+// Engine of the run (0 = current)
+static ENGINE: AtomicU8 = AtomicU8::new(0);
+
+/// Select the engine of the expanding modes
+pub fn set_engine(kind: EngineKind) {
+    ENGINE.store(kind as u8, Ordering::Relaxed);
+}
+
+/// Engine of the expanding modes
+pub fn engine() -> EngineKind {
+    let selected = ENGINE.load(Ordering::Relaxed);
+    EngineKind::ALL.into_iter().find(|kind| *kind as u8 == selected).unwrap_or(EngineKind::Current)
+}
+
+/// An engine of the selected kind (see set_engine)
+pub fn create_engine(input_path: &str, output_path: &str, options: EngineOptions) -> Box<dyn ExpansionEngine> {
+    engine().create(input_path, output_path, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn engines_are_selected_by_name_and_expand_behind_the_trait() {
+        assert_eq!(EngineKind::parse("current"), Ok(EngineKind::Current));
+        assert!(EngineKind::parse("v022").is_err());
+
+        let dir = crate::test_dir::TestDir::new("engine");
+        let dir_str = dir.to_string_lossy().into_owned();
+
+        let mut engine = create_engine(&dir_str, &dir_str, EngineOptions::default());
+        assert_eq!(engine.name(), "current");
+        engine.create_seed_lists();
+        let seeds = crate::filenames::find_input_filename(&dir_str, 3, 0).expect("seed file");
+        assert_eq!(crate::io_helpers::count_lists_in_file(&seeds).expect("count"), 58_896);
+        assert!(engine.timings().computation_secs >= 0.0);
+        assert!(engine.compactor().is_none());
+    }
+}
@@ -0,0 +1,170 @@
+//! Error module: what stopped a run, by kind
+//!
+//! The modes used to fail with a formatted message alone; a caller could print it
+//! but not tell a full disk from a wrong argument. They fail with a FunnyError
+//! instead, turned into a message and an exit code by the command line only.
+//!
+//! Key features:
+//! - Variants a caller can match: Io (a file operation, with its context, the file
+//!   and the io::Error), InvalidArgument, Validation (counts not matching), State
+//!   (state of a size locked or missing), StateMismatch (state of a size not
+//!   matching the run or another state), Config (options that cannot work
+//!   together), CorruptFile (one file that cannot be decoded), Integrity (files or
+//!   lists found corrupted or inconsistent), Failed (anything else)
+//! - Kinds, as in the run summary: io, validation (invalid arguments included),
+//!   state (mismatches included), config, integrity (corrupt files included), failed
+//! - is_retryable: io and failed errors may pass on a new try, the others not
+//! - Context: `.context("Error during count")` on any result whose error converts
+//!   (io::Error, FunnyError) prefixes the message and keeps the kind;
+//!   `.file_context("Failed to read", path)` also names the file, and makes an
+//!   undecodable file (InvalidData) a CorruptFile
+//! - A locked state (see file_info) converts to a state error
+//!
+//! Used by every mode (modes.rs), and by main.rs for the exit code and the run summary
+
+use std::path::{Path, PathBuf};
+
+/// Why a run failed
+#[derive(Debug, thiserror::Error)]
+pub enum FunnyError {
+    #[error("{}", describe_io(context, path.as_deref(), source))]
+    Io { context: String, path: Option<PathBuf>, #[source] source: std::io::Error },
+    #[error("{0}")]
+    InvalidArgument(String),
+    #[error("{0}")]
+    Validation(String),
+    #[error("{0}")]
+    State(String),
+    #[error("{reason} (state of size {size:02})")]
+    StateMismatch { size: u8, reason: String },
+    #[error("{0}")]
+    Config(String),
+    #[error("{reason}: {} is corrupted", path.display())]
+    CorruptFile { path: PathBuf, reason: String },
+    #[error("{0}")]
+    Integrity(String),
+    #[error("{0}")]
+    Failed(String),
+}
+
+pub type FunnyResult<T> = Result<T, FunnyError>;
+
+/// "context path: error", without the parts missing
+fn describe_io(context: &str, path: Option<&Path>, source: &std::io::Error) -> String {
+    let mut prefix = context.to_string();
+    if let Some(path) = path {
+        prefix = if prefix.is_empty() { path.display().to_string() } else { format!("{} {}", prefix, path.display()) };
+    }
+    if prefix.is_empty() { source.to_string() } else { format!("{}: {}", prefix, source) }
+}
+
+impl FunnyError {
+    /// Kind of the error, as in the run summary: "io", "validation", ...
+    pub fn kind(&self) -> &'static str {
+        match self {
+            FunnyError::Io { .. } => "io",
+            FunnyError::InvalidArgument(_) | FunnyError::Validation(_) => "validation",
+            FunnyError::State(_) | FunnyError::StateMismatch { .. } => "state",
+            FunnyError::Config(_) => "config",
+            FunnyError::CorruptFile { .. } | FunnyError::Integrity(_) => "integrity",
+            FunnyError::Failed(_) => "failed",
+        }
+    }
+
+    /// True if trying again may succeed (a file operation, or an unknown failure)
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, FunnyError::Io { .. } | FunnyError::Failed(_))
+    }
+
+    /// The same error, its message prefixed with `context`
+    pub fn with_context(self, context: &str) -> Self {
+        let prefix = |message: String| format!("{}: {}", context, message);
+        match self {
+            FunnyError::Io { context: inner, path, source } if inner.is_empty() => FunnyError::Io { context: context.to_string(), path, source },
+            FunnyError::Io { context: inner, path, source } => FunnyError::Io { context: prefix(inner), path, source },
+            FunnyError::InvalidArgument(message) => FunnyError::InvalidArgument(prefix(message)),
+            FunnyError::Validation(message) => FunnyError::Validation(prefix(message)),
+            FunnyError::State(message) => FunnyError::State(prefix(message)),
+            FunnyError::StateMismatch { size, reason } => FunnyError::StateMismatch { size, reason: prefix(reason) },
+            FunnyError::Config(message) => FunnyError::Config(prefix(message)),
+            FunnyError::CorruptFile { path, reason } => FunnyError::CorruptFile { path, reason: prefix(reason) },
+            FunnyError::Integrity(message) => FunnyError::Integrity(prefix(message)),
+            FunnyError::Failed(message) => FunnyError::Failed(prefix(message)),
+        }
+    }
+
+    /// The same error on the file `path`: an io error names it, and one reading
+    /// data that does not decode (InvalidData) is a CorruptFile
+    pub fn with_path(self, path: &Path) -> Self {
+        match self {
+            FunnyError::Io { context, source, .. } if source.kind() == std::io::ErrorKind::InvalidData => {
+                let reason = if context.is_empty() { source.to_string() } else { format!("{}: {}", context, source) };
+                FunnyError::CorruptFile { path: path.to_path_buf(), reason }
+            }
+            FunnyError::Io { context, path: None, source } => FunnyError::Io { context, path: Some(path.to_path_buf()), source },
+            other => other,
+        }
+    }
+}
+
+impl From<std::io::Error> for FunnyError {
+    fn from(error: std::io::Error) -> Self {
+        if crate::file_info::is_state_locked(&error) {
+            FunnyError::State(error.to_string())
+        } else {
+            FunnyError::Io { context: String::new(), path: None, source: error }
+        }
+    }
+}
+
+/// `.context(...)` on results whose error converts to a FunnyError
+pub trait Context<T> {
+    fn context(self, context: &str) -> FunnyResult<T>;
+
+    /// `.context(...)` of an operation on the file `path` (see FunnyError::with_path)
+    fn file_context(self, context: &str, path: impl AsRef<Path>) -> FunnyResult<T>;
+}
+
+impl<T, E: Into<FunnyError>> Context<T> for Result<T, E> {
+    fn context(self, context: &str) -> FunnyResult<T> {
+        self.map_err(|e| e.into().with_context(context))
+    }
+
+    fn file_context(self, context: &str, path: impl AsRef<Path>) -> FunnyResult<T> {
+        self.map_err(|e| e.into().with_context(context).with_path(path.as_ref()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_keep_their_kind_through_the_context() {
+        let io: FunnyResult<()> = Err(std::io::Error::new(std::io::ErrorKind::NotFound, "no such file")).context("Error during count");
+        let io = io.unwrap_err();
+        assert_eq!((io.kind(), io.to_string().as_str()), ("io", "Error during count: no such file"));
+        assert!(io.is_retryable() && std::error::Error::source(&io).is_some());
+
+        let validation = FunnyError::Validation("size 21 out of range".to_string()).with_context("Cascade");
+        assert_eq!((validation.kind(), validation.to_string().as_str()), ("validation", "Cascade: size 21 out of range"));
+        assert!(!validation.is_retryable());
+
+        let mismatch = FunnyError::StateMismatch { size: 9, reason: "reduced files".to_string() }.with_context("Size 9");
+        assert_eq!((mismatch.kind(), mismatch.to_string().as_str()), ("state", "Size 9: reduced files (state of size 09)"));
+    }
+
+    #[test]
+    fn file_errors_name_their_file() {
+        let path = Path::new("data/nsl_04_batch_000000_to_05_batch_000000.rkyv");
+        let missing: std::io::Result<()> = Err(std::io::Error::new(std::io::ErrorKind::NotFound, "no such file"));
+        let missing = missing.file_context("Failed to read", path).unwrap_err();
+        assert!(matches!(&missing, FunnyError::Io { path: Some(p), .. } if p == path));
+        assert_eq!(missing.to_string(), format!("Failed to read {}: no such file", path.display()));
+
+        let truncated: std::io::Result<()> = Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Truncated frame"));
+        let corrupt = truncated.file_context("Failed to read", path).unwrap_err();
+        assert_eq!((corrupt.kind(), corrupt.is_retryable()), ("integrity", false));
+        assert_eq!(corrupt.to_string(), format!("Failed to read: Truncated frame: {} is corrupted", path.display()));
+    }
+}
@@ -0,0 +1,223 @@
+//! Estimate module: predict the output, disk usage and runtime of a size
+//!
+//! Before launching a large size, a few input batches are sampled and expanded
+//! in memory (nothing is written besides a small disk-speed probe), and the
+//! measures are extrapolated to all input lists.
+//!
+//! Key features:
+//! - Input batches sampled evenly over the input files (first, ..., last)
+//! - Within a batch, every k-th list is expanded (lists are ordered by max_card,
+//!   so a prefix would be biased)
+//! - Measured: expansion factor, computation and conversion time per input list,
+//!   encoded bytes per output list (in the selected encoding), disk write speed
+//! - Extrapolated: output lists, output files, disk space, wall-clock time
+//!
+//! The estimate is only as good as the sample: the expansion factor varies
+//! with max_card, so sampling more batches gives a tighter figure.
+//!
+//! Used by --estimate mode
+
+use std::io::Write;
+use std::path::Path;
+use separator::Separatable;
+
+use crate::no_set_list::{NoSetList, NoSetListSerialized};
+use crate::utils::*;
+
+/// Input lists expanded per sampled batch
+const ESTIMATE_LISTS_PER_BATCH: u64 = 20_000;
+
+/// Output lists encoded to measure the bytes per list and the disk speed
+const ESTIMATE_PROBE_LISTS: usize = 200_000;
+
+/// Measures and extrapolation for one output size
+#[derive(Debug, Clone)]
+pub struct SizeEstimate {
+    pub size: u8,
+    pub input_files: u64,
+    pub input_lists: u64,
+    pub input_bytes: u64,
+    pub sampled_batches: u64,
+    pub sampled_lists: u64,
+    pub expansion_factor: f64,   // output lists per input list
+    pub compute_secs_per_list: f64,
+    pub conversion_secs_per_list: f64,
+    pub bytes_per_output_list: f64,
+    pub disk_mb_per_sec: f64,
+    pub output_lists: u64,
+    pub output_files: u64,
+    pub output_bytes: u64,
+    pub total_secs: f64,
+}
+
+/// Format a number of bytes as GB
+fn gb(bytes: u64) -> String {
+    format!("{:.2} GB", bytes as f64 / 1_073_741_824.0)
+}
+
+/// Format a duration in seconds as h/m/s
+fn duration(secs: f64) -> String {
+    let secs = secs.max(0.0) as u64;
+    format!("{}h {:02}m {:02}s", secs / 3600, (secs % 3600) / 60, secs % 60)
+}
+
+/// Indices of `count` items spread evenly over 0..total (first and last included)
+pub fn spread_indices(total: usize, count: usize) -> Vec<usize> {
+    let count = count.min(total);
+    match count {
+        0 => Vec::new(),
+        1 => vec![0],
+        _ => (0..count).map(|i| i * (total - 1) / (count - 1)).collect(),
+    }
+}
+
+/// Write `lists` to a probe file in `dir` (synced), returning (bytes, seconds)
+fn disk_probe(dir: &str, lists: &Vec<NoSetListSerialized>) -> std::io::Result<(u64, f64)> {
+    let bytes = crate::io_helpers::encode_lists(lists, crate::io_helpers::output_encoding())
+        .map_err(std::io::Error::other)?;
+    let path = Path::new(dir).join(format!("funny_estimate_probe_{}.tmp", std::process::id()));
+    let start = std::time::Instant::now();
+    {
+        let mut file = std::fs::File::create(&path)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+    }
+    let secs = start.elapsed().as_secs_f64();
+    std::fs::remove_file(&path)?;
+    Ok((bytes.len() as u64, secs))
+}
+
+/// Sample `batches` input batches of `size - 1` in `input_dir` and extrapolate the computation of `size`
+pub fn estimate_size(input_dir: &str, size: u8, batches: usize, max_lists_per_file: u64, strong_prune: bool) -> std::io::Result<SizeEstimate> {
+    test_print(&format!("\nESTIMATE MODE: Predicting the computation of size {:02}...", size));
+    test_print(&format!("   Input directory: {}", input_dir));
+    let input_size = size - 1;
+    let files = crate::filenames::list_input_files(input_dir, input_size);
+    if files.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound,
+            format!("No size {:02} files found in {}", input_size, input_dir)));
+    }
+
+    // Input totals (counts read from the archive headers, no decoding)
+    let mut input_lists = 0u64;
+    let mut input_bytes = 0u64;
+    for file in files.iter() {
+        input_lists += crate::io_helpers::count_lists_in_file(&file.path)?;
+        input_bytes += std::fs::metadata(crate::storage::resolve_path(&file.path))?.len();
+    }
+    test_print(&format!("   ... {} input lists of size {:02} in {} files ({})",
+        input_lists.separated_string(), input_size, files.len(), gb(input_bytes)));
+
+    // Expand every k-th list of the sampled batches
+    let mut sampled_lists = 0u64;
+    let mut created = 0u64;
+    let mut compute_secs = 0.0;
+    let mut conversion_secs = 0.0;
+    let mut probe: Vec<NoSetListSerialized> = Vec::new();
+    let sampled = spread_indices(files.len(), batches.max(1));
+    for &i in sampled.iter() {
+        let file = &files[i];
+        let count = crate::io_helpers::count_lists_in_file(&file.path)?;
+        let stride = (count / ESTIMATE_LISTS_PER_BATCH).max(1) as usize;
+        let mut position = 0usize;
+        let mut batch_lists = 0u64;
+        let mut batch_created = 0u64;
+        crate::io_helpers::load_lists_in_chunks(&file.path, 1_000_000, |chunk| {
+            let first = (stride - position % stride) % stride;
+            for list in chunk.iter().skip(first).step_by(stride) {
+                let conv_start = std::time::Instant::now();
+                let nsl = NoSetList::from_serialized(list);
+                conversion_secs += conv_start.elapsed().as_secs_f64();
+
+                let comp_start = std::time::Instant::now();
+                let children = nsl.build_higher_nsl(strong_prune);
+                compute_secs += comp_start.elapsed().as_secs_f64();
+
+                let conv_start = std::time::Instant::now();
+                for child in children.iter() {
+                    let serialized = child.to_serialized();
+                    if probe.len() < ESTIMATE_PROBE_LISTS {
+                        probe.push(serialized);
+                    }
+                }
+                conversion_secs += conv_start.elapsed().as_secs_f64();
+                batch_lists += 1;
+                batch_created += children.len() as u64;
+            }
+            position += chunk.len();
+            Ok(())
+        })?;
+        sampled_lists += batch_lists;
+        created += batch_created;
+        test_print(&format!("   ... batch {:06}: {} of {} lists expanded into {} lists",
+            file.batch, batch_lists.separated_string(), count.separated_string(), batch_created.separated_string()));
+    }
+    if sampled_lists == 0 {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "The sampled input batches hold no list"));
+    }
+
+    let (probe_bytes, probe_secs) = if probe.is_empty() { (0, 0.0) } else { disk_probe(input_dir, &probe)? };
+    let bytes_per_output_list = if probe.is_empty() { 0.0 } else { probe_bytes as f64 / probe.len() as f64 };
+    let disk_mb_per_sec = if probe_secs > 0.0 { probe_bytes as f64 / 1_048_576.0 / probe_secs } else { 0.0 };
+
+    let expansion_factor = created as f64 / sampled_lists as f64;
+    let compute_secs_per_list = compute_secs / sampled_lists as f64;
+    let conversion_secs_per_list = conversion_secs / sampled_lists as f64;
+    let output_lists = (expansion_factor * input_lists as f64).round() as u64;
+    let output_bytes = (bytes_per_output_list * output_lists as f64) as u64;
+    // Inputs read once and outputs written once, both at the measured disk speed
+    let io_secs = if disk_mb_per_sec > 0.0 {
+        (input_bytes + output_bytes) as f64 / 1_048_576.0 / disk_mb_per_sec
+    } else {
+        0.0
+    };
+    let total_secs = (compute_secs_per_list + conversion_secs_per_list) * input_lists as f64 + io_secs;
+
+    Ok(SizeEstimate {
+        size,
+        input_files: files.len() as u64,
+        input_lists,
+        input_bytes,
+        sampled_batches: sampled.len() as u64,
+        sampled_lists,
+        expansion_factor,
+        compute_secs_per_list,
+        conversion_secs_per_list,
+        bytes_per_output_list,
+        disk_mb_per_sec,
+        output_lists,
+        output_files: output_lists.div_ceil(max_lists_per_file.max(1)),
+        output_bytes,
+        total_secs,
+    })
+}
+
+/// Print the summary plan of an estimate
+pub fn print_estimate(estimate: &SizeEstimate) {
+    test_print(&format!("\n   Plan for size {:02} (from {} sampled lists in {} of {} input batches):",
+        estimate.size, estimate.sampled_lists.separated_string(), estimate.sampled_batches, estimate.input_files));
+    test_print(&format!("   - input lists:       {:>20} ({})", estimate.input_lists.separated_string(), gb(estimate.input_bytes)));
+    test_print(&format!("   - expansion factor:  {:>12.3} output lists per input list", estimate.expansion_factor));
+    test_print(&format!("   - output lists:      {:>20}", estimate.output_lists.separated_string()));
+    test_print(&format!("   - output files:      {:>20}", estimate.output_files.separated_string()));
+    test_print(&format!("   - disk space:        {:>20} ({:.1} bytes per list)", gb(estimate.output_bytes), estimate.bytes_per_output_list));
+    test_print(&format!("   - computation:       {:>20} ({:.2} us per input list)",
+        duration(estimate.compute_secs_per_list * estimate.input_lists as f64), estimate.compute_secs_per_list * 1e6));
+    test_print(&format!("   - conversion:        {:>20} ({:.2} us per input list)",
+        duration(estimate.conversion_secs_per_list * estimate.input_lists as f64), estimate.conversion_secs_per_list * 1e6));
+    test_print(&format!("   - disk speed:        {:>17.0} MB/s (measured write)", estimate.disk_mb_per_sec));
+    test_print(&format!("   - wall-clock time:   {:>20} (single process)", duration(estimate.total_secs)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sampled_batches_are_spread_evenly() {
+        assert_eq!(spread_indices(10, 3), vec![0, 4, 9]);
+        assert_eq!(spread_indices(2, 5), vec![0, 1]);
+        assert_eq!(spread_indices(7, 1), vec![0]);
+        assert!(spread_indices(0, 3).is_empty());
+    }
+}
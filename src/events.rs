@@ -0,0 +1,357 @@
+//! Events module: progress of a run, as events to observers
+//!
+//! A program embedding the library (a GUI, a monitor) needs the progress of a run
+//! as data, not as console lines. Every mode, the engine, the state and the
+//! warnings emit events to the observers registered; the command line registers
+//! ConsoleObserver, which prints them as before.
+//!
+//! Key features:
+//! - Observer: on_mode_started / on_mode_completed (every mode, see
+//!   modes::execute_mode), on_batch_loaded (input batch read), on_output_saved
+//!   (output file written), on_file_processed (file read, written, counted,
+//!   checked, verified, scanned, archived or deleted by a mode other than the
+//!   expansion), on_state_flushed (state of a size saved), on_size_completed
+//!   (lists created by the expansion of a size, or of the batches asked for),
+//!   on_warning (see findings::warn), on_progress (console line of the run, see
+//!   utils::test_print), on_result (JSON result of the benchmark, diff and merge
+//!   modes, printed on stdout by src/main.rs); every method defaults to nothing
+//! - Events are plain data (serializable), emitted on the thread doing the work
+//!   (the background compactor included): observers must be Send + Sync
+//! - No observer registered: the events go nowhere; the console lines are printed
+//!   by test_print and progress_print themselves, whatever the observers
+//! - Activity of the run: the batches read and the outputs written, tallied per
+//!   size from the events whatever the observers (see run_activity), for the run
+//!   summary; reset with the rest of the run by findings::begin_run
+//!
+//! Used by every mode (modes.rs), list_of_nsl, compaction, file_info, findings and
+//! utils::test_print; ConsoleObserver by src/main.rs
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use separator::Separatable;
+use serde::Serialize;
+
+use crate::findings::Finding;
+use crate::utils::*;
+
+/// An input batch read by the engine
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchLoaded {
+    pub size: u8,        // size of its lists
+    pub batch: u32,
+    pub nb_lists: u64,
+    pub filename: String,
+}
+
+/// An output file written by the engine
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputSaved {
+    pub size: u8,        // size of its lists
+    pub source_batch: u32,
+    pub batch: u32,
+    pub nb_lists: u64,
+    pub filename: String,
+}
+
+/// The state of a size saved (rkyv file or database)
+#[derive(Debug, Clone, Serialize)]
+pub struct StateFlushed {
+    pub size: u8,
+    pub directory: String,
+    pub nb_files: usize,
+}
+
+/// Lists created by the expansion of a size (or of the batches asked for)
+#[derive(Debug, Clone, Serialize)]
+pub struct SizeCompleted {
+    pub size: u8,        // size of the lists created
+    pub nb_lists: u64,
+    pub elapsed_secs: f64,
+}
+
+/// A mode starting (modes nested in a cascade or a history run included)
+#[derive(Debug, Clone, Serialize)]
+pub struct ModeStarted {
+    pub mode: String,
+}
+
+/// A mode ended: its message, or the error that stopped it
+#[derive(Debug, Clone, Serialize)]
+pub struct ModeCompleted {
+    pub mode: String,
+    pub succeeded: bool,
+    pub message: String,
+    pub elapsed_secs: f64,
+}
+
+/// The result of a mode as JSON (benchmark result, state differences, merge report)
+#[derive(Debug, Clone, Serialize)]
+pub struct ResultReady {
+    pub mode: String,
+    pub json: String,
+}
+
+/// What a mode did with a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileAction {
+    Read,
+    Written,
+    Counted,
+    Checked,
+    Verified,
+    Scanned,
+    Archived,
+    Deleted,
+}
+
+/// A file handled by a mode other than the expansion (compaction, count, check...)
+#[derive(Debug, Clone, Serialize)]
+pub struct FileProcessed {
+    pub mode: String,
+    pub size: u8,        // size of its lists
+    pub action: FileAction,
+    pub filename: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nb_lists: Option<u64>,
+}
+
+impl FileProcessed {
+    pub fn new(mode: &str, size: u8, action: FileAction, filename: impl Into<String>, nb_lists: Option<u64>) -> Self {
+        Self { mode: mode.to_string(), size, action, filename: filename.into(), nb_lists }
+    }
+}
+
+/// Files and lists of one size read and written by a run
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SizeActivity {
+    pub files_read: u64,
+    pub lists_read: u64,
+    pub files_written: u64,
+    pub lists_written: u64,
+}
+
+/// What a run read and wrote: per size of the lists, and the output files created
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RunActivity {
+    pub sizes: BTreeMap<u8, SizeActivity>,
+    pub files_created: Vec<String>,
+}
+
+// Activity of the run so far
+static ACTIVITY: Mutex<RunActivity> = Mutex::new(RunActivity { sizes: BTreeMap::new(), files_created: Vec::new() });
+
+/// Activity of the run so far
+pub fn run_activity() -> RunActivity {
+    ACTIVITY.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Forget the activity of the previous run (see findings::begin_run)
+pub(crate) fn reset_activity() {
+    *ACTIVITY.lock().unwrap_or_else(|e| e.into_inner()) = RunActivity::default();
+}
+
+/// Receiver of the events of a run
+pub trait Observer: Send + Sync {
+    fn on_mode_started(&self, _event: &ModeStarted) {}
+    fn on_mode_completed(&self, _event: &ModeCompleted) {}
+    fn on_batch_loaded(&self, _event: &BatchLoaded) {}
+    fn on_output_saved(&self, _event: &OutputSaved) {}
+    fn on_file_processed(&self, _event: &FileProcessed) {}
+    fn on_state_flushed(&self, _event: &StateFlushed) {}
+    fn on_size_completed(&self, _event: &SizeCompleted) {}
+    fn on_warning(&self, _warning: &Finding) {}
+    fn on_progress(&self, _line: &str) {}
+    fn on_result(&self, _event: &ResultReady) {}
+}
+
+// Observers of the run, in registration order
+static OBSERVERS: Mutex<Vec<Arc<dyn Observer>>> = Mutex::new(Vec::new());
+
+/// Register an observer of the events of the run
+pub fn add_observer(observer: Box<dyn Observer>) {
+    OBSERVERS.lock().unwrap_or_else(|e| e.into_inner()).push(Arc::from(observer));
+}
+
+/// Unregister every observer
+pub fn clear_observers() {
+    OBSERVERS.lock().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+/// Call `notify` on every observer (outside the lock: an observer may warn)
+fn emit(notify: impl Fn(&dyn Observer)) {
+    let observers = OBSERVERS.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    for observer in observers.iter() {
+        notify(observer.as_ref());
+    }
+}
+
+/// Emit a mode starting
+pub fn mode_started(event: ModeStarted) {
+    emit(|observer| observer.on_mode_started(&event));
+}
+
+/// Emit a mode ended
+pub fn mode_completed(event: ModeCompleted) {
+    emit(|observer| observer.on_mode_completed(&event));
+}
+
+/// Emit an input batch read
+pub fn batch_loaded(event: BatchLoaded) {
+    {
+        let mut activity = ACTIVITY.lock().unwrap_or_else(|e| e.into_inner());
+        let size = activity.sizes.entry(event.size).or_default();
+        size.files_read += 1;
+        size.lists_read += event.nb_lists;
+    }
+    emit(|observer| observer.on_batch_loaded(&event));
+}
+
+/// Emit an output file written
+pub fn output_saved(event: OutputSaved) {
+    {
+        let mut activity = ACTIVITY.lock().unwrap_or_else(|e| e.into_inner());
+        let size = activity.sizes.entry(event.size).or_default();
+        size.files_written += 1;
+        size.lists_written += event.nb_lists;
+        activity.files_created.push(event.filename.clone());
+    }
+    emit(|observer| observer.on_output_saved(&event));
+}
+
+/// Emit a file handled by a mode (read and written files count in run_activity)
+pub fn file_processed(event: FileProcessed) {
+    if matches!(event.action, FileAction::Read | FileAction::Written) {
+        let mut activity = ACTIVITY.lock().unwrap_or_else(|e| e.into_inner());
+        let size = activity.sizes.entry(event.size).or_default();
+        let lists = event.nb_lists.unwrap_or(0);
+        if event.action == FileAction::Read {
+            size.files_read += 1;
+            size.lists_read += lists;
+        } else {
+            size.files_written += 1;
+            size.lists_written += lists;
+            activity.files_created.push(event.filename.clone());
+        }
+    }
+    emit(|observer| observer.on_file_processed(&event));
+}
+
+/// Emit a state saved
+pub fn state_flushed(event: StateFlushed) {
+    emit(|observer| observer.on_state_flushed(&event));
+}
+
+/// Emit the end of the expansion of a size
+pub fn size_completed(event: SizeCompleted) {
+    emit(|observer| observer.on_size_completed(&event));
+}
+
+/// Emit a warning of the run (see findings::warn)
+pub fn warning(warning: &Finding) {
+    emit(|observer| observer.on_warning(warning));
+}
+
+/// Emit the JSON result of a mode
+pub fn result(event: ResultReady) {
+    emit(|observer| observer.on_result(&event));
+}
+
+/// Emit a console line of the run (see utils::test_print and utils::progress_print)
+pub fn progress(line: &str) {
+    emit(|observer| observer.on_progress(line));
+}
+
+/// The events as console lines (test_print, and debug_print for the outputs); the
+/// console lines of the run (on_progress) are printed by test_print itself
+pub struct ConsoleObserver;
+
+impl Observer for ConsoleObserver {
+    fn on_batch_loaded(&self, event: &BatchLoaded) {
+        test_print(&format!("   ... loaded {:>10} lists from batch {}",
+            event.nb_lists.separated_string(), event.batch));
+    }
+
+    fn on_output_saved(&self, event: &OutputSaved) {
+        debug_print(&format!("   ... saved   {:>10} no-set-lists  to  {}",
+            event.nb_lists.separated_string(), event.filename));
+    }
+
+    fn on_size_completed(&self, event: &SizeCompleted) {
+        let hours = (event.elapsed_secs / 3600.0) as u64;
+        let minutes = ((event.elapsed_secs % 3600.0) / 60.0) as u64;
+        let seconds = (event.elapsed_secs % 60.0) as u64;
+        test_print(&format!("   ... created a total of {:>15} no-set-{:02} lists \
+            in {:>10.2} seconds ({:02}h{:02}m{:02}s)",
+            event.nb_lists.separated_string(), event.size, event.elapsed_secs, hours, minutes, seconds));
+    }
+
+    fn on_warning(&self, warning: &Finding) {
+        test_print(&format!("Warning: {}", warning.message));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl Observer for Arc<Recorder> {
+        fn on_mode_started(&self, event: &ModeStarted) {
+            self.0.lock().unwrap().push(format!("started {}", event.mode));
+        }
+
+        fn on_mode_completed(&self, event: &ModeCompleted) {
+            self.0.lock().unwrap().push(format!("completed {} {}", event.mode, event.succeeded));
+        }
+
+        fn on_batch_loaded(&self, event: &BatchLoaded) {
+            self.0.lock().unwrap().push(format!("loaded {} {}", event.size, event.batch));
+        }
+
+        fn on_file_processed(&self, event: &FileProcessed) {
+            self.0.lock().unwrap().push(format!("{:?} {} {:?}", event.action, event.filename, event.nb_lists));
+        }
+
+        fn on_warning(&self, warning: &Finding) {
+            self.0.lock().unwrap().push(format!("warning {}", warning.kind));
+        }
+
+        fn on_progress(&self, line: &str) {
+            self.0.lock().unwrap().push(format!("progress {}", line));
+        }
+
+        fn on_result(&self, event: &ResultReady) {
+            self.0.lock().unwrap().push(format!("result {} {}", event.mode, event.json));
+        }
+    }
+
+    #[test]
+    fn observers_receive_the_events_and_the_warnings() {
+        let _lock = crate::findings::RUN_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = crate::test_dir::TestDir::new("events_modes");
+        let name = "nsl_04_batch_000000_to_05_batch_000000.rkyv";
+        let list = crate::no_set_list::NoSetListSerialized { n: 5, max_card: 9, no_set_list: vec![0, 1, 3, 4, 9],
+            remaining_cards_list: vec![10] };
+        assert!(crate::io_helpers::save_to_file_serialized(&vec![list], &dir.join(name).to_string_lossy()));
+
+        let recorder = Arc::new(Recorder(Mutex::new(Vec::new())));
+        add_observer(Box::new(recorder.clone()));
+        batch_loaded(BatchLoaded { size: 7, batch: 12, nb_lists: 3, filename: "nsl.rkyv".to_string() });
+        size_completed(SizeCompleted { size: 8, nb_lists: 9, elapsed_secs: 1.0 });
+        result(ResultReady { mode: "diff".to_string(), json: "{}".to_string() });
+        crate::findings::warn("events_test", "observed");
+        test_print("events_test line");
+        crate::modes::ProcessingConfig::count(5).input(dir.str()).force(true).run().expect("count");
+        clear_observers();
+        batch_loaded(BatchLoaded { size: 7, batch: 13, nb_lists: 3, filename: "nsl.rkyv".to_string() });
+
+        let seen = recorder.0.lock().unwrap().clone();
+        for expected in ["loaded 7 12", "warning events_test", "progress events_test line", "result diff {}", "started count",
+            &format!("Counted {} Some(1)", name), "completed count true"] {
+            assert!(seen.iter().any(|s| s == expected), "{} not in {:?}", expected, seen);
+        }
+        assert!(!seen.contains(&"loaded 7 13".to_string()));
+    }
+}
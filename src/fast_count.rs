@@ -0,0 +1,192 @@
+//! Fast-count module: list counts of a size derived from the file sizes
+//!
+//! --count maps every file not yet in the state to count its lists: on a fresh
+//! directory of several TB, that is a day of reading. Most of it can be avoided:
+//! the files written with a footer record their count, and the others hold lists
+//! of one size, so their byte count is nearly proportional to their list count.
+//!
+//! Key features:
+//! - Files with a footer: count read from the footer (exact, 24 bytes read)
+//! - Files without: a few of them (FAST_COUNT_SAMPLES, spread over the batches)
+//!   counted in full to calibrate the bytes per list, separately for compressed
+//!   and plain files; the others estimated from their size
+//! - Estimated entries marked in the state (estimated_counts) until a full
+//!   --count (or any registration of the file) replaces them
+//! - Spread of the calibration (min/max bytes per list of the samples) printed as
+//!   the error bound of the estimates
+//!
+//! Used by --count --fast mode
+
+use std::path::Path;
+use separator::Separatable;
+
+use crate::file_info::GlobalFileState;
+use crate::filenames::parse_filename;
+use crate::utils::*;
+
+/// Files counted in full per calibration (compressed and plain files apart)
+pub const FAST_COUNT_SAMPLES: usize = 8;
+
+/// Outcome of a fast count
+#[derive(Debug, Clone, Default)]
+pub struct FastCountReport {
+    pub from_footer: u64,          // files counted from their footer
+    pub sampled: u64,              // files counted in full to calibrate
+    pub estimated: u64,            // files whose count is estimated
+    pub lists_estimated: u64,      // lists in the estimated files
+    pub not_estimated: Vec<String>, // files left out (unreadable, or no calibration)
+}
+
+/// Bytes per list measured on the samples: (overall, min, max)
+type Calibration = (f64, f64, f64);
+
+/// Count the samples in full and return the bytes per list they give
+fn calibrate(state: &mut GlobalFileState, base_path: &str, files: &[(String, u64)], report: &mut FastCountReport)
+    -> Option<Calibration> {
+    let step = files.len().div_ceil(FAST_COUNT_SAMPLES).max(1);
+    let (mut bytes, mut lists) = (0u64, 0u64);
+    let (mut min, mut max) = (f64::MAX, 0f64);
+    for (name, size) in files.iter().step_by(step) {
+        let Ok(count) = crate::io_helpers::count_lists_in_file(&format!("{}/{}", base_path, name)) else { continue };
+        register(state, base_path, name, count);
+        report.sampled += 1;
+        if count > 0 {
+            let ratio = *size as f64 / count as f64;
+            (min, max) = (min.min(ratio), max.max(ratio));
+            bytes += size;
+            lists += count;
+        }
+    }
+    (lists > 0).then(|| (bytes as f64 / lists as f64, min, max))
+}
+
+/// Register `name` with `count` lists, taking its batches from its name
+fn register(state: &mut GlobalFileState, base_path: &str, name: &str, count: u64) {
+    let Some(parsed) = parse_filename(name) else { return };
+    let (bytes, mtime) = crate::storage::file_metadata(&format!("{}/{}", base_path, name))
+        .map(|(bytes, mtime)| (Some(bytes), mtime))
+        .unwrap_or((None, None));
+    state.register_file(name, parsed.source_batch, parsed.target_batch, count, parsed.compacted, bytes, mtime);
+    crate::events::file_processed(crate::events::FileProcessed::new("count", state.target_size(),
+        crate::events::FileAction::Counted, name, Some(count)));
+}
+
+/// Register the files of `target_size` in `base_path` missing from the state, with
+/// their footer count, a full count (calibration samples) or an estimate from their
+/// size, and save the state
+pub fn fast_count_size_files(base_path: &str, target_size: u8, force: bool) -> std::io::Result<FastCountReport> {
+    test_print(&format!("\nFAST COUNT MODE: Estimating the lists of size {:02} from the file sizes...", target_size));
+    test_print(&format!("   Input directory: {}", base_path));
+    let start_time = std::time::Instant::now();
+
+    // Without a saved state, loading would read every file: the point is not to
+    let mut state = if force || !GlobalFileState::has_saved_state(base_path, target_size) {
+        crate::file_info::acquire_state_lock(base_path, target_size)?;
+        GlobalFileState::new(base_path, target_size)
+    } else {
+        match GlobalFileState::from_sources(base_path, target_size) {
+            Ok(state) => state,
+            Err(e) if crate::file_info::is_state_locked(&e) => return Err(e),
+            Err(_) => GlobalFileState::new(base_path, target_size),
+        }
+    };
+    let known: std::collections::HashSet<String> = state.entries().keys().map(|(_, _, name)| name.clone()).collect();
+
+    let mut report = FastCountReport::default();
+    // Files without footer, by compression: (name, bytes)
+    let mut plain: Vec<(String, u64)> = Vec::new();
+    let mut compressed: Vec<(String, u64)> = Vec::new();
+    let mut names: Vec<String> = crate::storage::list_file_names(base_path)?.into_iter()
+        .filter(|name| !known.contains(name) && parse_filename(name).is_some_and(|p| p.target_size == target_size))
+        .collect();
+    names.sort();
+    test_print(&format!("   ... {} files not in the state", names.len()));
+    for name in names {
+        let path = format!("{}/{}", base_path, name);
+        match crate::io_helpers::file_footer(&path) {
+            Ok(Some(footer)) => {
+                register(&mut state, base_path, &name, footer.nb_lists);
+                report.from_footer += 1;
+            }
+            Ok(None) => match crate::storage::file_metadata(&path) {
+                Some((bytes, _)) if crate::io_helpers::is_compressed_file(Path::new(&path)) => compressed.push((name, bytes)),
+                Some((bytes, _)) => plain.push((name, bytes)),
+                None => report.not_estimated.push(name),
+            },
+            Err(_) => report.not_estimated.push(name),
+        }
+    }
+    test_print(&format!("   ... {} counted from their footer, {} plain and {} compressed files without footer",
+        report.from_footer, plain.len(), compressed.len()));
+
+    for (kind, files) in [("plain", plain), ("compressed", compressed)] {
+        if files.is_empty() {
+            continue;
+        }
+        let Some((per_list, min, max)) = calibrate(&mut state, base_path, &files, &mut report) else {
+            test_print(&format!("   [!!] No {} file could be counted to calibrate: {} files left out", kind, files.len()));
+            report.not_estimated.extend(files.into_iter().map(|(name, _)| name));
+            continue;
+        };
+        test_print(&format!("   ... {} files: {:.1} bytes per list (samples from {:.1} to {:.1}, estimates within {:+.1}% / {:+.1}%)",
+            kind, per_list, min, max, (per_list / max - 1.0) * 100.0, (per_list / min - 1.0) * 100.0));
+        let sampled: std::collections::HashSet<&String> = state.entries().keys().map(|(_, _, name)| name).collect();
+        let to_estimate: Vec<(String, u64)> = files.into_iter().filter(|(name, _)| !sampled.contains(name)).collect();
+        for (name, bytes) in to_estimate {
+            let count = (bytes as f64 / per_list).round() as u64;
+            register(&mut state, base_path, &name, count);
+            state.set_estimated(&name, true);
+            report.estimated += 1;
+            report.lists_estimated += count;
+        }
+    }
+
+    state.flush()?;
+    state.export_human_readable()?;
+    test_print(&format!("\nFast count completed in {:.2} seconds: {} from footers, {} counted, {} estimated ({} lists), {} left out",
+        start_time.elapsed().as_secs_f64(), report.from_footer, report.sampled, report.estimated,
+        report.lists_estimated.separated_string(), report.not_estimated.len()));
+    if !state.estimated().is_empty() {
+        test_print(&format!("   ... {} counts estimated in the state: run --count {} (without --fast) to replace them",
+            state.estimated().len(), target_size));
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::no_set_list::NoSetListSerialized;
+
+    #[test]
+    fn estimated_counts_are_marked_until_a_full_count() {
+        let dir = crate::test_dir::TestDir::new("fast_count");
+        let path = dir.to_string_lossy().into_owned();
+
+        let list = NoSetListSerialized { n: 3, max_card: 3, no_set_list: vec![0, 1, 3], remaining_cards_list: vec![4, 80] };
+        // Bare archives (written before the footer): only their size tells their count
+        let names: Vec<String> = (0..20u32).map(|batch| format!("nsl_02_batch_000000_to_03_batch_{:06}.rkyv", batch)).collect();
+        for (batch, name) in names.iter().enumerate() {
+            let lists = vec![list.clone(); 1000 + 10 * batch];
+            let bytes = rkyv::to_bytes::<_, 256>(&lists).expect("archive");
+            std::fs::write(dir.join(name), &bytes).expect("write");
+        }
+
+        let report = fast_count_size_files(&path, 3, false).expect("fast count");
+        assert_eq!(report.from_footer, 0);
+        assert_eq!(report.sampled + report.estimated, 20);
+        assert!(report.sampled >= 2 && report.estimated > 0, "{:?}", report);
+        let state = GlobalFileState::from_sources(&path, 3).expect("state");
+        assert_eq!(state.estimated().len() as u64, report.estimated);
+        for e in state.entries().values().filter(|e| state.estimated().contains(&e.filename)) {
+            let batch = e.target_batch as u64;
+            let actual = 1000 + 10 * batch;
+            assert!(e.nb_lists_in_file.abs_diff(actual) <= actual / 100, "{}: {} estimated, {} actual", e.filename, e.nb_lists_in_file, actual);
+        }
+
+        crate::list_of_nsl::count_size_files(&path, 3, false, false).expect("count");
+        let state = GlobalFileState::from_sources(&path, 3).expect("state");
+        assert!(state.estimated().is_empty());
+        assert!(state.entries().values().all(|e| e.nb_lists_in_file == 1000 + 10 * e.target_batch as u64));
+    }
+}
@@ -8,13 +8,37 @@
 //! - BTreeMap-backed in-memory state for fast lookups
 //! - Multi-source loading: JSON (fast) → TXT → intermediary → rkyv scan
 //! - Atomic persistence with .tmp files and rename
+//! - Optional SQLite backend (--state-backend sqlite): one row per file in
+//!   nsl_XX_global_info.sqlite, only the entries changed since the last flush are
+//!   written, in one transaction per flush
+//! - Advisory lock per state (nsl_XX_global_info.lock: PID and hostname), taken
+//!   on load and flush and kept until exit, so that two runs never race on a
+//!   state; locks of dead processes on this machine are taken over
+//! - Write-ahead journal (nsl_XX_global_info.journal): the state change of each
+//!   data-file operation is appended before the operation, replayed against the
+//!   disk on the next load if the run stopped before flushing, cleared by flush
+//! - Tombstones: every removed entry is recorded (filename, time, reason) and
+//!   persisted with the state, so --save-history applies the removals of every run
+//! - Consumed inputs: every input file fully processed is recorded (size, batch,
+//!   lists read, completion time), so restarts, prune and validate-chain do not
+//!   have to infer them from the output filenames
+//! - Compacted sources: the lists each compacted file holds from every source
+//!   batch merged into it (its filename only names the last one), persisted with
+//!   the state and optionally as a nsl_..._compacted.sources.json sidecar
+//! - Flush throttling (--flush-every N): output files flush the state every N
+//!   files, and at the end of each input file and of the run
+//! - Backup rotation (--keep-backups N): each flush writes the new state first,
+//!   then keeps the previous one as .rkyv.old and the N-1 before as
+//!   .rkyv.<timestamp>.old (restored by --restore-state)
 //! - File integrity checking and metadata tracking
 //!
 //! Used by all processing modes for state management
 
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs;
 use std::io::BufRead;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, Ordering};
 use separator::Separatable;
 use std::path::{Path, PathBuf};
 
@@ -23,7 +47,7 @@ use rkyv::check_archived_root;
 use rkyv::{Archive, Serialize as RkyvSerialize, Deserialize as RkyvDeserialize};
 use serde::{Deserialize, Serialize};
 
-use crate::no_set_list::NoSetListSerialized;
+use crate::filenames::Shard;
 use crate::utils::debug_print;
 
 /// Represents a single entry from the global count file plus on-disk metadata.
@@ -36,10 +60,536 @@ pub struct FileInfo {
     pub nb_lists_in_file: u64,
     pub filename: String,
     pub compacted: bool,
+    #[serde(default)]
+    pub compressed: bool, // zstd-compressed file (--compress)
     // Optional runtime metadata gathered during checks
     pub exists: Option<bool>,
     pub file_size_bytes: Option<u64>,
     pub modified_timestamp: Option<i64>, // unix seconds
+    #[serde(default)]
+    pub provenance: Option<Provenance>, // files written by size/unitary/cascade since schema version 4
+}
+
+/// Where and how a list file was produced
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Archive, RkyvSerialize, RkyvDeserialize)]
+#[archive(check_bytes)]
+pub struct Provenance {
+    pub hostname: String,
+    pub crate_version: String,
+    pub duration_ms: u64,           // wall-clock time from the previous output file (or the input load)
+    pub input_hash: Option<String>, // "crc32:<hex>" of the input file (from its footer)
+}
+
+impl Provenance {
+    /// Provenance of a file written on this machine by this version
+    pub fn here(duration_ms: u64, input_hash: Option<String>) -> Self {
+        Provenance { hostname: hostname(), crate_version: env!("CARGO_PKG_VERSION").to_string(), duration_ms, input_hash }
+    }
+
+    /// One line summary ("host, v0.4.14, 12.345s, input crc32:0a1b2c3d")
+    pub fn summary(&self) -> String {
+        format!("{}, v{}, {:.3}s, input {}", self.hostname, self.crate_version, self.duration_ms as f64 / 1000.0,
+            self.input_hash.as_deref().unwrap_or("hash unknown"))
+    }
+}
+
+/// Schema version of GlobalFileInfo, bumped whenever FileInfo or GlobalFileInfo
+/// change in a release: the rkyv layout changes with them, so each released version
+/// keeps a read-only struct and an explicit migration to the next one (see
+/// GlobalFileInfo::load_rkyv). Changes between two releases share one version.
+pub const STATE_SCHEMA_VERSION: u32 = 4;
+
+/// Why an entry was removed from the state
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Archive, RkyvSerialize, RkyvDeserialize)]
+#[archive(check_bytes)]
+#[serde(rename_all = "snake_case")]
+pub enum RemovalReason {
+    /// Merged into another file (--compact, cascade appends)
+    CompactedAway,
+    /// File gone from the disk, its entry dropped (--repair)
+    Pruned,
+    /// Removed by a user command on a file still present (--normalize renames)
+    Manual,
+    /// Corrupted, moved to the quarantine subdirectory (--scan)
+    Quarantined,
+}
+
+impl RemovalReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RemovalReason::CompactedAway => "compacted_away",
+            RemovalReason::Pruned => "pruned",
+            RemovalReason::Manual => "manual",
+            RemovalReason::Quarantined => "quarantined",
+        }
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "compacted_away" => Some(RemovalReason::CompactedAway),
+            "pruned" => Some(RemovalReason::Pruned),
+            "manual" => Some(RemovalReason::Manual),
+            "quarantined" => Some(RemovalReason::Quarantined),
+            _ => None,
+        }
+    }
+}
+
+/// Record of an entry removed from the state, persisted with it so that history
+/// merging (--save-history) and audits see the removals of every run
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Archive, RkyvSerialize, RkyvDeserialize)]
+#[archive(check_bytes)]
+pub struct Tombstone {
+    pub source_batch: u32,
+    pub target_batch: u32,
+    pub filename: String,
+    pub removed_at: i64, // unix seconds
+    pub reason: RemovalReason,
+}
+
+/// Input file fully processed by this size, recorded when its last output is saved
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Archive, RkyvSerialize, RkyvDeserialize)]
+#[archive(check_bytes)]
+pub struct ConsumedInput {
+    pub size: u8,            // size of the input lists
+    pub batch: u32,
+    #[serde(default)]
+    pub compacted: bool,     // a compacted input file (shares its batch number with a regular one)
+    #[serde(default)]
+    pub shard: Option<Shard>, // only the lists of this shard were expanded (--shard)
+    pub nb_lists: u64,       // lists read from the input file
+    pub completed_at: i64,   // unix seconds
+}
+
+impl ConsumedInput {
+    pub fn key(&self) -> InputKey {
+        InputKey { batch: self.batch, compacted: self.compacted, shard: self.shard }
+    }
+}
+
+/// Input file of a size, as its consumption is recorded: compacted and regular files
+/// are numbered apart, so the batch alone does not name a file, and a sharded run
+/// consumes only its shard of the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct InputKey {
+    pub batch: u32,
+    pub compacted: bool,
+    pub shard: Option<Shard>,
+}
+
+impl InputKey {
+    /// The whole input file `batch` (not sharded)
+    pub fn whole(batch: u32, compacted: bool) -> Self {
+        InputKey { batch, compacted, shard: None }
+    }
+}
+
+/// Lists a compacted file holds from one source batch
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Archive, RkyvSerialize, RkyvDeserialize)]
+#[archive(check_bytes)]
+pub struct SourceContribution {
+    pub source_batch: u32,
+    pub nb_lists: u64,
+}
+
+/// Source batches merged into a compacted file, in the order of its lists
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Archive, RkyvSerialize, RkyvDeserialize)]
+#[archive(check_bytes)]
+pub struct CompactedSources {
+    pub filename: String,
+    pub sources: Vec<SourceContribution>,
+}
+
+/// Append `nb_lists` lists of `source_batch` to `sources` (merged with the last
+/// contribution when it has the same batch)
+pub fn push_source(sources: &mut Vec<SourceContribution>, source_batch: u32, nb_lists: u64) {
+    if nb_lists == 0 {
+        return;
+    }
+    match sources.last_mut() {
+        Some(last) if last.source_batch == source_batch => last.nb_lists += nb_lists,
+        _ => sources.push(SourceContribution { source_batch, nb_lists }),
+    }
+}
+
+/// Split `sources` after its first `taken` lists: (contributions of the first
+/// `taken` lists, contributions of the rest)
+pub fn split_sources(sources: &[SourceContribution], taken: u64) -> (Vec<SourceContribution>, Vec<SourceContribution>) {
+    let (mut head, mut tail) = (Vec::new(), Vec::new());
+    let mut left = taken;
+    for s in sources.iter() {
+        let in_head = s.nb_lists.min(left);
+        left -= in_head;
+        push_source(&mut head, s.source_batch, in_head);
+        push_source(&mut tail, s.source_batch, s.nb_lists - in_head);
+    }
+    (head, tail)
+}
+
+/// Header of the rkyv state files of the current schema version (8 bytes, keeps the
+/// payload aligned): "NSLSTAT" followed by the schema version. Files without header
+/// are schema version 1 (LegacyFileInfo layout).
+pub const STATE_MAGIC: &[u8; 8] = b"NSLSTAT4";
+
+/// Header of the rkyv state files written since the recorded lists per file (read-only)
+const STATE_MAGIC_V3: &[u8; 8] = b"NSLSTAT3";
+
+/// Header of the rkyv state files written since the compressed flag (read-only)
+const STATE_MAGIC_V2: &[u8; 8] = b"NSLSTAT2";
+
+/// Schema version of the rkyv state file at `path`, read from its header (1 for
+/// files without header)
+pub fn state_schema_version<P: AsRef<Path>>(path: P) -> std::io::Result<u32> {
+    use std::io::Read;
+    let mut header = [0u8; 8];
+    let mut file = fs::File::open(path.as_ref())?;
+    let read = file.read(&mut header)?;
+    if read == header.len() && header.starts_with(b"NSLSTAT") && header[7].is_ascii_digit() {
+        Ok((header[7] - b'0') as u32)
+    } else {
+        Ok(1)
+    }
+}
+
+/// Where the global state of a size is persisted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateBackend {
+    /// nsl_XX_global_info.rkyv, rewritten completely on every flush
+    Rkyv,
+    /// nsl_XX_global_info.sqlite, one row per file, updated in place
+    Sqlite,
+}
+
+impl StateBackend {
+    /// Parse a --state-backend value
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "rkyv" => Ok(StateBackend::Rkyv),
+            "sqlite" => Ok(StateBackend::Sqlite),
+            _ => Err(format!("Unknown state backend '{}' (expected rkyv or sqlite)", text)),
+        }
+    }
+}
+
+// Backend of the global states (0 = rkyv, 1 = sqlite)
+static STATE_BACKEND: AtomicU8 = AtomicU8::new(0);
+
+/// Select the backend the global states are loaded from first and flushed to
+pub fn set_state_backend(backend: StateBackend) {
+    STATE_BACKEND.store(backend as u8, Ordering::Relaxed);
+}
+
+/// State backend currently selected
+pub fn state_backend() -> StateBackend {
+    match STATE_BACKEND.load(Ordering::Relaxed) {
+        1 => StateBackend::Sqlite,
+        _ => StateBackend::Rkyv,
+    }
+}
+
+// Output files registered between two flushes of the state (--flush-every)
+static FLUSH_EVERY: AtomicU64 = AtomicU64::new(1);
+
+/// Flush the state every `n` output files (see GlobalFileState::flush_throttled)
+pub fn set_flush_every(n: u64) {
+    FLUSH_EVERY.store(n.max(1), Ordering::Relaxed);
+}
+
+/// Output files registered between two flushes of the state
+pub fn flush_every() -> u64 {
+    FLUSH_EVERY.load(Ordering::Relaxed)
+}
+
+// Backups kept of each state and history file (--keep-backups)
+static KEEP_BACKUPS: AtomicU64 = AtomicU64::new(1);
+
+/// Keep `n` backups of each state and history file: the last one as .rkyv.old,
+/// the older ones as .rkyv.<timestamp>.old (see rotate_backups)
+pub fn set_keep_backups(n: u64) {
+    KEEP_BACKUPS.store(n.max(1), Ordering::Relaxed);
+}
+
+/// Backups kept of each state and history file
+pub fn keep_backups() -> u64 {
+    KEEP_BACKUPS.load(Ordering::Relaxed)
+}
+
+// Fix the inconsistencies found by the self-check of a loaded state (--fix-state-on-load)
+static FIX_STATE_ON_LOAD: AtomicBool = AtomicBool::new(false);
+
+/// Fix (in memory, saved with the next flush) the inconsistencies found when a
+/// state is loaded, instead of only logging them (see GlobalFileState::self_check)
+pub fn set_fix_state_on_load(fix: bool) {
+    FIX_STATE_ON_LOAD.store(fix, Ordering::Relaxed);
+}
+
+/// True if the inconsistencies of loaded states are fixed
+pub fn fix_state_on_load() -> bool {
+    FIX_STATE_ON_LOAD.load(Ordering::Relaxed)
+}
+
+/// Inconsistency found in a loaded state (see GlobalFileState::self_check)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateIssue {
+    /// The same file recorded under several (source, target) batches
+    DuplicateFilename { filename: String, batches: Vec<(u32, u32)> },
+    /// Batches of the entry different from the ones of its filename
+    BatchMismatch { filename: String, recorded: (u32, u32), in_name: (u32, u32) },
+    /// Filename of another target size (not fixed: the entry belongs elsewhere)
+    WrongSize { filename: String, size_in_name: u8 },
+    /// File recorded with 0 bytes: an interrupted write, holding no list
+    EmptyFile { filename: String, batches: (u32, u32) },
+    /// No list counted in a file holding bytes
+    ZeroCount { filename: String, batches: (u32, u32) },
+}
+
+impl StateIssue {
+    pub fn describe(&self) -> String {
+        match self {
+            StateIssue::DuplicateFilename { filename, batches } =>
+                format!("{} recorded {} times (batches {:?})", filename, batches.len(), batches),
+            StateIssue::BatchMismatch { filename, recorded, in_name } =>
+                format!("{} recorded as batches {:06} -> {:06}, its name says {:06} -> {:06}",
+                    filename, recorded.0, recorded.1, in_name.0, in_name.1),
+            StateIssue::WrongSize { filename, size_in_name } =>
+                format!("{} is a file of size {:02}", filename, size_in_name),
+            StateIssue::EmptyFile { filename, .. } => format!("{} recorded with 0 bytes", filename),
+            StateIssue::ZeroCount { filename, .. } => format!("{} holds bytes but 0 lists are recorded", filename),
+        }
+    }
+}
+
+/// Backup of a state or history file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateBackup {
+    pub timestamp: String, // %Y%m%d_%H%M%S: when the backed up state was written
+    pub path: PathBuf,
+}
+
+/// Timestamp of a backup: mtime of the file (local time)
+fn backup_timestamp(path: &Path) -> Option<String> {
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    Some(chrono::DateTime::<chrono::Local>::from(modified).format("%Y%m%d_%H%M%S").to_string())
+}
+
+/// Backups of the state file `path` (x.rkyv.old and x.rkyv.<timestamp>.old), oldest first
+pub fn state_backups(path: &Path) -> Vec<StateBackup> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Vec::new();
+    };
+    let name = name.to_string_lossy();
+    let mut backups: Vec<StateBackup> = fs::read_dir(dir).into_iter().flatten().flatten()
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let suffix = file_name.strip_prefix(name.as_ref())?.strip_suffix(".old")?;
+            let path = entry.path();
+            if suffix.is_empty() {
+                return Some(StateBackup { timestamp: backup_timestamp(&path)?, path });
+            }
+            let stamp = suffix.strip_prefix('.')?;
+            is_backup_timestamp(stamp).then(|| StateBackup { timestamp: stamp.to_string(), path })
+        })
+        .collect();
+    // The last backup (.old) is the most recent at equal timestamps
+    let last = dir.join(format!("{}.old", name));
+    backups.sort_by_key(|b| (b.timestamp.clone(), b.path == last));
+    backups
+}
+
+/// True for the timestamp of a rotated backup (20261018_093000)
+pub fn is_backup_timestamp(stamp: &str) -> bool {
+    stamp.len() == 15 && stamp.char_indices().all(|(i, c)| if i == 8 { c == '_' } else { c.is_ascii_digit() })
+}
+
+/// Back up the state file `path` as its last backup (x.rkyv.old) before it is replaced.
+/// The state itself stays in place: the caller renames the new one over it, so a crash
+/// leaves either state as the primary. With `keep` > 1 (flushes: keep_backups()), the
+/// previous last backup becomes x.rkyv.<timestamp>.old (replacing an older one of the
+/// same second) and the oldest timestamped backups beyond `keep` are deleted.
+pub fn rotate_backups(path: &Path, keep: u64) -> std::io::Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let last = PathBuf::from(format!("{}.old", path.display()));
+    if keep > 1 && last.exists() && let Some(stamp) = backup_timestamp(&last) {
+        let rotated = PathBuf::from(format!("{}.{}.old", path.display(), stamp));
+        with_retry("rename", &last, || fs::rename(&last, &rotated))?;
+        let mut rotated: Vec<StateBackup> = state_backups(path).into_iter()
+            .filter(|b| b.path != last)
+            .collect();
+        while rotated.len() as u64 > keep - 1 {
+            let oldest = rotated.remove(0);
+            debug_print(&format!("   ... deleting backup {}", oldest.path.display()));
+            let _ = fs::remove_file(&oldest.path);
+        }
+    }
+    // A hard link (a copy keeping the mtime where links are not supported) under a
+    // temporary name, then renamed over the last backup
+    let linked = PathBuf::from(format!("{}.old.tmp", path.display()));
+    let _ = fs::remove_file(&linked);
+    if fs::hard_link(path, &linked).is_err() {
+        with_retry("write", &linked, || fs::copy(path, &linked))?;
+        if let Ok(modified) = fs::metadata(path).and_then(|m| m.modified()) {
+            let _ = fs::File::options().write(true).open(&linked).and_then(|f| f.set_modified(modified));
+        }
+    }
+    with_retry("rename", &last, || fs::rename(&linked, &last))
+}
+
+/// GlobalFileInfo as stored by the versions before the recorded lists per file (read-only)
+#[derive(Archive, RkyvDeserialize)]
+#[archive(check_bytes)]
+struct GlobalFileInfoV2 {
+    entries: Vec<FileInfoV3>,
+}
+
+/// GlobalFileInfo as stored by schema version 3 (read-only)
+#[derive(Archive, RkyvDeserialize)]
+#[archive(check_bytes)]
+struct GlobalFileInfoV3 {
+    entries: Vec<FileInfoV3>,
+    max_lists_per_file: Option<u64>,
+}
+
+/// FileInfo as stored by the versions before the compressed flag (read-only)
+#[derive(Archive, RkyvDeserialize)]
+#[archive(check_bytes)]
+struct LegacyFileInfo {
+    source_batch: u32,
+    target_batch: u32,
+    cumulative_nb_lists: u64,
+    nb_lists_in_file: u64,
+    filename: String,
+    compacted: bool,
+    exists: Option<bool>,
+    file_size_bytes: Option<u64>,
+    modified_timestamp: Option<i64>,
+}
+
+/// FileInfo as stored by schema versions 2 and 3 (read-only)
+#[derive(Archive, RkyvDeserialize)]
+#[archive(check_bytes)]
+struct FileInfoV3 {
+    source_batch: u32,
+    target_batch: u32,
+    cumulative_nb_lists: u64,
+    nb_lists_in_file: u64,
+    filename: String,
+    compacted: bool,
+    compressed: bool,
+    exists: Option<bool>,
+    file_size_bytes: Option<u64>,
+    modified_timestamp: Option<i64>,
+}
+
+impl From<LegacyFileInfo> for FileInfoV3 {
+    fn from(e: LegacyFileInfo) -> Self {
+        FileInfoV3 {
+            source_batch: e.source_batch,
+            target_batch: e.target_batch,
+            cumulative_nb_lists: e.cumulative_nb_lists,
+            nb_lists_in_file: e.nb_lists_in_file,
+            filename: e.filename,
+            compacted: e.compacted,
+            compressed: false,
+            exists: e.exists,
+            file_size_bytes: e.file_size_bytes,
+            modified_timestamp: e.modified_timestamp,
+        }
+    }
+}
+
+/// Progress of the inputs of a size during a run (see GlobalFileState::input_progress)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct InputProgress {
+    pub inputs_done: u64,     // input batches consumed (recorded, or source batches of the entries)
+    pub inputs_total: u64,    // input batches of the previous size
+    pub inputs_this_run: u64, // consumed since the run started
+    pub lists_this_run: u64,  // lists read from them
+    pub elapsed_secs: f64,    // since the run started
+}
+
+impl InputProgress {
+    pub fn percent(&self) -> Option<f64> {
+        (self.inputs_total > 0).then(|| 100.0 * self.inputs_done as f64 / self.inputs_total as f64)
+    }
+
+    /// Input lists read per second in this run
+    pub fn lists_per_sec(&self) -> Option<f64> {
+        (self.elapsed_secs > 0.0).then(|| self.lists_this_run as f64 / self.elapsed_secs)
+    }
+
+    /// Seconds left at the pace of this run
+    pub fn eta_secs(&self) -> Option<f64> {
+        eta_secs(self.inputs_this_run, self.inputs_total.saturating_sub(self.inputs_done), self.elapsed_secs)
+    }
+
+    /// "43.2% (120 of 278 input batches), 1,234,567 lists/s, ETA 2h13m"
+    pub fn summary(&self) -> String {
+        let percent = self.percent().map_or("-".to_string(), |p| format!("{:.1}%", p));
+        let rate = self.lists_per_sec().map_or("-".to_string(), |r| (r as u64).separated_string());
+        format!("{} ({} of {} input batches), {} lists/s, ETA {}", percent, self.inputs_done, self.inputs_total,
+            rate, self.eta_secs().map_or("unknown".to_string(), format_duration))
+    }
+}
+
+/// Seconds left for `remaining` units of work, `done` of them having taken `elapsed_secs`
+pub fn eta_secs(done: u64, remaining: u64, elapsed_secs: f64) -> Option<f64> {
+    if remaining == 0 {
+        return Some(0.0);
+    }
+    (done > 0 && elapsed_secs > 0.0).then(|| remaining as f64 * elapsed_secs / done as f64)
+}
+
+/// "2h13m", "5m12s", "42s"
+pub fn format_duration(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    match (secs / 3600, secs % 3600 / 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m{:02}s", m, s),
+        (h, m, _) => format!("{}h{:02}m", h, m),
+    }
+}
+
+/// Current time in unix seconds
+pub fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+// Schema migrations, one step per version: 1 (entries only, no compressed flag)
+// -> 2 (compressed flag) -> 3 (lists per file) -> 4 (embedded schema version,
+// tombstones, consumed inputs, provenance, compacted sources, estimated counts,
+// isomorph-reduced and strong-pruned flags)
+
+fn migrate_v1(entries: Vec<LegacyFileInfo>) -> GlobalFileInfoV2 {
+    GlobalFileInfoV2 { entries: entries.into_iter().map(FileInfoV3::from).collect() }
+}
+
+fn migrate_v2(v2: GlobalFileInfoV2) -> GlobalFileInfoV3 {
+    GlobalFileInfoV3 { entries: v2.entries, max_lists_per_file: None }
+}
+
+/// What version 4 added is unknown for older states: no tombstone, no provenance, no
+/// compacted sources, every count read from its file, and no consumed input (the
+/// state users fall back on the source batches of the entries for them)
+fn migrate_v3(v3: GlobalFileInfoV3) -> GlobalFileInfo {
+    let entries = v3.entries.into_iter().map(|e| FileInfo {
+        source_batch: e.source_batch,
+        target_batch: e.target_batch,
+        cumulative_nb_lists: e.cumulative_nb_lists,
+        nb_lists_in_file: e.nb_lists_in_file,
+        filename: e.filename,
+        compacted: e.compacted,
+        compressed: e.compressed,
+        exists: e.exists,
+        file_size_bytes: e.file_size_bytes,
+        modified_timestamp: e.modified_timestamp,
+        provenance: None,
+    }).collect();
+    GlobalFileInfo { max_lists_per_file: v3.max_lists_per_file, ..GlobalFileInfo::new(entries) }
 }
 
 impl FileInfo {
@@ -52,23 +602,19 @@ impl FileInfo {
         let path = self.path_in(base_dir);
         let mut result = FileCheckResult::for_file(&self.filename);
 
-        match fs::metadata(&path) {
-            Ok(meta) => {
-                let modified = meta
-                    .modified()
-                    .ok()
-                    .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-                    .map(|d| d.as_secs() as i64);
+        match crate::storage::file_metadata(&path.to_string_lossy()) {
+            Some((bytes, modified)) => {
                 self.exists = Some(true);
-                self.file_size_bytes = Some(meta.len());
+                self.compressed = crate::io_helpers::is_compressed_file(&path);
+                self.file_size_bytes = Some(bytes);
                 self.modified_timestamp = modified;
                 result.exists = true;
-                result.file_size_bytes = Some(meta.len());
+                result.file_size_bytes = Some(bytes);
                 result.modified_timestamp = modified;
             }
-            Err(e) => {
+            None => {
                 self.exists = Some(false);
-                result.error = Some(format!("metadata error: {}", e));
+                result.error = Some(format!("metadata error: {} not found", path.display()));
                 return result;
             }
         }
@@ -94,24 +640,55 @@ impl FileInfo {
 #[archive(check_bytes)]
 pub struct GlobalFileInfo {
     pub entries: Vec<FileInfo>,
+    #[serde(default)]
+    pub max_lists_per_file: Option<u64>, // lists per output file of the last run (--lists-per-file)
+    #[serde(default)]
+    pub schema_version: u32, // STATE_SCHEMA_VERSION when written (0: JSON written before versioning)
+    #[serde(default)]
+    pub tombstones: Vec<Tombstone>, // entries removed from the state, kept across runs
+    #[serde(default)]
+    pub consumed_inputs: Vec<ConsumedInput>, // input files fully processed, by batch
+    #[serde(default)]
+    pub compacted_sources: Vec<CompactedSources>, // source batches of the compacted files
+    #[serde(default)]
+    pub estimated_counts: Vec<String>, // files whose list count is estimated from their size (--count --fast)
+    #[serde(default)]
+    pub isomorph_reduced: bool, // lists reduced by --isomorph-cache (in this size or one below): not exhaustive
+    #[serde(default)]
+    pub strong_pruned: bool, // lists pruned by --strong-prune (in this size or one below): not exhaustive
 }
 
 impl GlobalFileInfo {
     pub fn new(entries: Vec<FileInfo>) -> Self {
-        Self { entries }
+        Self { entries, max_lists_per_file: None, schema_version: STATE_SCHEMA_VERSION, tombstones: Vec::new(), consumed_inputs: Vec::new(),
+            compacted_sources: Vec::new(), estimated_counts: Vec::new(), isomorph_reduced: false, strong_pruned: false }
+    }
+
+    fn newer_schema_error(path: &Path, version: u32) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::InvalidData,
+            format!("{} has state schema version {} (this version reads up to {}; written by a newer version?)",
+                path.display(), version, STATE_SCHEMA_VERSION))
     }
 
     pub fn save_json<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
         Self::backup_if_exists(path.as_ref(), "json")?;
-        let file = fs::File::create(path)?;
-        serde_json::to_writer_pretty(file, self)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+        with_retry("write", path.as_ref(), || {
+            let file = fs::File::create(path.as_ref())?;
+            serde_json::to_writer_pretty(file, self)
+                .map_err(std::io::Error::other)
+        })
     }
 
     pub fn load_json<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
-        let file = fs::File::open(path)?;
-        serde_json::from_reader(file)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+        let file = with_retry("open", path.as_ref(), || fs::File::open(path.as_ref()))?;
+        let mut gfi: Self = serde_json::from_reader(file)
+            .map_err(std::io::Error::other)?;
+        if gfi.schema_version > STATE_SCHEMA_VERSION {
+            return Err(Self::newer_schema_error(path.as_ref(), gfi.schema_version));
+        }
+        // Fields added since an older JSON state are filled by their serde defaults
+        gfi.schema_version = STATE_SCHEMA_VERSION;
+        Ok(gfi)
     }
 
     /// Save to rkyv binary format (much faster than JSON)
@@ -119,22 +696,50 @@ impl GlobalFileInfo {
         use std::io::Write;
         Self::backup_if_exists(path.as_ref(), "rkyv")?;
         let bytes = rkyv::to_bytes::<_, 256>(self)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-        let mut file = fs::File::create(path)?;
-        file.write_all(&bytes)?;
-        file.sync_all()?;
-        Ok(())
+            .map_err(std::io::Error::other)?;
+        with_retry("write", path.as_ref(), || {
+            let mut file = fs::File::create(path.as_ref())?;
+            file.write_all(STATE_MAGIC)?;
+            file.write_all(&bytes)?;
+            file.sync_all()
+        })
     }
 
-    /// Load from rkyv binary format
+    /// Load from rkyv binary format, migrating older schema versions (detected from
+    /// the header) to the current one
     pub fn load_rkyv<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
-        let file = fs::File::open(path)?;
+        let file = with_retry("open", path.as_ref(), || fs::File::open(path.as_ref()))?;
         let mmap = unsafe { Mmap::map(&file)? };
-        let archived = check_archived_root::<Self>(&mmap[..])
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("rkyv validation error: {:?}", e)))?;
-        let deserialized: Self = archived.deserialize(&mut rkyv::Infallible)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("rkyv deserialization error: {:?}", e)))?;
-        Ok(deserialized)
+        let invalid = |what: &str, e: String| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("rkyv {} error: {}", what, e));
+        if let Some(payload) = mmap.strip_prefix(&STATE_MAGIC[..]) {
+            let archived = check_archived_root::<Self>(payload)
+                .map_err(|e| invalid("validation", format!("{:?}", e)))?;
+            return archived.deserialize(&mut rkyv::Infallible)
+                .map_err(|e| invalid("deserialization", format!("{:?}", e)));
+        }
+        if let Some(payload) = mmap.strip_prefix(&STATE_MAGIC_V3[..]) {
+            let archived = check_archived_root::<GlobalFileInfoV3>(payload)
+                .map_err(|e| invalid("validation", format!("{:?}", e)))?;
+            let v3: GlobalFileInfoV3 = archived.deserialize(&mut rkyv::Infallible)
+                .map_err(|e| invalid("deserialization", format!("{:?}", e)))?;
+            return Ok(migrate_v3(v3));
+        }
+        if let Some(payload) = mmap.strip_prefix(&STATE_MAGIC_V2[..]) {
+            let archived = check_archived_root::<GlobalFileInfoV2>(payload)
+                .map_err(|e| invalid("validation", format!("{:?}", e)))?;
+            let v2: GlobalFileInfoV2 = archived.deserialize(&mut rkyv::Infallible)
+                .map_err(|e| invalid("deserialization", format!("{:?}", e)))?;
+            return Ok(migrate_v3(migrate_v2(v2)));
+        }
+        let version = state_schema_version(path.as_ref())?;
+        if version > STATE_SCHEMA_VERSION {
+            return Err(Self::newer_schema_error(path.as_ref(), version));
+        }
+        let archived = check_archived_root::<Vec<LegacyFileInfo>>(&mmap[..])
+            .map_err(|e| invalid("validation", format!("{:?}", e)))?;
+        let entries: Vec<LegacyFileInfo> = archived.deserialize(&mut rkyv::Infallible)
+            .map_err(|e| invalid("deserialization", format!("{:?}", e)))?;
+        Ok(migrate_v3(migrate_v2(migrate_v1(entries))))
     }
 
     /// Backup existing file by renaming to _old before saving new version
@@ -144,7 +749,7 @@ impl GlobalFileInfo {
             if old_path.exists() {
                 let _ = fs::remove_file(&old_path); // Remove previous backup
             }
-            fs::rename(path, &old_path)?;
+            with_retry("rename", path, || fs::rename(path, &old_path))?;
         }
         Ok(())
     }
@@ -152,7 +757,7 @@ impl GlobalFileInfo {
     /// Load from a global count text file.
     pub fn from_global_count_file<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
         let text = fs::read_to_string(path)?;
-        Ok(Self { entries: parse_global_count_text(&text) })
+        Ok(Self::new(parse_global_count_text(&text)))
     }
 
     /// Load from intermediary count files in a directory and build aggregated entries.
@@ -162,10 +767,18 @@ impl GlobalFileInfo {
     pub fn from_intermediary_files(base_path: &str, target_size: u8, force: bool) -> std::io::Result<Self> {
         use crate::utils::test_print;
         use separator::Separatable;
-        
+
+        acquire_state_lock(base_path, target_size)?;
         let mut all_file_info: BTreeMap<(u32, u32), (String, u64, bool)> = BTreeMap::new();
         let mut seen_files: HashSet<String> = HashSet::new();
         let mut processed_source_batches: HashSet<u32> = HashSet::new();
+        let mut kept_tombstones: Vec<Tombstone> = Vec::new();
+        let mut kept_inputs: Vec<ConsumedInput> = Vec::new();
+        let mut kept_sources: Vec<CompactedSources> = Vec::new();
+        let mut kept_estimated: Vec<String> = Vec::new();
+        let mut kept_reduced = false;
+        let mut kept_pruned = false;
+        let mut kept_provenance: HashMap<String, Provenance> = HashMap::new();
         let pattern_new = format!("nsl_{:02}_intermediate_count_from_{:02}_", target_size, target_size - 1);
         let legacy_pattern = format!("no_set_list_input_intermediate_count_{:02}_", target_size - 1);
         
@@ -182,8 +795,17 @@ impl GlobalFileInfo {
                 test_print(&format!("   ... Loading existing rkyv file: {}", rkyv_path_load.display()));
                 match Self::load_rkyv(&rkyv_path_load) {
                     Ok(existing_gfi) => {
+                        kept_tombstones = existing_gfi.tombstones;
+                        kept_inputs = existing_gfi.consumed_inputs;
+                        kept_sources = existing_gfi.compacted_sources;
+                        kept_estimated = existing_gfi.estimated_counts;
+                        kept_reduced = existing_gfi.isomorph_reduced;
+                        kept_pruned = existing_gfi.strong_pruned;
                         // Extract existing data
                         for entry in existing_gfi.entries {
+                            if let Some(provenance) = entry.provenance.clone() {
+                                kept_provenance.insert(entry.filename.clone(), provenance);
+                            }
                             let key = (entry.source_batch, entry.target_batch);
                             all_file_info.insert(key, (entry.filename.clone(), entry.nb_lists_in_file, entry.compacted));
                             seen_files.insert(entry.filename.clone());
@@ -206,8 +828,17 @@ impl GlobalFileInfo {
                 test_print(&format!("   ... Loading existing JSON file: {}", json_path.display()));
                 match Self::load_json(&json_path) {
                     Ok(existing_gfi) => {
+                        kept_tombstones = existing_gfi.tombstones;
+                        kept_inputs = existing_gfi.consumed_inputs;
+                        kept_sources = existing_gfi.compacted_sources;
+                        kept_estimated = existing_gfi.estimated_counts;
+                        kept_reduced = existing_gfi.isomorph_reduced;
+                        kept_pruned = existing_gfi.strong_pruned;
                         // Extract existing data
                         for entry in existing_gfi.entries {
+                            if let Some(provenance) = entry.provenance.clone() {
+                                kept_provenance.insert(entry.filename.clone(), provenance);
+                            }
                             let key = (entry.source_batch, entry.target_batch);
                             all_file_info.insert(key, (entry.filename.clone(), entry.nb_lists_in_file, entry.compacted));
                             seen_files.insert(entry.filename.clone());
@@ -228,16 +859,13 @@ impl GlobalFileInfo {
         // Step 2: Collect all intermediary files and extract their source batch numbers
         let mut intermediary_files_with_batches: Vec<(std::path::PathBuf, u32)> = Vec::new();
         for entry in fs::read_dir(base_path)? {
-            if let Ok(e) = entry {
-                if let Some(name) = e.file_name().to_str() {
-                    if (name.starts_with(&pattern_new) || name.starts_with(&legacy_pattern)) && name.ends_with(".txt") {
-                        // Extract source batch number from filename
-                        if let Some(batch_str) = name.rsplit('_').next().and_then(|s| s.strip_suffix(".txt")) {
-                            if let Ok(batch) = batch_str.parse::<u32>() {
-                                intermediary_files_with_batches.push((e.path(), batch));
-                            }
-                        }
-                    }
+            if let Ok(e) = entry
+                && let Some(name) = e.file_name().to_str()
+                && (name.starts_with(&pattern_new) || name.starts_with(&legacy_pattern)) && name.ends_with(".txt") {
+                // Extract source batch number from filename
+                if let Some(batch_str) = name.rsplit('_').next().and_then(|s| s.strip_suffix(".txt"))
+                    && let Ok(batch) = batch_str.parse::<u32>() {
+                    intermediary_files_with_batches.push((e.path(), batch));
                 }
             }
         }
@@ -246,7 +874,7 @@ impl GlobalFileInfo {
             if all_file_info.is_empty() {
                 test_print("   ... No intermediary count files found, scanning .rkyv files directly...");
                 let scanned = scan_rkyv_files(base_path, target_size)?;
-                return Ok(Self { entries: scanned });
+                return Ok(Self::new(scanned));
             } else {
                 // We have data from JSON, no new intermediary files to process
                 test_print("   ... No new intermediary files to process, using existing JSON data");
@@ -257,11 +885,13 @@ impl GlobalFileInfo {
                         target_batch: tgt,
                         cumulative_nb_lists: 0,
                         nb_lists_in_file: count,
-                        filename: fname,
+                        filename: fname.clone(),
                         compacted,
+                        compressed: false,
                         exists: None,
                         file_size_bytes: None,
                         modified_timestamp: None,
+                        provenance: kept_provenance.get(&fname).cloned(),
                     })
                     .collect();
                 entries.sort_by(|a, b| match a.target_batch.cmp(&b.target_batch) {
@@ -273,7 +903,8 @@ impl GlobalFileInfo {
                     cumulative += e.nb_lists_in_file;
                     e.cumulative_nb_lists = cumulative;
                 }
-                return Ok(Self { entries });
+                return Ok(Self { tombstones: kept_tombstones, consumed_inputs: kept_inputs, compacted_sources: kept_sources,
+                    estimated_counts: kept_estimated, isomorph_reduced: kept_reduced, strong_pruned: kept_pruned, ..Self::new(entries) });
             }
         }
         
@@ -297,11 +928,13 @@ impl GlobalFileInfo {
                     target_batch: tgt,
                     cumulative_nb_lists: 0,
                     nb_lists_in_file: count,
-                    filename: fname,
+                    filename: fname.clone(),
                     compacted,
+                    compressed: false,
                     exists: None,
                     file_size_bytes: None,
                     modified_timestamp: None,
+                    provenance: kept_provenance.get(&fname).cloned(),
                 })
                 .collect();
             entries.sort_by(|a, b| match a.target_batch.cmp(&b.target_batch) {
@@ -313,7 +946,7 @@ impl GlobalFileInfo {
                 cumulative += e.nb_lists_in_file;
                 e.cumulative_nb_lists = cumulative;
             }
-            return Ok(Self { entries });
+            return Ok(Self::new(entries));
         }
         
         test_print(&format!("   ... {} input batches already processed, {} new batches to process", 
@@ -329,28 +962,27 @@ impl GlobalFileInfo {
                 // Show progress every file
                 test_print(&format!("   ... [{:>4}/{:<4}] Reading: {} (input batch {:06})", file_num, total_files, name, source_batch));
                 
-                let file = fs::File::open(&path)?;
+                let file = fs::File::open(path)?;
                 let reader = std::io::BufReader::new(file);
                 let mut lines_in_file = 0;
                 for line in reader.lines() {
                     let line = line?;
                     if line.trim().starts_with("...") {
                         let parts: Vec<&str> = line.split_whitespace().collect();
-                        if parts.len() >= 5 {
-                            if let Ok(count) = parts[1].parse::<u64>() {
-                                let filename = parts[4];
-                                if seen_files.contains(filename) {
-                                    continue;
-                                }
-                                let (src_batch, tgt_batch) = match parse_batches(filename) {
-                                    Some(v) => v,
-                                    None => continue,
-                                };
-                                let compacted = filename.contains("_compacted.rkyv");
-                                seen_files.insert(filename.to_string());
-                                all_file_info.insert((src_batch, tgt_batch), (filename.to_string(), count, compacted));
-                                lines_in_file += 1;
+                        if parts.len() >= 5
+                            && let Ok(count) = parts[1].parse::<u64>() {
+                            let filename = parts[4];
+                            if seen_files.contains(filename) {
+                                continue;
                             }
+                            let (src_batch, tgt_batch) = match parse_batches(filename) {
+                                Some(v) => v,
+                                None => continue,
+                            };
+                            let compacted = filename.contains("_compacted.rkyv");
+                            seen_files.insert(filename.to_string());
+                            all_file_info.insert((src_batch, tgt_batch), (filename.to_string(), count, compacted));
+                            lines_in_file += 1;
                         }
                     }
                 }
@@ -372,9 +1004,11 @@ impl GlobalFileInfo {
                             nb_lists_in_file: *count,
                             filename: fname.clone(),
                             compacted: *compacted,
+                            compressed: false,
                             exists: None,
                             file_size_bytes: None,
                             modified_timestamp: None,
+                            provenance: kept_provenance.get(fname).cloned(),
                         })
                         .collect();
                     
@@ -389,7 +1023,15 @@ impl GlobalFileInfo {
                         e.cumulative_nb_lists = cumulative;
                     }
                     
-                    let temp_gfi = GlobalFileInfo { entries };
+                    let temp_gfi = GlobalFileInfo {
+                        tombstones: kept_tombstones.clone(),
+                        consumed_inputs: kept_inputs.clone(),
+                        compacted_sources: kept_sources.clone(),
+                        estimated_counts: kept_estimated.clone(),
+                        isomorph_reduced: kept_reduced,
+                        strong_pruned: kept_pruned,
+                        ..GlobalFileInfo::new(entries)
+                    };
                     // Use rkyv binary format for intermediate saves (10-100x faster than JSON)
                     if let Err(e) = temp_gfi.save_rkyv(&rkyv_path) {
                         test_print(&format!("   ... Warning: Could not save intermediate progress: {}", e));
@@ -411,19 +1053,21 @@ impl GlobalFileInfo {
                 target_batch: tgt,
                 cumulative_nb_lists: 0,
                 nb_lists_in_file: count,
-                filename: fname,
+                filename: fname.clone(),
                 compacted,
+                compressed: false,
                 exists: None,
                 file_size_bytes: None,
                 modified_timestamp: None,
+                provenance: kept_provenance.get(&fname).cloned(),
             })
             .collect();
 
         // If no intermediary info was found (common for seeds/size 03), fall back to scanning .rkyv files directly.
         if entries.is_empty() {
-            debug_print(&format!("   ... No intermediary files found, scanning .rkyv files directly..."));
+            debug_print("   ... No intermediary files found, scanning .rkyv files directly...");
             let scanned = scan_rkyv_files(base_path, target_size)?;
-            return Ok(Self { entries: scanned });
+            return Ok(Self::new(scanned));
         }
 
         entries.sort_by(|a, b| match a.target_batch.cmp(&b.target_batch) {
@@ -437,7 +1081,8 @@ impl GlobalFileInfo {
             e.cumulative_nb_lists = cumulative;
         }
 
-        Ok(Self { entries })
+        Ok(Self { tombstones: kept_tombstones, consumed_inputs: kept_inputs, compacted_sources: kept_sources,
+            estimated_counts: kept_estimated, isomorph_reduced: kept_reduced, strong_pruned: kept_pruned, ..Self::new(entries) })
     }
 
     /// Run status checks on all entries, optionally deep-counting list totals.
@@ -457,14 +1102,237 @@ impl GlobalFileInfo {
 
 
 
+// Lock files of the states held by this process (see release_state_locks)
+static HELD_LOCKS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Lock files left empty by a crash between creating and writing them are stale after this
+const UNREADABLE_LOCK_AGE: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Lock file of the state of `target_size` in `base_dir`
+pub fn lock_path(base_dir: &str, target_size: u8) -> PathBuf {
+    Path::new(base_dir).join(format!("nsl_{:02}_global_info.lock", target_size))
+}
+
+/// Name of this machine, recorded in the lock files
+pub fn hostname() -> String {
+    #[cfg(unix)]
+    {
+        let mut buffer = [0u8; 256];
+        if unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) } == 0 {
+            let len = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+            return String::from_utf8_lossy(&buffer[..len]).into_owned();
+        }
+    }
+    std::env::var("COMPUTERNAME").or_else(|_| std::env::var("HOSTNAME")).unwrap_or_else(|_| "localhost".to_string())
+}
+
+/// True if the process `pid` of this machine is running
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+    let signalled = unsafe { libc::kill(pid as libc::pid_t, 0) } == 0;
+    signalled || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// True if the process `pid` of this machine is running
+#[cfg(windows)]
+fn process_alive(pid: u32) -> bool {
+    use std::ffi::c_void;
+    unsafe extern "system" {
+        fn OpenProcess(access: u32, inherit: i32, pid: u32) -> *mut c_void;
+        fn GetExitCodeProcess(process: *mut c_void, code: *mut u32) -> i32;
+        fn CloseHandle(handle: *mut c_void) -> i32;
+    }
+    const PROCESS_QUERY_LIMITED_INFORMATION: u32 = 0x1000;
+    const STILL_ACTIVE: u32 = 259;
+    const ERROR_ACCESS_DENIED: i32 = 5;
+    let handle = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid) };
+    if handle.is_null() {
+        return std::io::Error::last_os_error().raw_os_error() == Some(ERROR_ACCESS_DENIED);
+    }
+    let mut code = 0u32;
+    let queried = unsafe { GetExitCodeProcess(handle, &mut code) };
+    unsafe { CloseHandle(handle) };
+    queried == 0 || code == STILL_ACTIVE
+}
+
+#[cfg(not(any(unix, windows)))]
+fn process_alive(_pid: u32) -> bool {
+    true
+}
+
+/// True if the lock file `path` holding `holder` ("PID hostname") belongs to no
+/// running process: a dead process of this machine (or an earlier process with
+/// our PID), or an unreadable lock older than UNREADABLE_LOCK_AGE. Locks of other
+/// machines are never stale: their processes cannot be checked from here.
+fn lock_is_stale(path: &Path, holder: &str) -> bool {
+    let parsed = holder.split_once(' ').and_then(|(pid, host)| Some((pid.parse::<u32>().ok()?, host.trim())));
+    match parsed {
+        Some((pid, host)) if host == hostname() => pid == std::process::id() || !process_alive(pid),
+        Some(_) => false,
+        None => fs::metadata(path).and_then(|m| m.modified()).ok()
+            .and_then(|t| t.elapsed().ok())
+            .is_some_and(|age| age > UNREADABLE_LOCK_AGE),
+    }
+}
+
+/// Error of a state locked by another run (see is_state_locked)
+#[derive(Debug)]
+struct StateLocked(String);
+
+impl std::fmt::Display for StateLocked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for StateLocked {}
+
+/// True if `e` reports a state locked by another run: callers falling back to an
+/// empty state on load errors must stop on this one
+pub fn is_state_locked(e: &std::io::Error) -> bool {
+    e.get_ref().is_some_and(|inner| inner.is::<StateLocked>())
+}
+
+/// Take the advisory lock of the state of `target_size` in `base_dir` for the rest
+/// of this process (no-op if it already holds it, or if the directory does not
+/// exist or refuses the lock file: nothing can race on such a state). Fails with
+/// the holder if another run holds it.
+///
+/// A stale lock is taken over atomically: it is moved aside under a name of this
+/// process and removed only if what was moved is the stale lock read (another run
+/// taking it over at the same time may have replaced it: its lock is put back).
+/// The lock is then created exclusively, and read back to check it is ours.
+pub fn acquire_state_lock(base_dir: &str, target_size: u8) -> std::io::Result<()> {
+    use std::io::Write;
+    let path = lock_path(base_dir, target_size);
+    let mut held = HELD_LOCKS.lock().unwrap_or_else(|e| e.into_inner());
+    if held.contains(&path) {
+        return Ok(());
+    }
+    let owner = format!("{} {}", std::process::id(), hostname());
+    let locked = |holder: &str| {
+        let holder = holder.split_once(' ')
+            .map(|(pid, host)| format!("PID {} on {}", pid, host.trim()))
+            .unwrap_or_else(|| "a run starting".to_string());
+        std::io::Error::other(StateLocked(format!(
+            "Another run ({}) is using the size {:02} state of {}: wait for it to finish, or delete {} if it is gone",
+            holder, target_size, base_dir, path.display())))
+    };
+    for _ in 0..3 {
+        match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(owner.as_bytes())?;
+                file.sync_all()?;
+                // A run taking over a lock it read as stale may have moved ours aside
+                let holder = fs::read_to_string(&path).unwrap_or_default();
+                if holder != owner {
+                    return Err(locked(&holder));
+                }
+                held.push(path);
+                return Ok(());
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                let holder = fs::read_to_string(&path).unwrap_or_default();
+                if !lock_is_stale(&path, &holder) {
+                    return Err(locked(&holder));
+                }
+                let aside = path.with_extension(format!("lock.stale.{}", std::process::id()));
+                if fs::rename(&path, &aside).is_err() {
+                    continue; // removed meanwhile
+                }
+                let moved = fs::read_to_string(&aside).unwrap_or_default();
+                if moved != holder {
+                    // Not the stale lock: another run took it over in between
+                    if fs::hard_link(&aside, &path).is_err() && !path.exists() {
+                        let _ = fs::rename(&aside, &path);
+                    }
+                    let _ = fs::remove_file(&aside);
+                    return Err(locked(&moved));
+                }
+                debug_print(&format!("   ... removing stale state lock {} ({})", path.display(), holder.trim()));
+                let _ = fs::remove_file(&aside);
+            }
+            Err(e) => {
+                debug_print(&format!("   ... state of {} not locked: {}", base_dir, e));
+                return Ok(());
+            }
+        }
+    }
+    Err(std::io::Error::other(format!("Could not take the state lock {}", path.display())))
+}
+
+/// Release the state locks held by this process (at exit)
+pub fn release_state_locks() {
+    let owner = format!("{} {}", std::process::id(), hostname());
+    let mut held = HELD_LOCKS.lock().unwrap_or_else(|e| e.into_inner());
+    for path in held.drain(..) {
+        // Only remove a lock that is still ours (it may have been deleted by hand and retaken)
+        if fs::read_to_string(&path).is_ok_and(|holder| holder == owner) {
+            let _ = fs::remove_file(&path);
+        }
+    }
+}
+
+/// Journal of the state of `target_size` in `base_dir`
+pub fn journal_path(base_dir: &str, target_size: u8) -> PathBuf {
+    Path::new(base_dir).join(format!("nsl_{:02}_global_info.journal", target_size))
+}
+
+/// State change of a data-file operation, journaled before the operation (one JSON
+/// line each). Replayed against the disk: a change is applied only if the file
+/// shows the operation happened, with the list count read from the file.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum JournalOp {
+    /// A list file is about to be written, then registered
+    Register { filename: String, source_batch: u32, target_batch: u32, nb_lists: u64, compacted: bool },
+    /// A registered list file is about to be rewritten with `nb_lists` lists
+    Update { filename: String, source_batch: u32, target_batch: u32, nb_lists: u64 },
+    /// A registered list file is about to be deleted, then removed
+    Remove { filename: String, source_batch: u32, target_batch: u32 },
+}
+
 /// Mutable, incremental state for file info with atomic flush helpers.
 #[derive(Debug, Clone)]
 pub struct GlobalFileState {
     target_size: u8,
     base_dir: String,
+    /// Entries by key; their cumulative_nb_lists is only computed by to_vec (exports
+    /// and flushes), so that registering a file does not walk every entry
     entries: BTreeMap<(u32, u32, String), FileInfo>,
-    /// Track files removed during compaction (for history cleanup)
-    removed_entries: HashSet<(u32, u32, String)>,
+    /// (source_batch, target_batch) of the entries, by filename (files appended to
+    /// by cascade passes are found without scanning the entries)
+    batches_by_name: HashMap<String, Vec<(u32, u32)>>,
+    /// Entries removed from the state, by key (persisted, for history cleanup and audits)
+    tombstones: BTreeMap<(u32, u32, String), Tombstone>,
+    /// Input files fully processed, by batch
+    consumed_inputs: BTreeMap<InputKey, ConsumedInput>,
+    /// True when an input was recorded since the last flush (sqlite backend)
+    inputs_dirty: bool,
+    /// Source batches of the compacted files, by filename
+    compacted_sources: BTreeMap<String, Vec<SourceContribution>>,
+    /// True when the compacted sources changed since the last flush (sqlite backend)
+    sources_dirty: bool,
+    /// Files whose list count is estimated from their size (--count --fast), until counted
+    estimated: BTreeSet<String>,
+    /// True when the estimated counts changed since the last flush (sqlite backend)
+    estimated_dirty: bool,
+    /// Files taken by a running background compaction (not persisted): nothing else
+    /// plans, reads nor appends to them until it is applied
+    leased: HashSet<String>,
+    /// Lists per output file of the last run writing this size (None: not recorded)
+    max_lists_per_file: Option<u64>,
+    /// Lists reduced by --isomorph-cache, in this size or one below (not exhaustive)
+    isomorph_reduced: bool,
+    /// Lists pruned by --strong-prune, in this size or one below (not exhaustive)
+    strong_pruned: bool,
+    /// Entries written or removed since the last flush (sqlite backend)
+    dirty: HashSet<(u32, u32, String)>,
+    /// True once the database holds the state as of the last flush (sqlite backend:
+    /// otherwise the next flush writes every entry)
+    synced: bool,
+    /// Calls of flush_throttled since the last flush
+    unflushed: u64,
 }
 
 impl GlobalFileState {
@@ -477,25 +1345,163 @@ impl GlobalFileState {
             target_size, 
             base_dir: base_dir.to_string(), 
             entries: BTreeMap::new(),
-            removed_entries: HashSet::new(),
+            batches_by_name: HashMap::new(),
+            tombstones: BTreeMap::new(),
+            consumed_inputs: BTreeMap::new(),
+            inputs_dirty: false,
+            compacted_sources: BTreeMap::new(),
+            sources_dirty: false,
+            estimated: BTreeSet::new(),
+            estimated_dirty: false,
+            leased: HashSet::new(),
+            max_lists_per_file: None,
+            isomorph_reduced: false,
+            strong_pruned: false,
+            dirty: HashSet::new(),
+            synced: false,
+            unflushed: 0,
         }
     }
 
+    /// Load the state of `target_size` in `base_dir` from the first source found (see
+    /// `load_sources`), then replay the journal of a run that stopped before flushing
     pub fn from_sources(base_dir: &str, target_size: u8) -> std::io::Result<Self> {
+        acquire_state_lock(base_dir, target_size)?;
+        let mut state = Self::load_sources(base_dir, target_size)?;
+        state.replay_journal()?;
+        state.report_issues(fix_state_on_load());
+        Ok(state)
+    }
+
+    /// Inconsistencies of the entries: duplicate filenames, batches different from
+    /// the filename's, files of 0 bytes, files holding bytes with a count of 0
+    pub fn self_check(&self) -> Vec<StateIssue> {
+        let mut issues: Vec<StateIssue> = self.batches_by_name.iter()
+            .filter(|(_, batches)| batches.len() > 1)
+            .map(|(filename, batches)| StateIssue::DuplicateFilename { filename: filename.clone(), batches: batches.clone() })
+            .collect();
+        issues.sort_by_key(|issue| issue.describe());
+        for ((src, tgt, filename), info) in self.entries.iter() {
+            if let Some(parsed) = crate::filenames::parse_filename(filename) {
+                if parsed.target_size != self.target_size {
+                    issues.push(StateIssue::WrongSize { filename: filename.clone(), size_in_name: parsed.target_size });
+                } else if (parsed.source_batch, parsed.target_batch) != (*src, *tgt) {
+                    issues.push(StateIssue::BatchMismatch { filename: filename.clone(), recorded: (*src, *tgt),
+                        in_name: (parsed.source_batch, parsed.target_batch) });
+                }
+            }
+            if info.file_size_bytes == Some(0) {
+                issues.push(StateIssue::EmptyFile { filename: filename.clone(), batches: (*src, *tgt) });
+            } else if info.nb_lists_in_file == 0 && info.file_size_bytes.unwrap_or(0) > 0 {
+                issues.push(StateIssue::ZeroCount { filename: filename.clone(), batches: (*src, *tgt) });
+            }
+        }
+        issues
+    }
+
+    /// Fix one issue found by self_check; returns what was changed (None: not fixable)
+    pub fn fix_issue(&mut self, issue: &StateIssue) -> Option<String> {
+        match issue {
+            StateIssue::DuplicateFilename { filename, batches } => {
+                // Keep the entry whose batches match the filename, else the last one
+                let in_name = crate::filenames::parse_filename(filename).map(|p| (p.source_batch, p.target_batch));
+                let kept = in_name.filter(|b| batches.contains(b)).or(batches.last().copied())?;
+                for (src, tgt) in batches.iter().filter(|b| **b != kept) {
+                    self.remove_file(filename, *src, *tgt, RemovalReason::Manual);
+                }
+                Some(format!("kept batches {:06} -> {:06}", kept.0, kept.1))
+            }
+            StateIssue::BatchMismatch { filename, recorded, in_name } => {
+                if !self.has_entry(filename, recorded.0, recorded.1) {
+                    return None; // dropped as a duplicate
+                }
+                if self.has_entry(filename, in_name.0, in_name.1) {
+                    self.remove_file(filename, recorded.0, recorded.1, RemovalReason::Manual);
+                    return Some("dropped (already recorded under its own batches)".to_string());
+                }
+                let mut info = self.entries.get(&Self::key(recorded.0, recorded.1, filename))?.clone();
+                self.remove_file(filename, recorded.0, recorded.1, RemovalReason::Manual);
+                (info.source_batch, info.target_batch) = *in_name;
+                self.insert_entry(Self::key(in_name.0, in_name.1, filename), info);
+                self.dirty.insert(Self::key(in_name.0, in_name.1, filename));
+                Some("recorded under the batches of its name".to_string())
+            }
+            StateIssue::WrongSize { .. } => None,
+            StateIssue::EmptyFile { filename, batches: (src, tgt) } | StateIssue::ZeroCount { filename, batches: (src, tgt) } => {
+                let deep = matches!(issue, StateIssue::ZeroCount { .. });
+                let key = Self::key(*src, *tgt, filename);
+                let mut info = self.entries.get(&key)?.clone();
+                let check = info.refresh_status(&self.base_dir, deep);
+                if !check.exists || check.file_size_bytes == Some(0) {
+                    self.remove_file(filename, *src, *tgt, RemovalReason::Pruned);
+                    return Some("entry removed (no list on disk)".to_string());
+                }
+                let fixed = format!("refreshed from the disk: {} bytes, {} lists",
+                    info.file_size_bytes.unwrap_or(0).separated_string(), info.nb_lists_in_file.separated_string());
+                self.insert_entry(key.clone(), info);
+                self.dirty.insert(key);
+                Some(fixed)
+            }
+        }
+    }
+
+    /// Log the issues found by self_check, fixing them if `fix`
+    fn report_issues(&mut self, fix: bool) {
+        let issues = self.self_check();
+        if issues.is_empty() {
+            return;
+        }
+        crate::utils::test_print(&format!("   WARNING: the size {:02} state of {} has {} inconsistencies",
+            self.target_size, self.base_dir, issues.len()));
+        for issue in issues.iter() {
+            let fixed = if fix { self.fix_issue(issue) } else { None };
+            match fixed {
+                Some(change) => crate::utils::test_print(&format!("   ... {}: {}", issue.describe(), change)),
+                None => crate::utils::test_print(&format!("   ... {}", issue.describe())),
+            }
+        }
+        if fix {
+            crate::utils::test_print("   ... fixes saved with the next flush of the state");
+        } else {
+            crate::utils::test_print("   ... rerun with --fix-state-on-load to fix them (or use --repair)");
+        }
+    }
+
+    /// True if `base_dir` holds a saved state of `target_size` (rkyv, JSON or
+    /// database): without one, loading falls back on the legacy count files and
+    /// then on reading every list file
+    pub fn has_saved_state(base_dir: &str, target_size: u8) -> bool {
+        ["rkyv", "json"].iter()
+            .any(|ext| Path::new(base_dir).join(format!("nsl_{:02}_global_info.{}", target_size, ext)).exists())
+            || Self::database_path(base_dir, target_size).exists()
+    }
+
+    fn load_sources(base_dir: &str, target_size: u8) -> std::io::Result<Self> {
+        // Priority 0: database of the sqlite backend (when selected)
+        let database = Self::database_path(base_dir, target_size);
+        if state_backend() == StateBackend::Sqlite && database.exists() {
+            return Self::from_database(base_dir, target_size);
+        }
+
         // Priority 1: rkyv (authoritative format)
         let rkyv_path = Path::new(base_dir).join(format!("nsl_{:02}_global_info.rkyv", target_size));
         if rkyv_path.exists() {
             let gfi = GlobalFileInfo::load_rkyv(&rkyv_path)?;
-            return Ok(Self::from_vec(base_dir, target_size, gfi.entries));
+            return Ok(Self::from_info(base_dir, target_size, gfi));
         }
         
         // Priority 2: JSON (legacy format, migration path)
         let json_path = Path::new(base_dir).join(format!("nsl_{:02}_global_info.json", target_size));
         if json_path.exists() {
             let gfi = GlobalFileInfo::load_json(&json_path)?;
-            return Ok(Self::from_vec(base_dir, target_size, gfi.entries));
+            return Ok(Self::from_info(base_dir, target_size, gfi));
         }
         
+        // Database of a run with --state-backend sqlite, read back with the rkyv backend
+        if database.exists() {
+            return Self::from_database(base_dir, target_size);
+        }
+
         // Priority 3: Legacy global_count.txt files
         let primary = Path::new(base_dir).join(format!("nsl_{:02}_global_count.txt", target_size));
         let legacy_space = Path::new(base_dir).join(format!("nsl_{:02}_global count.txt", target_size));
@@ -509,24 +1515,192 @@ impl GlobalFileState {
         
         // Priority 4: Legacy intermediate count files (slowest)
         let gfi = GlobalFileInfo::from_intermediary_files(base_dir, target_size, false)?;
-        Ok(Self::from_vec(base_dir, target_size, gfi.entries))
+        Ok(Self::from_info(base_dir, target_size, gfi))
     }
 
-    fn from_vec(base_dir: &str, target_size: u8, entries: Vec<FileInfo>) -> Self {
-        let mut map = BTreeMap::new();
-        for e in entries {
+    /// Append `op` to the journal before the data-file operation it records (synced,
+    /// so that it survives a crash during the operation)
+    pub fn journal(&self, op: &JournalOp) -> std::io::Result<()> {
+        use std::io::Write;
+        let path = journal_path(&self.base_dir, self.target_size);
+        let mut line = serde_json::to_string(op).map_err(std::io::Error::other)?;
+        line.push('\n');
+        with_retry("write", &path, || {
+            let mut file = fs::OpenOptions::new().create(true).append(true).open(&path)?;
+            file.write_all(line.as_bytes())?;
+            file.sync_data()
+        })
+    }
+
+    /// Apply the journal left by a run that stopped before flushing: each change is
+    /// checked against the disk (a torn last line is ignored). The journal is kept
+    /// until the next flush, replaying it again is harmless.
+    fn replay_journal(&mut self) -> std::io::Result<()> {
+        let path = journal_path(&self.base_dir, self.target_size);
+        if !path.exists() {
+            return Ok(());
+        }
+        let text = with_retry("read", &path, || fs::read_to_string(&path))?;
+        let mut applied = 0;
+        for line in text.lines().filter(|l| !l.trim().is_empty()) {
+            let Ok(op) = serde_json::from_str::<JournalOp>(line) else {
+                debug_print(&format!("   ... ignoring torn journal line: {}", line));
+                break;
+            };
+            if self.apply_journaled(&op) {
+                applied += 1;
+            }
+        }
+        if applied > 0 {
+            crate::utils::test_print(&format!("   ... replayed {} changes from {} (run stopped before flushing the state)",
+                applied, path.display()));
+        }
+        Ok(())
+    }
+
+    /// Apply one journaled change if the disk shows its operation happened
+    fn apply_journaled(&mut self, op: &JournalOp) -> bool {
+        let (filename, src, tgt) = match op {
+            JournalOp::Register { filename, source_batch, target_batch, .. }
+            | JournalOp::Update { filename, source_batch, target_batch, .. }
+            | JournalOp::Remove { filename, source_batch, target_batch } => (filename, *source_batch, *target_batch),
+        };
+        let path = Path::new(&self.base_dir).join(filename).to_string_lossy().into_owned();
+        let exists = crate::storage::list_file_exists(&path);
+        if let JournalOp::Remove { .. } = op {
+            if exists || !self.has_entry(filename, src, tgt) {
+                return false;
+            }
+            self.remove_file(filename, src, tgt, RemovalReason::CompactedAway);
+            return true;
+        }
+        if !exists {
+            return false;
+        }
+        let nb_lists = match crate::io_helpers::count_lists_in_file(&path) {
+            Ok(nb_lists) => nb_lists,
+            Err(e) => {
+                crate::utils::test_print(&format!("   ... WARNING: journaled file {} unreadable ({}), not replayed", filename, e));
+                return false;
+            }
+        };
+        let (file_size, mtime) = crate::storage::file_metadata(&path)
+            .map(|(bytes, mtime)| (Some(bytes), mtime))
+            .unwrap_or((None, None));
+        match op {
+            JournalOp::Register { compacted, .. } => {
+                self.register_file(filename, src, tgt, nb_lists, *compacted, file_size, mtime);
+                true
+            }
+            _ => match self.entries.get(&Self::key(src, tgt, filename)).map(|e| e.compacted) {
+                Some(compacted) => {
+                    self.update_entry(filename, src, tgt, nb_lists, compacted, file_size, mtime);
+                    true
+                }
+                None => false,
+            },
+        }
+    }
+
+    /// Delete the journal once the state holds every change it records
+    fn clear_journal(&self) {
+        let path = journal_path(&self.base_dir, self.target_size);
+        if path.exists() && let Err(e) = with_retry("delete", &path, || fs::remove_file(&path)) {
+            debug_print(&format!("   ... could not delete {}: {}", path.display(), e));
+        }
+    }
+
+    /// Path of the database of the sqlite backend
+    pub fn database_path(base_dir: &str, target_size: u8) -> PathBuf {
+        Path::new(base_dir).join(format!("nsl_{:02}_global_info.sqlite", target_size))
+    }
+
+    /// Path the state is flushed to with the selected backend
+    pub fn state_path(&self) -> PathBuf {
+        match state_backend() {
+            StateBackend::Rkyv => Path::new(&self.base_dir).join(format!("nsl_{:02}_global_info.rkyv", self.target_size)),
+            StateBackend::Sqlite => Self::database_path(&self.base_dir, self.target_size),
+        }
+    }
+
+    fn from_database(base_dir: &str, target_size: u8) -> std::io::Result<Self> {
+        let gfi = sqlite_state::load(&Self::database_path(base_dir, target_size))?;
+        let mut state = Self::from_info(base_dir, target_size, gfi);
+        state.synced = true;
+        Ok(state)
+    }
+
+    fn from_info(base_dir: &str, target_size: u8, gfi: GlobalFileInfo) -> Self {
+        let mut state = Self::from_vec(base_dir, target_size, gfi.entries);
+        state.max_lists_per_file = gfi.max_lists_per_file;
+        state.isomorph_reduced = gfi.isomorph_reduced;
+        state.strong_pruned = gfi.strong_pruned;
+        state.tombstones = gfi.tombstones.into_iter()
+            .map(|t| (Self::key(t.source_batch, t.target_batch, &t.filename), t))
+            .collect();
+        state.consumed_inputs = gfi.consumed_inputs.into_iter().map(|c| (c.key(), c)).collect();
+        state.compacted_sources = gfi.compacted_sources.into_iter().map(|c| (c.filename, c.sources)).collect();
+        state.estimated = gfi.estimated_counts.into_iter().collect();
+        state
+    }
+
+    fn from_vec(base_dir: &str, target_size: u8, entries: Vec<FileInfo>) -> Self {
+        let mut map = BTreeMap::new();
+        let mut batches_by_name: HashMap<String, Vec<(u32, u32)>> = HashMap::new();
+        for e in entries {
+            batches_by_name.entry(e.filename.clone()).or_default().push((e.source_batch, e.target_batch));
             map.insert(Self::key(e.source_batch, e.target_batch, &e.filename), e);
         }
-        let mut state = Self { 
+        Self { 
             target_size, 
             base_dir: base_dir.to_string(), 
             entries: map,
-            removed_entries: HashSet::new(),
-        };
-        state.recompute_cumulative();
-        state
+            batches_by_name,
+            tombstones: BTreeMap::new(),
+            consumed_inputs: BTreeMap::new(),
+            inputs_dirty: false,
+            compacted_sources: BTreeMap::new(),
+            sources_dirty: false,
+            estimated: BTreeSet::new(),
+            estimated_dirty: false,
+            leased: HashSet::new(),
+            max_lists_per_file: None,
+            isomorph_reduced: false,
+            strong_pruned: false,
+            dirty: HashSet::new(),
+            synced: false,
+            unflushed: 0,
+        }
+    }
+
+    /// Insert (or replace) the entry `key`, keeping batches_by_name in step
+    fn insert_entry(&mut self, key: (u32, u32, String), fi: FileInfo) {
+        let batches = self.batches_by_name.entry(key.2.clone()).or_default();
+        if !batches.contains(&(key.0, key.1)) {
+            batches.push((key.0, key.1));
+        }
+        self.entries.insert(key, fi);
+    }
+
+    /// Remove the entry `key`, keeping batches_by_name in step
+    fn remove_entry(&mut self, key: &(u32, u32, String)) -> Option<FileInfo> {
+        let removed = self.entries.remove(key)?;
+        if let Some(batches) = self.batches_by_name.get_mut(&key.2) {
+            batches.retain(|b| *b != (key.0, key.1));
+            if batches.is_empty() {
+                self.batches_by_name.remove(&key.2);
+                if self.compacted_sources.remove(&key.2).is_some() {
+                    self.sources_dirty = true;
+                }
+                if self.estimated.remove(&key.2) {
+                    self.estimated_dirty = true;
+                }
+            }
+        }
+        Some(removed)
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn register_file(
         &mut self,
         filename: &str,
@@ -541,17 +1715,18 @@ impl GlobalFileState {
         // This happens in cascade mode when a file is appended to across multiple passes
         // In this case, the file is "renamed" (different source batch in filename) and we need
         // to remove the old entry to avoid duplicate entries in history
-        let keys_to_remove: Vec<(u32, u32, String)> = self.entries
-            .keys()
-            .filter(|(old_src, old_tgt, old_filename)| {
-                old_filename == filename && *old_tgt == tgt_batch && *old_src != src_batch
-            })
-            .cloned()
+        let keys_to_remove: Vec<(u32, u32, String)> = self.batches_by_name
+            .get(filename)
+            .into_iter()
+            .flatten()
+            .filter(|(old_src, old_tgt)| *old_tgt == tgt_batch && *old_src != src_batch)
+            .map(|(old_src, old_tgt)| Self::key(*old_src, *old_tgt, filename))
             .collect();
         
         for old_key in keys_to_remove {
-            self.entries.remove(&old_key);
-            self.removed_entries.insert(old_key);
+            self.remove_entry(&old_key);
+            self.dirty.insert(old_key.clone());
+            self.bury(old_key, RemovalReason::CompactedAway);
         }
         
         let fi = FileInfo {
@@ -561,42 +1736,281 @@ impl GlobalFileState {
             nb_lists_in_file,
             filename: filename.to_string(),
             compacted,
+            compressed: crate::io_helpers::is_compressed_file(Path::new(&self.base_dir).join(filename)),
             exists: Some(true),
             file_size_bytes,
             modified_timestamp,
+            provenance: None,
         };
-        self.entries.insert(Self::key(src_batch, tgt_batch, filename), fi);
-        self.recompute_cumulative();
+        self.insert_entry(Self::key(src_batch, tgt_batch, filename), fi);
+        self.tombstones.remove(&Self::key(src_batch, tgt_batch, filename));
+        self.dirty.insert(Self::key(src_batch, tgt_batch, filename));
+        self.set_estimated(filename, false);
     }
 
-    pub fn remove_file(&mut self, filename: &str, src_batch: u32, tgt_batch: u32) {
+    pub fn remove_file(&mut self, filename: &str, src_batch: u32, tgt_batch: u32, reason: RemovalReason) {
         let key = Self::key(src_batch, tgt_batch, filename);
-        self.entries.remove(&key);
+        self.remove_entry(&key);
+        self.dirty.insert(key.clone());
         // Track this removal for history cleanup
-        self.removed_entries.insert(key);
-        self.recompute_cumulative();
+        self.bury(key, reason);
+    }
+
+    /// Record the removal of the entry `key` now
+    fn bury(&mut self, key: (u32, u32, String), reason: RemovalReason) {
+        let removed_at = unix_now();
+        let tombstone = Tombstone { source_batch: key.0, target_batch: key.1, filename: key.2.clone(), removed_at, reason };
+        self.tombstones.insert(key, tombstone);
+    }
+
+    /// Apply a removal recorded by another state (history merging): the entry is
+    /// dropped if present and the tombstone kept as recorded; returns true if an
+    /// entry was dropped
+    pub fn apply_tombstone(&mut self, tombstone: &Tombstone) -> bool {
+        let key = Self::key(tombstone.source_batch, tombstone.target_batch, &tombstone.filename);
+        let removed = self.remove_entry(&key).is_some();
+        self.dirty.insert(key.clone());
+        self.tombstones.insert(key, tombstone.clone());
+        removed
     }
 
     pub fn update_count(&mut self, filename: &str, src_batch: u32, tgt_batch: u32, nb_lists_in_file: u64) {
         if let Some(e) = self.entries.get_mut(&Self::key(src_batch, tgt_batch, filename)) {
             e.nb_lists_in_file = nb_lists_in_file;
-            e.cumulative_nb_lists = 0;
-            self.recompute_cumulative();
+            self.dirty.insert(Self::key(src_batch, tgt_batch, filename));
         }
     }
 
+    /// Lists per output file recorded by the last run writing this size
+    pub fn max_lists_per_file(&self) -> Option<u64> {
+        self.max_lists_per_file
+    }
+
+    /// Record the lists per output file of the current run (saved with the state)
+    pub fn set_max_lists_per_file(&mut self, max_lists_per_file: Option<u64>) {
+        self.max_lists_per_file = max_lists_per_file;
+    }
+
+    /// True if the lists of this size were reduced by --isomorph-cache (in this size
+    /// or one below): a subset of the no-set-lists, not the exhaustive count
+    pub fn isomorph_reduced(&self) -> bool {
+        self.isomorph_reduced
+    }
+
+    /// Record whether the lists of this size are reduced by --isomorph-cache (saved with the state)
+    pub fn set_isomorph_reduced(&mut self, reduced: bool) {
+        self.isomorph_reduced = reduced;
+    }
+
+    /// True if the lists of this size were pruned by --strong-prune (in this size or
+    /// one below): fewer than the exhaustive count
+    pub fn strong_pruned(&self) -> bool {
+        self.strong_pruned
+    }
+
+    /// Record whether the lists of this size are pruned by --strong-prune (saved with the state)
+    pub fn set_strong_pruned(&mut self, pruned: bool) {
+        self.strong_pruned = pruned;
+    }
+
     pub fn entries(&self) -> &BTreeMap<(u32, u32, String), FileInfo> {
         &self.entries
     }
+
+    /// Size of the lists of the files of this state
+    pub fn target_size(&self) -> u8 {
+        self.target_size
+    }
     
     pub fn has_entry(&self, filename: &str, src_batch: u32, tgt_batch: u32) -> bool {
         self.entries.contains_key(&Self::key(src_batch, tgt_batch, filename))
     }
     
-    pub fn removed_entries(&self) -> &HashSet<(u32, u32, String)> {
-        &self.removed_entries
+    pub fn tombstones(&self) -> &BTreeMap<(u32, u32, String), Tombstone> {
+        &self.tombstones
+    }
+
+    /// Record that the input file `input` of size `size` was fully processed
+    /// (`nb_lists` lists read); counts as a change for flush_pending
+    pub fn record_consumed_input(&mut self, size: u8, input: InputKey, nb_lists: u64) {
+        let completed_at = unix_now();
+        self.consumed_inputs.insert(input, ConsumedInput { size, batch: input.batch, compacted: input.compacted,
+            shard: input.shard, nb_lists, completed_at });
+        self.inputs_dirty = true;
+        self.unflushed += 1;
+    }
+
+    pub fn consumed_inputs(&self) -> &BTreeMap<InputKey, ConsumedInput> {
+        &self.consumed_inputs
+    }
+
+    /// Batch numbers of the inputs consumed whole (every shard of the sharded ones),
+    /// compacted and regular alike
+    pub fn consumed_batches(&self) -> BTreeSet<u32> {
+        self.consumed_inputs.keys()
+            .filter(|k| self.is_input_consumed(InputKey::whole(k.batch, k.compacted)))
+            .map(|k| k.batch)
+            .collect()
+    }
+
+    /// True if the input file `input` was consumed (its shard of it for a sharded
+    /// `input`): recorded whole, recorded for this shard, recorded for every shard of
+    /// a partition, or, for a batch number none of whose files is recorded (states of
+    /// schema version 3 and older, or an interrupted input), inferred from the outputs
+    /// created from it by a run of the same shard
+    pub fn is_input_consumed(&self, input: InputKey) -> bool {
+        let whole = InputKey::whole(input.batch, input.compacted);
+        if self.consumed_inputs.contains_key(&whole) || self.consumed_inputs.contains_key(&input) {
+            return true;
+        }
+        let shards: Vec<Shard> = self.consumed_inputs.keys()
+            .filter(|k| (k.batch, k.compacted) == (input.batch, input.compacted))
+            .filter_map(|k| k.shard)
+            .collect();
+        let covered = |shard: &Shard| (0..shard.count).all(|index| shards.contains(&Shard { index, count: shard.count }));
+        if shards.iter().any(|shard| covered(shard) && input.shard.is_none_or(|s| s.count == shard.count)) {
+            return true;
+        }
+        let recorded = self.consumed_inputs.keys().any(|k| k.batch == input.batch);
+        !recorded && self.entries.values()
+            .any(|e| e.source_batch == input.batch && crate::filenames::shard_of(&e.filename) == input.shard)
+    }
+
+    /// The inputs of `inputs` consumed (see is_input_consumed), `sources` adding the
+    /// source batches known from outside the entries (history, compacted files) for
+    /// the batch numbers none of whose files is recorded
+    pub fn consumed_among(&self, inputs: &BTreeSet<InputKey>, sources: &BTreeSet<u32>) -> BTreeSet<InputKey> {
+        let unrecorded = |batch: u32| !self.consumed_inputs.keys().any(|k| k.batch == batch);
+        inputs.iter()
+            .filter(|k| self.is_input_consumed(**k) || (sources.contains(&k.batch) && unrecorded(k.batch)))
+            .copied()
+            .collect()
+    }
+
+    /// Record the source batches of the compacted file `filename` (none: forget them)
+    pub fn set_compacted_sources(&mut self, filename: &str, sources: Vec<SourceContribution>) {
+        if sources.is_empty() {
+            self.compacted_sources.remove(filename);
+        } else {
+            self.compacted_sources.insert(filename.to_string(), sources);
+        }
+        self.sources_dirty = true;
+    }
+
+    pub fn compacted_sources(&self) -> &BTreeMap<String, Vec<SourceContribution>> {
+        &self.compacted_sources
+    }
+
+    /// Source batches of the lists of `info`: the recorded ones for a compacted file,
+    /// its own source batch otherwise
+    pub fn sources_of(&self, info: &FileInfo) -> Vec<SourceContribution> {
+        match self.compacted_sources.get(&info.filename) {
+            Some(sources) => sources.clone(),
+            None => vec![SourceContribution { source_batch: info.source_batch, nb_lists: info.nb_lists_in_file }],
+        }
+    }
+
+    fn compacted_sources_vec(&self) -> Vec<CompactedSources> {
+        self.compacted_sources.iter()
+            .map(|(filename, sources)| CompactedSources { filename: filename.clone(), sources: sources.clone() })
+            .collect()
+    }
+
+    /// Mark the list count of `filename` as estimated from its size, or as counted
+    pub fn set_estimated(&mut self, filename: &str, estimated: bool) {
+        let changed = if estimated {
+            self.estimated.insert(filename.to_string())
+        } else {
+            self.estimated.remove(filename)
+        };
+        self.estimated_dirty |= changed;
+    }
+
+    /// Files whose list count is estimated from their size (--count --fast)
+    pub fn estimated(&self) -> &BTreeSet<String> {
+        &self.estimated
+    }
+
+    /// Lease `filenames` to a background compaction
+    pub fn lease_files<I: IntoIterator<Item = String>>(&mut self, filenames: I) {
+        self.leased.extend(filenames);
+    }
+
+    /// End the lease of `filenames` (background compaction applied or abandoned)
+    pub fn release_files<'a, I: IntoIterator<Item = &'a str>>(&mut self, filenames: I) {
+        for filename in filenames {
+            self.leased.remove(filename);
+        }
+    }
+
+    pub fn is_leased(&self, filename: &str) -> bool {
+        self.leased.contains(filename)
+    }
+
+    /// Add the consumed inputs recorded by another state (history merging)
+    pub fn merge_consumed_inputs(&mut self, inputs: &BTreeMap<InputKey, ConsumedInput>) {
+        for (key, input) in inputs.iter() {
+            self.consumed_inputs.insert(*key, input.clone());
+        }
+        self.inputs_dirty = true;
+    }
+
+    /// Highest input batch known to be done: the last consumed input, or, for the
+    /// batches processed before the consumed inputs were recorded (states of schema
+    /// version 3 and older), the highest source batch of the entries
+    pub fn last_consumed_batch(&self) -> Option<u32> {
+        let recorded = self.consumed_inputs.keys().map(|k| k.batch).max();
+        let inferred = self.entries.values().map(|e| e.source_batch).max();
+        recorded.max(inferred)
+    }
+
+    /// Highest regular input batch known to be done, the numbering of a restart
+    /// batch (compacted inputs are numbered apart): the last regular input consumed,
+    /// or, for the batches none of whose files is recorded (states of schema version
+    /// 3 and older), the highest source batch of their entries
+    pub fn last_consumed_regular_batch(&self) -> Option<u32> {
+        let recorded = self.consumed_inputs.keys().filter(|k| !k.compacted).map(|k| k.batch).max();
+        let inferred = self.entries.values()
+            .map(|e| e.source_batch)
+            .filter(|batch| !self.consumed_inputs.keys().any(|k| k.batch == *batch))
+            .max();
+        recorded.max(inferred)
+    }
+
+    /// Split `inputs` into those consumed by a run of `shard` (see is_input_consumed)
+    /// and those left to process. A restart batch skips the regular inputs below it
+    /// too; compacted inputs are numbered apart, so they are skipped only once consumed.
+    pub fn split_consumed_inputs(&self, inputs: Vec<crate::filenames::InputFile>, shard: Option<Shard>, start_batch: Option<u32>)
+        -> (Vec<crate::filenames::InputFile>, Vec<crate::filenames::InputFile>) {
+        inputs.into_iter().partition(|f| {
+            let before_start = !f.compacted && start_batch.is_some_and(|batch| f.batch < batch);
+            before_start || self.is_input_consumed(InputKey { shard, ..InputKey::whole(f.batch, f.compacted) })
+        })
+    }
+
+    /// Progress over `inputs` (the input files of the previous size), the inputs
+    /// consumed since `run_started_at` (unix seconds) giving the pace of the run
+    pub fn input_progress(&self, inputs: &std::collections::BTreeSet<InputKey>, run_started_at: i64) -> InputProgress {
+        let this_run = self.consumed_inputs.values().filter(|c| c.completed_at >= run_started_at);
+        InputProgress {
+            inputs_done: self.consumed_among(inputs, &std::collections::BTreeSet::new()).len() as u64,
+            inputs_total: inputs.len() as u64,
+            inputs_this_run: this_run.clone().count() as u64,
+            lists_this_run: this_run.map(|c| c.nb_lists).sum(),
+            elapsed_secs: (unix_now() - run_started_at).max(0) as f64,
+        }
+    }
+
+    /// Source batches of the entries above the last consumed input: outputs of an
+    /// input file whose processing was interrupted (empty without consumed inputs)
+    pub fn interrupted_batches(&self) -> std::collections::BTreeSet<u32> {
+        let Some(last) = self.consumed_inputs.keys().map(|k| k.batch).max() else {
+            return std::collections::BTreeSet::new();
+        };
+        self.entries.values().map(|e| e.source_batch).filter(|b| *b > last).collect()
     }
     
+    #[allow(clippy::too_many_arguments)]
     pub fn update_entry(
         &mut self,
         filename: &str,
@@ -610,13 +2024,23 @@ impl GlobalFileState {
         if let Some(e) = self.entries.get_mut(&Self::key(src_batch, tgt_batch, filename)) {
             e.nb_lists_in_file = nb_lists_in_file;
             e.compacted = compacted;
+            e.compressed = crate::io_helpers::is_compressed_file(e.path_in(&self.base_dir));
             e.file_size_bytes = file_size_bytes;
             e.modified_timestamp = modified_timestamp;
-            self.recompute_cumulative();
+            self.dirty.insert(Self::key(src_batch, tgt_batch, filename));
+        }
+    }
+
+    /// Record where and how the file of a registered entry was produced
+    pub fn set_provenance(&mut self, filename: &str, src_batch: u32, tgt_batch: u32, provenance: Provenance) {
+        if let Some(e) = self.entries.get_mut(&Self::key(src_batch, tgt_batch, filename)) {
+            e.provenance = Some(provenance);
+            self.dirty.insert(Self::key(src_batch, tgt_batch, filename));
         }
     }
     
     pub fn from_history_file(base_dir: &str, target_size: u8, format: &str) -> std::io::Result<Self> {
+        acquire_state_lock(base_dir, target_size)?;
         let path = if format == "rkyv" {
             Path::new(base_dir).join(format!("nsl_{:02}_global_info_history.rkyv", target_size))
         } else {
@@ -629,33 +2053,49 @@ impl GlobalFileState {
             GlobalFileInfo::load_json(&path)?
         };
         
-        Ok(Self::from_vec(base_dir, target_size, gfi.entries))
+        Ok(Self::from_info(base_dir, target_size, gfi))
     }
     
     pub fn flush_as_history(&mut self) -> std::io::Result<()> {
-        self.recompute_cumulative();
+        acquire_state_lock(&self.base_dir, self.target_size)?;
         let entries_vec = self.to_vec();
-        let gfi = GlobalFileInfo { entries: entries_vec };
+        let gfi = GlobalFileInfo {
+            entries: entries_vec,
+            max_lists_per_file: self.max_lists_per_file,
+            schema_version: STATE_SCHEMA_VERSION,
+            tombstones: self.tombstones.values().cloned().collect(),
+            consumed_inputs: self.consumed_inputs.values().cloned().collect(),
+            compacted_sources: self.compacted_sources_vec(),
+            estimated_counts: self.estimated.iter().cloned().collect(),
+            isomorph_reduced: self.isomorph_reduced,
+            strong_pruned: self.strong_pruned,
+        };
 
         let rkyv_path = Path::new(&self.base_dir).join(format!("nsl_{:02}_global_info_history.rkyv", self.target_size));
         
-        // Backup existing rkyv file before overwriting
-        if rkyv_path.exists() {
-            let backup_path = rkyv_path.with_extension("rkyv.old");
-            let _ = fs::rename(&rkyv_path, &backup_path);
-        }
-        
-        // Write to temp file, then rename atomically
+        // Write to temp file, back up the existing file (a failed write leaves it
+        // untouched), then rename atomically
         let rkyv_tmp = rkyv_path.with_extension("rkyv.tmp");
         gfi.save_rkyv(&rkyv_tmp)?;
-        fs::rename(rkyv_tmp, &rkyv_path)?;
+        rotate_backups(&rkyv_path, keep_backups())?;
+        with_retry("rename", &rkyv_path, || fs::rename(&rkyv_tmp, &rkyv_path))?;
 
         Ok(())
     }
     
     pub fn export_human_readable_as_history(&self) -> std::io::Result<()> {
         let entries_vec = self.to_vec();
-        let gfi = GlobalFileInfo { entries: entries_vec.clone() };
+        let gfi = GlobalFileInfo {
+            entries: entries_vec.clone(),
+            max_lists_per_file: self.max_lists_per_file,
+            schema_version: STATE_SCHEMA_VERSION,
+            tombstones: self.tombstones.values().cloned().collect(),
+            consumed_inputs: self.consumed_inputs.values().cloned().collect(),
+            compacted_sources: self.compacted_sources_vec(),
+            estimated_counts: self.estimated.iter().cloned().collect(),
+            isomorph_reduced: self.isomorph_reduced,
+            strong_pruned: self.strong_pruned,
+        };
 
         let json_path = Path::new(&self.base_dir).join(format!("nsl_{:02}_global_info_history.json", self.target_size));
         let txt_path = Path::new(&self.base_dir).join(format!("nsl_{:02}_global_info_history.txt", self.target_size));
@@ -663,21 +2103,23 @@ impl GlobalFileState {
         // JSON export
         let json_tmp = json_path.with_extension("json.tmp");
         let json_text = serde_json::to_string_pretty(&gfi)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-        fs::write(&json_tmp, json_text)?;
+            .map_err(std::io::Error::other)?;
+        with_retry("write", &json_tmp, || fs::write(&json_tmp, &json_text))?;
         if json_path.exists() { let _ = fs::remove_file(&json_path); }
-        fs::rename(json_tmp, &json_path)?;
+        with_retry("rename", &json_path, || fs::rename(&json_tmp, &json_path))?;
 
         // TXT export
         let txt_tmp = txt_path.with_extension("txt.tmp");
         let txt_body = render_global_count(&entries_vec, self.target_size, &self.base_dir);
-        fs::write(&txt_tmp, txt_body)?;
+        with_retry("write", &txt_tmp, || fs::write(&txt_tmp, &txt_body))?;
         if txt_path.exists() { let _ = fs::remove_file(&txt_path); }
-        fs::rename(txt_tmp, &txt_path)?;
+        with_retry("rename", &txt_path, || fs::rename(&txt_tmp, &txt_path))?;
 
         Ok(())
     }
 
+    /// Entries in (target_batch, source_batch, filename) order, with their cumulative
+    /// list counts (computed here only: one walk per export or flush)
     pub fn to_vec(&self) -> Vec<FileInfo> {
         let mut v: Vec<FileInfo> = self.entries.values().cloned().collect();
         v.sort_by(|a, b| match a.target_batch.cmp(&b.target_batch) {
@@ -687,36 +2129,114 @@ impl GlobalFileState {
             },
             other => other,
         });
+        let mut cumulative = 0u64;
+        for e in v.iter_mut() {
+            cumulative += e.nb_lists_in_file;
+            e.cumulative_nb_lists = cumulative;
+        }
         v
     }
 
+    /// Flush after an output file, once every flush_every() calls (--flush-every):
+    /// in between, the journal keeps the changes safe
+    pub fn flush_throttled(&mut self) -> std::io::Result<()> {
+        self.flush_throttled_every(flush_every())
+    }
+
+    fn flush_throttled_every(&mut self, every: u64) -> std::io::Result<()> {
+        self.unflushed += 1;
+        if self.unflushed >= every { self.flush() } else { Ok(()) }
+    }
+
+    /// Flush the changes left by flush_throttled (at file boundaries and at the end of a run)
+    pub fn flush_pending(&mut self) -> std::io::Result<()> {
+        if self.unflushed > 0 { self.flush() } else { Ok(()) }
+    }
+
     pub fn flush(&mut self) -> std::io::Result<()> {
-        self.recompute_cumulative();
+        acquire_state_lock(&self.base_dir, self.target_size)?;
+        self.unflushed = 0;
+        if state_backend() == StateBackend::Sqlite {
+            self.flush_to_database()?;
+            self.clear_journal();
+            self.emit_flushed();
+            return Ok(());
+        }
         let entries_vec = self.to_vec();
-        let gfi = GlobalFileInfo { entries: entries_vec };
+        let gfi = GlobalFileInfo {
+            entries: entries_vec,
+            max_lists_per_file: self.max_lists_per_file,
+            schema_version: STATE_SCHEMA_VERSION,
+            tombstones: self.tombstones.values().cloned().collect(),
+            consumed_inputs: self.consumed_inputs.values().cloned().collect(),
+            compacted_sources: self.compacted_sources_vec(),
+            estimated_counts: self.estimated.iter().cloned().collect(),
+            isomorph_reduced: self.isomorph_reduced,
+            strong_pruned: self.strong_pruned,
+        };
 
         // Save to rkyv as authoritative format
         let rkyv_path = Path::new(&self.base_dir).join(format!("nsl_{:02}_global_info.rkyv", self.target_size));
         
-        // Backup existing rkyv file before overwriting
-        if rkyv_path.exists() {
-            let backup_path = rkyv_path.with_extension("rkyv.old");
-            let _ = fs::rename(&rkyv_path, &backup_path);
-        }
-        
-        // Write to temp file, then rename atomically
+        // Write to temp file, back up the existing file (a failed write leaves it
+        // untouched), then rename atomically
         let rkyv_tmp = rkyv_path.with_extension("rkyv.tmp");
         gfi.save_rkyv(&rkyv_tmp)?;
-        fs::rename(rkyv_tmp, &rkyv_path)?;
+        rotate_backups(&rkyv_path, keep_backups())?;
+        with_retry("rename", &rkyv_path, || fs::rename(&rkyv_tmp, &rkyv_path))?;
+        self.clear_journal();
+        self.emit_flushed();
 
         Ok(())
     }
+
+    /// Tell the observers of the run that this state is saved
+    fn emit_flushed(&self) {
+        crate::events::state_flushed(crate::events::StateFlushed { size: self.target_size,
+            directory: self.base_dir.clone(), nb_files: self.entries.len() });
+    }
     
+    /// Write the entries changed since the last flush (all of them if the database
+    /// does not hold this state yet) to the database, in one transaction
+    fn flush_to_database(&mut self) -> std::io::Result<()> {
+        let database = Self::database_path(&self.base_dir, self.target_size);
+        let changes: Option<Vec<EntryChange>> = self.synced.then(|| {
+            self.dirty.iter().map(|key| (key, self.entries.get(key), self.tombstones.get(key))).collect()
+        });
+        let entries: Vec<&FileInfo> = self.entries.values().collect();
+        let tombstones: Vec<&Tombstone> = self.tombstones.values().collect();
+        let inputs: Option<Vec<&ConsumedInput>> = (self.inputs_dirty || !self.synced)
+            .then(|| self.consumed_inputs.values().collect());
+        let sources: Option<Vec<CompactedSources>> = (self.sources_dirty || self.estimated_dirty || !self.synced)
+            .then(|| self.compacted_sources_vec());
+        let estimated: Vec<String> = self.estimated.iter().cloned().collect();
+        with_retry("write", &database, || sqlite_state::save(&database, changes.as_deref(), &entries, &tombstones,
+            inputs.as_deref(), sources.as_deref().map(|sources| (sources, estimated.as_slice())),
+            &[("max_lists_per_file", self.max_lists_per_file.map(|n| n as i64)), ("isomorph_reduced", Some(self.isomorph_reduced as i64)),
+              ("strong_pruned", Some(self.strong_pruned as i64))]))?;
+        self.dirty.clear();
+        self.inputs_dirty = false;
+        self.sources_dirty = false;
+        self.estimated_dirty = false;
+        self.synced = true;
+        Ok(())
+    }
+
     /// Export human-readable JSON and TXT files from the current state
     /// This is a write-only operation - these files are not read during normal operation
     pub fn export_human_readable(&self) -> std::io::Result<()> {
         let entries_vec = self.to_vec();
-        let gfi = GlobalFileInfo { entries: entries_vec.clone() };
+        let gfi = GlobalFileInfo {
+            entries: entries_vec.clone(),
+            max_lists_per_file: self.max_lists_per_file,
+            schema_version: STATE_SCHEMA_VERSION,
+            tombstones: self.tombstones.values().cloned().collect(),
+            consumed_inputs: self.consumed_inputs.values().cloned().collect(),
+            compacted_sources: self.compacted_sources_vec(),
+            estimated_counts: self.estimated.iter().cloned().collect(),
+            isomorph_reduced: self.isomorph_reduced,
+            strong_pruned: self.strong_pruned,
+        };
 
         let json_path = Path::new(&self.base_dir).join(format!("nsl_{:02}_global_info.json", self.target_size));
         let txt_path = Path::new(&self.base_dir).join(format!("nsl_{:02}_global_info.txt", self.target_size));
@@ -724,37 +2244,68 @@ impl GlobalFileState {
         // JSON export
         let json_tmp = json_path.with_extension("json.tmp");
         let json_text = serde_json::to_string_pretty(&gfi)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-        fs::write(&json_tmp, json_text)?;
+            .map_err(std::io::Error::other)?;
+        with_retry("write", &json_tmp, || fs::write(&json_tmp, &json_text))?;
         if json_path.exists() { let _ = fs::remove_file(&json_path); }
-        fs::rename(json_tmp, &json_path)?;
+        with_retry("rename", &json_path, || fs::rename(&json_tmp, &json_path))?;
 
         // TXT export
         let txt_tmp = txt_path.with_extension("txt.tmp");
         let txt_body = render_global_count(&entries_vec, self.target_size, &self.base_dir);
-        fs::write(&txt_tmp, txt_body)?;
+        with_retry("write", &txt_tmp, || fs::write(&txt_tmp, &txt_body))?;
         if txt_path.exists() { let _ = fs::remove_file(&txt_path); }
-        fs::rename(txt_tmp, &txt_path)?;
+        with_retry("rename", &txt_path, || fs::rename(&txt_tmp, &txt_path))?;
 
         Ok(())
     }
 
-    fn recompute_cumulative(&mut self) {
-        let mut entries_sorted: Vec<_> = self.entries.values_mut().collect();
-        entries_sorted.sort_by(|a, b| match a.target_batch.cmp(&b.target_batch) {
-            std::cmp::Ordering::Equal => match a.source_batch.cmp(&b.source_batch) {
-                std::cmp::Ordering::Equal => a.filename.cmp(&b.filename),
-                other => other,
-            },
-            other => other,
-        });
-        let mut cumulative = 0u64;
-        for e in entries_sorted {
-            cumulative += e.nb_lists_in_file;
-            e.cumulative_nb_lists = cumulative;
+    /// Write the consolidated count report (nsl_XX_global_count.txt) of --count
+    pub fn export_global_count(&self) -> std::io::Result<()> {
+        let path = Path::new(&self.base_dir).join(format!("nsl_{:02}_global_count.txt", self.target_size));
+        let tmp = path.with_extension("txt.tmp");
+        let body = render_global_count(&self.to_vec(), self.target_size, &self.base_dir);
+        with_retry("write", &tmp, || fs::write(&tmp, &body))?;
+        with_retry("rename", &path, || fs::rename(&tmp, &path))
+    }
+
+    /// One CSV row per entry (all fields, provenance flattened), with a header line
+    pub fn to_csv(&self) -> String {
+        let opt = |v: Option<String>| v.unwrap_or_default();
+        let mut out = String::from("source_batch,target_batch,cumulative_nb_lists,nb_lists_in_file,filename,compacted,\
+            compressed,exists,file_size_bytes,modified_timestamp,hostname,crate_version,duration_ms,input_hash\n");
+        for e in self.to_vec().iter() {
+            let p = e.provenance.as_ref();
+            out.push_str(&format!("{},{},{},{},{},{},{},{},{},{},{},{},{},{}\n",
+                e.source_batch, e.target_batch, e.cumulative_nb_lists, e.nb_lists_in_file, csv_field(&e.filename),
+                e.compacted, e.compressed, opt(e.exists.map(|b| b.to_string())),
+                opt(e.file_size_bytes.map(|b| b.to_string())), opt(e.modified_timestamp.map(|t| t.to_string())),
+                opt(p.map(|p| csv_field(&p.hostname))), opt(p.map(|p| csv_field(&p.crate_version))),
+                opt(p.map(|p| p.duration_ms.to_string())), opt(p.and_then(|p| p.input_hash.clone()))));
         }
+        out
+    }
+
+    /// Write nsl_XX_global_info.csv (spreadsheet/pandas import) next to the state
+    pub fn export_csv(&self) -> std::io::Result<PathBuf> {
+        let csv_path = Path::new(&self.base_dir).join(format!("nsl_{:02}_global_info.csv", self.target_size));
+        let csv_tmp = csv_path.with_extension("csv.tmp");
+        let csv_body = self.to_csv();
+        with_retry("write", &csv_tmp, || fs::write(&csv_tmp, &csv_body))?;
+        if csv_path.exists() { let _ = fs::remove_file(&csv_path); }
+        with_retry("rename", &csv_path, || fs::rename(&csv_tmp, &csv_path))?;
+        Ok(csv_path)
     }
 }
+
+/// CSV field, quoted if it holds a separator, a quote or a line break
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
 /// Result of checking one file on disk.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct FileCheckResult {
@@ -779,17 +2330,17 @@ impl FileCheckResult {
     }
 }
 
-/// Count lists quickly without deserializing fully.
+/// `io_helpers::with_retry` of an operation on `path` (state files are rewritten
+/// on every flush, so network share hiccups hit them first)
+fn with_retry<T>(what: &str, path: &Path, op: impl FnMut() -> std::io::Result<T>) -> std::io::Result<T> {
+    crate::io_helpers::with_retry(what, &path.to_string_lossy(), op)
+}
+
+/// Count lists quickly without deserializing fully (any encoding).
 fn count_lists_in_file(path: &Path) -> std::io::Result<u64> {
-    let file = fs::File::open(path)?;
-    let mmap = unsafe { Mmap::map(&file)? };
-    match check_archived_root::<Vec<NoSetListSerialized>>(&mmap[..]) {
-        Ok(arch) => Ok(arch.len() as u64),
-        Err(e) => {
-            debug_print(&format!("   ... validation failed for {}: {:?}", path.display(), e));
-            Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Archive validation failed"))
-        }
-    }
+    crate::io_helpers::count_lists_in_file(&path.to_string_lossy()).inspect_err(|e| {
+        debug_print(&format!("   ... validation failed for {}: {}", path.display(), e));
+    })
 }
 
 /// Utility to derive FileInfo rows from the existing global count text.
@@ -824,9 +2375,11 @@ pub fn parse_global_count_text(text: &str) -> Vec<FileInfo> {
             nb_lists_in_file: nb_lists,
             filename,
             compacted,
+            compressed: false,
             exists: None,
             file_size_bytes: None,
             modified_timestamp: None,
+            provenance: None,
         });
     }
     entries
@@ -843,8 +2396,8 @@ pub fn render_global_count(entries: &[FileInfo], target_size: u8, base_path: &st
     lines.push(format!("# File Count Summary for no-set-{:02} lists", target_size));
     lines.push(format!("# Generated: {}", chrono::Local::now().format("%Y-%m-%d %H:%M:%S")));
     lines.push(format!("# Input directory: {}", base_path));
-    lines.push(format!("# Intermediary files used: N/A"));
-    lines.push("# Format: source_batch target_batch | cumulative_nb_lists | nb_lists_in_file | filename | compacted".to_string());
+    lines.push("# Intermediary files used: N/A".to_string());
+    lines.push("# Format: source_batch target_batch | cumulative_nb_lists | nb_lists_in_file | filename | compacted | provenance".to_string());
     lines.push("#".to_string());
 
     let mut cumulative = 0u64;
@@ -861,7 +2414,7 @@ pub fn render_global_count(entries: &[FileInfo], target_size: u8, base_path: &st
         } else {
             cumulative = e.cumulative_nb_lists;
         }
-        lines.push(format!(
+        let mut line = format!(
             "{:06} {:06} | {:>17} | {:>17} | {} | {}",
             e.source_batch,
             e.target_batch,
@@ -869,7 +2422,11 @@ pub fn render_global_count(entries: &[FileInfo], target_size: u8, base_path: &st
             e.nb_lists_in_file.separated_string(),
             e.filename,
             if e.compacted { "compacted" } else { "" }
-        ));
+        );
+        if let Some(provenance) = &e.provenance {
+            line.push_str(&format!(" | {}", provenance.summary()));
+        }
+        lines.push(line);
     }
 
     lines.push("#".to_string());
@@ -882,32 +2439,26 @@ pub fn render_global_count(entries: &[FileInfo], target_size: u8, base_path: &st
 pub fn scan_rkyv_files(base_path: &str, target_size: u8) -> std::io::Result<Vec<FileInfo>> {
     let mut entries: Vec<FileInfo> = Vec::new();
     let pattern = format!("_to_{:02}_batch_", target_size);
-    for entry in fs::read_dir(base_path)? {
-        if let Ok(e) = entry {
-            if let Some(name) = e.file_name().to_str() {
-                if name.starts_with("nsl_") && name.contains(&pattern) && name.ends_with(".rkyv") {
-                    let filename = name.to_string();
-                    let compacted = name.contains("_compacted.rkyv");
-                    let (src_batch, tgt_batch) = parse_batches(&filename).unwrap_or((0, 0));
-                    let count = count_lists_in_file(&e.path()).unwrap_or(0);
-                    entries.push(FileInfo {
-                        source_batch: src_batch,
-                        target_batch: tgt_batch,
-                        cumulative_nb_lists: 0,
-                        nb_lists_in_file: count,
-                        filename,
-                        compacted,
-                        exists: Some(true),
-                        file_size_bytes: e.metadata().ok().map(|m| m.len()),
-                        modified_timestamp: e
-                            .metadata()
-                            .ok()
-                            .and_then(|m| m.modified().ok())
-                            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-                            .map(|d| d.as_secs() as i64),
-                    });
-                }
-            }
+    for name in crate::storage::list_file_names(base_path)? {
+        if name.contains(&pattern) {
+            let path = Path::new(base_path).join(&name);
+            let compacted = name.contains("_compacted.rkyv");
+            let (src_batch, tgt_batch) = parse_batches(&name).unwrap_or((0, 0));
+            let count = count_lists_in_file(&path).unwrap_or(0);
+            let metadata = crate::storage::file_metadata(&path.to_string_lossy());
+            entries.push(FileInfo {
+                source_batch: src_batch,
+                target_batch: tgt_batch,
+                cumulative_nb_lists: 0,
+                nb_lists_in_file: count,
+                filename: name,
+                compacted,
+                compressed: crate::io_helpers::is_compressed_file(&path),
+                exists: Some(true),
+                file_size_bytes: metadata.map(|(bytes, _)| bytes),
+                modified_timestamp: metadata.and_then(|(_, modified)| modified),
+                provenance: None,
+            });
         }
     }
     entries.sort_by(|a, b| match a.target_batch.cmp(&b.target_batch) {
@@ -939,3 +2490,626 @@ fn parse_batches(filename: &str) -> Option<(u32, u32)> {
     }
     None
 }
+
+/// Entry of the state written since the last flush (None: removed), by key
+type EntryChange<'a> = (&'a (u32, u32, String), Option<&'a FileInfo>, Option<&'a Tombstone>);
+
+/// SQLite database of the global state of a size (--state-backend sqlite): tables
+/// `entries` (one row per file, keyed like the in-memory map), `tombstones` (same
+/// keys), `provenance` (same keys, files written with one), `consumed_inputs` (one
+/// row per input file), `compacted_sources` (one row per compacted file and source
+/// batch, in list order), `estimated_counts` (one row per file) and `meta`
+#[cfg(feature = "sqlite")]
+mod sqlite_state {
+    use std::collections::HashMap;
+    use std::path::Path;
+    use rusqlite::{params, Connection, OptionalExtension};
+
+    use super::{CompactedSources, ConsumedInput, EntryChange, FileInfo, GlobalFileInfo, Provenance, RemovalReason,
+        Shard, SourceContribution, Tombstone, STATE_SCHEMA_VERSION};
+
+    fn sql_error(e: rusqlite::Error) -> std::io::Error {
+        std::io::Error::other(e.to_string())
+    }
+
+    /// Open (or create) a database in WAL mode
+    fn open(database: &Path) -> std::io::Result<Connection> {
+        let conn = Connection::open(database).map_err(sql_error)?;
+        conn.busy_timeout(std::time::Duration::from_secs(60)).map_err(sql_error)?;
+        conn.query_row("PRAGMA journal_mode=WAL", [], |_| Ok(())).map_err(sql_error)?;
+        conn.execute_batch(
+            "PRAGMA synchronous=NORMAL;
+             CREATE TABLE IF NOT EXISTS entries (
+                 source_batch INTEGER NOT NULL, target_batch INTEGER NOT NULL, filename TEXT NOT NULL,
+                 nb_lists INTEGER NOT NULL, compacted INTEGER NOT NULL, compressed INTEGER NOT NULL,
+                 file_exists INTEGER, file_size_bytes INTEGER, modified INTEGER,
+                 PRIMARY KEY (source_batch, target_batch, filename));
+             CREATE TABLE IF NOT EXISTS tombstones (
+                 source_batch INTEGER NOT NULL, target_batch INTEGER NOT NULL, filename TEXT NOT NULL,
+                 removed_at INTEGER NOT NULL, reason TEXT NOT NULL,
+                 PRIMARY KEY (source_batch, target_batch, filename));
+             CREATE TABLE IF NOT EXISTS provenance (
+                 source_batch INTEGER NOT NULL, target_batch INTEGER NOT NULL, filename TEXT NOT NULL,
+                 hostname TEXT NOT NULL, crate_version TEXT NOT NULL, duration_ms INTEGER NOT NULL,
+                 input_hash TEXT,
+                 PRIMARY KEY (source_batch, target_batch, filename));
+             CREATE TABLE IF NOT EXISTS consumed_inputs (
+                 batch INTEGER NOT NULL, size INTEGER NOT NULL, nb_lists INTEGER NOT NULL,
+                 completed_at INTEGER NOT NULL, compacted INTEGER NOT NULL,
+                 shard_index INTEGER, shard_count INTEGER,
+                 PRIMARY KEY (batch, compacted, shard_index, shard_count));
+             CREATE TABLE IF NOT EXISTS compacted_sources (
+                 filename TEXT NOT NULL, position INTEGER NOT NULL, source_batch INTEGER NOT NULL,
+                 nb_lists INTEGER NOT NULL,
+                 PRIMARY KEY (filename, position));
+             CREATE TABLE IF NOT EXISTS estimated_counts (filename TEXT PRIMARY KEY);
+             CREATE TABLE IF NOT EXISTS meta (key TEXT PRIMARY KEY, value INTEGER);").map_err(sql_error)?;
+        Ok(conn)
+    }
+
+    /// Provenance of the entries, by key
+    fn load_provenance(conn: &Connection) -> std::io::Result<HashMap<(u32, u32, String), Provenance>> {
+        let mut statement = conn.prepare(
+            "SELECT source_batch, target_batch, filename, hostname, crate_version, duration_ms, input_hash
+             FROM provenance").map_err(sql_error)?;
+        let rows = statement.query_map([], |row| Ok(((row.get(0)?, row.get(1)?, row.get(2)?), Provenance {
+            hostname: row.get(3)?,
+            crate_version: row.get(4)?,
+            duration_ms: row.get::<_, i64>(5)? as u64,
+            input_hash: row.get(6)?,
+        }))).map_err(sql_error)?;
+        rows.collect::<Result<HashMap<_, _>, _>>().map_err(sql_error)
+    }
+
+    /// Every entry of the database (cumulative counts are recomputed by the caller)
+    pub fn load(database: &Path) -> std::io::Result<GlobalFileInfo> {
+        let conn = open(database)?;
+        let mut statement = conn.prepare(
+            "SELECT source_batch, target_batch, filename, nb_lists, compacted, compressed,
+                    file_exists, file_size_bytes, modified
+             FROM entries ORDER BY target_batch, source_batch, filename").map_err(sql_error)?;
+        let mut provenance = load_provenance(&conn)?;
+        let rows = statement.query_map([], |row| Ok(FileInfo {
+            source_batch: row.get(0)?,
+            target_batch: row.get(1)?,
+            cumulative_nb_lists: 0,
+            nb_lists_in_file: row.get::<_, i64>(3)? as u64,
+            filename: row.get(2)?,
+            compacted: row.get(4)?,
+            compressed: row.get(5)?,
+            exists: row.get(6)?,
+            file_size_bytes: row.get::<_, Option<i64>>(7)?.map(|b| b as u64),
+            modified_timestamp: row.get(8)?,
+            provenance: None,
+        })).map_err(sql_error)?;
+        let mut entries = rows.collect::<Result<Vec<FileInfo>, _>>().map_err(sql_error)?;
+        for e in entries.iter_mut() {
+            e.provenance = provenance.remove(&(e.source_batch, e.target_batch, e.filename.clone()));
+        }
+        let mut statement = conn.prepare(
+            "SELECT source_batch, target_batch, filename, removed_at, reason FROM tombstones").map_err(sql_error)?;
+        let rows = statement.query_map([], |row| Ok(Tombstone {
+            source_batch: row.get(0)?,
+            target_batch: row.get(1)?,
+            filename: row.get(2)?,
+            removed_at: row.get(3)?,
+            reason: RemovalReason::parse(&row.get::<_, String>(4)?).unwrap_or(RemovalReason::Manual),
+        })).map_err(sql_error)?;
+        let tombstones = rows.collect::<Result<Vec<Tombstone>, _>>().map_err(sql_error)?;
+        let mut statement = conn.prepare(
+            "SELECT size, batch, nb_lists, completed_at, compacted, shard_index, shard_count FROM consumed_inputs
+             ORDER BY batch, compacted, shard_count, shard_index").map_err(sql_error)?;
+        let rows = statement.query_map([], |row| Ok(ConsumedInput {
+            size: row.get(0)?,
+            batch: row.get(1)?,
+            compacted: row.get(4)?,
+            shard: match (row.get::<_, Option<u32>>(5)?, row.get::<_, Option<u32>>(6)?) {
+                (Some(index), Some(count)) => Some(Shard { index, count }),
+                _ => None,
+            },
+            nb_lists: row.get::<_, i64>(2)? as u64,
+            completed_at: row.get(3)?,
+        })).map_err(sql_error)?;
+        let consumed_inputs = rows.collect::<Result<Vec<ConsumedInput>, _>>().map_err(sql_error)?;
+        let mut statement = conn.prepare(
+            "SELECT filename, source_batch, nb_lists FROM compacted_sources ORDER BY filename, position").map_err(sql_error)?;
+        let rows = statement.query_map([], |row| Ok((row.get::<_, String>(0)?, SourceContribution {
+            source_batch: row.get(1)?,
+            nb_lists: row.get::<_, i64>(2)? as u64,
+        }))).map_err(sql_error)?;
+        let mut compacted_sources: Vec<CompactedSources> = Vec::new();
+        for row in rows {
+            let (filename, source) = row.map_err(sql_error)?;
+            match compacted_sources.last_mut() {
+                Some(last) if last.filename == filename => last.sources.push(source),
+                _ => compacted_sources.push(CompactedSources { filename, sources: vec![source] }),
+            }
+        }
+        let mut statement = conn.prepare("SELECT filename FROM estimated_counts ORDER BY filename").map_err(sql_error)?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0)).map_err(sql_error)?;
+        let estimated_counts = rows.collect::<Result<Vec<String>, _>>().map_err(sql_error)?;
+        let meta = |key: &str| conn.query_row("SELECT value FROM meta WHERE key = ?1", [key],
+            |row| row.get::<_, Option<i64>>(0)).optional().map_err(sql_error).map(Option::flatten);
+        let max_lists_per_file = meta("max_lists_per_file")?.map(|n| n as u64);
+        let isomorph_reduced = meta("isomorph_reduced")?.unwrap_or(0) != 0;
+        let strong_pruned = meta("strong_pruned")?.unwrap_or(0) != 0;
+        let schema_version = meta("schema_version")?.unwrap_or(0) as u32;
+        if schema_version > STATE_SCHEMA_VERSION {
+            return Err(GlobalFileInfo::newer_schema_error(database, schema_version));
+        }
+        Ok(GlobalFileInfo { entries, max_lists_per_file, schema_version: STATE_SCHEMA_VERSION, tombstones, consumed_inputs,
+            compacted_sources, estimated_counts, isomorph_reduced, strong_pruned })
+    }
+
+    /// Apply `changes` (entry and tombstone written, or None: removed) in one
+    /// transaction, or replace every entry and tombstone when there are no changes to
+    /// apply; the consumed inputs, and the compacted sources with the files of
+    /// estimated count, are replaced when given; `meta` rows (key, value) are written
+    pub fn save(database: &Path, changes: Option<&[EntryChange]>, entries: &[&FileInfo], tombstones: &[&Tombstone],
+                inputs: Option<&[&ConsumedInput]>, sources: Option<(&[CompactedSources], &[String])>,
+                meta: &[(&str, Option<i64>)]) -> std::io::Result<()> {
+        let mut conn = open(database)?;
+        let tx = conn.transaction().map_err(sql_error)?;
+        {
+            let mut upsert = tx.prepare(
+                "INSERT OR REPLACE INTO entries VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)").map_err(sql_error)?;
+            let mut record = tx.prepare(
+                "INSERT OR REPLACE INTO provenance VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)").map_err(sql_error)?;
+            let mut forget = tx.prepare(
+                "DELETE FROM provenance WHERE source_batch = ?1 AND target_batch = ?2 AND filename = ?3").map_err(sql_error)?;
+            let mut write = |e: &FileInfo| -> std::io::Result<usize> {
+                match &e.provenance {
+                    Some(p) => record.execute(params![e.source_batch, e.target_batch, e.filename, p.hostname,
+                        p.crate_version, p.duration_ms as i64, p.input_hash]),
+                    None => forget.execute(params![e.source_batch, e.target_batch, e.filename]),
+                }.map_err(sql_error)?;
+                upsert.execute(params![e.source_batch, e.target_batch, e.filename,
+                    e.nb_lists_in_file as i64, e.compacted, e.compressed, e.exists,
+                    e.file_size_bytes.map(|b| b as i64), e.modified_timestamp]).map_err(sql_error)
+            };
+            let mut bury = tx.prepare(
+                "INSERT OR REPLACE INTO tombstones VALUES (?1, ?2, ?3, ?4, ?5)").map_err(sql_error)?;
+            let mut write_tombstone = |t: &Tombstone| bury.execute(params![t.source_batch, t.target_batch,
+                t.filename, t.removed_at, t.reason.as_str()]).map_err(sql_error);
+            match changes {
+                Some(changes) => {
+                    let mut delete = tx.prepare(
+                        "DELETE FROM entries WHERE source_batch = ?1 AND target_batch = ?2 AND filename = ?3").map_err(sql_error)?;
+                    let mut delete_provenance = tx.prepare(
+                        "DELETE FROM provenance WHERE source_batch = ?1 AND target_batch = ?2 AND filename = ?3").map_err(sql_error)?;
+                    let mut unbury = tx.prepare(
+                        "DELETE FROM tombstones WHERE source_batch = ?1 AND target_batch = ?2 AND filename = ?3").map_err(sql_error)?;
+                    for (key, entry, tombstone) in changes {
+                        match entry {
+                            Some(e) => write(e)?,
+                            None => {
+                                delete_provenance.execute(params![key.0, key.1, key.2]).map_err(sql_error)?;
+                                delete.execute(params![key.0, key.1, key.2]).map_err(sql_error)?
+                            }
+                        };
+                        match tombstone {
+                            Some(t) => write_tombstone(t)?,
+                            None => unbury.execute(params![key.0, key.1, key.2]).map_err(sql_error)?,
+                        };
+                    }
+                }
+                None => {
+                    tx.execute("DELETE FROM entries", []).map_err(sql_error)?;
+                    tx.execute("DELETE FROM provenance", []).map_err(sql_error)?;
+                    tx.execute("DELETE FROM tombstones", []).map_err(sql_error)?;
+                    for e in entries {
+                        write(e)?;
+                    }
+                    for t in tombstones {
+                        write_tombstone(t)?;
+                    }
+                }
+            }
+        }
+        if let Some(inputs) = inputs {
+            tx.execute("DELETE FROM consumed_inputs", []).map_err(sql_error)?;
+            let mut insert = tx.prepare("INSERT INTO consumed_inputs VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)").map_err(sql_error)?;
+            for c in inputs {
+                insert.execute(params![c.batch, c.size, c.nb_lists as i64, c.completed_at, c.compacted,
+                    c.shard.map(|s| s.index), c.shard.map(|s| s.count)]).map_err(sql_error)?;
+            }
+        }
+        if let Some((sources, estimated)) = sources {
+            tx.execute("DELETE FROM estimated_counts", []).map_err(sql_error)?;
+            let mut insert = tx.prepare("INSERT INTO estimated_counts VALUES (?1)").map_err(sql_error)?;
+            for filename in estimated {
+                insert.execute(params![filename]).map_err(sql_error)?;
+            }
+            tx.execute("DELETE FROM compacted_sources", []).map_err(sql_error)?;
+            let mut insert = tx.prepare("INSERT INTO compacted_sources VALUES (?1, ?2, ?3, ?4)").map_err(sql_error)?;
+            for c in sources {
+                for (position, s) in c.sources.iter().enumerate() {
+                    insert.execute(params![c.filename, position as i64, s.source_batch, s.nb_lists as i64]).map_err(sql_error)?;
+                }
+            }
+        }
+        for (key, value) in meta {
+            tx.execute("INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)", params![key, value]).map_err(sql_error)?;
+        }
+        tx.execute("INSERT OR REPLACE INTO meta (key, value) VALUES ('schema_version', ?1)",
+            params![STATE_SCHEMA_VERSION as i64]).map_err(sql_error)?;
+        tx.commit().map_err(sql_error)
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+mod sqlite_state {
+    use std::path::Path;
+
+    use super::{CompactedSources, ConsumedInput, EntryChange, FileInfo, GlobalFileInfo, Tombstone};
+
+    fn unsupported() -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::Unsupported,
+            "The sqlite state backend needs a build with the `sqlite` feature (cargo build --release --features sqlite)")
+    }
+
+    pub fn load(_database: &Path) -> std::io::Result<GlobalFileInfo> {
+        Err(unsupported())
+    }
+
+    pub fn save(_database: &Path, _changes: Option<&[EntryChange]>, _entries: &[&FileInfo], _tombstones: &[&Tombstone],
+                _inputs: Option<&[&ConsumedInput]>, _sources: Option<(&[CompactedSources], &[String])>,
+                _meta: &[(&str, Option<i64>)]) -> std::io::Result<()> {
+        Err(unsupported())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rkyv::{Archive, Serialize as RkyvSerialize};
+
+    /// Layout of schema version 1 (no header): the entries only
+    #[derive(Archive, RkyvSerialize)]
+    struct EntryV1 {
+        source_batch: u32,
+        target_batch: u32,
+        cumulative_nb_lists: u64,
+        nb_lists_in_file: u64,
+        filename: String,
+        compacted: bool,
+        exists: Option<bool>,
+        file_size_bytes: Option<u64>,
+        modified_timestamp: Option<i64>,
+    }
+
+    /// Layout of schema version 3 (NSLSTAT3)
+    #[derive(Archive, RkyvSerialize)]
+    struct StateV3 {
+        entries: Vec<EntryV3>,
+        max_lists_per_file: Option<u64>,
+    }
+
+    #[derive(Archive, RkyvSerialize)]
+    struct EntryV3 {
+        source_batch: u32,
+        target_batch: u32,
+        cumulative_nb_lists: u64,
+        nb_lists_in_file: u64,
+        filename: String,
+        compacted: bool,
+        compressed: bool,
+        exists: Option<bool>,
+        file_size_bytes: Option<u64>,
+        modified_timestamp: Option<i64>,
+    }
+
+    fn entry(source_batch: u32, target_batch: u32, nb_lists: u64) -> FileInfo {
+        FileInfo {
+            source_batch, target_batch, cumulative_nb_lists: nb_lists, nb_lists_in_file: nb_lists,
+            filename: format!("nsl_04_batch_{:06}_to_05_batch_{:06}.rkyv", source_batch, target_batch),
+            compacted: false, compressed: true, exists: Some(true), file_size_bytes: Some(512), modified_timestamp: Some(1_700_000_000),
+            provenance: None,
+        }
+    }
+
+    /// A state using every field of the current schema
+    fn full_state() -> GlobalFileInfo {
+        let mut with_provenance = entry(0, 0, 7);
+        with_provenance.provenance = Some(Provenance {
+            hostname: "host".to_string(), crate_version: "0.4.14".to_string(), duration_ms: 1234,
+            input_hash: Some("crc32:0a1b2c3d".to_string()),
+        });
+        let compacted = FileInfo { compacted: true, ..entry(1, 1, 9) };
+        GlobalFileInfo {
+            max_lists_per_file: Some(1000),
+            tombstones: vec![Tombstone { source_batch: 2, target_batch: 2, filename: entry(2, 2, 0).filename,
+                removed_at: 1_700_000_100, reason: RemovalReason::Pruned }],
+            consumed_inputs: vec![ConsumedInput { size: 4, batch: 0, compacted: true, shard: Some(Shard { index: 1, count: 2 }),
+                nb_lists: 3, completed_at: 1_700_000_050 }],
+            compacted_sources: vec![CompactedSources { filename: compacted.filename.clone(),
+                sources: vec![SourceContribution { source_batch: 1, nb_lists: 4 }, SourceContribution { source_batch: 3, nb_lists: 5 }] }],
+            estimated_counts: vec![compacted.filename.clone()],
+            isomorph_reduced: true,
+            strong_pruned: true,
+            ..GlobalFileInfo::new(vec![with_provenance, compacted])
+        }
+    }
+
+    #[test]
+    fn current_state_round_trips_through_rkyv_and_json() {
+        let dir = crate::test_dir::TestDir::new("file_info_round_trip");
+        let state = full_state();
+
+        let rkyv_path = dir.join("nsl_05_global_info.rkyv");
+        state.save_rkyv(&rkyv_path).expect("save rkyv");
+        assert_eq!(state_schema_version(&rkyv_path).expect("version"), STATE_SCHEMA_VERSION);
+        assert_eq!(GlobalFileInfo::load_rkyv(&rkyv_path).expect("load rkyv"), state);
+
+        let json_path = dir.join("nsl_05_global_info.json");
+        state.save_json(&json_path).expect("save json");
+        assert_eq!(GlobalFileInfo::load_json(&json_path).expect("load json"), state);
+    }
+
+    #[test]
+    fn released_states_are_migrated_to_the_current_schema() {
+        let dir = crate::test_dir::TestDir::new("file_info_migration");
+
+        // Version 3: entries with the compressed flag and the lists per file
+        let v3 = StateV3 {
+            entries: vec![EntryV3 { source_batch: 0, target_batch: 0, cumulative_nb_lists: 7, nb_lists_in_file: 7,
+                filename: entry(0, 0, 7).filename, compacted: false, compressed: true, exists: Some(true),
+                file_size_bytes: Some(512), modified_timestamp: Some(1_700_000_000) }],
+            max_lists_per_file: Some(1000),
+        };
+        let v3_path = dir.join("v3.rkyv");
+        let mut bytes = b"NSLSTAT3".to_vec();
+        bytes.extend_from_slice(&rkyv::to_bytes::<_, 256>(&v3).expect("serialize"));
+        fs::write(&v3_path, bytes).expect("write");
+        assert_eq!(state_schema_version(&v3_path).expect("version"), 3);
+        let migrated = GlobalFileInfo::load_rkyv(&v3_path).expect("load v3");
+        let expected = GlobalFileInfo { max_lists_per_file: Some(1000), ..GlobalFileInfo::new(vec![entry(0, 0, 7)]) };
+        assert_eq!(migrated, expected);
+
+        // Version 1: no header, no compressed flag
+        let v1 = vec![EntryV1 { source_batch: 0, target_batch: 0, cumulative_nb_lists: 7, nb_lists_in_file: 7,
+            filename: entry(0, 0, 7).filename, compacted: false, exists: Some(true),
+            file_size_bytes: Some(512), modified_timestamp: Some(1_700_000_000) }];
+        let v1_path = dir.join("v1.rkyv");
+        fs::write(&v1_path, rkyv::to_bytes::<_, 256>(&v1).expect("serialize")).expect("write");
+        assert_eq!(state_schema_version(&v1_path).expect("version"), 1);
+        let migrated = GlobalFileInfo::load_rkyv(&v1_path).expect("load v1");
+        let expected = GlobalFileInfo::new(vec![FileInfo { compressed: false, ..entry(0, 0, 7) }]);
+        assert_eq!(migrated, expected);
+
+        // A migrated state is saved in the current schema
+        migrated.save_rkyv(&v1_path).expect("save");
+        assert_eq!(state_schema_version(&v1_path).expect("version"), STATE_SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn states_of_a_newer_schema_are_refused() {
+        let dir = crate::test_dir::TestDir::new("file_info_newer");
+        let path = dir.join("nsl_05_global_info.rkyv");
+        let mut bytes = format!("NSLSTAT{}", STATE_SCHEMA_VERSION + 1).into_bytes();
+        bytes.extend_from_slice(&rkyv::to_bytes::<_, 256>(&full_state()).expect("serialize"));
+        fs::write(&path, bytes).expect("write");
+        let error = GlobalFileInfo::load_rkyv(&path).expect_err("newer schema");
+        assert!(error.to_string().contains("newer version"), "{}", error);
+
+        let json_path = dir.join("nsl_05_global_info.json");
+        let newer = GlobalFileInfo { schema_version: STATE_SCHEMA_VERSION + 1, ..full_state() };
+        fs::write(&json_path, serde_json::to_string(&newer).expect("json")).expect("write");
+        assert!(GlobalFileInfo::load_json(&json_path).is_err());
+    }
+
+    #[test]
+    fn throttled_flushes_write_the_state_every_n_files() {
+        let dir = crate::test_dir::TestDir::new("file_info_flush_every");
+        let mut state = GlobalFileState::new(&dir.str(), 5);
+        let path = dir.join("nsl_05_global_info.rkyv");
+        let flushed_entries = || GlobalFileInfo::load_rkyv(&path).map(|gfi| gfi.entries.len()).unwrap_or(0);
+        for batch in 0..3 {
+            state.register_file(&entry(0, batch, 7).filename, 0, batch, 7, false, None, None);
+            state.flush_throttled_every(2).expect("flush");
+        }
+        assert_eq!(flushed_entries(), 2, "flushed after the second file only");
+
+        state.flush_pending().expect("flush pending");
+        assert_eq!(flushed_entries(), 3);
+        fs::remove_file(&path).expect("remove");
+        state.flush_pending().expect("nothing pending");
+        assert!(!path.exists(), "no flush without changes since the last one");
+    }
+
+    #[test]
+    fn cumulative_counts_follow_the_changes_of_the_entries() {
+        let mut state = GlobalFileState::new("unused", 5);
+        let cumulative = |state: &GlobalFileState| -> Vec<(u32, u64)> {
+            state.to_vec().iter().map(|e| (e.target_batch, e.cumulative_nb_lists)).collect()
+        };
+        for (batch, nb_lists) in [(2, 5), (0, 7), (1, 3)] {
+            state.register_file(&entry(0, batch, nb_lists).filename, 0, batch, nb_lists, false, None, None);
+        }
+        assert_eq!(cumulative(&state), vec![(0, 7), (1, 10), (2, 15)]);
+
+        state.update_count(&entry(0, 1, 3).filename, 0, 1, 4);
+        state.remove_file(&entry(0, 0, 7).filename, 0, 0, RemovalReason::Pruned);
+        assert_eq!(cumulative(&state), vec![(1, 4), (2, 9)]);
+
+        // A file appended to by a later source batch replaces its older entry
+        let appended = entry(0, 2, 5).filename;
+        state.register_file(&appended, 1, 2, 8, false, None, None);
+        assert_eq!(cumulative(&state), vec![(1, 4), (2, 12)]);
+        assert_eq!(state.entries().len(), 2);
+    }
+
+    #[test]
+    fn loaded_states_are_self_checked_and_fixed() {
+        let dir = crate::test_dir::TestDir::new("file_info_self_check");
+        let lists = vec![crate::no_set_list::NoSetListSerialized { n: 3, max_card: 9, no_set_list: vec![0, 5, 9], remaining_cards_list: vec![] }; 2];
+        assert!(crate::io_helpers::save_to_file_serialized(&lists, &dir.join(entry(3, 3, 0).filename).to_string_lossy()));
+        let wrong_size = "nsl_05_batch_000000_to_06_batch_000000.rkyv".to_string();
+        let gfi = GlobalFileInfo::new(vec![
+            entry(0, 0, 7),
+            FileInfo { target_batch: 1, ..entry(0, 0, 7) }, // duplicate filename
+            FileInfo { target_batch: 2, ..entry(1, 1, 4) }, // batches not matching the name
+            FileInfo { filename: wrong_size.clone(), ..entry(4, 4, 1) }, // file of size 06
+            FileInfo { file_size_bytes: Some(0), ..entry(2, 2, 5) }, // 0 bytes, not on disk
+            FileInfo { nb_lists_in_file: 0, ..entry(3, 3, 0) }, // 2 lists on disk
+        ]);
+        let mut state = GlobalFileState::from_info(&dir.str(), 5, gfi);
+        let issues = state.self_check();
+        assert_eq!(issues, vec![
+            StateIssue::DuplicateFilename { filename: entry(0, 0, 7).filename, batches: vec![(0, 0), (0, 1)] },
+            StateIssue::BatchMismatch { filename: entry(0, 0, 7).filename, recorded: (0, 1), in_name: (0, 0) },
+            StateIssue::BatchMismatch { filename: entry(1, 1, 4).filename, recorded: (1, 2), in_name: (1, 1) },
+            StateIssue::EmptyFile { filename: entry(2, 2, 5).filename, batches: (2, 2) },
+            StateIssue::ZeroCount { filename: entry(3, 3, 0).filename, batches: (3, 3) },
+            StateIssue::WrongSize { filename: wrong_size.clone(), size_in_name: 6 },
+        ]);
+
+        state.report_issues(false);
+        assert_eq!(state.self_check(), issues, "only reported without --fix-state-on-load");
+        state.report_issues(true);
+        assert_eq!(state.self_check(), vec![StateIssue::WrongSize { filename: wrong_size, size_in_name: 6 }]);
+        let entries: Vec<(u32, u32, u64)> = state.to_vec().iter()
+            .map(|e| (e.source_batch, e.target_batch, e.nb_lists_in_file)).collect();
+        assert_eq!(entries, vec![(0, 0, 7), (1, 1, 4), (3, 3, 2), (4, 4, 1)]);
+    }
+
+    #[test]
+    fn csv_rows_hold_every_field_with_quoted_text() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("rack \"3\""), "\"rack \"\"3\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+
+        let mut gfi = full_state();
+        gfi.entries[0].provenance.as_mut().unwrap().hostname = "lab, rack \"3\"".to_string();
+        let state = GlobalFileState::from_info("unused", 5, gfi);
+        let csv = state.to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].split(',').count(), 14);
+        assert_eq!(lines[1], format!("0,0,7,7,{},false,true,true,512,1700000000,\"lab, rack \"\"3\"\"\",0.4.14,1234,crc32:0a1b2c3d",
+            entry(0, 0, 7).filename));
+        assert_eq!(lines[2], format!("1,1,16,9,{},true,true,true,512,1700000000,,,,", entry(1, 1, 9).filename));
+    }
+
+    #[test]
+    fn consumed_inputs_are_tracked_by_batch_and_kind() {
+        let mut state = GlobalFileState::new("unused", 5);
+        let compacted = InputKey::whole(3, true);
+        let regular = InputKey::whole(3, false);
+        let older = InputKey::whole(1, false);
+        state.register_file(&entry(1, 0, 7).filename, 1, 0, 7, false, None, None);
+        state.register_file(&entry(3, 1, 7).filename, 3, 1, 7, false, None, None);
+
+        // Nothing recorded: inferred from the outputs (states of version 3 and older)
+        assert!(state.is_input_consumed(older));
+        assert!(state.is_input_consumed(regular));
+
+        // Compacted batch 3 consumed, then interrupted before the regular batch 3
+        state.record_consumed_input(4, compacted, 7);
+        assert!(state.is_input_consumed(compacted));
+        assert!(!state.is_input_consumed(regular), "regular file sharing the batch number of a consumed one");
+        assert!(state.is_input_consumed(older));
+        assert_eq!(state.consumed_batches(), BTreeSet::from([3]));
+        assert_eq!(state.last_consumed_batch(), Some(3));
+    }
+
+    #[test]
+    fn restarts_skip_consumed_inputs_of_both_numberings() {
+        let mut state = GlobalFileState::new("unused", 5);
+        state.record_consumed_input(4, InputKey::whole(0, false), 7);
+        state.record_consumed_input(4, InputKey::whole(1, false), 7);
+        state.record_consumed_input(4, InputKey::whole(8, true), 7);
+        assert_eq!(state.last_consumed_batch(), Some(8));
+        assert_eq!(state.last_consumed_regular_batch(), Some(1), "compacted batch 8 is not regular batch 8");
+
+        let input = |batch, compacted| crate::filenames::InputFile { batch, path: format!("{}{}", batch, compacted), compacted };
+        let inputs = || vec![input(0, false), input(1, false), input(2, false), input(5, true), input(8, true), input(9, false)];
+        let left = |start_batch| -> Vec<(u32, bool)> {
+            state.split_consumed_inputs(inputs(), None, start_batch).1.iter().map(|f| (f.batch, f.compacted)).collect()
+        };
+        assert_eq!(left(None), vec![(2, false), (5, true), (9, false)]);
+        assert_eq!(left(Some(2)), vec![(2, false), (5, true), (9, false)], "compacted 5 is kept below regular batch 2");
+        assert_eq!(left(Some(9)), vec![(5, true), (9, false)]);
+
+        // States of version 3 and older: inferred from the outputs
+        let mut older = GlobalFileState::new("unused", 5);
+        older.register_file(&entry(4, 0, 7).filename, 4, 0, 7, false, None, None);
+        assert_eq!(older.last_consumed_regular_batch(), Some(4));
+    }
+
+    #[test]
+    fn a_sharded_input_is_consumed_once_every_shard_is_recorded() {
+        let mut state = GlobalFileState::new("unused", 5);
+        let shard = |index| Some(Shard { index, count: 3 });
+        let whole = InputKey::whole(2, false);
+        state.record_consumed_input(4, InputKey { shard: shard(0), ..whole }, 7);
+        state.register_file(&crate::filenames::sharded_filename(&entry(2, 0, 7).filename, &Shard { index: 1, count: 3 }),
+            2, 0, 7, false, None, None); // shard 1 interrupted after its first output
+
+        assert!(state.is_input_consumed(InputKey { shard: shard(0), ..whole }));
+        assert!(!state.is_input_consumed(InputKey { shard: shard(1), ..whole }), "other shards still to run");
+        assert!(!state.is_input_consumed(whole));
+        assert!(state.consumed_batches().is_empty());
+
+        state.record_consumed_input(4, InputKey { shard: shard(1), ..whole }, 7);
+        state.record_consumed_input(4, InputKey { shard: shard(2), ..whole }, 7);
+        assert!(state.is_input_consumed(whole));
+        assert_eq!(state.consumed_batches(), BTreeSet::from([2]));
+    }
+
+    #[test]
+    fn backups_are_taken_without_moving_the_state_away() {
+        let dir = crate::test_dir::TestDir::new("file_info_backups");
+        let path = dir.join("nsl_05_global_info.rkyv");
+        let last = dir.join("nsl_05_global_info.rkyv.old");
+        fs::write(&path, b"first").expect("write");
+        rotate_backups(&path, 2).expect("rotate");
+        // Until the new state is renamed over it, the state is still in place
+        assert_eq!(fs::read(&path).expect("state"), b"first");
+        assert_eq!(fs::read(&last).expect("backup"), b"first");
+
+        let tmp = dir.join("nsl_05_global_info.rkyv.tmp");
+        fs::write(&tmp, b"second").expect("write");
+        fs::rename(&tmp, &path).expect("rename");
+        assert_eq!(fs::read(&last).expect("backup"), b"first", "the backup is not the replaced file");
+        rotate_backups(&path, 2).expect("rotate");
+        assert_eq!(fs::read(&last).expect("backup"), b"second");
+        assert_eq!(state_backups(&path).len(), 2);
+    }
+
+    #[test]
+    fn a_state_locked_by_another_run_is_refused() {
+        let dir = crate::test_dir::TestDir::new("file_info_lock_held");
+        let path = lock_path(&dir.str(), 5);
+        fs::write(&path, "4242 another-machine").expect("lock");
+
+        let error = acquire_state_lock(&dir.str(), 5).expect_err("held by another run");
+        assert!(is_state_locked(&error));
+        assert!(error.to_string().contains("PID 4242 on another-machine"));
+        assert_eq!(fs::read_to_string(&path).expect("lock"), "4242 another-machine", "the lock is left alone");
+    }
+
+    #[test]
+    fn a_stale_lock_is_taken_over_and_released() {
+        let dir = crate::test_dir::TestDir::new("file_info_lock_stale");
+        let path = lock_path(&dir.str(), 5);
+        let owner = format!("{} {}", std::process::id(), hostname());
+        fs::write(&path, format!("{} {}", i32::MAX, hostname())).expect("lock of a dead process");
+
+        acquire_state_lock(&dir.str(), 5).expect("stale lock taken over");
+        assert_eq!(fs::read_to_string(&path).expect("lock"), owner);
+        let leftovers = fs::read_dir(&dir).expect("dir").filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_string_lossy().contains(".stale.")).count();
+        assert_eq!(leftovers, 0, "the stale lock moved aside is removed");
+        acquire_state_lock(&dir.str(), 5).expect("already held");
+
+        release_state_locks();
+        assert!(!path.exists());
+        // A lock retaken by another run after ours was deleted by hand is not released
+        acquire_state_lock(&dir.str(), 5).expect("lock");
+        fs::write(&path, "4242 another-machine").expect("retaken");
+        release_state_locks();
+        assert_eq!(fs::read_to_string(&path).expect("lock"), "4242 another-machine");
+    }
+}
@@ -7,13 +7,18 @@
 //! - Consistent 6-digit batch numbering
 //! - Pattern-based file search with compacted file preference
 //! - Next available batch number detection
-//! - Last compacted batch detection for smart processing
+//! - Input plan listing every compacted and regular input file exactly once
+//! - Shard tags for partitioned runs (--shard K/M)
+//! - Cascade directory layout (11_to_12, 12_to_13c, then {n-1}c_to_{n}c)
 //!
 //! Filename format: nsl_{source_size:02}_batch_{source_batch:06}_to_{target_size:02}_batch_{target_batch:06}.rkyv
 //! Compacted format: Same as above with _compacted.rkyv suffix
+//! Sharded format: Same as above with _shard_{K:02}of{M:02}.rkyv suffix (read as input like
+//! the others: the shards of a size written into one directory are numbered apart)
 
 use std::path::Path;
-use std::fs;
+use rkyv::{Archive, Serialize as RkyvSerialize, Deserialize as RkyvDeserialize};
+use serde::{Deserialize, Serialize};
 
 /// Generate output filename with pattern:
 /// nsl_{source_size:02}_batch_{source_batch:06}_to_{target_size:02}_batch_{target_batch:06}.rkyv
@@ -37,6 +42,95 @@ pub fn output_filename(
     path.to_string_lossy().to_string()
 }
 
+/// Partition of the input lists by max_card: shard K of M holds the lists with max_card % M == K
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, Archive, RkyvSerialize, RkyvDeserialize)]
+#[archive(check_bytes)]
+pub struct Shard {
+    pub index: u32,
+    pub count: u32,
+}
+
+impl Shard {
+    /// Parse "K/M" (0 <= K < M)
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (k, m) = text.split_once('/')
+            .ok_or_else(|| format!("Invalid shard '{}': expected K/M (e.g. 0/4)", text))?;
+        let index: u32 = k.trim().parse().map_err(|_| format!("Invalid shard index '{}'", k))?;
+        let count: u32 = m.trim().parse().map_err(|_| format!("Invalid shard count '{}'", m))?;
+        if count == 0 || index >= count {
+            return Err(format!("Invalid shard '{}': K must be between 0 and M-1", text));
+        }
+        Ok(Shard { index, count })
+    }
+
+    /// True if a list with this max_card belongs to the shard
+    pub fn contains(&self, max_card: usize) -> bool {
+        max_card % self.count as usize == self.index as usize
+    }
+
+    /// Filename tag inserted before the .rkyv extension
+    pub fn tag(&self) -> String {
+        format!("_shard_{:02}of{:02}", self.index, self.count)
+    }
+}
+
+/// Insert the shard tag in an output filename (before the .rkyv extension)
+pub fn sharded_filename(filename: &str, shard: &Shard) -> String {
+    match filename.strip_suffix(".rkyv") {
+        Some(stem) => format!("{}{}.rkyv", stem, shard.tag()),
+        None => format!("{}{}", filename, shard.tag()),
+    }
+}
+
+/// Remove a trailing shard tag (_shard_KKofMM) from a filename stem, if any
+pub fn strip_shard_tag(stem: &str) -> &str {
+    match shard_tag_of(stem) {
+        Some((pos, _)) => &stem[..pos],
+        None => stem,
+    }
+}
+
+/// Shard of a filename or stem tagged _shard_KKofMM (None: untagged)
+pub fn shard_of(name: &str) -> Option<Shard> {
+    shard_tag_of(name.strip_suffix(".rkyv").unwrap_or(name)).map(|(_, shard)| shard)
+}
+
+/// Position and shard of the trailing shard tag of `stem`
+fn shard_tag_of(stem: &str) -> Option<(usize, Shard)> {
+    let pos = stem.rfind("_shard_")?;
+    let (k, m) = stem[pos + 7..].split_once("of")?;
+    Some((pos, Shard { index: k.parse().ok()?, count: m.parse().ok()? }))
+}
+
+/// Batches encoded in a list filename
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParsedFilename {
+    pub source_size: u8,
+    pub source_batch: u32,
+    pub target_size: u8,
+    pub target_batch: u32,
+    pub compacted: bool,
+}
+
+/// Parse nsl_{src:02}_batch_{src_batch}_to_{tgt:02}_batch_{tgt_batch}[_compacted][_shard_KKofMM].rkyv
+pub fn parse_filename(name: &str) -> Option<ParsedFilename> {
+    let stem = strip_shard_tag(name.strip_prefix("nsl_")?.strip_suffix(".rkyv")?);
+    let (stem, compacted) = match stem.strip_suffix("_compacted") {
+        Some(s) => (s, true),
+        None => (stem, false),
+    };
+    let (source, target) = stem.split_once("_to_")?;
+    let (source_size, source_batch) = source.split_once("_batch_")?;
+    let (target_size, target_batch) = target.split_once("_batch_")?;
+    Some(ParsedFilename {
+        source_size: source_size.parse().ok()?,
+        source_batch: source_batch.parse().ok()?,
+        target_size: target_size.parse().ok()?,
+        target_batch: target_batch.parse().ok()?,
+        compacted,
+    })
+}
+
 /// Find input filename for reading by matching the pattern
 /// *_to_{input_size}_batch_{target_batch}.rkyv or *_to_{input_size}_batch_{target_batch}_compacted.rkyv
 /// Returns the full path. Prefers compacted files when both exist.
@@ -51,8 +145,8 @@ pub fn find_input_filename(base_path: &str, input_size: u8, target_batch: u32) -
     crate::utils::test_print(&format!("   ... looking for input file matching: *{} or *{} in {}", 
         pattern_regular, pattern_compacted, base_path));
 
-    let entries = match fs::read_dir(base_path) {
-        Ok(e) => e,
+    let names = match crate::storage::list_file_names(base_path) {
+        Ok(names) => names,
         Err(err) => {
             crate::utils::debug_print(&format!("   ... ERROR: Cannot read directory {}: {}", base_path, err));
             return None;
@@ -62,15 +156,14 @@ pub fn find_input_filename(base_path: &str, input_size: u8, target_batch: u32) -
     let mut found_regular: Option<String> = None;
     let mut found_compacted: Option<String> = None;
 
-    for entry in entries.flatten() {
-        if let Some(name) = entry.file_name().to_str() {
-            if name.starts_with("nsl_") && name.ends_with(&pattern_compacted) {
-                found_compacted = Some(entry.path().to_string_lossy().to_string());
-                crate::utils::debug_print(&format!("   ... found compacted: {}", name));
-            } else if name.starts_with("nsl_") && name.ends_with(&pattern_regular) {
-                found_regular = Some(entry.path().to_string_lossy().to_string());
-                crate::utils::debug_print(&format!("   ... found regular: {}", name));
-            }
+    for name in names.iter() {
+        let path = Path::new(base_path).join(name).to_string_lossy().to_string();
+        if name.ends_with(&pattern_compacted) {
+            found_compacted = Some(path);
+            crate::utils::debug_print(&format!("   ... found compacted: {}", name));
+        } else if name.ends_with(&pattern_regular) {
+            found_regular = Some(path);
+            crate::utils::debug_print(&format!("   ... found regular: {}", name));
         }
     }
 
@@ -82,6 +175,13 @@ pub fn find_input_filename(base_path: &str, input_size: u8, target_batch: u32) -
         return Some(path);
     }
 
+    // Files written by older versions are read on the fly (see migrate)
+    if let Some(legacy) = crate::migrate::legacy_input_files(base_path, input_size).into_iter()
+        .find(|f| f.batch == target_batch) {
+        crate::utils::test_print(&format!("   ... found legacy file: {}", legacy.path));
+        return Some(legacy.path);
+    }
+
     crate::utils::test_print("   ... no matching file found");
     None
 }
@@ -89,33 +189,30 @@ pub fn find_input_filename(base_path: &str, input_size: u8, target_batch: u32) -
 /// Get next available output batch number by scanning filenames only.
 /// Only considers files whose source batch is < `restart_batch`.
 pub fn get_next_output_batch_from_files(base_path: &str, target_size: u8, restart_batch: u32) -> u32 {
-    let entries = match fs::read_dir(base_path) {
-        Ok(e) => e,
+    let names = match crate::storage::list_file_names(base_path) {
+        Ok(names) => names,
         Err(_) => return 0, // Directory doesn't exist, start from batch 0
     };
 
     let pattern_prefix = format!("_to_{:02}_batch_", target_size);
     let mut max_target_batch: Option<u32> = None;
 
-    for entry in entries.flatten() {
-        if let Some(name) = entry.file_name().to_str() {
-            if name.starts_with("nsl_") && name.contains(&pattern_prefix) && name.ends_with(".rkyv") {
-                if let Some(to_pos) = name.find("_to_") {
-                    let before_to = &name[..to_pos];
-                    if let Some(batch_pos) = before_to.rfind("_batch_") {
-                        let batch_str = &before_to[batch_pos + 7..];
-                        if let Ok(source_batch_num) = batch_str.parse::<u32>() {
-                            if source_batch_num < restart_batch {
-                                let after_to = &name[to_pos + 4..];
-                                if let Some(target_batch_pos) = after_to.rfind("_batch_") {
-                                    let target_batch_str = &after_to[target_batch_pos + 7..after_to.len() - 5];
-                                    if let Ok(target_batch_num) = target_batch_str.parse::<u32>() {
-                                        max_target_batch = Some(
-                                            max_target_batch.map_or(target_batch_num, |current_max| current_max.max(target_batch_num))
-                                        );
-                                    }
-                                }
-                            }
+    for name in names.iter().filter(|n| n.contains(&pattern_prefix)) {
+        if let Some(to_pos) = name.find("_to_") {
+            let before_to = &name[..to_pos];
+            if let Some(batch_pos) = before_to.rfind("_batch_") {
+                let batch_str = &before_to[batch_pos + 7..];
+                if let Ok(source_batch_num) = batch_str.parse::<u32>() && source_batch_num < restart_batch {
+                    let after_to = &name[to_pos + 4..];
+                    if let Some(target_batch_pos) = after_to.rfind("_batch_") {
+                        // Compacted and sharded outputs also count: new batches must not reuse their numbers
+                        let target_batch_str = strip_shard_tag(after_to[target_batch_pos + 7..]
+                            .trim_end_matches(".rkyv"))
+                            .trim_end_matches("_compacted");
+                        if let Ok(target_batch_num) = target_batch_str.parse::<u32>() {
+                            max_target_batch = Some(
+                                max_target_batch.map_or(target_batch_num, |current_max| current_max.max(target_batch_num))
+                            );
                         }
                     }
                 }
@@ -128,43 +225,144 @@ pub fn get_next_output_batch_from_files(base_path: &str, target_size: u8, restar
     next_batch
 }
 
-/// Find the highest target batch number among compacted files for a given size.
-/// Returns None if no compacted files are found.
-/// This is useful to determine up to which batch we should process when avoiding non-compacted files.
-pub fn get_last_compacted_batch(base_path: &str, target_size: u8) -> Option<u32> {
-    let entries = match fs::read_dir(base_path) {
-        Ok(e) => e,
-        Err(_) => return None,
+/// Input file found on disk, identified by the target batch number in its name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputFile {
+    pub batch: u32,         // target batch number of the file (= input batch number)
+    pub path: String,       // full path of the file
+    pub compacted: bool,    // true for *_compacted.rkyv files
+}
+
+/// List all input files holding lists of `input_size`, compacted, regular and sharded alike.
+///
+/// Unlike `find_input_filename`, a regular file is never shadowed by a compacted file
+/// sharing its batch number: both are returned, so every list on disk is planned once.
+/// Sorted by batch number, compacted file first when a batch number is shared. Since
+/// compaction numbers its files from 0 upwards, compacted inputs come first and the
+/// regular files not yet merged into them follow.
+pub fn list_input_files(base_path: &str, input_size: u8) -> Vec<InputFile> {
+    let names = match crate::storage::list_file_names(base_path) {
+        Ok(names) => names,
+        Err(err) => {
+            crate::utils::debug_print(&format!("list_input_files: Cannot read directory {}: {}", base_path, err));
+            return Vec::new();
+        }
     };
 
-    let pattern_prefix = format!("_to_{:02}_batch_", target_size);
-    let mut max_compacted_batch: Option<u32> = None;
+    let pattern_prefix = format!("_to_{:02}_batch_", input_size);
+    let mut files: Vec<InputFile> = Vec::new();
 
-    for entry in entries.flatten() {
-        if let Some(name) = entry.file_name().to_str() {
-            // Only look at compacted files
-            if name.starts_with("nsl_") && name.contains(&pattern_prefix) && name.ends_with("_compacted.rkyv") {
-                if let Some(to_pos) = name.find("_to_") {
-                    let after_to = &name[to_pos + 4..];
-                    if let Some(target_batch_pos) = after_to.rfind("_batch_") {
-                        // Extract batch number: skip "_batch_" and remove "_compacted.rkyv"
-                        let target_batch_str = &after_to[target_batch_pos + 7..after_to.len() - 15]; // 15 = "_compacted.rkyv".len()
-                        if let Ok(target_batch_num) = target_batch_str.parse::<u32>() {
-                            max_compacted_batch = Some(
-                                max_compacted_batch.map_or(target_batch_num, |current_max| current_max.max(target_batch_num))
-                            );
-                        }
-                    }
-                }
+    for name in names.iter() {
+        if let Some(pos) = name.find(&pattern_prefix) {
+            let after = strip_shard_tag(name[pos + pattern_prefix.len()..].trim_end_matches(".rkyv"));
+            let (batch_str, compacted) = match after.strip_suffix("_compacted") {
+                Some(b) => (b, true),
+                None => (after, false),
+            };
+            if let Ok(batch) = batch_str.parse::<u32>() {
+                files.push(InputFile {
+                    batch,
+                    path: Path::new(base_path).join(name).to_string_lossy().to_string(),
+                    compacted,
+                });
             }
         }
     }
 
-    if let Some(max) = max_compacted_batch {
-        crate::utils::debug_print(&format!("get_last_compacted_batch: highest compacted batch for size {:02} = {:06}", target_size, max));
-    } else {
-        crate::utils::debug_print(&format!("get_last_compacted_batch: no compacted files found for size {:02}", target_size));
+    files.sort_by(|a, b| a.batch.cmp(&b.batch).then(b.compacted.cmp(&a.compacted)));
+    files
+}
+
+/// `list_input_files` plus the legacy files (nlist_*.bin / nlist_*.rkyv, see migrate)
+/// of the batches that have no file in the current format, read on the fly by size mode
+/// instead of requiring a --migrate first.
+pub fn list_input_files_with_legacy(base_path: &str, input_size: u8) -> Vec<InputFile> {
+    let mut files = list_input_files(base_path, input_size);
+    let legacy: Vec<InputFile> = crate::migrate::legacy_input_files(base_path, input_size).into_iter()
+        .filter(|legacy| !files.iter().any(|f| f.batch == legacy.batch))
+        .map(|legacy| InputFile { batch: legacy.batch, path: legacy.path, compacted: false })
+        .collect();
+    files.extend(legacy);
+    files.sort_by(|a, b| a.batch.cmp(&b.batch).then(b.compacted.cmp(&a.compacted)));
+    files
+}
+
+/// Name of the cascade directory holding the lists of `size`: {size-1}_to_{size}
+/// up to 12 (seeds: 2_to_3), 12_to_13c for 13, {size-1}c_to_{size}c from 14
+pub fn cascade_directory_name(size: u8) -> String {
+    match size {
+        ..=12 => format!("{}_to_{}", size - 1, size),
+        13 => "12_to_13c".to_string(),
+        _ => format!("{}c_to_{}c", size - 1, size),
+    }
+}
+
+/// Get directory path for a given size in cascade mode
+/// Returns (input_dir, output_dir) for the given input size
+/// Sizes up to 12 are in {size-1}_to_{size} (seeds: 2_to_3), compacted sizes in
+/// {size-1}c_to_{size}c
+pub fn get_cascade_directories(root_directory: &str, input_size: u8) -> (String, String) {
+    let input_dir = Path::new(root_directory).join(cascade_directory_name(input_size));
+    let output_dir = Path::new(root_directory).join(cascade_directory_name(input_size + 1));
+    (
+        input_dir.to_string_lossy().to_string(),
+        output_dir.to_string_lossy().to_string()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filenames_are_parsed() {
+        let parsed = parse_filename("nsl_13_batch_000012_to_14_batch_000345_compacted.rkyv").expect("parse");
+        assert_eq!((parsed.source_size, parsed.source_batch, parsed.target_size, parsed.target_batch, parsed.compacted),
+            (13, 12, 14, 345, true));
+        assert_eq!(parse_filename("nsl_03_batch_000000_to_04_batch_000001_shard_01of04.rkyv").map(|p| p.target_batch), Some(1));
+        assert_eq!(parse_filename("nsl_04_global_info.rkyv"), None);
+    }
+
+    #[test]
+    fn cascade_directories_cover_every_size() {
+        let dirs = |input_size| {
+            let (input, output) = get_cascade_directories("root", input_size);
+            (input.replace('\\', "/"), output.replace('\\', "/"))
+        };
+        assert_eq!(dirs(3), ("root/2_to_3".to_string(), "root/3_to_4".to_string()));
+        assert_eq!(dirs(11), ("root/10_to_11".to_string(), "root/11_to_12".to_string()));
+        assert_eq!(dirs(12), ("root/11_to_12".to_string(), "root/12_to_13c".to_string()));
+        assert_eq!(dirs(13), ("root/12_to_13c".to_string(), "root/13c_to_14c".to_string()));
+        // Each size is written where the next one reads it
+        for input_size in 3..19 {
+            assert_eq!(dirs(input_size).1, dirs(input_size + 1).0);
+        }
+    }
+
+    #[test]
+    fn shard_outputs_are_listed_as_inputs() {
+        let dir = crate::test_dir::TestDir::new("filenames_shards");
+        for name in ["nsl_04_batch_000000_to_05_batch_000000_shard_00of02.rkyv",
+                     "nsl_04_batch_000000_to_05_batch_000001_shard_01of02.rkyv",
+                     "nsl_04_batch_000001_to_05_batch_000000_compacted_shard_00of02.rkyv"] {
+            std::fs::write(dir.join(name), b"").expect("write");
+        }
+        let files = list_input_files(&dir.to_string_lossy(), 5);
+        let planned: Vec<(u32, bool)> = files.iter().map(|f| (f.batch, f.compacted)).collect();
+        assert_eq!(planned, vec![(0, true), (0, false), (1, false)]);
+    }
+
+    #[test]
+    fn shard_tags_round_trip() {
+        let shard = Shard::parse("1/4").expect("valid shard");
+        assert!(shard.contains(5) && !shard.contains(6));
+        assert!(Shard::parse("4/4").is_err() && Shard::parse("1-4").is_err());
+
+        let name = sharded_filename("nsl_05_batch_000003_to_06_batch_000007.rkyv", &shard);
+        assert_eq!(name, "nsl_05_batch_000003_to_06_batch_000007_shard_01of04.rkyv");
+        assert_eq!(strip_shard_tag("06_batch_000007_shard_01of04"), "06_batch_000007");
+        assert_eq!(strip_shard_tag("06_batch_000007"), "06_batch_000007");
+        assert_eq!(shard_of(&name), Some(shard));
+        assert_eq!(shard_of("nsl_05_batch_000003_to_06_batch_000007.rkyv"), None);
     }
-    
-    max_compacted_batch
 }
@@ -0,0 +1,132 @@
+//! Target filter module: drop the lists of a size that cannot reach a target size
+//!
+//! At sizes 16+ most stored lists can never grow to 20 cards. Rewriting a size's
+//! files with only the lists that still can makes every downstream step smaller.
+//!
+//! Key features:
+//! - A list of n cards is kept when n + extension_upper_bound(cards, remaining)
+//!   reaches the target (the bound is never above the remaining count, and sound)
+//! - Files rewritten in place (tmp + rename), in the selected output encoding
+//! - Global state entries updated with the new counts, sizes and mtimes
+//! - Files left empty are kept, so batch numbering stays continuous for --check
+//!
+//! Note: after filtering, the counts of this size and of every size computed from
+//! it no longer match the reference counts (only the target is fully explored).
+//!
+//! Used by --filter-target mode
+
+use std::path::Path;
+use separator::Separatable;
+
+use crate::file_info::GlobalFileState;
+use crate::no_set_list::{extension_upper_bound, NoSetListSerialized};
+use crate::utils::*;
+
+/// Result of filtering one size
+#[derive(Debug, Clone)]
+pub struct FilterReport {
+    pub target: u8,
+    pub size: u8,
+    pub files_checked: u64,
+    pub files_rewritten: u64,
+    pub lists_before: u64,
+    pub lists_after: u64,
+}
+
+/// True if the list may still be extended to `target` cards
+pub fn can_reach_target(list: &NoSetListSerialized, target: u8) -> bool {
+    list.no_set_list.len() + extension_upper_bound(&list.no_set_list, &list.remaining_cards_list) >= target as usize
+}
+
+/// Rewrite every file of `size` in `base_path`, keeping only the lists that can reach `target`
+pub fn filter_size_files(base_path: &str, size: u8, target: u8) -> std::io::Result<FilterReport> {
+    test_print(&format!("\nFILTER MODE: Keeping size {:02} lists that can reach {} cards...", size, target));
+    test_print(&format!("   Directory: {}", base_path));
+    let start_time = std::time::Instant::now();
+
+    let files = crate::filenames::list_input_files(base_path, size);
+    if files.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound,
+            format!("No size {:02} files found in {}", size, base_path)));
+    }
+    let mut state = GlobalFileState::from_sources(base_path, size)?;
+
+    let mut report = FilterReport {
+        target,
+        size,
+        files_checked: 0,
+        files_rewritten: 0,
+        lists_before: 0,
+        lists_after: 0,
+    };
+
+    for file in files.iter() {
+        let name = Path::new(&file.path).file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let lists = crate::io_helpers::load_lists_from_file(&file.path)?;
+        let before = lists.len() as u64;
+        let kept: Vec<NoSetListSerialized> = lists.into_iter().filter(|l| can_reach_target(l, target)).collect();
+        let after = kept.len() as u64;
+        report.files_checked += 1;
+        report.lists_before += before;
+        report.lists_after += after;
+
+        if after == before {
+            test_print(&format!("   ... {:>10} lists kept in {} (unchanged)", before.separated_string(), name));
+            continue;
+        }
+
+        // Written atomically: an interrupted run leaves the original intact
+        if !crate::io_helpers::save_to_file_serialized(&kept, &file.path) {
+            return Err(std::io::Error::other(format!("Cannot write {}", file.path)));
+        }
+        report.files_rewritten += 1;
+        test_print(&format!("   ... {:>10} of {:>10} lists kept in {}",
+            after.separated_string(), before.separated_string(), name));
+
+        // Update the state entry of this file
+        let keys: Vec<(u32, u32, String)> = state.entries().keys()
+            .filter(|(_, _, filename)| *filename == name)
+            .cloned()
+            .collect();
+        if keys.is_empty() {
+            test_print(&format!("   ... WARNING: {} is not recorded in the global state (run --count {})", name, size));
+        }
+        let metadata = std::fs::metadata(crate::storage::resolve_path(&file.path)).ok();
+        let file_size = metadata.as_ref().map(|m| m.len());
+        let mtime = metadata.as_ref()
+            .and_then(|m| m.modified().ok())
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64);
+        for (src_batch, tgt_batch, filename) in keys {
+            state.update_entry(&filename, src_batch, tgt_batch, after, file.compacted, file_size, mtime);
+        }
+    }
+
+    if report.files_rewritten > 0 {
+        state.flush()?;
+        state.export_human_readable()?;
+    }
+
+    test_print(&format!("   ... filtered {} lists down to {} in {:.2}s",
+        report.lists_before.separated_string(), report.lists_after.separated_string(),
+        start_time.elapsed().as_secs_f64()));
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_are_kept_only_when_target_is_reachable() {
+        // 3 cards + 2 remaining cards: 6 cards are out of reach
+        let list = NoSetListSerialized {
+            n: 3,
+            max_card: 3,
+            no_set_list: vec![0, 1, 3],
+            remaining_cards_list: vec![4, 5],
+        };
+        assert!(can_reach_target(&list, 4));
+        assert!(!can_reach_target(&list, 6));
+    }
+}
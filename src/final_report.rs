@@ -0,0 +1,306 @@
+//! Final size report module: one consolidated record of a size's computation
+//!
+//! Once a size is finalized, --final-report gathers everything known about it
+//! into a single `nsl_{size:02}_final_report.json`, the citable record of that size.
+//!
+//! Key features:
+//! - Totals from the global state (files, lists, source batches)
+//! - History summary (files ever recorded, including deleted/compacted ones)
+//! - Verification results from the --verify report, when present
+//! - Timing aggregates from the timing records appended by each processing run
+//! - Compaction summary (compacted vs regular files and lists)
+//! - Discovery registry entries and orbit summary, when present
+//! - Comparison of the total with the reference counts (see validate_counts), not
+//!   made for the sizes reduced by --isomorph-cache or pruned by --strong-prune
+//!
+//! Used by --final-report mode; timing records are written by list_of_nsl
+
+use std::io::{BufRead, Write};
+use std::path::Path;
+use separator::Separatable;
+use serde::{Deserialize, Serialize};
+
+use crate::file_info::GlobalFileState;
+use crate::utils::*;
+
+/// Timing of one processing run, appended to nsl_{size:02}_timing.jsonl
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimingRecord {
+    pub timestamp: String,
+    pub lists_created: u64,
+    pub elapsed_secs: f64,
+    pub computation_secs: f64,
+    pub file_io_secs: f64,
+    pub conversion_secs: f64,
+    #[serde(default)]
+    pub isomorph_lookups: u64,      // children looked up in the isomorph cache (--isomorph-cache)
+    #[serde(default)]
+    pub isomorph_hits: u64,         // children dropped as isomorphic
+}
+
+fn timing_path(base_dir: &str, target_size: u8) -> std::path::PathBuf {
+    Path::new(base_dir).join(format!("nsl_{:02}_timing.jsonl", target_size))
+}
+
+/// Append the timing of one processing run (one JSON object per line)
+pub fn append_timing_record(base_dir: &str, target_size: u8, record: &TimingRecord) -> std::io::Result<()> {
+    let line = serde_json::to_string(record)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(timing_path(base_dir, target_size))?;
+    writeln!(file, "{}", line)
+}
+
+/// Load all timing records of a size (unparsable lines are skipped)
+pub fn load_timing_records(base_dir: &str, target_size: u8) -> Vec<TimingRecord> {
+    match std::fs::File::open(timing_path(base_dir, target_size)) {
+        Ok(file) => std::io::BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .filter_map(|line| serde_json::from_str(&line).ok())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Totals {
+    pub files: u64,
+    pub lists: u64,
+    pub source_batches: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HistorySummary {
+    pub files_ever_recorded: u64,
+    pub lists_ever_recorded: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VerificationSummary {
+    pub files_checked: u64,
+    pub lists_checked: u64,
+    pub invalid_lists: u64,
+    pub unreadable_files: u64,
+    pub covers_all_lists: bool,     // lists_checked == current total
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TimingSummary {
+    pub runs: u64,
+    pub lists_created: u64,
+    pub elapsed_secs: f64,
+    pub computation_secs: f64,
+    pub file_io_secs: f64,
+    pub conversion_secs: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CompactionSummary {
+    pub compacted_files: u64,
+    pub lists_in_compacted_files: u64,
+    pub regular_files: u64,
+    pub lists_in_regular_files: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReferenceComparison {
+    pub reference_count: Option<u64>,
+    pub computed_count: u64,
+    pub exhaustive: bool, // false: lists reduced by --isomorph-cache or pruned by --strong-prune
+    pub matches: Option<bool>, // None: no reference count, or not exhaustive
+}
+
+/// Consolidated record of one size (written as nsl_{size:02}_final_report.json)
+#[derive(Debug, Clone, Serialize)]
+pub struct FinalReport {
+    pub size: u8,
+    pub generated_at: String,
+    pub directory: String,
+    pub totals: Totals,
+    pub history: Option<HistorySummary>,
+    pub verification: Option<VerificationSummary>,
+    pub timing: TimingSummary,
+    pub compaction: CompactionSummary,
+    pub discoveries: Vec<serde_json::Value>,
+    pub orbits: Option<serde_json::Value>,
+    pub reference: ReferenceComparison,
+}
+
+/// Read a JSON file written by another mode, if present
+fn read_json(path: &Path) -> Option<serde_json::Value> {
+    let text = std::fs::read_to_string(path).ok()?;
+    serde_json::from_str(&text).ok()
+}
+
+/// Gather everything known about `size` in `base_dir` into a final report
+pub fn build_final_report(base_dir: &str, size: u8) -> std::io::Result<FinalReport> {
+    test_print(&format!("\nFinal report for size {:02}...", size));
+    test_print(&format!("   Directory: {}", base_dir));
+
+    // Totals and compaction summary from the global state
+    let state = GlobalFileState::from_sources(base_dir, size)?;
+    let mut totals = Totals { files: 0, lists: 0, source_batches: 0 };
+    let mut compaction = CompactionSummary {
+        compacted_files: 0, lists_in_compacted_files: 0, regular_files: 0, lists_in_regular_files: 0,
+    };
+    let mut source_batches = std::collections::BTreeSet::new();
+    for info in state.entries().values() {
+        totals.files += 1;
+        totals.lists += info.nb_lists_in_file;
+        source_batches.insert(info.source_batch);
+        if info.compacted {
+            compaction.compacted_files += 1;
+            compaction.lists_in_compacted_files += info.nb_lists_in_file;
+        } else {
+            compaction.regular_files += 1;
+            compaction.lists_in_regular_files += info.nb_lists_in_file;
+        }
+    }
+    totals.source_batches = source_batches.len() as u64;
+    if totals.files == 0 {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound,
+            format!("No size {:02} files recorded in {}", size, base_dir)));
+    }
+    test_print(&format!("   ... state: {} files, {} lists", totals.files, totals.lists.separated_string()));
+
+    // History (optional)
+    let history = GlobalFileState::from_history_file(base_dir, size, "rkyv")
+        .or_else(|_| GlobalFileState::from_history_file(base_dir, size, "json"))
+        .ok()
+        .map(|h| HistorySummary {
+            files_ever_recorded: h.entries().len() as u64,
+            lists_ever_recorded: h.entries().values().map(|e| e.nb_lists_in_file).sum(),
+        });
+
+    // Verification results (optional, from --verify)
+    let verify_path = Path::new(base_dir).join(format!("nsl_{:02}_verify_report.json", size));
+    let verification = read_json(&verify_path).map(|v| {
+        let field = |name: &str| v.get(name).and_then(|x| x.as_u64()).unwrap_or(0);
+        let lists_checked = field("lists_checked");
+        VerificationSummary {
+            files_checked: field("files_checked"),
+            lists_checked,
+            invalid_lists: field("invalid_lists"),
+            unreadable_files: v.get("unreadable_files").and_then(|x| x.as_array()).map_or(0, |a| a.len() as u64),
+            covers_all_lists: lists_checked == totals.lists,
+        }
+    });
+    if verification.is_none() {
+        test_print("   ... no verification report found (run --verify first to include it)");
+    }
+
+    // Timing aggregates
+    let records = load_timing_records(base_dir, size);
+    let timing = TimingSummary {
+        runs: records.len() as u64,
+        lists_created: records.iter().map(|r| r.lists_created).sum(),
+        elapsed_secs: records.iter().fold(0.0, |t, r| t + r.elapsed_secs),
+        computation_secs: records.iter().fold(0.0, |t, r| t + r.computation_secs),
+        file_io_secs: records.iter().fold(0.0, |t, r| t + r.file_io_secs),
+        conversion_secs: records.iter().fold(0.0, |t, r| t + r.conversion_secs),
+    };
+
+    // Discovery registry and orbit summary (optional)
+    let discoveries_path = Path::new(base_dir).join(format!("nsl_{:02}_discoveries.json", size));
+    let discoveries = read_json(&discoveries_path)
+        .and_then(|v| v.as_array().cloned())
+        .unwrap_or_default();
+    let orbits_path = Path::new(base_dir).join(format!("nsl_{:02}_orbits.json", size));
+    let orbits = read_json(&orbits_path).map(|mut v| {
+        // Keep the summary only: the representatives are in the orbits report itself
+        if let Some(obj) = v.as_object_mut() {
+            obj.remove("orbits");
+        }
+        v
+    });
+
+    // Reference count comparison
+    let reference_count = crate::validate_counts::reference_count(size);
+    let exhaustive = !state.isomorph_reduced() && !state.strong_pruned();
+    let reference = ReferenceComparison {
+        reference_count,
+        computed_count: totals.lists,
+        exhaustive,
+        matches: reference_count.filter(|_| exhaustive).map(|c| c == totals.lists),
+    };
+
+    Ok(FinalReport {
+        size,
+        generated_at: chrono::Local::now().to_rfc3339(),
+        directory: base_dir.to_string(),
+        totals,
+        history,
+        verification,
+        timing,
+        compaction,
+        discoveries,
+        orbits,
+        reference,
+    })
+}
+
+/// Print the report summary and save it as nsl_{size:02}_final_report.json
+pub fn save_final_report(base_dir: &str, report: &FinalReport) -> std::io::Result<()> {
+    test_print(&format!("\nSize {:02}: {} lists in {} files ({} compacted)",
+        report.size, report.totals.lists.separated_string(), report.totals.files,
+        report.compaction.compacted_files));
+    match &report.verification {
+        Some(v) => test_print(&format!("   Verification: {} lists checked, {} invalid{}",
+            v.lists_checked.separated_string(), v.invalid_lists,
+            if v.covers_all_lists { "" } else { " (does not cover the current total)" })),
+        None => test_print("   Verification: not run"),
+    }
+    test_print(&format!("   Timing: {} runs, {:.0}s total", report.timing.runs, report.timing.elapsed_secs));
+    match report.reference.matches {
+        Some(true) => test_print("   Reference count: match"),
+        Some(false) => test_print(&format!("   Reference count: MISMATCH (expected {})",
+            report.reference.reference_count.unwrap_or(0).separated_string())),
+        None if !report.reference.exhaustive => test_print("   Reference count: not comparable \
+            (lists reduced by --isomorph-cache or pruned by --strong-prune)"),
+        None => test_print("   Reference count: none published for this size"),
+    }
+
+    let path = Path::new(base_dir).join(format!("nsl_{:02}_final_report.json", report.size));
+    let json = serde_json::to_string_pretty(report)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    std::fs::write(&path, json)?;
+    test_print(&format!("   Report saved: {}", path.display()));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_timings_and_verification_are_consolidated() {
+        let dir = crate::test_dir::TestDir::new("final_report");
+        let dir_str = dir.to_string_lossy().into_owned();
+        assert!(build_final_report(&dir_str, 4).is_err_and(|e| e.kind() == std::io::ErrorKind::NotFound));
+
+        let mut state = GlobalFileState::new(&dir_str, 4);
+        state.register_file("nsl_03_batch_000000_to_04_batch_000000.rkyv", 0, 0, 600_000, false, None, None);
+        state.register_file("nsl_03_batch_000001_to_04_batch_000001_compacted.rkyv", 1, 1, 404_589, true, None, None);
+        state.flush().expect("flush");
+        for secs in [10.0, 5.0] {
+            let record = TimingRecord { timestamp: "now".to_string(), lists_created: 1, elapsed_secs: secs, computation_secs: secs,
+                file_io_secs: 0.0, conversion_secs: 0.0, isomorph_lookups: 0, isomorph_hits: 0 };
+            append_timing_record(&dir_str, 4, &record).expect("timing");
+        }
+        std::fs::write(dir.join("nsl_04_verify_report.json"),
+            r#"{"files_checked": 2, "lists_checked": 1004589, "invalid_lists": 0, "unreadable_files": []}"#).expect("verify");
+
+        let report = build_final_report(&dir_str, 4).expect("report");
+        assert_eq!((report.totals.files, report.totals.lists, report.totals.source_batches), (2, 1_004_589, 2));
+        assert_eq!((report.compaction.compacted_files, report.compaction.lists_in_compacted_files), (1, 404_589));
+        assert_eq!((report.timing.runs, report.timing.elapsed_secs), (2, 15.0));
+        assert!(report.verification.as_ref().is_some_and(|v| v.covers_all_lists && v.invalid_lists == 0));
+        assert_eq!(report.reference.matches, Some(true));
+
+        save_final_report(&dir_str, &report).expect("save");
+        assert!(dir.join("nsl_04_final_report.json").exists());
+    }
+}
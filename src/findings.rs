@@ -0,0 +1,310 @@
+//! Findings module: machine-readable outcome of the integrity modes
+//!
+//! --check, --verify, --validate-chain and --scan print what they find for a human and
+//! also collect it as findings (warnings or integrity errors), saved as a JSON
+//! report next to the files, for scripts to read instead of the console text.
+//!
+//! Key features:
+//! - One finding per problem: severity, kind (stable identifier), message and,
+//!   when it applies, the file and batch concerned
+//! - Report: mode, status (clean, warnings, errors), exit code, findings, then
+//!   the fields of the mode's own report
+//! - Exit code of the run, from the worst status recorded:
+//!   0 clean, 1 the run itself failed (I/O error, bad arguments),
+//!   2 warnings only, 3 integrity errors
+//! - Run warnings: the problems a mode works around (compaction, export or history
+//!   failing after the lists are written...) recorded as warnings of the run, so
+//!   that it exits with 2 instead of 0
+//! - Run summary: one JSON line at the end of every mode (mode, outcome, exit
+//!   code, message, kind of the error if it failed, run warnings, lists read and
+//!   written per size, files created), for scripts reading the console
+//! - begin_run: the status, warnings and activity recorded are those of the run
+//!   only; ProcessingConfig::run starts each run with it, so that a program
+//!   running several does not report the warnings of the previous ones
+//!
+//! Used by --check, --verify, --validate-chain and --scan, and by every mode for
+//! the run warnings and summary
+
+use std::path::Path;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+use serde::Serialize;
+
+use crate::error::{FunnyError, FunnyResult};
+use crate::utils::*;
+
+/// Exit code of a run that failed before reaching a verdict
+pub const EXIT_FAILED: i32 = 1;
+
+/// How serious a finding is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// Verdict of an integrity mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Clean,
+    Warnings,
+    Errors,
+}
+
+impl Status {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Status::Clean => 0,
+            Status::Warnings => 2,
+            Status::Errors => 3,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Self> {
+        [Status::Clean, Status::Warnings, Status::Errors].into_iter().find(|s| s.exit_code() as u8 == code)
+    }
+}
+
+/// One problem found
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Finding {
+    pub severity: Severity,
+    pub kind: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub batch: Option<u32>,
+}
+
+impl Finding {
+    pub fn warning(kind: &str, message: impl Into<String>) -> Self {
+        Self { severity: Severity::Warning, kind: kind.to_string(), message: message.into(), file: None, batch: None }
+    }
+
+    pub fn error(kind: &str, message: impl Into<String>) -> Self {
+        Self { severity: Severity::Error, ..Self::warning(kind, message) }
+    }
+
+    pub fn with_file(self, file: impl Into<String>) -> Self {
+        Self { file: Some(file.into()), ..self }
+    }
+
+    pub fn with_batch(self, batch: u32) -> Self {
+        Self { batch: Some(batch), ..self }
+    }
+}
+
+/// Findings of one run of an integrity mode, with the mode's own report (`details`)
+#[derive(Debug, Clone, Serialize)]
+pub struct FindingsReport<T: Serialize> {
+    pub mode: String,
+    pub status: Status,
+    pub exit_code: i32,
+    pub findings: Vec<Finding>,
+    #[serde(flatten)]
+    pub details: T,
+}
+
+impl<T: Serialize> FindingsReport<T> {
+    pub fn new(mode: &str, findings: Vec<Finding>, details: T) -> Self {
+        let status = match findings.iter().map(|f| f.severity).max() {
+            None => Status::Clean,
+            Some(Severity::Warning) => Status::Warnings,
+            Some(Severity::Error) => Status::Errors,
+        };
+        Self { mode: mode.to_string(), status, exit_code: status.exit_code(), findings, details }
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.findings.iter().filter(|f| f.severity == severity).count()
+    }
+
+    /// Write the report as JSON to `dir`/`filename` and record its status for the exit code
+    pub fn save(&self, dir: &str, filename: &str) -> std::io::Result<()> {
+        let path = Path::new(dir).join(filename);
+        let json = serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+        crate::io_helpers::write_file_atomic(&path.to_string_lossy(), json.as_bytes())?;
+        test_print(&format!("   Report saved: {} ({} errors, {} warnings)", path.display(),
+            self.count(Severity::Error), self.count(Severity::Warning)));
+        record_status(self.status);
+        Ok(())
+    }
+
+    /// Outcome of the mode: `message` if clean, with the warnings counted, and an
+    /// error (exit code 3, see `exit_code`) on integrity errors
+    pub fn outcome(&self, message: String) -> FunnyResult<String> {
+        match self.status {
+            Status::Clean => Ok(message),
+            Status::Warnings => Ok(format!("{} ({} warnings)", message, self.count(Severity::Warning))),
+            Status::Errors => Err(FunnyError::Integrity(format!("{} FAILED: {} integrity errors, {} warnings", self.mode,
+                self.count(Severity::Error), self.count(Severity::Warning)))),
+        }
+    }
+}
+
+// Worst status recorded by the run (u8::MAX: none)
+static RUN_STATUS: AtomicU8 = AtomicU8::new(u8::MAX);
+
+/// Record the status of a report: the run exits with the worst one
+pub fn record_status(status: Status) {
+    let code = status.exit_code() as u8;
+    let _ = RUN_STATUS.fetch_update(Ordering::Relaxed, Ordering::Relaxed,
+        |current| (current == u8::MAX || code > current).then_some(code));
+}
+
+/// Exit code of the run: the worst status recorded, else 0; EXIT_FAILED if the run
+/// `failed` without integrity errors (the warnings before a failure do not hide it)
+pub fn exit_code(failed: bool) -> i32 {
+    exit_code_for(Status::from_code(RUN_STATUS.load(Ordering::Relaxed)), failed)
+}
+
+fn exit_code_for(recorded: Option<Status>, failed: bool) -> i32 {
+    match recorded {
+        Some(Status::Errors) => Status::Errors.exit_code(),
+        _ if failed => EXIT_FAILED,
+        Some(status) => status.exit_code(),
+        None => 0,
+    }
+}
+
+// Warnings of the run, in the order they were raised
+static RUN_WARNINGS: Mutex<Vec<Finding>> = Mutex::new(Vec::new());
+
+/// Print a warning and record it as a warning of the run (exit code 2 if it completes)
+pub fn warn(kind: &str, message: impl Into<String>) {
+    let finding = Finding::warning(kind, message);
+    crate::events::warning(&finding);
+    record_status(Status::Warnings);
+    RUN_WARNINGS.lock().unwrap_or_else(|e| e.into_inner()).push(finding);
+}
+
+/// Warnings of the run so far
+pub fn run_warnings() -> Vec<Finding> {
+    RUN_WARNINGS.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Start a new run: forget the status, the warnings and the activity (see
+/// events::run_activity) recorded by the previous one
+pub fn begin_run() {
+    RUN_STATUS.store(u8::MAX, Ordering::Relaxed);
+    RUN_WARNINGS.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    crate::events::reset_activity();
+}
+
+// Held by the tests relying on what the run records, begin_run clearing it
+#[cfg(test)]
+pub(crate) static RUN_TEST_LOCK: Mutex<()> = Mutex::new(());
+
+/// Outcome of a run, one per exit code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunOutcome {
+    Completed,
+    CompletedWithWarnings,
+    IntegrityErrors,
+    Failed,
+}
+
+impl RunOutcome {
+    pub fn from_exit_code(code: i32) -> Self {
+        match code {
+            0 => RunOutcome::Completed,
+            2 => RunOutcome::CompletedWithWarnings,
+            3 => RunOutcome::IntegrityErrors,
+            _ => RunOutcome::Failed,
+        }
+    }
+}
+
+/// Last line of every run
+#[derive(Debug, Clone, Serialize)]
+pub struct RunSummary {
+    pub mode: String,
+    pub outcome: RunOutcome,
+    pub exit_code: i32,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<String>,  // kind of the error of a failed run (see error)
+    pub elapsed_secs: f64,
+    pub warnings: Vec<Finding>,
+    #[serde(flatten)]
+    pub activity: crate::events::RunActivity,  // lists read and written per size, files created
+}
+
+impl RunSummary {
+    /// Summary of the run of `mode` ended with `result`
+    pub fn new(mode: &str, result: &FunnyResult<String>, elapsed_secs: f64) -> Self {
+        Self::with_exit_code(mode, result, exit_code(result.is_err()), run_warnings(), elapsed_secs)
+    }
+
+    fn with_exit_code(mode: &str, result: &FunnyResult<String>, exit_code: i32, warnings: Vec<Finding>,
+        elapsed_secs: f64) -> Self {
+        let (message, error_kind) = match result {
+            Ok(message) => (message.clone(), None),
+            Err(e) => (e.to_string(), Some(e.kind().to_string())),
+        };
+        Self { mode: mode.to_string(), outcome: RunOutcome::from_exit_code(exit_code), exit_code,
+            message, error_kind, elapsed_secs, warnings, activity: crate::events::run_activity() }
+    }
+
+    /// Print the warnings of the run, then the summary as one JSON line ("RUN SUMMARY {...}")
+    pub fn print(&self) {
+        if !self.warnings.is_empty() {
+            test_print(&format!("\n{} warnings during the run:", self.warnings.len()));
+            for warning in &self.warnings {
+                test_print(&format!("   - [{}] {}", warning.kind, warning.message));
+            }
+        }
+        let json = serde_json::to_string(self).unwrap_or_default();
+        test_print(&format!("\nRUN SUMMARY {}", json));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_follows_the_worst_finding() {
+        let clean = FindingsReport::new("check", Vec::new(), ());
+        assert_eq!((clean.status, clean.exit_code), (Status::Clean, 0));
+        let warned = FindingsReport::new("check", vec![Finding::warning("missing_batch", "gap").with_batch(3)], ());
+        assert_eq!((warned.status, warned.exit_code), (Status::Warnings, 2));
+        assert!(warned.outcome("done".to_string()).is_ok());
+        let failed = FindingsReport::new("check", vec![Finding::warning("missing_batch", "gap"),
+            Finding::error("count_mismatch", "5 recorded, 3 on disk").with_file("a.rkyv")], ());
+        assert_eq!((failed.status, failed.exit_code), (Status::Errors, 3));
+        assert!(failed.outcome("done".to_string()).is_err());
+        let json = serde_json::to_value(&failed).expect("json");
+        assert_eq!(json["findings"][1]["severity"], "error");
+        assert_eq!(json["findings"][1]["file"], "a.rkyv");
+        assert!(json["findings"][0].get("file").is_none());
+    }
+
+    #[test]
+    fn run_warnings_turn_a_completed_run_into_exit_code_2() {
+        assert_eq!(exit_code_for(None, false), 0);
+        assert_eq!(exit_code_for(Some(Status::Warnings), false), 2);
+        assert_eq!(exit_code_for(Some(Status::Warnings), true), EXIT_FAILED);
+        assert_eq!(exit_code_for(Some(Status::Errors), true), 3);
+
+        // The run statics are shared by the tests: only the warning raised here is looked for
+        let _lock = RUN_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        warn("output_compaction", "Output compaction encountered an issue: disk full");
+        let warnings: Vec<Finding> = run_warnings().into_iter().filter(|w| w.kind == "output_compaction").collect();
+        assert_eq!(warnings.len(), 1);
+        let summary = RunSummary::with_exit_code("size", &Ok("Processing completed".to_string()),
+            exit_code_for(Some(Status::Warnings), false), warnings.clone(), 1.5);
+        assert_eq!((summary.outcome, summary.exit_code), (RunOutcome::CompletedWithWarnings, 2));
+        let failed = RunSummary::with_exit_code("size", &Err(FunnyError::from(std::io::Error::other("disk gone")).with_context("Failed to read batch 3")),
+            exit_code_for(Some(Status::Warnings), true), warnings, 1.5);
+        assert_eq!((failed.outcome, failed.exit_code), (RunOutcome::Failed, EXIT_FAILED));
+        let json = serde_json::to_value(&failed).expect("json");
+        assert_eq!(json["outcome"], "failed");
+        assert_eq!((json["message"].as_str(), json["error_kind"].as_str()), (Some("Failed to read batch 3: disk gone"), Some("io")));
+        assert_eq!(json["warnings"][0]["kind"], "output_compaction");
+    }
+}
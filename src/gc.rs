@@ -0,0 +1,374 @@
+//! GC module: find and remove the artifacts left behind by interrupted runs
+//!
+//! Crashes leave temporary files (.tmp, .tmp.<pid>), state backups (.rkyv.old,
+//! .rkyv.<timestamp>.old, .json_old, .rkyv_old) and intermediate count files whose information is already
+//! in the global state. This module lists them and, on request, deletes them.
+//!
+//! Key features:
+//! - Temporary files: kept when the file they were replacing is a missing nsl_*
+//!   file (the .tmp may then be the only copy), or when modified in the last hour
+//!   (a run may still be writing it)
+//! - State backups: only once the state file they back up is present and loads,
+//!   and beyond the newest --keep-backups ones
+//! - Intermediate count files: only when the state or history of their size
+//!   records their source batch
+//! - Optional size filter, total bytes reported; nothing deleted without --force
+//! - Untracked files of a size (for --check): every file of the directory that
+//!   the state does not track, classified as orphan artifact (above, reclaimable
+//!   unless kept), list file missing from the state, legacy format or unknown
+//!
+//! Used by --gc and --check modes
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use separator::Separatable;
+use serde::Serialize;
+
+use crate::file_info::GlobalFileState;
+use crate::utils::*;
+
+/// Temporary files modified more recently than this may belong to a running process
+const GC_MIN_AGE: Duration = Duration::from_secs(3600);
+
+/// Kind of artifact
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArtifactKind {
+    Temporary,
+    StateBackup,
+    IntermediateCount,
+}
+
+impl ArtifactKind {
+    fn label(&self) -> &'static str {
+        match self {
+            ArtifactKind::Temporary => "temporary",
+            ArtifactKind::StateBackup => "state backup",
+            ArtifactKind::IntermediateCount => "intermediate count",
+        }
+    }
+}
+
+/// One artifact found in the directory
+#[derive(Debug, Clone)]
+pub struct Artifact {
+    pub filename: String,
+    pub kind: ArtifactKind,
+    pub bytes: u64,
+    pub kept: Option<String>, // reason why it must not be deleted
+}
+
+/// What a file not tracked by the state is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UntrackedKind {
+    Orphan,        // artifact of an interrupted run (see ArtifactKind)
+    UntrackedList, // list file of the size missing from the state
+    Legacy,        // file of a legacy format (see migrate)
+    Unknown,
+}
+
+impl UntrackedKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            UntrackedKind::Orphan => "orphan",
+            UntrackedKind::UntrackedList => "untracked list",
+            UntrackedKind::Legacy => "legacy",
+            UntrackedKind::Unknown => "unknown",
+        }
+    }
+}
+
+/// File of a size directory not tracked by the state
+#[derive(Debug, Clone, Serialize)]
+pub struct UntrackedFile {
+    pub filename: String,
+    pub kind: UntrackedKind,
+    pub detail: String,
+    pub bytes: u64,
+    pub reclaimable: bool, // safe to delete (--gc --force deletes it)
+}
+
+/// Outcome of a collection
+#[derive(Debug, Clone, Default)]
+pub struct GcReport {
+    pub artifacts: Vec<Artifact>,
+    pub files_deleted: u64,
+    pub bytes_freed: u64,
+}
+
+impl GcReport {
+    /// Artifacts safe to delete
+    pub fn removable(&self) -> impl Iterator<Item = &Artifact> {
+        self.artifacts.iter().filter(|a| a.kept.is_none())
+    }
+}
+
+/// Kind of artifact and the file it stands for ("x.rkyv.tmp.123" -> "x.rkyv")
+fn classify(name: &str) -> Option<(ArtifactKind, String)> {
+    if let Some((stem, pid)) = name.rsplit_once(".tmp.")
+        && !pid.is_empty() && pid.chars().all(|c| c.is_ascii_digit()) {
+        return Some((ArtifactKind::Temporary, stem.to_string()));
+    }
+    if let Some(stem) = name.strip_suffix(".tmp") {
+        return Some((ArtifactKind::Temporary, stem.to_string()));
+    }
+    if let Some(stem) = name.strip_suffix(".old") {
+        // Rotated backups (--keep-backups): x.rkyv.<timestamp>.old
+        let stem = stem.rsplit_once('.')
+            .filter(|(_, stamp)| crate::file_info::is_backup_timestamp(stamp))
+            .map_or(stem, |(target, _)| target);
+        return Some((ArtifactKind::StateBackup, stem.to_string()));
+    }
+    for ext in ["json", "rkyv"] {
+        if let Some(stem) = name.strip_suffix(&format!(".{}_old", ext)) {
+            return Some((ArtifactKind::StateBackup, format!("{}.{}", stem, ext)));
+        }
+    }
+    if name.starts_with("nsl_") && name.contains("_intermediate_count_from_") && name.ends_with(".txt") {
+        return Some((ArtifactKind::IntermediateCount, name.to_string()));
+    }
+    None
+}
+
+/// Size a filename belongs to (list files: target size; state and count files: nsl_XX_)
+fn size_of(name: &str) -> Option<u8> {
+    let name = name.trim_start_matches('.');
+    if let Some(parsed) = crate::filenames::parse_filename(name) {
+        return Some(parsed.target_size);
+    }
+    name.strip_prefix("nsl_")?.get(..2)?.parse().ok()
+}
+
+/// Source batches recorded for `size` in the state and the history of `dir` (None without a state)
+fn recorded_sources(dir: &str, size: u8) -> Option<BTreeSet<u32>> {
+    let has_state = ["rkyv", "json", "sqlite"].iter()
+        .any(|ext| Path::new(dir).join(format!("nsl_{:02}_global_info.{}", size, ext)).exists());
+    if !has_state {
+        return None;
+    }
+    let state = GlobalFileState::from_sources(dir, size).ok()?;
+    let mut sources: BTreeSet<u32> = state.entries().values().map(|e| e.source_batch).collect();
+    if let Ok(history) = GlobalFileState::from_history_file(dir, size, "rkyv") {
+        sources.extend(history.entries().values().map(|e| e.source_batch));
+    }
+    Some(sources)
+}
+
+/// Why an artifact must be kept (None if it can be deleted)
+fn keep_reason(dir: &str, kind: ArtifactKind, name: &str, target: &str, modified: Option<SystemTime>,
+    keep: u64, sources: &mut BTreeMap<u8, Option<BTreeSet<u32>>>) -> Option<String> {
+    let target_path = Path::new(dir).join(target);
+    match kind {
+        ArtifactKind::Temporary => {
+            let age = modified.and_then(|m| SystemTime::now().duration_since(m).ok()).unwrap_or_default();
+            if age < GC_MIN_AGE {
+                Some("modified less than an hour ago (a run may be writing it)".to_string())
+            } else if target.starts_with("nsl_") && !target_path.exists() {
+                Some(format!("{} is missing: this may be its only copy", target))
+            } else {
+                None
+            }
+        }
+        ArtifactKind::StateBackup => {
+            if !target_path.exists() {
+                return Some(format!("{} is missing: this is the only copy", target));
+            }
+            let loads = if target.ends_with(".json") {
+                crate::file_info::GlobalFileInfo::load_json(&target_path).is_ok()
+            } else {
+                crate::file_info::GlobalFileInfo::load_rkyv(&target_path).is_ok()
+            };
+            if !loads {
+                return Some(format!("{} does not load", target));
+            }
+            // The rotation of --keep-backups N keeps the N newest backups of each state
+            let backups = crate::file_info::state_backups(&target_path);
+            let newest = backups.len().saturating_sub(keep as usize);
+            if backups[newest..].iter().any(|b| b.path.file_name().is_some_and(|n| n == name)) {
+                Some(format!("one of the {} newest backups (--keep-backups)", keep))
+            } else {
+                None
+            }
+        }
+        ArtifactKind::IntermediateCount => {
+            let batch = name.strip_suffix(".txt").and_then(|n| n.rsplit('_').next()).and_then(|b| b.parse::<u32>().ok());
+            let (Some(size), Some(batch)) = (size_of(name), batch) else {
+                return Some("unexpected name".to_string());
+            };
+            let recorded = sources.entry(size).or_insert_with(|| recorded_sources(dir, size));
+            match recorded {
+                Some(batches) if batches.contains(&batch) => None,
+                Some(_) => Some(format!("source batch {:06} not in the size {:02} state", batch, size)),
+                None => Some(format!("no size {:02} state: this is the only record", size)),
+            }
+        }
+    }
+}
+
+/// Classify the files of `dir` belonging to `size` (or to no size) that are not in
+/// `tracked` (the filenames of the state entries); the state, count, report and log
+/// files of the tool (nsl_*) and the files of the other sizes are left out
+pub fn classify_untracked(dir: &str, size: u8, tracked: &std::collections::HashSet<&str>) -> std::io::Result<Vec<UntrackedFile>> {
+    let mut names: Vec<String> = std::fs::read_dir(dir)?.flatten()
+        .filter(|e| e.path().is_file())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+
+    let mut files = Vec::new();
+    let mut sources = BTreeMap::new();
+    let legacy_count = format!("no_set_list_input_intermediate_count_{:02}_", size.saturating_sub(1));
+    for name in names {
+        let metadata = std::fs::metadata(Path::new(dir).join(&name))?;
+        let (kind, detail, reclaimable) = if let Some((kind, target)) = classify(&name) {
+            if size_of(&target) != Some(size) {
+                continue;
+            }
+            match keep_reason(dir, kind, &name, &target, metadata.modified().ok(), crate::file_info::keep_backups(), &mut sources) {
+                Some(reason) => (UntrackedKind::Orphan, format!("{}, kept: {}", kind.label(), reason), false),
+                None => (UntrackedKind::Orphan, kind.label().to_string(), true),
+            }
+        } else if let Some(parsed) = crate::filenames::parse_filename(&name) {
+            if parsed.target_size != size || tracked.contains(name.as_str()) {
+                continue;
+            }
+            (UntrackedKind::UntrackedList, "not in the state: partial file of a crashed run, or register it (--check --fix)".to_string(), false)
+        } else if let Some((legacy_size, _, _)) = crate::migrate::parse_legacy_filename(&name) {
+            if legacy_size != size {
+                continue;
+            }
+            (UntrackedKind::Legacy, "legacy list file (convert it with --migrate)".to_string(), false)
+        } else if name.starts_with(&legacy_count) {
+            (UntrackedKind::Legacy, "legacy intermediate count file".to_string(), false)
+        } else if name.starts_with("nsl_") || name.starts_with("no_set_list_") {
+            continue;
+        } else {
+            (UntrackedKind::Unknown, "unknown file".to_string(), false)
+        };
+        files.push(UntrackedFile { filename: name, kind, detail, bytes: metadata.len(), reclaimable });
+    }
+    Ok(files)
+}
+
+/// List the artifacts of `dir` (of size `size` only, if given) and delete the removable ones if `delete`
+pub fn collect_garbage(dir: &str, size: Option<u8>, delete: bool) -> std::io::Result<GcReport> {
+    test_print(&format!("\nGC MODE: Artifacts of {} in {}", size.map_or("all sizes".to_string(), |s| format!("size {:02}", s)), dir));
+
+    let mut names: Vec<String> = std::fs::read_dir(dir)?.flatten()
+        .filter(|e| e.path().is_file())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+
+    let mut report = GcReport::default();
+    let mut sources = BTreeMap::new();
+    for name in names {
+        let Some((kind, target)) = classify(&name) else { continue };
+        if size.is_some() && size_of(&target) != size {
+            continue;
+        }
+        let metadata = std::fs::metadata(Path::new(dir).join(&name))?;
+        let kept = keep_reason(dir, kind, &name, &target, metadata.modified().ok(), crate::file_info::keep_backups(), &mut sources);
+        report.artifacts.push(Artifact { filename: name, kind, bytes: metadata.len(), kept });
+    }
+
+    for artifact in report.artifacts.iter() {
+        match &artifact.kept {
+            Some(reason) => test_print(&format!("   kept    | {:<18} | {} ({})", artifact.kind.label(), artifact.filename, reason)),
+            None => test_print(&format!("   {:<7} | {:<18} | {} ({} bytes)", if delete { "delete" } else { "found" },
+                artifact.kind.label(), artifact.filename, artifact.bytes.separated_string())),
+        }
+    }
+    let removable: Vec<(String, u64)> = report.removable().map(|a| (a.filename.clone(), a.bytes)).collect();
+    let total: u64 = removable.iter().map(|(_, b)| b).sum();
+    if delete {
+        for (filename, bytes) in removable.iter() {
+            std::fs::remove_file(Path::new(dir).join(filename))?;
+            report.files_deleted += 1;
+            report.bytes_freed += bytes;
+        }
+        test_print(&format!("   ... {} artifacts deleted, {} bytes freed", report.files_deleted, report.bytes_freed.separated_string()));
+    } else {
+        test_print(&format!("   ... {} removable artifacts, {} bytes (delete them with --force)",
+            removable.len(), total.separated_string()));
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn artifacts_are_classified_and_only_safe_ones_removed() {
+        assert_eq!(classify("nsl_05_global_info.rkyv.tmp.4242"), Some((ArtifactKind::Temporary, "nsl_05_global_info.rkyv".to_string())));
+        assert_eq!(classify("nsl_05_global_info.json_old"), Some((ArtifactKind::StateBackup, "nsl_05_global_info.json".to_string())));
+        assert_eq!(classify("nsl_05_global_info.rkyv"), None);
+
+        let dir = crate::test_dir::TestDir::new("gc");
+        let dir_str = dir.to_string_lossy().into_owned();
+        let mut state = GlobalFileState::new(&dir_str, 5);
+        state.register_file("nsl_04_batch_000000_to_05_batch_000000.rkyv", 0, 0, 3, false, None, None);
+        state.flush().expect("flush");
+        state.flush().expect("flush again"); // leaves nsl_05_global_info.rkyv.old
+        for batch in [0, 1] {
+            std::fs::write(dir.join(format!("nsl_05_intermediate_count_from_04_{:06}.txt", batch)), "3").expect("write");
+        }
+        std::fs::write(dir.join("nsl_05_global_info.json.tmp"), "{}").expect("write"); // too recent
+
+        let report = collect_garbage(&dir_str, Some(5), true).expect("gc");
+        let deleted: Vec<&str> = report.removable().map(|a| a.filename.as_str()).collect();
+        assert_eq!(deleted, vec!["nsl_05_intermediate_count_from_04_000000.txt"]);
+        assert!(dir.join("nsl_05_global_info.rkyv.old").exists()); // the newest backup (--keep-backups 1)
+        assert!(dir.join("nsl_05_intermediate_count_from_04_000001.txt").exists());
+        assert!(dir.join("nsl_05_global_info.json.tmp").exists());
+    }
+
+    #[test]
+    fn only_backups_beyond_keep_backups_are_removed() {
+        let dir = crate::test_dir::TestDir::new("gc_keep_backups");
+        let dir_str = dir.to_string_lossy().into_owned();
+        let mut state = GlobalFileState::new(&dir_str, 5);
+        state.register_file("nsl_04_batch_000000_to_05_batch_000000.rkyv", 0, 0, 3, false, None, None);
+        state.flush().expect("flush");
+        state.flush().expect("flush again"); // leaves nsl_05_global_info.rkyv.old
+        for stamp in ["20260101_000000", "20260102_000000", "20260103_000000"] {
+            std::fs::write(dir.join(format!("nsl_05_global_info.rkyv.{}.old", stamp)), "x").expect("write");
+        }
+
+        let kept = |name: &str, keep: u64| keep_reason(&dir_str, ArtifactKind::StateBackup, name, "nsl_05_global_info.rkyv",
+            None, keep, &mut BTreeMap::new()).is_some();
+        assert!(kept("nsl_05_global_info.rkyv.old", 1));
+        assert!(!kept("nsl_05_global_info.rkyv.20260103_000000.old", 1));
+        assert!(kept("nsl_05_global_info.rkyv.old", 3));
+        assert!(kept("nsl_05_global_info.rkyv.20260103_000000.old", 3));
+        assert!(kept("nsl_05_global_info.rkyv.20260102_000000.old", 3));
+        assert!(!kept("nsl_05_global_info.rkyv.20260101_000000.old", 3));
+    }
+
+    #[test]
+    fn untracked_files_are_classified() {
+        let dir = crate::test_dir::TestDir::new("untracked");
+        let dir_str = dir.to_string_lossy().into_owned();
+        let tracked = "nsl_04_batch_000000_to_05_batch_000000.rkyv";
+        let mut state = GlobalFileState::new(&dir_str, 5);
+        state.register_file(tracked, 0, 0, 3, false, None, None);
+        state.flush().expect("flush");
+        state.flush().expect("flush again"); // leaves nsl_05_global_info.rkyv.old
+        for name in [tracked, "nsl_04_batch_000001_to_05_batch_000001.rkyv", "nsl_03_batch_000000_to_04_batch_000000.rkyv",
+            "nlist_05_batch_000002.bin", "nsl_05_intermediate_count_from_04_000000.txt", "notes.txt", "nsl_05_check_report.json"] {
+            std::fs::write(dir.join(name), "x").expect("write");
+        }
+
+        let files = classify_untracked(&dir_str, 5, &[tracked].into_iter().collect()).expect("classify");
+        let found: Vec<(&str, UntrackedKind, bool)> = files.iter().map(|f| (f.filename.as_str(), f.kind, f.reclaimable)).collect();
+        assert_eq!(found, vec![
+            ("nlist_05_batch_000002.bin", UntrackedKind::Legacy, false),
+            ("notes.txt", UntrackedKind::Unknown, false),
+            ("nsl_04_batch_000001_to_05_batch_000001.rkyv", UntrackedKind::UntrackedList, false),
+            ("nsl_05_global_info.rkyv.old", UntrackedKind::Orphan, false),
+            ("nsl_05_intermediate_count_from_04_000000.txt", UntrackedKind::Orphan, true),
+        ]);
+    }
+}
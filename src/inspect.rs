@@ -0,0 +1,220 @@
+//! Inspect module: examine a single list file
+//!
+//! For a file that looks suspicious (unexpected size, odd count in the state),
+//! everything that can be learnt from the file alone, in one pass.
+//!
+//! Key features:
+//! - Archive validated (rkyv bytecheck, plain, delta or packed encoding and zstd compression detected)
+//! - List count, histograms of the list sizes (n) and remaining-card counts
+//! - Min/max max_card
+//! - Invalid lists (same checks as --verify) and duplicates within the file
+//!   (64-bit hash of the cards: a false positive is possible but very unlikely)
+//! - First and last lists rendered as text
+//! - Provenance recorded in the state of the file's directory (host, version,
+//!   duration, input hash), if any
+//!
+//! Used by --inspect mode
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use separator::Separatable;
+use serde::Serialize;
+
+use crate::file_info::Provenance;
+use crate::no_set_list::{NoSetList, NoSetListSerialized};
+use crate::utils::*;
+
+/// Lists rendered at the head and at the tail of the file
+const INSPECT_SHOWN_LISTS: usize = 5;
+
+/// Max number of duplicates and invalid lists detailed (all are counted)
+const INSPECT_MAX_DETAILED: usize = 20;
+
+/// What was found in one file
+#[derive(Debug, Clone, Serialize)]
+pub struct InspectReport {
+    pub file: String,
+    pub bytes: u64,
+    pub encoding: String,
+    pub format_version: u8,
+    pub nb_lists: u64,
+    pub size_histogram: BTreeMap<u8, u64>,         // n -> lists
+    pub remaining_histogram: BTreeMap<usize, u64>, // remaining cards -> lists
+    pub min_max_card: Option<usize>,
+    pub max_max_card: Option<usize>,
+    pub duplicates: u64,
+    pub duplicate_pairs: Vec<(u64, u64)>,          // (first index, duplicate index)
+    pub invalid_lists: u64,
+    pub invalid_details: Vec<(u64, Vec<String>)>,  // (index, problems)
+    pub first_lists: Vec<String>,
+    pub last_lists: Vec<String>,
+    pub provenance: Option<Provenance>,
+}
+
+fn cards_hash(list: &NoSetListSerialized) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    list.no_set_list.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn render(index: u64, list: &NoSetListSerialized) -> String {
+    format!("#{:<10} {}", index, NoSetList::from_serialized(list))
+}
+
+/// Provenance of `filepath` in the state of its directory (None: no state, or no
+/// provenance recorded)
+fn recorded_provenance(filepath: &str, name: &str, size: u8) -> Option<Provenance> {
+    let dir = Path::new(filepath).parent()
+        .map(|d| d.to_string_lossy().into_owned())
+        .filter(|d| !d.is_empty())
+        .unwrap_or_else(|| ".".to_string());
+    let state = crate::dry_run::load_state_readonly(&dir, size).ok()?;
+    state.entries().values().find(|e| e.filename == name)?.provenance.clone()
+}
+
+/// Inspect the list file `filepath`
+pub fn inspect_file(filepath: &str) -> std::io::Result<InspectReport> {
+    test_print(&format!("\nINSPECT MODE: {}", filepath));
+    let bytes = std::fs::metadata(crate::storage::resolve_path(filepath))?.len();
+    let format = crate::io_helpers::file_format(filepath)?;
+
+    // Size expected from the filename (files named otherwise are checked against their own n)
+    let name = Path::new(filepath).file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let expected_size = crate::filenames::parse_filename(&name).map(|p| p.target_size);
+
+    let mut report = InspectReport {
+        file: filepath.to_string(),
+        bytes,
+        encoding: format!("{}{}", format.encoding.name(), if format.compressed { " + zstd" } else { "" }),
+        format_version: format.version,
+        nb_lists: 0,
+        size_histogram: BTreeMap::new(),
+        remaining_histogram: BTreeMap::new(),
+        min_max_card: None,
+        max_max_card: None,
+        duplicates: 0,
+        duplicate_pairs: Vec::new(),
+        invalid_lists: 0,
+        invalid_details: Vec::new(),
+        first_lists: Vec::new(),
+        last_lists: Vec::new(),
+        provenance: expected_size.and_then(|size| recorded_provenance(filepath, &name, size)),
+    };
+    let total = crate::io_helpers::count_lists_in_file(filepath)?;
+    let tail_start = total.saturating_sub(INSPECT_SHOWN_LISTS as u64).max(INSPECT_SHOWN_LISTS as u64);
+    let mut first_seen: HashMap<u64, u64> = HashMap::new();
+    let mut index = 0u64;
+
+    crate::io_helpers::load_lists_in_chunks(filepath, 1_000_000, |chunk| {
+        for list in chunk.iter() {
+            *report.size_histogram.entry(list.n).or_insert(0) += 1;
+            *report.remaining_histogram.entry(list.remaining_cards_list.len()).or_insert(0) += 1;
+            report.min_max_card = Some(report.min_max_card.map_or(list.max_card, |m| m.min(list.max_card)));
+            report.max_max_card = Some(report.max_max_card.map_or(list.max_card, |m| m.max(list.max_card)));
+
+            let problems = crate::verify::check_list(list, expected_size.unwrap_or(list.n));
+            if !problems.is_empty() {
+                report.invalid_lists += 1;
+                if report.invalid_details.len() < INSPECT_MAX_DETAILED {
+                    report.invalid_details.push((index, problems));
+                }
+            }
+            let hash = cards_hash(list);
+            if let Some(&first) = first_seen.get(&hash) {
+                report.duplicates += 1;
+                if report.duplicate_pairs.len() < INSPECT_MAX_DETAILED {
+                    report.duplicate_pairs.push((first, index));
+                }
+            } else {
+                first_seen.insert(hash, index);
+            }
+
+            if index < INSPECT_SHOWN_LISTS as u64 {
+                report.first_lists.push(render(index, list));
+            } else if index >= tail_start {
+                report.last_lists.push(render(index, list));
+            }
+            index += 1;
+        }
+        Ok(())
+    })?;
+
+    report.nb_lists = index;
+    Ok(report)
+}
+
+/// Print an inspection report
+pub fn print_inspect(report: &InspectReport) {
+    test_print(&format!("   ... archive valid ({} encoding, {} bytes)", report.encoding, report.bytes.separated_string()));
+    if report.format_version < crate::io_helpers::FORMAT_VERSION {
+        test_print(&format!("   ... format version {} (older than {}, see --migrate-format)", report.format_version,
+            crate::io_helpers::FORMAT_VERSION));
+    }
+    test_print(&format!("   ... {} lists", report.nb_lists.separated_string()));
+    match &report.provenance {
+        Some(provenance) => test_print(&format!("   ... written by {}", provenance.summary())),
+        None => test_print("   ... no provenance recorded in the state"),
+    }
+    if let (Some(min), Some(max)) = (report.min_max_card, report.max_max_card) {
+        test_print(&format!("   ... max_card from {} to {}", min, max));
+    }
+    test_print("   List sizes (n):");
+    for (n, count) in report.size_histogram.iter() {
+        test_print(&format!("      {:>2} cards: {:>15}", n, count.separated_string()));
+    }
+    test_print("   Remaining cards:");
+    for (len, count) in report.remaining_histogram.iter() {
+        test_print(&format!("      {:>2} cards: {:>15}", len, count.separated_string()));
+    }
+    test_print(&format!("   ... {} invalid lists", report.invalid_lists.separated_string()));
+    for (index, problems) in report.invalid_details.iter() {
+        test_print(&format!("      #{}: {}", index, problems.join("; ")));
+    }
+    test_print(&format!("   ... {} duplicate lists", report.duplicates.separated_string()));
+    for (first, duplicate) in report.duplicate_pairs.iter() {
+        test_print(&format!("      #{} duplicates #{}", duplicate, first));
+    }
+    test_print("   First lists:");
+    for text in report.first_lists.iter() {
+        test_print(&format!("      {}", text));
+    }
+    if !report.last_lists.is_empty() {
+        test_print("   Last lists:");
+        for text in report.last_lists.iter() {
+            test_print(&format!("      {}", text));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicates_and_histograms_are_reported() {
+        let dir = crate::test_dir::TestDir::new("inspect");
+        let dir_str = dir.to_string_lossy().into_owned();
+
+        let a = NoSetListSerialized { n: 4, max_card: 5, no_set_list: vec![0, 1, 3, 5], remaining_cards_list: vec![6, 7] };
+        let b = NoSetListSerialized { n: 4, max_card: 6, no_set_list: vec![0, 1, 3, 6], remaining_cards_list: vec![7] };
+        let file = crate::filenames::output_filename(&dir_str, 3, 0, 4, 0);
+        assert!(crate::io_helpers::save_to_file_serialized(&vec![a.clone(), b, a], &file));
+
+        let name = Path::new(&file).file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let mut state = crate::file_info::GlobalFileState::new(&dir_str, 4);
+        state.register_file(&name, 0, 0, 3, false, None, None);
+        state.set_provenance(&name, 0, 0, Provenance::here(1500, Some("crc32:0a1b2c3d".to_string())));
+        state.flush().expect("flush");
+
+        let report = inspect_file(&file).expect("inspect");
+        assert_eq!(report.nb_lists, 3);
+        let provenance = report.provenance.as_ref().expect("provenance recorded");
+        assert_eq!((provenance.duration_ms, provenance.crate_version.as_str()), (1500, env!("CARGO_PKG_VERSION")));
+        assert_eq!(report.duplicate_pairs, vec![(0, 2)]);
+        assert_eq!(report.size_histogram.get(&4), Some(&3));
+        assert_eq!((report.min_max_card, report.max_max_card), (Some(5), Some(6)));
+        assert_eq!(report.first_lists.len() + report.last_lists.len(), 3);
+    }
+}
@@ -1,25 +1,502 @@
 use std::fs::File;
 use std::io;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicI32, AtomicU8, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 use memmap2::Mmap;
 use rkyv::check_archived_root;
-use rkyv::Deserialize;
+use rkyv::{AlignedVec, Deserialize};
 
-use crate::no_set_list::NoSetListSerialized;
+use crate::no_set_list::{NoSetList, NoSetListDelta, NoSetListPacked, NoSetListSerialized, PACKED_LIST_BYTES};
 
-/// Save a vector of `NoSetListSerialized` using rkyv to `filename`.
+// ============================================================================
+// On-disk list encodings
+// ============================================================================
+
+/// Header of plain files (8 bytes, keeps the rkyv payload aligned), followed by
+/// the rkyv archive. Files written before format version 1 have no header: they
+/// are a bare rkyv archive, still read (see --migrate-format to upgrade them).
+pub const PLAIN_MAGIC: &[u8; 8] = b"NSLPLAN1";
+
+/// Header of delta-encoded files (8 bytes, keeps the rkyv payload aligned).
+pub const DELTA_MAGIC: &[u8; 8] = b"NSLDELT1";
+
+/// Header of bit-packed files (8 bytes), followed by PACKED_LIST_BYTES per list
+/// (see NoSetListPacked). The payload is not an rkyv archive.
+pub const PACKED_MAGIC: &[u8; 8] = b"NSLPACK1";
+
+/// Header of framed files written by ListFileWriter (8 bytes), followed by the total
+/// list count (u64 LE) and the offset of the frame index (u64 LE, 0 = no index).
+/// Each frame follows: payload length (u64 LE), list count (u64 LE), then the payload
+/// (a plain or delta-encoded archive of the frame's lists) zero-padded to a multiple
+/// of 8 bytes. The index ends the file: (frame offset, list count) per frame, u64 LE.
+pub const FRAMED_MAGIC: &[u8; 8] = b"NSLFRAM2";
+
+/// Version of the list file format written: every archive starts with a versioned
+/// header ("NSL" + kind + version digit) and every file ends with a footer.
+/// Version 0: bare rkyv archives of plain files, no footer.
+pub const FORMAT_VERSION: u8 = 1;
+
+/// True if `header` is a versioned header ("NSL", 4 uppercase letters, a digit),
+/// known or not: an unknown one comes from a newer format, never a bare archive
+fn is_versioned_header(header: &[u8]) -> bool {
+    header.len() >= 8 && header.starts_with(b"NSL")
+        && header[3..7].iter().all(|b| b.is_ascii_uppercase()) && header[7].is_ascii_digit()
+}
+
+/// Bytes of the framed file header (magic + total list count + index offset)
+pub(crate) const FILE_HEADER_LEN: usize = 24;
+
+/// Bytes of a frame header (payload length + list count), and of an index entry
+const FRAME_HEADER_LEN: usize = 16;
+
+/// Lists per frame of ListFileWriter (a frame is serialized in memory at once)
+pub const FRAME_LISTS: usize = 100_000;
+
+/// Encoding used when writing list files (reading always detects the encoding)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ListEncoding {
+    /// PLAIN_MAGIC + rkyv archive of Vec<NoSetListSerialized> (the historical
+    /// format, written without header before format version 1)
+    Plain,
+    /// DELTA_MAGIC + rkyv archive of Vec<NoSetListDelta>
+    Delta,
+    /// PACKED_MAGIC + one NoSetListPacked (24 bytes) per list
+    Packed,
+}
+
+impl ListEncoding {
+    /// Name used by --encoding and in reports
+    pub fn name(&self) -> &'static str {
+        match self {
+            ListEncoding::Plain => "plain",
+            ListEncoding::Delta => "delta",
+            ListEncoding::Packed => "packed",
+        }
+    }
+}
+
+// Encoding of newly written files (0 = Plain, the default; 1 = Delta; 2 = Packed)
+static OUTPUT_ENCODING: AtomicU8 = AtomicU8::new(0);
+
+/// Select the encoding of the list files written from now on
+pub fn set_output_encoding(encoding: ListEncoding) {
+    OUTPUT_ENCODING.store(encoding as u8, Ordering::Relaxed);
+}
+
+/// Encoding of the list files written from now on
+pub fn output_encoding() -> ListEncoding {
+    match OUTPUT_ENCODING.load(Ordering::Relaxed) {
+        1 => ListEncoding::Delta,
+        2 => ListEncoding::Packed,
+        _ => ListEncoding::Plain,
+    }
+}
+
+/// Magic number of a zstd frame: compressed files are a zstd stream of a plain or
+/// delta-encoded file, detected from these first 4 bytes
+pub const ZSTD_MAGIC: &[u8; 4] = &[0x28, 0xB5, 0x2F, 0xFD];
+
+// zstd level of newly written files (0 = not compressed, the default)
+static OUTPUT_COMPRESSION: AtomicI32 = AtomicI32::new(0);
+
+/// Compress the list files written from now on with zstd at `level` (None: no compression)
+pub fn set_output_compression(level: Option<i32>) {
+    OUTPUT_COMPRESSION.store(level.unwrap_or(0), Ordering::Relaxed);
+}
+
+/// zstd level of the list files written from now on (None: no compression)
+pub fn output_compression() -> Option<i32> {
+    match OUTPUT_COMPRESSION.load(Ordering::Relaxed) {
+        0 => None,
+        level => Some(level),
+    }
+}
+
+// ============================================================================
+// Retries of file system operations
+// ============================================================================
+
+// Attempts of each file system operation (1 = no retry), and delay before the
+// first retry in milliseconds (doubled at each following retry)
+static IO_RETRY_ATTEMPTS: AtomicU32 = AtomicU32::new(3);
+static IO_RETRY_BACKOFF_MS: AtomicU64 = AtomicU64::new(200);
+
+/// Retry transient file system errors up to `attempts` times in total, waiting
+/// `backoff_ms` before the first retry and twice as long before each next one
+pub fn set_io_retry(attempts: u32, backoff_ms: u64) {
+    IO_RETRY_ATTEMPTS.store(attempts.max(1), Ordering::Relaxed);
+    IO_RETRY_BACKOFF_MS.store(backoff_ms, Ordering::Relaxed);
+}
+
+/// True for the errors network shares (SMB, NFS) and Windows return transiently:
+/// sharing and lock violations, dropped connections, stale handles, timeouts
+pub fn is_transient_error(e: &io::Error) -> bool {
+    use io::ErrorKind::*;
+    if matches!(e.kind(), Interrupted | TimedOut | WouldBlock | ResourceBusy | ConnectionReset
+        | ConnectionAborted | NotConnected | NetworkDown | NetworkUnreachable | HostUnreachable) {
+        return true;
+    }
+    #[cfg(windows)]
+    {
+        // Access denied (target held open), sharing / lock violation, network errors
+        if e.kind() == PermissionDenied || matches!(e.raw_os_error(), Some(32 | 33 | 59 | 64)) {
+            return true;
+        }
+    }
+    #[cfg(unix)]
+    {
+        if matches!(e.raw_os_error(), Some(libc::EIO | libc::ESTALE)) {
+            return true;
+        }
+    }
+    false
+}
+
+/// Run the file system operation `op` (`what` on `path`, for the log), retrying
+/// it on transient errors (see `set_io_retry`); other errors are returned at once
+pub fn with_retry<T>(what: &str, path: &str, mut op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let attempts = IO_RETRY_ATTEMPTS.load(Ordering::Relaxed);
+    let mut delay = IO_RETRY_BACKOFF_MS.load(Ordering::Relaxed);
+    let mut attempt = 1;
+    loop {
+        match op() {
+            Err(e) if attempt < attempts && is_transient_error(&e) => {
+                debug_print(&format!("with_retry: {} of {} failed (attempt {} of {}): {}; retrying in {} ms",
+                    what, path, attempt, attempts, e, delay));
+                std::thread::sleep(std::time::Duration::from_millis(delay));
+                delay = delay.saturating_mul(2);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+// ============================================================================
+// Integrity footer
+// ============================================================================
+
+/// Magic of the footer ending every list file written: magic (8 bytes), list count
+/// (u64 LE), CRC32 of all the bytes of the file before the footer (u32 LE), then 4
+/// zero bytes. Compressed files carry it after the zstd stream, so a file can be
+/// checked without decompressing it. Files written before it are read unchecked.
+pub const FOOTER_MAGIC: &[u8; 8] = b"NSLSUM01";
+
+/// Bytes of the integrity footer
+const FOOTER_LEN: usize = 24;
+
+/// Integrity footer of a list file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileFooter {
+    pub nb_lists: u64,
+    pub crc32: u32,
+}
+
+impl FileFooter {
+    fn to_bytes(self) -> [u8; FOOTER_LEN] {
+        let mut bytes = [0u8; FOOTER_LEN];
+        bytes[..8].copy_from_slice(FOOTER_MAGIC);
+        bytes[8..16].copy_from_slice(&self.nb_lists.to_le_bytes());
+        bytes[16..20].copy_from_slice(&self.crc32.to_le_bytes());
+        bytes
+    }
+}
+
+/// Split a list file into its content and its footer (None: written without footer)
+fn split_footer(bytes: &[u8]) -> (&[u8], Option<FileFooter>) {
+    match bytes.len().checked_sub(FOOTER_LEN) {
+        Some(end) if bytes[end..].starts_with(FOOTER_MAGIC) => {
+            let footer = &bytes[end..];
+            let nb_lists = u64::from_le_bytes(footer[8..16].try_into().unwrap());
+            let crc32 = u32::from_le_bytes(footer[16..20].try_into().unwrap());
+            (&bytes[..end], Some(FileFooter { nb_lists, crc32 }))
+        }
+        _ => (bytes, None),
+    }
+}
+
+/// Content of a list file without its footer, once its CRC32 is checked
+fn verified_content<'a>(filepath: &str, bytes: &'a [u8]) -> io::Result<&'a [u8]> {
+    let (content, footer) = split_footer(bytes);
+    if let Some(footer) = footer {
+        let crc32 = crc32fast::hash(content);
+        if crc32 != footer.crc32 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                "Checksum mismatch in {}: footer records {:08x}, content hashes to {:08x}", filepath, footer.crc32, crc32)));
+        }
+    }
+    Ok(content)
+}
+
+/// Footer of an open list file, read from its last bytes without checking it
+fn read_footer(file: &mut File) -> io::Result<Option<FileFooter>> {
+    let mut tail = [0u8; FOOTER_LEN];
+    if file.metadata()?.len() < FOOTER_LEN as u64 {
+        return Ok(None);
+    }
+    file.seek(SeekFrom::End(-(FOOTER_LEN as i64)))?;
+    file.read_exact(&mut tail)?;
+    Ok(split_footer(&tail).1)
+}
+
+/// Open the list file at `filepath`, on whichever root of its directory holds it
+fn open_resolved(filepath: &str) -> io::Result<File> {
+    File::open(crate::storage::resolve_path(filepath))
+}
+
+/// `open_resolved`, retried on transient errors
+fn open_list_file<P: AsRef<std::path::Path>>(filepath: P) -> io::Result<File> {
+    let filepath = filepath.as_ref().to_string_lossy();
+    with_retry("open", &filepath, || open_resolved(&filepath))
+}
+
+/// Footer of the list file at `filepath`, not checked (None: written without footer)
+pub fn file_footer(filepath: &str) -> io::Result<Option<FileFooter>> {
+    with_retry("read", filepath, || read_footer(&mut open_resolved(filepath)?))
+}
+
+/// Check the footer of the list file at `filepath` without decoding it: the footer
+/// if the checksum matches, None for a file written without footer, an InvalidData
+/// error if the file is corrupted
+pub fn verify_file_checksum(filepath: &str) -> io::Result<Option<FileFooter>> {
+    let file = open_list_file(filepath)?;
+    let mmap = unsafe { Mmap::map(&file)? };
+    verified_content(filepath, &mmap[..])?;
+    Ok(split_footer(&mmap[..]).1)
+}
+
+/// Writer computing the CRC32 of the bytes written through it
+struct Crc32Writer<W: Write> {
+    inner: W,
+    hasher: crc32fast::Hasher,
+}
+
+impl<W: Write> Crc32Writer<W> {
+    fn new(inner: W) -> Self {
+        Self { inner, hasher: crc32fast::Hasher::new() }
+    }
+}
+
+impl<W: Write> Write for Crc32Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// True if the file at `filepath` is zstd-compressed (false if it cannot be read)
+pub fn is_compressed_file<P: AsRef<std::path::Path>>(filepath: P) -> bool {
+    let mut magic = [0u8; 4];
+    let filepath = filepath.as_ref().to_string_lossy();
+    with_retry("read", &filepath, || open_resolved(&filepath)?.read_exact(&mut magic)).is_ok() && &magic == ZSTD_MAGIC
+}
+
+/// On-disk format of a list file (see `file_format`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileFormat {
+    /// FORMAT_VERSION, or 0 for a file written before it (bare archive or no footer)
+    pub version: u8,
+    pub encoding: ListEncoding,
+    pub compressed: bool,
+    pub framed: bool,
+}
+
+/// Format of the list file at `filepath`, read from its first archive header (inside
+/// the zstd stream of a compressed file, and in the first frame of a framed file)
+/// and its footer, without decoding the lists
+pub fn file_format(filepath: &str) -> io::Result<FileFormat> {
+    let compressed = is_compressed_file(filepath);
+    let (mut reader, footer): (Box<dyn Read>, bool) = if let Some(bytes) = crate::storage::list_store().open(filepath)? {
+        // Databases hold the frames, their checksum is the database's
+        (Box::new(io::Cursor::new(bytes.into_vec())), true)
+    } else if compressed {
+        (Box::new(zstd::stream::read::Decoder::new(open_list_file(filepath)?)?), file_footer(filepath)?.is_some())
+    } else {
+        (Box::new(open_list_file(filepath)?), file_footer(filepath)?.is_some())
+    };
+    let mut header = [0u8; 8];
+    let read = reader.read_exact(&mut header).is_ok();
+    let framed = read && &header == FRAMED_MAGIC;
+    // A framed file without frame holds no archive: only its footer tells its version
+    let mut headered = read;
+    if framed {
+        let mut headers = [0u8; FILE_HEADER_LEN - 8 + FRAME_HEADER_LEN];
+        if reader.read_exact(&mut headers).is_err() || reader.read_exact(&mut header).is_err() {
+            header = [0u8; 8];
+        } else {
+            headered = is_versioned_header(&header);
+        }
+    } else {
+        headered = read && is_versioned_header(&header);
+    }
+    let encoding = match &header {
+        h if h == DELTA_MAGIC => ListEncoding::Delta,
+        h if h == PACKED_MAGIC => ListEncoding::Packed,
+        _ => ListEncoding::Plain,
+    };
+    let version = if headered && footer { FORMAT_VERSION } else { 0 };
+    Ok(FileFormat { version, encoding, compressed, framed })
+}
+
+/// Call `f` on the content of a list file: memory-mapped, or decompressed into an
+/// aligned buffer if the file is zstd-compressed (rkyv needs aligned bytes), or read
+/// from its database (see `storage`). The footer is checked and stripped first.
+fn with_file_bytes<R>(filepath: &str, f: impl FnOnce(&[u8]) -> io::Result<R>) -> io::Result<R> {
+    if let Some(bytes) = crate::storage::list_store().open(filepath)? {
+        return f(&bytes[..]);
+    }
+    let file = open_list_file(filepath)?;
+    let mmap = unsafe { Mmap::map(&file)? };
+    let content = verified_content(filepath, &mmap[..])?;
+    if !content.starts_with(ZSTD_MAGIC) {
+        return f(content);
+    }
+    let mut decoder = zstd::stream::read::Decoder::with_buffer(content)?;
+    let mut bytes = AlignedVec::new();
+    let mut buffer = vec![0u8; 1 << 20];
+    loop {
+        let read = decoder.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        bytes.extend_from_slice(&buffer[..read]);
+    }
+    f(verified_content(filepath, &bytes[..])?)
+}
+
+/// Serialize lists in the given encoding (bytes ready to be written to a file)
+#[allow(clippy::ptr_arg)] // rkyv serializes the Vec itself
+pub fn encode_lists(list: &Vec<NoSetListSerialized>, encoding: ListEncoding) -> Result<Vec<u8>, String> {
+    match encoding {
+        ListEncoding::Plain => {
+            let payload = rkyv::to_bytes::<_, 256>(list).map_err(|e| e.to_string())?;
+            let mut bytes = Vec::with_capacity(PLAIN_MAGIC.len() + payload.len());
+            bytes.extend_from_slice(PLAIN_MAGIC);
+            bytes.extend_from_slice(&payload);
+            Ok(bytes)
+        }
+        ListEncoding::Delta => {
+            let deltas: Vec<NoSetListDelta> = list.iter().map(NoSetListDelta::from_serialized).collect();
+            let payload = rkyv::to_bytes::<_, 256>(&deltas).map_err(|e| e.to_string())?;
+            let mut bytes = Vec::with_capacity(DELTA_MAGIC.len() + payload.len());
+            bytes.extend_from_slice(DELTA_MAGIC);
+            bytes.extend_from_slice(&payload);
+            Ok(bytes)
+        }
+        ListEncoding::Packed => {
+            let mut bytes = Vec::with_capacity(PACKED_MAGIC.len() + list.len() * PACKED_LIST_BYTES);
+            bytes.extend_from_slice(PACKED_MAGIC);
+            for l in list.iter() {
+                bytes.extend_from_slice(&NoSetListPacked::from_serialized(l).0);
+            }
+            Ok(bytes)
+        }
+    }
+}
+
+// ============================================================================
+// Atomic writes
+// ============================================================================
+
+/// Temporary file a data file is written to before being renamed into place
+/// ("x.rkyv" -> "x.rkyv.tmp.<pid>", which --gc recognizes as a leftover of "x.rkyv")
+pub fn atomic_tmp_path(filename: &str) -> String {
+    format!("{}.tmp.{}", filename, std::process::id())
+}
+
+/// Rename the fully written (and synced) `tmp` over `filename`, then sync the
+/// directory so the rename itself survives a crash. On Windows the rename fails
+/// while another process (indexer, antivirus, a reader) holds the target open:
+/// it is retried like every transient error (see `with_retry`). The tmp file is
+/// removed if the rename fails.
+pub fn commit_atomic_write(tmp: &str, filename: &str) -> io::Result<()> {
+    let (tmp, filename) = crate::storage::resolve_rename(tmp, filename);
+    let (tmp, filename) = (tmp.as_str(), filename.as_str());
+    if let Err(e) = with_retry("rename", filename, || std::fs::rename(tmp, filename)) {
+        let _ = std::fs::remove_file(tmp);
+        return Err(e);
+    }
+    #[cfg(unix)]
+    if let Some(dir) = std::path::Path::new(filename).parent() {
+        let dir = if dir.as_os_str().is_empty() { std::path::Path::new(".") } else { dir };
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Write `bytes` to `filename` atomically: to a tmp file, fsynced, then renamed
+/// into place, so a crash never leaves a truncated `filename` behind
+pub fn write_file_atomic(filename: &str, bytes: &[u8]) -> io::Result<()> {
+    let tmp = atomic_tmp_path(filename);
+    let written = with_retry("write", &tmp, || {
+        let mut file = File::create(&tmp)?;
+        file.write_all(bytes)?;
+        file.sync_all()
+    });
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e);
+    }
+    commit_atomic_write(&tmp, filename)
+}
+
+/// Save a vector of `NoSetListSerialized` using rkyv to `filename`, in the selected
+/// output encoding (see `set_output_encoding`), zstd-compressed if selected
+/// (see `set_output_compression`). The file is written atomically (see `write_file_atomic`).
 /// Returns true on success, false on error (legacy API retained).
 pub fn save_to_file_serialized(list: &Vec<NoSetListSerialized>, filename: &str) -> bool {
-    debug_print(&format!("save_to_file_serialized: Serializing {} n-lists to {} using rkyv", list.len(), filename));
+    if crate::storage::list_store().keeps_frames(filename) {
+        // The store keeps frames (databases): write the lists as a framed file
+        let written = ListFileWriter::create(filename).and_then(|mut writer| {
+            for chunk in list.chunks(FRAME_LISTS) {
+                writer.write_frame(&chunk.to_vec())?;
+            }
+            writer.finish()
+        });
+        if let Err(e) = &written {
+            debug_print(&format!("save_to_file_nlist: Error writing {}: {}", filename, e));
+        }
+        return written.is_ok();
+    }
+    save_lists_as(list, filename, output_encoding(), output_compression())
+}
+
+/// `save_to_file_serialized` in the given encoding and compression (regardless of
+/// the selected ones), to a file on disk. Returns true on success, false on error.
+#[allow(clippy::ptr_arg)] // rkyv serializes the Vec itself
+pub fn save_lists_as(list: &Vec<NoSetListSerialized>, filename: &str, encoding: ListEncoding, compression: Option<i32>) -> bool {
+    debug_print(&format!("save_to_file_serialized: Serializing {} n-lists to {} using rkyv ({:?})", list.len(), filename, encoding));
 
-    let bytes = match rkyv::to_bytes::<_, 256>(list) {
+    let mut bytes = match encode_lists(list, encoding) {
         Ok(b) => b,
         Err(e) => {
             debug_print(&format!("save_to_file_nlist: Error serializing: {}", e));
             return false;
         }
     };
+    if let Some(level) = compression {
+        bytes = match zstd::bulk::compress(&bytes, level) {
+            Ok(b) => b,
+            Err(e) => {
+                debug_print(&format!("save_to_file_nlist: Error compressing: {}", e));
+                return false;
+            }
+        };
+    }
+    let footer = FileFooter { nb_lists: list.len() as u64, crc32: crc32fast::hash(&bytes) };
+    bytes.extend_from_slice(&footer.to_bytes());
 
-    match std::fs::write(filename, bytes) {
+    // The file content changes: drop any cached copy
+    invalidate_cached_batch(filename);
+
+    match write_file_atomic(&crate::storage::placement_path(filename), &bytes) {
         Ok(_) => {
             debug_print(&format!("save_to_file_nlist: Saved {} n-lists to {}", list.len(), filename));
             true
@@ -31,61 +508,803 @@ pub fn save_to_file_serialized(list: &Vec<NoSetListSerialized>, filename: &str)
     }
 }
 
-/// Read a vector of `NoSetListSerialized` from `filename` using memory mapping and rkyv.
-/// Returns `Some(vec)` on success, `None` on error.
-pub fn read_from_file_serialized(filename: &str) -> Option<Vec<NoSetListSerialized>> {
-    debug_print(&format!("read_from_file_serialized: Loading n-lists from {} using rkyv", filename));
+/// Streaming writer of framed list files: the lists are serialized one frame at a
+/// time, in the selected output encoding and compression, so only the current frame
+/// is held in memory (save_to_file_serialized serializes the whole file at once).
+/// finish() appends the frame index and the footer, and fills in the header; a
+/// compressed stream cannot be rewritten, so compressed files keep an empty header
+/// and no index.
+/// With the sqlite storage backend, the frames go to the database instead.
+pub struct ListFileWriter {
+    sink: FrameSink,
+    encoding: ListEncoding,
+    nb_lists: u64,
+    offset: u64,                // bytes written so far (uncompressed)
+    index: Vec<(u64, u64)>,     // (frame offset, list count)
+    paths: Option<(String, String)>, // (tmp, final) of files, renamed into place by finish
+}
 
-    let file = match File::open(filename) {
-        Ok(f) => f,
-        Err(e) => {
-            debug_print(&format!("read_from_file_nlist: Error opening {}: {}", filename, e));
-            return None;
+enum FrameSink {
+    File(Crc32Writer<BufWriter<File>>),     // header written before, not hashed (see finish)
+    Zstd(zstd::stream::write::Encoder<'static, Crc32Writer<BufWriter<File>>>),
+    Store(Box<dyn crate::storage::FrameWriter>),
+}
+
+impl FrameSink {
+    /// Write bytes to the file (not used for databases, which store whole frames)
+    fn write_all(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match self {
+            FrameSink::File(f) => f.write_all(bytes),
+            FrameSink::Zstd(z) => z.write_all(bytes),
+            FrameSink::Store(_) => Err(io::Error::other("Store sinks keep whole frames")),
         }
-    };
+    }
+}
 
-    let mmap = unsafe {
-        match Mmap::map(&file) {
-            Ok(m) => m,
-            Err(e) => {
-                debug_print(&format!("read_from_file_nlist: Error mapping {}: {}", filename, e));
-                return None;
+/// Frame of `lists` in `encoding`: frame header, payload, padding to 8 bytes
+#[allow(clippy::ptr_arg)] // rkyv serializes the Vec itself
+pub(crate) fn encode_frame(lists: &Vec<NoSetListSerialized>, encoding: ListEncoding) -> io::Result<Vec<u8>> {
+    let payload = encode_lists(lists, encoding)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len().next_multiple_of(8));
+    frame.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    frame.extend_from_slice(&(lists.len() as u64).to_le_bytes());
+    frame.extend_from_slice(&payload);
+    frame.resize(FRAME_HEADER_LEN + payload.len().next_multiple_of(8), 0);
+    Ok(frame)
+}
+
+impl ListFileWriter {
+    /// Start writing `filename` and write the file header. Files are written to a
+    /// tmp file that finish renames into place (see `write_file_atomic`).
+    pub fn create(filename: &str) -> io::Result<Self> {
+        Self::create_as(filename, output_encoding(), output_compression())
+    }
+
+    /// `create` in the given encoding and compression (regardless of the selected ones)
+    pub fn create_as(filename: &str, encoding: ListEncoding, compression: Option<i32>) -> io::Result<Self> {
+        // The file content changes: drop any cached copy
+        invalidate_cached_batch(filename);
+        let mut paths = None;
+        let sink = if let Some(writer) = crate::storage::list_store().create(filename)? {
+            FrameSink::Store(writer)
+        } else {
+            let target = crate::storage::placement_path(filename);
+            let tmp = atomic_tmp_path(&target);
+            let mut file = BufWriter::new(with_retry("create", &tmp, || File::create(&tmp))?);
+            paths = Some((tmp, target));
+            match compression {
+                Some(level) => {
+                    let mut sink = FrameSink::Zstd(zstd::stream::write::Encoder::new(Crc32Writer::new(file), level)?);
+                    sink.write_all(FRAMED_MAGIC)?;
+                    sink.write_all(&[0u8; FILE_HEADER_LEN - 8])?;
+                    sink
+                }
+                None => {
+                    file.write_all(FRAMED_MAGIC)?;
+                    file.write_all(&[0u8; FILE_HEADER_LEN - 8])?;
+                    FrameSink::File(Crc32Writer::new(file))
+                }
             }
+        };
+        Ok(Self { sink, encoding, nb_lists: 0, offset: FILE_HEADER_LEN as u64, index: Vec::new(), paths })
+    }
+
+    /// Serialize `lists` as the next frame
+    #[allow(clippy::ptr_arg)] // rkyv serializes the Vec itself
+    pub fn write_frame(&mut self, lists: &Vec<NoSetListSerialized>) -> io::Result<()> {
+        let frame = encode_frame(lists, self.encoding)?;
+        match &mut self.sink {
+            FrameSink::Store(d) => d.write_frame(lists.len() as u64, &frame)?,
+            sink => sink.write_all(&frame)?,
+        }
+        self.index.push((self.offset, lists.len() as u64));
+        self.offset += frame.len() as u64;
+        self.nb_lists += lists.len() as u64;
+        Ok(())
+    }
+
+    /// Write the frame index, the footer and the header, then sync the file and
+    /// rename it into place; returns the number of lists written
+    pub fn finish(self) -> io::Result<u64> {
+        match self.sink {
+            FrameSink::File(mut f) => {
+                for (offset, nb_lists) in self.index.iter() {
+                    f.write_all(&offset.to_le_bytes())?;
+                    f.write_all(&nb_lists.to_le_bytes())?;
+                }
+                // The header is final only now: its CRC32 is combined with the rest
+                let mut header = [0u8; FILE_HEADER_LEN];
+                header[..8].copy_from_slice(FRAMED_MAGIC);
+                header[8..16].copy_from_slice(&self.nb_lists.to_le_bytes());
+                header[16..].copy_from_slice(&self.offset.to_le_bytes());
+                let mut hasher = crc32fast::Hasher::new();
+                hasher.update(&header);
+                hasher.combine(&f.hasher);
+                let footer = FileFooter { nb_lists: self.nb_lists, crc32: hasher.finalize() };
+                let mut file = f.inner.into_inner().map_err(|e| e.into_error())?;
+                file.write_all(&footer.to_bytes())?;
+                file.seek(SeekFrom::Start(0))?;
+                file.write_all(&header)?;
+                file.sync_all()?;
+            }
+            FrameSink::Zstd(z) => {
+                let mut f = z.finish()?;
+                let footer = FileFooter { nb_lists: self.nb_lists, crc32: f.hasher.clone().finalize() };
+                f.inner.write_all(&footer.to_bytes())?;
+                f.inner.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+            }
+            FrameSink::Store(d) => d.finish(self.nb_lists, self.offset)?,
+        }
+        if let Some((tmp, filename)) = &self.paths {
+            commit_atomic_write(tmp, filename)?;
         }
+        Ok(self.nb_lists)
+    }
+
+    /// `finish` without the final rename: the file stays under its tmp name until
+    /// the returned write is committed (files stored in a database are committed now)
+    pub fn finish_pending(mut self) -> io::Result<PendingListFile> {
+        let paths = self.paths.take();
+        let nb_lists = self.finish()?;
+        Ok(PendingListFile { paths, nb_lists })
+    }
+}
+
+/// List file written and synced under its tmp name (see `ListFileWriter::finish_pending`)
+pub struct PendingListFile {
+    paths: Option<(String, String)>, // (tmp, final)
+    pub nb_lists: u64,
+}
+
+impl PendingListFile {
+    /// Rename the file into place
+    pub fn commit(self) -> io::Result<()> {
+        match &self.paths {
+            Some((tmp, filename)) => commit_atomic_write(tmp, filename),
+            None => Ok(()),
+        }
+    }
+}
+
+fn validation_error<E: std::fmt::Debug>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Archive validation failed: {:?}", e))
+}
+
+/// The two u64 LE of a 16-byte header or index entry
+fn read_u64_pair(bytes: &[u8]) -> (u64, u64) {
+    (u64::from_le_bytes(bytes[..8].try_into().unwrap()), u64::from_le_bytes(bytes[8..16].try_into().unwrap()))
+}
+
+/// rkyv archives of a list file: the file itself, or the payload and list count of
+/// each frame of a framed file (None: count not recorded). The frames are located
+/// from the index when the file has one, by walking the frame headers otherwise.
+fn archives(bytes: &[u8]) -> io::Result<Vec<(&[u8], Option<u64>)>> {
+    if !bytes.starts_with(FRAMED_MAGIC) {
+        return Ok(vec![(bytes, None)]);
+    }
+    let truncated = || io::Error::new(io::ErrorKind::InvalidData, "Truncated frame in framed list file");
+    let frame_at = |offset: usize| -> io::Result<(&[u8], u64, usize)> {
+        let header = bytes.get(offset..offset + FRAME_HEADER_LEN).ok_or_else(truncated)?;
+        let (len, nb_lists) = read_u64_pair(header);
+        let start = offset + FRAME_HEADER_LEN;
+        let payload = bytes.get(start..start + len as usize).ok_or_else(truncated)?;
+        Ok((payload, nb_lists, start + (len as usize).next_multiple_of(8)))
     };
+    let (_, index_offset) = read_u64_pair(bytes.get(8..FILE_HEADER_LEN).ok_or_else(truncated)?);
+    let mut frames = Vec::new();
+    if index_offset == 0 {
+        // No index (compressed, or not finished): walk the frames up to the end
+        let mut offset = FILE_HEADER_LEN;
+        while offset < bytes.len() {
+            let (payload, nb_lists, end) = frame_at(offset)?;
+            frames.push((payload, Some(nb_lists)));
+            offset = end;
+        }
+        return Ok(frames);
+    }
+    let index = bytes.get(index_offset as usize..).filter(|i| i.len() % FRAME_HEADER_LEN == 0).ok_or_else(truncated)?;
+    for entry in index.chunks(FRAME_HEADER_LEN) {
+        let (offset, indexed) = read_u64_pair(entry);
+        let (payload, nb_lists, end) = frame_at(offset as usize)?;
+        if nb_lists != indexed || end as u64 > index_offset {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Frame index does not match the frames"));
+        }
+        frames.push((payload, Some(nb_lists)));
+    }
+    Ok(frames)
+}
 
-    match check_archived_root::<Vec<NoSetListSerialized>>(&mmap) {
-        Ok(archived_vec) => {
-            let deserialized: Vec<NoSetListSerialized> = archived_vec
-                .deserialize(&mut rkyv::Infallible)
-                .expect("Deserialization should not fail after validation");
-            debug_print(&format!("read_from_file_serialized: deserialized {} n-lists", deserialized.len()));
-            Some(deserialized)
+/// List count recorded in the header of an indexed framed file, read without mapping
+/// the file, or recorded in its database (None: another format, or no index to check
+/// the header against)
+fn indexed_count(filepath: &str) -> io::Result<Option<u64>> {
+    if let Some(nb_lists) = crate::storage::list_store().count(filepath)? {
+        return Ok(Some(nb_lists));
+    }
+    with_retry("read", filepath, || {
+        let mut file = open_resolved(filepath)?;
+        let mut header = [0u8; FILE_HEADER_LEN];
+        if file.read_exact(&mut header).is_err() || !header.starts_with(FRAMED_MAGIC) {
+            return Ok(None);
         }
-        Err(e) => {
-            debug_print(&format!("read_from_file_serialized: Validation error for {}: {:?}", filename, e));
-            None
+        let (nb_lists, index_offset) = read_u64_pair(&header[8..]);
+        let mut len = file.metadata()?.len();
+        if read_footer(&mut file)?.is_some() {
+            len -= FOOTER_LEN as u64;
         }
+        let consistent = index_offset >= FILE_HEADER_LEN as u64 && index_offset <= len
+            && (len - index_offset) % FRAME_HEADER_LEN as u64 == 0;
+        Ok(if consistent { Some(nb_lists) } else { None })
+    })
+}
+
+/// Check that a frame holds the number of lists its header records
+fn check_frame_count(nb_lists: usize, recorded: Option<u64>) -> io::Result<()> {
+    match recorded {
+        Some(count) if count != nb_lists as u64 => Err(io::Error::new(io::ErrorKind::InvalidData,
+            format!("Frame records {} lists but holds {}", count, nb_lists))),
+        _ => Ok(()),
     }
 }
 
-/// Load lists from a file path and return io::Result<Vec<NoSetListSerialized>> (uses rkyv + mmap)
+/// A validated archive of lists (a whole file or a frame), in any encoding
+enum ListArchive<'a> {
+    Plain(&'a rkyv::Archived<Vec<NoSetListSerialized>>),
+    Delta(&'a rkyv::Archived<Vec<NoSetListDelta>>),
+    Packed(&'a [u8]),   // PACKED_LIST_BYTES per list
+}
+
+impl<'a> ListArchive<'a> {
+    /// Validate an archive (dispatched on its versioned header) and the list count its
+    /// frame records. A header of an unknown kind or version is refused rather than
+    /// read as a bare archive of format version 0.
+    fn open(archive: &'a [u8], recorded: Option<u64>) -> io::Result<Self> {
+        let view = if let Some(payload) = archive.strip_prefix(&PLAIN_MAGIC[..]) {
+            ListArchive::Plain(check_archived_root::<Vec<NoSetListSerialized>>(payload).map_err(validation_error)?)
+        } else if let Some(payload) = archive.strip_prefix(&DELTA_MAGIC[..]) {
+            ListArchive::Delta(check_archived_root::<Vec<NoSetListDelta>>(payload).map_err(validation_error)?)
+        } else if let Some(records) = archive.strip_prefix(&PACKED_MAGIC[..]) {
+            let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, format!("Packed payload validation failed: {}", msg));
+            if records.len() % PACKED_LIST_BYTES != 0 {
+                return Err(invalid(format!("{} bytes is not a whole number of lists", records.len())));
+            }
+            if let Some(i) = records.chunks(PACKED_LIST_BYTES).position(|r| NoSetListPacked::decode(r).is_none()) {
+                return Err(invalid(format!("list {} is inconsistent", i)));
+            }
+            ListArchive::Packed(records)
+        } else if is_versioned_header(archive) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                "Unsupported list format '{}' (written by a newer version?)", String::from_utf8_lossy(&archive[..8]))));
+        } else {
+            ListArchive::Plain(check_archived_root::<Vec<NoSetListSerialized>>(archive).map_err(validation_error)?)
+        };
+        check_frame_count(view.len(), recorded)?;
+        Ok(view)
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            ListArchive::Plain(lists) => lists.len(),
+            ListArchive::Delta(lists) => lists.len(),
+            ListArchive::Packed(records) => records.len() / PACKED_LIST_BYTES,
+        }
+    }
+
+    /// Decode the lists `from..to` (clamped to the archive)
+    fn decode(&self, from: usize, to: usize) -> Vec<NoSetListSerialized> {
+        let to = to.min(self.len());
+        let from = from.min(to);
+        match self {
+            ListArchive::Plain(lists) => lists[from..to].iter()
+                .map(|l| l.deserialize(&mut rkyv::Infallible).expect("Deserialization should never fail with Infallible"))
+                .collect(),
+            ListArchive::Delta(lists) => lists[from..to].iter().map(|l| l.to_serialized()).collect(),
+            ListArchive::Packed(records) => records[from * PACKED_LIST_BYTES..to * PACKED_LIST_BYTES]
+                .chunks(PACKED_LIST_BYTES)
+                .map(|r| NoSetListPacked::decode(r).expect("packed lists are checked when opened"))
+                .collect(),
+        }
+    }
+
+    /// Build the list `i` as a NoSetList (plain archives are read in place)
+    fn no_set_list(&self, i: usize) -> NoSetList {
+        match self {
+            ListArchive::Plain(lists) => lists[i].to_no_set_list(),
+            ListArchive::Delta(lists) => NoSetList::from_serialized(&lists[i].to_serialized()),
+            ListArchive::Packed(records) => NoSetList::from_serialized(
+                &NoSetListPacked::decode(&records[i * PACKED_LIST_BYTES..(i + 1) * PACKED_LIST_BYTES])
+                    .expect("packed lists are checked when opened")),
+        }
+    }
+}
+
+/// The validated lists of a mapped list file, read in place (see `with_mapped_lists`)
+pub struct MappedLists<'a> {
+    archives: Vec<ListArchive<'a>>,
+}
+
+impl MappedLists<'_> {
+    /// Number of lists in the file
+    pub fn len(&self) -> usize {
+        self.archives.iter().map(|a| a.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The lists of the file, last to first (the order the processing stack pops
+    /// them), each built as a NoSetList only when reached
+    pub fn iter_rev(&self) -> impl Iterator<Item = NoSetList> + '_ {
+        self.archives.iter().rev().flat_map(|a| (0..a.len()).rev().map(move |i| a.no_set_list(i)))
+    }
+}
+
+/// Validate the lists of a file and hand them to `f` without decoding them up front:
+/// the file stays memory-mapped while `f` runs, and each list is built as a NoSetList
+/// when iterated, so the whole file never exists as owned lists in memory (a compressed
+/// file is decompressed in memory first). Any encoding, framed or not.
+pub fn with_mapped_lists<R>(filepath: &str, f: impl FnOnce(&MappedLists) -> io::Result<R>) -> io::Result<R> {
+    with_file_bytes(filepath, |bytes| {
+        let archives = archives(bytes)?.into_iter()
+            .map(|(archive, recorded)| ListArchive::open(archive, recorded))
+            .collect::<io::Result<Vec<_>>>()?;
+        f(&MappedLists { archives })
+    })
+}
+
+/// Load lists from a file path and return io::Result<Vec<NoSetListSerialized>> (uses rkyv + mmap).
+/// Plain, delta-encoded and bit-packed files are all accepted (detected from the
+/// header), framed or not, zstd-compressed or not, and so are legacy nlist files.
 pub fn load_lists_from_file(filepath: &str) -> io::Result<Vec<NoSetListSerialized>> {
-    let file = File::open(filepath)?;
-    let mmap = unsafe { Mmap::map(&file)? };
+    // Legacy bincode inputs (nlist_*.bin) are converted on the fly
+    if crate::migrate::is_legacy_bincode(filepath) {
+        return crate::migrate::load_legacy_bincode(filepath);
+    }
+    with_file_bytes(filepath, decode_lists)
+}
 
-    match check_archived_root::<Vec<NoSetListSerialized>>(&mmap[..]) {
-        Ok(archived_lists) => {
-            let lists: Vec<NoSetListSerialized> = archived_lists
-                .deserialize(&mut rkyv::Infallible)
-                .expect("Deserialization should never fail with Infallible");
-            Ok(lists)
+/// Decode all the lists of the content of a list file (any format)
+pub(crate) fn decode_lists(bytes: &[u8]) -> io::Result<Vec<NoSetListSerialized>> {
+    let mut lists: Vec<NoSetListSerialized> = Vec::new();
+    for (archive, recorded) in archives(bytes)? {
+        let view = ListArchive::open(archive, recorded)?;
+        let decoded = view.decode(0, view.len());
+        if lists.is_empty() {
+            lists = decoded;
+        } else {
+            lists.extend(decoded);
         }
-        Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Archive validation failed: {:?}", e))),
     }
+    Ok(lists)
+}
+
+/// Decode the lists of a file `chunk_size` at a time, calling `f` on each chunk
+/// (any encoding, framed or not). Only one chunk is decoded in memory at
+/// once; the file itself is memory-mapped (a compressed file is decompressed in memory
+/// first), or read frame by frame from a store keeping its frames (see
+/// ListStore::frames). A chunk never spans two frames. Returns the number of lists read.
+pub fn load_lists_in_chunks<F>(filepath: &str, chunk_size: usize, mut f: F) -> io::Result<u64>
+where
+    F: FnMut(Vec<NoSetListSerialized>) -> io::Result<()>,
+{
+    let chunk_size = chunk_size.max(1);
+    if let Some(mut frames) = crate::storage::list_store().frames(filepath)? {
+        let truncated = || io::Error::new(io::ErrorKind::InvalidData, "Truncated frame in framed list file");
+        let mut total = 0u64;
+        while let Some(frame) = frames.next_frame()? {
+            let (len, nb_lists) = read_u64_pair(frame.get(..FRAME_HEADER_LEN).ok_or_else(truncated)?);
+            let payload = frame.get(FRAME_HEADER_LEN..FRAME_HEADER_LEN + len as usize).ok_or_else(truncated)?;
+            let view = ListArchive::open(payload, Some(nb_lists))?;
+            for from in (0..view.len()).step_by(chunk_size) {
+                let lists = view.decode(from, from + chunk_size);
+                total += lists.len() as u64;
+                f(lists)?;
+            }
+        }
+        return Ok(total);
+    }
+    with_file_bytes(filepath, |bytes| {
+        let mut total = 0u64;
+        for (archive, recorded) in archives(bytes)? {
+            let view = ListArchive::open(archive, recorded)?;
+            for from in (0..view.len()).step_by(chunk_size) {
+                let lists = view.decode(from, from + chunk_size);
+                total += lists.len() as u64;
+                f(lists)?;
+            }
+        }
+        Ok(total)
+    })
+}
+
+/// Load `count` lists of a file starting at list `start` (fewer if the file ends
+/// before). The frames of a framed file that lie outside the range are skipped
+/// without being validated nor decoded, so only the pages of the needed frames are
+/// read; other files are decoded whole and sliced.
+pub fn load_lists_range(filepath: &str, start: usize, count: usize) -> io::Result<Vec<NoSetListSerialized>> {
+    let end = start.saturating_add(count);
+    with_file_bytes(filepath, |bytes| {
+        let mut lists: Vec<NoSetListSerialized> = Vec::new();
+        let mut position = 0usize; // index of the first list of the current archive
+        for (archive, recorded) in archives(bytes)? {
+            if position >= end {
+                break;
+            }
+            if let Some(nb_lists) = recorded
+                && position + nb_lists as usize <= start {
+                position += nb_lists as usize;
+                continue;
+            }
+            let view = ListArchive::open(archive, recorded)?;
+            lists.extend(view.decode(start.saturating_sub(position), end - position));
+            position += view.len();
+        }
+        Ok(lists)
+    })
+}
+
+/// Count the lists stored in a file without decoding them (any encoding, framed
+/// or not, compressed or not). For an indexed framed file this is a read of
+/// the header; other files are mapped and their archives validated.
+pub fn count_lists_in_file(filepath: &str) -> io::Result<u64> {
+    if crate::migrate::is_legacy_bincode(filepath) {
+        return crate::migrate::load_legacy_bincode(filepath).map(|lists| lists.len() as u64);
+    }
+    if let Some(nb_lists) = indexed_count(filepath)? {
+        return Ok(nb_lists);
+    }
+    with_file_bytes(filepath, |bytes| {
+        let mut total = 0u64;
+        for (archive, recorded) in archives(bytes)? {
+            total += ListArchive::open(archive, recorded)?.len() as u64;
+        }
+        Ok(total)
+    })
+}
+
+// ============================================================================
+// LRU cache of decoded batches
+// ============================================================================
+
+// Decoded batches of the run (--cache-batches; capacity 0 = cache disabled, the default).
+// Each cached batch costs as much RAM as the decoded file (several GB for full batches).
+static BATCH_CACHE: Mutex<BatchCache> = Mutex::new(BatchCache::new(0));
+
+/// One decoded batch, identified by its path and the file length/mtime when loaded
+struct CachedBatch {
+    path: String,
+    len: u64,
+    modified: Option<SystemTime>,
+    lists: Arc<Vec<NoSetListSerialized>>,
+}
+
+/// LRU cache of decoded batches: at most `capacity` batches, least recently used first
+pub struct BatchCache {
+    capacity: usize,
+    batches: Vec<CachedBatch>,
+}
+
+impl BatchCache {
+    pub const fn new(capacity: usize) -> Self {
+        Self { capacity, batches: Vec::new() }
+    }
+
+    /// Keep at most `capacity` batches (0 disables the cache)
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        let excess = self.batches.len().saturating_sub(capacity);
+        self.batches.drain(..excess);
+    }
+
+    /// Drop the cached copy of `filepath`, if any
+    pub fn invalidate(&mut self, filepath: &str) {
+        self.batches.retain(|c| c.path != filepath);
+    }
+
+    /// The cached lists of `filepath` if the file still has this length and mtime
+    fn lookup(&mut self, filepath: &str, len: u64, modified: Option<SystemTime>) -> Option<Arc<Vec<NoSetListSerialized>>> {
+        let pos = self.batches.iter().position(|c| c.path == filepath)?;
+        let cached = self.batches.remove(pos);
+        if cached.len != len || cached.modified != modified {
+            return None;
+        }
+        debug_print(&format!("load_lists_cached: cache hit for {}", filepath));
+        let lists = Arc::clone(&cached.lists);
+        self.batches.push(cached);
+        Some(lists)
+    }
+
+    /// Cache the lists of `filepath`, evicting the least recently used batch if full
+    fn insert(&mut self, filepath: &str, len: u64, modified: Option<SystemTime>, lists: &Arc<Vec<NoSetListSerialized>>) {
+        self.invalidate(filepath);
+        if self.batches.len() >= self.capacity {
+            let evicted = self.batches.remove(0);
+            debug_print(&format!("load_lists_cached: evicting {}", evicted.path));
+        }
+        self.batches.push(CachedBatch { path: filepath.to_string(), len, modified, lists: Arc::clone(lists) });
+    }
+
+    /// Load lists from a file path through `cache` (see load_lists_cached)
+    pub fn load(cache: &Mutex<BatchCache>, filepath: &str) -> io::Result<Arc<Vec<NoSetListSerialized>>> {
+        if cache.lock().unwrap().capacity == 0 {
+            return load_lists_from_file(filepath).map(Arc::new);
+        }
+
+        let (len, modified) = if crate::storage::list_store().keeps_frames(filepath) {
+            let (len, modified) = crate::storage::file_metadata(filepath)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} not found", filepath)))?;
+            (len, modified.map(|s| std::time::UNIX_EPOCH + std::time::Duration::from_secs(s as u64)))
+        } else {
+            let metadata = std::fs::metadata(crate::storage::resolve_path(filepath))?;
+            (metadata.len(), metadata.modified().ok())
+        };
+        if let Some(lists) = cache.lock().unwrap().lookup(filepath, len, modified) {
+            return Ok(lists);
+        }
+
+        // Read outside the lock: decoding a full batch takes a while
+        let lists = Arc::new(load_lists_from_file(filepath)?);
+        let mut cache = cache.lock().unwrap();
+        if cache.capacity > 0 {
+            cache.insert(filepath, len, modified, &lists);
+        }
+        Ok(lists)
+    }
+}
+
+/// Set how many decoded batches are kept in memory (0 disables the cache)
+pub fn set_batch_cache_capacity(capacity: usize) {
+    BATCH_CACHE.lock().unwrap().set_capacity(capacity);
+}
+
+/// True if decoded batches are cached (--cache-batches)
+pub fn batch_cache_enabled() -> bool {
+    BATCH_CACHE.lock().unwrap().capacity > 0
+}
+
+/// Drop the cached copy of `filepath`, if any (call when the file is rewritten or deleted)
+pub fn invalidate_cached_batch(filepath: &str) {
+    BATCH_CACHE.lock().unwrap().invalidate(filepath);
+}
+
+/// Load lists from a file path through the batch cache.
+///
+/// A cached batch is reused only if the file length and modification time are unchanged,
+/// so a file rewritten by another step is always read again. With the cache disabled
+/// this is `load_lists_from_file` wrapped in an `Arc`.
+pub fn load_lists_cached(filepath: &str) -> io::Result<Arc<Vec<NoSetListSerialized>>> {
+    BatchCache::load(&BATCH_CACHE, filepath)
 }
 
 // Minimal debug_print to mirror crate function expectations when used from this module
 fn debug_print(s: &str) {
     crate::utils::debug_print(s);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn make_list(card: usize) -> NoSetListSerialized {
+        NoSetListSerialized { n: 3, max_card: card, no_set_list: vec![0, 1, card], remaining_cards_list: vec![] }
+    }
+
+    #[test]
+    fn cached_batch_is_reloaded_after_rewrite() {
+        let dir = crate::test_dir::TestDir::new("cache");
+        let path = dir.join("batch.rkyv").to_string_lossy().into_owned();
+
+        // A cache of its own: the cache of the run is left as the other tests expect it
+        let cache = Mutex::new(BatchCache::new(64));
+        assert!(save_to_file_serialized(&vec![make_list(3)], &path));
+        let first = BatchCache::load(&cache, &path).expect("load");
+        let again = BatchCache::load(&cache, &path).expect("load");
+        assert!(Arc::ptr_eq(&first, &again), "second load should come from the cache");
+
+        assert!(save_to_file_serialized(&vec![make_list(4), make_list(5)], &path));
+        let rewritten = BatchCache::load(&cache, &path).expect("load");
+        assert_eq!(rewritten.len(), 2);
+        assert_eq!(rewritten[0].max_card, 4);
+        assert_eq!(fs::read_dir(&dir).expect("read dir").count(), 1, "rewrites leave no tmp file behind");
+
+        cache.lock().unwrap().invalidate(&path);
+        assert!(!Arc::ptr_eq(&BatchCache::load(&cache, &path).expect("load"), &rewritten), "invalidated");
+    }
+
+    #[test]
+    fn an_interrupted_write_leaves_the_previous_file_intact() {
+        let dir = crate::test_dir::TestDir::new("atomic_write");
+        let path = dir.join("nsl_04_batch_000000_to_05_batch_000000.rkyv").to_string_lossy().into_owned();
+        let first = encode_lists(&vec![make_list(3), make_list(4)], ListEncoding::Plain).unwrap();
+        write_file_atomic(&path, &first).expect("write");
+
+        // A run killed while writing the new version: only its tmp file is truncated
+        let second = encode_lists(&(3..40).map(make_list).collect(), ListEncoding::Plain).unwrap();
+        fs::write(atomic_tmp_path(&path), &second[..second.len() / 2]).unwrap();
+        assert_eq!(load_lists_from_file(&path).expect("previous version").len(), 2);
+
+        // The next write replaces the leftover tmp file
+        write_file_atomic(&path, &second).expect("write");
+        assert_eq!(load_lists_from_file(&path).expect("new version").len(), 37);
+        assert_eq!(fs::read_dir(&dir).expect("read dir").count(), 1, "no tmp file left behind");
+
+        // A write that cannot be renamed into place fails without leaving its tmp file
+        let taken = dir.join("taken");
+        fs::create_dir_all(taken.join("inner")).unwrap();
+        assert!(write_file_atomic(&taken.to_string_lossy(), &first).is_err());
+        assert!(!std::path::Path::new(&atomic_tmp_path(&taken.to_string_lossy())).exists());
+    }
+
+    #[test]
+    fn transient_errors_are_retried_and_others_returned_at_once() {
+        let mut calls = 0;
+        let result = with_retry("read", "nowhere", || {
+            calls += 1;
+            if calls < 3 { Err(io::Error::from(io::ErrorKind::Interrupted)) } else { Ok(calls) }
+        });
+        assert_eq!(result.expect("third attempt succeeds"), 3);
+
+        let mut calls = 0;
+        let result: io::Result<()> = with_retry("open", "nowhere", || {
+            calls += 1;
+            Err(io::Error::from(io::ErrorKind::NotFound))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1, "permanent errors are not retried");
+    }
+
+    #[test]
+    fn every_encoding_compressed_or_not_is_read_transparently() {
+        let dir = crate::test_dir::TestDir::new("delta");
+        let plain = dir.join("plain.rkyv").to_string_lossy().into_owned();
+        let delta = dir.join("delta.rkyv").to_string_lossy().into_owned();
+        let packed = dir.join("packed.rkyv").to_string_lossy().into_owned();
+
+        let lists: Vec<NoSetListSerialized> = (3..60)
+            .map(|c| NoSetListSerialized { n: 3, max_card: c, no_set_list: vec![0, 1, c], remaining_cards_list: vec![c + 1, 80] })
+            .collect();
+        fs::write(&plain, encode_lists(&lists, ListEncoding::Plain).unwrap()).unwrap();
+        fs::write(&delta, encode_lists(&lists, ListEncoding::Delta).unwrap()).unwrap();
+        fs::write(&packed, encode_lists(&lists, ListEncoding::Packed).unwrap()).unwrap();
+        assert_eq!(file_format(&packed).unwrap().encoding, ListEncoding::Packed);
+        let compressed: Vec<String> = [(&plain, "plain.rkyv.zst"), (&delta, "delta.rkyv.zst")].iter()
+            .map(|(source, name)| {
+                let path = dir.join(name).to_string_lossy().into_owned();
+                fs::write(&path, zstd::bulk::compress(&fs::read(source).unwrap(), 3).unwrap()).unwrap();
+                path
+            })
+            .collect();
+        assert!(is_compressed_file(&compressed[0]) && !is_compressed_file(&plain));
+
+        for path in [&plain, &delta, &packed, &compressed[0], &compressed[1]] {
+            let loaded = load_lists_from_file(path).expect("load");
+            assert_eq!(loaded.len(), lists.len());
+            for (a, b) in loaded.iter().zip(lists.iter()) {
+                assert_eq!((a.n, a.max_card), (b.n, b.max_card));
+                assert_eq!(a.no_set_list, b.no_set_list);
+                assert_eq!(a.remaining_cards_list, b.remaining_cards_list);
+            }
+            assert_eq!(count_lists_in_file(path).unwrap(), lists.len() as u64);
+            // Read in place, last to first
+            let mapped: Vec<NoSetList> = with_mapped_lists(path, |m| Ok(m.iter_rev().collect())).expect("mapped");
+            assert_eq!(mapped.len(), lists.len());
+            for (a, b) in mapped.iter().rev().map(NoSetList::to_serialized).zip(lists.iter()) {
+                assert_eq!((a.n, a.max_card), (b.n, b.max_card));
+                assert_eq!(a.no_set_list, b.no_set_list);
+                assert_eq!(a.remaining_cards_list, b.remaining_cards_list);
+            }
+        }
+        // Format version 0 (bare archive) still read, an unknown version refused
+        let bare = dir.join("bare.rkyv").to_string_lossy().into_owned();
+        fs::write(&bare, &encode_lists(&lists, ListEncoding::Plain).unwrap()[PLAIN_MAGIC.len()..]).unwrap();
+        assert_eq!(load_lists_from_file(&bare).expect("load").len(), lists.len());
+        assert_eq!(file_format(&bare).unwrap().version, 0);
+        let newer = dir.join("newer.rkyv").to_string_lossy().into_owned();
+        fs::write(&newer, [&b"NSLPLAN9"[..], &[0u8; 64]].concat()).unwrap();
+        assert!(load_lists_from_file(&newer).err().expect("refused").to_string().contains("Unsupported list format"));
+        assert!(fs::metadata(&delta).unwrap().len() < fs::metadata(&plain).unwrap().len());
+        assert_eq!(fs::metadata(&packed).unwrap().len(), 8 + 24 * lists.len() as u64);
+
+    }
+
+    #[test]
+    fn compressed_files_round_trip_in_every_encoding() {
+        let dir = crate::test_dir::TestDir::new("zstd");
+        let lists: Vec<NoSetListSerialized> = (0..200)
+            .map(|i| NoSetListSerialized { n: 4, max_card: 40 + i % 30, no_set_list: vec![0, 1, 3, 40 + i % 30], remaining_cards_list: (41 + i % 30..81).collect() })
+            .collect();
+        for encoding in [ListEncoding::Plain, ListEncoding::Delta, ListEncoding::Packed] {
+            let plain = dir.join(format!("{:?}.rkyv", encoding)).to_string_lossy().into_owned();
+            let compressed = dir.join(format!("nsl_03_batch_000000_to_04_batch_00000{}.rkyv", encoding as u8)).to_string_lossy().into_owned();
+            assert!(save_lists_as(&lists, &plain, encoding, None));
+            assert!(save_lists_as(&lists, &compressed, encoding, Some(3)));
+            assert!(is_compressed_file(&compressed) && !is_compressed_file(&plain));
+            assert!(fs::metadata(&compressed).unwrap().len() < fs::metadata(&plain).unwrap().len());
+
+            let loaded = load_lists_from_file(&compressed).expect("load");
+            assert_eq!(loaded.len(), lists.len());
+            for (a, b) in loaded.iter().zip(lists.iter()) {
+                assert_eq!((a.n, a.max_card, &a.no_set_list, &a.remaining_cards_list), (b.n, b.max_card, &b.no_set_list, &b.remaining_cards_list));
+            }
+            assert_eq!(count_lists_in_file(&compressed).unwrap(), lists.len() as u64);
+            assert_eq!(verify_file_checksum(&compressed).unwrap().map(|f| f.nb_lists), Some(lists.len() as u64));
+        }
+
+        // The state records which files are compressed
+        let mut state = crate::file_info::GlobalFileState::new(&dir.str(), 4);
+        state.register_file("nsl_03_batch_000000_to_04_batch_000000.rkyv", 0, 0, 200, false, None, None);
+        assert!(state.entries().values().all(|e| e.compressed));
+    }
+
+    #[test]
+    fn framed_files_are_streamed_and_read_back() {
+        let dir = crate::test_dir::TestDir::new("framed");
+        let path = dir.join("framed.rkyv").to_string_lossy().into_owned();
+
+        let lists: Vec<NoSetListSerialized> = (3..60).map(make_list).collect();
+        let mut writer = ListFileWriter::create(&path).expect("create");
+        for frame in lists.chunks(25) {
+            writer.write_frame(&frame.to_vec()).expect("frame");
+        }
+        assert_eq!(writer.finish().expect("finish"), lists.len() as u64);
+
+        let loaded = load_lists_from_file(&path).expect("load");
+        assert_eq!(loaded.iter().map(|l| l.max_card).collect::<Vec<_>>(), (3..60).collect::<Vec<_>>());
+        assert_eq!(count_lists_in_file(&path).unwrap(), lists.len() as u64);
+        let mut chunks = Vec::new();
+        load_lists_in_chunks(&path, 10, |chunk| { chunks.push(chunk.len()); Ok(()) }).expect("chunks");
+        assert_eq!(chunks, vec![10, 10, 5, 10, 10, 5, 7]); // chunks stop at frame boundaries
+        let range = load_lists_range(&path, 20, 12).expect("range");
+        assert_eq!(range.iter().map(|l| l.max_card).collect::<Vec<_>>(), (23..35).collect::<Vec<_>>());
+        assert_eq!(load_lists_range(&path, 50, 100).expect("tail").len(), 7);
+
+        // The count is read from the header; any read of a corrupt file fails its checksum
+        let mut bytes = fs::read(&path).unwrap();
+        assert_eq!(indexed_count(&path).unwrap(), Some(lists.len() as u64));
+        assert_eq!(verify_file_checksum(&path).unwrap().map(|f| f.nb_lists), Some(lists.len() as u64));
+        let in_last_frame = bytes.len() - FOOTER_LEN - 3 * 16 - 32; // 32 bytes before the index (3 entries)
+        bytes[in_last_frame] ^= 0xFF;
+        fs::write(&path, &bytes).unwrap();
+        assert_eq!(count_lists_in_file(&path).unwrap(), lists.len() as u64);
+        let error = load_lists_range(&path, 0, 25).err().expect("corrupt file");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(verify_file_checksum(&path).is_err());
+
+        // A truncated file is rejected
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - FOOTER_LEN - 8]).unwrap();
+        assert!(count_lists_in_file(&path).is_err());
+    }
+
+    #[test]
+    fn list_ranges_stop_at_the_bounds_of_the_file() {
+        let dir = crate::test_dir::TestDir::new("ranges");
+        let path = dir.join("framed.rkyv").to_string_lossy().into_owned();
+        let lists: Vec<NoSetListSerialized> = (3..60).map(make_list).collect();
+        let mut writer = ListFileWriter::create(&path).expect("create");
+        for frame in lists.chunks(25) {
+            writer.write_frame(&frame.to_vec()).expect("frame");
+        }
+        writer.finish().expect("finish");
+
+        // Ranges on a frame boundary, empty, past the end or unbounded
+        let cards = |start, count| -> Vec<usize> {
+            load_lists_range(&path, start, count).expect("range").iter().map(|l| l.max_card).collect()
+        };
+        assert_eq!(cards(25, 25), (28..53).collect::<Vec<_>>());
+        assert_eq!(cards(24, 2), vec![27, 28]);
+        assert!(cards(10, 0).is_empty());
+        assert!(cards(57, 10).is_empty());
+        assert!(cards(usize::MAX, usize::MAX).is_empty());
+        assert_eq!(cards(55, usize::MAX), vec![58, 59]);
+        // Files that are not framed are decoded whole, then sliced
+        let plain = dir.join("plain.rkyv").to_string_lossy().into_owned();
+        assert!(save_lists_as(&lists, &plain, ListEncoding::Plain, None));
+        assert_eq!(load_lists_range(&plain, 20, 12).expect("range").iter().map(|l| l.max_card).collect::<Vec<_>>(),
+            (23..35).collect::<Vec<_>>());
+        assert!(load_lists_range(&plain, 100, 5).expect("past the end").is_empty());
+    }
+}
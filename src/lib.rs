@@ -0,0 +1,88 @@
+//! Funny: the search for the grail of Set (combinations of up to 20 cards with no
+//! sets), as a library
+//!
+//! The funny binary is a thin command line over this crate: it parses the
+//! arguments into a ProcessingConfig and runs it with execute_mode. Other tools
+//! can do the same, or call the engine, the state and the compaction directly.
+//!
+//! Key features:
+//! - modes: ProcessingConfig, ProcessingMode and the mode executors (execute_mode,
+//!   execute_size_mode, execute_cascade_mode, ...); ProcessingConfig::size(15)
+//!   .input("data").run() builds and runs a configuration without the command line
+//! - list_of_nsl: the expansion engine (ListOfNSL) and the count, check and
+//!   compaction of the files of a size
+//! - engine: the ExpansionEngine trait the modes drive, implemented by ListOfNSL
+//! - events: progress events (modes started and completed, batches loaded,
+//!   outputs saved, files processed, states flushed, sizes completed, warnings,
+//!   console lines, mode results) to the observers registered; the CLI prints
+//!   them (the console lines are printed by utils::test_print whatever the observers)
+//! - file_info: the state of a size (GlobalFileState)
+//! - compaction, io_helpers, storage: the list files
+//! - The run-wide options (encoding, storage backend, compaction, ...) are set
+//!   through the setters of their modules, as the CLI does before running a mode;
+//!   they are process-wide, shared by every run of the process (see
+//!   modes::ProcessingConfigBuilder)
+//!
+//! Used by the funny binary (src/main.rs)
+
+pub mod utils;
+pub mod set;
+pub mod no_set_list;
+pub mod io_helpers;
+pub mod filenames;
+pub mod compaction;
+pub mod list_of_nsl;
+pub mod file_info;
+pub mod orbits;
+pub mod verify;
+pub mod final_report;
+pub mod random_walk;
+pub mod filter_target;
+pub mod stats;
+pub mod validate_counts;
+pub mod sample;
+pub mod split;
+pub mod migrate;
+pub mod convert;
+pub mod benchmark;
+pub mod estimate;
+pub mod prune;
+pub mod repair;
+pub mod state_diff;
+pub mod archive;
+pub mod dry_run;
+pub mod watch;
+pub mod inspect;
+pub mod top;
+pub mod normalize;
+pub mod validate_chain;
+pub mod gc;
+pub mod reencode;
+pub mod dataset;
+pub mod storage;
+pub mod checksum;
+pub mod manifest;
+pub mod migrate_format;
+pub mod upgrade_state;
+pub mod overview;
+pub mod findings;
+pub mod scan;
+pub mod restore_state;
+pub mod merge_state;
+pub mod fast_count;
+pub mod count_follow;
+pub mod disk_guard;
+pub mod cascade_status;
+pub mod cascade_journal;
+pub mod notify;
+pub mod cascade_roots;
+pub mod engine;
+pub mod events;
+pub mod modes;
+pub mod error;
+#[cfg(test)]
+mod test_dir;
+
+pub use crate::file_info::GlobalFileState;
+pub use crate::list_of_nsl::ListOfNSL;
+pub use crate::modes::{execute_mode, ProcessingConfig, ProcessingConfigBuilder, ProcessingMode};
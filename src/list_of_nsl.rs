@@ -1,37 +1,40 @@
-/// Version 0.4.13: Hybrid stack-optimized computation with auto-compaction for sizes 13+
-/// Added: Cascade mode for automated multi-size processing
-/// 
-/// This implementation combines the best of both worlds:
-/// - Uses NoSetList (stack arrays) for computation → 4-5× faster
-/// - Converts to NoSetListSerialized (heap Vecs) for I/O → compact 2GB files
-/// - GlobalFileState with incremental JSON/TXT saves after each output file
-/// - Recognizes both regular and compacted input files (*_compacted.rkyv)
-/// - Supports batch range processing for smart compaction workflows
-/// 
-/// Performance characteristics:
-/// - Computation: Same speed as v0.3.0 (stack-optimized)
-/// - File size: ~2GB per 20M batch (compact with size_32 rkyv)
-/// - Memory: Moderate (~12-15GB peak during conversion + save)
-/// - Tracking: In-memory state with O(1) lookups, atomic JSON/TXT persistence
-///
-/// This is the only active version of the project.
-
-// Rkyv imports for zero-copy serialization
-use rkyv::check_archived_root;
-
+//! Version 0.4.13: Hybrid stack-optimized computation with auto-compaction for sizes 13+
+//! Added: Cascade mode for automated multi-size processing
+//! 
+//! This implementation combines the best of both worlds:
+//! - Uses NoSetList (stack arrays) for computation → 4-5× faster
+//! - Converts to NoSetListSerialized (heap Vecs) for I/O → compact 2GB files
+//! - GlobalFileState with incremental JSON/TXT saves after each output file
+//! - Recognizes both regular and compacted input files (*_compacted.rkyv)
+//! - Supports batch range processing for smart compaction workflows
+//! 
+//! Performance characteristics:
+//! - Computation: Same speed as v0.3.0 (stack-optimized)
+//! - File size: ~2GB per 20M batch (compact with size_32 rkyv)
+//! - Memory: Moderate (the output lists, plus one ~100k-list frame during save;
+//!   input files are read in place from the mapped archive)
+//! - Tracking: In-memory state with O(1) lookups, atomic JSON/TXT persistence
+//!
+//! This is the only active version of the project.
+
+use std::collections::{BTreeSet, HashSet};
 use separator::Separatable;
+use serde::Serialize;
 use crate::utils::*;
 use crate::set::*;
 use crate::no_set_list::*;
 use crate::io_helpers::*;
 use crate::filenames::*;
-use crate::file_info::GlobalFileState;
+use crate::file_info::{FileCheckResult, FileInfo, GlobalFileState, InputKey, Provenance};
+use crate::orbits::Canonicalizer;
+use crate::findings::{Finding, FindingsReport};
 
 /// Batch processor: NoSetList for compute, NoSetListSerialized for I/O
 pub struct ListOfNSL {
     pub current_size: u8,              // # of cards in the current no-set-lists
     pub current: Vec<NoSetList>,       // current n-lists (stack-based for computation)
     pub current_file_batch: u32,       // Current input file batch number (5 digits)
+    pub current_file_compacted: bool,  // Current input file is a compacted file
     pub current_file_list_count: u64,  // Lists loaded from current input file
     pub current_total_list_count: u64, // Total lists processed across all input files
     pub new: Vec<NoSetList>,           // newly created n+1-lists (stack-based during compute)
@@ -43,7 +46,20 @@ pub struct ListOfNSL {
     pub computation_time: f64,         // time spent in core algorithm
     pub file_io_time: f64,             // time spent in file I/O operations
     pub conversion_time: f64,          // time spent converting between formats
+    pub strong_prune: bool,            // apply the extension upper bound when expanding
+    pub shard: Option<Shard>,          // only expand the input lists of this max_card shard (--shard K/M)
+    pub isomorph_cache: bool,          // drop children isomorphic to one already created from the same input batch
+                                       // (unsound for exhaustive counts: their remaining cards may differ)
+    pub isomorph_lookups: u64,         // children looked up in the isomorph cache
+    pub isomorph_hits: u64,            // children dropped by the isomorph cache
+    isomorph_seen: HashSet<u128>,      // canonical forms of the children of the current input batch
+    canonicalizer: Option<Canonicalizer>,
     input_intermediary_buffer: Vec<String>, // Buffer for input-intermediary file lines
+    input_hash: Option<String>,        // "crc32:<hex>" of the current input file (provenance)
+    output_started: std::time::Instant, // previous output file saved (or input loaded)
+    progress_inputs: BTreeSet<InputKey>, // input files of the current size (progress line)
+    run_started_at: i64,               // unix seconds, start of the current size (progress pace)
+    pub compactor: Option<crate::compaction::BackgroundCompactor>, // compacts the outputs while processing (--background-compact)
 }
 
 impl ListOfNSL {
@@ -53,6 +69,7 @@ impl ListOfNSL {
             current_size: 0,
             current: Vec::new(),
             current_file_batch: 0,
+            current_file_compacted: false,
             current_file_list_count: 0,
             current_total_list_count: 0,
             new: Vec::new(),
@@ -64,7 +81,19 @@ impl ListOfNSL {
             computation_time: 0.0,
             file_io_time: 0.0,
             conversion_time: 0.0,
+            strong_prune: false,
+            shard: None,
+            isomorph_cache: false,
+            isomorph_lookups: 0,
+            isomorph_hits: 0,
+            isomorph_seen: HashSet::new(),
+            canonicalizer: None,
             input_intermediary_buffer: Vec::new(),
+            input_hash: None,
+            output_started: std::time::Instant::now(),
+            progress_inputs: BTreeSet::new(),
+            run_started_at: 0,
+            compactor: None,
         }
     }
     
@@ -74,6 +103,7 @@ impl ListOfNSL {
             current_size: 0,
             current: Vec::new(),
             current_file_batch: 0,
+            current_file_compacted: false,
             current_file_list_count: 0,
             current_total_list_count: 0,
             new: Vec::new(),
@@ -85,7 +115,19 @@ impl ListOfNSL {
             computation_time: 0.0,
             file_io_time: 0.0,
             conversion_time: 0.0,
+            strong_prune: false,
+            shard: None,
+            isomorph_cache: false,
+            isomorph_lookups: 0,
+            isomorph_hits: 0,
+            isomorph_seen: HashSet::new(),
+            canonicalizer: None,
             input_intermediary_buffer: Vec::new(),
+            input_hash: None,
+            output_started: std::time::Instant::now(),
+            progress_inputs: BTreeSet::new(),
+            run_started_at: 0,
+            compactor: None,
         }
     }
     
@@ -95,6 +137,7 @@ impl ListOfNSL {
             current_size: 0,
             current: Vec::new(),
             current_file_batch: 0,
+            current_file_compacted: false,
             current_file_list_count: 0,
             current_total_list_count: 0,
             new: Vec::new(),
@@ -106,7 +149,19 @@ impl ListOfNSL {
             computation_time: 0.0,
             file_io_time: 0.0,
             conversion_time: 0.0,
+            strong_prune: false,
+            shard: None,
+            isomorph_cache: false,
+            isomorph_lookups: 0,
+            isomorph_hits: 0,
+            isomorph_seen: HashSet::new(),
+            canonicalizer: None,
             input_intermediary_buffer: Vec::new(),
+            input_hash: None,
+            output_started: std::time::Instant::now(),
+            progress_inputs: BTreeSet::new(),
+            run_started_at: 0,
+            compactor: None,
         }
     }
     
@@ -197,8 +252,8 @@ impl ListOfNSL {
         let compacted: Vec<NoSetListSerialized> = nlists.iter().map(|nlist| NoSetListSerialized {
             n: nlist.n,
             max_card: nlist.max_card,
-            no_set_list: nlist.no_set_list.iter().copied().collect(),
-            remaining_cards_list: nlist.remaining_cards_list.iter().copied().collect(),
+            no_set_list: nlist.no_set_list.to_vec(),
+            remaining_cards_list: nlist.remaining_cards_list.to_vec(),
         }).collect();
         
         let file = output_filename(&self.output_path, 0, 0, 3, 0);
@@ -222,33 +277,22 @@ impl ListOfNSL {
         self.current_file_list_count = 0;
     }
     
-    /// Load a batch of current n-lists from file (reads NoSetListSerialized, converts to NoSetList)
-    /// Reads output files from previous processing step that target current_size
-    fn refill_current_from_file(&mut self) -> bool {
-        // Find input file: any file that was output to create current_size at current_file_batch
-        let filename = match find_input_filename(&self.input_path, self.current_size, self.current_file_batch) {
-            Some(f) => f,
-            None => {
-                debug_print(&format!("   ... No input file found for size {:02} batch {:06} in {}",
-                    self.current_size, self.current_file_batch, self.input_path));
-                debug_print(&format!("refill_current_from_file: No file found for size {:02} batch {:06}",
-                    self.current_size, self.current_file_batch));
-                return false;
-            }
-        };
-        
+    /// Load a batch of current n-lists from an explicit file path (reads NoSetListSerialized,
+    /// converts to NoSetList); used when decoded batches are cached (see `process_input_path`)
+    fn refill_current_from_path(&mut self, filename: &str) -> bool {
         // Time the file read operation
         let io_start = std::time::Instant::now();
         
-        let result = read_from_file_serialized(&filename);
+        // Goes through the batch cache (if enabled with --cache-batches)
+        let result = load_lists_cached(filename);
         self.file_io_time += io_start.elapsed().as_secs_f64();
         
         match result {
-            Some(vec_nlist) => {
+            Ok(vec_nlist) => {
                 // Convert from NoSetListSerialized to NoSetList for fast computation
                 let conv_start = std::time::Instant::now();
                 let vec_nsl: Vec<NoSetList> = vec_nlist.iter()
-                    .map(|nl| NoSetList::from_serialized(nl))
+                    .map(NoSetList::from_serialized)
                     .collect();
                 self.conversion_time += conv_start.elapsed().as_secs_f64();
                 debug_print(&format!("   ... loaded  {:>10} no-set-lists from {}", 
@@ -262,9 +306,9 @@ impl ListOfNSL {
                     self.current_file_list_count, self.current_total_list_count));
                 true
             }
-            None => {
-                debug_print(&format!("refill_current_from_file: Error loading from {}", 
-                    filename));
+            Err(e) => {
+                debug_print(&format!("refill_current_from_file: Error loading from {}: {}", 
+                    filename, e));
                 false
             }
         }
@@ -272,46 +316,67 @@ impl ListOfNSL {
     
     /// Save current batch (converts NoSetList to NoSetListSerialized for compact storage)
     fn save_new_to_file(&mut self, state: Option<&mut GlobalFileState>) -> bool {
-        let file = output_filename(
+        let mut file = output_filename(
             &self.output_path, 
             self.current_size, 
             self.current_file_batch,
             self.current_size + 1, 
             self.new_output_batch
         );
+        if let Some(shard) = &self.shard {
+            file = sharded_filename(&file, shard);
+        }
         let additional_new = self.new.len() as u64;
+
+        // Journal the registration first: a crash before the state is flushed must
+        // not leave the file invisible to the next runs
+        if let Some(state) = state.as_deref() {
+            let filename = std::path::Path::new(&file).file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_else(|| file.clone());
+            let op = crate::file_info::JournalOp::Register { filename, source_batch: self.current_file_batch,
+                target_batch: self.new_output_batch, nb_lists: additional_new, compacted: false };
+            if let Err(e) = state.journal(&op) {
+                debug_print(&format!("save_new_to_file: Error journaling {}: {}", file, e));
+                return false;
+            }
+        }
         
-        // Convert to NoSetListSerialized for compact serialization
-        let conv_start = std::time::Instant::now();
-        let nlists: Vec<NoSetListSerialized> = self.new.iter().map(|nsl| nsl.to_serialized()).collect();
-        self.conversion_time += conv_start.elapsed().as_secs_f64();
-        
-        // Clone to fresh Vecs to eliminate capacity bloat
-        let compacted: Vec<NoSetListSerialized> = nlists.iter().map(|nlist| NoSetListSerialized {
-            n: nlist.n,
-            max_card: nlist.max_card,
-            no_set_list: nlist.no_set_list.iter().copied().collect(),
-            remaining_cards_list: nlist.remaining_cards_list.iter().copied().collect(),
-        }).collect();
-        
-        // Time the file write operation
+        // Stream the lists to the file one frame at a time: only one frame is
+        // converted to NoSetListSerialized and serialized in memory at once
+        let mut conversion_time = 0.0;
         let io_start = std::time::Instant::now();
+        let saved = (|| -> std::io::Result<u64> {
+            let mut writer = ListFileWriter::create(&file)?;
+            // --also-parquet: the same frames go to the dataset part of the file
+            let mut part = if crate::dataset::also_parquet() {
+                Some(crate::dataset::DatasetPartWriter::create(&file)?)
+            } else {
+                None
+            };
+            for chunk in self.new.chunks(FRAME_LISTS) {
+                let conv_start = std::time::Instant::now();
+                let nlists: Vec<NoSetListSerialized> = chunk.iter().map(|nsl| nsl.to_serialized()).collect();
+                conversion_time += conv_start.elapsed().as_secs_f64();
+                writer.write_frame(&nlists)?;
+                if let Some(part) = part.as_mut() {
+                    part.write_chunk(self.current_file_batch, &nlists)?;
+                }
+            }
+            if let Some(part) = part {
+                part.finish()?;
+            }
+            writer.finish()
+        })();
+        self.conversion_time += conversion_time;
         
-        match save_to_file_serialized(&compacted, &file) {
-            true => {
-                self.file_io_time += io_start.elapsed().as_secs_f64();
+        match saved {
+            Ok(_) => {
+                self.file_io_time += io_start.elapsed().as_secs_f64() - conversion_time;
 
                 // Register in state or buffer for legacy intermediary file
                 if let Some(state) = state {
                     let file_path = std::path::Path::new(&file);
-                    let (file_size, mtime) = file_path.metadata()
-                        .ok()
-                        .map(|m| (
-                            Some(m.len()),
-                            m.modified().ok()
-                                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-                                .map(|d| d.as_secs() as i64)
-                        ))
+                    let (file_size, mtime) = crate::storage::file_metadata(&file)
+                        .map(|(bytes, mtime)| (Some(bytes), mtime))
                         .unwrap_or((None, None));
                     
                     let filename = file_path.file_name()
@@ -328,25 +393,32 @@ impl ListOfNSL {
                         file_size,
                         mtime,
                     );
+                    let provenance = Provenance::here(self.output_started.elapsed().as_millis() as u64, self.input_hash.clone());
+                    state.set_provenance(&filename, self.current_file_batch, self.new_output_batch, provenance);
                     
-                    // Flush state immediately after saving each output file
-                    if let Err(e) = state.flush() {
+                    // Flush state after saving each output file (every --flush-every files)
+                    if let Err(e) = state.flush_throttled() {
                         debug_print(&format!("Error flushing global state: {}", e));
                     }
+                    if let Some(compactor) = self.compactor.as_mut() {
+                        compactor.poll(state);
+                    }
                 } else {
                     // Fallback to legacy buffer system
                     self.buffer_input_intermediary_line(self.new_output_batch, additional_new);
                 }
+                crate::events::output_saved(crate::events::OutputSaved { size: self.current_size + 1,
+                    source_batch: self.current_file_batch, batch: self.new_output_batch, nb_lists: additional_new,
+                    filename: file });
                 self.new_total_list_count += additional_new;
                 self.new_output_batch += 1;
                 self.new.clear();
-                debug_print(&format!("   ... saved   {:>10} no-set-lists  to  {}", 
-                    additional_new.separated_string(), file));
+                self.output_started = std::time::Instant::now();
                 true
             }
-            false => {
-                self.file_io_time += io_start.elapsed().as_secs_f64();
-                debug_print(&format!("save_new_to_file: Error saving to {}", file));
+            Err(e) => {
+                self.file_io_time += io_start.elapsed().as_secs_f64() - conversion_time;
+                debug_print(&format!("save_new_to_file: Error saving to {}: {}", file, e));
                 false
             }
         }
@@ -416,45 +488,145 @@ impl ListOfNSL {
             of no-set-{:02} ({} lists)", self.current_file_batch, self.current_size, 
             self.current.len()));
         
-        // Don't reset new_output_batch - keep continuous numbering across all source files
-        let file_new_count_start = self.new_total_list_count;
+        // Sharded run: only the input lists of this shard are expanded
+        if let Some(shard) = self.shard {
+            self.current.retain(|nsl| shard.contains(nsl.max_card));
+            debug_print(&format!("   ... shard {}/{}: {} input lists kept", shard.index, shard.count, self.current.len()));
+        }
         
+        let file_new_count_start = self.begin_input_file();
         let len = self.current.len() as u64;
         let mut i = 0u64;
-        
-        while !self.current.is_empty() {
-            debug_print_noln(&format!("{:>5} ", len - i));
-            
-            // Pop current n-list
-            let current_nsl = self.current.pop().unwrap();
-            
-            // Time the core computation (STACK-OPTIMIZED)
-            let comp_start = std::time::Instant::now();
-            let new_nsls = current_nsl.build_higher_nsl();
-            self.computation_time += comp_start.elapsed().as_secs_f64();
-            
-            debug_print_noln(&format!("-> +{:>5} new - ", new_nsls.len()));
-            
-            // Add to new vector (still NoSetList for now)
-            self.new.extend(new_nsls);
-            
-            if i % 4 == 0 || i + 1 == len {
-                debug_print(&format!(" - {:>8}", self.new.len()));
-            }
+        while let Some(current_nsl) = self.current.pop() {
+            self.expand_input_list(current_nsl, i, len, max, state.as_deref_mut());
+            i += 1;
+        }
+        self.end_input_file(file_new_count_start, state)
+    }
+    
+    /// Process one input file in place: the lists are read straight from the mapped
+    /// file, each built as a NoSetList only when popped, instead of decoding the whole
+    /// file into `current` first (same order, so the same outputs).
+    /// Used when the batch cache is disabled (the cache keeps decoded batches).
+    fn process_mapped_file(&mut self, filename: &str, max: &u64, mut state: Option<&mut GlobalFileState>) -> bool {
+        let io_start = std::time::Instant::now();
+        let mut time_in_lists = 0.0; // expansion and saves, timed on their own
+        let result = with_mapped_lists(filename, |lists| {
+            let len = lists.len() as u64;
+            self.current_file_list_count = len;
+            self.current_total_list_count += len;
+            crate::events::batch_loaded(crate::events::BatchLoaded { size: self.current_size,
+                batch: self.current_file_batch, nb_lists: len, filename: filename.to_string() });
+            debug_print(&format!("process_mapped_file: Processing batch {} of no-set-{:02} ({} lists in place)",
+                self.current_file_batch, self.current_size, len));
             
-            // Check if we need to save
-            if self.new.len() as u64 >= *max {
-                test_print(&format!("   ... saving batch ({:>10} lists), output batch {}", 
-                    self.new.len().separated_string(), self.new_output_batch));
-                if !self.save_new_to_file(state.as_deref_mut()) {
-                    test_print("   ... ERROR: Failed to save batch");
-                    debug_print("process_one_file_of_current_size_n: Error saving batch");
+            let file_new_count_start = self.begin_input_file();
+            let lists_start = std::time::Instant::now();
+            let shard = self.shard;
+            for (i, current_nsl) in lists.iter_rev().enumerate() {
+                // Sharded run: only the input lists of this shard are expanded
+                if shard.is_none_or(|shard| shard.contains(current_nsl.max_card)) {
+                    self.expand_input_list(current_nsl, i as u64, len, max, state.as_deref_mut());
                 }
             }
-
-            i += 1;
+            let created = self.end_input_file(file_new_count_start, state);
+            time_in_lists = lists_start.elapsed().as_secs_f64();
+            Ok(created)
+        });
+        // Mapping, validation and building the lists
+        self.conversion_time += io_start.elapsed().as_secs_f64() - time_in_lists;
+        
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                debug_print(&format!("process_mapped_file: Error loading from {}: {}", filename, e));
+                false
+            }
+        }
+    }
+    
+    /// Load an input file and process it, in place or through `current` when
+    /// decoded batches are cached. Returns false if the file could not be loaded.
+    fn process_input_path(&mut self, filename: &str, max: &u64, state: Option<&mut GlobalFileState>) -> bool {
+        // Provenance of the outputs: the CRC32 of the input footer (files written without one have none)
+        self.input_hash = crate::io_helpers::file_footer(filename).ok().flatten()
+            .map(|footer| format!("crc32:{:08x}", footer.crc32));
+        self.output_started = std::time::Instant::now();
+        self.current_file_compacted = filename.ends_with("_compacted.rkyv");
+        // Legacy bincode inputs cannot be mapped: they are decoded whole
+        if !batch_cache_enabled() && !crate::migrate::is_legacy_bincode(filename) {
+            return self.process_mapped_file(filename, max, state);
+        }
+        if !self.refill_current_from_path(filename) {
+            return false;
+        }
+        crate::events::batch_loaded(crate::events::BatchLoaded { size: self.current_size,
+            batch: self.current_file_batch, nb_lists: self.current.len() as u64, filename: filename.to_string() });
+        self.process_one_file_of_current_size_n(max, state);
+        true
+    }
+    
+    /// Find the input file of `current_file_batch` and process it (see `process_input_path`)
+    fn process_input_batch(&mut self, max: &u64, state: Option<&mut GlobalFileState>) -> bool {
+        match find_input_filename(&self.input_path, self.current_size, self.current_file_batch) {
+            Some(filename) => self.process_input_path(&filename, max, state),
+            None => {
+                debug_print(&format!("   ... No input file found for size {:02} batch {:06} in {}",
+                    self.current_size, self.current_file_batch, self.input_path));
+                false
+            }
+        }
+    }
+    
+    /// Start the children of a new input file; returns the list count to diff at the end
+    fn begin_input_file(&mut self) -> u64 {
+        // The isomorph cache only spans the children of one input batch
+        self.isomorph_seen.clear();
+        if self.isomorph_cache && self.canonicalizer.is_none() {
+            self.canonicalizer = Some(Canonicalizer::new());
+        }
+        // Don't reset new_output_batch - keep continuous numbering across all source files
+        self.new_total_list_count
+    }
+    
+    /// Expand one input list (the `i`-th of `len`), saving an output batch when full
+    fn expand_input_list(&mut self, current_nsl: NoSetList, i: u64, len: u64, max: &u64, state: Option<&mut GlobalFileState>) {
+        debug_print_noln(&format!("{:>5} ", len - i));
+        
+        // Time the core computation (STACK-OPTIMIZED)
+        let comp_start = std::time::Instant::now();
+        let mut new_nsls = current_nsl.build_higher_nsl(self.strong_prune);
+        if let (true, Some(canonicalizer)) = (self.isomorph_cache, self.canonicalizer.as_ref()) {
+            let before = new_nsls.len() as u64;
+            let seen = &mut self.isomorph_seen;
+            new_nsls.retain(|nsl| seen.insert(canonicalizer.canonical_form(nsl.no_set_slice()).mask));
+            self.isomorph_lookups += before;
+            self.isomorph_hits += before - new_nsls.len() as u64;
+        }
+        self.computation_time += comp_start.elapsed().as_secs_f64();
+        
+        debug_print_noln(&format!("-> +{:>5} new - ", new_nsls.len()));
+        
+        // Add to new vector (still NoSetList for now)
+        self.new.extend(new_nsls);
+        
+        if i.is_multiple_of(4) || i + 1 == len {
+            debug_print(&format!(" - {:>8}", self.new.len()));
         }
         
+        // Check if we need to save
+        if self.new.len() as u64 >= *max {
+            test_print(&format!("   ... saving batch ({:>10} lists), output batch {}", 
+                self.new.len().separated_string(), self.new_output_batch));
+            if !self.save_new_to_file(state) {
+                test_print("   ... ERROR: Failed to save batch");
+                debug_print("process_one_file_of_current_size_n: Error saving batch");
+            }
+        }
+    }
+    
+    /// Save the last (partial) output batch of an input file; returns the lists it created
+    fn end_input_file(&mut self, file_new_count_start: u64, mut state: Option<&mut GlobalFileState>) -> u64 {
         // Save any remaining lists from this input file (even if < max)
         if !self.new.is_empty() {
             test_print(&format!("   ... saving final batch ({} lists), output batch {}",
@@ -466,6 +638,17 @@ impl ListOfNSL {
                 debug_print("process_one_file_of_current_size_n: Error saving final batch");
             }
         }
+        // Input file boundary: record the input as consumed and flush what
+        // --flush-every left pending
+        if let Some(state) = state {
+            let input = InputKey { batch: self.current_file_batch, compacted: self.current_file_compacted, shard: self.shard };
+            state.record_consumed_input(self.current_size, input, self.current_file_list_count);
+            if let Err(e) = state.flush_pending() {
+                debug_print(&format!("Error flushing global state: {}", e));
+            }
+            let progress = state.input_progress(&self.progress_inputs, self.run_started_at);
+            test_print(&format!("   ... size {:02} progress: {}", self.current_size + 1, progress.summary()));
+        }
         
         // Calculate and log this file's statistics
         let file_new_total = self.new_total_list_count - file_new_count_start;
@@ -484,6 +667,8 @@ impl ListOfNSL {
     /// Initialize processing state for a given size and starting batch
     fn init_processing_state(&mut self, current_size: u8, start_batch: u32) {
         self.computation_time = 0.0;
+        self.isomorph_lookups = 0;
+        self.isomorph_hits = 0;
         self.file_io_time = 0.0;
         self.conversion_time = 0.0;
         self.current_size = current_size;
@@ -493,6 +678,9 @@ impl ListOfNSL {
         self.current_total_list_count = 0;
         self.new.clear();
         self.new_file_list_count = 0;
+        self.progress_inputs = crate::filenames::list_input_files(&self.input_path, current_size)
+            .iter().map(|f| InputKey::whole(f.batch, f.compacted)).collect();
+        self.run_started_at = crate::file_info::unix_now();
     }
     
     /// Initialize output batch number (for restart/unitary modes)
@@ -519,11 +707,42 @@ impl ListOfNSL {
             self.file_io_time, (self.file_io_time / elapsed_secs * 100.0),
             self.conversion_time, (self.conversion_time / elapsed_secs * 100.0),
             overhead, (overhead / elapsed_secs * 100.0)));
+        if self.isomorph_cache {
+            let hit_rate = if self.isomorph_lookups == 0 { 0.0 } else { self.isomorph_hits as f64 / self.isomorph_lookups as f64 * 100.0 };
+            test_print(&format!("   ... isomorph cache: {} of {} children dropped ({:.1}% hit rate)",
+                self.isomorph_hits.separated_string(), self.isomorph_lookups.separated_string(), hit_rate));
+        }
+        
+        // Keep a record of this run for the final size report
+        let record = crate::final_report::TimingRecord {
+            timestamp: chrono::Local::now().to_rfc3339(),
+            lists_created: self.new_total_list_count,
+            elapsed_secs,
+            computation_secs: self.computation_time,
+            file_io_secs: self.file_io_time,
+            conversion_secs: self.conversion_time,
+            isomorph_lookups: self.isomorph_lookups,
+            isomorph_hits: self.isomorph_hits,
+        };
+        if let Err(e) = crate::final_report::append_timing_record(&self.output_path, self.current_size + 1, &record) {
+            debug_print(&format!("print_timing_report: could not save timing record: {}", e));
+        }
     }
     
     /// Process batches in a loop with consistent logging
     /// Returns number of batches processed
-    fn process_batch_loop(&mut self, max: &u64, stop_after_one: bool, mut state: Option<&mut GlobalFileState>) -> u32 {
+    fn save_legacy_intermediary_file(&mut self) {
+        let batch_width = 6;
+        let intermediary_filename = format!(
+            "no_set_list_input_intermediate_count_{:02}_{:0width$}.txt",
+            self.current_size, self.current_file_batch,
+            width = batch_width
+        );
+        self.write_input_intermediary_file();
+        test_print(&format!("   ... saving input intermediary file {}", intermediary_filename));
+    }
+    
+    fn process_batch_loop(&mut self, max: &u64, mut state: Option<&mut GlobalFileState>) -> u32 {
         let mut batches_processed = 0;
         
         loop {
@@ -532,33 +751,18 @@ impl ListOfNSL {
                 test_print("");
             }
             test_print(&format!("   ... loading batch {}", self.current_file_batch));
-            let loaded = self.refill_current_from_file();
+            let loaded = self.process_input_batch(max, state.as_deref_mut());
 
             if loaded {
-                test_print(&format!("   ... loaded {:>10} lists from batch {}", 
-                    self.current.len().separated_string(), self.current_file_batch));
-
-                self.process_one_file_of_current_size_n(max, state.as_deref_mut());
 
                 // Write legacy intermediary file only if not using state
                 if state.is_none() {
-                    let batch_width = 6;
-                    let intermediary_filename = format!(
-                        "no_set_list_input_intermediate_count_{:02}_{:0width$}.txt",
-                        self.current_size, self.current_file_batch,
-                        width = batch_width
-                    );
-                    self.write_input_intermediary_file();
-                    test_print(&format!("   ... saving input intermediary file {}", intermediary_filename));
+                    self.save_legacy_intermediary_file();
                 }
                 batches_processed += 1;
                 
                 // Increment batch counter to move to next input file
                 self.current_file_batch += 1;
-                
-                if stop_after_one {
-                    break;
-                }
             } else {
                 debug_print(&format!("process_batch_loop: no more files for size {:02}", 
                     self.current_size));
@@ -591,7 +795,7 @@ impl ListOfNSL {
         self.new_total_list_count = 0;
         
         // Process all batches
-        self.process_batch_loop(max, false, state);
+        self.process_batch_loop(max, state);
         
         debug_print(&format!("process_all_files_of_current_size_n: Finished \
             processing size {:02}", self.current_size));
@@ -604,81 +808,55 @@ impl ListOfNSL {
         self.new_total_list_count
     }
     
-    /// Process files starting from a specific batch number (for restart capability)
-    /// Used to resume processing after interruption
-    pub fn process_from_batch(&mut self, current_size: u8, start_batch: u32, max: &u64, state: Option<&mut GlobalFileState>) -> u64 {
+    /// Process an explicit plan of input files (see `filenames::list_input_files`)
+    /// Each file is loaded by path, so a regular file sharing its batch number with a
+    /// compacted file is processed too instead of being shadowed by it.
+    /// `output_reference_batch` drives the output numbering as in restart mode: output
+    /// batches continue after the outputs created from input batches below it.
+    pub fn process_input_files(&mut self, current_size: u8, files: &[InputFile], output_reference_batch: u32, max: &u64, mut state: Option<&mut GlobalFileState>) -> u64 {
         if current_size < 3 {
-            debug_print("process_from_batch: size must be >= 3");
+            debug_print("process_input_files: size must be >= 3");
             return 0;
         }
         
-        debug_print(&format!("process_from_batch: start processing no-set-{:02} from batch {}", 
-            current_size, start_batch));
+        debug_print(&format!("process_input_files: processing {} input files of no-set-{:02}", 
+            files.len(), current_size));
         
         let start_time = std::time::Instant::now();
         
-        // Initialize from specific batch
-        self.init_processing_state(current_size, start_batch);
-        self.init_output_batch(start_batch);  // Scan for next available output batch
-        
-        // Process all batches from start_batch onwards
-        self.process_batch_loop(max, false, state);
-        
-        debug_print(&format!("process_from_batch: Finished processing size {:02} from batch {}", 
-            self.current_size, start_batch));
+        let first_batch = files.first().map(|f| f.batch).unwrap_or(0);
+        self.init_processing_state(current_size, first_batch);
+        self.init_output_batch(output_reference_batch);
         
-        // Report results
-        let elapsed_secs = start_time.elapsed().as_secs_f64();
-        created_a_total_of(self.new_total_list_count, self.current_size + 1, elapsed_secs);
-        self.print_timing_report(start_time);
-        
-        self.new_total_list_count
-    }
-    
-    /// Process files within a specific batch range (inclusive)
-    /// Used when we want to limit processing to a specific range (e.g., only compacted files)
-    pub fn process_batch_range(&mut self, current_size: u8, start_batch: u32, end_batch: u32, max: &u64, mut state: Option<&mut GlobalFileState>) -> u64 {
-        if current_size < 3 {
-            debug_print("process_batch_range: size must be >= 3");
-            return 0;
-        }
-        
-        debug_print(&format!("process_batch_range: processing no-set-{:02} from batch {} to {}", 
-            current_size, start_batch, end_batch));
-        
-        let start_time = std::time::Instant::now();
-        
-        // Initialize from specific batch
-        self.init_processing_state(current_size, start_batch);
-        self.init_output_batch(start_batch);  // Scan for next available output batch
-        
-        // Process batches in the range [start_batch, end_batch]
-        let mut batches_processed = 0u64;
-        for batch in start_batch..=end_batch {
-            self.current_file_batch = batch;
+        let mut files_processed = 0u64;
+        for file in files {
+            if !crate::disk_guard::has_room(&self.output_path) {
+                test_print(&format!("   ... stopping before input batch {:06}: not enough disk space (see --cascade-min-free-gb)", file.batch));
+                break;
+            }
+            self.current_file_batch = file.batch;
             
             // Add blank line before loading next batch (except for the first one)
-            if batches_processed > 0 {
+            if files_processed > 0 {
                 test_print("");
             }
-            test_print(&format!("   ... loading batch {}", self.current_file_batch));
+            test_print(&format!("   ... loading batch {}{}", self.current_file_batch,
+                if file.compacted { " (compacted)" } else { "" }));
             
-            // Try to load this batch
-            if self.refill_current_from_file() {
-                test_print(&format!("   ... loaded {:>10} lists from batch {}", 
-                    self.current.len().separated_string(), self.current_file_batch));
-                
-                // Process the cards and create new lists
-                self.process_one_file_of_current_size_n(max, state.as_deref_mut());
-                batches_processed += 1;
+            if self.process_input_path(&file.path, max, state.as_deref_mut()) {
+                files_processed += 1;
+                crate::cascade_status::batch_done(file.batch, self.new_total_list_count);
+                // The journal resumes a cascade from a restart batch: regular numbering only
+                if !file.compacted {
+                    crate::cascade_journal::batch_done(file.batch);
+                }
             } else {
-                // File not found - this could be normal if some batches don't exist
-                test_print(&format!("   ... Batch {:06} not found, skipping", batch));
+                test_print(&format!("   ... ERROR: Could not load {}, skipping", file.path));
             }
         }
         
-        debug_print(&format!("process_batch_range: Finished processing size {:02} batches {} to {} ({} batches processed)", 
-            self.current_size, start_batch, end_batch, batches_processed));
+        debug_print(&format!("process_input_files: Finished processing size {:02} ({} files processed)", 
+            self.current_size, files_processed));
         
         // Report results
         let elapsed_secs = start_time.elapsed().as_secs_f64();
@@ -689,35 +867,54 @@ impl ListOfNSL {
     }
     
     /// Process a single input batch (unitary processing)
-    /// Processes one specific input file and generates its output files
+    /// Finds the input file of the batch (the compacted one first) and processes it
     pub fn process_single_batch(&mut self, input_size: u8, input_batch: u32, max: &u64, state: Option<&mut GlobalFileState>) -> u64 {
+        match find_input_filename(&self.input_path, input_size, input_batch) {
+            Some(path) => {
+                let compacted = path.ends_with("_compacted.rkyv");
+                self.process_single_input(input_size, &InputFile { batch: input_batch, path, compacted }, max, state)
+            }
+            None => {
+                test_print(&format!("   ... ERROR: Could not load input file for size {:02} batch {:06}",
+                    input_size, input_batch));
+                0
+            }
+        }
+    }
+    
+    /// Process one specific input file (unitary processing)
+    /// Loaded by path: a regular file sharing its batch number with a compacted file
+    /// is processed, not shadowed by it
+    pub fn process_single_input(&mut self, input_size: u8, input: &InputFile, max: &u64, state: Option<&mut GlobalFileState>) -> u64 {
         if input_size < 3 {
-            debug_print("process_single_batch: input size must be >= 3");
+            debug_print("process_single_input: input size must be >= 3");
             return 0;
         }
         
-        debug_print(&format!("process_single_batch: processing input size {:02} batch {:06}", 
-            input_size, input_batch));
+        debug_print(&format!("process_single_input: processing input size {:02} batch {:06}", 
+            input_size, input.batch));
         
         let start_time = std::time::Instant::now();
         
         // Initialize for single batch
-        self.init_processing_state(input_size, input_batch);
-        self.init_output_batch(input_batch);  // Scan for next available output batch
+        self.init_processing_state(input_size, input.batch);
+        self.init_output_batch(input.batch);  // Scan for next available output batch
         
         test_print(&format!("   ... will create output starting from batch {:06}", self.new_output_batch));
+        test_print(&format!("   ... loading batch {}{}", input.batch, if input.compacted { " (compacted)" } else { "" }));
         
-        // Process only this one batch
-        let batches_processed = self.process_batch_loop(max, true, state);
-        
-        if batches_processed == 0 {
-            test_print(&format!("   ... ERROR: Could not load input file for size {:02} batch {:06}",
-                input_size, input_batch));
+        let legacy = state.is_none();
+        if !self.process_input_path(&input.path, max, state) {
+            test_print(&format!("   ... ERROR: Could not load input file {}", input.path));
             return 0;
         }
+        // Write legacy intermediary file only if not using state
+        if legacy {
+            self.save_legacy_intermediary_file();
+        }
         
-        debug_print(&format!("process_single_batch: Finished processing size {:02} batch {:06}", 
-            input_size, input_batch));
+        debug_print(&format!("process_single_input: Finished processing size {:02} batch {:06}", 
+            input_size, input.batch));
         
         // Report results (slightly different format for unitary)
         test_print(&format!("   ... created {:>17} new no-set-{:02} lists from this batch",
@@ -749,8 +946,7 @@ impl Default for ListOfNSL {
 /// - Final report: nsl_{target_size:02}_global_count.txt
 /// 
 /// All files are stored in the same directory as the source files (base_path)
-pub fn count_size_files(base_path: &str, target_size: u8, force: bool, _keep_state: bool) -> std::io::Result<()> {
-    use std::fs;
+pub fn count_size_files(base_path: &str, target_size: u8, force: bool, keep_state: bool) -> std::io::Result<()> {
     use std::path::PathBuf;
     
     test_print(&format!("\nCounting files for size {:02}...", target_size));
@@ -762,16 +958,13 @@ pub fn count_size_files(base_path: &str, target_size: u8, force: bool, _keep_sta
     
     let start_time = std::time::Instant::now();
     
-    // Step 1: Scan for all .rkyv files in directory
-    let entries = fs::read_dir(base_path)?;
+    // Step 1: Scan for all .rkyv files in directory (or in its databases, see storage)
     let pattern = format!("_to_{:02}_batch_", target_size);
     
     let mut all_files: Vec<PathBuf> = Vec::new();
-    for entry in entries.flatten() {
-        if let Some(name) = entry.file_name().to_str() {
-            if name.starts_with("nsl_") && name.contains(&pattern) && name.ends_with(".rkyv") {
-                all_files.push(entry.path());
-            }
+    for name in crate::storage::list_file_names(base_path)? {
+        if name.contains(&pattern) {
+            all_files.push(std::path::Path::new(base_path).join(name));
         }
     }
     all_files.sort();
@@ -789,6 +982,7 @@ pub fn count_size_files(base_path: &str, target_size: u8, force: bool, _keep_sta
                 test_print(&format!("   ... Loaded {} files from existing global info", file_count));
                 existing_state
             }
+            Err(e) if crate::file_info::is_state_locked(&e) => return Err(e),
             Err(e) => {
                 test_print(&format!("   ... Could not load existing global info: {}", e));
                 test_print("   ... Creating new state...");
@@ -796,17 +990,22 @@ pub fn count_size_files(base_path: &str, target_size: u8, force: bool, _keep_sta
             }
         }
     } else {
+        crate::file_info::acquire_state_lock(base_path, target_size)?;
         test_print("   ... FORCE mode: Creating new state from scratch...");
         GlobalFileState::new(base_path, target_size)
     };
     
-    // Build set of files already in state
+    // Build set of files already in state (the counts estimated by --fast are counted again)
     let mut seen_files: HashSet<String> = state.entries().keys()
         .map(|(_, _, filename)| filename.clone())
+        .filter(|filename| !state.estimated().contains(filename))
         .collect();
+    if !state.estimated().is_empty() {
+        test_print(&format!("   ... {} estimated counts (--fast) to replace", state.estimated().len()));
+    }
     
     // Step 3: Scan directory for .rkyv files not in state and add them
-    test_print(&format!("   ... Scanning directory for files not in state..."));
+    test_print("   ... Scanning directory for files not in state...");
     let mut files_added = 0;
     let mut files_counted = 0;
     
@@ -838,45 +1037,34 @@ pub fn count_size_files(base_path: &str, target_size: u8, force: bool, _keep_sta
                 
                 if let Some(src_batch_pos) = before_to.rfind("_batch_") {
                     let src_batch_str = &before_to[src_batch_pos + 7..];
-                    if let Ok(src_batch) = src_batch_str.parse::<u32>() {
-                        if let Some(tgt_batch_pos) = after_to.rfind("_batch_") {
-                            let tgt_batch_str = &after_to[tgt_batch_pos + 7..];
-                            if let Ok(tgt_batch) = tgt_batch_str.parse::<u32>() {
-                                // Count lists in this file
-                                use memmap2::Mmap;
-                                if let Ok(file) = fs::File::open(path) {
-                                    if let Ok(mmap) = unsafe { Mmap::map(&file) } {
-                                        if let Ok(arch) = check_archived_root::<Vec<NoSetListSerialized>>(&mmap[..]) {
-                                            let count = arch.len() as u64;
-                                            let is_compacted = name.contains("_compacted.rkyv");
-                                            
-                                            // Get file metadata
-                                            let (file_size, mtime) = path.metadata()
-                                                .ok()
-                                                .map(|m| (
-                                                    Some(m.len()),
-                                                    m.modified().ok()
-                                                        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-                                                        .map(|d| d.as_secs() as i64)
-                                                ))
-                                                .unwrap_or((None, None));
-                                            
-                                            // Add to state
-                                            state.register_file(
-                                                &filename,
-                                                src_batch,
-                                                tgt_batch,
-                                                count,
-                                                is_compacted,
-                                                file_size,
-                                                mtime
-                                            );
-                                            
-                                            seen_files.insert(filename.clone());
-                                            files_added += 1;
-                                        }
-                                    }
-                                }
+                    if let Ok(src_batch) = src_batch_str.parse::<u32>()
+                        && let Some(tgt_batch_pos) = after_to.rfind("_batch_") {
+                        let tgt_batch_str = &after_to[tgt_batch_pos + 7..];
+                        if let Ok(tgt_batch) = tgt_batch_str.parse::<u32>() {
+                            // Count lists in this file
+                            if let Ok(count) = crate::io_helpers::count_lists_in_file(&path.to_string_lossy()) {
+                                let is_compacted = name.contains("_compacted.rkyv");
+                                    
+                                // Get file metadata
+                                let (file_size, mtime) = crate::storage::file_metadata(&path.to_string_lossy())
+                                    .map(|(bytes, mtime)| (Some(bytes), mtime))
+                                    .unwrap_or((None, None));
+                                    
+                                // Add to state
+                                state.register_file(
+                                    &filename,
+                                    src_batch,
+                                    tgt_batch,
+                                    count,
+                                    is_compacted,
+                                    file_size,
+                                    mtime
+                                );
+                                    
+                                crate::events::file_processed(crate::events::FileProcessed::new("count", target_size,
+                                    crate::events::FileAction::Counted, filename.as_str(), Some(count)));
+                                seen_files.insert(filename.clone());
+                                files_added += 1;
                             }
                         }
                     }
@@ -897,13 +1085,22 @@ pub fn count_size_files(base_path: &str, target_size: u8, force: bool, _keep_sta
     test_print(&format!("\n   ... Saving state with {} files...", state.entries().len()));
     state.flush()?;
     
-    // Export human-readable formats
+    // Export human-readable formats and the consolidated report
     state.export_human_readable()?;
+    state.export_global_count()?;
+    
+    // Progress files left by an interrupted count (kept with keep_state)
+    if !keep_state {
+        for ext in ["partial", "processed"] {
+            let _ = std::fs::remove_file(std::path::Path::new(base_path).join(format!("nsl_{:02}_global_count.{}", target_size, ext)));
+        }
+    }
     
     let elapsed = start_time.elapsed().as_secs_f64();
     test_print(&format!("\nCount completed in {:.2} seconds", elapsed));
-    test_print(&format!("State saved to: {}/nsl_{:02}_global_info.rkyv", base_path, target_size));
+    test_print(&format!("State saved to: {}", state.state_path().display()));
     test_print(&format!("Exported to: {}/nsl_{:02}_global_info.json and .txt", base_path, target_size));
+    test_print(&format!("Report saved to: {}/nsl_{:02}_global_count.txt", base_path, target_size));
     Ok(())
 }
 
@@ -944,26 +1141,21 @@ fn _is_intermediary_file_valid(intermediary_file: &str, source_files: &[std::pat
 /// Process a batch of files and write results to an intermediary file
 /// Helper: create input-intermediary files from a list of .rkyv files (one per source batch)
 fn _create_input_intermediary_from_files(files: &[std::path::PathBuf], output_file: &str) -> std::io::Result<u64> {
-    use std::fs::File;
     use std::io::Write;
-    use memmap2::Mmap;
 
     let mut total = 0u64;
     let mut out = std::fs::File::create(output_file)?;
 
     for path in files {
         if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-            let file = File::open(path)?;
-            let mmap = unsafe { Mmap::map(&file)? };
-            match check_archived_root::<Vec<NoSetListSerialized>>(&mmap[..]) {
-                Ok(arch) => {
-                    let count = arch.len() as u64;
+            match crate::io_helpers::count_lists_in_file(&path.to_string_lossy()) {
+                Ok(count) => {
                     total += count;
                     writeln!(out, "   ... {:>8} lists in {}", count, name)?;
                     test_print(&format!("       {:>10} lists in {}", count.separated_string(), name));
                 }
                 Err(e) => {
-                    debug_print(&format!("create_input_intermediary_from_files: Validation error for {}: {:?}", name, e));
+                    debug_print(&format!("create_input_intermediary_from_files: Validation error for {}: {}", name, e));
                 }
             }
         }
@@ -972,16 +1164,10 @@ fn _create_input_intermediary_from_files(files: &[std::path::PathBuf], output_fi
     Ok(total)
 }
 
-/// Helper to print large numbers with thousand separators and timing info
+/// Report the `nb` lists of `size` created in `elapsed_secs` (see events::SizeCompleted)
 pub fn created_a_total_of(nb: u64, size: u8, elapsed_secs: f64) {
-        let hours = (elapsed_secs / 3600.0) as u64;
-        let minutes = ((elapsed_secs % 3600.0) / 60.0) as u64;
-        let seconds = (elapsed_secs % 60.0) as u64;
-        
-        test_print(&format!("   ... created a total of {:>15} no-set-{:02} lists \
-            in {:>10.2} seconds ({:02}h{:02}m{:02}s)", 
-            nb.separated_string(), size, elapsed_secs, hours, minutes, seconds));
-    }
+    crate::events::size_completed(crate::events::SizeCompleted { size, nb_lists: nb, elapsed_secs });
+}
 
 
 
@@ -991,6 +1177,91 @@ mod tests {
     use std::fs::{self, File};
     use std::io::Write;
 
+    #[test]
+    fn deep_count_reports_counts_differing_from_the_state() {
+        let base = crate::test_dir::TestDir::new("deep_count");
+        let base_path = base.to_string_lossy().into_owned();
+        let lists: Vec<NoSetListSerialized> = (0..3).map(|i| NoSetListSerialized {
+            n: 3, max_card: 9, no_set_list: vec![i, 5, 9], remaining_cards_list: vec![],
+        }).collect();
+        let mut state = GlobalFileState::new(&base_path, 9);
+        // Batch 0 recorded right, batch 1 recorded with 5 lists, batch 2 missing
+        for (batch, recorded) in [(0u32, 3u64), (1, 5), (2, 3)] {
+            let name = format!("nsl_08_batch_000000_to_09_batch_{:06}.rkyv", batch);
+            if batch < 2 {
+                assert!(save_to_file_serialized(&lists, &format!("{}/{}", base_path, name)));
+            }
+            state.register_file(&name, 0, batch, recorded, false, None, None);
+        }
+
+        let mismatches = deep_count_mismatches(&base_path, &state, 2);
+        let found: Vec<(u32, Option<u64>, bool)> = mismatches.iter()
+            .map(|(info, check)| (info.target_batch, check.list_count, check.error.is_some())).collect();
+        assert_eq!(found, vec![(1, Some(3), false), (2, None, true)]);
+
+        state.flush().unwrap();
+        let report = check_size_files(&base_path, 9, Some(2), None).unwrap();
+        assert_eq!(report.status, crate::findings::Status::Errors);
+        let mismatched: Vec<Option<u32>> = report.findings.iter()
+            .filter(|f| f.kind == "count_mismatch").map(|f| f.batch).collect();
+        assert_eq!(mismatched, vec![Some(1), Some(2)]);
+    }
+
+    #[test]
+    fn against_input_reports_unconsumed_input_batches() {
+        let base = crate::test_dir::TestDir::new("against_input");
+        let (input_dir, output_dir) = (base.join("08"), base.join("09"));
+        fs::create_dir_all(&input_dir).unwrap();
+        fs::create_dir_all(&output_dir).unwrap();
+        let (input_path, output_path) = (input_dir.to_string_lossy().into_owned(), output_dir.to_string_lossy().into_owned());
+        let mut inputs = GlobalFileState::new(&input_path, 8);
+        for batch in 0..3 {
+            inputs.register_file(&format!("nsl_07_batch_000000_to_08_batch_{:06}.rkyv", batch), 0, batch, 10, false, None, None);
+        }
+        inputs.flush().unwrap();
+        let mut outputs = GlobalFileState::new(&output_path, 9);
+        for batch in 0..2 {
+            outputs.register_file(&format!("nsl_08_batch_{:06}_to_09_batch_{:06}.rkyv", batch, batch), batch, batch, 10, false, None, None);
+        }
+        outputs.flush().unwrap();
+
+        let report = check_size_files(&output_path, 9, None, Some(&input_path)).unwrap();
+        let unconsumed: Vec<Option<u32>> = report.findings.iter()
+            .filter(|f| f.kind == "unconsumed_input").map(|f| f.batch).collect();
+        assert_eq!(unconsumed, vec![Some(2)]);
+        assert_eq!(report.details.input_dir.as_deref(), Some(input_path.as_str()));
+    }
+
+    #[test]
+    fn batch_collisions_recommend_the_recorded_file() {
+        let dir = crate::test_dir::TestDir::new("collisions");
+        let path = dir.to_string_lossy().into_owned();
+        let list = NoSetListSerialized { n: 3, max_card: 3, no_set_list: vec![0, 1, 3], remaining_cards_list: vec![] };
+        // Batch 0: two regular files from different sources, only the second recorded
+        // Batch 1: the regular and _compacted variants of one file, neither recorded
+        let files = [("nsl_02_batch_000000_to_03_batch_000000.rkyv", 1), ("nsl_02_batch_000001_to_03_batch_000000.rkyv", 2),
+            ("nsl_02_batch_000002_to_03_batch_000001.rkyv", 1), ("nsl_02_batch_000002_to_03_batch_000001_compacted.rkyv", 3)];
+        for (name, nb) in files {
+            assert!(crate::io_helpers::save_to_file_serialized(&vec![list.clone(); nb], &format!("{}/{}", path, name)));
+        }
+        let mut state = GlobalFileState::new(&path, 3);
+        state.register_file(files[1].0, 1, 0, 2, false, None, None);
+        state.flush().unwrap();
+
+        let names: Vec<String> = files.iter().map(|(name, _)| name.to_string()).collect();
+        let collisions = find_batch_collisions(&path, &names, &state);
+        assert_eq!(collisions.len(), 2);
+        assert_eq!((collisions[0].target_batch, collisions[0].candidates[0].filename.as_str()), (0, files[1].0));
+        assert_eq!(collisions[0].reason, "recorded in the state with the count of the file");
+        assert_eq!((collisions[1].target_batch, collisions[1].candidates[0].filename.as_str()), (1, files[3].0));
+        assert_eq!(collisions[1].reason, "most lists on disk");
+
+        let report = check_size_files(&path, 3, None, None).unwrap();
+        let collided: Vec<Option<u32>> = report.findings.iter()
+            .filter(|f| f.kind == "batch_collision").map(|f| f.batch).collect();
+        assert_eq!(collided, vec![Some(0), Some(1)]);
+    }
+
     #[test]
     fn incremental_count_resume() {
         // Create a temporary directory
@@ -1159,6 +1430,38 @@ mod tests {
         let _ = fs::remove_dir_all(&base);
     }
 
+    #[test]
+    fn isomorph_cache_drops_children_already_seen_in_the_batch() {
+        let (i, j, k) = (0, 1, 3);
+        let forbidden = [next_to_set(i, j), next_to_set(i, k), next_to_set(j, k)];
+        let remaining: Vec<usize> = ((k + 1)..81).filter(|c| !forbidden.contains(c)).collect();
+        let seed = NoSetList::from_slices(3, k, &[i, j, k], &remaining);
+        let children = seed.build_higher_nsl(false).len() as u64;
+
+        let mut lists = ListOfNSL::new();
+        lists.isomorph_cache = true;
+        lists.begin_input_file();
+        lists.expand_input_list(seed, 0, 1, &u64::MAX, None);
+        let kept = lists.new.len() as u64;
+        assert_eq!(lists.isomorph_lookups, children);
+        assert!(kept > 0 && kept < children, "misses are kept, hits dropped ({} of {} kept)", kept, children);
+        assert_eq!(lists.isomorph_hits, children - kept);
+
+        // Within the same input batch, every child of the same list is a hit
+        lists.expand_input_list(seed, 0, 1, &u64::MAX, None);
+        assert_eq!((lists.new.len() as u64, lists.isomorph_hits), (kept, 2 * children - kept));
+
+        // A new input batch starts with an empty cache
+        lists.begin_input_file();
+        lists.expand_input_list(seed, 0, 1, &u64::MAX, None);
+        assert_eq!(lists.new.len() as u64, 2 * kept);
+
+        let mut plain = ListOfNSL::new();
+        plain.begin_input_file();
+        plain.expand_input_list(seed, 0, 1, &u64::MAX, None);
+        assert_eq!((plain.new.len() as u64, plain.isomorph_lookups), (children, 0), "no cache, no lookups");
+    }
+
 }
 
 /// Regenerate the consolidated global report from the partial CSV file.
@@ -1181,11 +1484,10 @@ pub fn _regenerate_report_from_partial(base_path: &str, target_size: u8, partial
     for line in reader.lines() {
         let line = line?;
         let parts: Vec<&str> = line.splitn(4, ',').collect();
-        if parts.len() == 4 {
-            if let (Ok(src), Ok(tgt), Ok(count)) = (parts[0].parse::<u32>(), parts[1].parse::<u32>(), parts[2].parse::<u64>()) {
-                let filename = parts[3].to_string();
-                by_file.insert(filename, (src, tgt, count));
-            }
+        if parts.len() == 4
+            && let (Ok(src), Ok(tgt), Ok(count)) = (parts[0].parse::<u32>(), parts[1].parse::<u32>(), parts[2].parse::<u64>()) {
+            let filename = parts[3].to_string();
+            by_file.insert(filename, (src, tgt, count));
         }
     }
 
@@ -1225,10 +1527,147 @@ pub fn _regenerate_report_from_partial(base_path: &str, target_size: u8, partial
     Ok(())
 }
 
+/// One of the files claiming a target batch (shards of a file taken together)
+#[derive(Debug, Clone)]
+pub struct CollisionCandidate {
+    pub filename: String,      // without shard tag
+    pub recorded: Option<u64>, // lists recorded in the state (None: not in the state)
+    pub on_disk: Option<u64>,  // lists in the file (None: unreadable)
+}
+
+impl CollisionCandidate {
+    fn count_matches(&self) -> bool {
+        self.recorded.is_some() && self.recorded == self.on_disk
+    }
+}
+
+/// Files claiming the same target batch, with the one to keep
+#[derive(Debug, Clone)]
+pub struct BatchCollision {
+    pub target_batch: u32,
+    pub candidates: Vec<CollisionCandidate>, // the one to keep first
+    pub reason: String,                      // why the first one is kept
+}
+
+// File without shard tag -> (source batch, compacted, names of its shards)
+type FilesOfBatch<'a> = std::collections::BTreeMap<String, (u32, bool, Vec<&'a String>)>;
+
+/// Output files of `names` (in `base_path`) whose target batch collides: regular
+/// files from different source batches, compacted files from different source
+/// batches, or the regular and _compacted variants of one file (a compacted file
+/// and a regular one from different sources may share a number: compaction
+/// numbers its files on their own). The candidates are ranked by: recorded in the
+/// state with the count of the file, recorded in the state, most lists on disk
+pub fn find_batch_collisions(base_path: &str, names: &[String], state: &GlobalFileState) -> Vec<BatchCollision> {
+    use std::collections::BTreeMap;
+    let mut by_batch: BTreeMap<u32, FilesOfBatch> = BTreeMap::new();
+    for name in names.iter() {
+        let Some(parsed) = parse_filename(name) else { continue };
+        let stem = name.strip_suffix(".rkyv").unwrap_or(name);
+        let file = format!("{}.rkyv", strip_shard_tag(stem));
+        by_batch.entry(parsed.target_batch).or_default()
+            .entry(file).or_insert((parsed.source_batch, parsed.compacted, Vec::new())).2.push(name);
+    }
+
+    let mut collisions = Vec::new();
+    for (target_batch, files) in by_batch.iter().filter(|(_, files)| files.len() > 1) {
+        let collides = |(a_src, a_compacted): (u32, bool), (b_src, b_compacted): (u32, bool)| {
+            a_compacted == b_compacted || a_src == b_src
+        };
+        let involved: Vec<_> = files.iter()
+            .filter(|(a, (a_src, a_compacted, _))| files.iter()
+                .any(|(b, (b_src, b_compacted, _))| a != &b && collides((*a_src, *a_compacted), (*b_src, *b_compacted))))
+            .collect();
+        if involved.is_empty() {
+            continue;
+        }
+        let mut candidates: Vec<CollisionCandidate> = involved.into_iter().map(|(file, (_, _, shards))| {
+            let recorded: Vec<u64> = state.entries().values()
+                .filter(|e| shards.contains(&&e.filename))
+                .map(|e| e.nb_lists_in_file)
+                .collect();
+            let on_disk: Option<u64> = shards.iter()
+                .map(|shard| count_lists_in_file(&format!("{}/{}", base_path, shard)).ok())
+                .sum();
+            CollisionCandidate {
+                filename: file.clone(),
+                recorded: (!recorded.is_empty()).then(|| recorded.iter().sum()),
+                on_disk,
+            }
+        }).collect();
+        candidates.sort_by_key(|c| std::cmp::Reverse((c.count_matches(), c.recorded.is_some(), c.on_disk.unwrap_or(0))));
+        let (keep, runner_up) = (&candidates[0], &candidates[1]);
+        let reason = if keep.count_matches() && !runner_up.count_matches() {
+            "recorded in the state with the count of the file"
+        } else if keep.recorded.is_some() && runner_up.recorded.is_none() {
+            "the only one recorded in the state"
+        } else if keep.on_disk.unwrap_or(0) > runner_up.on_disk.unwrap_or(0) {
+            "most lists on disk"
+        } else {
+            "no clear choice: compare them with --inspect"
+        }.to_string();
+        collisions.push(BatchCollision { target_batch: *target_batch, candidates, reason });
+    }
+    collisions
+}
+
+/// Re-count the lists of every file recorded in `state`, on `workers` threads (0: one
+/// per core), and return the entries whose file is missing, unreadable or holds
+/// another number of lists than the state records, with what was found
+pub fn deep_count_mismatches(base_path: &str, state: &GlobalFileState, workers: usize) -> Vec<(FileInfo, FileCheckResult)> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    let entries: Vec<FileInfo> = state.entries().values().cloned().collect();
+    let workers = match workers {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    }.min(entries.len().max(1));
+    let next = AtomicUsize::new(0);
+    let mut mismatches: Vec<_> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers).map(|_| scope.spawn(|| {
+            let mut found = Vec::new();
+            while let Some(info) = entries.get(next.fetch_add(1, Ordering::Relaxed)) {
+                let check = info.clone().refresh_status(base_path, true);
+                crate::events::file_processed(crate::events::FileProcessed::new("check", state.target_size(),
+                    crate::events::FileAction::Checked, info.filename.as_str(), check.list_count));
+                if check.error.is_some() || check.list_count != Some(info.nb_lists_in_file) {
+                    found.push((info.clone(), check));
+                }
+            }
+            found
+        })).collect();
+        handles.into_iter().flat_map(|h| h.join().unwrap_or_default()).collect()
+    });
+    mismatches.sort_by_key(|(info, _)| (info.target_batch, info.source_batch));
+    mismatches
+}
+
+/// What --check looked at, saved with its findings (nsl_{size:02}_check_report.json)
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckDetails {
+    pub size: u8,
+    pub files_found: u64,
+    pub first_batch: Option<u32>,
+    pub last_batch: Option<u32>,
+    pub deep: bool,               // list counts re-read from the files (--deep)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_dir: Option<String>, // previous size checked for consumption (--against-input)
+    pub untracked: Vec<crate::gc::UntrackedFile>, // files of the directory not tracked by the state
+    pub reclaimable_bytes: u64,                   // bytes of the orphans safe to delete (--gc --force)
+}
+
 /// Check repository integrity for a specific size
     /// - Lists missing output batches (should be continuous)
     /// - Lists files mentioned in intermediary files but missing from directory
-pub fn check_size_files(base_path: &str, target_size: u8) -> std::io::Result<()> {
+    /// - Lists target batches claimed by several files, with the one to keep
+    /// - Warns about the list counts estimated from the file sizes (--count --fast)
+    /// - Classifies the files the state does not track (orphan artifacts, list files
+    ///   missing from the state, legacy formats, unknown) with the reclaimable space
+    /// - With `deep` (worker threads, 0: one per core): re-counts the lists of every
+    ///   file and reports the state entries whose count differs
+    /// - With `against_input` (directory of the size target_size - 1): reports the input
+    ///   batches with no output, as --validate-chain does for one pair of sizes
+    /// - Returns the findings (see findings), for the check report
+pub fn check_size_files(base_path: &str, target_size: u8, deep: Option<usize>, against_input: Option<&str>) -> std::io::Result<FindingsReport<CheckDetails>> {
     use std::fs;
     use std::path::PathBuf;
     use std::collections::{BTreeSet, HashMap};
@@ -1237,29 +1676,26 @@ pub fn check_size_files(base_path: &str, target_size: u8) -> std::io::Result<()>
     test_print(&format!("\nCHECK MODE: Analyzing repository for size {:02}...", target_size));
     test_print(&format!("   Directory: {}", base_path));
     
-    // Step 1: Scan directory and collect all output files
-    let entries = fs::read_dir(base_path)?;
+    // Step 1: Scan directory (and its databases, see storage) and collect all output files
     let pattern = format!("_to_{:02}_batch_", target_size);
     
     let mut all_files: Vec<String> = Vec::new();
     let mut batch_numbers: BTreeSet<u32> = BTreeSet::new();
+    let mut findings: Vec<Finding> = Vec::new();
     
-    for entry in entries.flatten() {
-        if let Some(name) = entry.file_name().to_str() {
-            if name.starts_with("nsl_") && name.contains(&pattern) && name.ends_with(".rkyv") {
-                all_files.push(name.to_string());
-                
-                // Extract target batch number
-                if let Some(to_pos) = name.find("_to_") {
-                    let after_to = &name[to_pos + 4..];
-                    if let Some(tgt_batch_pos) = after_to.rfind("_batch_") {
-                        let tgt_batch_str = &after_to[tgt_batch_pos + 7..after_to.len() - 5]; // -5 for ".rkyv"
-                        if let Ok(batch_num) = tgt_batch_str.parse::<u32>() {
-                            batch_numbers.insert(batch_num);
-                        }
+    for name in crate::storage::list_file_names(base_path)? {
+        if name.contains(&pattern) {
+            // Extract target batch number
+            if let Some(to_pos) = name.find("_to_") {
+                let after_to = &name[to_pos + 4..];
+                if let Some(tgt_batch_pos) = after_to.rfind("_batch_") {
+                    let tgt_batch_str = &after_to[tgt_batch_pos + 7..after_to.len() - 5]; // -5 for ".rkyv"
+                    if let Ok(batch_num) = tgt_batch_str.parse::<u32>() {
+                        batch_numbers.insert(batch_num);
                     }
                 }
             }
+            all_files.push(name);
         }
     }
     
@@ -1285,6 +1721,8 @@ pub fn check_size_files(base_path: &str, target_size: u8) -> std::io::Result<()>
             test_print(&format!("   [!!] Found {} missing batches:", missing_batches.len()));
             for batch in &missing_batches {
                 test_print(&format!("        - Batch {:06}", batch));
+                findings.push(Finding::warning("missing_batch", format!("batch {:06} missing from the sequence", batch))
+                    .with_batch(*batch));
             }
         }
     } else {
@@ -1337,11 +1775,14 @@ pub fn check_size_files(base_path: &str, target_size: u8) -> std::io::Result<()>
             test_print(&format!("   [!!] Found {} files in consolidated file but missing from directory:", missing_from_consolidated.len()));
             for filename in &missing_from_consolidated {
                 test_print(&format!("        - {}", filename));
+                findings.push(Finding::error("missing_file", "listed in the consolidated count file, missing from the directory")
+                    .with_file(filename.as_str()));
             }
         }
     } else {
         test_print(&format!("\n   Consolidated count file not found: nsl_{:02}_global_count.txt", target_size));
         test_print("   Run --count mode first to generate count file");
+        findings.push(Finding::warning("no_count_file", format!("nsl_{:02}_global_count.txt not found", target_size)));
     }
     
     // Step 3: Read intermediary count files and check for missing files
@@ -1351,10 +1792,9 @@ pub fn check_size_files(base_path: &str, target_size: u8) -> std::io::Result<()>
     
     let mut intermediary_files: Vec<PathBuf> = Vec::new();
     for entry in entries.flatten() {
-        if let Some(name) = entry.file_name().to_str() {
-            if name.starts_with(&count_pattern_new) && name.ends_with(".txt") {
-                intermediary_files.push(entry.path());
-            }
+        if let Some(name) = entry.file_name().to_str()
+            && name.starts_with(&count_pattern_new) && name.ends_with(".txt") {
+            intermediary_files.push(entry.path());
         }
     }
     
@@ -1397,12 +1837,148 @@ pub fn check_size_files(base_path: &str, target_size: u8) -> std::io::Result<()>
             test_print(&format!("   [!!] Found {} files listed but missing from directory:", missing_files.len()));
             for filename in &missing_files {
                 test_print(&format!("        - {}", filename));
+                // Compaction deletes the files it merged: not an integrity error on its own
+                findings.push(Finding::warning("missing_intermediary_file", "listed in an intermediary count file, missing from the directory")
+                    .with_file(filename.as_str()));
             }
         }
     }
     
+    // Step 4: Lineage of the compacted files (source batches recorded by compaction)
+    let state = crate::dry_run::load_state_readonly(base_path, target_size)?;
+    let mut lists_by_name: HashMap<&str, u64> = HashMap::new();
+    for e in state.entries().values() {
+        *lists_by_name.entry(e.filename.as_str()).or_default() += e.nb_lists_in_file;
+    }
+    let sources = state.compacted_sources();
+    let untracked = state.entries().values()
+        .filter(|e| e.compacted && !sources.contains_key(&e.filename))
+        .count();
+    if sources.is_empty() && untracked == 0 {
+        test_print("\n   No compacted files in the state");
+    } else {
+        test_print(&format!("\n   Lineage of {} compacted files", sources.len()));
+        let mut mismatches = 0usize;
+        for (filename, contributions) in sources.iter() {
+            let from_sources: u64 = contributions.iter().map(|c| c.nb_lists).sum();
+            let batches: Vec<String> = contributions.iter()
+                .map(|c| format!("{:06}:{}", c.source_batch, c.nb_lists.separated_string()))
+                .collect();
+            test_print(&format!("   ... {} <- {}", filename, batches.join(", ")));
+            if lists_by_name.get(filename.as_str()) != Some(&from_sources) {
+                mismatches += 1;
+                let recorded = lists_by_name.get(filename.as_str()).copied().unwrap_or(0);
+                test_print(&format!("        [!!] {} lists from the sources, {} recorded for the file",
+                    from_sources.separated_string(), recorded.separated_string()));
+                findings.push(Finding::error("lineage_mismatch",
+                    format!("{} lists from the sources, {} recorded for the file", from_sources, recorded))
+                    .with_file(filename.as_str()));
+            }
+        }
+        if mismatches == 0 {
+            test_print("   [OK] Every compacted file holds the lists of its recorded sources");
+        }
+        if untracked > 0 {
+            test_print(&format!("   {} compacted files have no recorded sources (compacted before they were recorded)", untracked));
+        }
+    }
+
+    // Counts estimated from the file sizes (--count --fast) are not counts yet
+    if !state.estimated().is_empty() {
+        test_print(&format!("\n   {} list counts are estimated from the file sizes (--count --fast): run --count {} to count them",
+            state.estimated().len(), target_size));
+        findings.push(Finding::warning("estimated_counts",
+            format!("{} list counts estimated from the file sizes", state.estimated().len())));
+    }
+
+    // Step 5: Target batches claimed by several files (restart accidents)
+    let collisions = find_batch_collisions(base_path, &all_files, &state);
+    if collisions.is_empty() {
+        test_print("\n   [OK] No target batch claimed by several files");
+    } else {
+        test_print(&format!("\n   [!!] Found {} target batches claimed by several files:", collisions.len()));
+        for collision in collisions.iter() {
+            let describe = |c: &CollisionCandidate| format!("{} ({} recorded, {} on disk)", c.filename,
+                c.recorded.map_or_else(|| "not".to_string(), |n| n.separated_string()),
+                c.on_disk.map_or_else(|| "unreadable".to_string(), |n| n.separated_string()));
+            test_print(&format!("        - Batch {:06}: keep {} ({})", collision.target_batch,
+                describe(&collision.candidates[0]), collision.reason));
+            for other in collision.candidates[1..].iter() {
+                test_print(&format!("          remove {}", describe(other)));
+            }
+            let others: Vec<&str> = collision.candidates[1..].iter().map(|c| c.filename.as_str()).collect();
+            findings.push(Finding::error("batch_collision", format!("{} files claim batch {:06}: keep {} ({}), remove {}",
+                collision.candidates.len(), collision.target_batch, collision.candidates[0].filename,
+                collision.reason, others.join(", ")))
+                .with_file(collision.candidates[0].filename.as_str()).with_batch(collision.target_batch));
+        }
+    }
+
+    // Step 6 (--deep): the list counts of the state against the files
+    if let Some(workers) = deep {
+        test_print(&format!("\n   Re-counting the lists of {} files", state.entries().len()));
+        let started = std::time::Instant::now();
+        let mismatches = deep_count_mismatches(base_path, &state, workers);
+        if mismatches.is_empty() {
+            test_print(&format!("   [OK] Every file holds the lists recorded in the state ({:.1}s)", started.elapsed().as_secs_f64()));
+        } else {
+            test_print(&format!("   [!!] Found {} files whose list count differs from the state:", mismatches.len()));
+            for (info, check) in mismatches.iter() {
+                let message = match (&check.error, check.list_count) {
+                    (Some(error), _) => format!("{} lists recorded, {}", info.nb_lists_in_file.separated_string(), error),
+                    (None, found) => format!("{} lists recorded, {} on disk",
+                        info.nb_lists_in_file.separated_string(), found.unwrap_or(0).separated_string()),
+                };
+                let estimated = state.estimated().contains(&info.filename);
+                test_print(&format!("        - {}: {}{}", info.filename, message, if estimated { " (estimated)" } else { "" }));
+                let finding = if estimated && check.error.is_none() {
+                    Finding::warning("estimated_count_mismatch", message)
+                } else {
+                    Finding::error("count_mismatch", message)
+                };
+                findings.push(finding.with_file(info.filename.as_str()).with_batch(info.target_batch));
+            }
+        }
+    }
+
+    // Step 7 (--against-input): consumption of the input batches of the previous size
+    if let Some(input_dir) = against_input {
+        test_print(&format!("\n   Consumption of the size {:02} batches of {}", target_size - 1, input_dir));
+        let link = crate::validate_chain::validate_link(input_dir, base_path, target_size - 1)?;
+        crate::validate_chain::print_link(&link);
+        findings.extend(crate::validate_chain::link_findings(&link));
+    }
+
+    // Step 8: Files of the directory the state does not track (crashes leave some behind)
+    let tracked: HashSet<&str> = state.entries().values().map(|e| e.filename.as_str()).collect();
+    let untracked = crate::gc::classify_untracked(base_path, target_size, &tracked)?;
+    let reclaimable_bytes: u64 = untracked.iter().filter(|f| f.reclaimable).map(|f| f.bytes).sum();
+    if untracked.is_empty() {
+        test_print("\n   [OK] Every file of the directory is tracked by the state");
+    } else {
+        test_print(&format!("\n   {} files not tracked by the state ({} bytes reclaimable with --gc --force):",
+            untracked.len(), reclaimable_bytes.separated_string()));
+        for file in untracked.iter() {
+            test_print(&format!("        - {:<14} | {:>15} bytes | {} ({})", file.kind.label(),
+                file.bytes.separated_string(), file.filename, file.detail));
+            if file.kind == crate::gc::UntrackedKind::UntrackedList {
+                findings.push(Finding::warning("untracked_list_file", file.detail.as_str()).with_file(file.filename.as_str()));
+            }
+        }
+    }
+
     test_print("\nCheck completed");
-    return Ok(());
+    let details = CheckDetails {
+        size: target_size,
+        files_found: all_files.len() as u64,
+        first_batch: batch_numbers.iter().next().copied(),
+        last_batch: batch_numbers.iter().next_back().copied(),
+        deep: deep.is_some(),
+        input_dir: against_input.map(str::to_string),
+        untracked,
+        reclaimable_bytes,
+    };
+    Ok(FindingsReport::new("check", findings, details))
 }
 
 /// Compact small output files into larger 10M-entry batches
@@ -1420,10 +1996,10 @@ fn save_compacted_batch(filepath: &str, lists: &[NoSetListSerialized]) -> std::i
     
     let mut serializer = AllocSerializer::<4096>::default();
     serializer.serialize_value(&lists_vec)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Serialization error: {:?}", e)))?;
+        .map_err(|e| std::io::Error::other(format!("Serialization error: {:?}", e)))?;
     
     let bytes = serializer.into_serializer().into_inner();
-    std::fs::write(filepath, bytes)?;
+    crate::io_helpers::write_file_atomic(filepath, &bytes)?;
     
     Ok(())
 }
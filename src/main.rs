@@ -1,61 +1,123 @@
-/// Manage the search for the grail of Set: combinations of up to 20 cards 
-/// with no sets
-///
-/// Version 0.4.14 - Added --save-history mode for historical state preservation
-/// Added: --save-history mode to merge current state with historical records
-/// Enhanced: Automatic history saving after --size, --unitary, and --cascade modes
-/// Enhanced: Cascade mode calls internal functions instead of spawning subprocesses
-/// Previous: --cascade mode for automated multi-size processing
-/// Previous: --size mode with compaction workflow for sizes 13+
-/// Previous: Automatic input/output compaction for sizes 13+
-/// 
-/// CLI Usage:
-///   funny.exe --size 3 -o .\output                          # Create seed lists (size 3)
-///   funny.exe --size 5 -i .\input -o .\output               # Build size 5 from size 4
-///   funny.exe --size 5 2 -i .\input -o .\output             # Restart size 5 from input batch 2
-///   funny.exe --size 14 -i .\input -o .\output              # Build size 14 (auto-compact input & output)
-///   funny.exe --size 14 -i .\input -o .\output --force      # Build size 14 (process all files, not just compacted)
-///   funny.exe --unitary 5 2 -i .\input -o .\output          # Process only input batch 2
-///   funny.exe --cascade 12 -i X:\funny                      # Cascade from size 12 (process 13-20)
-///   funny.exe --save-history 14 -i .\14_to_15               # Save historical state for size 14
-///   funny.exe --count 6 -i .\output                         # Count size 6 files
-///   funny.exe --check 6 -o .\output                         # Check size 6 integrity
-///   funny.exe --compact 15 -i .\14_to_15                    # Compact all size 15 files
-///   funny.exe --compact 15 5000 -i .\14_to_15               # Compact up to batch 5000
-///   funny.exe                                               # Default mode (sizes 4-20)
-///
-/// Arguments:
-///   --size, -s <SIZE> [BATCH]  Target output size (3-20), optional batch to restart from
-///                              If omitted, runs default behavior (creates seeds + sizes 4-20)
-///   --unitary <SIZE> <BATCH>   Process only one specific input batch (unitary processing)
-///   --cascade <INPUT_SIZE>     Process all sizes from INPUT_SIZE (12-19) to size 20
-///                              Automatically detects last processed batch per size
-///   --save-history <SIZE>      Merge current state with historical records for preservation
-///                              Automatically called after --size, --unitary, --cascade
-///   --count <SIZE>             Count existing files and create summary report
-///   --check <SIZE>             Check repository integrity (missing batches/files)
-///   --force                    Force regeneration of count file (with size batch/unitary)
-///   --input-path, -i           Optional: Directory for input files (defaults to current)
-///                              For cascade mode: root directory with subdirectories
-///   --output-path, -o          Optional: Directory for output files (defaults to input)
-///
-/// Implementation:
-///   - Hybrid approach: NoSetList (stack) for fast computation, NoSetListSerialized (heap) for compact I/O
-///   - Creates .rkyv files with size_32 encoding (~2GB per 20M batch)
-///   - 4-5× faster than heap-only v0.2.2 while maintaining compact file sizes
-
-mod utils;
-mod set;
-mod no_set_list;
-mod io_helpers;
-mod filenames;
-mod compaction;
-mod list_of_nsl;
-mod file_info;
+//! Manage the search for the grail of Set: combinations of up to 20 cards 
+//! with no sets
+//!
+//! Version 0.4.14 - Added --save-history mode for historical state preservation
+//! Added: --save-history mode to merge current state with historical records
+//! Enhanced: Automatic history saving after --size, --unitary, and --cascade modes
+//! Enhanced: Cascade mode calls internal functions instead of spawning subprocesses
+//! Previous: --cascade mode for automated multi-size processing
+//! Previous: --size mode with compaction workflow for sizes 13+
+//! Previous: Automatic input/output compaction for sizes 13+
+//! 
+//! CLI Usage:
+//!   funny.exe --size 3 -o .\output                          # Create seed lists (size 3)
+//!   funny.exe --size 5 -i .\input -o .\output               # Build size 5 from size 4
+//!   funny.exe --size 5 2 -i .\input -o .\output             # Restart size 5 from input batch 2
+//!   funny.exe --size 14 -i .\input -o .\output              # Build size 14 (auto-compact input & output)
+//!   funny.exe --size 14 -i .\input -o .\output --force      # Build size 14 (regenerate count file first)
+//!   funny.exe --unitary 5 2 -i .\input -o .\output          # Process only input batch 2
+//!   funny.exe --cascade 12 -i X:\funny                      # Cascade from size 12 (process 13-20)
+//!   funny.exe --cascade 3 -i X:\funny                       # Whole pipeline: seeds, then sizes 4-20
+//!   funny.exe --cascade 14 --stop-at 17 --skip-sizes 16 -i X:\funny  # Sizes 15 and 17 only
+//!   funny.exe --save-history 14 -i .\14_to_15               # Save historical state for size 14
+//!   funny.exe --count 6 -i .\output                         # Count size 6 files
+//!   funny.exe --count 15 -i .\15 --fast                      # Estimate size 15 counts from file sizes
+//!   funny.exe --count 16 -i .\16 --follow 10                 # Live count of size 16, every 10 minutes
+//!   funny.exe --check 6 -o .\output                         # Check size 6 integrity
+//!   funny.exe --compact 15 -i .\14_to_15                    # Compact all size 15 files
+//!   funny.exe --compact 15 5000 -i .\14_to_15               # Compact up to batch 5000
+//!   funny.exe --compact 15 -i .\nas -o .\local --delete-originals  # Out-of-place, verified
+//!   funny.exe --export-lists nsl_*_to_05_*.rkyv -i .\out     # Export lists as .txt/.json
+//!   funny.exe --orbits 6 -i .\output                        # Count size 6 lists up to symmetry
+//!   funny.exe --verify 6 -i .\output                        # Re-check every size 6 list
+//!   funny.exe --final-report 6 -i .\output                  # Consolidated report for size 6
+//!   funny.exe --random-walk 1000000 --seed 42 -o .\walks     # Monte-Carlo exploration
+//!   funny.exe --filter-target 20 16 -i .\15_to_16           # Keep lists able to reach 20
+//!   funny.exe --stats 6 -i .\output                         # Card-composition statistics
+//!   funny.exe --extract 6 12 4095 -i .\output                # Print one stored list
+//!   funny.exe --sample 6 10000 --seed 1 -i .\output         # Uniform random sample
+//!   funny.exe --split 7 10000000 -i .\output                # Split oversized files
+//!   funny.exe --migrate -i .\old_runs                       # Convert legacy files
+//!   funny.exe --convert 6 --to parquet -i .\output          # Export to Parquet
+//!   funny.exe --benchmark -o .\bench                       # Standard benchmark (JSON)
+//!   funny.exe --estimate 15 -i .\14                         # Predict size 15
+//!   funny.exe --prune 7 -i .\07 -o .\08                     # Delete consumed inputs
+//!   funny.exe --repair 14 -i .\14                           # Reconcile state and disk
+//!   funny.exe --diff 14 -i .\backup -o .\14                 # Compare two states
+//!   funny.exe --archive 7 -i .\07 -o .\cold                 # Package a size as .tar.zst
+//!   funny.exe --unarchive .\cold\nsl_07_archive.tar.zst -o .\07  # Restore an archive
+//!   funny.exe --watch 14 -i .\14 -o .\15                    # Process batches as they arrive
+//!   funny.exe --inspect .\14\nsl_13_batch_000042_to_14_batch_000107.rkyv  # Examine one file
+//!   funny.exe --top 12 20 -i .\12                           # Lists with the most headroom
+//!   funny.exe --normalize-filenames 14 -i .\14              # Rename to 6-digit batch names
+//!   funny.exe --validate-chain 12 16 -i T:\data\funny_set_exploration  # Cross-size audit
+//!   funny.exe --export-cards 6 0 -i .\06                    # Lists as readable SET cards
+//!   funny.exe --gc 14 -i .\14 --force                      # Delete stale temp/backup files
+//!   funny.exe --reencode 12 -i .\12 --encoding packed      # Shrink a finished size
+//!   funny.exe --checksum 12 -i .\12                        # Detect corrupted files
+//!   funny.exe --verify-manifest 12 -i .\12                 # Check a copied size
+//!   funny.exe --migrate-format 12 -i .\12                  # Upgrade old files
+//!   funny.exe --upgrade-state 12 -i .\12                   # Upgrade an old state
+//!   funny.exe --overview -i .\cascade                       # Progress of every size
+//!   funny.exe --restore-state 14 20261018_0930 -i .\13_to_14  # Roll the state back to a backup
+//!   funny.exe --merge-state 14 -i .\hostA\14 -o .\14       # Merge another machine's state
+//!   funny.exe --rebalance 15 --compact-size 10000000 -i .\15 # Even out the compacted files
+//!   funny.exe --scan 14 -i .\14                             # Quarantine corrupted files
+//!   funny.exe --count-all -i .\cascade                      # Grand total of every size
+//!   funny.exe --history-diff 15 -i .\15                     # History against current state
+//!   funny.exe                                               # Default mode (sizes 4-20)
+//!
+//! Arguments:
+//!   --size, -s <SIZE> [BATCH]  Target output size (3-20), optional regular input batch to restart from
+//!                              If omitted, runs default behavior (creates seeds + sizes 4-20)
+//!   --unitary <SIZE> <BATCH>   Process only one specific input batch (unitary processing)
+//!   --cascade <INPUT_SIZE>     Process all sizes from INPUT_SIZE (3-19) to size 20 (--flat: one directory)
+//!                              Automatically detects last processed batch per size
+//!   --save-history <SIZE>      Merge current state with historical records for preservation
+//!                              Automatically called after --size, --unitary, --cascade
+//!   --count <SIZE>             Count existing files and create summary report
+//!   --check <SIZE>             Check repository integrity (missing batches/files)
+//!   --force                    Force regeneration of count file (with size batch/unitary)
+//!   --cache-batches <N>        Keep the last N decoded input batches in memory (default 0)
+//!   --memory-limit <GB>        Scale lists per file and the batch cache to GB of RAM
+//!   --lists-per-file <N>       Lists per output file (also compaction batches), recorded in state
+//!   --compact-size <N>         Lists per compacted file (default: lists per output file)
+//!   --compact-min-files <K>    Compact only while K non-compacted files remain (default 2)
+//!   --sources-sidecar          Write the source batches of each compacted file to a .sources.json
+//!   --background-compact       With --size 13+: compact the outputs while processing
+//!   --dedupe                   With --compact: drop lists whose card set was already compacted
+//!   --compact-min-free-gb <G>  Keep G GB free while compacting (--compact-low-space abort|pause)
+//!   --delete-early             With --compact -o --delete-originals: delete each source once verified
+//!   --file-size-gb <G>         Lists per output file targeting files of about G GB
+//!   --strong-prune             Drop lists whose remaining cards cannot reach 12 cards
+//!   --isomorph-cache           Drop children isomorphic to another child of the same batch
+//!   --validate-counts [JSON]   Fail if a completed size total differs from its reference count
+//!   --shard <K/M>              Only expand input lists with max_card % M == K (size/unitary)
+//!   --encoding <E>             Encoding of the list files written: plain, delta or packed
+//!   --compress[=LEVEL]         zstd-compress the list files written (default level 3)
+//!   --also-parquet             Also write each output file to the Parquet dataset of its size
+//!   --storage <B>              Where list files live: files (default) or sqlite databases
+//!   --state-backend <B>        Where the state of a size lives: rkyv (default) or sqlite
+//!   --flush-every <N>          Flush the state every N output files (default 1, journaled)
+//!   --keep-backups <N>         Backups kept of each state file (default 1: .rkyv.old)
+//!   --fix-state-on-load        Fix the inconsistencies found in loaded states
+//!   --placement <P>            Root of new files of multi-volume dirs: round-robin, free-space
+//!   --engine <E>               Expansion engine of the size steps and --benchmark: current
+//!   --io-retries <N>           Attempts of each file operation on transient errors (default 3)
+//!   --io-backoff-ms <MS>       Delay before the first retry, doubled at each retry (default 200)
+//!   --input-path, -i           Optional: Directory for input files (defaults to current)
+//!                              For cascade mode: root directory with subdirectories
+//!   --output-path, -o          Optional: Directory for output files (defaults to input)
+//!                              -i / -o accept several roots separated by ';' (multi-volume)
+//!
+//! Implementation:
+//!   - Hybrid approach: NoSetList (stack) for fast computation, NoSetListSerialized (heap) for compact I/O
+//!   - Creates .rkyv files with size_32 encoding (~2GB per 20M batch)
+//!   - 4-5× faster than heap-only v0.2.2 while maintaining compact file sizes
 
 use clap::Parser;
-use separator::Separatable;
-use crate::utils::*;
+use funny::utils::*;
+use funny::modes::*;
 
 /// CLI arguments structure
 #[derive(Parser, Debug)]
@@ -68,6 +130,9 @@ use crate::utils::*;
         "   - Purpose: Build a specific output size.\n",
         "   - Single arg (--size 5): Process size 5 from input batch 0.\n",
         "   - Two args (--size 5 2): Resume size 5 from input batch 2.\n",
+        "   - Inputs: compacted files first, then regular files not\n",
+        "     yet merged; batches already recorded in the output\n",
+        "     state are skipped (nothing processed twice).\n",
         "   - Input path (-i): dir to read input files (defaults to\n",
         "     current dir).\n",
         "   - Output path (-o): dir to write outputs (defaults to\n",
@@ -75,6 +140,9 @@ use crate::utils::*;
         "   - --force: regenerates count file when restarting from\n",
         "     a batch.\n",
         "   - --keep_state: preserves partial/processed state files.\n",
+        "   - --background-compact (sizes 13+): a worker thread\n",
+        "     compacts the outputs already written while processing\n",
+        "     goes on, instead of all of them afterwards.\n",
         "   - Example: --size 5 -i ./in -o ./out\n",
         "   - Example: --size 5 2 -i ./in -o ./out --force\n\n",
         "2) Unitary mode (`--unitary <SIZE> <BATCH>`)\n",
@@ -95,25 +163,95 @@ use crate::utils::*;
         "     reporting.\n",
         "   - --keep_state: affects whether intermediary files are\n",
         "     preserved.\n",
-        "   - Example: --count 6 -i ./out --force\n\n",
+        "   - --fast: counts read from the footers, or estimated from\n",
+        "     the file sizes (bytes per list calibrated on a few files\n",
+        "     counted in full); estimated entries are marked in the\n",
+        "     state (estimated_counts) until a --count without --fast\n",
+        "     counts them.\n",
+        "   - Example: --count 6 -i ./out --force\n",
+        "   - --follow [MINUTES]: count again every MINUTES (default\n",
+        "     10), registering only the new files, with the rolling\n",
+        "     lists/hour and the projected completion (input batches\n",
+        "     of SIZE-1 consumed); stops on funny_watch.stop in -i.\n",
+        "   - Example: --count 15 -i ./15 --fast\n",
+        "   - Example: --count 16 -i ./16 --follow 10\n\n",
         "4) Check mode (`--check <SIZE>`)\n",
         "   - Purpose: Verify repository integrity for an output\n",
         "     size.\n",
-        "   - Input path (-i): not used.\n",
+        "   - Input path (-i): with --against-input only.\n",
         "   - Output path (-o): dir containing files to check\n",
         "     (defaults to current dir).\n",
         "   - --force/--keep_state: not applicable.\n",
-        "   - Example: --check 8 -o ./out\n\n",
-        "5) Compact mode (`--compact <SIZE> [MAX_BATCH]`)\\n",
-        "   - Purpose: Consolidate many small output files into\\n",
-        "     larger batches.\\n",
-        "   - Optional MAX_BATCH: stop compaction after processing\\n",
-        "     files up to this output batch number.\\n",
-        "   - Input path (-i): dir containing files to compact.\\n",
-        "   - Output path (-o): dir to write compacted files\\n",
-        "     (defaults to input).\\n",
-        "   - Example: --compact 12 -i ./out\\n",
-        "   - Example: --compact 12 5000 -i ./out (stop at batch 5000)\\n\\n",
+        "   - --deep [WORKERS]: also re-count the lists of every file\n",
+        "     (WORKERS threads, default one per core) and report the\n",
+        "     state entries whose count differs from the file.\n",
+        "   - --fix: first remove the state entries of missing files,\n",
+        "     register (and count) untracked files, and renumber the\n",
+        "     files to close spurious gaps (batches no file ever had)\n",
+        "     when safe; every fix is appended to nsl_{size}_repair.log.\n",
+        "   - --against-input -i DIR: also load the size SIZE-1 files\n",
+        "     of DIR and report its batches with no output of size\n",
+        "     SIZE (unconsumed), and outputs from unknown batches.\n",
+        "   - Target batches claimed by several files (duplicates,\n",
+        "     or the regular and _compacted variants of one file) are\n",
+        "     reported as errors, with the file to keep.\n",
+        "   - Files the state does not track are classified (orphan\n",
+        "     artifact, list file missing from the state, legacy\n",
+        "     format, unknown), with the space --gc can reclaim.\n",
+        "   - Findings saved as nsl_{size}_check_report.json (see\n",
+        "     Exit codes below).\n",
+        "   - Example: --check 8 -o ./out\n",
+        "   - Example: --check 14 -o ./14 --deep 8\n",
+        "   - Example: --check 14 -o ./14 --fix\n",
+        "   - Example: --check 14 -o ./14 --against-input -i ./13\n\n",
+        "5) Compact mode (`--compact <SIZE> [MAX_BATCH]`)\n",
+        "   - Purpose: Consolidate many small output files into\n",
+        "     larger batches.\n",
+        "   - Optional MAX_BATCH: stop compaction after processing\n",
+        "     files up to this output batch number.\n",
+        "   - Input path (-i): dir containing files to compact.\n",
+        "   - Output path (-o): dir to write compacted files\n",
+        "     (defaults to input: in-place compaction).\n",
+        "   - With another -o: out-of-place compaction. The sources\n",
+        "     are only read; the written files are re-read and their\n",
+        "     count and checksum checked against the sources (on a\n",
+        "     mismatch they are deleted). --delete-originals then\n",
+        "     deletes the sources and updates both states.\n",
+        "   - --compact-size N: lists per compacted file (default: the\n",
+        "     lists per output file), e.g. 50M on a big-RAM machine.\n",
+        "   - --compact-min-files K: compact only while at least K\n",
+        "     non-compacted files remain (default 2), to let more\n",
+        "     fragments accumulate. Both also apply to the automatic\n",
+        "     compaction of sizes 13+ (size, default and cascade).\n",
+        "   - The lists each compacted file takes from every source\n",
+        "     batch are recorded in the state (shown by --check);\n",
+        "     --sources-sidecar also writes them next to the file\n",
+        "     (<compacted file>.sources.json).\n",
+        "   - Each in-place compaction is journaled first\n",
+        "     (nsl_XX_compaction.journal): if a run stops halfway,\n",
+        "     the next --compact finishes it or rolls it back.\n",
+        "   - --dedupe (in place): lists whose card set was already\n",
+        "     compacted by the run are dropped; the number dropped\n",
+        "     is reported and the state counts adjusted (one 16-byte\n",
+        "     key per list compacted is kept in memory).\n",
+        "   - --compact-min-free-gb G: before each compacted file the\n",
+        "     free space is checked (room for the new files plus G GB).\n",
+        "     Below it, --compact-low-space abort (default) stops\n",
+        "     cleanly between two files, pause waits (re-checking\n",
+        "     every minute) until space is freed. Also applies to the\n",
+        "     automatic compaction of sizes 13+.\n",
+        "   - --delete-early (with -o and --delete-originals): each\n",
+        "     compacted file is verified as soon as it is written and\n",
+        "     the sources whose lists are all in verified files are\n",
+        "     deleted then, instead of once everything is verified.\n",
+        "   - Example: --compact 12 -i ./out\n",
+        "   - Example: --compact 12 5000 -i ./out (stop at batch 5000)\n",
+        "   - --dry-run (in place): prints the plan (origins grouped\n",
+        "     into each compacted file, deletions and rewrites), the\n",
+        "     number of files created and the files/lists per\n",
+        "     magnitude of list count before and after.\n",
+        "   - Example: --compact 12 -i ./out --dry-run\n",
+        "   - Example: --compact 12 -i ./nas/12 -o ./local/12 --delete-originals\n\n",
         "6) Legacy-count mode (`--legacy-count <SIZE>` )\n",
         "   - Purpose: Read existing global/intermediary counts and\n",
         "     emit nsl_{size}_global_info.json/.txt without\n",
@@ -123,28 +261,519 @@ use crate::utils::*;
         "7) Create-JSON mode (`--create-json <SIZE>`)\n",
         "   - Purpose: Export human-readable JSON and TXT files from\n",
         "     the rkyv state file (write-only, for inspection).\n",
+        "   - --format csv writes nsl_{size}_global_info.csv instead:\n",
+        "     one row per file entry, all fields (compacted flag, size,\n",
+        "     timestamps, provenance), for spreadsheets and pandas.\n",
         "   - Input path (-i): directory with rkyv state file.\n",
         "   - Output path: not used.\n",
-        "   - Example: --create-json 10 -i ./09_to_10\n\n",
+        "   - Example: --create-json 10 -i ./09_to_10\n",
+        "   - Example: --create-json 10 --format csv -i ./09_to_10\n\n",
         "8) Cascade mode (`--cascade <INPUT_SIZE>`)\n",
         "   - Purpose: Process all output sizes starting from a given\n",
-        "     input size (12-19) up to size 20.\n",
+        "     input size (3-19) up to size 20 (from 3: the seed lists\n",
+        "     are created in 2_to_3, then sizes 4 to 20).\n",
         "   - Automatically detects last processed batch per size and\n",
         "     continues from there.\n",
         "   - Input path (-i): root directory containing subdirectories\n",
-        "     (11_to_12, 12_to_13c, 13c_to_14c, etc.).\n",
+        "     (2_to_3, 3_to_4, ..., 11_to_12, 12_to_13c, 13c_to_14c,\n",
+        "     etc.), created as needed.\n",
+        "   - --flat: every size in the root directory itself, no\n",
+        "     subdirectories.\n",
+        "   - Several drives: --cascade-roots D:\\f,E:\\f searches the\n",
+        "     size directories on these roots after -i (a missing one\n",
+        "     is created on the first root with room, see\n",
+        "     --cascade-min-free-gb), and --cascade-root 17=F:\\f puts\n",
+        "     the size 17 directory under F:\\f (repeatable); -i keeps\n",
+        "     the journal and the status.\n",
+        "   - --stop-at TO: last output size processed (default 20).\n",
+        "   - --skip-sizes 15,17: output sizes left out (handled\n",
+        "     elsewhere); the next sizes still read their directory.\n",
+        "   - --cascade-min-free-gb G: free space kept in the output\n",
+        "     directory, checked before each size and each input batch;\n",
+        "     below it (--cascade-low-space): stop cleanly with the\n",
+        "     command to resume (default), pause until space is freed,\n",
+        "     or prune the consumed inputs of the earlier sizes first.\n",
+        "   - --cascade-retries N / --cascade-retry-delay SECS: a failed\n",
+        "     size is tried again up to N times (default 0), resuming\n",
+        "     from its state, after SECS s (default 60), then twice as\n",
+        "     long between the next tries.\n",
+        "   - --cascade-skip-failed: a size still failing is left out\n",
+        "     and the cascade goes on with the next sizes (their inputs\n",
+        "     may already be there); the run then exits with code 1.\n",
+        "   - --force and --keep_state apply to every size step;\n",
+        "     --cascade-step SIZE:FLAGS overrides them for output size\n",
+        "     SIZE, FLAGS among force, no-force, keep-state and\n",
+        "     no-keep-state (comma-separated; repeat for other sizes).\n",
+        "   - Example: --cascade 14 --force --cascade-step 16:no-force -i X:\\funny\n",
+        "   - Journal: ROOT/cascade_journal.jsonl records each input\n",
+        "     batch processed and each size completed; a new run skips\n",
+        "     the completed sizes (unless an earlier size had work) and\n",
+        "     resumes the others after their last journaled batch\n",
+        "     (sizes not in it: from the state of their outputs).\n",
+        "   - Status: ROOT/cascade_status.json (current size and input\n",
+        "     batch, lists produced, timestamps, ETA), updated after\n",
+        "     each input batch, for dashboards and --overview.\n",
         "   - Output path: not used (determined automatically).\n",
         "   - Example: --cascade 12 -i X:\\funny\n",
+        "   - Example: --cascade 14 --stop-at 17 --skip-sizes 16 -i X:\\funny\n",
         "   - Directory structure expected:\n",
+        "     2_to_3/           (seed lists, input for size 4)\n",
+        "     ...\n",
         "     11_to_12/         (input for size 13)\n",
         "     12_to_13c/        (output size 13, input for 14)\n",
         "     13c_to_14c/       (output size 14, input for 15)\n",
         "     ... and so on\n\n",
+        "9) Export-lists mode (`--export-lists <FILENAME>`)\n",
+        "   - Purpose: Write the lists of rkyv files as readable\n",
+        "     .txt and .json files next to them.\n",
+        "   - FILENAME may contain wildcards (* and ?).\n",
+        "   - Input path (-i): directory with the rkyv files.\n",
+        "   - Output path: not used.\n",
+        "   - Example: --export-lists \"nsl_*_to_05_*.rkyv\" -i ./out\n\n",
+        "10) Orbits mode (`--orbits <SIZE>`)\n",
+        "   - Purpose: Count stored lists up to symmetry (affine maps\n",
+        "     of the 81 cards, which preserve sets).\n",
+        "   - Reports the number of orbits and the orbit-size\n",
+        "     distribution, saved as nsl_{size}_orbits.json.\n",
+        "   - Input path (-i): directory with the size files.\n",
+        "   - Output path: not used.\n",
+        "   - Example: --orbits 6 -i ./05_to_06\n\n",
+        "11) Verify mode (`--verify <SIZE>`)\n",
+        "   - Purpose: Deep check of every stored list: no set among\n",
+        "     its cards, remaining cards exactly the compatible\n",
+        "     cards above max_card.\n",
+        "   - Violations are listed with file and offset in\n",
+        "     nsl_{size}_verify_report.json; exits with code 3\n",
+        "     if any list is invalid.\n",
+        "   - Input path (-i): directory with the size files.\n",
+        "   - Output path: not used.\n",
+        "   - Example: --verify 6 -i ./05_to_06\n\n",
+        "12) Final-report mode (`--final-report <SIZE>`)\n",
+        "   - Purpose: Write nsl_{size}_final_report.json, the\n",
+        "     consolidated record of a finalized size: totals,\n",
+        "     history, verification results (--verify), timing,\n",
+        "     compaction, discoveries, orbits (--orbits) and the\n",
+        "     comparison with the published reference counts.\n",
+        "   - Input path (-i): directory with the size files.\n",
+        "   - Output path: not used.\n",
+        "   - Example: --final-report 6 -i ./05_to_06\n\n",
+        "13) Random-walk mode (`--random-walk <ITERATIONS> [--seed N]`)\n",
+        "   - Purpose: Monte-Carlo exploration: grow ITERATIONS random\n",
+        "     complete no-set-lists from random seeds.\n",
+        "   - Reports the distribution of final sizes; lists of size\n",
+        "     19/20 are added to nsl_{size}_discoveries.json.\n",
+        "   - --seed: RNG seed for reproducible runs (default: clock).\n",
+        "   - Output path (-o, else -i): where reports are written.\n",
+        "   - Example: --random-walk 1000000 --seed 42 -o ./walks\n\n",
+        "14) Filter-target mode (`--filter-target <TARGET> <SIZE>`)\n",
+        "   - Purpose: Rewrite the size files keeping only the lists\n",
+        "     that can still reach TARGET cards (e.g. 20), and update\n",
+        "     the state counts, so the following sizes shrink.\n",
+        "   - Counts of this size and the sizes computed from it no\n",
+        "     longer match the reference counts.\n",
+        "   - Input path (-i): directory with the size files.\n",
+        "   - Output path: not used.\n",
+        "   - Example: --filter-target 20 16 -i ./15_to_16\n\n",
+        "15) Stats mode (`--stats <SIZE>`)\n",
+        "   - Purpose: Card-composition statistics of the stored lists:\n",
+        "     per-card frequencies, max_card histogram and\n",
+        "     remaining-length histogram.\n",
+        "   - Saved as nsl_{size}_stats.json and nsl_{size}_stats.txt.\n",
+        "   - Input path (-i): directory with the size files.\n",
+        "   - Output path: not used.\n",
+        "   - Example: --stats 6 -i ./05_to_06\n\n",
+        "16) Extract mode (`--extract <SIZE> <FILE_BATCH> <INDEX>`)\n",
+        "   - Purpose: Print one stored list for debugging: its cards\n",
+        "     and remaining cards as indices, base-3 digits and\n",
+        "     number/color/shape/shading.\n",
+        "   - FILE_BATCH: target batch of the file (compacted file\n",
+        "     preferred); INDEX: position of the list in the file.\n",
+        "   - Input path (-i): directory with the size files.\n",
+        "   - Example: --extract 6 12 4095 -i ./05_to_06\n\n",
+        "17) Sample mode (`--sample <SIZE> <N> [--seed S]`)\n",
+        "   - Purpose: Export N lists of a size drawn uniformly among\n",
+        "     all stored lists (per-file counts from the state).\n",
+        "   - Saved as nsl_{size}_sample_{N}.rkyv and .json.\n",
+        "   - --seed: RNG seed for reproducible samples (default: clock).\n",
+        "   - Input path (-i): directory with the size files.\n",
+        "   - Output path (-o): where the sample is written (default: -i).\n",
+        "   - Example: --sample 6 10000 --seed 1 -i ./05_to_06\n\n",
+        "18) Split mode (`--split <SIZE> <MAX_LISTS>`)\n",
+        "   - Purpose: Rewrite every size file holding more than MAX_LISTS\n",
+        "     lists into parts of at most MAX_LISTS lists.\n",
+        "   - The first part keeps its name; the others get new target\n",
+        "     batches after the highest existing one.\n",
+        "   - Global state updated; split before computing the next size.\n",
+        "   - Input path (-i): directory with the size files.\n",
+        "   - Example: --split 7 10000000 -i ./06_to_07\n\n",
+        "19) Migrate mode (`--migrate [--delete-originals]`)\n",
+        "   - Purpose: Convert files of older versions (nlist_XX_batch_YYY.bin,\n",
+        "     nlist_v31_*.rkyv, nlist_*.rkyv) to the nsl_* rkyv format.\n",
+        "   - Batch YYY of size XX becomes nsl_{XX-1}_batch_000000_to_XX_batch_YYY.\n",
+        "   - Converted files are registered in the global state.\n",
+        "   - --delete-originals: remove each legacy file once converted.\n",
+        "   - Input path (-i): directory with the legacy files.\n",
+        "   - Example: --migrate -i ./old_runs --delete-originals\n\n",
+        "20) Convert mode (`--convert <SIZE> --to parquet|dataset|csv|jsonl`)\n",
+        "   - Purpose: Export every list of a size to a standard format,\n",
+        "     one row per list: batch, n, max_card, cards, remaining.\n",
+        "   - Files are streamed chunk by chunk (bounded memory).\n",
+        "   - Saved as nsl_{size}_lists.{csv,jsonl,parquet}.\n",
+        "   - dataset: one Parquet file per list file in nsl_{size}_dataset/\n",
+        "     (size, max_card, cards and remaining 81-bit masks,\n",
+        "     source_batch), also written during processing by\n",
+        "     --also-parquet.\n",
+        "   - Parquet and dataset need a build with --features parquet.\n",
+        "   - Input path (-i): directory with the size files.\n",
+        "   - Output path (-o): where the export is written (default: -i).\n",
+        "   - Example: --convert 6 --to csv -i ./05_to_06 -o ./exports\n\n",
+        "21) Benchmark mode (`--benchmark [MAX_SIZE]`)\n",
+        "   - Purpose: Time a fixed workload to compare machines and builds:\n",
+        "     seed lists expanded up to MAX_SIZE (default 6, max 8).\n",
+        "   - Per size: computation / file I/O / conversion times and\n",
+        "     throughputs (lists/s, MB/s).\n",
+        "   - JSON result printed on stdout, saved as benchmark_result.json.\n",
+        "   - Output path (-o): scratch directory parent (removed after).\n",
+        "   - Example: --benchmark -o ./bench > result.json\n\n",
+        "22) Estimate mode (`--estimate <SIZE> [BATCHES]`)\n",
+        "   - Purpose: Predict what computing SIZE will take: samples\n",
+        "     BATCHES input batches (default 3), expands every k-th list.\n",
+        "   - Prints output lists, files, disk space and wall-clock time.\n",
+        "   - Honors --strong-prune and --encoding.\n",
+        "   - Input path (-i): directory with the SIZE-1 files.\n",
+        "   - Example: --estimate 15 5 -i ./14\n\n",
+        "23) Prune mode (`--prune <SIZE> [--archive-dir DIR]`)\n",
+        "   - Purpose: Free the space of size SIZE files already consumed\n",
+        "     by size SIZE+1 (source batches of its state and history).\n",
+        "   - Every recorded SIZE+1 output must exist with its count,\n",
+        "     otherwise nothing is pruned.\n",
+        "   - --archive-dir: move the files there instead of deleting them.\n",
+        "   - Pruned files listed in nsl_{size}_pruned.json.\n",
+        "   - Input path (-i): directory with the SIZE files.\n",
+        "   - Output path (-o): directory with the SIZE+1 files (default: -i).\n",
+        "   - Example: --prune 7 -i ./06_to_07 -o ./07_to_08\n\n",
+        "24) Repair mode (`--repair <SIZE>`)\n",
+        "   - Purpose: Reconcile the global state with the files on disk:\n",
+        "     stale entries removed, unknown files counted and registered,\n",
+        "     files changed since recorded recounted.\n",
+        "   - Report written as nsl_{size}_repair_report.txt.\n",
+        "   - Input path (-i): directory with the size files and state.\n",
+        "   - Example: --repair 14 -i ./14\n\n",
+        "25) Diff mode (`--diff <SIZE> -i DIR_A -o DIR_B`)\n",
+        "   - Purpose: Compare the global states of a size in two\n",
+        "     directories (e.g. a backup and the live state).\n",
+        "   - Added (+), removed (-) and modified (~) entries, count deltas\n",
+        "     and total list difference; JSON printed on stdout.\n",
+        "   - Input path (-i): directory of state A.\n",
+        "   - Output path (-o): directory of state B (nothing is written).\n",
+        "   - Example: --diff 14 -i ./backup/14 -o ./14 > diff.json\n\n",
+        "26) Archive mode (`--archive <SIZE>` / `--unarchive <ARCHIVE>`)\n",
+        "   - Purpose: Package a completed size as one file for cold storage,\n",
+        "     and restore it.\n",
+        "   - --archive bundles the size files, state and history of -i into\n",
+        "     -o/nsl_{size}_archive.tar.zst (default -o: input directory), with\n",
+        "     an embedded manifest of list counts and SHA-256 hashes.\n",
+        "   - --unarchive restores the files into -o (default: current directory)\n",
+        "     and checks every hash; existing files are never overwritten.\n",
+        "   - Example: --archive 7 -i ./07 -o ./cold\n",
+        "   - Example: --unarchive ./cold/nsl_07_archive.tar.zst -o ./07\n\n",
+        "27) Watch mode (`--watch <SIZE> [--watch-interval SECS]`)\n",
+        "   - Purpose: Process input batches copied in incrementally.\n",
+        "   - SIZE is the input size (as for --unitary); the input directory\n",
+        "     is polled every SECS seconds (default 60).\n",
+        "   - A batch is processed (unitary style) once its file is complete:\n",
+        "     same size and mtime on two polls, readable header.\n",
+        "   - Batches already in the output state or history are skipped;\n",
+        "     state and history are updated after every batch.\n",
+        "   - Stop: create funny_watch.stop in the output directory.\n",
+        "   - Honors --strong-prune, --isomorph-cache and --shard.\n",
+        "   - Example: --watch 14 -i ./14 -o ./15 --watch-interval 120\n\n",
+        "28) Inspect mode (`--inspect <FILE>`)\n",
+        "   - Purpose: Examine one suspicious list file.\n",
+        "   - Validates the archive (any encoding) and reports the list\n",
+        "     count, list-size and remaining-card histograms, min/max\n",
+        "     max_card, invalid lists (--verify checks) and duplicates.\n",
+        "   - Prints the first and last lists as text.\n",
+        "   - FILE is relative to -i when given (absolute paths work as is).\n",
+        "   - Example: --inspect nsl_13_batch_000042_to_14_batch_000107.rkyv -i ./14\n\n",
+        "29) Top mode (`--top <SIZE> [N] [--rank-by remaining|bound]`)\n",
+        "   - Purpose: Leaderboard of the lists with the most headroom.\n",
+        "   - Ranks the stored lists of SIZE by remaining cards (default) or\n",
+        "     by reachable bound (n + extension upper bound); keeps N (10).\n",
+        "   - Printed with decoded cards, saved as nsl_{size}_top.json in -i.\n",
+        "   - Example: --top 12 20 --rank-by bound -i ./12\n\n",
+        "30) Normalize-filenames mode (`--normalize-filenames <SIZE>`)\n",
+        "   - Purpose: Fix directories mixing 5-digit and 6-digit batch names.\n",
+        "   - Renames every nsl_* file of SIZE to the canonical 6-digit name\n",
+        "     (_compacted and shard tags kept) and updates its state entry,\n",
+        "     flushing the state after each rename.\n",
+        "   - Also rewrites the names in the intermediate count files.\n",
+        "   - Never overwrites: a name already taken is reported as a conflict.\n",
+        "   - Legacy nlist_* files are only reported (use --migrate).\n",
+        "   - Example: --normalize-filenames 14 -i ./14\n\n",
+        "31) Validate-chain mode (`--validate-chain <FROM> <TO>`)\n",
+        "   - Purpose: End-to-end audit of a cascade directory tree (-i ROOT).\n",
+        "   - For each pair of sizes (N, N+1) from FROM to TO, in the --cascade\n",
+        "     directories: every size N batch must be a source batch of the\n",
+        "     size N+1 state or history, with no gap in the batch numbering.\n",
+        "   - Reports unconsumed inputs and outputs with unknown sources.\n",
+        "   - Read-only, except the findings saved as\n",
+        "     nsl_{from}_to_{to}_chain_report.json in ROOT; gaps are\n",
+        "     warnings (exit code 2), the rest errors (exit code 3).\n",
+        "   - Example: --validate-chain 12 16 -i T:\\data\\funny_set_exploration\n\n",
+        "32) Export-cards mode (`--export-cards <SIZE> <FILE_BATCH>`)\n",
+        "   - Purpose: Read the lists of one file as actual SET cards.\n",
+        "   - Writes <file>_cards.txt next to the size SIZE file of batch\n",
+        "     FILE_BATCH: one block per list, each card as its index and its\n",
+        "     attributes (e.g. \"2 green striped ovals\").\n",
+        "   - --extract uses the same card decoding.\n",
+        "   - Example: --export-cards 6 0 -i ./06\n\n",
+        "33) GC mode (`--gc [SIZE] [--force]`)\n",
+        "   - Purpose: Clean the artifacts left behind by crashes in -i.\n",
+        "   - Finds .tmp / .tmp.<pid> files, state backups (.rkyv.old,\n",
+        "     .json_old) and intermediate count files already recorded in\n",
+        "     the state or history; reports them with their total size.\n",
+        "   - Keeps anything that may be the only copy (missing target,\n",
+        "     state that does not load, temp files younger than an hour)\n",
+        "     and the newest --keep-backups N backups of each state.\n",
+        "   - Only lists by default; --force deletes the removable ones.\n",
+        "   - Example: --gc 14 -i ./14 --force\n\n",
+        "34) Reencode mode (`--reencode <SIZE> --encoding <E> [--compress]`)\n",
+        "   - Purpose: Convert the files of a size to another encoding.\n",
+        "   - Rewrites every size SIZE file of -i in the --encoding given\n",
+        "     (plain, delta or packed), zstd-compressed with --compress.\n",
+        "   - Files already in that encoding are left untouched; the\n",
+        "     others are written as .tmp, checked, then renamed.\n",
+        "   - The state records the new file sizes and mtimes.\n",
+        "   - Example: --reencode 12 -i ./12 --encoding packed\n\n",
+        "35) Checksum mode (`--checksum <SIZE>`)\n",
+        "   - Purpose: Detect silent corruption of the files of a size.\n",
+        "   - Every list file written ends with a footer: list count\n",
+        "     and CRC32 of the file bytes, also checked on every read.\n",
+        "   - Checks all the footers without decoding any list, and\n",
+        "     compares the list counts with the global state.\n",
+        "   - Fails if a file is corrupted; files written by older\n",
+        "     versions are reported as unchecked (--reencode adds\n",
+        "     their footer).\n",
+        "   - Example: --checksum 12 -i ./12\n\n",
+        "36) Verify-manifest mode (`--verify-manifest <SIZE>`)\n",
+        "   - Purpose: Check a copied size against its manifest.\n",
+        "   - Every size and unitary run writes nsl_{size}_manifest.json\n",
+        "     (filename, bytes, SHA-256, list count of each list file;\n",
+        "     unchanged files are not hashed again).\n",
+        "   - Re-hashes every file listed and reports mismatches, missing\n",
+        "     files and list files not in the manifest.\n",
+        "   - Fails on a mismatch or a missing file.\n",
+        "   - Example: --verify-manifest 12 -i /mnt/copy/12\n\n",
+        "37) Migrate-format mode (`--migrate-format <SIZE>`)\n",
+        "   - Purpose: Upgrade the files of a size to the current format.\n",
+        "   - List files start with a versioned header (NSLPLAN1,\n",
+        "     NSLDELT1, NSLPACK1 or NSLFRAM2) and end with a footer;\n",
+        "     older plain files are a bare rkyv archive, still read.\n",
+        "   - Rewrites those older files in place (.tmp, checked, then\n",
+        "     renamed), keeping their encoding and compression.\n",
+        "   - Files of an unknown (newer) version are refused.\n",
+        "   - Example: --migrate-format 12 -i ./12\n\n",
+        "38) Upgrade-state mode (`--upgrade-state <SIZE>`)\n",
+        "   - Purpose: Rewrite the global state in the current schema.\n",
+        "   - The rkyv state starts with NSLSTAT<version>; states of\n",
+        "     older schema versions are migrated in memory on load.\n",
+        "   - Rewrites the older state files (current and history,\n",
+        "     rkyv and JSON) in place, keeping the original as .old.\n",
+        "   - States of an unknown (newer) schema version are refused.\n",
+        "   - Example: --upgrade-state 12 -i ./12\n\n",
+        "39) Overview mode (`--overview`)\n",
+        "   - Purpose: Big picture of an exploration, one row per size.\n",
+        "   - -i is the root directory: cascade directories (as with\n",
+        "     --cascade), ROOT/{N-1}_to_{N}, or ROOT itself.\n",
+        "   - Per size: files, lists, disk usage, last activity, and\n",
+        "     percent of the previous size's batches consumed.\n",
+        "   - The cascade of ROOT, from its cascade_status.json.\n",
+        "   - Read-only.\n",
+        "   - Example: --overview -i ./cascade\n\n",
+        "40) Restore-state mode (`--restore-state <SIZE> [TIMESTAMP]`)\n",
+        "   - Purpose: Roll the state of a size back to a backup.\n",
+        "   - Lists the backups of nsl_XX_global_info.rkyv (.rkyv.old\n",
+        "     and .rkyv.<timestamp>.old, see --keep-backups) and puts\n",
+        "     back the one matching TIMESTAMP (%Y%m%d_%H%M%S, or any\n",
+        "     unambiguous prefix), by default the most recent one.\n",
+        "   - The replaced state becomes the last backup (undo by\n",
+        "     restoring again); the journal is deleted.\n",
+        "   - rkyv state backend only.\n",
+        "   - Example: --restore-state 14 20261018_0930 -i ./13_to_14\n\n",
+        "41) Merge-state mode (`--merge-state <SIZE> -i DIR_A -o DIR_B`)\n",
+        "   - Purpose: Merge the state of a size produced on another\n",
+        "     machine (A) into the local one (B).\n",
+        "   - Target batches of A only are added; batches recorded\n",
+        "     differently by both states are conflicts, settled by\n",
+        "     --merge-policy: fail (default, nothing written),\n",
+        "     prefer-newer (latest file modification) or\n",
+        "     prefer-larger-count (most lists); B is kept on ties.\n",
+        "   - Consumed inputs of A are added; report (added, identical,\n",
+        "     conflicts, files of A missing from B) as JSON on stdout.\n",
+        "   - Example: --merge-state 14 -i ./hostA/14 -o ./14 --merge-policy prefer-newer\n\n",
+        "42) Rebalance mode (`--rebalance <SIZE>`)\n",
+        "   - Purpose: Rewrite the compacted files of a size left\n",
+        "     uneven by runs with different batch sizes into files\n",
+        "     of exactly --compact-size lists (default: the lists\n",
+        "     per output file).\n",
+        "   - The leading files already at that size are kept; the\n",
+        "     others are streamed, in batch order, into new files\n",
+        "     numbered on from the first one rewritten. A short last\n",
+        "     file is left partial (not compacted) for --compact.\n",
+        "   - New files are staged, journaled (nsl_XX_rebalance.journal),\n",
+        "     then renamed; an interrupted run is finished by the next.\n",
+        "   - Global state and lineage (compacted sources) updated.\n",
+        "   - Input path (-i): directory with the size files.\n",
+        "   - Example: --rebalance 15 --compact-size 10000000 -i ./15\n\n",
+        "43) Scan mode (`--scan <SIZE> [WORKERS]`)\n",
+        "   - Purpose: Find the files that fail partway through\n",
+        "     decoding: every archive of every file validated and\n",
+        "     every list decoded, on WORKERS threads (default: one\n",
+        "     per core).\n",
+        "   - Fields of each list checked (n, cards ascending and\n",
+        "     below 81, max_card, remaining cards above it), and the\n",
+        "     list count against the footer (sets: see --verify).\n",
+        "   - Corrupted files moved to the quarantine/ subdirectory\n",
+        "     and removed from the state; report and findings in\n",
+        "     nsl_{size}_scan_report.json (exit code 3 if any).\n",
+        "   - Input path (-i): directory with the size files.\n",
+        "   - Example: --scan 14 -i ./14 8\n\n",
+        "44) Count-all mode (`--count-all`)\n",
+        "   - Purpose: Grand total of an exploration in one command.\n",
+        "   - -i is the root directory, as with --overview.\n",
+        "   - Runs --count on every size found (--force: states\n",
+        "     rebuilt from the files), then reports per size the\n",
+        "     lists, files, disk usage and growth factor from the\n",
+        "     previous size, and the totals.\n",
+        "   - Saved as nsl_grand_total.json and .txt in ROOT.\n",
+        "   - Example: --count-all -i ./cascade\n\n",
+        "45) History-diff mode (`--history-diff <SIZE>`)\n",
+        "   - Purpose: Audit what was deleted or consumed since the\n",
+        "     history was saved (nsl_{size}_global_info_history).\n",
+        "   - Entries of the history only (with their tombstone\n",
+        "     reason, or unrecorded, and whether still on disk, totalled\n",
+        "     per reason), of the current state only, and entries whose\n",
+        "     list counts differ.\n",
+        "   - Input path (-i): directory with the size files. Read-only.\n",
+        "   - Printed in the log, and as JSON on stdout.\n",
+        "   - Example: --history-diff 15 -i ./15 > history_diff.json\n\n",
         "COMMON FLAGS: -i/--input-path, -o/--output-path, --force,\n",
-        "  --keep_state\n",
+        "  --keep_state, --cache-batches <N>, --memory-limit <GB>,\n",
+        "  --lists-per-file <N>, --file-size-gb <G>, --compact-size <N>,\n",
+        "  --compact-min-files <K>, --sources-sidecar, --strong-prune,\n",
+        "  --isomorph-cache, --validate-counts [JSON], --shard <K/M>,\n",
+        "  --encoding <plain|delta|packed>, --compress[=LEVEL],\n",
+        "  --also-parquet, --storage <files|sqlite>, --dry-run,\n",
+        "  --state-backend <rkyv|sqlite>, --flush-every <N>,\n",
+        "  --keep-backups <N>, --fix-state-on-load,\n",
+        "  --placement <round-robin|free-space>, --io-retries <N>,\n",
+        "  --io-backoff-ms <MS>, --engine <current>\n",
         "  The sections above show how each flag affects specific\n",
         "  modes (e.g. --force regenerates counts for --count,\n",
-        "  --size with batch, and --unitary).\n"
+        "  --size with batch, and --unitary).\n",
+        "  --cache-batches N keeps the last N decoded input batches\n",
+        "  in memory (each costs the RAM of a decoded file).\n",
+        "  --lists-per-file N / --file-size-gb G set the lists per\n",
+        "  output file (and compaction batch) directly, or from a\n",
+        "  target file size (estimated from --encoding); the value\n",
+        "  used is recorded in the state of the size.\n",
+        "  --memory-limit GB sizes the run for GB of RAM: half of it\n",
+        "  buffers output lists (lists per file, also the compaction\n",
+        "  batch size; 16 GB = the default 10M) and --cache-batches\n",
+        "  is lowered to fit a quarter of it.\n",
+        "  --strong-prune (size/unitary/default) also drops lists whose\n",
+        "  remaining cards provably cannot reach 12 cards; stored\n",
+        "  counts are then lower than the reference counts. The size\n",
+        "  and the following ones are marked pruned in their state\n",
+        "  (--count warns, --validate-counts refuses them, and a size\n",
+        "  never mixes pruned and exhaustive files).\n",
+        "  --isomorph-cache (size/unitary/default) drops children\n",
+        "  isomorphic to another child of the same input batch. Two\n",
+        "  isomorphic children may have different remaining cards, so\n",
+        "  descendants of a dropped child are lost: the size and the\n",
+        "  following ones are no longer exhaustive (marked reduced in\n",
+        "  their state; --count warns, --validate-counts refuses them,\n",
+        "  and a size never mixes reduced and exhaustive files). The\n",
+        "  canonical form costs about n^5 per child: slower, not faster.\n",
+        "  --validate-counts (size/default/cascade) compares the total\n",
+        "  of each completed size with the built-in counts of sizes\n",
+        "  3-10, or with a JSON file of expected values ({\"11\": N}),\n",
+        "  and fails on mismatch.\n",
+        "  --shard K/M (size/unitary) only expands the input lists\n",
+        "  with max_card % M == K; outputs are tagged _shard_KKofMM.\n",
+        "  Run every shard into the same output directory: an input\n",
+        "  counts as consumed once all M shards recorded it, and the\n",
+        "  next size reads the shard outputs like any other file.\n",
+        "  --encoding delta writes list files with one byte per card\n",
+        "  (about 3x smaller); --encoding packed stores each list as\n",
+        "  two 81-bit card masks (24 bytes); every encoding is always\n",
+        "  readable (see --reencode to convert existing files).\n",
+        "  --compress[=LEVEL] (size/unitary/compact/cascade/watch/\n",
+        "  reencode/migrate-format)\n",
+        "  zstd-compresses the list files written (level 1-22,\n",
+        "  default 3); compressed files are read transparently and\n",
+        "  flagged as compressed in the state.\n",
+        "  --also-parquet (size/unitary/cascade/watch) writes each\n",
+        "  output file as a part of the Parquet dataset of its size\n",
+        "  (nsl_XX_dataset/, see --convert --to dataset).\n",
+        "  --storage sqlite (size/unitary/count/check/stats/top)\n",
+        "  writes the list files into one SQLite database per size\n",
+        "  (nsl_XX_lists.sqlite, WAL journal) instead of one file per\n",
+        "  batch; files already on disk are still read. Needs a\n",
+        "  build with --features sqlite; not with --compress.\n",
+        "  --state-backend sqlite keeps the state of each size in\n",
+        "  nsl_XX_global_info.sqlite (one row per file) instead of\n",
+        "  rewriting nsl_XX_global_info.rkyv on every flush: each\n",
+        "  flush only writes the files changed, in one transaction.\n",
+        "  The JSON/TXT exports are unchanged; an existing rkyv state\n",
+        "  is read once and moved to the database (and back with\n",
+        "  rkyv if no rkyv state is left). Needs --features sqlite.\n",
+        "  Every run locks the states it loads or saves with\n",
+        "  nsl_XX_global_info.lock (PID and hostname) until it exits:\n",
+        "  a second run on the same state fails at once. The lock of\n",
+        "  a dead process of the same machine is taken over; delete\n",
+        "  the lock of a run of another machine once it is gone.\n",
+        "  --flush-every N (size/unitary/default/cascade) flushes the\n",
+        "  state every N output files instead of after each one (the\n",
+        "  rewrite of a large rkyv state dominates the run time), and\n",
+        "  always at the end of each input file and of the run. The\n",
+        "  journal (nsl_XX_global_info.journal) records every file in\n",
+        "  between: a run stopped early is replayed by the next one.\n",
+        "  Each flush writes the new state before moving the previous\n",
+        "  one to .rkyv.old; --keep-backups N also keeps the N-1\n",
+        "  before it as .rkyv.<timestamp>.old (--restore-state).\n",
+        "  Each loaded state is self-checked: duplicate filenames,\n",
+        "  batches not matching the filename, files of 0 bytes and\n",
+        "  files with no list counted are logged; --fix-state-on-load\n",
+        "  fixes them (saved with the next flush of the state).\n",
+        "  -i / -o \"D:\\a;E:\\b\" spread a directory over several\n",
+        "  volumes: the first root holds the state and reports, list\n",
+        "  files go to any root and are found on all of them (also\n",
+        "  by compaction, count, check...; pass the same roots to\n",
+        "  every run). --placement round-robin (default) cycles\n",
+        "  through the roots, free-space picks the emptiest one.\n",
+        "  --io-retries N / --io-backoff-ms MS: every open, read,\n",
+        "  write, rename and delete is tried up to N times (default\n",
+        "  3) on transient errors of network shares (sharing or lock\n",
+        "  violations, dropped connections, stale handles), waiting\n",
+        "  MS ms (default 200), then twice as long, between tries.\n",
+        "  --dry-run (size/compact/prune/repair/cascade) prints the\n",
+        "  files that would be read, written, rewritten, deleted or\n",
+        "  renamed, and modifies nothing.\n",
+        "  --notify TARGET (cascade/size/compact, repeatable) sends a\n",
+        "  JSON summary when the run completes or fails, and when the\n",
+        "  disk runs low: http://HOST[:PORT]/PATH or https://... (POST),\n",
+        "  smtp://HOST[:PORT]/TO (SMTP relay without authentication).\n",
+        "  Needs --features notify.\n",
+        "  Exit codes: 0 completed, 1 the run failed, 2 completed with\n",
+        "  warnings (a step worked around: compaction, export, history\n",
+        "  or manifest failing after the lists are written; warnings\n",
+        "  of --check, --verify, --validate-chain and --scan), 3\n",
+        "  integrity errors (findings in the JSON report of these\n",
+        "  modes). Every run ends with one line \"RUN SUMMARY {json}\"\n",
+        "  (mode, outcome, exit_code, message, error_kind: io,\n",
+        "  validation, state, config, integrity or failed, warnings).\n"
     )
 )]
 struct Args {
@@ -159,8 +788,8 @@ struct Args {
     #[arg(long, num_args = 2, value_names = ["SIZE", "BATCH"], conflicts_with_all = ["size", "count"], help = "Process a single input batch: SIZE BATCH")]
     unitary: Option<Vec<u32>>,
 
-    /// Force regeneration of count file (affects --count, --size with batch, and --unitary)
-    #[arg(long, help = "Force regeneration of count file (affects --count, --size with batch, and --unitary)")]
+    /// Force regeneration of count file (affects --count, --size with batch, --unitary and --cascade)
+    #[arg(long, help = "Force regeneration of count file (affects --count, --size with batch, --unitary and the --cascade steps)")]
     force: bool,
 
     /// Keep partial and processed state files after a successful run
@@ -179,6 +808,10 @@ struct Args {
     #[arg(long, conflicts_with_all = ["size", "unitary", "count", "compact", "check", "legacy_count"], help = "Export JSON and TXT files from rkyv state (human-readable format)")]
     create_json: Option<u8>,
 
+    /// With --create-json: json (JSON and TXT) or csv (one row per file entry)
+    #[arg(long, default_value = "json", value_parser = ["json", "csv"], requires = "create_json", help = "With --create-json: export format, json (JSON and TXT, default) or csv (nsl_XX_global_info.csv)")]
+    format: String,
+
     /// Compact small output files into larger batches: <SIZE> [MAX_BATCH]
     /// Consolidates multiple small output files into larger batches.
     /// Optional MAX_BATCH parameter stops compaction after processing files up to that batch number.
@@ -190,12 +823,83 @@ struct Args {
     #[arg(long, conflicts_with_all = ["size", "unitary", "count", "compact"], help = "Check repository integrity for a specific size")]
     check: Option<u8>,
 
+    /// With --check: re-count the lists of every file against the state
+    /// WORKERS threads (default: one per core).
+    #[arg(long, value_name = "WORKERS", num_args = 0..=1, default_missing_value = "0", requires = "check", help = "With --check: re-count the lists of every file on WORKERS threads (default: one per core) and report counts differing from the state")]
+    deep: Option<usize>,
+
+    /// With --check: repair the state first (see repair), then close the spurious
+    /// gaps of the batch numbering when safe, logging every fix.
+    #[arg(long, requires = "check", help = "With --check: remove the entries of missing files, register (and count) untracked files, renumber to close spurious gaps when safe, and log every fix in nsl_SIZE_repair.log")]
+    fix: bool,
+
+    /// With --check: the input batches of the previous size in -i with no output
+    #[arg(long, requires = "check", help = "With --check: load the size SIZE-1 files of -i and report the input batches with no output of size SIZE (unconsumed), as --validate-chain does for one pair of sizes")]
+    against_input: bool,
+
+    /// With --count: counts from the footers, or estimated from the file sizes
+    #[arg(long, requires = "count", help = "With --count: read the counts from the file footers, or estimate them from the file sizes (bytes per list calibrated on a few files counted in full), marking the estimated entries in the state until a full --count")]
+    fast: bool,
+
+    /// With --count: count again every MINUTES, with the rolling throughput
+    #[arg(long, value_name = "MINUTES", num_args = 0..=1, default_missing_value = "10", requires = "count", conflicts_with = "fast", help = "With --count: re-count every MINUTES (default 10), registering only the new files, and print the rolling lists/hour and projected completion until -i/funny_watch.stop exists")]
+    follow: Option<u64>,
+
     /// Cascade mode: process all sizes starting from a given input size
     /// Generates output files of growing sizes by processing unprocessed batches.
-    /// Takes the starting input size (12-19) and uses the current directory or -i as root.
-    #[arg(long, conflicts_with_all = ["size", "unitary", "count", "compact", "check"], help = "Cascade mode: process sizes starting from input size (12-19)")]
+    /// Takes the starting input size (3-19) and uses the current directory or -i as root.
+    #[arg(long, conflicts_with_all = ["size", "unitary", "count", "compact", "check"], help = "Cascade mode: process sizes starting from input size (3-19)")]
     cascade: Option<u8>,
 
+    /// With --cascade: every size in the root directory (no per-size subdirectories)
+    #[arg(long, requires = "cascade", help = "With --cascade: read and write every size in the root directory itself instead of the {N-1}_to_{N} subdirectories")]
+    flat: bool,
+
+    /// With --cascade: last output size processed
+    #[arg(long, value_name = "TO", requires = "cascade", help = "With --cascade: stop after output size TO (default 20)")]
+    stop_at: Option<u8>,
+
+    /// With --cascade: output sizes not processed
+    #[arg(long, value_name = "SIZES", value_delimiter = ',', requires = "cascade", help = "With --cascade: comma-separated output sizes to skip (e.g. 15,17)")]
+    skip_sizes: Vec<u8>,
+
+    /// With --cascade: more roots searched for the size directories
+    #[arg(long, value_name = "ROOTS", value_delimiter = ',', requires = "cascade", conflicts_with = "flat", help = "With --cascade: comma-separated roots searched after -i for the size directories (a missing one is created on the first root with room)")]
+    cascade_roots: Vec<String>,
+
+    /// With --cascade: root of the directory of one size
+    #[arg(long, value_name = "SIZE=ROOT", requires = "cascade", conflicts_with = "flat", help = "With --cascade: the directory of the size SIZE lists is under ROOT (repeatable)")]
+    cascade_root: Vec<String>,
+
+    /// With --cascade: free space to keep in the output directories, in GB
+    /// Checked before each size and before each input batch; see --cascade-low-space.
+    #[arg(long, value_name = "G", value_parser = parse_file_size_gb, requires = "cascade", help = "With --cascade: keep G GB free in the output directory, checked before each size and each input batch (see --cascade-low-space)")]
+    cascade_min_free_gb: Option<f64>,
+
+    /// What the cascade does below --cascade-min-free-gb: stop (default), pause or prune
+    #[arg(long, default_value = "stop", value_parser = ["stop", "pause", "prune"], requires = "cascade_min_free_gb", help = "Below --cascade-min-free-gb: stop cleanly with a resume hint (default), pause until space is freed, or prune the consumed inputs of the earlier sizes (then stop if still low)")]
+    cascade_low_space: String,
+
+    /// With --cascade: tries again of a failed size, resuming from its state
+    #[arg(long, value_name = "N", default_value_t = 0, requires = "cascade", help = "With --cascade: try a failed size again up to N times (default 0), resuming from its state")]
+    cascade_retries: u32,
+
+    /// With --cascade: seconds before the first retry of a failed size, doubled at each retry
+    #[arg(long, value_name = "SECS", default_value_t = 60, requires = "cascade", help = "With --cascade: seconds before the first retry of a failed size (default 60), doubled at each retry")]
+    cascade_retry_delay: u64,
+
+    /// With --cascade: go on with the next sizes when a size still fails
+    #[arg(long, requires = "cascade", help = "With --cascade: leave out a size still failing after its retries and go on with the next sizes (exit code 1 at the end)")]
+    cascade_skip_failed: bool,
+
+    /// With --cascade: --force / --keep_state of one output size, e.g. 16:no-force,keep-state
+    #[arg(long, value_name = "SIZE:FLAGS", requires = "cascade", help = "With --cascade: override --force / --keep_state for output size SIZE; FLAGS among force, no-force, keep-state, no-keep-state, comma-separated (repeatable)")]
+    cascade_step: Vec<String>,
+
+    /// Where to send the end (or failure) of a cascade, size or compact run
+    #[arg(long, value_name = "TARGET", help = "Notify TARGET when a --cascade, --size or --compact run completes or fails, or runs low on disk: http://HOST[:PORT]/PATH, https://... (POST of a JSON summary), smtp://HOST[:PORT]/TO (mail through a relay) (repeatable; needs --features notify)")]
+    notify: Vec<String>,
+
     /// Save history mode: merge current state with historical state
     /// Preserves records of all files ever processed, even if deleted.
     #[arg(long, conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade"], help = "Save history: merge current state with historical records for a size")]
@@ -206,61 +910,391 @@ struct Args {
     #[arg(long, conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count"], help = "Export lists from rkyv files to human-readable .txt and .json")]
     export_lists: Option<String>,
 
+    /// Orbits mode: count stored lists of a size up to symmetry
+    /// Groups lists by canonical form under the affine group AGL(4,3).
+    #[arg(long, conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists"], help = "Orbits: count stored lists of a size up to symmetry (3-20)")]
+    orbits: Option<u8>,
+
+    /// Verify mode: deep re-check of every stored list of a size
+    /// Re-checks the no-set property and the remaining cards of each list.
+    #[arg(long, conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits"], help = "Verify: re-check every stored list of a size (no set, remaining cards) (3-20)")]
+    verify: Option<u8>,
+
+    /// Final report mode: consolidated record of a finalized size
+    /// Combines state, history, verification, timing, compaction and reference counts.
+    #[arg(long, conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify"], help = "Final report: write nsl_XX_final_report.json for a finalized size (3-20)")]
+    final_report: Option<u8>,
+
+    /// Random-walk mode: Monte-Carlo exploration of large no-set-lists
+    /// Grows random complete lists from random seeds, ITERATIONS times.
+    #[arg(long, value_name = "ITERATIONS", conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report"], help = "Random walk: grow ITERATIONS random complete no-set-lists (see --seed)")]
+    random_walk: Option<u64>,
+
+    /// RNG seed for random-walk and sample modes (reproducible runs)
+    #[arg(long, help = "RNG seed for --random-walk and --sample (default: from the clock)")]
+    seed: Option<u64>,
+
+    /// Apply the stronger reachability bound when expanding lists
+    /// Drops lists whose remaining cards provably cannot reach 12 cards (fewer stored lists).
+    #[arg(long, help = "Strong pruning: drop lists whose remaining cards cannot reach 12 cards (changes stored counts)")]
+    strong_prune: bool,
+
+    /// Drop children isomorphic to one already created from the same input batch
+    /// Per-batch hash set of canonical forms (see --orbits); hit rate in the timing report.
+    #[arg(long, help = "Isomorph cache: drop children isomorphic to another child of the same input batch (changes stored counts)")]
+    isomorph_cache: bool,
+
+    /// Compare the total of each completed size with the expected counts
+    /// Built-in table for sizes 3-10, optionally overridden/extended by a JSON file ({"11": N, ...}).
+    #[arg(long, num_args = 0..=1, value_name = "JSON", conflicts_with_all = ["unitary", "strong_prune", "isomorph_cache"], help = "Validate the total of each completed size against reference counts (optional JSON of expected values)")]
+    validate_counts: Option<Option<String>>,
+
+    /// Print the files a destructive mode would read, write, delete or rename, and stop
+    /// Honored by size, compact, prune, repair and cascade; nothing is modified.
+    #[arg(long, help = "Dry run: print the file operations of size/compact/prune/repair/cascade without touching anything")]
+    dry_run: bool,
+
+    /// Only expand the input lists whose max_card falls into shard K of M (max_card % M == K)
+    /// Outputs are tagged _shard_KKofMM; the shards of a size share its output directory.
+    #[arg(long, value_name = "K/M", conflicts_with_all = ["validate_counts", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats"], help = "Shard K/M: only expand input lists with max_card % M == K (size and unitary modes)")]
+    shard: Option<String>,
+
+    /// Filter mode: keep only the lists of a size that can still reach TARGET cards
+    /// Rewrites the size files and updates the state counts (downstream steps shrink).
+    #[arg(long, num_args = 2, value_names = ["TARGET", "SIZE"], conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk"], help = "Filter: keep only the size SIZE lists able to reach TARGET cards (rewrites files): TARGET SIZE")]
+    filter_target: Option<Vec<u8>>,
+
+    /// Stats mode: card-composition statistics of the stored lists of a size
+    /// Per-card frequencies, max_card and remaining-length histograms (JSON + TXT).
+    #[arg(long, conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target"], help = "Stats: per-card frequencies and histograms of the stored lists of a size (3-20)")]
+    stats: Option<u8>,
+
+    /// Extract mode: print one stored list in index and decoded card form
+    /// Opens the SIZE file of batch FILE_BATCH (compacted preferred) and prints list INDEX.
+    #[arg(long, num_args = 3, value_names = ["SIZE", "FILE_BATCH", "INDEX"], conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats"], help = "Extract: print list INDEX of the size SIZE file with batch FILE_BATCH: SIZE FILE_BATCH INDEX")]
+    extract: Option<Vec<u64>>,
+
+    /// Sample mode: export N lists of a size drawn uniformly at random
+    /// Writes nsl_XX_sample_N.rkyv and .json (reproducible with --seed).
+    #[arg(long, num_args = 2, value_names = ["SIZE", "N"], conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract"], help = "Sample: export N uniformly drawn lists of a size (see --seed): SIZE N")]
+    sample: Option<Vec<u64>>,
+
+    /// Split mode: break the files of a size holding more than MAX_LISTS lists
+    /// Extra parts get new target batches; the global state is updated.
+    #[arg(long, num_args = 2, value_names = ["SIZE", "MAX_LISTS"], conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample"], help = "Split: rewrite files above MAX_LISTS lists into smaller batches: SIZE MAX_LISTS")]
+    split: Option<Vec<u64>>,
+
+    /// Migrate mode: convert legacy nlist_*.bin / nlist_v31_*.rkyv / nlist_*.rkyv files
+    /// Converted files follow the nsl_* naming and are registered in the global state.
+    #[arg(long, conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample", "split"], help = "Migrate: convert legacy nlist_* files of -i to the current format (see --delete-originals)")]
+    migrate: bool,
+
+    /// With --migrate: delete each legacy file once its conversion is written
+    /// With --compact -o DIR: delete the compacted sources once the written files are verified
+    #[arg(long, help = "With --migrate: delete the legacy files once converted; with --compact -o DIR: delete the sources once the compacted files are verified")]
+    delete_originals: bool,
+
+    /// Convert mode: export every list of a size to CSV, JSONL or Parquet (see --to)
+    /// Streams the rkyv files: one row per list in nsl_XX_lists.<format>.
+    #[arg(long, value_name = "SIZE", requires = "to", conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample", "split", "migrate"], help = "Convert: export the lists of a size to --to parquet|dataset|csv|jsonl")]
+    convert: Option<u8>,
+
+    /// With --convert: output format
+    #[arg(long, value_name = "FORMAT", requires = "convert", value_parser = ["parquet", "dataset", "csv", "jsonl"], help = "With --convert: output format (parquet, dataset, csv or jsonl)")]
+    to: Option<String>,
+
+    /// Benchmark mode: time the seed lists expanded up to MAX_SIZE (default 6)
+    /// Prints a JSON result (also saved as benchmark_result.json in -o).
+    #[arg(long, value_name = "MAX_SIZE", num_args = 0..=1, default_missing_value = "6", conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample", "split", "migrate", "convert"], help = "Benchmark: time a fixed workload (seeds expanded to MAX_SIZE, default 6) and print JSON")]
+    benchmark: Option<u8>,
+
+    /// Estimate mode: predict output lists, disk space and runtime of a size
+    /// Samples BATCHES input batches (default 3) of SIZE-1 lists from -i.
+    #[arg(long, num_args = 1..=2, value_names = ["SIZE", "BATCHES"], conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample", "split", "migrate", "convert", "benchmark"], help = "Estimate: predict output lists, disk space and runtime of a size: SIZE [BATCHES]")]
+    estimate: Option<Vec<u64>>,
+
+    /// Prune mode: delete the size files fully consumed by the next size
+    /// Outputs are verified first; see --archive-dir to move the files instead.
+    #[arg(long, value_name = "SIZE", conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample", "split", "migrate", "convert", "benchmark", "estimate"], help = "Prune: delete size SIZE inputs consumed by size SIZE+1 (outputs verified first)")]
+    prune: Option<u8>,
+
+    /// With --prune: move the consumed files to this directory instead of deleting them
+    #[arg(long, value_name = "DIR", requires = "prune", help = "With --prune: move the consumed files to DIR instead of deleting them")]
+    archive_dir: Option<String>,
+
+    /// Repair mode: reconcile the global state of a size with the files on disk
+    /// Writes nsl_XX_repair_report.txt next to the state.
+    #[arg(long, value_name = "SIZE", conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample", "split", "migrate", "convert", "benchmark", "estimate", "prune"], help = "Repair: remove stale state entries, register unknown files, fix counts of a size")]
+    repair: Option<u8>,
+
+    /// Diff mode: compare the global states of a size in -i (A) and -o (B)
+    /// Added/removed/modified entries and count deltas, in the log and as JSON.
+    #[arg(long, value_name = "SIZE", conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample", "split", "migrate", "convert", "benchmark", "estimate", "prune", "repair"], help = "Diff: compare the size SIZE states of -i (A) and -o (B)")]
+    diff: Option<u8>,
+
+    /// Archive mode: package the files, state and history of a size as a .tar.zst
+    /// With an embedded manifest of list counts and SHA-256 hashes.
+    #[arg(long, value_name = "SIZE", conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample", "split", "migrate", "convert", "benchmark", "estimate", "prune", "repair", "diff"], help = "Archive: bundle size SIZE of -i into -o/nsl_SIZE_archive.tar.zst (with a manifest of counts and hashes)")]
+    archive: Option<u8>,
+
+    /// Unarchive mode: restore an archive written by --archive into -o
+    /// Every file is checked against the SHA-256 of the manifest.
+    #[arg(long, value_name = "ARCHIVE", conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample", "split", "migrate", "convert", "benchmark", "estimate", "prune", "repair", "diff", "archive"], help = "Unarchive: restore the files of ARCHIVE into -o, checking their SHA-256")]
+    unarchive: Option<String>,
+
+    /// Watch mode: process the input batches of size SIZE as they appear in -i
+    /// Each new complete batch is processed in unitary style; stops on funny_watch.stop in -o.
+    #[arg(long, value_name = "SIZE", conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample", "split", "migrate", "convert", "benchmark", "estimate", "prune", "repair", "diff", "archive", "unarchive"], help = "Watch: process each new input batch of size SIZE appearing in -i (unitary style) until -o/funny_watch.stop exists")]
+    watch: Option<u8>,
+
+    /// With --watch: seconds between two polls of the input directory
+    #[arg(long, default_value_t = 60, value_name = "SECS", requires = "watch", help = "With --watch: seconds between two polls of the input directory (default 60)")]
+    watch_interval: u64,
+
+    /// Inspect mode: examine a single list file
+    /// Validation, count, histograms, max_card range, invalid and duplicate lists, first/last lists.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample", "split", "migrate", "convert", "benchmark", "estimate", "prune", "repair", "diff", "archive", "unarchive", "watch"], help = "Inspect: validate FILE and report its count, histograms, max_card range, invalid and duplicate lists, first/last lists")]
+    inspect: Option<String>,
+
+    /// Top mode: leaderboard of the stored lists of a size with the most headroom
+    /// The N lists (default 10) with the most remaining cards, or the highest bound (--rank-by bound).
+    #[arg(long, num_args = 1..=2, value_names = ["SIZE", "N"], conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample", "split", "migrate", "convert", "benchmark", "estimate", "prune", "repair", "diff", "archive", "unarchive", "watch", "inspect"], help = "Top: print the N lists of size SIZE with the most remaining cards (or highest bound): SIZE [N]")]
+    top: Option<Vec<u64>>,
+
+    /// With --top: ranking criterion
+    #[arg(long, default_value = "remaining", value_parser = ["remaining", "bound"], requires = "top", help = "With --top: rank by remaining cards or by reachable bound (default remaining)")]
+    rank_by: String,
+
+    /// Normalize-filenames mode: rename the files of SIZE to the canonical scheme
+    /// 6-digit batch numbers, _compacted and shard tags kept; the state is updated after each rename.
+    #[arg(long, value_name = "SIZE", conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample", "split", "migrate", "convert", "benchmark", "estimate", "prune", "repair", "diff", "archive", "unarchive", "watch", "inspect", "top"], help = "Normalize filenames: rename the SIZE files to nsl_XX_batch_NNNNNN_to_YY_batch_NNNNNN[...].rkyv and update the state")]
+    normalize_filenames: Option<u8>,
+
+    /// Validate-chain mode: cross-size consistency of a cascade directory tree
+    /// Every size N input batch consumed by size N+1, every output from a known input.
+    #[arg(long, num_args = 2, value_names = ["FROM", "TO"], conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample", "split", "migrate", "convert", "benchmark", "estimate", "prune", "repair", "diff", "archive", "unarchive", "watch", "inspect", "top", "normalize_filenames"], help = "Validate chain: check each pair of sizes FROM..TO under the cascade root -i (unconsumed inputs, gaps, unknown sources): FROM TO")]
+    validate_chain: Option<Vec<u8>>,
+
+    /// Export-cards mode: write the lists of one file as readable card descriptions
+    /// Opens the SIZE file of batch FILE_BATCH (compacted preferred) and writes <file>_cards.txt.
+    #[arg(long, num_args = 2, value_names = ["SIZE", "FILE_BATCH"], conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample", "split", "migrate", "convert", "benchmark", "estimate", "prune", "repair", "diff", "archive", "unarchive", "watch", "inspect", "top", "normalize_filenames", "validate_chain"], help = "Export cards: write the lists of the size SIZE file with batch FILE_BATCH as card descriptions (<file>_cards.txt): SIZE FILE_BATCH")]
+    export_cards: Option<Vec<u64>>,
+
+    /// GC mode: find the artifacts of interrupted runs (temp files, state backups, redundant count files)
+    /// Each artifact cross-checked against the state; deleted only with --force.
+    #[arg(long, num_args = 0..=1, value_name = "SIZE", conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample", "split", "migrate", "convert", "benchmark", "estimate", "prune", "repair", "diff", "archive", "unarchive", "watch", "inspect", "top", "normalize_filenames", "validate_chain", "export_cards"], help = "GC: list the stale .tmp/.old/_old files and redundant intermediate count files of -i (of SIZE only, if given); --force deletes them")]
+    gc: Option<Vec<u8>>,
+
+    /// Reencode mode: rewrite the files of a size in the encoding of --encoding (and --compress)
+    /// Names, batches and lists unchanged; each file checked, then renamed over the original.
+    #[arg(long, value_name = "SIZE", conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample", "split", "migrate", "convert", "benchmark", "estimate", "prune", "repair", "diff", "archive", "unarchive", "watch", "inspect", "top", "normalize_filenames", "validate_chain", "export_cards", "gc"], help = "Reencode: rewrite the size SIZE files of -i in the --encoding (plain|delta|packed) and --compress settings, updating the state")]
+    reencode: Option<u8>,
+
+    /// Checksum mode: verify the integrity footer (CRC32 and list count) of every file of a size
+    /// Reads each file once without decoding it; fails if any file is corrupted.
+    #[arg(long, value_name = "SIZE", conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample", "split", "migrate", "convert", "benchmark", "estimate", "prune", "repair", "diff", "archive", "unarchive", "watch", "inspect", "top", "normalize_filenames", "validate_chain", "export_cards", "gc", "reencode"], help = "Checksum: verify the CRC32 footer of every size SIZE file of -i without decoding the lists (fails on corruption)")]
+    checksum: Option<u8>,
+
+    /// Verify-manifest mode: re-hash the files of a size against its nsl_XX_manifest.json
+    /// Reports hash or size mismatches, missing files and files not in the manifest.
+    #[arg(long, value_name = "SIZE", conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample", "split", "migrate", "convert", "benchmark", "estimate", "prune", "repair", "diff", "archive", "unarchive", "watch", "inspect", "top", "normalize_filenames", "validate_chain", "export_cards", "gc", "reencode", "checksum"], help = "Verify manifest: re-hash the size SIZE files of -i against nsl_SIZE_manifest.json (mismatches, missing and unlisted files)")]
+    verify_manifest: Option<u8>,
+
+    /// Migrate-format mode: upgrade the files of a size to the current file format version
+    /// Files written without versioned header or footer are rewritten in place, same encoding.
+    #[arg(long, value_name = "SIZE", conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample", "split", "migrate", "convert", "benchmark", "estimate", "prune", "repair", "diff", "archive", "unarchive", "watch", "inspect", "top", "normalize_filenames", "validate_chain", "export_cards", "gc", "reencode", "checksum", "verify_manifest"], help = "Migrate format: rewrite the size SIZE files of -i written in an older file format (no versioned header or footer), keeping their encoding, updating the state")]
+    migrate_format: Option<u8>,
+
+    /// Upgrade-state mode: rewrite the global state of a size in the current schema version
+    /// Older rkyv and JSON states (current and history) are migrated and rewritten in place.
+    #[arg(long, value_name = "SIZE", conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample", "split", "migrate", "convert", "benchmark", "estimate", "prune", "repair", "diff", "archive", "unarchive", "watch", "inspect", "top", "normalize_filenames", "validate_chain", "export_cards", "gc", "reencode", "checksum", "verify_manifest", "migrate_format"], help = "Upgrade state: rewrite the size SIZE global state of -i (rkyv and JSON, current and history) written in an older schema version")]
+    upgrade_state: Option<u8>,
+
+    /// Overview mode: progress table of every size under the root directory -i
+    /// Cascade layout aware; reads the per-size states only.
+    #[arg(long, conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample", "split", "migrate", "convert", "benchmark", "estimate", "prune", "repair", "diff", "archive", "unarchive", "watch", "inspect", "top", "normalize_filenames", "validate_chain", "export_cards", "gc", "reencode", "checksum", "verify_manifest", "migrate_format", "upgrade_state"], help = "Overview: table of every size under the root directory -i (cascade layout aware): files, lists, disk usage, last activity and percent of the previous size consumed")]
+    overview: bool,
+
+    /// Restore-state mode: roll the state of a size back to one of its backups
+    /// The replaced state becomes the last backup, so a restore can be undone.
+    #[arg(long, num_args = 1..=2, value_names = ["SIZE", "TIMESTAMP"], conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample", "split", "migrate", "convert", "benchmark", "estimate", "prune", "repair", "diff", "archive", "unarchive", "watch", "inspect", "top", "normalize_filenames", "validate_chain", "export_cards", "gc", "reencode", "checksum", "verify_manifest", "migrate_format", "upgrade_state", "overview"], help = "Restore-state: put back the backup of the size SIZE state of -i matching TIMESTAMP (%Y%m%d_%H%M%S or a prefix; default: the most recent): SIZE [TIMESTAMP]")]
+    restore_state: Option<Vec<String>>,
+
+    /// Merge-state mode: merge the state of a size in -i (another machine) into -o
+    /// Overlapping target batches are settled by --merge-policy.
+    #[arg(long, value_name = "SIZE", conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample", "split", "migrate", "convert", "benchmark", "estimate", "prune", "repair", "diff", "archive", "unarchive", "watch", "inspect", "top", "normalize_filenames", "validate_chain", "export_cards", "gc", "reencode", "checksum", "verify_manifest", "migrate_format", "upgrade_state", "overview", "restore_state"], help = "Merge state: merge the size SIZE state of -i (A) into the one of -o (B), conflicting target batches settled by --merge-policy")]
+    merge_state: Option<u8>,
+
+    /// With --merge-state: how conflicting target batches are settled
+    #[arg(long, default_value = "fail", value_parser = ["fail", "prefer-newer", "prefer-larger-count"], requires = "merge_state", help = "With --merge-state: conflict policy, fail (default), prefer-newer or prefer-larger-count")]
+    merge_policy: String,
+
+    /// Rebalance mode: rewrite the compacted files of a size into uniform files
+    /// Files of --compact-size lists (default: lists per output file); state updated.
+    #[arg(long, value_name = "SIZE", conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample", "split", "migrate", "convert", "benchmark", "estimate", "prune", "repair", "diff", "archive", "unarchive", "watch", "inspect", "top", "normalize_filenames", "validate_chain", "export_cards", "gc", "reencode", "checksum", "verify_manifest", "migrate_format", "upgrade_state", "overview", "restore_state", "merge_state"], help = "Rebalance: rewrite the compacted files of size SIZE in -i into files of --compact-size lists (default: lists per output file)")]
+    rebalance: Option<u8>,
+
+    /// Scan mode: decode and check every list of every file of a size, in parallel
+    /// Corrupted files are moved to quarantine/ and removed from the state.
+    #[arg(long, num_args = 1..=2, value_names = ["SIZE", "WORKERS"], conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample", "split", "migrate", "convert", "benchmark", "estimate", "prune", "repair", "diff", "archive", "unarchive", "watch", "inspect", "top", "normalize_filenames", "validate_chain", "export_cards", "gc", "reencode", "checksum", "verify_manifest", "migrate_format", "upgrade_state", "overview", "restore_state", "merge_state", "rebalance"], help = "Scan: decode every list of the size SIZE files of -i on WORKERS threads (default: one per core), check its fields, move the corrupted files to quarantine/ and remove them from the state: SIZE [WORKERS]")]
+    scan: Option<Vec<u64>>,
+
+    /// Count-all mode: --count every size under the root directory -i, then one grand total
+    /// Per size lists, files, disk usage and growth factor, saved as nsl_grand_total.json/.txt.
+    #[arg(long, conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample", "split", "migrate", "convert", "benchmark", "estimate", "prune", "repair", "diff", "archive", "unarchive", "watch", "inspect", "top", "normalize_filenames", "validate_chain", "export_cards", "gc", "reencode", "checksum", "verify_manifest", "migrate_format", "upgrade_state", "overview", "restore_state", "merge_state", "rebalance", "scan"], help = "Count all: run --count on every size under the root directory -i (cascade layout aware) and save one grand total (lists, files, disk usage, growth factor per size) as nsl_grand_total.json and .txt")]
+    count_all: bool,
+
+    /// History-diff mode: compare the history of a size in -i with its current state
+    /// Entries of the history only (deleted or consumed, with their reason), of the current state only, differing counts.
+    #[arg(long, value_name = "SIZE", conflicts_with_all = ["size", "unitary", "count", "compact", "check", "cascade", "save_history", "create_json", "legacy_count", "export_lists", "orbits", "verify", "final_report", "random_walk", "filter_target", "stats", "extract", "sample", "split", "migrate", "convert", "benchmark", "estimate", "prune", "repair", "diff", "archive", "unarchive", "watch", "inspect", "top", "normalize_filenames", "validate_chain", "export_cards", "gc", "reencode", "checksum", "verify_manifest", "migrate_format", "upgrade_state", "overview", "restore_state", "merge_state", "rebalance", "scan", "count_all"], help = "History diff: compare the size SIZE history (nsl_SIZE_global_info_history) of -i with its current state: entries of the history only (with their tombstone reason), of the current state only, and differing counts")]
+    history_diff: Option<u8>,
+
+    /// Number of decoded input batches kept in memory (0 = no cache)
+    /// Avoids re-reading and re-validating batches loaded several times in one run.
+    #[arg(long, default_value_t = 0, value_name = "N", help = "Keep the last N decoded input batches in memory (default 0: disabled)")]
+    cache_batches: usize,
+
+    /// RAM available to the run, in GB (default: sized for ~16 GB)
+    /// Scales the lists buffered per output file (which also sizes compaction
+    /// batches) and caps the batch cache.
+    #[arg(long, value_name = "GB", value_parser = clap::value_parser!(u64).range(1..=65536), help = "RAM available in GB: scales lists per output file (also compaction batches) and caps --cache-batches (default: sized for ~16 GB)")]
+    memory_limit: Option<u64>,
+
+    /// Lists per output file, which is also the compaction batch size (default 10M)
+    /// Overrides --memory-limit; the value used is recorded in the state of the size.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), conflicts_with = "file_size_gb", help = "Lists per output file, also the compaction batch size (default 10M; overrides --memory-limit)")]
+    lists_per_file: Option<u64>,
+
+    /// Lists per compacted file (default: the lists per output file)
+    /// A big-RAM machine can build larger compacted files than it writes output files.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), help = "Lists per compacted file, --compact, --rebalance and the automatic compaction of sizes 13+ (default: the lists per output file)")]
+    compact_size: Option<u64>,
+
+    /// Non-compacted files needed to (keep) compacting (default 2)
+    /// A higher value delays compaction until more fragments accumulate.
+    #[arg(long, default_value_t = 2, value_name = "K", value_parser = clap::value_parser!(u64).range(2..), help = "Compact only while at least K non-compacted files remain (default 2)")]
+    compact_min_files: u64,
+
+    /// Write the source batches of each compacted file next to it
+    /// (<compacted file>.sources.json); the state records them either way.
+    #[arg(long, help = "Write the source batches of each compacted file to <compacted file>.sources.json")]
+    sources_sidecar: bool,
+
+    /// Compact the outputs of sizes 13+ while they are processed
+    /// A worker thread writes full compacted files from the outputs already written.
+    #[arg(long, requires = "size", help = "With --size 13+: compact the outputs already written on a worker thread while processing goes on")]
+    background_compact: bool,
+
+    /// Drop duplicate card sets while compacting in place
+    /// Every list compacted by the run is hashed (16 bytes per list in memory).
+    #[arg(long, requires = "compact", help = "With --compact (in place): drop the lists whose card set was already compacted by the run, and adjust the state counts")]
+    dedupe: bool,
+
+    /// Free space to keep while compacting, in GB (no check by default)
+    /// Checked before each compacted file; see --compact-low-space.
+    #[arg(long, value_name = "G", value_parser = parse_file_size_gb, help = "Keep G GB free on the compacted volume: checked before each compacted file (see --compact-low-space)")]
+    compact_min_free_gb: Option<f64>,
+
+    /// What compaction does below --compact-min-free-gb: abort (default) or pause
+    #[arg(long, default_value = "abort", value_parser = ["abort", "pause"], requires = "compact_min_free_gb", help = "Below --compact-min-free-gb: abort (stop cleanly between two files, default) or pause (wait until space is freed)")]
+    compact_low_space: String,
+
+    /// Delete each source as soon as the compacted files holding its lists are verified
+    #[arg(long, requires = "delete_originals", help = "With --compact -o DIR --delete-originals: verify each compacted file once written and delete the sources it completes right away")]
+    delete_early: bool,
+
+    /// Lists per output file targeting files of about G GB (estimated from the encoding)
+    #[arg(long, value_name = "G", value_parser = parse_file_size_gb, help = "Lists per output file for files of about G GB, estimated from --encoding before compression (overrides --memory-limit)")]
+    file_size_gb: Option<f64>,
+
+    /// Encoding of the list files written (reading detects all of them)
+    /// delta: one byte per card, much smaller files for large sizes.
+    /// packed: two 81-bit card masks, 24 bytes per list whatever the size.
+    #[arg(long, default_value = "plain", value_parser = ["plain", "delta", "packed"], help = "Encoding of written list files: plain, delta or packed (default plain)")]
+    encoding: String,
+
+    /// zstd compression of the list files written (reading detects it)
+    /// --compress uses level 3; --compress=LEVEL picks the level (1-22).
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "3", value_name = "LEVEL", value_parser = clap::value_parser!(i32).range(1..=22), help = "Compress written list files with zstd (size/unitary/compact/cascade/watch/reencode/migrate-format; default level 3)")]
+    compress: Option<i32>,
+
+    /// Also write each output list file as a Parquet dataset part (nsl_XX_dataset/)
+    /// Columns: size, max_card, cards and remaining bitmasks, source_batch.
+    #[arg(long, help = "Also write each output file as a Parquet part in nsl_XX_dataset/ (size/unitary/cascade/watch; needs --features parquet)")]
+    also_parquet: bool,
+
+    /// Storage backend of the list files: one file per batch, or one SQLite database per size
+    /// Files already on disk are read with either backend.
+    #[arg(long, default_value = "files", value_parser = ["files", "sqlite"], help = "Storage of the list files: files or sqlite (nsl_XX_lists.sqlite; size/unitary/count/check/stats/top; needs --features sqlite)")]
+    storage: String,
+
+    /// Backend of the global state of each size: rkyv file, or SQLite database
+    /// The sqlite backend writes only the entries changed since the last flush.
+    #[arg(long, default_value = "rkyv", value_parser = ["rkyv", "sqlite"], help = "Backend of the state of each size: rkyv (nsl_XX_global_info.rkyv) or sqlite (nsl_XX_global_info.sqlite, updated per file; needs --features sqlite)")]
+    state_backend: String,
+
+    /// Output files written between two flushes of the state (size/unitary/default/cascade)
+    /// The journal covers the files written since the last flush.
+    #[arg(long, default_value_t = 1, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), help = "Flush the state every N output files, and at the end of each input file and of the run (default 1; the journal covers the files in between)")]
+    flush_every: u64,
+
+    /// Backups kept of each state and history file (rkyv backend)
+    /// The last one is .rkyv.old, the older ones .rkyv.<timestamp>.old (see --restore-state).
+    #[arg(long, default_value_t = 1, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), help = "Keep N backups of each state and history file: .rkyv.old, then .rkyv.<timestamp>.old (default 1; see --restore-state)")]
+    keep_backups: u64,
+
+    /// Fix the inconsistencies found when a state is loaded (saved with its next flush)
+    /// Duplicate filenames, batches different from the filename's, files of 0 bytes or with no list.
+    #[arg(long, help = "Fix the inconsistencies the self-check finds in loaded states (duplicate filenames, batches not matching the filename, empty files, zero counts) instead of only logging them")]
+    fix_state_on_load: bool,
+
+    /// Attempts of each file system operation (open, read, write, rename, delete)
+    /// Transient errors of network shares (sharing violations, dropped connections) are retried.
+    #[arg(long, default_value_t = 3, value_name = "N", value_parser = clap::value_parser!(u32).range(1..=100), help = "Attempts of each file operation on transient errors, e.g. SMB sharing violations (default 3; 1 = no retry)")]
+    io_retries: u32,
+
+    /// Delay before the first retry of a file system operation, doubled at each retry
+    #[arg(long, default_value_t = 200, value_name = "MS", help = "Delay before the first retry of a file operation in ms, doubled at each retry (default 200)")]
+    io_backoff_ms: u64,
+
+    /// Placement of new list files over the roots of a multi-volume directory
+    #[arg(long, default_value = "round-robin", value_parser = ["round-robin", "free-space"], help = "Root new list files go to when -i/-o list several roots (D:\\a;E:\\b): round-robin or free-space")]
+    placement: String,
+
+    /// Expansion engine of the size steps (see engine.rs)
+    #[arg(long, default_value = "current", value_parser = ["current"], help = "Expansion engine of --size, --unitary, --cascade and --benchmark (current)")]
+    engine: String,
+
     /// Input directory path (optional)
     /// Directory to read input files from; usage varies by mode.
-    #[arg(short, long, help = "Input directory path (optional)")]
+    #[arg(short, long, help = "Input directory path (optional; several roots separated by ';' spread it over volumes)")]
     input_path: Option<String>,
 
     /// Output directory path (optional)
     /// Directory to write output files to; usage varies by mode.
-    #[arg(short, long, help = "Output directory path (optional)")]
+    #[arg(short, long, help = "Output directory path (optional; several roots separated by ';' spread it over volumes)")]
     output_path: Option<String>,
 }
 
-/// Parse size argument into start and end range
-/// Examples: "5" -> (5, 5), "5-7" -> (5, 7)
-
-
-/// Unified configuration for all processing modes
-#[derive(Debug)]
-struct ProcessingConfig {
-    mode: ProcessingMode,
-    input_dir: String,
-    output_dir: String,
-    max_lists_per_file: u64,
-    force_recount: bool,
-    keep_state: bool,
-}
-
-/// Processing mode enumeration
-#[derive(Debug)]
-enum ProcessingMode {
-    Count { size: u8 },
-    LegacyCount { size: u8 },
-    CreateJson { size: u8 },
-    Check { size: u8 },
-    Compact { size: u8, max_batch: Option<u32> },
-    Size { size: u8, start_batch: Option<u32> },
-    Unitary { size: u8, batch: u32 },
-    Cascade { starting_input_size: u8, root_directory: String },
-    SaveHistory { size: u8 },
-    ExportLists { filename: String },
-    Default,
+/// Parse the --file-size-gb value (a positive number of GB)
+fn parse_file_size_gb(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(gb) if gb.is_finite() && gb > 0.0 => Ok(gb),
+        _ => Err(format!("'{}' is not a positive number of GB", value)),
+    }
 }
 
-impl ProcessingMode {
-    /// Check if this mode requires log file initialization
-    fn requires_logging(&self) -> bool {
-        matches!(self, 
-            ProcessingMode::Count { .. } | 
-            ProcessingMode::LegacyCount { .. } |
-            ProcessingMode::CreateJson { .. } |
-            ProcessingMode::Check { .. } | 
-            ProcessingMode::Compact { .. } |
-            ProcessingMode::Cascade { .. } |
-            ProcessingMode::SaveHistory { .. } |
-            ProcessingMode::ExportLists { .. })
+/// Lists per output file of the run: --lists-per-file, else --file-size-gb, else
+/// --memory-limit, else `default`
+fn lists_per_file(args: &Args, default: u64) -> u64 {
+    if let Some(n) = args.lists_per_file {
+        return n;
+    }
+    if let Some(gb) = args.file_size_gb {
+        let bytes = gb * (1u64 << 30) as f64;
+        return ((bytes as u64) / bytes_per_list_estimate(funny::io_helpers::output_encoding())).max(1);
     }
+    args.memory_limit.map(lists_per_file_for_memory).unwrap_or(default)
 }
 
 /// Validate size parameter for different modes
@@ -272,88 +1306,160 @@ fn validate_size(size: u8, mode_name: &str, min: u8, max: u8) -> Result<(), Stri
     }
 }
 
-/// Resolve paths for modes that use both input and output with fallback logic
-/// Resolve input/output paths based on mode requirements
-fn resolve_paths(
-    mode: &ProcessingMode,
-    input_arg: Option<&str>,
-    output_arg: Option<&str>
-) -> (String, String) {
-    match mode {
-        ProcessingMode::Count { .. } => {
-            // Count only uses input
-            (input_arg.unwrap_or(".").to_string(), String::new())
-        },
-        ProcessingMode::LegacyCount { .. } => {
-            (input_arg.unwrap_or(".").to_string(), String::new())
-        },
-        ProcessingMode::CreateJson { .. } => {
-            (input_arg.unwrap_or(".").to_string(), String::new())
-        },
-        ProcessingMode::Check { .. } => {
-            // Check only uses output
-            (String::new(), output_arg.unwrap_or(".").to_string())
-        },
-        ProcessingMode::Cascade { .. } => {
-            // Cascade uses input as root directory
-            let root = input_arg.unwrap_or(".").to_string();
-            (root, String::new())
-        },
-        ProcessingMode::SaveHistory { .. } => {
-            // SaveHistory uses input directory
-            (input_arg.unwrap_or(".").to_string(), String::new())
-        },
-        ProcessingMode::Size { .. } | ProcessingMode::Unitary { .. } | ProcessingMode::Compact { .. } => {
-            // These modes default output to input if not specified
-            let input = input_arg.unwrap_or(".").to_string();
-            let output = output_arg.unwrap_or(&input).to_string();
-            (input, output)
-        },
-        ProcessingMode::Default => {
-            // Default mode has hardcoded fallback
-            let path = output_arg.unwrap_or(r"T:\data\funny_set_exploration").to_string();
-            (path.clone(), path)
-        }
-    }
-}
-
-/// Handle force recount if enabled
-fn handle_force_recount(
-    enabled: bool,
-    directory: &str,
-    target_size: u8
-    , keep_state: bool
-) -> Result<(), String> {
-    if !enabled {
-        return Ok(());
-    }
-    
-    use crate::list_of_nsl::count_size_files;
-    
-    test_print(&format!("\nFORCE MODE: Regenerating count file for size {}...", target_size));
-    count_size_files(directory, target_size, true, keep_state)
-        .map_err(|e| format!("Error regenerating count file: {}", e))?;
-    test_print("Count file regenerated successfully\n");
-    Ok(())
-}
-
-/// Print directories with consistent formatting
-fn print_directories(input: &str, output: &str) {
-    if !input.is_empty() {
-        test_print(&format!("Input directory: {}", input));
-    }
-    if !output.is_empty() {
-        test_print(&format!("Output directory: {}", output));
-    }
+/// RNG seed of the random modes: --seed, else from the clock (the seed used is always printed)
+fn rng_seed(args: &Args) -> u64 {
+    args.seed.unwrap_or_else(|| chrono::Local::now().timestamp_nanos_opt().unwrap_or(0) as u64)
 }
 
 /// Build unified configuration from parsed arguments
 fn build_config(args: &Args, max_per_file: u64) -> Result<ProcessingConfig, String> {
     // Determine processing mode from arguments
-    let mode = if let Some(starting_input_size) = args.cascade {
-        validate_size(starting_input_size, "Cascade", 12, 19)?;
-        let root_directory = args.input_path.clone().unwrap_or_else(|| ".".to_string());
-        ProcessingMode::Cascade { starting_input_size, root_directory }
+    let mode = if let Some(verify_size) = args.verify {
+        validate_size(verify_size, "Verify", 3, 20)?;
+        ProcessingMode::Verify { size: verify_size }
+    } else if let Some(report_size) = args.final_report {
+        validate_size(report_size, "Final-report", 3, 20)?;
+        ProcessingMode::FinalReport { size: report_size }
+    } else if let Some(iterations) = args.random_walk {
+        ProcessingMode::RandomWalk { iterations, rng_seed: rng_seed(args) }
+    } else if let Some(values) = &args.filter_target {
+        let (target, size) = (values[0], values[1]);
+        validate_size(size, "Filter-target", 3, 19)?;
+        if target <= size || target > 20 {
+            return Err(format!("Filter-target: TARGET must be between {} and 20 (got {})", size + 1, target));
+        }
+        ProcessingMode::FilterTarget { target, size }
+    } else if let Some(stats_size) = args.stats {
+        validate_size(stats_size, "Stats", 3, 20)?;
+        ProcessingMode::Stats { size: stats_size }
+    } else if let Some(values) = &args.extract {
+        let size = u8::try_from(values[0]).map_err(|_| format!("Extract: invalid size {}", values[0]))?;
+        validate_size(size, "Extract", 3, 20)?;
+        let batch = u32::try_from(values[1]).map_err(|_| format!("Extract: invalid batch {}", values[1]))?;
+        ProcessingMode::Extract { size, batch, index: values[2] }
+    } else if let Some(values) = &args.sample {
+        let size = u8::try_from(values[0]).map_err(|_| format!("Sample: invalid size {}", values[0]))?;
+        validate_size(size, "Sample", 3, 20)?;
+        ProcessingMode::Sample { size, nb_lists: values[1], rng_seed: rng_seed(args) }
+    } else if let Some(values) = &args.split {
+        let size = u8::try_from(values[0]).map_err(|_| format!("Split: invalid size {}", values[0]))?;
+        validate_size(size, "Split", 3, 20)?;
+        if values[1] == 0 {
+            return Err("Split: MAX_LISTS must be at least 1".to_string());
+        }
+        ProcessingMode::Split { size, max_lists: values[1] }
+    } else if args.migrate {
+        ProcessingMode::Migrate { delete_originals: args.delete_originals }
+    } else if let Some(size) = args.convert {
+        validate_size(size, "Convert", 3, 20)?;
+        let format = funny::convert::ConvertFormat::parse(args.to.as_deref().unwrap_or_default())?;
+        ProcessingMode::Convert { size, format }
+    } else if let Some(max_size) = args.benchmark {
+        validate_size(max_size, "Benchmark", 4, 8)?;
+        ProcessingMode::Benchmark { max_size }
+    } else if let Some(values) = &args.estimate {
+        let size = u8::try_from(values[0]).map_err(|_| format!("Estimate: invalid size {}", values[0]))?;
+        validate_size(size, "Estimate", 4, 20)?;
+        let batches = values.get(1).map_or(3, |&b| b as usize);
+        ProcessingMode::Estimate { size, batches }
+    } else if let Some(size) = args.prune {
+        validate_size(size, "Prune", 3, 19)?;
+        ProcessingMode::Prune { size, archive_dir: args.archive_dir.clone() }
+    } else if let Some(size) = args.repair {
+        validate_size(size, "Repair", 3, 20)?;
+        ProcessingMode::Repair { size }
+    } else if let Some(size) = args.diff {
+        validate_size(size, "Diff", 3, 20)?;
+        ProcessingMode::Diff { size }
+    } else if let Some(size) = args.archive {
+        validate_size(size, "Archive", 3, 20)?;
+        ProcessingMode::Archive { size }
+    } else if let Some(archive) = args.unarchive.clone() {
+        ProcessingMode::Unarchive { archive }
+    } else if let Some(size) = args.watch {
+        validate_size(size, "Watch", 3, 19)?;
+        ProcessingMode::Watch { size, interval_secs: args.watch_interval.max(1) }
+    } else if let Some(file) = args.inspect.clone() {
+        ProcessingMode::Inspect { file }
+    } else if let Some(values) = &args.top {
+        let size = u8::try_from(values[0]).map_err(|_| format!("Top: invalid size {}", values[0]))?;
+        validate_size(size, "Top", 3, 20)?;
+        let count = values.get(1).map_or(10, |&n| n.max(1) as usize);
+        ProcessingMode::Top { size, count, ranking: funny::top::TopRanking::parse(&args.rank_by)? }
+    } else if let Some(size) = args.normalize_filenames {
+        validate_size(size, "NormalizeFilenames", 3, 20)?;
+        ProcessingMode::NormalizeFilenames { size }
+    } else if let Some(values) = &args.validate_chain {
+        let (from_size, to_size) = (values[0], values[1]);
+        validate_size(from_size, "ValidateChain", 12, 19)?;
+        validate_size(to_size, "ValidateChain", from_size + 1, 20)?;
+        ProcessingMode::ValidateChain { from_size, to_size }
+    } else if let Some(values) = &args.export_cards {
+        let size = u8::try_from(values[0]).map_err(|_| format!("Export-cards: invalid size {}", values[0]))?;
+        validate_size(size, "Export-cards", 3, 20)?;
+        let batch = u32::try_from(values[1]).map_err(|_| format!("Export-cards: invalid batch {}", values[1]))?;
+        ProcessingMode::ExportCards { size, batch }
+    } else if let Some(values) = &args.gc {
+        let size = values.first().copied();
+        if let Some(size) = size {
+            validate_size(size, "Gc", 3, 20)?;
+        }
+        ProcessingMode::Gc { size, delete: args.force }
+    } else if let Some(size) = args.reencode {
+        validate_size(size, "Reencode", 3, 20)?;
+        ProcessingMode::Reencode { size }
+    } else if let Some(size) = args.checksum {
+        validate_size(size, "Checksum", 3, 20)?;
+        ProcessingMode::Checksum { size }
+    } else if let Some(size) = args.verify_manifest {
+        validate_size(size, "VerifyManifest", 3, 20)?;
+        ProcessingMode::VerifyManifest { size }
+    } else if let Some(size) = args.migrate_format {
+        validate_size(size, "MigrateFormat", 3, 20)?;
+        ProcessingMode::MigrateFormat { size }
+    } else if let Some(size) = args.upgrade_state {
+        validate_size(size, "UpgradeState", 3, 20)?;
+        ProcessingMode::UpgradeState { size }
+    } else if args.overview {
+        ProcessingMode::Overview
+    } else if let Some(ref restore_vec) = args.restore_state {
+        let size: u8 = restore_vec[0].parse()
+            .map_err(|_| format!("RestoreState: invalid size {}", restore_vec[0]))?;
+        validate_size(size, "RestoreState", 3, 20)?;
+        ProcessingMode::RestoreState { size, timestamp: restore_vec.get(1).cloned() }
+    } else if let Some(size) = args.merge_state {
+        validate_size(size, "MergeState", 3, 20)?;
+        let policy = funny::merge_state::MergePolicy::parse(&args.merge_policy)?;
+        ProcessingMode::MergeState { size, policy }
+    } else if let Some(size) = args.rebalance {
+        validate_size(size, "Rebalance", 3, 20)?;
+        ProcessingMode::Rebalance { size }
+    } else if let Some(values) = &args.scan {
+        let size = u8::try_from(values[0]).map_err(|_| format!("Scan: invalid size {}", values[0]))?;
+        validate_size(size, "Scan", 3, 20)?;
+        ProcessingMode::Scan { size, workers: values.get(1).map_or(0, |&n| n as usize) }
+    } else if args.count_all {
+        ProcessingMode::CountAll
+    } else if let Some(size) = args.history_diff {
+        validate_size(size, "History-diff", 3, 20)?;
+        ProcessingMode::HistoryDiff { size }
+    } else if let Some(starting_input_size) = args.cascade {
+        validate_size(starting_input_size, "Cascade", 3, 19)?;
+        let root_directory = args.input_path.as_deref().map(funny::storage::register_volumes).unwrap_or_else(|| ".".to_string());
+        let roots = funny::cascade_roots::CascadeRoots::new(&root_directory, &args.cascade_roots, &args.cascade_root, args.flat)?;
+        let stop_at = args.stop_at.unwrap_or(20);
+        validate_size(stop_at, "Cascade stop-at", starting_input_size + 1, 20)?;
+        for &skipped in &args.skip_sizes {
+            validate_size(skipped, "Cascade skipped", starting_input_size + 1, stop_at)?;
+        }
+        let retry = CascadeRetry { retries: args.cascade_retries, delay_secs: args.cascade_retry_delay,
+            skip_failed: args.cascade_skip_failed };
+        let step_flags = parse_cascade_steps(&args.cascade_step, StepFlags { force: args.force, keep_state: args.keep_state })?;
+        for &size in step_flags.keys() {
+            validate_size(size, "Cascade step", starting_input_size + 1, stop_at)?;
+        }
+        ProcessingMode::Cascade { starting_input_size, roots, stop_at, skip_sizes: args.skip_sizes.clone(), retry,
+            step_flags }
     } else if let Some(save_history_size) = args.save_history {
         validate_size(save_history_size, "SaveHistory", 3, 20)?;
         ProcessingMode::SaveHistory { size: save_history_size }
@@ -365,19 +1471,24 @@ fn build_config(args: &Args, max_per_file: u64) -> Result<ProcessingConfig, Stri
         } else {
             None
         };
-        ProcessingMode::Compact { size: compact_size, max_batch }
+        ProcessingMode::Compact { size: compact_size, max_batch, delete_originals: args.delete_originals }
+    } else if let Some(orbits_size) = args.orbits {
+        validate_size(orbits_size, "Orbits", 3, 20)?;
+        ProcessingMode::Orbits { size: orbits_size }
+    } else if let Some(ref filename) = args.export_lists {
+        ProcessingMode::ExportLists { filename: filename.clone() }
     } else if let Some(legacy_size) = args.legacy_count {
         validate_size(legacy_size, "Legacy-count", 3, 20)?;
         ProcessingMode::LegacyCount { size: legacy_size }
     } else if let Some(create_json_size) = args.create_json {
         validate_size(create_json_size, "Create-json", 3, 20)?;
-        ProcessingMode::CreateJson { size: create_json_size }
+        ProcessingMode::CreateJson { size: create_json_size, csv: args.format == "csv" }
     } else if let Some(check_size) = args.check {
         validate_size(check_size, "Check", 3, 20)?;
-        ProcessingMode::Check { size: check_size }
+        ProcessingMode::Check { size: check_size, deep: args.deep, fix: args.fix, against_input: args.against_input }
     } else if let Some(count_size) = args.count {
         validate_size(count_size, "Count", 3, 20)?;
-        ProcessingMode::Count { size: count_size }
+        ProcessingMode::Count { size: count_size, fast: args.fast, follow: args.follow.map(|minutes| minutes.max(1)) }
     } else if let Some(ref size_vec) = args.size {
         let size = size_vec[0] as u8;
         validate_size(size, "Size", 3, 20)?;
@@ -409,867 +1520,114 @@ fn build_config(args: &Args, max_per_file: u64) -> Result<ProcessingConfig, Stri
         ProcessingMode::Default
     };
 
-    // Resolve paths based on mode
-    // Compact mode must be in-place: disallow an explicit output path
-    if let ProcessingMode::Compact { .. } = mode {
-        if args.output_path.is_some() {
-            return Err("Compact mode is in-place only; do not provide -o/--output-path".to_string());
-        }
-    }
-
-    let (input_dir, output_dir) = resolve_paths(&mode, args.input_path.as_deref(), args.output_path.as_deref());
-
-    Ok(ProcessingConfig {
-        mode,
-        input_dir,
-        output_dir,
-        max_lists_per_file: max_per_file,
-        force_recount: args.force,
-        keep_state: args.keep_state,
-    })
-}
-
-/// Execute the appropriate mode based on configuration
-fn execute_mode(config: &ProcessingConfig) -> Result<String, String> {
-    use crate::list_of_nsl::{count_size_files, compact_size_files, check_size_files};
-    use std::path::Path;
-    use std::fs;
-    
-    match &config.mode {
-        ProcessingMode::Count { size } => {
-            // Banner is printed by count_size_files function
-            count_size_files(&config.input_dir, *size, config.force_recount, config.keep_state)
-                .map_err(|e| format!("Error during count: {}", e))?;
-            Ok("Count completed successfully".to_string())
-        },
-
-        ProcessingMode::LegacyCount { size } => {
-            use crate::file_info::GlobalFileState;
-            use std::collections::HashSet;
-            use std::io::BufRead;
-            
-            let input_base = &config.input_dir;
-            test_print(&format!("Legacy-count mode for size {:02}", size));
-            
-            // Step 1: Load from JSON first (authoritative format if available)
-            let mut state = GlobalFileState::from_sources(input_base, *size)
-                .unwrap_or_else(|_| {
-                    test_print("   ... No existing state found, starting fresh");
-                    GlobalFileState::new(input_base, *size)
-                });
-            
-            let initial_count = state.entries().len();
-            let mut seen_files: HashSet<String> = state.entries().keys()
-                .map(|(_, _, filename)| filename.clone())
-                .collect();
-            let mut processed_batches: HashSet<u32> = state.entries().values()
-                .map(|e| e.source_batch)
-                .collect();
-            
-            test_print(&format!("   ... Loaded {} files from {} source batches", 
-                initial_count, processed_batches.len()));
-            
-            // Step 2: Complement with intermediary count files
-            let mut files_added = 0;
-            let mut added_from_rkyv = 0;
-            let pattern = format!("nsl_{:02}_intermediate_count_from_{:02}_", size, size - 1);
-            let mut intermediary_files: Vec<(std::path::PathBuf, u32)> = Vec::new();
-            
-            for entry in fs::read_dir(input_base).map_err(|e| format!("Error reading directory: {}", e))? {
-                if let Ok(e) = entry {
-                    if let Some(name) = e.file_name().to_str() {
-                        if name.starts_with(&pattern) && name.ends_with(".txt") {
-                            if let Some(batch_str) = name.rsplit('_').next().and_then(|s| s.strip_suffix(".txt")) {
-                                if let Ok(batch) = batch_str.parse::<u32>() {
-                                    intermediary_files.push((e.path(), batch));
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-            
-            intermediary_files.sort_by_key(|(_, batch)| *batch);
-            let unprocessed: Vec<_> = intermediary_files.iter()
-                .filter(|(_, batch)| !processed_batches.contains(batch))
-                .collect();
-            
-            if !unprocessed.is_empty() {
-                test_print(&format!("   ... Found {} unprocessed intermediate count files", unprocessed.len()));
-                
-                for (path, batch) in unprocessed {
-                    if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                        let file = fs::File::open(path).map_err(|e| format!("Error opening {}: {}", name, e))?;
-                        let reader = std::io::BufReader::new(file);
-                        
-                        for line in reader.lines() {
-                            let line = line.map_err(|e| format!("Error reading line: {}", e))?;
-                            // Strip UTF-8 BOM if present
-                            let line_clean = line.strip_prefix('\u{FEFF}').unwrap_or(&line);
-                            let trimmed = line_clean.trim();
-                            
-                            if trimmed.starts_with("...") {
-                                // Parse: "...  8528436 lists in filename.rkyv"
-                                if let Some(rest) = trimmed.strip_prefix("...") {
-                                    let rest = rest.trim();
-                                    let parts: Vec<&str> = rest.split_whitespace().collect();
-                                    if parts.len() >= 4 && parts[1] == "lists" && parts[2] == "in" {
-                                        if let Ok(count) = parts[0].parse::<u64>() {
-                                            let filename = parts[3].to_string();
-                                            
-                                            if seen_files.contains(&filename) {
-                                                continue;
-                                            }
-                                            
-                                            // Parse batch numbers from filename
-                                            if let Some(to_pos) = filename.find("_to_") {
-                                                let before_to = &filename[..to_pos];
-                                                let after_raw = &filename[to_pos + 4..];
-                                                let after_to = after_raw
-                                                    .strip_suffix("_compacted.rkyv")
-                                                    .or_else(|| after_raw.strip_suffix(".rkyv"))
-                                                    .unwrap_or(after_raw);
-                                                
-                                                if let Some(src_pos) = before_to.rfind("_batch_") {
-                                                    if let Ok(src_batch) = before_to[src_pos + 7..].parse::<u32>() {
-                                                        if let Some(tgt_pos) = after_to.rfind("_batch_") {
-                                                            if let Ok(tgt_batch) = after_to[tgt_pos + 7..].parse::<u32>() {
-                                                                let is_compacted = filename.contains("_compacted.rkyv");
-                                                                state.register_file(&filename, src_batch, tgt_batch, count, is_compacted, None, None);
-                                                                seen_files.insert(filename);
-
-                                                                files_added += 1;
-                                                            }
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        
-                        processed_batches.insert(*batch);
-                    }
-                }
-                
-                test_print(&format!("   ... Added {} new files from intermediate counts", files_added));
-            }
-            
-            // Step 3: If --force, scan rkyv files directly to fill remaining gaps
-            if config.force_recount {
-                test_print("   ... FORCE mode: Scanning .rkyv files to fill gaps...");
-                
-                let mut rkyv_files: Vec<std::path::PathBuf> = Vec::new();
-                for entry in fs::read_dir(input_base).map_err(|e| format!("Error reading directory: {}", e))? {
-                    if let Ok(e) = entry {
-                        if let Some(name) = e.file_name().to_str() {
-                            if name.ends_with(".rkyv") && name.contains(&format!("_to_{:02}_", size)) {
-                                rkyv_files.push(e.path());
-                            }
-                        }
-                    }
-                }
-                
-                test_print(&format!("   ... Found {} total rkyv files in directory", rkyv_files.len()));
-                
-                // Filter to only files not already in state
-                let missing_files: Vec<_> = rkyv_files.iter()
-                    .filter(|p| {
-                        p.file_name()
-                            .and_then(|n| n.to_str())
-                            .map(|name| !seen_files.contains(name))
-                            .unwrap_or(false)
-                    })
-                    .collect();
-                
-                test_print(&format!("   ... {} files missing from state, need introspection", missing_files.len()));
-                
-                if missing_files.is_empty() {
-                    test_print("   ... All rkyv files already in state, nothing to introspect");
-                } else {
-                    let total_missing = missing_files.len();
-                    let mut processed = 0;
-                    for path in missing_files {
-                        if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                            processed += 1;
-                            test_print(&format!("   ... [{}/{}] Reading {}", processed, total_missing, name));
-                            
-                            // Parse batch numbers
-                            if let Some(to_pos) = name.find("_to_") {
-                                let before_to = &name[..to_pos];
-                                let after_raw = &name[to_pos + 4..];
-                                let after_to = after_raw
-                                    .strip_suffix("_compacted.rkyv")
-                                    .or_else(|| after_raw.strip_suffix(".rkyv"))
-                                    .unwrap_or(after_raw);
-                                
-                                if let Some(src_pos) = before_to.rfind("_batch_") {
-                                    if let Ok(src_batch) = before_to[src_pos + 7..].parse::<u32>() {
-                                        if let Some(tgt_pos) = after_to.rfind("_batch_") {
-                                            if let Ok(tgt_batch) = after_to[tgt_pos + 7..].parse::<u32>() {
-                                                // Count lists in rkyv file
-                                                use memmap2::Mmap;
-                                                use rkyv::check_archived_root;
-                                                use crate::no_set_list::NoSetListSerialized;
-                                                
-                                                if let Ok(file) = fs::File::open(&path) {
-                                                    if let Ok(mmap) = unsafe { Mmap::map(&file) } {
-                                                        if let Ok(arch) = check_archived_root::<Vec<NoSetListSerialized>>(&mmap[..]) {
-                                                            let count = arch.len() as u64;
-                                                            let is_compacted = name.contains("_compacted.rkyv");
-                                                            
-                                                            // Get file metadata
-                                                            let (file_size, mtime) = path.metadata()
-                                                                .ok()
-                                                                .map(|m| (
-                                                                    Some(m.len()),
-                                                                    m.modified().ok()
-                                                                        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-                                                                        .map(|d| d.as_secs() as i64)
-                                                                ))
-                                                                .unwrap_or((None, None));
-                                                            
-                                                            state.register_file(name, src_batch, tgt_batch, count, is_compacted, file_size, mtime);
-                                                            seen_files.insert(name.to_string());
-                                                            added_from_rkyv += 1;
-                                                            
-                                                            test_print(&format!("       {} lists counted, saving state...", count));
-                                                            state.flush().map_err(|e| format!("Error saving rkyv after {}: {}", name, e))?;
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-                
-                if added_from_rkyv > 0 {
-                    test_print(&format!("   ... Added {} files from direct rkyv scan", added_from_rkyv));
-                }
-            }
-            
-            // Only save if we actually added new data
-            let total_files_added = files_added + added_from_rkyv;
-            
-            if total_files_added > 0 {
-                test_print("   ... Saving updated state...");
-                state.flush().map_err(|e| format!("Error saving rkyv: {}", e))?;
-                state.export_human_readable().map_err(|e| format!("Error exporting JSON/TXT: {}", e))?;
-                
-                let rkyv_path = Path::new(input_base).join(format!("nsl_{:02}_global_info.rkyv", size));
-                let json_path = Path::new(input_base).join(format!("nsl_{:02}_global_info.json", size));
-                let txt_path = Path::new(input_base).join(format!("nsl_{:02}_global_info.txt", size));
-                
-                test_print(&format!("Wrote {}, {} and {}", rkyv_path.display(), json_path.display(), txt_path.display()));
-            } else {
-                test_print("   ... No changes detected, skipping file writes");
-            }
-            
-            test_print(&format!("Total: {} files from {} unique source batches", 
-                state.entries().len(), 
-                state.entries().values().map(|e| e.source_batch).collect::<HashSet<_>>().len()));
-            Ok("Legacy count completed successfully".to_string())
-        },
-        
-        ProcessingMode::CreateJson { size } => {
-            use crate::file_info::GlobalFileState;
-            
-            test_print(&format!("Creating human-readable JSON/TXT exports for size {:02}...", size));
-            
-            // Load state from rkyv (authoritative format)
-            let state = GlobalFileState::from_sources(&config.input_dir, *size)
-                .map_err(|e| format!("Error loading state: {}", e))?;
-            
-            test_print(&format!("   ... Loaded {} files from rkyv state", state.entries().len()));
-            
-            // Export to human-readable formats
-            state.export_human_readable()
-                .map_err(|e| format!("Error exporting JSON/TXT: {}", e))?;
-            
-            let json_path = Path::new(&config.input_dir).join(format!("nsl_{:02}_global_info.json", size));
-            let txt_path = Path::new(&config.input_dir).join(format!("nsl_{:02}_global_info.txt", size));
-            
-            test_print(&format!("Exported {} and {}", json_path.display(), txt_path.display()));
-            Ok("JSON/TXT export completed successfully".to_string())
-        },
-        
-        ProcessingMode::Check { size } => {
-            // Banner is printed by check_size_files function
-            check_size_files(&config.output_dir, *size)
-                .map_err(|e| format!("Error during check: {}", e))?;
-            Ok("Check completed successfully".to_string())
-        },
-        
-        ProcessingMode::Compact { size, max_batch } => {
-            // Banner is printed by compact_size_files function
-            compact_size_files(&config.input_dir, &config.output_dir, *size, config.max_lists_per_file, *max_batch)
-                .map_err(|e| format!("Error during compaction: {}", e))?;
-            Ok("Compaction completed successfully".to_string())
-        },
-        
-        ProcessingMode::Size { size, start_batch } => {
-            execute_size_mode(config, *size, *start_batch)
-        },
-        
-        ProcessingMode::Unitary { size, batch } => {
-            execute_unitary_mode(config, *size, *batch)
-        },
-        
-        ProcessingMode::Cascade { starting_input_size, root_directory } => {
-            execute_cascade_mode(*starting_input_size, root_directory, config.max_lists_per_file)
-        },
-        
-        ProcessingMode::SaveHistory { size } => {
-            execute_save_history_mode(&config.input_dir, *size)
-        },
-        
-        ProcessingMode::Default => {
-            execute_default_mode(config)
-        },
-    }
-}
-
-/// Execute size mode: process specific size, optionally restarting from a batch
-fn execute_size_mode(config: &ProcessingConfig, output_size: u8, start_batch: Option<u32>) -> Result<String, String> {
-    use crate::list_of_nsl::ListOfNSL;
-    use crate::file_info::GlobalFileState;
-    use crate::filenames::get_last_compacted_batch;
-    use crate::compaction::compact_size_files;
-    
-    if let Some(batch) = start_batch {
-        test_print(&format!("RESTART MODE: Resuming output size {} from input batch {}", output_size, batch));
-        handle_force_recount(config.force_recount, &config.output_dir, output_size, config.keep_state)?;
-    } else {
-        test_print(&format!("Target output size = {} cards", output_size));
+    if args.dry_run && !matches!(mode, ProcessingMode::Size { .. } | ProcessingMode::Compact { .. } |
+        ProcessingMode::Prune { .. } | ProcessingMode::Repair { .. } | ProcessingMode::Cascade { .. }) {
+        return Err("--dry-run is only honored by --size, --compact, --prune, --repair and --cascade".to_string());
     }
-    test_print(&format!("Batch size: {} entries/file (~1GB, compact)", config.max_lists_per_file.separated_string()));
-    print_directories(&config.input_dir, &config.output_dir);
-    test_print("\n======================\n");
-
-    let mut no_set_lists = ListOfNSL::with_paths(&config.input_dir, &config.output_dir);
-
-    // Handle size 3: create seed lists directly
-    if output_size == 3 {
-        test_print("Creating seed lists (size 3)...");
-        no_set_lists.create_seed_lists();
-        test_print("Seed lists created successfully.\n");
-        return Ok("Seed lists (size 3) created successfully".to_string());
+    if args.compress.is_some() && !matches!(mode, ProcessingMode::Size { .. } | ProcessingMode::Unitary { .. } |
+        ProcessingMode::Compact { .. } | ProcessingMode::Cascade { .. } | ProcessingMode::Watch { .. } |
+        ProcessingMode::Reencode { .. } | ProcessingMode::MigrateFormat { .. }) {
+        return Err("--compress is only honored by --size, --unitary, --compact, --cascade, --watch, --reencode and --migrate-format".to_string());
     }
-
-    // For size 4+, need to create seed lists first if starting from batch 0
-    if output_size == 4 && start_batch.is_none() {
-        test_print("Creating seed lists (size 3)...");
-        // Create seed lists with output to input directory (so they don't pollute output dir)
-        let mut seed_generator = ListOfNSL::with_paths(&config.input_dir, &config.input_dir);
-        seed_generator.create_seed_lists();
-        test_print("Seed lists created successfully.\n");
-    }
-
-    // Step 1: For sizes 13+, run compaction on input directory before processing
-    let source_size = output_size - 1;
-    if source_size >= 13 {
-        test_print(&format!("\n=== Pre-processing: Compacting input files (size {}) ===", source_size));
-        match compact_size_files(&config.input_dir, &config.input_dir, source_size, config.max_lists_per_file, None) {
-            Ok(_) => test_print("Input compaction completed successfully.\n"),
-            Err(e) => test_print(&format!("Warning: Input compaction encountered an issue: {}\n", e)),
+    if args.also_parquet {
+        if !matches!(mode, ProcessingMode::Size { .. } | ProcessingMode::Unitary { .. } |
+            ProcessingMode::Cascade { .. } | ProcessingMode::Watch { .. }) {
+            return Err("--also-parquet is only honored by --size, --unitary, --cascade and --watch".to_string());
         }
-    }
-
-    // Step 2: Determine processing range
-    // If --force is not set and source size >= 13, only process up to the last compacted batch
-    let max_input_batch = if !config.force_recount && source_size >= 13 {
-        match get_last_compacted_batch(&config.input_dir, source_size) {
-            Some(last_compacted) => {
-                test_print(&format!("Processing only compacted input files up to batch {:06} (use --force to process all files)", last_compacted));
-                Some(last_compacted)
-            }
-            None => {
-                test_print("Warning: No compacted input files found. Will process all available files.");
-                None
-            }
+        if !cfg!(feature = "parquet") {
+            return Err("--also-parquet needs a build with the `parquet` feature (cargo build --release --features parquet)".to_string());
         }
-    } else {
-        None
-    };
-
-    // Step 3: Process the requested size
-    let mut global_state = GlobalFileState::from_sources(&config.output_dir, output_size)
-        .map_err(|e| format!("Failed to load global state: {}", e))?;
-    
-    if let Some(batch) = start_batch {
-        test_print(&format!("Start processing from input batch {} to create no-set-lists of size {}:", batch, output_size));
-        
-        // If max_input_batch is set, we need to handle the range specially
-        if let Some(max_batch) = max_input_batch {
-            if batch <= max_batch {
-                // Process from start_batch up to max_batch
-                test_print(&format!("   ... processing batches {:06} to {:06} (compacted only)", batch, max_batch));
-                no_set_lists.process_batch_range(source_size, batch, max_batch, &config.max_lists_per_file, Some(&mut global_state));
-            } else {
-                test_print(&format!("Warning: Start batch {} is beyond last compacted batch {}. No processing needed.", batch, max_batch));
-            }
-        } else {
-            no_set_lists.process_from_batch(source_size, batch, &config.max_lists_per_file, Some(&mut global_state));
+    }
+    if funny::storage::StorageBackend::parse(&args.storage)? == funny::storage::StorageBackend::Sqlite {
+        if !matches!(mode, ProcessingMode::Size { .. } | ProcessingMode::Unitary { .. } | ProcessingMode::Count { .. } |
+            ProcessingMode::Check { .. } | ProcessingMode::Stats { .. } | ProcessingMode::Top { .. }) {
+            return Err("--storage sqlite is only honored by --size, --unitary, --count, --check, --stats and --top".to_string());
         }
-    } else {
-        test_print(&format!("Start processing files to create no-set-lists of size {}:", output_size));
-        
-        if let Some(max_batch) = max_input_batch {
-            // Process from 0 to max_batch
-            test_print(&format!("   ... processing batches 000000 to {:06} (compacted only)", max_batch));
-            no_set_lists.process_batch_range(source_size, 0, max_batch, &config.max_lists_per_file, Some(&mut global_state));
-        } else {
-            no_set_lists.process_all_files_of_current_size_n(source_size, &config.max_lists_per_file, Some(&mut global_state));
+        if args.fix {
+            return Err("--fix renames and counts plain files: not available with --storage sqlite".to_string());
         }
-    }
-    
-    test_print(&format!("\nCompleted size {}! Generated files: no-set-list_{:02}_batch_*.rkyv\n", output_size, output_size));
-    
-    // Step 4: For sizes 13+, run compaction on output directory after processing
-    if output_size >= 13 {
-        test_print(&format!("\n=== Post-processing: Compacting output files (size {}) ===", output_size));
-        match compact_size_files(&config.output_dir, &config.output_dir, output_size, config.max_lists_per_file, None) {
-            Ok(_) => {
-                test_print("Output compaction completed successfully.\n");
-                // Note: compact_size_files already exports human-readable files (JSON/TXT)
-            },
-            Err(e) => test_print(&format!("Warning: Output compaction encountered an issue: {}\n", e)),
+        if args.compress.is_some() {
+            return Err("--storage sqlite stores uncompressed frames: drop --compress".to_string());
         }
-    } else {
-        // For sizes < 13, no compaction runs, so we need to export human-readable files here
-        test_print(&format!("\nExporting global state files for size {}...", output_size));
-        match global_state.export_human_readable() {
-            Ok(_) => test_print(&format!("Exported: {}/nsl_{:02}_global_info.json and .txt\n", config.output_dir, output_size)),
-            Err(e) => test_print(&format!("Warning: Failed to export JSON/TXT: {}\n", e)),
+        if !cfg!(feature = "sqlite") {
+            return Err("--storage sqlite needs a build with the `sqlite` feature (cargo build --release --features sqlite)".to_string());
         }
     }
-    
-    // Save history at the end
-    test_print(&format!("\nSaving historical state for size {}...", output_size));
-    let history_config = ProcessingConfig {
-        mode: ProcessingMode::SaveHistory { size: output_size },
-        input_dir: config.output_dir.clone(),
-        output_dir: String::new(),
-        max_lists_per_file: config.max_lists_per_file,
-        force_recount: false,
-        keep_state: false,
-    };
-    match execute_mode(&history_config) {
-        Ok(_) => test_print("Historical state saved successfully.\n"),
-        Err(e) => test_print(&format!("Warning: Failed to save history: {}\n", e)),
-    }
-    
-    if start_batch.is_some() {
-        Ok(format!("Size {} processing completed (restarted from batch {})", output_size, start_batch.unwrap()))
-    } else {
-        Ok(format!("Size {} processing completed", output_size))
-    }
-}
 
-/// Execute unitary mode: process a single input batch
-fn execute_unitary_mode(config: &ProcessingConfig, unitary_size: u8, unitary_batch: u32) -> Result<String, String> {
-    use crate::list_of_nsl::ListOfNSL;
-    use crate::file_info::GlobalFileState;
-    
-    test_print(&format!("UNITARY MODE: Processing input size {} batch {}", unitary_size, unitary_batch));
-    test_print(&format!("Output: size {} files", unitary_size + 1));
-    test_print(&format!("Batch size: {} entries/file (~1GB, compact)", config.max_lists_per_file.separated_string()));
-    print_directories(&config.input_dir, &config.output_dir);
-    
-    handle_force_recount(config.force_recount, &config.output_dir, unitary_size + 1, config.keep_state)?;
-    test_print("\n======================\n");
+    if funny::file_info::StateBackend::parse(&args.state_backend)? == funny::file_info::StateBackend::Sqlite
+        && !cfg!(feature = "sqlite") {
+        return Err("--state-backend sqlite needs a build with the `sqlite` feature (cargo build --release --features sqlite)".to_string());
+    }
 
-    let mut no_set_lists = ListOfNSL::with_paths(&config.input_dir, &config.output_dir);
-    let target_size = unitary_size + 1;
-    let mut global_state = GlobalFileState::from_sources(&config.output_dir, target_size)
-        .map_err(|e| format!("Failed to load global state: {}", e))?;
-    
-    test_print(&format!("Processing input size {} batch {}:", unitary_size, unitary_batch));
-    no_set_lists.process_single_batch(unitary_size, unitary_batch, &config.max_lists_per_file, Some(&mut global_state));
-    
-    // Export human-readable state files
-    test_print(&format!("\nExporting global state files for size {}...", target_size));
-    match global_state.export_human_readable() {
-        Ok(_) => test_print(&format!("Exported: {}/nsl_{:02}_global_info.json and .txt\n", config.output_dir, target_size)),
-        Err(e) => test_print(&format!("Warning: Failed to export JSON/TXT: {}\n", e)),
+    // Resolve paths based on mode
+    // --delete-originals only applies to migration and out-of-place compaction
+    if args.delete_originals && !args.migrate && args.compact.is_none() {
+        return Err("--delete-originals needs --migrate or --compact -o DIR".to_string());
     }
-    
-    // Save history at the end
-    test_print(&format!("\nSaving historical state for size {}...", target_size));
-    let history_config = ProcessingConfig {
-        mode: ProcessingMode::SaveHistory { size: target_size },
-        input_dir: config.output_dir.clone(),
-        output_dir: String::new(),
-        max_lists_per_file: config.max_lists_per_file,
-        force_recount: false,
-        keep_state: false,
-    };
-    match execute_mode(&history_config) {
-        Ok(_) => test_print("Historical state saved successfully.\n"),
-        Err(e) => test_print(&format!("Warning: Failed to save history: {}\n", e)),
+    if let ProcessingMode::Compact { delete_originals: true, .. } = mode
+        && args.output_path.is_none() {
+        return Err("--delete-originals needs an output directory with --compact (-o DIR: out-of-place compaction)".to_string());
+    }
+    if args.against_input && args.input_path.is_none() {
+        return Err("--against-input compares with the previous size: give its directory with -i DIR".to_string());
+    }
+    if args.dedupe && args.output_path.as_ref().is_some_and(|o| args.input_path.as_ref() != Some(o)) {
+        return Err("--dedupe only applies to in-place compaction (the out-of-place one checks the files against their sources)".to_string());
     }
-    
-    Ok(format!("Unitary processing completed for size {} batch {}", unitary_size, unitary_batch))
-}
 
-/// Get directory path for a given size in cascade mode
-/// Returns (input_dir, output_dir) for the given output size
-fn get_cascade_directories(root_directory: &str, input_size: u8) -> (String, String) {
-    use std::path::Path;
-    
-    let output_size = input_size + 1;
-    
-    // Input directory pattern
-    let input_dir = if input_size == 12 {
-        // Size 12 comes from 11_to_12
-        Path::new(root_directory).join("11_to_12")
-    } else if input_size == 13 {
-        // Size 13 comes from 12_to_13c (12 doesn't have 'c')
-        Path::new(root_directory).join("12_to_13c")
-    } else {
-        // Size 14+ comes from {size-1}c_to_{size}c
-        Path::new(root_directory).join(format!("{}c_to_{}c", input_size - 1, input_size))
-    };
-    
-    // Output directory pattern
-    let output_dir = if output_size == 13 {
-        // Size 13 goes to 12_to_13c
-        Path::new(root_directory).join("12_to_13c")
-    } else {
-        // Size 14+ goes to {size-1}c_to_{size}c
-        Path::new(root_directory).join(format!("{}c_to_{}c", output_size - 1, output_size))
-    };
-    
-    (
-        input_dir.to_string_lossy().to_string(),
-        output_dir.to_string_lossy().to_string()
-    )
-}
+    // Directories spread over several volumes are named by their first root from here on
+    let input_arg = args.input_path.as_deref().map(funny::storage::register_volumes);
+    let output_arg = args.output_path.as_deref().map(funny::storage::register_volumes);
+    let multi_volume = [&args.input_path, &args.output_path].iter().any(|p| p.as_ref().is_some_and(|p| p.contains(';')));
+    if multi_volume && funny::storage::storage_backend() != funny::storage::StorageBackend::Files {
+        return Err("Multi-volume directories (-i/-o with ';') need --storage files".to_string());
+    }
+    let (input_dir, output_dir) = funny::modes::resolve_paths(&mode, input_arg.as_deref(), output_arg.as_deref());
 
-/// Find the highest source batch number in the output directory
-/// Returns None if no files found, or the max source batch number
-fn find_max_source_batch(output_dir: &str, output_size: u8) -> Option<u32> {
-    use std::fs;
-    
-    let entries = match fs::read_dir(output_dir) {
-        Ok(e) => e,
-        Err(_) => return None,
+    let shard = match &args.shard {
+        Some(text) => Some(funny::filenames::Shard::parse(text)?),
+        None => None,
     };
-    
-    let pattern = format!("_to_{:02}_batch_", output_size);
-    let mut max_source_batch: Option<u32> = None;
-    
-    for entry in entries.flatten() {
-        if let Some(name) = entry.file_name().to_str() {
-            if name.starts_with("nsl_") && name.contains(&pattern) && name.ends_with(".rkyv") {
-                // Parse source batch from filename: nsl_{size}_batch_{source_batch}_to_...
-                if let Some(to_pos) = name.find("_to_") {
-                    let before_to = &name[..to_pos];
-                    if let Some(batch_pos) = before_to.rfind("_batch_") {
-                        let batch_str = &before_to[batch_pos + 7..];
-                        if let Ok(source_batch) = batch_str.parse::<u32>() {
-                            max_source_batch = Some(
-                                max_source_batch.map_or(source_batch, |current| current.max(source_batch))
-                            );
-                        }
-                    }
-                }
-            }
-        }
-    }
-    
-    max_source_batch
-}
 
-/// Execute save-history mode: merge current state with historical state
-fn execute_save_history_mode(input_dir: &str, size: u8) -> Result<String, String> {
-    use crate::file_info::GlobalFileState;
-    use std::path::Path;
-    
-    test_print(&format!("\n================================================================="));
-    test_print(&format!("SAVE HISTORY MODE - Size {}", size));
-    test_print(&format!("Directory: {}", input_dir));
-    test_print(&format!("=================================================================\n"));
-    
-    // Load current state
-    test_print("Loading current state...");
-    let current_state = GlobalFileState::from_sources(input_dir, size)
-        .map_err(|e| format!("Failed to load current state: {}", e))?;
-    let current_count = current_state.entries().len();
-    test_print(&format!("   Current state: {} entries", current_count));
-    
-    // Try to load existing history
-    let history_rkyv_path = Path::new(input_dir).join(format!("nsl_{:02}_global_info_history.rkyv", size));
-    let history_json_path = Path::new(input_dir).join(format!("nsl_{:02}_global_info_history.json", size));
-    
-    let mut historical_state = if history_rkyv_path.exists() {
-        test_print("Loading existing history from rkyv...");
-        GlobalFileState::from_history_file(input_dir, size, "rkyv")
-            .map_err(|e| format!("Failed to load history from rkyv: {}", e))?
-    } else if history_json_path.exists() {
-        test_print("Loading existing history from JSON...");
-        GlobalFileState::from_history_file(input_dir, size, "json")
-            .map_err(|e| format!("Failed to load history from JSON: {}", e))?
-    } else {
-        test_print("No existing history found, creating new historical state...");
-        GlobalFileState::new(input_dir, size)
+    let expected_counts = match &args.validate_counts {
+        Some(user_file) => Some(funny::validate_counts::load_expected_counts(user_file.as_deref())
+            .map_err(|e| format!("Cannot load expected counts: {}", e))?),
+        None => None,
     };
-    
-    let initial_history_count = historical_state.entries().len();
-    test_print(&format!("   Historical state: {} entries", initial_history_count));
-    
-    // Remove entries from history that were removed from current state
-    let removed_entries = current_state.removed_entries();
-    if !removed_entries.is_empty() {
-        test_print(&format!("\nRemoving {} consumed files from history...", removed_entries.len()));
-        let mut removed_count = 0;
-        for (src, tgt, filename) in removed_entries.iter() {
-            if historical_state.has_entry(filename, *src, *tgt) {
-                historical_state.remove_file(filename, *src, *tgt);
-                removed_count += 1;
-            }
-        }
-        test_print(&format!("   Removed: {} entries from history", removed_count));
-    }
-    
-    // Merge current state into historical state
-    test_print("\nMerging current state into history...");
-    let mut added_count = 0;
-    let mut updated_count = 0;
-    
-    for ((src, tgt, filename), info) in current_state.entries().iter() {
-        if historical_state.has_entry(filename, *src, *tgt) {
-            // Entry exists, update it (in case counts changed)
-            historical_state.update_entry(
-                filename,
-                *src,
-                *tgt,
-                info.nb_lists_in_file,
-                info.compacted,
-                info.file_size_bytes,
-                info.modified_timestamp,
-            );
-            updated_count += 1;
-        } else {
-            // New entry, add it
-            historical_state.register_file(
-                filename,
-                *src,
-                *tgt,
-                info.nb_lists_in_file,
-                info.compacted,
-                info.file_size_bytes,
-                info.modified_timestamp,
-            );
-            added_count += 1;
-        }
-    }
-    
-    let final_history_count = historical_state.entries().len();
-    let removed_count = removed_entries.len();
-    
-    test_print(&format!("   Added: {} new entries", added_count));
-    test_print(&format!("   Updated: {} existing entries", updated_count));
-    if removed_count > 0 {
-        test_print(&format!("   Removed: {} consumed entries", removed_count));
+
+    for target in &args.notify {
+        funny::notify::parse_target(target)?;
     }
-    test_print(&format!("   Total historical entries: {}", final_history_count));
-    
-    // Save historical state as triplet
-    test_print("\nSaving historical state...");
-    historical_state.flush_as_history()
-        .map_err(|e| format!("Failed to save historical state: {}", e))?;
-    historical_state.export_human_readable_as_history()
-        .map_err(|e| format!("Failed to export historical JSON/TXT: {}", e))?;
-    
-    test_print(&format!("   Saved: {}", history_rkyv_path.display()));
-    test_print(&format!("   Saved: {}", history_json_path.display()));
-    test_print(&format!("   Saved: {}", Path::new(input_dir).join(format!("nsl_{:02}_global_info_history.txt", size)).display()));
-    
-    test_print(&format!("\n================================================================="));
-    test_print(&format!("SAVE HISTORY COMPLETED"));
-    test_print(&format!("=================================================================\n"));
-    
-    let removed_count = removed_entries.len();
-    if removed_count > 0 {
-        Ok(format!("History saved: {} total entries ({} added, {} updated, {} removed)", 
-            final_history_count, added_count, updated_count, removed_count))
-    } else {
-        Ok(format!("History saved: {} total entries ({} added, {} updated)", 
-            final_history_count, added_count, updated_count))
+    if !args.notify.is_empty() && !cfg!(feature = "notify") {
+        return Err("--notify needs a build with the `notify` feature (cargo build --release --features notify)".to_string());
     }
-}
 
-/// Execute cascade mode: process all sizes starting from a given input size
-fn execute_cascade_mode(starting_input_size: u8, root_directory: &str, max_lists_per_file: u64) -> Result<String, String> {
-    use std::path::Path;
-    
-    test_print(&format!("\n================================================================="));
-    test_print(&format!("CASCADE MODE - Starting from input size {}", starting_input_size));
-    test_print(&format!("Root directory: {}", root_directory));
-    test_print(&format!("=================================================================\n"));
-    
-    let mut total_sizes_processed = 0;
-    let mut total_commands_executed = 0;
-    
-    // Process each size from starting_input_size to 19 (output sizes 13 to 20)
-    for input_size in starting_input_size..=19 {
-        let output_size = input_size + 1;
-        
-        test_print(&format!("\n--- Step {}: Processing size {} (from input size {}) ---",
-            input_size - starting_input_size + 1, output_size, input_size));
-        
-        // Get directories
-        let (input_dir, output_dir) = get_cascade_directories(root_directory, input_size);
-        
-        // Check if input directory exists
-        if !Path::new(&input_dir).exists() {
-            test_print(&format!("   Input directory does not exist: {}", input_dir));
-            test_print(&format!("   Skipping size {}", output_size));
-            continue;
-        }
-        
-        // Check if output directory exists, create if not
-        if !Path::new(&output_dir).exists() {
-            test_print(&format!("   Output directory does not exist, creating: {}", output_dir));
-            std::fs::create_dir_all(&output_dir)
-                .map_err(|e| format!("Failed to create output directory {}: {}", output_dir, e))?;
-        }
-        
-        // Find the last processed batch
-        let last_processed = find_max_source_batch(&output_dir, output_size);
-        let next_batch = match last_processed {
-            Some(batch) => batch + 1,
-            None => 0,
-        };
-        
-        test_print(&format!("   Last processed input batch: {}",
-            last_processed.map_or("none".to_string(), |b| format!("{:06}", b))));
-        test_print(&format!("   Next batch to process: {:06}", next_batch));
-        test_print(&format!("   Input directory:  {}", input_dir));
-        test_print(&format!("   Output directory: {}", output_dir));
-        
-        test_print(&format!("\n   Processing: --size {} {} -i \"{}\" -o \"{}\"\n",
-            output_size, next_batch, input_dir, output_dir));
-        
-        // Build configuration for this size (call internal functions directly)
-        let size_config = ProcessingConfig {
-            mode: ProcessingMode::Size { 
-                size: output_size, 
-                start_batch: if next_batch > 0 { Some(next_batch) } else { None }
-            },
-            input_dir: input_dir.clone(),
-            output_dir: output_dir.clone(),
-            max_lists_per_file,
-            force_recount: false,
-            keep_state: false,
-        };
-        
-        // Execute the size mode directly (same as if user entered the command)
-        match execute_mode(&size_config) {
-            Ok(_) => {
-                test_print(&format!("\n   ✓ Size {} processing completed successfully\n", output_size));
-                
-                // Save history for this size
-                test_print(&format!("   Saving historical state for size {}...", output_size));
-                let history_config = ProcessingConfig {
-                    mode: ProcessingMode::SaveHistory { size: output_size },
-                    input_dir: output_dir.clone(),
-                    output_dir: String::new(),
-                    max_lists_per_file,
-                    force_recount: false,
-                    keep_state: false,
-                };
-                match execute_mode(&history_config) {
-                    Ok(_) => test_print("   Historical state saved.\n"),
-                    Err(e) => test_print(&format!("   Warning: Failed to save history: {}\n", e)),
-                }
-                
-                total_sizes_processed += 1;
-            }
-            Err(e) => {
-                test_print(&format!("\n   ✗ Size {} processing failed: {}\n", output_size, e));
-                test_print(&format!("   Stopping cascade at this point.\n"));
-                break;
-            }
-        }
-        
-        total_commands_executed += 1;
-    }
-    
-    test_print(&format!("\n================================================================="));
-    test_print(&format!("CASCADE MODE COMPLETED"));
-    test_print(&format!("Sizes processed: {}", total_sizes_processed));
-    test_print(&format!("Commands executed: {}", total_commands_executed));
-    test_print(&format!("=================================================================\n"));
-    
-    Ok(format!("Cascade mode completed: {} sizes processed", total_sizes_processed))
+    Ok(ProcessingConfig {
+        mode,
+        input_dir,
+        output_dir,
+        max_lists_per_file: lists_per_file(args, max_per_file),
+        memory_limit_gb: args.memory_limit,
+        force_recount: args.force,
+        keep_state: args.keep_state,
+        strong_prune: args.strong_prune,
+        isomorph_cache: args.isomorph_cache,
+        expected_counts,
+        shard,
+        dry_run: args.dry_run,
+    })
 }
 
-/// Execute default mode: process the whole pipeline (seeds + sizes 4 to 20)
-fn execute_default_mode(config: &ProcessingConfig) -> Result<String, String> {
-    use crate::list_of_nsl::ListOfNSL;
-    use crate::file_info::GlobalFileState;
-    
-    test_print("   - will create          58.896 no-set-lists with  3 cards");
-    test_print("   - will create       1.004.589 no-set-lists with  4 cards");
-    test_print("   - will create      13.394.538 no-set-lists with  5 cards");
-    test_print("   - will create     141.370.218 no-set-lists with  6 cards");
-    test_print("   - will create   1.180.345.041 no-set-lists with  7 cards");
-    test_print("   - will create   7.920.450.378 no-set-lists with  8 cards");
-    test_print("   - will create  43.126.538.805 no-set-lists with  9 cards");
-    test_print("   - will create 193.375.848.191 no-set-lists with 10 cards");
-    test_print("   - will create  __.___.___.___ no-set-lists with 11 cards");
-    test_print("   - will create  __.___.___.___ no-set-lists with 12 cards");
-    test_print("   - will create  __.___.___.___ no-set-lists with 13 cards");
-    test_print("   - will create  __.___.___.___ no-set-lists with 14 cards");
-    test_print("   - will create  __.___.___.___ no-set-lists with 15 cards");
-    test_print("   - will create  __.___.___.___ no-set-lists with 16 cards");
-    test_print("   - will create  __.___.___.___ no-set-lists with 17 cards");
-    test_print("   - will create  __.___.___.___ no-set-lists with 18 cards");
-    test_print("   - will create  __.___.___.___ no-set-lists with 19 cards");
-    test_print("   - will create  __.___.___.___ no-set-lists with 20 cards");
-    test_print("\n======================\n");
-
-    let mut no_set_lists = ListOfNSL::with_path(&config.input_dir);
-
-    // Create all seed lists
-    test_print("Creating seed lists...");
-    no_set_lists.create_seed_lists();
-
-    // Expand from seed_lists to size 4, 5, 6...
-    for size in 3..19 {
-        let target_size = size + 1;
-        let mut global_state = GlobalFileState::from_sources(&config.output_dir, target_size)
-            .map_err(|e| format!("Failed to load global state: {}", e))?;
-        test_print(&format!("\nStart processing files to create no-set-lists of size {}:", target_size));
-        no_set_lists.process_all_files_of_current_size_n(size, &config.max_lists_per_file, Some(&mut global_state));
-        
-        // Export human-readable state files for this size
-        test_print(&format!("Exporting global state files for size {}...", target_size));
-        match global_state.export_human_readable() {
-            Ok(_) => test_print(&format!("Exported: {}/nsl_{:02}_global_info.json and .txt", config.output_dir, target_size)),
-            Err(e) => test_print(&format!("Warning: Failed to export JSON/TXT: {}", e)),
-        }
+/// Prints the JSON results of the modes on stdout (the log goes to stderr), so they can be piped
+struct ResultPrinter;
+
+impl funny::events::Observer for ResultPrinter {
+    fn on_result(&self, event: &funny::events::ResultReady) {
+        println!("{}", event.json);
     }
-    
-    Ok("Default pipeline completed (sizes 3-20)".to_string())
 }
 
 fn main() {
-    /// Max number of n-list saved per file for v0.4.0
-    /// - Each NoSetList: 792 bytes during compute (stack)
-    /// - Each NoSetListSerialized: ~100 bytes after conversion (heap)
-    /// - 20M entries × 100 bytes = ~2GB per file after serialization
-    /// - Peak RAM during save: ~10.5GB (vec + archive + overhead)
-    const MAX_NLISTS_PER_FILE: u64 = 10_000_000;
-
     // Parse command-line arguments
     let args = Args::parse();
 
@@ -1279,8 +1637,46 @@ fn main() {
     test_print_off();
     test_print_on();
 
+
+    // Select the encoding of the list files written
+    match args.encoding.as_str() {
+        "delta" => funny::io_helpers::set_output_encoding(funny::io_helpers::ListEncoding::Delta),
+        "packed" => funny::io_helpers::set_output_encoding(funny::io_helpers::ListEncoding::Packed),
+        _ => {}
+    }
+    funny::io_helpers::set_output_compression(args.compress);
+    funny::dataset::set_also_parquet(args.also_parquet);
+    if let Ok(backend) = funny::storage::StorageBackend::parse(&args.storage) {
+        funny::storage::set_storage_backend(backend);
+    }
+    if let Ok(backend) = funny::file_info::StateBackend::parse(&args.state_backend) {
+        funny::file_info::set_state_backend(backend);
+    }
+    funny::file_info::set_flush_every(args.flush_every);
+    funny::file_info::set_keep_backups(args.keep_backups);
+    funny::file_info::set_fix_state_on_load(args.fix_state_on_load);
+    funny::compaction::set_compact_size(args.compact_size);
+    funny::compaction::set_compact_min_files(args.compact_min_files);
+    funny::compaction::set_sources_sidecar(args.sources_sidecar);
+    funny::compaction::set_background_compact(args.background_compact);
+    funny::compaction::set_dedupe(args.dedupe);
+    funny::compaction::set_min_free_space(args.compact_min_free_gb, args.compact_low_space == "pause");
+    funny::compaction::set_delete_early(args.delete_early);
+    if let Ok(action) = funny::disk_guard::LowSpaceAction::parse(&args.cascade_low_space) {
+        funny::disk_guard::set_min_free_space(args.cascade_min_free_gb, action);
+    }
+    if let Ok(placement) = funny::storage::Placement::parse(&args.placement) {
+        funny::storage::set_placement(placement);
+    }
+    funny::io_helpers::set_io_retry(args.io_retries, args.io_backoff_ms);
+    if let Ok(engine) = funny::engine::EngineKind::parse(&args.engine) {
+        funny::engine::set_engine(engine);
+    }
+    funny::events::add_observer(Box::new(funny::events::ConsoleObserver));
+    funny::events::add_observer(Box::new(ResultPrinter));
+
     // Build unified configuration
-    let config = match build_config(&args, MAX_NLISTS_PER_FILE) {
+    let config = match build_config(&args, funny::modes::MAX_NLISTS_PER_FILE) {
         Ok(cfg) => cfg,
         Err(e) => {
             eprintln!("Error: {}", e);
@@ -1288,22 +1684,42 @@ fn main() {
         }
     };
 
+    // Setup the input batch cache (within the memory limit, if any)
+    let cache_batches = match config.memory_limit_gb {
+        Some(gb) => {
+            let affordable = batch_cache_for_memory(args.cache_batches, gb, config.max_lists_per_file);
+            if affordable < args.cache_batches {
+                eprintln!("Warning: --cache-batches lowered from {} to {} to fit the {} GB memory limit",
+                    args.cache_batches, affordable, gb);
+            }
+            affordable
+        }
+        None => args.cache_batches,
+    };
+    funny::io_helpers::set_batch_cache_capacity(cache_batches);
+
+    funny::notify::configure(&args.notify, &config.mode.name());
+
     // Initialize logging for applicable modes
     if config.mode.requires_logging() {
         init_log_file();
     }
 
-    banner(concat!("Funny Set Exploration [0.4.14]"));
+    banner("Funny Set Exploration [0.4.14]");
     
     // Execute mode and handle result
-    match execute_mode(&config) {
-        Ok(message) => {
-            test_print(&format!("\n{}!", message));
-            std::process::exit(0);
-        }
-        Err(e) => {
-            eprintln!("{}", e);
-            std::process::exit(1);
-        }
+    let start_time = std::time::Instant::now();
+    let result = execute_mode(&config);
+    funny::file_info::release_state_locks();
+    match &result {
+        Ok(message) => test_print(&format!("\n{}!", message)),
+        Err(e) => eprintln!("{}", e),
+    }
+    let summary = funny::findings::RunSummary::new(&config.mode.name(), &result, start_time.elapsed().as_secs_f64());
+    summary.print();
+    if matches!(config.mode, ProcessingMode::Cascade { .. } | ProcessingMode::Size { .. } | ProcessingMode::Compact { .. }) {
+        let event = if summary.outcome == funny::findings::RunOutcome::Failed { "failed" } else { "completed" };
+        funny::notify::notify(event, &summary.message, Some(&summary));
     }
+    std::process::exit(summary.exit_code);
 }
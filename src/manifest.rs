@@ -0,0 +1,239 @@
+//! Manifest module: per-size manifest of the list files of a directory
+//!
+//! Moving terabytes of list files between machines needs a way to prove the copy
+//! is complete and intact. nsl_{size:02}_manifest.json records, for every list file
+//! of the size, its byte size, SHA-256 and list count; --verify-manifest re-hashes
+//! the files on the other side against it.
+//!
+//! Key features:
+//! - Written after every size and unitary run (so also by cascade and watch);
+//!   files whose byte size and mtime did not change keep their hash, so only the
+//!   new or rewritten files are hashed again
+//! - List counts taken from the global state when recorded, read from the file otherwise
+//! - Verification re-hashes every file and reports hash or size mismatches, files
+//!   missing from the directory and list files the manifest does not know
+//! - Written as .tmp, then renamed
+//!
+//! Used by size and unitary runs, and by --verify-manifest mode
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use separator::Separatable;
+use serde::{Deserialize, Serialize};
+
+use crate::file_info::GlobalFileState;
+use crate::utils::*;
+
+/// One list file of a manifest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestFile {
+    pub filename: String,
+    pub bytes: u64,
+    pub nb_lists: u64,
+    pub sha256: String,
+    #[serde(default)]
+    pub modified: Option<i64>, // unix seconds when hashed (unchanged files are not hashed again)
+}
+
+/// Manifest of the list files of one size
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SizeManifest {
+    pub size: u8,
+    pub created_at: String,
+    pub funny_version: String,
+    pub total_lists: u64,
+    pub files: Vec<ManifestFile>,
+}
+
+/// Result of writing a manifest
+#[derive(Debug, Clone)]
+pub struct ManifestReport {
+    pub files: u64,
+    pub files_hashed: u64,
+    pub total_lists: u64,
+    pub bytes: u64,
+}
+
+/// Result of checking a directory against its manifest
+#[derive(Debug, Clone, Default)]
+pub struct ManifestCheck {
+    pub size: u8,
+    pub files_checked: u64,
+    pub files_ok: u64,
+    pub mismatched: Vec<(String, String)>,  // (filename, problem)
+    pub missing: Vec<String>,               // in the manifest, not in the directory
+    pub unlisted: Vec<String>,              // in the directory, not in the manifest
+}
+
+impl ManifestCheck {
+    /// True if every file of the manifest is present and intact
+    pub fn is_ok(&self) -> bool {
+        self.mismatched.is_empty() && self.missing.is_empty()
+    }
+}
+
+/// Manifest file of `size` in `dir`
+pub fn manifest_path(dir: &str, size: u8) -> PathBuf {
+    Path::new(dir).join(format!("nsl_{:02}_manifest.json", size))
+}
+
+/// Load the manifest of `size` in `dir`
+pub fn load_manifest(dir: &str, size: u8) -> std::io::Result<SizeManifest> {
+    let text = std::fs::read_to_string(manifest_path(dir, size))?;
+    serde_json::from_str(&text).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// Byte size and mtime (unix seconds) of a file
+fn file_stamp(path: &Path) -> std::io::Result<(u64, Option<i64>)> {
+    let metadata = std::fs::metadata(crate::storage::resolve_path(&path.to_string_lossy()))?;
+    let modified = metadata.modified().ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64);
+    Ok((metadata.len(), modified))
+}
+
+/// Names of the list files of `size` in `dir`
+fn list_filenames(dir: &str, size: u8) -> BTreeSet<String> {
+    crate::filenames::list_input_files(dir, size).iter()
+        .filter_map(|f| Path::new(&f.path).file_name().map(|n| n.to_string_lossy().into_owned()))
+        .collect()
+}
+
+/// Write (or refresh) the manifest of the list files of `size` in `dir`
+pub fn write_manifest(dir: &str, size: u8) -> std::io::Result<ManifestReport> {
+    let path = manifest_path(dir, size);
+    test_print(&format!("   ... writing manifest {}", path.display()));
+
+    let previous: BTreeMap<String, ManifestFile> = load_manifest(dir, size)
+        .map(|m| m.files.into_iter().map(|f| (f.filename.clone(), f)).collect())
+        .unwrap_or_default();
+    let recorded: BTreeMap<String, u64> = match GlobalFileState::from_sources(dir, size) {
+        Ok(state) => state.entries().values().map(|e| (e.filename.clone(), e.nb_lists_in_file)).collect(),
+        Err(e) if crate::file_info::is_state_locked(&e) => return Err(e),
+        Err(_) => BTreeMap::new(),
+    };
+
+    let mut manifest = SizeManifest {
+        size,
+        created_at: chrono::Local::now().to_rfc3339(),
+        funny_version: env!("CARGO_PKG_VERSION").to_string(),
+        total_lists: 0,
+        files: Vec::new(),
+    };
+    let mut files_hashed = 0u64;
+    for filename in list_filenames(dir, size) {
+        let file_path = Path::new(dir).join(&filename);
+        let (bytes, modified) = file_stamp(&file_path)?;
+        let entry = match previous.get(&filename) {
+            Some(known) if known.bytes == bytes && known.modified.is_some() && known.modified == modified => ManifestFile {
+                nb_lists: recorded.get(&filename).copied().unwrap_or(known.nb_lists),
+                ..known.clone()
+            },
+            _ => {
+                files_hashed += 1;
+                let (sha256, _) = crate::archive::hash_file(&file_path)?;
+                let nb_lists = match recorded.get(&filename) {
+                    Some(&count) => count,
+                    None => crate::io_helpers::count_lists_in_file(&file_path.to_string_lossy())?,
+                };
+                ManifestFile { filename: filename.clone(), bytes, nb_lists, sha256, modified }
+            }
+        };
+        manifest.total_lists += entry.nb_lists;
+        manifest.files.push(entry);
+    }
+
+    let json = serde_json::to_vec_pretty(&manifest)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, json)?;
+    std::fs::rename(&tmp, &path)?;
+
+    let report = ManifestReport {
+        files: manifest.files.len() as u64,
+        files_hashed,
+        total_lists: manifest.total_lists,
+        bytes: manifest.files.iter().map(|f| f.bytes).sum(),
+    };
+    test_print(&format!("   ... manifest: {} files ({} hashed), {} lists, {} bytes",
+        report.files, report.files_hashed, report.total_lists.separated_string(), report.bytes.separated_string()));
+    Ok(report)
+}
+
+/// Re-hash the list files of `size` in `dir` and compare them with its manifest
+pub fn verify_manifest(dir: &str, size: u8) -> std::io::Result<ManifestCheck> {
+    test_print(&format!("\nVERIFY MANIFEST MODE: Checking size {:02} files against {}", size, manifest_path(dir, size).display()));
+    let start_time = std::time::Instant::now();
+    let manifest = load_manifest(dir, size)?;
+
+    let mut check = ManifestCheck { size, ..Default::default() };
+    let mut on_disk = list_filenames(dir, size);
+    for (i, entry) in manifest.files.iter().enumerate() {
+        if (i + 1) % 100 == 0 {
+            progress_print(&format!("   ... {} of {} files hashed", i + 1, manifest.files.len()));
+        }
+        check.files_checked += 1;
+        if !on_disk.remove(&entry.filename) {
+            check.missing.push(entry.filename.clone());
+            continue;
+        }
+        let (sha256, bytes) = crate::archive::hash_file(&Path::new(dir).join(&entry.filename))?;
+        if bytes != entry.bytes {
+            check.mismatched.push((entry.filename.clone(), format!("{} bytes, manifest records {}", bytes, entry.bytes)));
+        } else if sha256 != entry.sha256 {
+            check.mismatched.push((entry.filename.clone(), format!("SHA-256 {} differs from the manifest", sha256)));
+        } else {
+            check.files_ok += 1;
+        }
+    }
+    check.unlisted = on_disk.into_iter().collect();
+
+    for (name, problem) in check.mismatched.iter() {
+        test_print(&format!("   [!!] {}: {}", name, problem));
+    }
+    for name in check.missing.iter() {
+        test_print(&format!("   [!!] {}: missing", name));
+    }
+    for name in check.unlisted.iter() {
+        test_print(&format!("   [??] {}: not in the manifest", name));
+    }
+    test_print(&format!("   ... {} of {} files match the manifest ({} lists recorded) in {:.2}s",
+        check.files_ok, check.files_checked, manifest.total_lists.separated_string(), start_time.elapsed().as_secs_f64()));
+    Ok(check)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::no_set_list::NoSetListSerialized;
+
+    #[test]
+    fn manifest_detects_modified_missing_and_unlisted_files() {
+        let dir = crate::test_dir::TestDir::new("manifest");
+        let dir_str = dir.to_string_lossy().into_owned();
+
+        let list = NoSetListSerialized { n: 3, max_card: 3, no_set_list: vec![0, 1, 3], remaining_cards_list: vec![4, 80] };
+        let files: Vec<String> = (0..3).map(|b| crate::filenames::output_filename(&dir_str, 2, b, 3, b)).collect();
+        for file in files.iter() {
+            assert!(crate::io_helpers::save_to_file_serialized(&vec![list.clone(), list.clone()], file));
+        }
+        let report = write_manifest(&dir_str, 3).expect("write");
+        assert_eq!((report.files, report.files_hashed, report.total_lists), (3, 3, 6));
+        assert_eq!(write_manifest(&dir_str, 3).expect("refresh").files_hashed, 0, "unchanged files are not hashed again");
+        assert!(verify_manifest(&dir_str, 3).expect("verify").is_ok());
+
+        let mut bytes = std::fs::read(&files[0]).expect("read");
+        bytes[0] ^= 0x01;
+        std::fs::write(&files[0], bytes).expect("write");
+        std::fs::remove_file(&files[1]).expect("remove");
+        let extra = crate::filenames::output_filename(&dir_str, 2, 3, 3, 3);
+        assert!(crate::io_helpers::save_to_file_serialized(&vec![list], &extra));
+
+        let check = verify_manifest(&dir_str, 3).expect("verify");
+        assert!(!check.is_ok());
+        assert_eq!(check.files_ok, 1);
+        assert_eq!(check.mismatched.len(), 1);
+        assert_eq!(check.missing, vec!["nsl_02_batch_000001_to_03_batch_000001.rkyv"]);
+        assert_eq!(check.unlisted, vec!["nsl_02_batch_000003_to_03_batch_000003.rkyv"]);
+    }
+}
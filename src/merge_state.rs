@@ -0,0 +1,294 @@
+//! Merge-state module: merge the state of a size produced on another machine
+//!
+//! When the directories of two hosts are combined, their global states overlap
+//! on target batches both hosts numbered. The state of -i (A) is merged into the
+//! state of -o (B), each overlapping target batch being settled by a policy.
+//!
+//! Key features:
+//! - Entries grouped by target batch: batches of A only are added, batches with
+//!   the same entries on both sides are left alone, the others are conflicts
+//! - Conflict policies: fail (default, nothing written), prefer-newer (latest
+//!   file modification wins), prefer-larger-count (most lists wins); B is kept
+//!   on ties
+//! - Entries of B losing a conflict are removed with a tombstone, the entries of
+//!   A taken keep their provenance and compacted sources
+//! - Consumed inputs of A unknown to B are added
+//! - Report of added, identical and conflicting batches (log, and JSON printed on
+//!   stdout by the command line), with the files taken from A missing from B's
+//!   directory
+//!
+//! Used by --merge-state mode
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use separator::Separatable;
+use serde::Serialize;
+
+use crate::file_info::{FileInfo, GlobalFileState, RemovalReason};
+use crate::state_diff::DiffEntry;
+use crate::utils::*;
+
+/// How a target batch recorded differently by both states is settled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergePolicy {
+    Fail,
+    PreferNewer,
+    PreferLargerCount,
+}
+
+impl MergePolicy {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text.to_ascii_lowercase().as_str() {
+            "fail" => Ok(MergePolicy::Fail),
+            "prefer-newer" => Ok(MergePolicy::PreferNewer),
+            "prefer-larger-count" => Ok(MergePolicy::PreferLargerCount),
+            other => Err(format!("Unknown merge policy '{}' (expected fail, prefer-newer or prefer-larger-count)", other)),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MergePolicy::Fail => "fail",
+            MergePolicy::PreferNewer => "prefer-newer",
+            MergePolicy::PreferLargerCount => "prefer-larger-count",
+        }
+    }
+}
+
+/// Target batch recorded differently by both states
+#[derive(Debug, Clone, Serialize)]
+pub struct MergeConflict {
+    pub target_batch: u32,
+    pub entries_a: Vec<DiffEntry>,
+    pub entries_b: Vec<DiffEntry>,
+    pub lists_a: u64,
+    pub lists_b: u64,
+    pub newest_a: Option<i64>,
+    pub newest_b: Option<i64>,
+    /// "A", "B", or "none" with the fail policy
+    pub winner: String,
+}
+
+/// Result of merging state A into state B for one size
+#[derive(Debug, Clone, Serialize)]
+pub struct MergeReport {
+    pub size: u8,
+    pub dir_a: String,
+    pub dir_b: String,
+    pub policy: String,
+    pub entries_a: usize,
+    pub entries_b: usize,
+    pub entries_after: usize,
+    pub added: Vec<DiffEntry>,
+    pub identical_batches: usize,
+    pub conflicts: Vec<MergeConflict>,
+    pub inputs_added: usize,
+    /// Files of the entries taken from A not found in B's directory
+    pub missing_files: Vec<String>,
+}
+
+impl MergeReport {
+    /// True if the fail policy met conflicts (nothing was merged)
+    pub fn refused(&self) -> bool {
+        self.policy == MergePolicy::Fail.as_str() && !self.conflicts.is_empty()
+    }
+}
+
+fn diff_entry(e: &FileInfo) -> DiffEntry {
+    DiffEntry {
+        filename: e.filename.clone(),
+        source_batch: e.source_batch,
+        target_batch: e.target_batch,
+        nb_lists: e.nb_lists_in_file,
+    }
+}
+
+/// Entries of a state grouped by target batch
+fn by_target_batch(state: &GlobalFileState) -> BTreeMap<u32, Vec<&FileInfo>> {
+    let mut groups: BTreeMap<u32, Vec<&FileInfo>> = BTreeMap::new();
+    for info in state.entries().values() {
+        groups.entry(info.target_batch).or_default().push(info);
+    }
+    groups
+}
+
+fn same_entries(a: &[&FileInfo], b: &[&FileInfo]) -> bool {
+    let key = |e: &&FileInfo| (e.source_batch, e.filename.clone(), e.nb_lists_in_file, e.compacted);
+    a.iter().map(key).collect::<BTreeSet<_>>() == b.iter().map(key).collect::<BTreeSet<_>>()
+}
+
+/// Merge the loaded state `a` into `b` (left untouched if the fail policy meets conflicts)
+pub fn merge_states(size: u8, dir_a: &str, a: &GlobalFileState, dir_b: &str, b: &mut GlobalFileState,
+    policy: MergePolicy) -> MergeReport {
+    let mut report = MergeReport {
+        size,
+        dir_a: dir_a.to_string(),
+        dir_b: dir_b.to_string(),
+        policy: policy.as_str().to_string(),
+        entries_a: a.entries().len(),
+        entries_b: b.entries().len(),
+        entries_after: b.entries().len(),
+        added: Vec::new(),
+        identical_batches: 0,
+        conflicts: Vec::new(),
+        inputs_added: 0,
+        missing_files: Vec::new(),
+    };
+
+    let groups_b = by_target_batch(b);
+    let mut taken: Vec<FileInfo> = Vec::new();  // entries of A to register in B
+    let mut dropped: Vec<FileInfo> = Vec::new(); // entries of B losing a conflict
+    for (tgt, group_a) in by_target_batch(a) {
+        let Some(group_b) = groups_b.get(&tgt) else {
+            report.added.extend(group_a.iter().map(|e| diff_entry(e)));
+            taken.extend(group_a.into_iter().cloned());
+            continue;
+        };
+        if same_entries(&group_a, group_b) {
+            report.identical_batches += 1;
+            continue;
+        }
+        let lists = |g: &[&FileInfo]| g.iter().map(|e| e.nb_lists_in_file).sum::<u64>();
+        let newest = |g: &[&FileInfo]| g.iter().filter_map(|e| e.modified_timestamp).max();
+        let (lists_a, lists_b) = (lists(&group_a), lists(group_b));
+        let (newest_a, newest_b) = (newest(&group_a), newest(group_b));
+        let a_wins = match policy {
+            MergePolicy::Fail => None,
+            MergePolicy::PreferNewer => Some(newest_a > newest_b),
+            MergePolicy::PreferLargerCount => Some(lists_a > lists_b),
+        };
+        if a_wins == Some(true) {
+            dropped.extend(group_b.iter().map(|e| (*e).clone()));
+            taken.extend(group_a.iter().map(|e| (*e).clone()));
+        }
+        report.conflicts.push(MergeConflict {
+            target_batch: tgt,
+            entries_a: group_a.iter().map(|e| diff_entry(e)).collect(),
+            entries_b: group_b.iter().map(|e| diff_entry(e)).collect(),
+            lists_a,
+            lists_b,
+            newest_a,
+            newest_b,
+            winner: match a_wins { None => "none", Some(true) => "A", Some(false) => "B" }.to_string(),
+        });
+    }
+    if report.refused() {
+        return report;
+    }
+
+    // Losers first: an entry of A may reuse the key of the entry of B it replaces
+    for e in dropped.iter() {
+        b.remove_file(&e.filename, e.source_batch, e.target_batch, RemovalReason::Manual);
+    }
+    for e in taken.iter() {
+        b.register_file(&e.filename, e.source_batch, e.target_batch, e.nb_lists_in_file, e.compacted,
+            e.file_size_bytes, e.modified_timestamp);
+        if let Some(provenance) = e.provenance.clone() {
+            b.set_provenance(&e.filename, e.source_batch, e.target_batch, provenance);
+        }
+        if let Some(sources) = a.compacted_sources().get(&e.filename) {
+            b.set_compacted_sources(&e.filename, sources.clone());
+        }
+        if !Path::new(dir_b).join(&e.filename).exists() {
+            report.missing_files.push(e.filename.clone());
+        }
+    }
+    let new_inputs: BTreeMap<_, _> = a.consumed_inputs().iter()
+        .filter(|(key, _)| !b.consumed_inputs().contains_key(key))
+        .map(|(key, input)| (*key, input.clone()))
+        .collect();
+    report.inputs_added = new_inputs.len();
+    if !new_inputs.is_empty() {
+        b.merge_consumed_inputs(&new_inputs);
+    }
+    report.entries_after = b.entries().len();
+    report
+}
+
+/// Merge the state of `size` in `dir_a` into the one in `dir_b`, saving it unless
+/// the fail policy met conflicts
+pub fn merge_size_states(dir_a: &str, dir_b: &str, size: u8, policy: MergePolicy) -> std::io::Result<MergeReport> {
+    test_print(&format!("\nMERGE STATE MODE: Merging the size {:02} state of A into B ({})", size, policy.as_str()));
+    test_print(&format!("   A: {}", dir_a));
+    test_print(&format!("   B: {}", dir_b));
+    let a = GlobalFileState::from_sources(dir_a, size)?;
+    let mut b = GlobalFileState::from_sources(dir_b, size)?;
+    let report = merge_states(size, dir_a, &a, dir_b, &mut b, policy);
+    if !report.refused() {
+        b.flush()?;
+        b.export_human_readable()?;
+    }
+    Ok(report)
+}
+
+/// Print the merge report (human-readable in the log) and emit it as JSON (see events::result)
+pub fn print_merge_report(report: &MergeReport) -> std::io::Result<()> {
+    test_print(&format!("   ... A: {} entries, B: {} entries", report.entries_a, report.entries_b));
+    for e in report.added.iter() {
+        test_print(&format!("   + {:06} {:06} | {:>15} | {}", e.source_batch, e.target_batch, e.nb_lists.separated_string(), e.filename));
+    }
+    for c in report.conflicts.iter() {
+        let when = |t: Option<i64>| t.map_or("-".to_string(), |t| t.to_string());
+        test_print(&format!("   ! target batch {:06}: A {} lists (modified {}), B {} lists (modified {}) -> {}",
+            c.target_batch, c.lists_a.separated_string(), when(c.newest_a), c.lists_b.separated_string(),
+            when(c.newest_b), match c.winner.as_str() { "none" => "unresolved", "A" => "A kept", _ => "B kept" }));
+        for e in c.entries_a.iter() {
+            test_print(&format!("       A {:06} | {:>15} | {}", e.source_batch, e.nb_lists.separated_string(), e.filename));
+        }
+        for e in c.entries_b.iter() {
+            test_print(&format!("       B {:06} | {:>15} | {}", e.source_batch, e.nb_lists.separated_string(), e.filename));
+        }
+    }
+    test_print(&format!("   ... {} batches added, {} identical, {} conflicts, {} consumed inputs added",
+        report.added.len(), report.identical_batches, report.conflicts.len(), report.inputs_added));
+    if report.refused() {
+        test_print("   ... conflicts with the fail policy: nothing merged (use --merge-policy prefer-newer or prefer-larger-count)");
+    } else {
+        test_print(&format!("   ... B now has {} entries", report.entries_after));
+    }
+    for filename in report.missing_files.iter() {
+        test_print(&format!("   Warning: {} is not in B's directory yet (copy it from A)", filename));
+    }
+
+    let json = serde_json::to_string_pretty(report)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    crate::events::result(crate::events::ResultReady { mode: "merge_state".to_string(), json });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_info::InputKey;
+
+    fn host_states() -> (GlobalFileState, GlobalFileState) {
+        let mut a = GlobalFileState::new("a", 5);
+        a.register_file("f0.rkyv", 0, 0, 10, false, None, Some(100));
+        a.register_file("f1a.rkyv", 1, 1, 30, false, None, Some(50));
+        a.register_file("f2.rkyv", 2, 2, 5, false, None, Some(100));
+        a.record_consumed_input(4, InputKey::whole(2, false), 7);
+        let mut b = GlobalFileState::new("b", 5);
+        b.register_file("f0.rkyv", 0, 0, 10, false, None, Some(100));
+        b.register_file("f1b.rkyv", 3, 1, 20, false, None, Some(200));
+        (a, b)
+    }
+
+    #[test]
+    fn conflicts_follow_the_policy() {
+        let (a, mut b) = host_states();
+        let refused = merge_states(5, "a", &a, "b", &mut b, MergePolicy::Fail);
+        assert!(refused.refused());
+        assert_eq!((refused.conflicts.len(), refused.identical_batches), (1, 1));
+        assert_eq!(b.entries().len(), 2, "the fail policy leaves B untouched");
+
+        let newer = merge_states(5, "a", &a, "b", &mut b, MergePolicy::PreferNewer);
+        assert_eq!(newer.conflicts[0].winner, "B");
+        assert_eq!((newer.added.len(), newer.inputs_added, newer.entries_after), (1, 1, 3));
+
+        let (a, mut b) = host_states();
+        let larger = merge_states(5, "a", &a, "b", &mut b, MergePolicy::PreferLargerCount);
+        assert_eq!(larger.conflicts[0].winner, "A");
+        assert!(b.has_entry("f1a.rkyv", 1, 1) && !b.has_entry("f1b.rkyv", 3, 1));
+        assert!(b.tombstones().keys().any(|k| k.2 == "f1b.rkyv"));
+    }
+}
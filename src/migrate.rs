@@ -0,0 +1,243 @@
+//! Migration module: convert files written by older versions to the current format
+//!
+//! Legacy formats detected (by filename):
+//! - v0.1-v0.2: nlist_{size:02}_batch_{batch:06}.bin (bincode of Vec<NList>)
+//! - v0.3.1:    nlist_v31_{size:02}_batch_{batch:06}.rkyv (rkyv of Vec<NList>)
+//! - v0.3.2+:   nlist_{size:02}_batch_{batch:06}.rkyv (rkyv of Vec<NList>)
+//!
+//! NList had the same fields as today's NoSetListSerialized (it was only renamed
+//! in v0.3.2), so the lists are decoded as NoSetListSerialized directly.
+//!
+//! Key features:
+//! - Each legacy batch becomes nsl_{size-1:02}_batch_000000_to_{size:02}_batch_{batch:06}.rkyv
+//!   (legacy names do not record the source batch: 0 is used; size 3 comes from size 00
+//!   as the seed lists do), in the selected encoding
+//! - Converted files registered in the global state of their size
+//! - A legacy batch whose target batch is already used by a current file is skipped
+//! - Originals deleted only on request (--delete-originals), once the conversion is written
+//! - Without migration, size mode reads the legacy files of the batches that have no
+//!   current file directly (see filenames::list_input_files_with_legacy)
+//!
+//! Used by --migrate mode, and by size mode for legacy inputs
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use separator::Separatable;
+
+use crate::file_info::GlobalFileState;
+use crate::filenames::output_filename;
+use crate::no_set_list::NoSetListSerialized;
+use crate::utils::*;
+
+/// On-disk format of a legacy file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LegacyFormat {
+    Bincode,
+    Rkyv,
+}
+
+/// Legacy file found in the migrated directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LegacyFile {
+    pub path: String,
+    pub size: u8,
+    pub batch: u32,
+    pub format: LegacyFormat,
+}
+
+/// Result of a migration run
+#[derive(Debug, Clone, Default)]
+pub struct MigrateReport {
+    pub files_found: u64,
+    pub files_converted: u64,
+    pub files_skipped: u64,
+    pub originals_deleted: u64,
+    pub lists_converted: u64,
+}
+
+/// Size, batch and format of a legacy filename (nlist_[v31_]XX_batch_YYYYYY.bin|.rkyv)
+pub fn parse_legacy_filename(name: &str) -> Option<(u8, u32, LegacyFormat)> {
+    let rest = name.strip_prefix("nlist_")?;
+    let rest = rest.strip_prefix("v31_").unwrap_or(rest);
+    let (stem, format) = if let Some(stem) = rest.strip_suffix(".bin") {
+        (stem, LegacyFormat::Bincode)
+    } else {
+        (rest.strip_suffix(".rkyv")?, LegacyFormat::Rkyv)
+    };
+    let (size, batch) = stem.split_once("_batch_")?;
+    Some((size.parse().ok()?, batch.parse().ok()?, format))
+}
+
+/// List the legacy files of `dir`, sorted by size then batch
+pub fn find_legacy_files(dir: &str) -> std::io::Result<Vec<LegacyFile>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)?.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if let Some((size, batch, format)) = parse_legacy_filename(&name) {
+            files.push(LegacyFile { path: entry.path().to_string_lossy().into_owned(), size, batch, format });
+        }
+    }
+    files.sort_by_key(|f| (f.size, f.batch));
+    Ok(files)
+}
+
+/// Legacy files of `size` in `dir`, one per batch (rkyv preferred over bincode when a
+/// batch exists in both), sorted by batch. Size mode reads them on the fly for the
+/// batches that have no file in the current format.
+pub fn legacy_input_files(dir: &str, size: u8) -> Vec<LegacyFile> {
+    let mut files: Vec<LegacyFile> = find_legacy_files(dir).unwrap_or_default().into_iter()
+        .filter(|f| f.size == size)
+        .collect();
+    files.sort_by_key(|f| (f.batch, f.format == LegacyFormat::Bincode));
+    files.dedup_by_key(|f| f.batch);
+    files
+}
+
+/// True if `path` names a legacy bincode file (nlist_XX_batch_YYYYYY.bin)
+pub fn is_legacy_bincode(path: &str) -> bool {
+    let name = Path::new(path).file_name().unwrap_or_default().to_string_lossy();
+    matches!(parse_legacy_filename(&name), Some((_, _, LegacyFormat::Bincode)))
+}
+
+/// Decode the lists of a legacy bincode file
+pub fn load_legacy_bincode(path: &str) -> std::io::Result<Vec<NoSetListSerialized>> {
+    let reader = BufReader::new(File::open(path)?);
+    bincode::deserialize_from(reader)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path, e)))
+}
+
+/// Decode the lists of a legacy file
+pub fn load_legacy_file(file: &LegacyFile) -> std::io::Result<Vec<NoSetListSerialized>> {
+    match file.format {
+        LegacyFormat::Bincode => load_legacy_bincode(&file.path),
+        LegacyFormat::Rkyv => crate::io_helpers::load_lists_from_file(&file.path),
+    }
+}
+
+/// Convert every legacy file of `dir` in place, optionally deleting the originals
+pub fn migrate_directory(dir: &str, delete_originals: bool) -> std::io::Result<MigrateReport> {
+    test_print("\nMIGRATE MODE: Converting legacy files to the current format...");
+    test_print(&format!("   Directory: {}", dir));
+    let start_time = std::time::Instant::now();
+
+    let legacy = find_legacy_files(dir)?;
+    let mut report = MigrateReport { files_found: legacy.len() as u64, ..Default::default() };
+    if legacy.is_empty() {
+        test_print("   ... no legacy file found");
+        return Ok(report);
+    }
+
+    let mut states: BTreeMap<u8, GlobalFileState> = BTreeMap::new();
+    for file in legacy.iter() {
+        let name = Path::new(&file.path).file_name().unwrap_or_default().to_string_lossy().into_owned();
+        // Never overwrite or shadow a current file with the same target batch
+        if crate::filenames::list_input_files(dir, file.size).iter().any(|f| f.batch == file.batch) {
+            test_print(&format!("   ... {} skipped: size {:02} batch {:06} already exists in the current format",
+                name, file.size, file.batch));
+            report.files_skipped += 1;
+            continue;
+        }
+
+        let lists = load_legacy_file(file)?;
+        // Seed lists (size 3) are recorded as coming from size 0, as in create_seed_lists
+        let source_size = if file.size <= 3 { 0 } else { file.size - 1 };
+        let target = output_filename(dir, source_size, 0, file.size, file.batch);
+        if !crate::io_helpers::save_to_file_serialized(&lists, &target) {
+            return Err(std::io::Error::other(format!("Cannot write {}", target)));
+        }
+
+        let metadata = std::fs::metadata(crate::storage::resolve_path(&target)).ok();
+        let file_size = metadata.as_ref().map(|m| m.len());
+        let mtime = metadata.as_ref()
+            .and_then(|m| m.modified().ok())
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64);
+        let state = match states.entry(file.size) {
+            std::collections::btree_map::Entry::Occupied(e) => e.into_mut(),
+            std::collections::btree_map::Entry::Vacant(e) => e.insert(GlobalFileState::from_sources(dir, file.size)?),
+        };
+        let target_name = Path::new(&target).file_name().unwrap_or_default().to_string_lossy().into_owned();
+        state.register_file(&target_name, 0, file.batch, lists.len() as u64, false, file_size, mtime);
+
+        report.files_converted += 1;
+        report.lists_converted += lists.len() as u64;
+        test_print(&format!("   ... {:>10} lists: {} -> {}", lists.len().separated_string(), name, target_name));
+
+        if delete_originals {
+            crate::storage::remove_list_file(&file.path)?;
+            report.originals_deleted += 1;
+        }
+    }
+
+    for state in states.values_mut() {
+        state.flush()?;
+        state.export_human_readable()?;
+    }
+
+    test_print(&format!("   ... {} of {} legacy files converted ({} lists, {} skipped, {} originals deleted) in {:.2}s",
+        report.files_converted, report.files_found, report.lists_converted.separated_string(),
+        report.files_skipped, report.originals_deleted, start_time.elapsed().as_secs_f64()));
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_filenames_are_recognised() {
+        assert_eq!(parse_legacy_filename("nlist_07_batch_000042.bin"), Some((7, 42, LegacyFormat::Bincode)));
+        assert_eq!(parse_legacy_filename("nlist_v31_05_batch_000003.rkyv"), Some((5, 3, LegacyFormat::Rkyv)));
+        assert_eq!(parse_legacy_filename("nlist_06_batch_000000.rkyv"), Some((6, 0, LegacyFormat::Rkyv)));
+        assert_eq!(parse_legacy_filename("nsl_03_batch_000000_to_04_batch_000000.rkyv"), None);
+        assert_eq!(parse_legacy_filename("nlist_06_batch_000000.json"), None);
+    }
+
+    #[test]
+    fn bincode_files_are_converted_and_registered() {
+        let dir = crate::test_dir::TestDir::new("migrate");
+        let dir_str = dir.to_string_lossy().into_owned();
+
+        let lists = vec![
+            NoSetListSerialized { n: 3, max_card: 3, no_set_list: vec![0, 1, 3], remaining_cards_list: vec![4, 5] },
+            NoSetListSerialized { n: 3, max_card: 4, no_set_list: vec![0, 1, 4], remaining_cards_list: vec![5] },
+        ];
+        let legacy = dir.join("nlist_03_batch_000002.bin");
+        std::fs::write(&legacy, bincode::serialize(&lists).expect("bincode")).expect("write legacy");
+
+        let report = migrate_directory(&dir_str, true).expect("migrate");
+        assert_eq!(report.files_converted, 1);
+        assert_eq!(report.lists_converted, 2);
+        assert!(!legacy.exists());
+
+        let converted = output_filename(&dir_str, 0, 0, 3, 2);
+        let read = crate::io_helpers::load_lists_from_file(&converted).expect("read converted");
+        assert_eq!(read.len(), 2);
+        assert_eq!(read[1].no_set_list, vec![0, 1, 4]);
+        let state = GlobalFileState::from_sources(&dir_str, 3).expect("state");
+        assert_eq!(state.entries().values().map(|e| e.nb_lists_in_file).sum::<u64>(), 2);
+    }
+
+    #[test]
+    fn legacy_inputs_are_planned_and_read_without_migration() {
+        let dir = crate::test_dir::TestDir::new("legacy_input");
+        let dir_str = dir.to_string_lossy().into_owned();
+
+        let lists = vec![NoSetListSerialized { n: 4, max_card: 9, no_set_list: vec![0, 1, 3, 9], remaining_cards_list: vec![10, 80] }];
+        for batch in [0, 1] {
+            std::fs::write(dir.join(format!("nlist_04_batch_{:06}.bin", batch)), bincode::serialize(&lists).expect("bincode")).expect("write");
+        }
+        // Batch 1 exists in the current format: its legacy file is ignored
+        let current = output_filename(&dir_str, 3, 0, 4, 1);
+        assert!(crate::io_helpers::save_to_file_serialized(&lists, &current));
+
+        let plan = crate::filenames::list_input_files_with_legacy(&dir_str, 4);
+        assert_eq!(plan.iter().map(|f| (f.batch, is_legacy_bincode(&f.path))).collect::<Vec<_>>(), vec![(0, true), (1, false)]);
+        let legacy = crate::filenames::find_input_filename(&dir_str, 4, 0).expect("legacy input");
+        let read = crate::io_helpers::load_lists_from_file(&legacy).expect("read legacy");
+        assert_eq!(read[0].no_set_list, vec![0, 1, 3, 9]);
+        assert_eq!(crate::io_helpers::count_lists_in_file(&legacy).expect("count"), 1);
+    }
+}
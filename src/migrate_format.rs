@@ -0,0 +1,155 @@
+//! Migrate-format module: upgrade the files of a size to the current file format
+//!
+//! Files written before format version 1 start with a bare rkyv archive (plain
+//! encoding, no header) and may have no integrity footer; they are still read, but
+//! a future change of NoSetListSerialized could not tell them apart. Migrating
+//! rewrites them with a versioned header and a footer, in place.
+//!
+//! Key features:
+//! - Files already in the current format version are left untouched
+//! - Each file keeps its name, encoding and compression (framed files stay framed)
+//! - Each file rewritten into a .tmp file, its list count checked against the
+//!   original, then renamed over the original
+//! - State entries updated (file size, mtime) and flushed once
+//!
+//! Used by --migrate-format mode
+
+use std::path::Path;
+use separator::Separatable;
+
+use crate::file_info::GlobalFileState;
+use crate::io_helpers::{FileFormat, ListFileWriter, FORMAT_VERSION, FRAME_LISTS};
+use crate::utils::*;
+
+/// Result of migrating the files of one size
+#[derive(Debug, Clone)]
+pub struct MigrateFormatReport {
+    pub size: u8,
+    pub files_checked: u64,
+    pub files_migrated: u64,
+    pub lists_migrated: u64,
+}
+
+/// Rewrite `path` in the current format version, keeping its encoding and
+/// compression; returns its list count
+fn migrate_file(path: &str, format: FileFormat) -> std::io::Result<u64> {
+    let tmp = format!("{}.tmp", path);
+    let compression = if format.compressed {
+        Some(crate::io_helpers::output_compression().unwrap_or(3))
+    } else {
+        None
+    };
+    let read = if format.framed {
+        let mut writer = ListFileWriter::create_as(&tmp, format.encoding, compression)?;
+        let read = crate::io_helpers::load_lists_in_chunks(path, FRAME_LISTS, |chunk| writer.write_frame(&chunk))?;
+        let written = writer.finish()?;
+        if written != read {
+            let _ = std::fs::remove_file(&tmp);
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+                format!("{} lists read from {} but {} written", read, path, written)));
+        }
+        read
+    } else {
+        let lists = crate::io_helpers::load_lists_from_file(path)?;
+        if !crate::io_helpers::save_lists_as(&lists, &tmp, format.encoding, compression) {
+            return Err(std::io::Error::other(format!("Failed to write {}", tmp)));
+        }
+        lists.len() as u64
+    };
+    if crate::io_helpers::count_lists_in_file(&tmp)? != read {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+            format!("{} lists read from {} but not found in {}", read, path, tmp)));
+    }
+    crate::io_helpers::commit_atomic_write(&tmp, path)?;
+    crate::io_helpers::invalidate_cached_batch(path);
+    Ok(read)
+}
+
+/// Upgrade every file of `size` in `base_path` to the current format version
+pub fn migrate_format_size_files(base_path: &str, size: u8) -> std::io::Result<MigrateFormatReport> {
+    test_print(&format!("\nMIGRATE FORMAT MODE: Upgrading size {:02} files to format version {}...", size, FORMAT_VERSION));
+    test_print(&format!("   Directory: {}", base_path));
+    let start_time = std::time::Instant::now();
+
+    let files = crate::filenames::list_input_files(base_path, size);
+    if files.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound,
+            format!("No size {:02} files found in {}", size, base_path)));
+    }
+    let mut state = GlobalFileState::from_sources(base_path, size)?;
+
+    let mut report = MigrateFormatReport { size, files_checked: 0, files_migrated: 0, lists_migrated: 0 };
+
+    for file in files.iter() {
+        report.files_checked += 1;
+        let name = Path::new(&file.path).file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let format = crate::io_helpers::file_format(&file.path)?;
+        if format.version == FORMAT_VERSION {
+            debug_print(&format!("   ... {} already in format version {} (unchanged)", name, FORMAT_VERSION));
+            continue;
+        }
+        let nb_lists = migrate_file(&file.path, format)?;
+        let (file_size, mtime) = crate::storage::file_metadata(&file.path)
+            .map(|(bytes, mtime)| (Some(bytes), mtime)).unwrap_or((None, None));
+
+        let keys: Vec<(u32, u32, String)> = state.entries().keys()
+            .filter(|(_, _, filename)| *filename == name)
+            .cloned()
+            .collect();
+        if keys.is_empty() {
+            test_print(&format!("   ... WARNING: {} is not recorded in the global state (run --count {})", name, size));
+        }
+        for (src, tgt, filename) in keys {
+            state.update_entry(&filename, src, tgt, nb_lists, file.compacted, file_size, mtime);
+        }
+
+        report.files_migrated += 1;
+        report.lists_migrated += nb_lists;
+        test_print(&format!("   ... {:>10} lists in {}: format version {} -> {}", nb_lists.separated_string(), name,
+            format.version, FORMAT_VERSION));
+    }
+
+    if report.files_migrated > 0 {
+        state.flush()?;
+        state.export_human_readable()?;
+    }
+
+    test_print(&format!("   ... {} of {} files migrated ({} lists) in {:.2}s",
+        report.files_migrated, report.files_checked, report.lists_migrated.separated_string(),
+        start_time.elapsed().as_secs_f64()));
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io_helpers::ListEncoding;
+    use crate::no_set_list::NoSetListSerialized;
+
+    #[test]
+    fn headerless_files_are_upgraded_and_current_ones_left_alone() {
+        let dir = crate::test_dir::TestDir::new("migrate_format");
+        let dir_str = dir.to_string_lossy().into_owned();
+
+        let lists = vec![
+            NoSetListSerialized { n: 3, max_card: 3, no_set_list: vec![0, 1, 3], remaining_cards_list: vec![4, 80] },
+            NoSetListSerialized { n: 3, max_card: 9, no_set_list: vec![0, 1, 9], remaining_cards_list: vec![] },
+        ];
+        let legacy = crate::filenames::output_filename(&dir_str, 2, 0, 3, 0);
+        let current = crate::filenames::output_filename(&dir_str, 2, 1, 3, 1);
+        // Format version 0: bare rkyv archive, no footer
+        let bare = rkyv::to_bytes::<_, 256>(&lists).expect("serialize");
+        std::fs::write(&legacy, &bare[..]).expect("write");
+        assert!(crate::io_helpers::save_lists_as(&lists, &current, ListEncoding::Plain, None));
+        assert_eq!(crate::io_helpers::file_format(&legacy).expect("format").version, 0);
+        let untouched = std::fs::read(&current).expect("read");
+
+        let report = migrate_format_size_files(&dir_str, 3).expect("migrate");
+        assert_eq!((report.files_checked, report.files_migrated, report.lists_migrated), (2, 1, 2));
+        let format = crate::io_helpers::file_format(&legacy).expect("format");
+        assert_eq!((format.version, format.encoding, format.compressed), (FORMAT_VERSION, ListEncoding::Plain, false));
+        assert_eq!(std::fs::read(&legacy).expect("read"), untouched, "same lists, same bytes as a new file");
+        assert_eq!(crate::io_helpers::load_lists_from_file(&legacy).expect("load")[1].max_card, 9);
+    }
+}
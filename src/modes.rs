@@ -0,0 +1,2475 @@
+//! Modes module: configuration of a run and the executors of the processing modes
+//!
+//! The command line builds a ProcessingConfig from its arguments; execute_mode
+//! runs it. Library users build the configuration themselves.
+//!
+//! Key features:
+//! - ProcessingConfig: mode, directories, lists per file, and the options of the
+//!   size steps (force, keep_state, pruning, expected counts, shard, dry run)
+//! - ProcessingMode: one variant per mode, with its parameters
+//! - Builder (library users, tests): ProcessingConfig::size(15).input("data")
+//!   .run(), checked as the command line checks its arguments, and returning the
+//!   run summary or a FunnyError; resolve_paths gives the default directories
+//! - execute_mode: dispatch to the executor of the mode; the result is the
+//!   message of the run, or its error. Every mode is framed by a ModeStarted and
+//!   a ModeCompleted event (see events)
+//!
+//! Used by every mode (through src/main.rs)
+
+use std::collections::BTreeMap;
+use separator::Separatable;
+use crate::error::{Context, FunnyError, FunnyResult};
+use crate::utils::*;
+
+/// Unified configuration for all processing modes
+#[derive(Debug)]
+pub struct ProcessingConfig {
+    pub mode: ProcessingMode,
+    pub input_dir: String,
+    pub output_dir: String,
+    pub max_lists_per_file: u64,
+    pub memory_limit_gb: Option<u64>,                 // set by --memory-limit (max_lists_per_file follows it)
+    pub force_recount: bool,
+    pub keep_state: bool,
+    pub strong_prune: bool,
+    pub isomorph_cache: bool,
+    pub expected_counts: Option<BTreeMap<u8, u64>>,   // set by --validate-counts
+    pub shard: Option<crate::filenames::Shard>,       // set by --shard (size and unitary modes)
+    pub dry_run: bool,                                // set by --dry-run (destructive modes only)
+}
+
+/// Max number of n-list saved per file for v0.4.0
+/// - Each NoSetList: 792 bytes during compute (stack)
+/// - Each NoSetListSerialized: ~100 bytes after conversion (heap)
+/// - 20M entries × 100 bytes = ~2GB per file after serialization
+/// - Peak RAM during save: ~10.5GB (vec + archive + overhead)
+pub const MAX_NLISTS_PER_FILE: u64 = 10_000_000;
+
+impl ProcessingConfig {
+    /// Builder of a --size run: process the size `size` lists
+    pub fn size(size: u8) -> ProcessingConfigBuilder {
+        ProcessingConfigBuilder::new(ProcessingMode::Size { size, start_batch: None })
+    }
+
+    /// Builder of a --unitary run: process the input batch `batch` of `size`
+    pub fn unitary(size: u8, batch: u32) -> ProcessingConfigBuilder {
+        ProcessingConfigBuilder::new(ProcessingMode::Unitary { size, batch })
+    }
+
+    /// Builder of a --count run
+    pub fn count(size: u8) -> ProcessingConfigBuilder {
+        ProcessingConfigBuilder::new(ProcessingMode::Count { size, fast: false, follow: None })
+    }
+
+    /// Builder of a --check run
+    pub fn check(size: u8) -> ProcessingConfigBuilder {
+        ProcessingConfigBuilder::new(ProcessingMode::Check { size, deep: None, fix: false, against_input: false })
+    }
+
+    /// Builder of a --compact run (in place, all batches)
+    pub fn compact(size: u8) -> ProcessingConfigBuilder {
+        ProcessingConfigBuilder::new(ProcessingMode::Compact { size, max_batch: None, delete_originals: false })
+    }
+
+    /// Builder of a run of any mode
+    pub fn builder(mode: ProcessingMode) -> ProcessingConfigBuilder {
+        ProcessingConfigBuilder::new(mode)
+    }
+
+    /// Run the configuration (see execute_mode) and release its state locks: the
+    /// summary of the run (its own warnings and activity only, see
+    /// findings::begin_run), or the error that stopped it
+    pub fn run(&self) -> FunnyResult<crate::findings::RunSummary> {
+        crate::findings::begin_run();
+        let start_time = std::time::Instant::now();
+        let result = execute_mode(self);
+        crate::file_info::release_state_locks();
+        let message = result?;
+        Ok(crate::findings::RunSummary::new(&self.mode.name(), &Ok(message), start_time.elapsed().as_secs_f64()))
+    }
+}
+
+/// ProcessingConfig built without the command line:
+/// `ProcessingConfig::size(15).input("data").run()`,
+/// `ProcessingConfig::check(15).deep(true).threads(8).run()`
+///
+/// The directories left unset default as on the command line (see resolve_paths),
+/// and build() checks what the command line checks on its arguments.
+///
+/// The run-wide options (encoding, compression, storage and state backends,
+/// compaction, engine, I/O retry, disk guard, notifications) are not part of the
+/// configuration: they are process-wide settings, set through the setters of their
+/// modules (io_helpers::set_output_encoding, engine::set_engine...) as src/main.rs
+/// does, and shared by every run of the process. Runs needing different run-wide
+/// options cannot run at the same time in one process.
+#[derive(Debug)]
+pub struct ProcessingConfigBuilder {
+    mode: ProcessingMode,
+    input: Option<String>,
+    output: Option<String>,
+    lists_per_file: Option<u64>,
+    memory_limit_gb: Option<u64>,
+    threads: Option<usize>,
+    deep: bool,
+    start_batch: Option<u32>,
+    force_recount: bool,
+    keep_state: bool,
+    strong_prune: bool,
+    isomorph_cache: bool,
+    expected_counts: Option<BTreeMap<u8, u64>>,
+    shard: Option<crate::filenames::Shard>,
+    dry_run: bool,
+}
+
+impl ProcessingConfigBuilder {
+    fn new(mode: ProcessingMode) -> Self {
+        Self { mode, input: None, output: None, lists_per_file: None, memory_limit_gb: None, threads: None,
+            deep: false, start_batch: None, force_recount: false, keep_state: false, strong_prune: false, isomorph_cache: false,
+            expected_counts: None, shard: None, dry_run: false }
+    }
+
+    /// Input directory (-i)
+    pub fn input(mut self, dir: impl Into<String>) -> Self {
+        self.input = Some(dir.into());
+        self
+    }
+
+    /// Output directory (-o)
+    pub fn output(mut self, dir: impl Into<String>) -> Self {
+        self.output = Some(dir.into());
+        self
+    }
+
+    /// Lists per output file (default MAX_NLISTS_PER_FILE, or what the memory limit allows)
+    pub fn lists_per_file(mut self, lists: u64) -> Self {
+        self.lists_per_file = Some(lists);
+        self
+    }
+
+    /// Memory limit in GB (--memory-limit): sets the lists per file unless given
+    pub fn memory_limit_gb(mut self, gb: u64) -> Self {
+        self.memory_limit_gb = Some(gb);
+        self
+    }
+
+    /// Worker threads of the modes running on several (check --deep, scan); 0 is
+    /// one per core. Only sets the worker count: a check stays shallow without
+    /// deep(true), and the other modes (the size steps included) refuse it.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// --deep of a --check run: re-count the lists of every file (on one thread per
+    /// core, unless threads() says otherwise)
+    pub fn deep(mut self, deep: bool) -> Self {
+        self.deep = deep;
+        self
+    }
+
+    /// First input batch of a --size run (--size SIZE BATCH)
+    pub fn start_batch(mut self, batch: u32) -> Self {
+        self.start_batch = Some(batch);
+        self
+    }
+
+    /// --force
+    pub fn force(mut self, force: bool) -> Self {
+        self.force_recount = force;
+        self
+    }
+
+    /// --keep-state
+    pub fn keep_state(mut self, keep_state: bool) -> Self {
+        self.keep_state = keep_state;
+        self
+    }
+
+    /// --strong-prune
+    pub fn strong_prune(mut self, strong_prune: bool) -> Self {
+        self.strong_prune = strong_prune;
+        self
+    }
+
+    /// --isomorph-cache
+    pub fn isomorph_cache(mut self, isomorph_cache: bool) -> Self {
+        self.isomorph_cache = isomorph_cache;
+        self
+    }
+
+    /// --validate-counts with these expected counts per size
+    pub fn expected_counts(mut self, counts: BTreeMap<u8, u64>) -> Self {
+        self.expected_counts = Some(counts);
+        self
+    }
+
+    /// --shard
+    pub fn shard(mut self, shard: crate::filenames::Shard) -> Self {
+        self.shard = Some(shard);
+        self
+    }
+
+    /// --dry-run
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// The configuration, checked as the command line checks its arguments
+    pub fn build(self) -> FunnyResult<ProcessingConfig> {
+        let mut mode = self.mode;
+        let invalid = |message: String| Err(FunnyError::InvalidArgument(message));
+        let (name, size, max) = match &mode {
+            ProcessingMode::Size { size, .. } => ("Size", *size, 20),
+            ProcessingMode::Unitary { size, .. } => ("Unitary", *size, 19),
+            ProcessingMode::Count { size, .. } => ("Count", *size, 20),
+            ProcessingMode::Check { size, .. } => ("Check", *size, 20),
+            ProcessingMode::Compact { size, .. } => ("Compact", *size, 20),
+            _ => ("", 3, 20),
+        };
+        if !(3..=max).contains(&size) {
+            return invalid(format!("{} size {} out of range (3-{})", name, size, max));
+        }
+        match (&mut mode, self.start_batch) {
+            (_, None) => {}
+            (ProcessingMode::Size { size: 3, .. }, Some(batch)) if batch > 0 => {
+                return invalid("Cannot specify batch number for size 3 (seed lists)".to_string());
+            }
+            (ProcessingMode::Size { start_batch, .. }, Some(batch)) => *start_batch = Some(batch).filter(|b| *b > 0),
+            (_, Some(_)) => return invalid("start_batch only applies to a --size run".to_string()),
+        }
+        match &mut mode {
+            ProcessingMode::Check { deep, .. } if self.deep => *deep = Some(self.threads.or(*deep).unwrap_or(0)),
+            ProcessingMode::Check { deep: Some(workers), .. } | ProcessingMode::Scan { workers, .. } =>
+                *workers = self.threads.unwrap_or(*workers),
+            ProcessingMode::Check { deep: None, .. } if self.threads.is_some() =>
+                return Err(FunnyError::Config("threads only applies to a deep check (deep(true))".to_string())),
+            _ if self.deep => return Err(FunnyError::Config("deep only applies to a --check run".to_string())),
+            _ if self.threads.is_some() =>
+                return Err(FunnyError::Config("threads only applies to --check --deep and --scan".to_string())),
+            _ => {}
+        }
+        if self.dry_run && !matches!(mode, ProcessingMode::Size { .. } | ProcessingMode::Compact { .. } |
+            ProcessingMode::Prune { .. } | ProcessingMode::Repair { .. } | ProcessingMode::Cascade { .. }) {
+            return Err(FunnyError::Config("--dry-run is only honored by --size, --compact, --prune, --repair and --cascade".to_string()));
+        }
+        let (input_dir, output_dir) = resolve_paths(&mode, self.input.as_deref(), self.output.as_deref());
+        let max_lists_per_file = self.lists_per_file
+            .or(self.memory_limit_gb.map(lists_per_file_for_memory))
+            .unwrap_or(MAX_NLISTS_PER_FILE);
+        Ok(ProcessingConfig {
+            mode,
+            input_dir,
+            output_dir,
+            max_lists_per_file,
+            memory_limit_gb: self.memory_limit_gb,
+            force_recount: self.force_recount,
+            keep_state: self.keep_state,
+            strong_prune: self.strong_prune,
+            isomorph_cache: self.isomorph_cache,
+            expected_counts: self.expected_counts,
+            shard: self.shard,
+            dry_run: self.dry_run,
+        })
+    }
+
+    /// Build and run the configuration: the summary of the run (outcome, message,
+    /// warnings, elapsed time), or the error that stopped it
+    pub fn run(self) -> FunnyResult<crate::findings::RunSummary> {
+        self.build()?.run()
+    }
+}
+
+/// What the cascade does when a size fails
+#[derive(Debug, Clone, Copy)]
+pub struct CascadeRetry {
+    pub retries: u32,
+    pub delay_secs: u64,  // before the first retry, doubled at each retry
+    pub skip_failed: bool, // go on with the next sizes
+}
+
+/// --force and --keep_state of one cascade step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepFlags {
+    pub force: bool,
+    pub keep_state: bool,
+}
+
+/// Flags of each output size of a cascade: `defaults` (from --force and --keep_state)
+/// with the --cascade-step overrides ("16:no-force,keep-state")
+pub fn parse_cascade_steps(values: &[String], defaults: StepFlags) -> Result<BTreeMap<u8, StepFlags>, String> {
+    let mut steps = BTreeMap::new();
+    for value in values {
+        let (size, flags) = value.split_once(':')
+            .ok_or_else(|| format!("Invalid --cascade-step '{}' (expected SIZE:FLAGS)", value))?;
+        let size: u8 = size.trim().parse().map_err(|_| format!("Invalid size in --cascade-step '{}'", value))?;
+        let step: &mut StepFlags = steps.entry(size).or_insert(defaults);
+        for flag in flags.split(',').map(str::trim) {
+            match flag {
+                "force" => step.force = true,
+                "no-force" => step.force = false,
+                "keep-state" => step.keep_state = true,
+                "no-keep-state" => step.keep_state = false,
+                other => return Err(format!("Unknown flag '{}' in --cascade-step '{}' (force, no-force, keep-state, no-keep-state)",
+                    other, value)),
+            }
+        }
+    }
+    Ok(steps)
+}
+
+/// Input sizes processed by a cascade from `starting_input_size` up to the output size
+/// `stop_at` (--stop-at), minus the output sizes of --skip-sizes, with their flags
+pub fn cascade_steps(starting_input_size: u8, stop_at: u8, skip_sizes: &[u8], step_flags: &BTreeMap<u8, StepFlags>,
+    defaults: StepFlags) -> Vec<(u8, StepFlags)> {
+    (starting_input_size..stop_at)
+        .filter(|input_size| !skip_sizes.contains(&(input_size + 1)))
+        .map(|input_size| (input_size, step_flags.get(&(input_size + 1)).copied().unwrap_or(defaults)))
+        .collect()
+}
+
+/// Processing mode enumeration
+#[derive(Debug)]
+pub enum ProcessingMode {
+    Count { size: u8, fast: bool, follow: Option<u64> },
+    LegacyCount { size: u8 },
+    CreateJson { size: u8, csv: bool },
+    Check { size: u8, deep: Option<usize>, fix: bool, against_input: bool },
+    Compact { size: u8, max_batch: Option<u32>, delete_originals: bool },
+    Size { size: u8, start_batch: Option<u32> },
+    Unitary { size: u8, batch: u32 },
+    Cascade { starting_input_size: u8, roots: crate::cascade_roots::CascadeRoots, stop_at: u8, skip_sizes: Vec<u8>, retry: CascadeRetry,
+        step_flags: BTreeMap<u8, StepFlags> },
+    SaveHistory { size: u8 },
+    ExportLists { filename: String },
+    Orbits { size: u8 },
+    Verify { size: u8 },
+    FinalReport { size: u8 },
+    RandomWalk { iterations: u64, rng_seed: u64 },
+    FilterTarget { target: u8, size: u8 },
+    Stats { size: u8 },
+    Extract { size: u8, batch: u32, index: u64 },
+    Sample { size: u8, nb_lists: u64, rng_seed: u64 },
+    Split { size: u8, max_lists: u64 },
+    Migrate { delete_originals: bool },
+    Convert { size: u8, format: crate::convert::ConvertFormat },
+    Benchmark { max_size: u8 },
+    Estimate { size: u8, batches: usize },
+    Prune { size: u8, archive_dir: Option<String> },
+    Repair { size: u8 },
+    Diff { size: u8 },
+    Archive { size: u8 },
+    Unarchive { archive: String },
+    Watch { size: u8, interval_secs: u64 },
+    Inspect { file: String },
+    Top { size: u8, count: usize, ranking: crate::top::TopRanking },
+    NormalizeFilenames { size: u8 },
+    ValidateChain { from_size: u8, to_size: u8 },
+    ExportCards { size: u8, batch: u32 },
+    Gc { size: Option<u8>, delete: bool },
+    Reencode { size: u8 },
+    Checksum { size: u8 },
+    VerifyManifest { size: u8 },
+    MigrateFormat { size: u8 },
+    UpgradeState { size: u8 },
+    Overview,
+    RestoreState { size: u8, timestamp: Option<String> },
+    MergeState { size: u8, policy: crate::merge_state::MergePolicy },
+    Rebalance { size: u8 },
+    Scan { size: u8, workers: usize },
+    CountAll,
+    HistoryDiff { size: u8 },
+    Default,
+}
+
+impl ProcessingMode {
+    /// Name of the mode in the run summary: "save_history" for SaveHistory
+    pub fn name(&self) -> String {
+        let variant = format!("{:?}", self);
+        let variant = variant.split([' ', '{', '(']).next().unwrap_or_default();
+        let mut name = String::new();
+        for (i, c) in variant.chars().enumerate() {
+            if c.is_ascii_uppercase() && i > 0 {
+                name.push('_');
+            }
+            name.push(c.to_ascii_lowercase());
+        }
+        name
+    }
+
+    /// Check if this mode requires log file initialization
+    pub fn requires_logging(&self) -> bool {
+        matches!(self, 
+            ProcessingMode::Count { .. } | 
+            ProcessingMode::LegacyCount { .. } |
+            ProcessingMode::CreateJson { .. } |
+            ProcessingMode::Check { .. } | 
+            ProcessingMode::Compact { .. } |
+            ProcessingMode::Cascade { .. } |
+            ProcessingMode::SaveHistory { .. } |
+            ProcessingMode::ExportLists { .. } |
+            ProcessingMode::Orbits { .. } |
+            ProcessingMode::Verify { .. } |
+            ProcessingMode::FinalReport { .. } |
+            ProcessingMode::RandomWalk { .. } |
+            ProcessingMode::FilterTarget { .. } |
+            ProcessingMode::Stats { .. } |
+            ProcessingMode::Sample { .. } |
+            ProcessingMode::Split { .. } |
+            ProcessingMode::Migrate { .. } |
+            ProcessingMode::Convert { .. } |
+            ProcessingMode::Benchmark { .. } |
+            ProcessingMode::Estimate { .. } |
+            ProcessingMode::Prune { .. } |
+            ProcessingMode::Repair { .. } |
+            ProcessingMode::Diff { .. } |
+            ProcessingMode::Archive { .. } |
+            ProcessingMode::Unarchive { .. } |
+            ProcessingMode::Watch { .. } |
+            ProcessingMode::Inspect { .. } |
+            ProcessingMode::Top { .. } |
+            ProcessingMode::NormalizeFilenames { .. } |
+            ProcessingMode::ValidateChain { .. } |
+            ProcessingMode::ExportCards { .. } |
+            ProcessingMode::Gc { .. } |
+            ProcessingMode::Reencode { .. } |
+            ProcessingMode::Checksum { .. } |
+            ProcessingMode::VerifyManifest { .. } |
+            ProcessingMode::MigrateFormat { .. } |
+            ProcessingMode::UpgradeState { .. } |
+            ProcessingMode::Overview |
+            ProcessingMode::RestoreState { .. } |
+            ProcessingMode::MergeState { .. } |
+            ProcessingMode::Rebalance { .. } |
+            ProcessingMode::Scan { .. } |
+            ProcessingMode::CountAll |
+            ProcessingMode::HistoryDiff { .. })
+    }
+}
+
+/// Lists buffered per output file for `memory_limit_gb` GB of RAM
+/// Half of the RAM goes to the output lists, buffered as stack NoSetList (792 bytes
+/// each) until saved; the rest covers the frame being serialized, the mapped input
+/// file and the state. 16 GB gives about the default 10M lists per file.
+pub fn lists_per_file_for_memory(memory_limit_gb: u64) -> u64 {
+    let budget = memory_limit_gb.saturating_mul(1 << 30) / 2;
+    (budget / std::mem::size_of::<crate::no_set_list::NoSetList>() as u64)
+        .max(crate::io_helpers::FRAME_LISTS as u64)
+}
+
+/// Estimated bytes of one list in a written file, before compression
+pub fn bytes_per_list_estimate(encoding: crate::io_helpers::ListEncoding) -> u64 {
+    match encoding {
+        crate::io_helpers::ListEncoding::Plain => 100,  // ~2GB per 20M lists
+        crate::io_helpers::ListEncoding::Delta => 50,
+        crate::io_helpers::ListEncoding::Packed => crate::no_set_list::PACKED_LIST_BYTES as u64,
+    }
+}
+
+/// Resolve paths for modes that use both input and output with fallback logic
+/// Resolve input/output paths based on mode requirements
+pub fn resolve_paths(
+    mode: &ProcessingMode,
+    input_arg: Option<&str>,
+    output_arg: Option<&str>
+) -> (String, String) {
+    match mode {
+        ProcessingMode::Count { .. } => {
+            // Count only uses input
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::LegacyCount { .. } => {
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::CreateJson { .. } => {
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::Check { .. } => {
+            // Check uses output, and input with --against-input
+            (input_arg.unwrap_or_default().to_string(), output_arg.unwrap_or(".").to_string())
+        },
+        ProcessingMode::Cascade { .. } => {
+            // Cascade uses input as root directory
+            let root = input_arg.unwrap_or(".").to_string();
+            (root, String::new())
+        },
+        ProcessingMode::SaveHistory { .. } => {
+            // SaveHistory uses input directory
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::ExportLists { .. } => {
+            // ExportLists writes the exports next to the rkyv files
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::Orbits { .. } => {
+            // Orbits reads the lists and writes its report in the input directory
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::Verify { .. } => {
+            // Verify reads the lists and writes its report in the input directory
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::FinalReport { .. } => {
+            // FinalReport reads the state files and writes its report in the input directory
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::RandomWalk { .. } => {
+            // RandomWalk only writes its report and discoveries
+            (String::new(), output_arg.or(input_arg).unwrap_or(".").to_string())
+        },
+        ProcessingMode::FilterTarget { .. } => {
+            // FilterTarget rewrites the size files in place
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::Stats { .. } => {
+            // Stats reads the lists and writes its exports in the input directory
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::Extract { .. } => {
+            // Extract only reads the input directory
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::Sample { .. } => {
+            // Sample reads the size files and writes the sample to -o (default: input directory)
+            let input = input_arg.unwrap_or(".").to_string();
+            let output = output_arg.unwrap_or(&input).to_string();
+            (input, output)
+        },
+        ProcessingMode::Split { .. } => {
+            // Split rewrites the size files in place
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::Migrate { .. } => {
+            // Migrate converts the legacy files in place
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::Convert { .. } => {
+            // Convert reads the size files and writes the export to -o (default: input directory)
+            let input = input_arg.unwrap_or(".").to_string();
+            let output = output_arg.unwrap_or(&input).to_string();
+            (input, output)
+        },
+        ProcessingMode::Benchmark { .. } => {
+            // Benchmark runs in a scratch directory under -o and saves its result there
+            (String::new(), output_arg.or(input_arg).unwrap_or(".").to_string())
+        },
+        ProcessingMode::Estimate { .. } => {
+            // Estimate only reads the input files (and probes the disk in the input directory)
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::Prune { .. } => {
+            // Prune deletes inputs of -i consumed by the outputs of -o (default: same directory)
+            let input = input_arg.unwrap_or(".").to_string();
+            let output = output_arg.unwrap_or(&input).to_string();
+            (input, output)
+        },
+        ProcessingMode::Repair { .. } => {
+            // Repair rewrites the state of the size directory
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::Diff { .. } => {
+            // Diff compares the state of -i (A) with the state of -o (B)
+            (input_arg.unwrap_or(".").to_string(), output_arg.unwrap_or(".").to_string())
+        },
+        ProcessingMode::Archive { .. } => {
+            // Archive reads the size files of -i and writes the archive to -o (default: input directory)
+            let input = input_arg.unwrap_or(".").to_string();
+            let output = output_arg.unwrap_or(&input).to_string();
+            (input, output)
+        },
+        ProcessingMode::Unarchive { .. } => {
+            // Unarchive restores into -o (default: current directory)
+            (String::new(), output_arg.unwrap_or(".").to_string())
+        },
+        ProcessingMode::Watch { .. } => {
+            // Watch reads the new batches of -i and writes to -o (default: input directory)
+            let input = input_arg.unwrap_or(".").to_string();
+            let output = output_arg.unwrap_or(&input).to_string();
+            (input, output)
+        },
+        ProcessingMode::Inspect { .. } => {
+            // Inspect reads one file (relative to -i when given)
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::Top { .. } => {
+            // Top reads the size files and saves the leaderboard in the input directory
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::NormalizeFilenames { .. } => {
+            // Normalize renames the files of the size directory in place
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::ValidateChain { .. } => {
+            // ValidateChain uses input as the cascade root directory
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::ExportCards { .. } => {
+            // ExportCards reads one file and writes its export next to it
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::Gc { .. } => {
+            // Gc cleans the input directory
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::Reencode { .. } => {
+            // Reencode rewrites the input directory in place
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::Checksum { .. } => {
+            // Checksum only reads the input directory
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::VerifyManifest { .. } => {
+            // Verify-manifest only reads the input directory
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::MigrateFormat { .. } => {
+            // Migrate-format rewrites the input directory in place
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::UpgradeState { .. } => {
+            // Upgrade-state rewrites the state of the input directory in place
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::Overview => {
+            // Overview uses input as the root directory (cascade layout or single directory)
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::RestoreState { .. } => {
+            // Restore-state rewrites the state of the input directory in place
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::MergeState { .. } => {
+            // Merge-state reads the state of -i (A) and rewrites the state of -o (B)
+            (input_arg.unwrap_or(".").to_string(), output_arg.unwrap_or(".").to_string())
+        },
+        ProcessingMode::Rebalance { .. } => {
+            // Rebalance rewrites the compacted files in place
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::Scan { .. } => {
+            // Scan reads the input directory, quarantining within it
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::CountAll => {
+            // Count-all uses input as the root directory (cascade layout or single directory)
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::HistoryDiff { .. } => {
+            // History-diff reads the history and the state of the input directory
+            (input_arg.unwrap_or(".").to_string(), String::new())
+        },
+        ProcessingMode::Size { .. } | ProcessingMode::Unitary { .. } | ProcessingMode::Compact { .. } => {
+            // These modes default output to input if not specified
+            let input = input_arg.unwrap_or(".").to_string();
+            let output = output_arg.unwrap_or(&input).to_string();
+            (input, output)
+        },
+        ProcessingMode::Default => {
+            // Default mode has hardcoded fallback
+            let path = output_arg.unwrap_or(r"T:\data\funny_set_exploration").to_string();
+            (path.clone(), path)
+        }
+    }
+}
+
+/// Record the lists per output file of this run in the state of its size, noting a change
+fn record_lists_per_file(state: &mut crate::file_info::GlobalFileState, max_lists_per_file: u64) {
+    if let Some(previous) = state.max_lists_per_file()
+        && previous != max_lists_per_file {
+        test_print(&format!("Note: lists per file changed from {} (recorded in state) to {}",
+            previous.separated_string(), max_lists_per_file.separated_string()));
+    }
+    state.set_max_lists_per_file(Some(max_lists_per_file));
+}
+
+/// Record in the state of the outputs whether its lists are reduced by --isomorph-cache
+/// (this run uses it, or its inputs in `input_dir` are reduced); a size never mixes
+/// reduced and exhaustive files
+fn record_isomorph_reduced(state: &mut crate::file_info::GlobalFileState, input_dir: &str, input_size: u8,
+    isomorph_cache: bool) -> FunnyResult<()> {
+    let inputs_reduced = crate::dry_run::load_state_readonly(input_dir, input_size).is_ok_and(|s| s.isomorph_reduced());
+    let reduced = isomorph_cache || inputs_reduced;
+    if !state.entries().is_empty() && state.isomorph_reduced() != reduced {
+        let (recorded, now) = if reduced { ("exhaustive", "reduced by --isomorph-cache") } else { ("reduced by --isomorph-cache", "exhaustive") };
+        return Err(FunnyError::StateMismatch { size: input_size + 1, reason: format!("the files are {}, this run's \
+            would be {}: reduced and exhaustive lists are not mixed in one size", recorded, now) });
+    }
+    state.set_isomorph_reduced(reduced);
+    Ok(())
+}
+
+/// Record in the state of the outputs whether its lists are pruned by --strong-prune
+/// (this run uses it, or its inputs in `input_dir` are pruned); a size never mixes
+/// pruned and exhaustive files
+fn record_strong_pruned(state: &mut crate::file_info::GlobalFileState, input_dir: &str, input_size: u8,
+    strong_prune: bool) -> FunnyResult<()> {
+    let inputs_pruned = crate::dry_run::load_state_readonly(input_dir, input_size).is_ok_and(|s| s.strong_pruned());
+    let pruned = strong_prune || inputs_pruned;
+    if !state.entries().is_empty() && state.strong_pruned() != pruned {
+        let (recorded, now) = if pruned { ("exhaustive", "pruned by --strong-prune") } else { ("pruned by --strong-prune", "exhaustive") };
+        return Err(FunnyError::Validation(format!("the size {:02} files are {}, this run's would be {}: \
+            pruned and exhaustive lists are not mixed in one size", input_size + 1, recorded, now)));
+    }
+    state.set_strong_pruned(pruned);
+    Ok(())
+}
+
+/// Warn when the lists of `size` in `dir` are reduced by --isomorph-cache or pruned
+/// by --strong-prune
+fn warn_if_not_exhaustive(dir: &str, size: u8) {
+    let Ok(state) = crate::dry_run::load_state_readonly(dir, size) else { return };
+    if state.isomorph_reduced() {
+        crate::findings::warn("isomorph_reduced", format!("the size {:02} lists are reduced by --isomorph-cache: \
+            a subset of the no-set-lists, not the exhaustive count", size));
+    }
+    if state.strong_pruned() {
+        crate::findings::warn("strong_pruned", format!("the size {:02} lists are pruned by --strong-prune: \
+            a subset of the no-set-lists, not the exhaustive count", size));
+    }
+}
+
+/// Decoded input batches that fit in a quarter of `memory_limit_gb` GB of RAM, at
+/// most `requested` (each cached batch holds up to `max_lists_per_file` lists)
+pub fn batch_cache_for_memory(requested: usize, memory_limit_gb: u64, max_lists_per_file: u64) -> usize {
+    // NoSetListSerialized plus its two Vecs (about 24 cards at 8 bytes)
+    let list_bytes = (std::mem::size_of::<crate::no_set_list::NoSetListSerialized>() + 24 * 8) as u64;
+    let batch_bytes = max_lists_per_file.max(1).saturating_mul(list_bytes);
+    let affordable = memory_limit_gb.saturating_mul(1 << 30) / 4 / batch_bytes;
+    requested.min(affordable.try_into().unwrap_or(usize::MAX))
+}
+
+/// Expansion options of the run (see engine)
+fn engine_options(config: &ProcessingConfig) -> crate::engine::EngineOptions {
+    crate::engine::EngineOptions {
+        strong_prune: config.strong_prune,
+        isomorph_cache: config.isomorph_cache,
+        shard: config.shard,
+    }
+}
+
+/// Print the output batch size of a size or unitary run
+fn print_batch_size(config: &ProcessingConfig) {
+    match config.memory_limit_gb {
+        Some(gb) => test_print(&format!("Batch size: {} entries/file (memory limit {} GB)",
+            config.max_lists_per_file.separated_string(), gb)),
+        None => test_print(&format!("Batch size: {} entries/file", config.max_lists_per_file.separated_string())),
+    }
+}
+
+/// Handle force recount if enabled
+fn handle_force_recount(
+    enabled: bool,
+    directory: &str,
+    target_size: u8
+    , keep_state: bool
+) -> FunnyResult<()> {
+    if !enabled {
+        return Ok(());
+    }
+    
+    use crate::list_of_nsl::count_size_files;
+    
+    test_print(&format!("\nFORCE MODE: Regenerating count file for size {}...", target_size));
+    count_size_files(directory, target_size, true, keep_state)
+        .context("Error regenerating count file")?;
+    test_print("Count file regenerated successfully\n");
+    Ok(())
+}
+
+/// Print directories with consistent formatting
+fn print_directories(input: &str, output: &str) {
+    if !input.is_empty() {
+        test_print(&format!("Input directory: {}", input));
+    }
+    if !output.is_empty() {
+        test_print(&format!("Output directory: {}", output));
+    }
+}
+
+/// Execute the appropriate mode based on configuration, between a ModeStarted and a
+/// ModeCompleted event (see events)
+pub fn execute_mode(config: &ProcessingConfig) -> FunnyResult<String> {
+    let mode = config.mode.name();
+    crate::events::mode_started(crate::events::ModeStarted { mode: mode.clone() });
+    let start_time = std::time::Instant::now();
+    let result = dispatch_mode(config);
+    let message = match &result {
+        Ok(message) => message.clone(),
+        Err(e) => e.to_string(),
+    };
+    crate::events::mode_completed(crate::events::ModeCompleted { mode, succeeded: result.is_ok(), message,
+        elapsed_secs: start_time.elapsed().as_secs_f64() });
+    result
+}
+
+/// Run the executor of the mode of `config`
+fn dispatch_mode(config: &ProcessingConfig) -> FunnyResult<String> {
+    use crate::list_of_nsl::{count_size_files, compact_size_files, check_size_files};
+    use std::path::Path;
+    use std::fs;
+    
+    match &config.mode {
+        ProcessingMode::Count { size, follow: Some(minutes), .. } => {
+            crate::count_follow::follow_count(&config.input_dir, *size, *minutes)
+                .context("Error during count follow")?;
+            Ok("Count follow stopped".to_string())
+        },
+
+        ProcessingMode::Count { size, fast: true, .. } => {
+            crate::fast_count::fast_count_size_files(&config.input_dir, *size, config.force_recount)
+                .context("Error during fast count")?;
+            warn_if_not_exhaustive(&config.input_dir, *size);
+            Ok("Fast count completed successfully".to_string())
+        },
+
+        ProcessingMode::Count { size, fast: false, .. } => {
+            // Banner is printed by count_size_files function
+            count_size_files(&config.input_dir, *size, config.force_recount, config.keep_state)
+                .context("Error during count")?;
+            warn_if_not_exhaustive(&config.input_dir, *size);
+            Ok("Count completed successfully".to_string())
+        },
+
+        ProcessingMode::LegacyCount { size } => {
+            use crate::file_info::GlobalFileState;
+            use std::collections::HashSet;
+            use std::io::BufRead;
+            
+            let input_base = &config.input_dir;
+            test_print(&format!("Legacy-count mode for size {:02}", size));
+            
+            // Step 1: Load from JSON first (authoritative format if available)
+            let mut state = match GlobalFileState::from_sources(input_base, *size) {
+                Ok(state) => state,
+                Err(e) if crate::file_info::is_state_locked(&e) => return Err(e.into()),
+                Err(_) => {
+                    test_print("   ... No existing state found, starting fresh");
+                    GlobalFileState::new(input_base, *size)
+                }
+            };
+            
+            let initial_count = state.entries().len();
+            let mut seen_files: HashSet<String> = state.entries().keys()
+                .map(|(_, _, filename)| filename.clone())
+                .collect();
+            let mut processed_batches: HashSet<u32> = state.entries().values()
+                .map(|e| e.source_batch)
+                .collect();
+            
+            test_print(&format!("   ... Loaded {} files from {} source batches", 
+                initial_count, processed_batches.len()));
+            
+            // Step 2: Complement with intermediary count files
+            let mut files_added = 0;
+            let mut added_from_rkyv = 0;
+            let pattern = format!("nsl_{:02}_intermediate_count_from_{:02}_", size, size - 1);
+            let mut intermediary_files: Vec<(std::path::PathBuf, u32)> = Vec::new();
+            
+            for entry in fs::read_dir(input_base).context("Error reading directory")? {
+                if let Ok(e) = entry
+                    && let Some(name) = e.file_name().to_str()
+                    && name.starts_with(&pattern) && name.ends_with(".txt")
+                    && let Some(batch_str) = name.rsplit('_').next().and_then(|s| s.strip_suffix(".txt"))
+                    && let Ok(batch) = batch_str.parse::<u32>() {
+                    intermediary_files.push((e.path(), batch));
+                }
+            }
+            
+            intermediary_files.sort_by_key(|(_, batch)| *batch);
+            let unprocessed: Vec<_> = intermediary_files.iter()
+                .filter(|(_, batch)| !processed_batches.contains(batch))
+                .collect();
+            
+            if !unprocessed.is_empty() {
+                test_print(&format!("   ... Found {} unprocessed intermediate count files", unprocessed.len()));
+                
+                for (path, batch) in unprocessed {
+                    if path.file_name().and_then(|n| n.to_str()).is_some() {
+                        let file = fs::File::open(path).file_context("Error opening", path)?;
+                        let reader = std::io::BufReader::new(file);
+                        
+                        for line in reader.lines() {
+                            let line = line.file_context("Error reading", path)?;
+                            // Strip UTF-8 BOM if present
+                            let line_clean = line.strip_prefix('\u{FEFF}').unwrap_or(&line);
+                            let trimmed = line_clean.trim();
+                            
+                            if trimmed.starts_with("...") {
+                                // Parse: "...  8528436 lists in filename.rkyv"
+                                if let Some(rest) = trimmed.strip_prefix("...") {
+                                    let rest = rest.trim();
+                                    let parts: Vec<&str> = rest.split_whitespace().collect();
+                                    if parts.len() >= 4 && parts[1] == "lists" && parts[2] == "in"
+                                        && let Ok(count) = parts[0].parse::<u64>() {
+                                        let filename = parts[3].to_string();
+                                            
+                                        if seen_files.contains(&filename) {
+                                            continue;
+                                        }
+                                            
+                                        // Parse batch numbers from filename
+                                        if let Some(to_pos) = filename.find("_to_") {
+                                            let before_to = &filename[..to_pos];
+                                            let after_raw = &filename[to_pos + 4..];
+                                            let after_to = after_raw
+                                                .strip_suffix("_compacted.rkyv")
+                                                .or_else(|| after_raw.strip_suffix(".rkyv"))
+                                                .unwrap_or(after_raw);
+                                                
+                                            if let Some(src_pos) = before_to.rfind("_batch_")
+                                                && let Ok(src_batch) = before_to[src_pos + 7..].parse::<u32>()
+                                                && let Some(tgt_pos) = after_to.rfind("_batch_")
+                                                && let Ok(tgt_batch) = after_to[tgt_pos + 7..].parse::<u32>() {
+                                                let is_compacted = filename.contains("_compacted.rkyv");
+                                                state.register_file(&filename, src_batch, tgt_batch, count, is_compacted, None, None);
+                                                seen_files.insert(filename);
+
+                                                files_added += 1;
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                        
+                        processed_batches.insert(*batch);
+                    }
+                }
+                
+                test_print(&format!("   ... Added {} new files from intermediate counts", files_added));
+            }
+            
+            // Step 3: If --force, scan rkyv files directly to fill remaining gaps
+            if config.force_recount {
+                test_print("   ... FORCE mode: Scanning .rkyv files to fill gaps...");
+                
+                let mut rkyv_files: Vec<std::path::PathBuf> = Vec::new();
+                for entry in fs::read_dir(input_base).context("Error reading directory")? {
+                    if let Ok(e) = entry
+                        && let Some(name) = e.file_name().to_str()
+                        && name.ends_with(".rkyv") && name.contains(&format!("_to_{:02}_", size)) {
+                        rkyv_files.push(e.path());
+                    }
+                }
+                
+                test_print(&format!("   ... Found {} total rkyv files in directory", rkyv_files.len()));
+                
+                // Filter to only files not already in state
+                let missing_files: Vec<_> = rkyv_files.iter()
+                    .filter(|p| {
+                        p.file_name()
+                            .and_then(|n| n.to_str())
+                            .map(|name| !seen_files.contains(name))
+                            .unwrap_or(false)
+                    })
+                    .collect();
+                
+                test_print(&format!("   ... {} files missing from state, need introspection", missing_files.len()));
+                
+                if missing_files.is_empty() {
+                    test_print("   ... All rkyv files already in state, nothing to introspect");
+                } else {
+                    let total_missing = missing_files.len();
+                    let mut processed = 0;
+                    for path in missing_files {
+                        if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+                            processed += 1;
+                            test_print(&format!("   ... [{}/{}] Reading {}", processed, total_missing, name));
+                            
+                            // Parse batch numbers
+                            if let Some(to_pos) = name.find("_to_") {
+                                let before_to = &name[..to_pos];
+                                let after_raw = &name[to_pos + 4..];
+                                let after_to = after_raw
+                                    .strip_suffix("_compacted.rkyv")
+                                    .or_else(|| after_raw.strip_suffix(".rkyv"))
+                                    .unwrap_or(after_raw);
+                                
+                                if let Some(src_pos) = before_to.rfind("_batch_")
+                                    && let Ok(src_batch) = before_to[src_pos + 7..].parse::<u32>()
+                                    && let Some(tgt_pos) = after_to.rfind("_batch_")
+                                    && let Ok(tgt_batch) = after_to[tgt_pos + 7..].parse::<u32>() {
+                                    // Count lists in rkyv file
+                                    if let Ok(count) = crate::io_helpers::count_lists_in_file(&path.to_string_lossy()) {
+                                        let is_compacted = name.contains("_compacted.rkyv");
+                                                    
+                                        // Get file metadata
+                                        let (file_size, mtime) = path.metadata()
+                                            .ok()
+                                            .map(|m| (
+                                                Some(m.len()),
+                                                m.modified().ok()
+                                                    .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                                                    .map(|d| d.as_secs() as i64)
+                                            ))
+                                            .unwrap_or((None, None));
+                                                    
+                                        state.register_file(name, src_batch, tgt_batch, count, is_compacted, file_size, mtime);
+                                        seen_files.insert(name.to_string());
+                                        added_from_rkyv += 1;
+                                                    
+                                        test_print(&format!("       {} lists counted, saving state...", count));
+                                        state.flush().context(&format!("Error saving rkyv after {}", name))?;
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                
+                if added_from_rkyv > 0 {
+                    test_print(&format!("   ... Added {} files from direct rkyv scan", added_from_rkyv));
+                }
+            }
+            
+            // Only save if we actually added new data
+            let total_files_added = files_added + added_from_rkyv;
+            
+            if total_files_added > 0 {
+                test_print("   ... Saving updated state...");
+                state.flush().context("Error saving rkyv")?;
+                state.export_human_readable().context("Error exporting JSON/TXT")?;
+                
+                let rkyv_path = state.state_path();
+                let json_path = Path::new(input_base).join(format!("nsl_{:02}_global_info.json", size));
+                let txt_path = Path::new(input_base).join(format!("nsl_{:02}_global_info.txt", size));
+                
+                test_print(&format!("Wrote {}, {} and {}", rkyv_path.display(), json_path.display(), txt_path.display()));
+            } else {
+                test_print("   ... No changes detected, skipping file writes");
+            }
+            
+            test_print(&format!("Total: {} files from {} unique source batches", 
+                state.entries().len(), 
+                state.entries().values().map(|e| e.source_batch).collect::<HashSet<_>>().len()));
+            Ok("Legacy count completed successfully".to_string())
+        },
+        
+        ProcessingMode::CreateJson { size, csv } => {
+            use crate::file_info::GlobalFileState;
+            
+            test_print(&format!("Creating human-readable {} exports for size {:02}...", if *csv { "CSV" } else { "JSON/TXT" }, size));
+            
+            // Load state from rkyv (authoritative format)
+            let state = GlobalFileState::from_sources(&config.input_dir, *size)
+                .context("Error loading state")?;
+            
+            test_print(&format!("   ... Loaded {} files from rkyv state", state.entries().len()));
+            
+            if *csv {
+                let csv_path = state.export_csv()
+                    .context("Error exporting CSV")?;
+                test_print(&format!("Exported {}", csv_path.display()));
+                return Ok("CSV export completed successfully".to_string());
+            }
+            
+            // Export to human-readable formats
+            state.export_human_readable()
+                .context("Error exporting JSON/TXT")?;
+            
+            let json_path = Path::new(&config.input_dir).join(format!("nsl_{:02}_global_info.json", size));
+            let txt_path = Path::new(&config.input_dir).join(format!("nsl_{:02}_global_info.txt", size));
+            
+            test_print(&format!("Exported {} and {}", json_path.display(), txt_path.display()));
+            Ok("JSON/TXT export completed successfully".to_string())
+        },
+        
+        ProcessingMode::Check { size, deep, fix, against_input } => {
+            if *fix {
+                let fixes = crate::repair::fix_size_files(&config.output_dir, *size)
+                    .context("Error during check --fix")?;
+                test_print(&format!("   ... {} fixes applied, checking the result", fixes.changes()));
+            }
+            // Banner is printed by check_size_files function
+            let report = check_size_files(&config.output_dir, *size, *deep,
+                against_input.then_some(config.input_dir.as_str()))
+                .context("Error during check")?;
+            report.save(&config.output_dir, &format!("nsl_{:02}_check_report.json", size))
+                .context("Error saving check report")?;
+            report.outcome("Check completed successfully".to_string())
+        },
+        
+        ProcessingMode::Compact { .. } if config.dry_run && config.input_dir != config.output_dir => {
+            Err(FunnyError::Config("--dry-run plans in-place compaction only (no -o)".to_string()))
+        },
+
+        ProcessingMode::Compact { size, max_batch, .. } if config.dry_run => {
+            let (plan, efficiency) = crate::compaction::plan_compaction_report(&config.input_dir, *size,
+                config.max_lists_per_file, *max_batch)
+                .context("Error planning compaction")?;
+            plan.print();
+            efficiency.print();
+            Ok("Compaction dry run completed (nothing modified)".to_string())
+        },
+
+        ProcessingMode::Compact { size, max_batch, delete_originals } if config.input_dir != config.output_dir => {
+            let report = crate::compaction::compact_size_files_to(&config.input_dir, &config.output_dir, *size,
+                config.max_lists_per_file, *max_batch, *delete_originals)
+                .context("Error during compaction")?;
+            Ok(format!("Out-of-place compaction of size {:02} completed: {} lists of {} files into {} verified files{}{}",
+                report.size, report.lists.separated_string(), report.sources, report.outputs.len(),
+                if report.originals_deleted { ", originals deleted" } else { "" },
+                if report.stopped_low_space { " (stopped on low disk space: run again once space is freed)" } else { "" }))
+        },
+
+        ProcessingMode::Compact { size, max_batch, .. } => {
+            // Banner is printed by compact_size_files function
+            compact_size_files(&config.input_dir, &config.output_dir, *size, config.max_lists_per_file, *max_batch)
+                .context("Error during compaction")?;
+            Ok("Compaction completed successfully".to_string())
+        },
+        
+        ProcessingMode::Size { size, start_batch } => {
+            execute_size_mode(config, *size, *start_batch)
+        },
+        
+        ProcessingMode::Unitary { size, batch } => {
+            execute_unitary_mode(config, *size, *batch)
+        },
+        
+        ProcessingMode::Cascade { starting_input_size, roots, stop_at, skip_sizes, retry, step_flags } => {
+            let defaults = StepFlags { force: config.force_recount, keep_state: config.keep_state };
+            let steps = cascade_steps(*starting_input_size, *stop_at, skip_sizes, step_flags, defaults);
+            execute_cascade_mode(&steps, roots, *retry, config.max_lists_per_file, config.expected_counts.as_ref(), config.dry_run)
+        },
+        
+        ProcessingMode::SaveHistory { size } => {
+            execute_save_history_mode(&config.input_dir, *size)
+        },
+        
+        ProcessingMode::ExportLists { filename } => {
+            execute_export_lists_mode(&config.input_dir, filename)
+        },
+        
+        ProcessingMode::Orbits { size } => {
+            let report = crate::orbits::analyze_orbits(&config.input_dir, *size)
+                .context("Error during orbit analysis")?;
+            crate::orbits::save_orbit_report(&config.input_dir, &report)
+                .context("Error saving orbit report")?;
+            Ok(format!("Orbit analysis completed: {} orbits for size {}", report.nb_orbits, size))
+        },
+        
+        ProcessingMode::Verify { size } => {
+            let report = crate::verify::verify_size_files(&config.input_dir, *size)
+                .context("Error during verification")?;
+            warn_if_not_exhaustive(&config.input_dir, *size);
+            let findings = crate::verify::save_verify_report(&config.input_dir, &report)
+                .context("Error saving verification report")?;
+            if report.is_clean() {
+                Ok(format!("Verification completed: all {} lists of size {} are valid", report.lists_checked, size))
+            } else {
+                Err(FunnyError::Integrity(format!("Verification FAILED for size {}: {} invalid lists, {} unreadable files ({} findings)",
+                    size, report.invalid_lists, report.unreadable_files.len(), findings.findings.len())))
+            }
+        },
+        
+        ProcessingMode::FinalReport { size } => {
+            let report = crate::final_report::build_final_report(&config.input_dir, *size)
+                .context("Error building final report")?;
+            crate::final_report::save_final_report(&config.input_dir, &report)
+                .context("Error saving final report")?;
+            Ok(format!("Final report for size {} completed", size))
+        },
+        
+        ProcessingMode::RandomWalk { iterations, rng_seed } => {
+            let report = crate::random_walk::run_random_walk(&config.output_dir, *iterations, *rng_seed)
+                .context("Error during random walk")?;
+            crate::random_walk::save_random_walk_report(&config.output_dir, &report)
+                .context("Error saving random walk report")?;
+            Ok(format!("Random walk completed: best size {} ({} new discoveries)", report.best_size, report.new_discoveries))
+        },
+        
+        ProcessingMode::FilterTarget { target, size } => {
+            let report = crate::filter_target::filter_size_files(&config.input_dir, *size, *target)
+                .context("Error during target filtering")?;
+            Ok(format!("Filter completed: {} of {} size {} lists can reach {} cards ({} files rewritten)",
+                report.lists_after, report.lists_before, report.size, report.target, report.files_rewritten))
+        },
+        
+        ProcessingMode::Stats { size } => {
+            let stats = crate::stats::compute_size_stats(&config.input_dir, *size)
+                .context("Error computing statistics")?;
+            crate::stats::save_size_stats(&config.input_dir, &stats)
+                .context("Error saving statistics")?;
+            Ok(format!("Statistics completed: {} lists of size {}", stats.lists, size))
+        },
+        
+        ProcessingMode::Extract { size, batch, index } => {
+            execute_extract_mode(&config.input_dir, *size, *batch, *index)
+        },
+        
+        ProcessingMode::Sample { size, nb_lists, rng_seed } => {
+            let sample = crate::sample::sample_size_lists(&config.input_dir, *size, *nb_lists, *rng_seed)
+                .context("Error during sampling")?;
+            crate::sample::save_sample(&config.output_dir, *size, &sample)
+                .context("Error saving sample")?;
+            Ok(format!("Sample completed: {} lists of size {}", sample.len(), size))
+        },
+        
+        ProcessingMode::Split { size, max_lists } => {
+            let report = crate::split::split_size_files(&config.input_dir, *size, *max_lists)
+                .context("Error during split")?;
+            Ok(format!("Split completed: {} of {} size {} files split into {} new files (max {} lists)",
+                report.files_split, report.files_checked, report.size, report.files_created, report.max_lists))
+        },
+        
+        ProcessingMode::Migrate { delete_originals } => {
+            let report = crate::migrate::migrate_directory(&config.input_dir, *delete_originals)
+                .context("Error during migration")?;
+            Ok(format!("Migration completed: {} of {} legacy files converted ({} skipped)",
+                report.files_converted, report.files_found, report.files_skipped))
+        },
+        
+        ProcessingMode::Convert { size, format } => {
+            let report = crate::convert::convert_size_files(&config.input_dir, &config.output_dir, *size, *format)
+                .context("Error during conversion")?;
+            Ok(format!("Conversion completed: {} lists of size {} from {} files written to {}",
+                report.lists, report.size, report.files, report.output))
+        },
+        
+        ProcessingMode::Benchmark { max_size } => {
+            let result = crate::benchmark::run_benchmark(&config.output_dir, *max_size)
+                .context("Error during benchmark")?;
+            crate::benchmark::save_benchmark_result(&config.output_dir, &result)
+                .context("Error saving benchmark result")?;
+            Ok(format!("Benchmark completed: {} lists in {:.2}s ({:.0} lists/s)",
+                result.total_lists, result.total_secs, result.lists_per_sec))
+        },
+        
+        ProcessingMode::Estimate { size, batches } => {
+            let estimate = crate::estimate::estimate_size(&config.input_dir, *size, *batches,
+                config.max_lists_per_file, config.strong_prune)
+                .context("Error during estimation")?;
+            crate::estimate::print_estimate(&estimate);
+            Ok(format!("Estimate completed: ~{} lists of size {} expected", estimate.output_lists, estimate.size))
+        },
+        
+        ProcessingMode::Prune { size, archive_dir } => {
+            let report = crate::prune::prune_consumed_inputs(&config.input_dir, &config.output_dir, *size, archive_dir.as_deref(), config.dry_run)
+                .context("Error during prune")?;
+            Ok(format!("Prune {}: {} size {} files {} (input batches up to {:06} consumed)",
+                if config.dry_run { "dry run completed" } else { "completed" }, report.files_pruned, report.size,
+                if config.dry_run { "would be pruned" } else { "pruned" }, report.consumed_up_to))
+        },
+        
+        ProcessingMode::Repair { size } => {
+            let report = crate::repair::repair_size_state(&config.input_dir, *size, config.dry_run)
+                .context("Error during repair")?;
+            Ok(format!("Repair {}: {} state changes for size {} ({} unreadable files)",
+                if config.dry_run { "dry run completed" } else { "completed" },
+                report.changes(), report.size, report.unreadable.len()))
+        },
+        
+        ProcessingMode::Diff { size } => {
+            let diff = crate::state_diff::diff_size_states(&config.input_dir, &config.output_dir, *size)
+                .context("Error during diff")?;
+            crate::state_diff::print_state_diff(&diff)
+                .context("Error printing diff")?;
+            Ok(format!("Diff completed: {} added, {} removed, {} modified entries for size {}",
+                diff.added.len(), diff.removed.len(), diff.modified.len(), diff.size))
+        },
+        
+        ProcessingMode::Archive { size } => {
+            let report = crate::archive::archive_size(&config.input_dir, &config.output_dir, *size)
+                .context("Error during archive")?;
+            Ok(format!("Archive completed: {} files ({} lists) of size {} in {}",
+                report.files, report.total_lists, report.size, report.archive_path))
+        },
+        
+        ProcessingMode::Unarchive { archive } => {
+            let report = crate::archive::unarchive(archive, &config.output_dir)
+                .context("Error during unarchive")?;
+            Ok(format!("Unarchive completed: {} files ({} lists) of size {} restored in {}",
+                report.files, report.total_lists, report.size, config.output_dir))
+        },
+        
+        ProcessingMode::Watch { size, interval_secs } => {
+            execute_watch_mode(config, *size, *interval_secs)
+        },
+        
+        ProcessingMode::Inspect { file } => {
+            let path = Path::new(&config.input_dir).join(file);
+            let report = crate::inspect::inspect_file(&path.to_string_lossy())
+                .file_context("Error inspecting", &path)?;
+            crate::inspect::print_inspect(&report);
+            Ok(format!("Inspect completed: {} lists, {} invalid, {} duplicates",
+                report.nb_lists, report.invalid_lists, report.duplicates))
+        },
+        
+        ProcessingMode::Top { size, count, ranking } => {
+            let report = crate::top::top_lists(&config.input_dir, *size, *count, *ranking)
+                .context("Error during top")?;
+            crate::top::print_and_save_top(&report, &config.input_dir)
+                .context("Error saving the leaderboard")?;
+            Ok(format!("Top completed: best {} of {} lists of size {}",
+                report.entries.len(), report.lists_scanned, report.size))
+        },
+        
+        ProcessingMode::NormalizeFilenames { size } => {
+            let report = crate::normalize::normalize_size_filenames(&config.input_dir, *size)
+                .context("Error normalizing filenames")?;
+            Ok(format!("Normalize completed: {} size {} files renamed, {} conflicts, {} legacy files",
+                report.renamed.len(), report.size, report.conflicts.len(), report.legacy.len()))
+        },
+        
+        ProcessingMode::ValidateChain { from_size, to_size } => {
+            let report = crate::validate_chain::validate_chain(&config.input_dir, *from_size, *to_size)
+                .context("Error validating the chain")?;
+            let findings = crate::validate_chain::save_chain_report(&config.input_dir, *from_size, *to_size, &report)
+                .context("Error saving chain report")?;
+            if report.is_clean() {
+                Ok(format!("Chain validated: sizes {} to {} are consistent", from_size, to_size))
+            } else if findings.status == crate::findings::Status::Warnings {
+                findings.outcome(format!("Chain validated: sizes {} to {} consumed, with gaps in the numbering", from_size, to_size))
+            } else {
+                let broken = report.links.iter().filter(|l| !l.is_clean()).count();
+                Err(FunnyError::Integrity(format!("Chain validation FAILED: {} of {} size pairs inconsistent", broken, report.links.len())))
+            }
+        },
+        
+        ProcessingMode::ExportCards { size, batch } => {
+            execute_export_cards_mode(&config.input_dir, *size, *batch)
+        },
+        
+        ProcessingMode::Gc { size, delete } => {
+            let report = crate::gc::collect_garbage(&config.input_dir, *size, *delete)
+                .context("Error during gc")?;
+            if *delete {
+                Ok(format!("GC completed: {} artifacts deleted, {} bytes freed", report.files_deleted, report.bytes_freed))
+            } else {
+                Ok(format!("GC completed: {} removable artifacts found (use --force to delete them)", report.removable().count()))
+            }
+        },
+        
+        ProcessingMode::Reencode { size } => {
+            let report = crate::reencode::reencode_size_files(&config.input_dir, *size)
+                .context("Error during reencode")?;
+            Ok(format!("Reencode completed: {} of {} size {:02} files rewritten as {}{} ({} -> {} bytes)",
+                report.files_rewritten, report.files_checked, report.size, report.encoding.name(),
+                if report.compressed { " + zstd" } else { "" }, report.bytes_before, report.bytes_after))
+        },
+        
+        ProcessingMode::Checksum { size } => {
+            let report = crate::checksum::checksum_size_files(&config.input_dir, *size)
+                .context("Error during checksum scan")?;
+            if !report.corrupted.is_empty() {
+                return Err(FunnyError::Integrity(format!("{} of {} size {:02} files are corrupted: {}", report.corrupted.len(),
+                    report.files_checked, report.size,
+                    report.corrupted.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(", "))));
+            }
+            Ok(format!("Checksum completed: {} of {} files verified ({} without checksum)",
+                report.files_verified, report.files_checked, report.unchecked.len()))
+        },
+        
+        ProcessingMode::VerifyManifest { size } => {
+            let check = crate::manifest::verify_manifest(&config.input_dir, *size)
+                .context("Error during manifest verification")?;
+            if !check.is_ok() {
+                return Err(FunnyError::Integrity(format!("Size {:02} does not match its manifest: {} mismatched, {} missing files",
+                    check.size, check.mismatched.len(), check.missing.len())));
+            }
+            Ok(format!("Manifest verified: {} of {} files match ({} not in the manifest)",
+                check.files_ok, check.files_checked, check.unlisted.len()))
+        },
+        
+        ProcessingMode::MigrateFormat { size } => {
+            let report = crate::migrate_format::migrate_format_size_files(&config.input_dir, *size)
+                .context("Error during format migration")?;
+            Ok(format!("Format migration completed: {} of {} size {:02} files upgraded ({} lists)",
+                report.files_migrated, report.files_checked, report.size, report.lists_migrated))
+        },
+        
+        ProcessingMode::UpgradeState { size } => {
+            let report = crate::upgrade_state::upgrade_state_files(&config.input_dir, *size)
+                .context("Error during state upgrade")?;
+            Ok(format!("State upgrade completed: {} of {} size {:02} state files upgraded",
+                report.files_upgraded, report.files_checked, report.size))
+        },
+        
+        ProcessingMode::Overview => {
+            let sizes = crate::overview::overview(&config.input_dir)
+                .context("Error during overview")?;
+            let lists: u64 = sizes.iter().map(|s| s.lists).sum();
+            Ok(format!("Overview completed: {} sizes found, {} lists in total", sizes.len(), lists.separated_string()))
+        },
+        
+        ProcessingMode::RestoreState { size, timestamp } => {
+            let report = crate::restore_state::restore_state(&config.input_dir, *size, timestamp.as_deref())
+                .context("Error during state restore")?;
+            Ok(format!("State restore completed: size {:02} state rolled back to {} ({} entries)",
+                report.size, report.timestamp, report.entries.separated_string()))
+        },
+        
+        ProcessingMode::MergeState { size, policy } => {
+            let report = crate::merge_state::merge_size_states(&config.input_dir, &config.output_dir, *size, *policy)
+                .context("Error during state merge")?;
+            crate::merge_state::print_merge_report(&report)
+                .context("Error printing merge report")?;
+            if report.refused() {
+                return Err(FunnyError::StateMismatch { size: report.size,
+                    reason: format!("State merge refused: {} conflicting target batches (see the report)", report.conflicts.len()) });
+            }
+            Ok(format!("State merge completed: {} batches added, {} conflicts settled ({}), {} entries for size {:02}",
+                report.added.len(), report.conflicts.len(), report.policy, report.entries_after, report.size))
+        },
+
+        ProcessingMode::Rebalance { size } => {
+            let report = crate::compaction::rebalance_compacted_files(&config.input_dir, *size, config.max_lists_per_file)
+                .context("Error during rebalance")?;
+            Ok(format!("Rebalance completed: {} compacted files of size {:02} kept, {} rewritten into {} files ({} lists, {} per file)",
+                report.files_kept, report.size, report.files_rewritten, report.files_created,
+                report.lists.separated_string(), report.target_lists.separated_string()))
+        },
+
+        ProcessingMode::Scan { size, workers } => {
+            let report = crate::scan::scan_size_files(&config.input_dir, *size, *workers)
+                .context("Error during scan")?;
+            let findings = crate::scan::save_scan_report(&config.input_dir, &report)
+                .context("Error saving scan report")?;
+            findings.outcome(format!("Scan completed: {} files of size {:02} sound ({} lists)",
+                report.files_scanned, report.size, report.lists_scanned.separated_string()))
+        },
+
+        ProcessingMode::CountAll => {
+            let total = crate::overview::count_all(&config.input_dir, config.force_recount)
+                .context("Error during count-all")?;
+            Ok(format!("Count-all completed: {} sizes, {} lists in {} files", total.sizes.len(),
+                total.lists.separated_string(), total.files.separated_string()))
+        },
+
+        ProcessingMode::HistoryDiff { size } => {
+            let diff = crate::state_diff::diff_size_history(&config.input_dir, *size)
+                .context("Error during history diff")?;
+            crate::state_diff::print_history_diff(&diff)
+                .context("Error printing history diff")?;
+            Ok(format!("History diff completed: {} in the history only, {} in the current state only, {} differing for size {}",
+                diff.history_only.len(), diff.current_only.len(), diff.differing.len(), diff.size))
+        },
+        
+        ProcessingMode::Default => {
+            execute_default_mode(config)
+        },
+    }
+}
+
+/// With --validate-counts, fail if the total of a completed size differs from its expected count
+fn check_expected_count(expected_counts: Option<&BTreeMap<u8, u64>>, directory: &str, size: u8) -> FunnyResult<()> {
+    let Some(expected) = expected_counts else {
+        return Ok(());
+    };
+    let check = crate::validate_counts::validate_size_count(directory, size, expected)
+        .context(&format!("Count validation failed for size {}", size))?;
+    if check.is_mismatch() {
+        return Err(FunnyError::Validation(format!("COUNT MISMATCH for size {}: {} lists produced, {} expected",
+            check.size, check.computed, check.expected.unwrap_or(0))));
+    }
+    Ok(())
+}
+
+/// Execute size mode: process specific size, optionally restarting from a batch
+pub fn execute_size_mode(config: &ProcessingConfig, output_size: u8, start_batch: Option<u32>) -> FunnyResult<String> {
+    use crate::file_info::GlobalFileState;
+    use crate::filenames::list_input_files_with_legacy;
+    use crate::compaction::compact_size_files;
+    
+    if config.dry_run {
+        crate::dry_run::plan_size(&config.input_dir, &config.output_dir, output_size, start_batch,
+            config.max_lists_per_file, config.force_recount)
+            .context(&format!("Error planning size {}", output_size))?
+            .print();
+        return Ok(format!("Size {} dry run completed (nothing modified)", output_size));
+    }
+
+    if let Some(batch) = start_batch {
+        test_print(&format!("RESTART MODE: Resuming output size {} from input batch {}", output_size, batch));
+        handle_force_recount(config.force_recount, &config.output_dir, output_size, config.keep_state)?;
+    } else {
+        test_print(&format!("Target output size = {} cards", output_size));
+    }
+    print_batch_size(config);
+    print_directories(&config.input_dir, &config.output_dir);
+    test_print("\n======================\n");
+
+    let mut no_set_lists = crate::engine::create_engine(&config.input_dir, &config.output_dir, engine_options(config));
+
+    // Handle size 3: create seed lists directly
+    if output_size == 3 {
+        test_print("Creating seed lists (size 3)...");
+        no_set_lists.create_seed_lists();
+        test_print("Seed lists created successfully.\n");
+        return Ok("Seed lists (size 3) created successfully".to_string());
+    }
+
+    // For size 4+, need to create seed lists first if starting from batch 0
+    if output_size == 4 && start_batch.is_none() {
+        test_print("Creating seed lists (size 3)...");
+        // Create seed lists with output to input directory (so they don't pollute output dir)
+        let mut seed_generator = crate::engine::create_engine(&config.input_dir, &config.input_dir, Default::default());
+        seed_generator.create_seed_lists();
+        test_print("Seed lists created successfully.\n");
+    }
+
+    // Input bookkeeping: the output state records each input file consumed, by batch
+    // and kind (a compacted file and a regular file share their batch numbers)
+    let source_size = output_size - 1;
+    let mut global_state = GlobalFileState::from_sources(&config.output_dir, output_size)
+        .context("Failed to load global state")?;
+    record_lists_per_file(&mut global_state, config.max_lists_per_file);
+    record_isomorph_reduced(&mut global_state, &config.input_dir, output_size - 1, config.isomorph_cache)?;
+    record_strong_pruned(&mut global_state, &config.input_dir, output_size - 1, config.strong_prune)?;
+    let last_done = global_state.last_consumed_batch();
+    warn_interrupted_batches(&global_state);
+
+    // Step 1: For sizes 13+, run compaction on input directory before processing
+    // Only on a fresh run: compaction renumbers the input files it merges, which would
+    // break the bookkeeping of the input batches already consumed.
+    if source_size >= 13 {
+        if let Some(last) = last_done {
+            test_print(&format!("\n=== Pre-processing: input compaction skipped (input batches up to {:06} already consumed) ===", last));
+        } else {
+            test_print(&format!("\n=== Pre-processing: Compacting input files (size {}) ===", source_size));
+            match compact_size_files(&config.input_dir, &config.input_dir, source_size, config.max_lists_per_file, None) {
+                Ok(_) => test_print("Input compaction completed successfully.\n"),
+                Err(e) => crate::findings::warn("input_compaction", format!("Input compaction encountered an issue: {}\n", e)),
+            }
+        }
+    }
+
+    // Step 2: Plan the input files from disk
+    // Compacted files come first, then the regular files not merged into them yet
+    // Legacy files (nlist_*) of the batches without a current file are read on the fly
+    let plan = list_input_files_with_legacy(&config.input_dir, source_size);
+    let nb_compacted = plan.iter().filter(|f| f.compacted).count();
+    test_print(&format!("Input plan: {} compacted + {} regular files of size {}",
+        nb_compacted, plan.len() - nb_compacted, source_size));
+    let nb_legacy = plan.iter()
+        .filter(|f| std::path::Path::new(&f.path).file_name()
+            .and_then(|n| crate::migrate::parse_legacy_filename(&n.to_string_lossy())).is_some())
+        .count();
+    if nb_legacy > 0 {
+        test_print(&format!("   ... {} of them legacy files, converted on the fly (--migrate converts them for good)", nb_legacy));
+    }
+
+    // Step 3: Process the requested size, skipping the input files already consumed
+    // (a sharded run skips the inputs consumed whole or for its own shard) and, from
+    // a restart batch, the regular input files below it
+    match start_batch {
+        Some(batch) => test_print(&format!("Start processing from input batch {} to create no-set-lists of size {}:", batch, output_size)),
+        None => test_print(&format!("Start processing files to create no-set-lists of size {}:", output_size)),
+    }
+    let (done, files) = global_state.split_consumed_inputs(plan, config.shard, start_batch);
+    if !done.is_empty() {
+        test_print(&format!("   ... {} input files already processed (from state){}", done.len(),
+            if start_batch.is_some() { " or before the restart batch" } else { "" }));
+    }
+    
+    if output_size >= 13 && crate::compaction::background_compact() {
+        test_print("Background compaction of the outputs enabled");
+        *no_set_lists.compactor() = Some(crate::compaction::BackgroundCompactor::new(&config.output_dir, output_size,
+            config.max_lists_per_file));
+    }
+    if files.is_empty() {
+        test_print("   ... no input files left to process");
+    } else {
+        crate::cascade_status::inputs_planned(files.len() as u64);
+        // The outputs of the consumed inputs stay: new outputs are numbered after them all
+        no_set_lists.process_input_files(source_size, &files, u32::MAX, &config.max_lists_per_file, Some(&mut global_state));
+    }
+    if let Some(mut compactor) = no_set_lists.compactor().take() {
+        compactor.finish(&mut global_state);
+        test_print(&format!("Background compaction: {} compacted files ({} lists) while processing",
+            compactor.files_created, compactor.lists_compacted.separated_string()));
+    }
+    global_state.flush_pending().context("Failed to flush global state")?;
+    if crate::disk_guard::stopped() {
+        return Err(FunnyError::Failed(format!("Size {} stopped on low disk space in {} (state saved)", output_size, config.output_dir)));
+    }
+    
+    test_print(&format!("\nCompleted size {}! Generated files: no-set-list_{:02}_batch_*.rkyv\n", output_size, output_size));
+    
+    // Step 4: For sizes 13+, run compaction on output directory after processing
+    if output_size >= 13 {
+        test_print(&format!("\n=== Post-processing: Compacting output files (size {}) ===", output_size));
+        match compact_size_files(&config.output_dir, &config.output_dir, output_size, config.max_lists_per_file, None) {
+            Ok(_) => {
+                test_print("Output compaction completed successfully.\n");
+                // Note: compact_size_files already exports human-readable files (JSON/TXT)
+            },
+            Err(e) => crate::findings::warn("output_compaction", format!("Output compaction encountered an issue: {}\n", e)),
+        }
+    } else {
+        // For sizes < 13, no compaction runs, so we need to export human-readable files here
+        test_print(&format!("\nExporting global state files for size {}...", output_size));
+        match global_state.export_human_readable() {
+            Ok(_) => test_print(&format!("Exported: {}/nsl_{:02}_global_info.json and .txt\n", config.output_dir, output_size)),
+            Err(e) => crate::findings::warn("export", format!("Failed to export JSON/TXT: {}\n", e)),
+        }
+    }
+    
+    // Save history at the end
+    test_print(&format!("\nSaving historical state for size {}...", output_size));
+    let history_config = ProcessingConfig {
+        mode: ProcessingMode::SaveHistory { size: output_size },
+        input_dir: config.output_dir.clone(),
+        output_dir: String::new(),
+        max_lists_per_file: config.max_lists_per_file,
+        memory_limit_gb: config.memory_limit_gb,
+        force_recount: false,
+        keep_state: false,
+        strong_prune: false,
+        isomorph_cache: false,
+        expected_counts: None,
+        shard: None,
+        dry_run: false,
+    };
+    match execute_mode(&history_config) {
+        Ok(_) => test_print("Historical state saved successfully.\n"),
+        Err(e) => crate::findings::warn("history", format!("Failed to save history: {}\n", e)),
+    }
+    write_run_manifest(&config.output_dir, output_size);
+    
+    check_expected_count(config.expected_counts.as_ref(), &config.output_dir, output_size)?;
+    
+    if let Some(start_batch) = start_batch {
+        Ok(format!("Size {} processing completed (restarted from batch {})", output_size, start_batch))
+    } else {
+        Ok(format!("Size {} processing completed", output_size))
+    }
+}
+
+/// Execute unitary mode: process a single input batch
+pub fn execute_unitary_mode(config: &ProcessingConfig, unitary_size: u8, unitary_batch: u32) -> FunnyResult<String> {
+    run_unitary(config, unitary_size, unitary_batch, None)
+}
+
+/// Process the input batch `unitary_batch`: the file `input` if given (--watch hands
+/// out files, a regular and a compacted file may share a batch number), else the
+/// file found for the batch
+fn run_unitary(config: &ProcessingConfig, unitary_size: u8, unitary_batch: u32, input: Option<&crate::filenames::InputFile>) -> FunnyResult<String> {
+    use crate::file_info::GlobalFileState;
+    
+    test_print(&format!("UNITARY MODE: Processing input size {} batch {}", unitary_size, unitary_batch));
+    test_print(&format!("Output: size {} files", unitary_size + 1));
+    print_batch_size(config);
+    print_directories(&config.input_dir, &config.output_dir);
+    
+    handle_force_recount(config.force_recount, &config.output_dir, unitary_size + 1, config.keep_state)?;
+    test_print("\n======================\n");
+
+    let mut no_set_lists = crate::engine::create_engine(&config.input_dir, &config.output_dir, engine_options(config));
+    let target_size = unitary_size + 1;
+    let mut global_state = GlobalFileState::from_sources(&config.output_dir, target_size)
+        .context("Failed to load global state")?;
+    record_lists_per_file(&mut global_state, config.max_lists_per_file);
+    record_isomorph_reduced(&mut global_state, &config.input_dir, unitary_size, config.isomorph_cache)?;
+    record_strong_pruned(&mut global_state, &config.input_dir, unitary_size, config.strong_prune)?;
+    
+    test_print(&format!("Processing input size {} batch {}:", unitary_size, unitary_batch));
+    match input {
+        Some(input) => no_set_lists.process_single_input(unitary_size, input, &config.max_lists_per_file, Some(&mut global_state)),
+        None => no_set_lists.process_single_batch(unitary_size, unitary_batch, &config.max_lists_per_file, Some(&mut global_state)),
+    };
+    global_state.flush_pending().context("Failed to flush global state")?;
+    
+    // Export human-readable state files
+    test_print(&format!("\nExporting global state files for size {}...", target_size));
+    match global_state.export_human_readable() {
+        Ok(_) => test_print(&format!("Exported: {}/nsl_{:02}_global_info.json and .txt\n", config.output_dir, target_size)),
+        Err(e) => crate::findings::warn("export", format!("Failed to export JSON/TXT: {}\n", e)),
+    }
+    
+    // Save history at the end
+    test_print(&format!("\nSaving historical state for size {}...", target_size));
+    let history_config = ProcessingConfig {
+        mode: ProcessingMode::SaveHistory { size: target_size },
+        input_dir: config.output_dir.clone(),
+        output_dir: String::new(),
+        max_lists_per_file: config.max_lists_per_file,
+        memory_limit_gb: config.memory_limit_gb,
+        force_recount: false,
+        keep_state: false,
+        strong_prune: false,
+        isomorph_cache: false,
+        expected_counts: None,
+        shard: None,
+        dry_run: false,
+    };
+    match execute_mode(&history_config) {
+        Ok(_) => test_print("Historical state saved successfully.\n"),
+        Err(e) => crate::findings::warn("history", format!("Failed to save history: {}\n", e)),
+    }
+    write_run_manifest(&config.output_dir, target_size);
+    
+    Ok(format!("Unitary processing completed for size {} batch {}", unitary_size, unitary_batch))
+}
+
+/// Execute watch mode: process each new input batch of `input_size` in unitary style
+pub fn execute_watch_mode(config: &ProcessingConfig, input_size: u8, interval_secs: u64) -> FunnyResult<String> {
+    use crate::watch::{WatchTracker, stop_requested, WATCH_STOP_FILE};
+
+    test_print(&format!("WATCH MODE: Processing new input batches of size {} as they appear", input_size));
+    print_directories(&config.input_dir, &config.output_dir);
+    test_print(&format!("   Poll interval: {} s; stop by creating {}/{}", interval_secs, config.output_dir, WATCH_STOP_FILE));
+
+    let mut tracker = WatchTracker::new(&config.input_dir, &config.output_dir, input_size)
+        .context(&format!("Failed to load the size {} state", input_size + 1))?;
+    test_print(&format!("   ... {} input batches already processed", tracker.nb_done()));
+
+    let mut processed = 0u64;
+    let mut failed = 0u64;
+    'watch: loop {
+        if stop_requested(&config.output_dir) {
+            break;
+        }
+        for input in tracker.ready_inputs() {
+            let batch = input.batch;
+            test_print(&format!("\n--- New input batch {:06}{} of size {} ---", batch,
+                if input.compacted { " (compacted)" } else { "" }, input_size));
+            let unitary_config = ProcessingConfig {
+                mode: ProcessingMode::Unitary { size: input_size, batch },
+                input_dir: config.input_dir.clone(),
+                output_dir: config.output_dir.clone(),
+                max_lists_per_file: config.max_lists_per_file,
+                memory_limit_gb: config.memory_limit_gb,
+                force_recount: false,
+                keep_state: config.keep_state,
+                strong_prune: config.strong_prune,
+                isomorph_cache: config.isomorph_cache,
+                expected_counts: None,
+                shard: config.shard,
+                dry_run: false,
+            };
+            match run_unitary(&unitary_config, input_size, batch, Some(&input)) {
+                Ok(_) => {
+                    tracker.mark_done(&input);
+                    processed += 1;
+                }
+                Err(e) => {
+                    test_print(&format!("   ... batch {:06} failed ({}); it will not be retried in this run", batch, e));
+                    tracker.mark_failed(&input);
+                    failed += 1;
+                }
+            }
+            if stop_requested(&config.output_dir) {
+                break 'watch;
+            }
+        }
+        std::thread::sleep(std::time::Duration::from_secs(interval_secs));
+    }
+
+    Ok(format!("Watch stopped: {} input batches of size {} processed, {} failed", processed, input_size, failed))
+}
+
+/// Warn about the input batches whose processing was interrupted: their outputs are
+/// kept and the batch is not processed again (its remaining lists are missing)
+fn warn_interrupted_batches(state: &crate::file_info::GlobalFileState) {
+    for batch in state.interrupted_batches() {
+        test_print(&format!("   ... WARNING: input batch {:06} was interrupted (outputs saved, input not consumed): \
+            remove its outputs and run --unitary on it to complete it", batch));
+    }
+}
+
+/// Find the last regular input batch consumed by `output_size` in the output directory
+/// (the numbering of a restart batch): from its state, else from the highest source
+/// batch of the output files
+/// Returns None if nothing was consumed yet
+fn find_max_source_batch(output_dir: &str, output_size: u8) -> Option<u32> {
+    use std::fs;
+    
+    if let Ok(state) = crate::dry_run::load_state_readonly(output_dir, output_size)
+        && let Some(last) = state.last_consumed_regular_batch() {
+        return Some(last);
+    }
+    
+    let entries = match fs::read_dir(output_dir) {
+        Ok(e) => e,
+        Err(_) => return None,
+    };
+    
+    let pattern = format!("_to_{:02}_batch_", output_size);
+    let mut max_source_batch: Option<u32> = None;
+    
+    for entry in entries.flatten() {
+        if let Some(name) = entry.file_name().to_str()
+            && name.starts_with("nsl_") && name.contains(&pattern) && name.ends_with(".rkyv") {
+            // Parse source batch from filename: nsl_{size}_batch_{source_batch}_to_...
+            if let Some(to_pos) = name.find("_to_") {
+                let before_to = &name[..to_pos];
+                if let Some(batch_pos) = before_to.rfind("_batch_") {
+                    let batch_str = &before_to[batch_pos + 7..];
+                    if let Ok(source_batch) = batch_str.parse::<u32>() {
+                        max_source_batch = Some(
+                            max_source_batch.map_or(source_batch, |current| current.max(source_batch))
+                        );
+                    }
+                }
+            }
+        }
+    }
+    
+    max_source_batch
+}
+
+/// Refresh the manifest of the list files of `size` at the end of a run (see manifest)
+fn write_run_manifest(output_dir: &str, size: u8) {
+    if crate::storage::storage_backend() != crate::storage::StorageBackend::Files {
+        return; // files stored in a database have no bytes of their own to hash
+    }
+    if let Err(e) = crate::manifest::write_manifest(output_dir, size) {
+        crate::findings::warn("manifest", format!("Failed to write the manifest: {}\n", e));
+    }
+}
+
+/// Execute save-history mode: merge current state with historical state
+pub fn execute_save_history_mode(input_dir: &str, size: u8) -> FunnyResult<String> {
+    use crate::file_info::GlobalFileState;
+    use std::path::Path;
+    
+    test_print("\n=================================================================");
+    test_print(&format!("SAVE HISTORY MODE - Size {}", size));
+    test_print(&format!("Directory: {}", input_dir));
+    test_print("=================================================================\n");
+    
+    // Load current state
+    test_print("Loading current state...");
+    let current_state = GlobalFileState::from_sources(input_dir, size)
+        .context("Failed to load current state")?;
+    let current_count = current_state.entries().len();
+    test_print(&format!("   Current state: {} entries", current_count));
+    
+    // Try to load existing history
+    let history_rkyv_path = Path::new(input_dir).join(format!("nsl_{:02}_global_info_history.rkyv", size));
+    let history_json_path = Path::new(input_dir).join(format!("nsl_{:02}_global_info_history.json", size));
+    
+    let mut historical_state = if history_rkyv_path.exists() {
+        test_print("Loading existing history from rkyv...");
+        GlobalFileState::from_history_file(input_dir, size, "rkyv")
+            .context("Failed to load history from rkyv")?
+    } else if history_json_path.exists() {
+        test_print("Loading existing history from JSON...");
+        GlobalFileState::from_history_file(input_dir, size, "json")
+            .context("Failed to load history from JSON")?
+    } else {
+        test_print("No existing history found, creating new historical state...");
+        GlobalFileState::new(input_dir, size)
+    };
+    
+    if current_state.max_lists_per_file().is_some() {
+        historical_state.set_max_lists_per_file(current_state.max_lists_per_file());
+    }
+    
+    let initial_history_count = historical_state.entries().len();
+    test_print(&format!("   Historical state: {} entries", initial_history_count));
+    
+    // Remove entries from history that were removed from current state (tombstones
+    // are persisted with the state, so this covers the removals of every run)
+    let tombstones = current_state.tombstones();
+    let mut removed_count = 0;
+    if !tombstones.is_empty() {
+        test_print(&format!("\nApplying {} tombstones to history...", tombstones.len()));
+        for tombstone in tombstones.values() {
+            if historical_state.apply_tombstone(tombstone) {
+                removed_count += 1;
+            }
+        }
+        test_print(&format!("   Removed: {} entries from history", removed_count));
+    }
+    
+    // Merge current state into historical state
+    test_print("\nMerging current state into history...");
+    historical_state.merge_consumed_inputs(current_state.consumed_inputs());
+    let mut added_count = 0;
+    let mut updated_count = 0;
+    
+    for ((src, tgt, filename), info) in current_state.entries().iter() {
+        if historical_state.has_entry(filename, *src, *tgt) {
+            // Entry exists, update it (in case counts changed)
+            historical_state.update_entry(
+                filename,
+                *src,
+                *tgt,
+                info.nb_lists_in_file,
+                info.compacted,
+                info.file_size_bytes,
+                info.modified_timestamp,
+            );
+            updated_count += 1;
+        } else {
+            // New entry, add it
+            historical_state.register_file(
+                filename,
+                *src,
+                *tgt,
+                info.nb_lists_in_file,
+                info.compacted,
+                info.file_size_bytes,
+                info.modified_timestamp,
+            );
+            added_count += 1;
+        }
+    }
+    
+    let final_history_count = historical_state.entries().len();
+    
+    test_print(&format!("   Added: {} new entries", added_count));
+    test_print(&format!("   Updated: {} existing entries", updated_count));
+    if removed_count > 0 {
+        test_print(&format!("   Removed: {} consumed entries", removed_count));
+    }
+    test_print(&format!("   Total historical entries: {}", final_history_count));
+    
+    // Save historical state as triplet
+    test_print("\nSaving historical state...");
+    historical_state.flush_as_history()
+        .context("Failed to save historical state")?;
+    historical_state.export_human_readable_as_history()
+        .context("Failed to export historical JSON/TXT")?;
+    
+    test_print(&format!("   Saved: {}", history_rkyv_path.display()));
+    test_print(&format!("   Saved: {}", history_json_path.display()));
+    test_print(&format!("   Saved: {}", Path::new(input_dir).join(format!("nsl_{:02}_global_info_history.txt", size)).display()));
+    
+    test_print("\n=================================================================");
+    test_print("SAVE HISTORY COMPLETED");
+    test_print("=================================================================\n");
+    
+    if removed_count > 0 {
+        Ok(format!("History saved: {} total entries ({} added, {} updated, {} removed)", 
+            final_history_count, added_count, updated_count, removed_count))
+    } else {
+        Ok(format!("History saved: {} total entries ({} added, {} updated)", 
+            final_history_count, added_count, updated_count))
+    }
+}
+
+/// Execute export-lists mode: write each matching rkyv file as .txt and .json
+/// The filename may contain wildcards (e.g. "nsl_*_to_05_batch_*.rkyv").
+pub fn execute_export_lists_mode(input_dir: &str, filename: &str) -> FunnyResult<String> {
+    use crate::io_helpers::load_lists_from_file;
+    use crate::no_set_list::NoSetList;
+    use std::path::Path;
+    use wildmatch::WildMatch;
+
+    test_print("\n=================================================================");
+    test_print(&format!("EXPORT LISTS MODE - Files matching {}", filename));
+    test_print(&format!("Directory: {}", input_dir));
+    test_print("=================================================================\n");
+
+    let pattern = WildMatch::new(filename);
+    let mut names: Vec<String> = std::fs::read_dir(input_dir)
+        .file_context("Cannot read directory", input_dir)?
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str().map(|n| n.to_string()))
+        .filter(|name| name.ends_with(".rkyv") && pattern.matches(name))
+        .collect();
+    names.sort();
+
+    if names.is_empty() {
+        return Err(FunnyError::InvalidArgument(format!("No rkyv file matching {} in {}", filename, input_dir)));
+    }
+
+    for name in names.iter() {
+        let path = Path::new(input_dir).join(name);
+        let lists = load_lists_from_file(&path.to_string_lossy())
+            .file_context("Failed to read", &path)?;
+
+        let txt: String = lists.iter()
+            .map(|l| NoSetList::from_serialized(l).to_string() + "\n")
+            .collect();
+        let json = serde_json::to_string_pretty(&lists)
+            .map_err(|e| FunnyError::Failed(format!("Failed to encode {} as JSON: {}", name, e)))?;
+        std::fs::write(path.with_extension("txt"), txt)
+            .file_context("Failed to write", path.with_extension("txt"))?;
+        std::fs::write(path.with_extension("json"), json)
+            .file_context("Failed to write", path.with_extension("json"))?;
+        test_print(&format!("   Exported {:>10} lists from {}", lists.len().separated_string(), name));
+    }
+
+    Ok(format!("Exported {} file(s) to .txt and .json", names.len()))
+}
+
+/// Execute export-cards mode: write the lists of one file as readable card descriptions
+/// Output: <file stem>_cards.txt next to the file, one block per list.
+pub fn execute_export_cards_mode(input_dir: &str, size: u8, batch: u32) -> FunnyResult<String> {
+    use crate::filenames::find_input_filename;
+    use crate::set::Card;
+    use std::io::{BufWriter, Write};
+    use std::path::Path;
+
+    let path = find_input_filename(input_dir, size, batch)
+        .ok_or_else(|| FunnyError::InvalidArgument(format!("No size {} file with batch {:06} in {}", size, batch, input_dir)))?;
+    let output = Path::new(&path).with_extension("").to_string_lossy().into_owned() + "_cards.txt";
+    test_print(&format!("\nEXPORT CARDS MODE: {} -> {}", path, output));
+
+    let file = std::fs::File::create(&output)
+        .file_context("Failed to create", &output)?;
+    let mut writer = BufWriter::new(file);
+    let mut index = 0u64;
+    crate::io_helpers::load_lists_in_chunks(&path, 1_000_000, |chunk| {
+        for list in chunk.iter() {
+            writeln!(writer, "#{} (n = {}, max_card = {}, {} remaining cards)",
+                index, list.n, list.max_card, list.remaining_cards_list.len())?;
+            for &card in list.no_set_list.iter() {
+                writeln!(writer, "   {:>2}  {}", card, Card::decode(card))?;
+            }
+            writeln!(writer)?;
+            index += 1;
+        }
+        Ok(())
+    }).file_context("Failed to export", &path)?;
+    writer.flush().file_context("Failed to write", &output)?;
+
+    Ok(format!("Exported {} lists of size {} batch {:06} to {}", index.separated_string(), size, batch, output))
+}
+
+/// Execute extract mode: print one stored list in index and decoded card form
+pub fn execute_extract_mode(input_dir: &str, size: u8, batch: u32, index: u64) -> FunnyResult<String> {
+    use crate::filenames::find_input_filename;
+    use crate::io_helpers::load_lists_cached;
+    use crate::set::{index_to_base3, Card};
+
+    let path = find_input_filename(input_dir, size, batch)
+        .ok_or_else(|| FunnyError::InvalidArgument(format!("No size {} file with batch {:06} in {}", size, batch, input_dir)))?;
+    let lists = load_lists_cached(&path)
+        .file_context("Failed to read", &path)?;
+    let list = lists.get(index as usize)
+        .ok_or_else(|| FunnyError::InvalidArgument(format!("Index {} out of range: {} holds {} lists", index, path, lists.len())))?;
+
+    test_print(&format!("\nFile: {}", path));
+    test_print(&format!("List #{} of {} (n = {}, max_card = {})", index, lists.len(), list.n, list.max_card));
+    test_print(&format!("   no_set_list:          {:?}", list.no_set_list));
+    test_print(&format!("   remaining_cards_list: {:?}", list.remaining_cards_list));
+    for (title, cards) in [("Cards", &list.no_set_list), ("Remaining cards", &list.remaining_cards_list)] {
+        test_print(&format!("\n   {} (index  base-3  card):", title));
+        for &card in cards.iter() {
+            let digits: String = index_to_base3(card).iter().map(|d| d.to_string()).collect();
+            test_print(&format!("   {:>5}  {}  {}", card, digits, Card::decode(card)));
+        }
+    }
+
+    Ok(format!("Extracted list {} of size {} batch {:06}", index, size, batch))
+}
+
+/// Execute cascade mode: process all sizes starting from a given input size
+/// Process the input sizes of `steps` in order (output sizes `steps` + 1), each with its flags
+pub fn execute_cascade_mode(steps: &[(u8, StepFlags)], roots: &crate::cascade_roots::CascadeRoots, retry: CascadeRetry, max_lists_per_file: u64, expected_counts: Option<&BTreeMap<u8, u64>>, dry_run: bool) -> FunnyResult<String> {
+    use std::path::Path;
+    
+    test_print("\n=================================================================");
+    test_print(&format!("CASCADE MODE - Output sizes {}",
+        steps.iter().map(|(input_size, _)| (input_size + 1).to_string()).collect::<Vec<_>>().join(", ")));
+    test_print(&format!("Root directory: {}", roots.describe()));
+    let root_directory = roots.main();
+    test_print("=================================================================\n");
+    
+    let mut total_sizes_processed = 0;
+    let mut total_commands_executed = 0;
+    let mut failed_sizes: Vec<u8> = Vec::new();
+    let mut stopped = false;
+    let mut upstream_changed = false; // an earlier size had work: the later ones have new inputs
+    if !dry_run {
+        let sizes: Vec<u8> = steps.iter().map(|(input_size, _)| input_size + 1).collect();
+        crate::cascade_status::start(root_directory, &sizes);
+        crate::cascade_journal::set_active(Some(crate::cascade_journal::CascadeJournal::open(root_directory)));
+        test_print(&format!("Status file: {}/{}", root_directory, crate::cascade_status::CASCADE_STATUS_FILE));
+    }
+    
+    // Process each size asked for (output sizes up to 20)
+    for (step, &(input_size, flags)) in steps.iter().enumerate() {
+        let output_size = input_size + 1;
+        
+        test_print(&format!("\n--- Step {}: Processing size {} (from input size {}) ---",
+            step + 1, output_size, input_size));
+        
+        // Get directories
+        let (input_dir, output_dir) = roots.directories(input_size);
+
+        // The seed lists are created in the input directory by size 4 (see execute_size_mode)
+        if input_size == 3 && !dry_run && !Path::new(&input_dir).exists() {
+            test_print(&format!("   Seed directory does not exist, creating: {}", input_dir));
+            std::fs::create_dir_all(&input_dir)
+                .file_context("Failed to create seed directory", &input_dir)?;
+        }
+
+        // Check if input directory exists
+        if !Path::new(&input_dir).exists() {
+            test_print(&format!("   Input directory does not exist: {}", input_dir));
+            test_print(&format!("   Skipping size {}", output_size));
+            continue;
+        }
+        
+        // With --dry-run: plan this size and go on with the next one
+        if dry_run {
+            let start_batch = find_max_source_batch(&output_dir, output_size).map(|b| b + 1);
+            let mut plan = crate::dry_run::plan_size(&input_dir, &output_dir, output_size, start_batch, max_lists_per_file, flags.force)
+                .context(&format!("Error planning size {}", output_size))?;
+            if !Path::new(&output_dir).exists() {
+                plan.note(format!("output directory {} created", output_dir));
+            }
+            plan.print();
+            total_commands_executed += 1;
+            continue;
+        }
+
+        // Check if output directory exists, create if not
+        if !Path::new(&output_dir).exists() {
+            test_print(&format!("   Output directory does not exist, creating: {}", output_dir));
+            std::fs::create_dir_all(&output_dir)
+                .file_context("Failed to create output directory", &output_dir)?;
+        }
+        
+        // Free space: the earlier sizes may be pruned to make room (--cascade-low-space prune)
+        let earlier: Vec<(String, String, u8)> = (3..input_size)
+            .map(|size| {
+                let (size_input, size_output) = roots.directories(size);
+                (size_input, size_output, size)
+            })
+            .filter(|(size_input, size_output, _)| Path::new(size_input).exists() && Path::new(size_output).exists())
+            .collect();
+        if !crate::disk_guard::room_before_size(&output_dir, &earlier) {
+            crate::findings::warn("low_disk_space", format!("Cascade stopped before size {}: not enough disk space in {}; \
+                free some space and resume with --cascade {} {}", output_size, output_dir, input_size, roots.resume_args()));
+            stopped = true;
+            break;
+        }
+
+        // Completed in an earlier run (cascade journal): nothing new to process
+        let journaled = crate::cascade_journal::with_active(|journal| journal.progress(output_size).cloned()).flatten();
+        if journaled.as_ref().is_some_and(|progress| progress.completed) && !upstream_changed {
+            test_print(&format!("   Size {} completed by an earlier run (cascade journal): skipped", output_size));
+            continue;
+        }
+        upstream_changed = true;
+        crate::cascade_status::size_started(output_size);
+        crate::cascade_journal::with_active(|journal| journal.start_size(output_size));
+
+        // Each try resumes from the last processed batch (--cascade-retries)
+        let result = run_with_retries(retry, output_size, || {
+            // Find the last processed batch: journaled, else from the outputs
+            let last_processed = crate::cascade_journal::with_active(|journal| journal.progress(output_size).and_then(|p| p.last_batch))
+                .flatten()
+                .or_else(|| find_max_source_batch(&output_dir, output_size));
+            let next_batch = match last_processed {
+                Some(batch) => batch + 1,
+                None => 0,
+            };
+            
+            test_print(&format!("   Last processed input batch: {}",
+                last_processed.map_or("none".to_string(), |b| format!("{:06}", b))));
+            test_print(&format!("   Next batch to process: {:06}", next_batch));
+            test_print(&format!("   Input directory:  {}", input_dir));
+            test_print(&format!("   Output directory: {}", output_dir));
+            
+            test_print(&format!("\n   Processing: --size {} {} -i \"{}\" -o \"{}\"{}{}\n",
+                output_size, next_batch, input_dir, output_dir,
+                if flags.force { " --force" } else { "" }, if flags.keep_state { " --keep_state" } else { "" }));
+            
+            // Build configuration for this size (call internal functions directly)
+            let size_config = ProcessingConfig {
+                mode: ProcessingMode::Size { 
+                    size: output_size, 
+                    start_batch: if next_batch > 0 { Some(next_batch) } else { None }
+                },
+                input_dir: input_dir.clone(),
+                output_dir: output_dir.clone(),
+                max_lists_per_file,
+                memory_limit_gb: None,
+                force_recount: flags.force,
+                keep_state: flags.keep_state,
+                strong_prune: false,
+                isomorph_cache: false,
+                expected_counts: expected_counts.cloned(),
+                shard: None,
+                dry_run: false,
+            };
+            
+            // Execute the size mode directly (same as if user entered the command)
+            execute_mode(&size_config)
+        });
+        crate::cascade_status::size_ended(output_size, result.is_ok());
+        if result.is_ok()
+            && let Some(Err(e)) = crate::cascade_journal::with_active(|journal| journal.complete_size()) {
+            test_print(&format!("   ... Warning: could not write to the cascade journal: {}", e));
+        }
+        match result {
+            Ok(_) => {
+                test_print(&format!("\n   ✓ Size {} processing completed successfully\n", output_size));
+                
+                // Save history for this size
+                test_print(&format!("   Saving historical state for size {}...", output_size));
+                let history_config = ProcessingConfig {
+                    mode: ProcessingMode::SaveHistory { size: output_size },
+                    input_dir: output_dir.clone(),
+                    output_dir: String::new(),
+                    max_lists_per_file,
+                    memory_limit_gb: None,
+                    force_recount: false,
+                    keep_state: false,
+                    strong_prune: false,
+                    isomorph_cache: false,
+                    expected_counts: None,
+                    shard: None,
+                    dry_run: false,
+                };
+                match execute_mode(&history_config) {
+                    Ok(_) => test_print("   Historical state saved.\n"),
+                    Err(e) => crate::findings::warn("history", format!("Failed to save history: {}\n", e)),
+                }
+                
+                total_sizes_processed += 1;
+            }
+            Err(e) if crate::disk_guard::stopped() => {
+                crate::findings::warn("low_disk_space", format!("{}; free some space and resume with --cascade {} {}",
+                    e, input_size, roots.resume_args()));
+                stopped = true;
+                break;
+            }
+            Err(e) if retry.skip_failed => {
+                test_print(&format!("\n   ✗ Size {} processing failed: {}\n", output_size, e));
+                test_print("   Size left out (--cascade-skip-failed); going on with the next sizes.\n");
+                failed_sizes.push(output_size);
+            }
+            Err(e) => {
+                test_print(&format!("\n   ✗ Size {} processing failed: {}\n", output_size, e));
+                test_print("   Stopping cascade at this point.\n");
+                failed_sizes.push(output_size);
+                break;
+            }
+        }
+        
+        total_commands_executed += 1;
+    }
+    
+    test_print("\n=================================================================");
+    test_print("CASCADE MODE COMPLETED");
+    test_print(&format!("Sizes processed: {}", total_sizes_processed));
+    test_print(&format!("Commands executed: {}", total_commands_executed));
+    test_print("=================================================================\n");
+    
+    crate::cascade_journal::set_active(None);
+    crate::cascade_status::finish(if !failed_sizes.is_empty() { "failed" } else if stopped { "stopped" } else { "completed" });
+    if !failed_sizes.is_empty() && retry.skip_failed {
+        return Err(FunnyError::Failed(format!("Cascade mode completed: {} sizes processed, sizes {:?} failed (left out)",
+            total_sizes_processed, failed_sizes)));
+    }
+    Ok(format!("Cascade mode completed: {} sizes processed", total_sizes_processed))
+}
+
+/// Seconds to wait before retry `attempt` + 1 (the delay doubles at each retry)
+fn retry_delay(retry: CascadeRetry, attempt: u32) -> u64 {
+    retry.delay_secs.saturating_mul(1u64 << attempt.min(16))
+}
+
+/// Run `step` until it succeeds, fails with an error not worth retrying, or has
+/// been retried `retry.retries` times (--cascade-retries)
+fn run_with_retries<T>(retry: CascadeRetry, output_size: u8, mut step: impl FnMut() -> FunnyResult<T>) -> FunnyResult<T> {
+    let mut attempt = 0;
+    loop {
+        match step() {
+            Err(e) if e.is_retryable() && attempt < retry.retries && !crate::disk_guard::stopped() => {
+                let delay = retry_delay(retry, attempt);
+                attempt += 1;
+                test_print(&format!("\n   ✗ Size {} processing failed: {}", output_size, e));
+                test_print(&format!("   Retry {} of {} in {}s\n", attempt, retry.retries, delay));
+                std::thread::sleep(std::time::Duration::from_secs(delay));
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Execute default mode: process the whole pipeline (seeds + sizes 4 to 20)
+pub fn execute_default_mode(config: &ProcessingConfig) -> FunnyResult<String> {
+    use crate::file_info::GlobalFileState;
+    
+    test_print("   - will create          58.896 no-set-lists with  3 cards");
+    test_print("   - will create       1.004.589 no-set-lists with  4 cards");
+    test_print("   - will create      13.394.538 no-set-lists with  5 cards");
+    test_print("   - will create     141.370.218 no-set-lists with  6 cards");
+    test_print("   - will create   1.180.345.041 no-set-lists with  7 cards");
+    test_print("   - will create   7.920.450.378 no-set-lists with  8 cards");
+    test_print("   - will create  43.126.538.805 no-set-lists with  9 cards");
+    test_print("   - will create 193.375.848.191 no-set-lists with 10 cards");
+    test_print("   - will create  __.___.___.___ no-set-lists with 11 cards");
+    test_print("   - will create  __.___.___.___ no-set-lists with 12 cards");
+    test_print("   - will create  __.___.___.___ no-set-lists with 13 cards");
+    test_print("   - will create  __.___.___.___ no-set-lists with 14 cards");
+    test_print("   - will create  __.___.___.___ no-set-lists with 15 cards");
+    test_print("   - will create  __.___.___.___ no-set-lists with 16 cards");
+    test_print("   - will create  __.___.___.___ no-set-lists with 17 cards");
+    test_print("   - will create  __.___.___.___ no-set-lists with 18 cards");
+    test_print("   - will create  __.___.___.___ no-set-lists with 19 cards");
+    test_print("   - will create  __.___.___.___ no-set-lists with 20 cards");
+    test_print("\n======================\n");
+
+    let mut no_set_lists = crate::engine::create_engine(&config.input_dir, &config.input_dir,
+        crate::engine::EngineOptions { shard: None, ..engine_options(config) });
+
+    // Create all seed lists
+    test_print("Creating seed lists...");
+    no_set_lists.create_seed_lists();
+
+    // Expand from seed_lists to size 4, 5, 6...
+    for size in 3..19 {
+        let target_size = size + 1;
+        let mut global_state = GlobalFileState::from_sources(&config.output_dir, target_size)
+            .context("Failed to load global state")?;
+        record_lists_per_file(&mut global_state, config.max_lists_per_file);
+        record_isomorph_reduced(&mut global_state, &config.output_dir, size, config.isomorph_cache)?;
+        record_strong_pruned(&mut global_state, &config.output_dir, size, config.strong_prune)?;
+        test_print(&format!("\nStart processing files to create no-set-lists of size {}:", target_size));
+        no_set_lists.process_all_files_of_current_size_n(size, &config.max_lists_per_file, Some(&mut global_state));
+        global_state.flush_pending().context("Failed to flush global state")?;
+        
+        // Export human-readable state files for this size
+        test_print(&format!("Exporting global state files for size {}...", target_size));
+        match global_state.export_human_readable() {
+            Ok(_) => test_print(&format!("Exported: {}/nsl_{:02}_global_info.json and .txt", config.output_dir, target_size)),
+            Err(e) => crate::findings::warn("export", format!("Failed to export JSON/TXT: {}", e)),
+        }
+        check_expected_count(config.expected_counts.as_ref(), &config.output_dir, target_size)?;
+    }
+    
+    Ok("Default pipeline completed (sizes 3-20)".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builder_checks_and_defaults_like_the_command_line() {
+        let config = ProcessingConfig::size(15).input("data").start_batch(0).build().expect("config");
+        assert!(matches!(config.mode, ProcessingMode::Size { size: 15, start_batch: None }));
+        assert_eq!((config.input_dir.as_str(), config.output_dir.as_str()), ("data", "data"));
+        assert_eq!(config.max_lists_per_file, MAX_NLISTS_PER_FILE);
+        let config = ProcessingConfig::check(12).output("out").deep(true).threads(8).memory_limit_gb(16).build().expect("config");
+        assert!(matches!(config.mode, ProcessingMode::Check { deep: Some(8), .. }));
+        assert_eq!(config.max_lists_per_file, lists_per_file_for_memory(16));
+        let config = ProcessingConfig::check(12).deep(true).build().expect("config");
+        assert!(matches!(config.mode, ProcessingMode::Check { deep: Some(0), .. }));
+
+        let kind = |builder: ProcessingConfigBuilder| builder.build().map(|_| ()).unwrap_err().kind();
+        assert_eq!(kind(ProcessingConfig::size(21)), "validation");
+        assert_eq!(kind(ProcessingConfig::size(3).start_batch(2)), "validation");
+        assert_eq!(kind(ProcessingConfig::count(5).start_batch(2)), "validation");
+        assert_eq!(kind(ProcessingConfig::count(5).dry_run(true)), "config");
+        assert_eq!(kind(ProcessingConfig::check(12).threads(8)), "config"); // threads does not turn --deep on
+        assert_eq!(kind(ProcessingConfig::size(15).threads(8)), "config");
+    }
+
+    #[test]
+    fn memory_limit_is_split_between_output_lists_and_batch_cache() {
+        let list_bytes = std::mem::size_of::<crate::no_set_list::NoSetList>() as u64;
+        let lists = lists_per_file_for_memory(16);
+        assert!(lists * list_bytes <= 8 << 30 && (lists + 1) * list_bytes > 8 << 30, "half of the RAM buffers the output lists");
+        assert_eq!(lists_per_file_for_memory(32), 2 * lists);
+        assert_eq!(lists_per_file_for_memory(0), crate::io_helpers::FRAME_LISTS as u64, "at least one frame per file");
+
+        assert_eq!(batch_cache_for_memory(4, 256, lists), 4, "small requests fit");
+        let capped = batch_cache_for_memory(1000, 16, lists);
+        assert!((1..1000).contains(&capped), "a quarter of the RAM caches batches");
+        assert!(batch_cache_for_memory(1000, 64, lists) >= 4 * capped);
+        assert!(batch_cache_for_memory(1000, 16, lists / 2) >= 2 * capped, "smaller batches, more of them");
+        assert_eq!(batch_cache_for_memory(8, 16, 0), batch_cache_for_memory(8, 16, 1), "empty batches count as one list");
+    }
+
+    #[test]
+    fn cascade_steps_stop_at_and_skip_the_sizes_asked_for() {
+        let flags = StepFlags { force: false, keep_state: false };
+        let sizes = |stop_at, skip_sizes: &[u8]| -> Vec<u8> {
+            cascade_steps(14, stop_at, skip_sizes, &BTreeMap::new(), flags).iter().map(|(input_size, _)| input_size + 1).collect()
+        };
+        assert_eq!(sizes(20, &[]), vec![15, 16, 17, 18, 19, 20]);
+        assert_eq!(sizes(17, &[16]), vec![15, 17]);
+        assert_eq!(sizes(16, &[15, 16]), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn cascade_step_overrides_apply_on_top_of_the_command_line_flags() {
+        let defaults = StepFlags { force: true, keep_state: false };
+        let values = ["16:no-force,keep-state".to_string(), "17:no-force".to_string(), "16:force".to_string()];
+        let overrides = parse_cascade_steps(&values, defaults).expect("parse");
+        assert_eq!(overrides.get(&16), Some(&StepFlags { force: true, keep_state: true }), "later values add to earlier ones");
+        assert_eq!(overrides.get(&17), Some(&StepFlags { force: false, keep_state: false }));
+
+        let steps = cascade_steps(14, 18, &[], &overrides, defaults);
+        assert_eq!(steps.iter().map(|(input_size, flags)| (input_size + 1, flags.force, flags.keep_state)).collect::<Vec<_>>(),
+            vec![(15, true, false), (16, true, true), (17, false, false), (18, true, false)]);
+
+        assert!(parse_cascade_steps(&["16".to_string()], defaults).is_err());
+        assert!(parse_cascade_steps(&["x:force".to_string()], defaults).is_err());
+        assert!(parse_cascade_steps(&["16:fast".to_string()], defaults).is_err());
+    }
+
+    #[test]
+    fn failed_cascade_steps_are_retried_with_backoff() {
+        let retry = CascadeRetry { retries: 2, delay_secs: 5, skip_failed: false };
+        assert_eq!((0..4).map(|attempt| retry_delay(retry, attempt)).collect::<Vec<_>>(), vec![5, 10, 20, 40]);
+
+        let retry = CascadeRetry { delay_secs: 0, ..retry };
+        let run = |failures: u32, error: fn() -> FunnyError| {
+            let mut calls = 0;
+            let result = run_with_retries(retry, 15, || {
+                calls += 1;
+                if calls <= failures { Err(error()) } else { Ok(calls) }
+            });
+            (result.map_err(|e| e.kind()), calls)
+        };
+        let transient = || FunnyError::Failed("NAS hiccup".to_string());
+        assert_eq!(run(2, transient), (Ok(3), 3));
+        assert_eq!(run(3, transient), (Err("failed"), 3), "given up after 2 retries");
+        assert_eq!(run(1, || FunnyError::Validation("bad input".to_string())), (Err("validation"), 1), "not retryable");
+    }
+
+    #[test]
+    fn builder_runs_a_mode_and_returns_its_summary() {
+        let _lock = crate::findings::RUN_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = crate::test_dir::TestDir::new("modes_builder");
+
+        let summary = ProcessingConfig::count(5).input(dir.to_string_lossy()).run().expect("run");
+        assert_eq!((summary.mode.as_str(), summary.error_kind.as_deref()), ("count", None));
+    }
+
+    #[test]
+    fn each_run_reports_its_own_warnings_and_activity() {
+        use crate::no_set_list::NoSetListSerialized;
+        let _lock = crate::findings::RUN_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = crate::test_dir::TestDir::new("modes_run_activity");
+        let list = NoSetListSerialized { n: 4, max_card: 4, no_set_list: vec![0, 1, 3, 4], remaining_cards_list: (9..81).collect() };
+        assert!(crate::io_helpers::save_to_file_serialized(&vec![list],
+            &dir.join("nsl_03_batch_000000_to_04_batch_000000.rkyv").to_string_lossy()));
+
+        crate::findings::warn("previous_run", "raised before the run");
+        let summary = ProcessingConfig::size(5).input(dir.str()).run().expect("run");
+        assert!(summary.warnings.iter().all(|w| w.kind != "previous_run"));
+        // Other tests may run the engine meanwhile: only what this run did is looked for
+        let read = summary.activity.sizes.get(&4).expect("size 4 read");
+        assert!(read.files_read >= 1 && read.lists_read >= 1);
+        assert!(summary.activity.sizes.get(&5).is_some_and(|written| written.files_written >= 1));
+        assert!(summary.activity.files_created.iter().any(|f| f.contains("nsl_04_batch_000000_to_05_batch_")));
+        let json = serde_json::to_value(&summary).expect("json");
+        assert!(json["sizes"]["4"]["lists_read"].as_u64().is_some_and(|n| n >= 1));
+    }
+
+    #[test]
+    fn pruned_and_exhaustive_files_are_not_mixed() {
+        use crate::file_info::GlobalFileState;
+        let dir = crate::test_dir::TestDir::new("modes_strong_pruned");
+        let mut inputs = GlobalFileState::new(&dir.str(), 4);
+        inputs.set_strong_pruned(true);
+        inputs.flush().expect("flush");
+
+        // Pruned inputs make pruned outputs, even without --strong-prune
+        let mut state = GlobalFileState::new(&dir.str(), 5);
+        record_strong_pruned(&mut state, &dir.str(), 4, false).expect("empty size");
+        assert!(state.strong_pruned());
+
+        let mut state = GlobalFileState::new(&dir.str(), 6);
+        state.register_file("nsl_05_batch_000000_to_06_batch_000000.rkyv", 0, 0, 10, false, None, None);
+        assert!(matches!(record_strong_pruned(&mut state, &dir.str(), 5, true), Err(FunnyError::Validation(_))));
+        record_strong_pruned(&mut state, &dir.str(), 5, false).expect("exhaustive run");
+        assert!(!state.strong_pruned());
+    }
+}
@@ -1,17 +1,17 @@
-/// Stack-optimized NoSetList using fixed-size arrays
-/// 
-/// This module provides a zero-heap-allocation implementation using
-/// fixed-size stack arrays instead of Vec<usize>. This eliminates all heap
-/// allocations during the core algorithm execution, providing significant
-/// performance improvements through:
-/// - Elimination of malloc/free overhead
-/// - Better cache locality (stack data)
-/// - Predictable memory layout
-/// - No heap fragmentation
-///
-/// Maximum sizes:
-/// - no_set_list: 20 cards (maximum we search for)
-/// - remaining_cards_list: 81 cards (full deck)
+//! Stack-optimized NoSetList using fixed-size arrays
+//! 
+//! This module provides a zero-heap-allocation implementation using
+//! fixed-size stack arrays instead of Vec<usize>. This eliminates all heap
+//! allocations during the core algorithm execution, providing significant
+//! performance improvements through:
+//! - Elimination of malloc/free overhead
+//! - Better cache locality (stack data)
+//! - Predictable memory layout
+//! - No heap fragmentation
+//!
+//! Maximum sizes:
+//! - no_set_list: 20 cards (maximum we search for)
+//! - remaining_cards_list: 81 cards (full deck)
 
 use crate::set::*;
 use std::cmp::min;
@@ -41,6 +41,44 @@ pub struct NoSetList {
     pub remaining_cards_list_len: u8,
 }
 
+/// String representation of the no-set-list
+impl std::fmt::Display for NoSetList {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // check there are at least 3 cards in no-set-list
+        if self.no_set_list_len < 3 {
+            return f.write_str("invalid");
+        }
+    
+        // build no-set-list message
+        let mut nsl_msg = "(".to_string();
+        for i in 0..self.no_set_list_len {
+            let card = self.no_set_list[i as usize];
+            nsl_msg.push_str(&format!("{:>2}", card));
+            if i + 1 < self.no_set_list_len {
+                nsl_msg.push('.');
+            }
+        }
+        nsl_msg.push(')');
+    
+        // build remaining cards list message
+        let mut rcl_msg = "[".to_string();
+        if self.remaining_cards_list_len == 0 {
+            rcl_msg.push_str("...");
+        } else {
+            for i in 0..self.remaining_cards_list_len {
+                rcl_msg.push_str(&format!("{:>2}", self.remaining_cards_list[i as usize]));
+                if i + 1 < self.remaining_cards_list_len {
+                    rcl_msg.push('.');
+                }
+            }
+        }
+        rcl_msg.push(']');
+    
+        // consolidate the whole string
+        write!(f, "{:>2}-list: max={:>2} : {}+{}", self.size, self.max_card, nsl_msg, rcl_msg)
+    }
+}
+
 impl NoSetList {
     /// Create a new NoSetList with empty arrays
     pub fn new() -> Self {
@@ -91,42 +129,6 @@ impl NoSetList {
         &self.remaining_cards_list[..self.remaining_cards_list_len as usize]
     }
     
-    /// Return a string representation of the no-set-list
-    pub fn to_string(&self) -> String {
-        // check there are at least 3 cards in no-set-list
-        if self.no_set_list_len < 3 {
-            return "invalid".to_string();
-        }
-        
-        // build no-set-list message
-        let mut nsl_msg = "(".to_string();
-        for i in 0..self.no_set_list_len {
-            let card = self.no_set_list[i as usize];
-            nsl_msg.push_str(&format!("{:>2}", card));
-            if i + 1 < self.no_set_list_len {
-                nsl_msg.push_str(".");
-            }
-        }
-        nsl_msg.push_str(")");
-        
-        // build remaining cards list message
-        let mut rcl_msg = "[".to_string();
-        if self.remaining_cards_list_len == 0 {
-            rcl_msg.push_str("...");
-        } else {
-            for i in 0..self.remaining_cards_list_len {
-                rcl_msg.push_str(&format!("{:>2}", self.remaining_cards_list[i as usize]));
-                if i + 1 < self.remaining_cards_list_len {
-                    rcl_msg.push_str(".");
-                }
-            }
-        }
-        rcl_msg.push_str("]");
-        
-        // consolidate the whole string
-        format!("{:>2}-list: max={:>2} : {}+{}", self.size, self.max_card, nsl_msg, rcl_msg)
-    }
-    
     /// Build all possible (n+1)-no-set-lists from this n-no-set-list
     /// 
     /// This is the stack-optimized version that eliminates ALL heap allocations
@@ -140,7 +142,10 @@ impl NoSetList {
     /// 
     /// # Returns
     /// Vector of new (n+1)-no-set-lists (Vec allocation unavoidable for return)
-    pub fn build_higher_nsl(&self) -> Vec<NoSetList> {
+    /// 
+    /// With `strong_prune`, a list is also dropped when `extension_upper_bound`
+    /// proves its remaining cards cannot bring it to 12 cards.
+    pub fn build_higher_nsl(&self, strong_prune: bool) -> Vec<NoSetList> {
         // Pre-allocate capacity based on remaining cards for 5-10% speedup
         // Most of the time, we generate < remaining_cards results due to pruning
         let estimated_capacity = self.remaining_cards_list_len as usize;
@@ -204,7 +209,13 @@ impl NoSetList {
             // CHECK: Pruning threshold (need enough cards to reach 12)
             // ================================================================
             let cards_needed = 12 - min(n_plus_1_len as usize, 12);
-            if (remaining_len as usize) >= cards_needed {
+            let reachable = (remaining_len as usize) >= cards_needed
+                && (!strong_prune || cards_needed == 0
+                    || extension_upper_bound(
+                        &n_plus_1_primary[..n_plus_1_len as usize],
+                        &n_plus_1_remaining[..remaining_len as usize],
+                    ) >= cards_needed);
+            if reachable {
                 // Valid (n+1)-no-set-list found - create and store it
                 let n_plus_1_nsl = NoSetList {
                     size: self.size + 1,
@@ -224,6 +235,37 @@ impl NoSetList {
     }
 }
 
+/// Upper bound on the number of remaining cards that can be added to a list
+/// 
+/// Two remaining cards r and q with q = next_to_set(p, r) for a card p of the
+/// list can never both be added. Each pair of a greedy matching of such pairs
+/// excludes at least one card, so |remaining| - |matching| is a sound bound
+/// (tighter than |remaining|, still never below the real maximum).
+pub fn extension_upper_bound(no_set: &[usize], remaining: &[usize]) -> usize {
+    let mut available: u128 = 0;
+    for &r in remaining {
+        available |= 1u128 << r;
+    }
+    
+    let mut matched_pairs = 0;
+    for &r in remaining {
+        if available & (1u128 << r) == 0 {
+            continue;
+        }
+        for &p in no_set {
+            let q = next_to_set(p, r);
+            if q != r && available & (1u128 << q) != 0 {
+                // r and q exclude each other: remove both from the pool
+                available &= !((1u128 << r) | (1u128 << q));
+                matched_pairs += 1;
+                break;
+            }
+        }
+    }
+    
+    remaining.len() - matched_pairs
+}
+
 impl Default for NoSetList {
     fn default() -> Self {
         Self::new()
@@ -286,10 +328,204 @@ impl NoSetList {
     }
 }
 
+// ============================================================================
+// NoSetListDelta: delta-encoded serialization format
+// ============================================================================
+
+/// NoSetListDelta: delta-encoded variant of NoSetListSerialized
+///
+/// Both card lists are strictly increasing and cards are < 81, so each card is
+/// stored as its difference with the previous one in a single byte:
+/// - no_set_deltas: first card absolute, then differences
+/// - remaining_deltas: differences, the first one relative to max_card
+///
+/// max_card is not stored (it is the last card of the list).
+/// Roughly 3x smaller than NoSetListSerialized (4 bytes per card with size_32).
+#[derive(Clone)]
+#[derive(Archive, RkyvSerialize, RkyvDeserialize)]
+#[archive(check_bytes)]
+pub struct NoSetListDelta {
+    pub n: u8,
+    pub no_set_deltas: Vec<u8>,
+    pub remaining_deltas: Vec<u8>,
+}
+
+impl NoSetListDelta {
+    /// Delta-encode a NoSetListSerialized (card lists must be strictly increasing)
+    pub fn from_serialized(serialized: &NoSetListSerialized) -> Self {
+        let mut previous = 0;
+        let no_set_deltas = serialized.no_set_list.iter().map(|&c| {
+            let delta = (c - previous) as u8;
+            previous = c;
+            delta
+        }).collect();
+        let mut previous = serialized.max_card;
+        let remaining_deltas = serialized.remaining_cards_list.iter().map(|&c| {
+            let delta = (c - previous) as u8;
+            previous = c;
+            delta
+        }).collect();
+        NoSetListDelta { n: serialized.n, no_set_deltas, remaining_deltas }
+    }
+
+    /// Decode back to NoSetListSerialized
+    pub fn to_serialized(&self) -> NoSetListSerialized {
+        decode_delta(self.n, &self.no_set_deltas, &self.remaining_deltas)
+    }
+}
+
+impl ArchivedNoSetListSerialized {
+    /// Build the stack-based NoSetList straight from the archived (memory-mapped)
+    /// representation, without the intermediate heap Vecs
+    pub fn to_no_set_list(&self) -> NoSetList {
+        let mut nsl = NoSetList::new();
+        nsl.size = self.n;
+        nsl.max_card = self.max_card as usize;
+        for (slot, &card) in nsl.no_set_list.iter_mut().zip(self.no_set_list.iter()) {
+            *slot = card as usize;
+        }
+        nsl.no_set_list_len = self.no_set_list.len().min(20) as u8;
+        for (slot, &card) in nsl.remaining_cards_list.iter_mut().zip(self.remaining_cards_list.iter()) {
+            *slot = card as usize;
+        }
+        nsl.remaining_cards_list_len = self.remaining_cards_list.len().min(78) as u8;
+        nsl
+    }
+}
+
+impl ArchivedNoSetListDelta {
+    /// Decode straight from the archived (memory-mapped) representation
+    pub fn to_serialized(&self) -> NoSetListSerialized {
+        decode_delta(self.n, &self.no_set_deltas, &self.remaining_deltas)
+    }
+}
+
+fn decode_delta(n: u8, no_set_deltas: &[u8], remaining_deltas: &[u8]) -> NoSetListSerialized {
+    let mut card = 0;
+    let no_set_list: Vec<usize> = no_set_deltas.iter().map(|&d| {
+        card += d as usize;
+        card
+    }).collect();
+    let max_card = no_set_list.last().copied().unwrap_or(0);
+    let mut card = max_card;
+    let remaining_cards_list = remaining_deltas.iter().map(|&d| {
+        card += d as usize;
+        card
+    }).collect();
+    NoSetListSerialized { n, max_card, no_set_list, remaining_cards_list }
+}
+
+// ============================================================================
+// NoSetListPacked: bit-packed serialization format
+// ============================================================================
+
+/// Bytes of a bit-packed list
+pub const PACKED_LIST_BYTES: usize = 24;
+
+/// Bytes of an 81-bit card mask
+pub const MASK_BYTES: usize = 11;
+
+/// NoSetListPacked: bit-packed variant of NoSetListSerialized (fixed 24 bytes)
+///
+/// A list is fully determined by its cards and its remaining cards: each set is
+/// stored as an 81-bit mask (card c is bit c % 8 of byte c / 8, 11 bytes), followed
+/// by n and max_card (one byte each, checked against the masks when decoding).
+/// The size of a list no longer depends on its number of cards.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct NoSetListPacked(pub [u8; PACKED_LIST_BYTES]);
+
+impl NoSetListPacked {
+    /// Pack a NoSetListSerialized (cards must be < 81)
+    pub fn from_serialized(serialized: &NoSetListSerialized) -> Self {
+        let mut bytes = [0u8; PACKED_LIST_BYTES];
+        for &c in serialized.no_set_list.iter() {
+            bytes[c / 8] |= 1 << (c % 8);
+        }
+        for &c in serialized.remaining_cards_list.iter() {
+            bytes[MASK_BYTES + c / 8] |= 1 << (c % 8);
+        }
+        bytes[2 * MASK_BYTES] = serialized.n;
+        bytes[2 * MASK_BYTES + 1] = serialized.max_card as u8;
+        NoSetListPacked(bytes)
+    }
+
+    /// 81-bit mask of the cards of the list
+    pub fn cards_mask(&self) -> &[u8] {
+        &self.0[..MASK_BYTES]
+    }
+
+    /// 81-bit mask of the remaining cards
+    pub fn remaining_mask(&self) -> &[u8] {
+        &self.0[MASK_BYTES..2 * MASK_BYTES]
+    }
+
+    /// Decode a packed list from its bytes (None if they are not 24 bytes, or
+    /// inconsistent: n or max_card not matching the cards, or cards >= 81)
+    pub fn decode(bytes: &[u8]) -> Option<NoSetListSerialized> {
+        let bytes: &[u8; PACKED_LIST_BYTES] = bytes.try_into().ok()?;
+        NoSetListPacked(*bytes).to_serialized()
+    }
+
+    /// Decode back to NoSetListSerialized (None if inconsistent, see decode)
+    pub fn to_serialized(self) -> Option<NoSetListSerialized> {
+        let cards = |mask: &[u8]| -> Vec<usize> {
+            (0..MASK_BYTES * 8).filter(|&c| mask[c / 8] & (1 << (c % 8)) != 0).collect()
+        };
+        let no_set_list = cards(self.cards_mask());
+        let remaining_cards_list = cards(self.remaining_mask());
+        let n = self.0[2 * MASK_BYTES];
+        let max_card = self.0[2 * MASK_BYTES + 1] as usize;
+        let in_range = no_set_list.iter().chain(remaining_cards_list.iter()).all(|&c| c < 81);
+        if !in_range || no_set_list.len() != n as usize || no_set_list.last().copied().unwrap_or(0) != max_card {
+            return None;
+        }
+        Some(NoSetListSerialized { n, max_card, no_set_list, remaining_cards_list })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    
+
+    #[test]
+    fn test_delta_encoding_round_trip() {
+        let serialized = NoSetListSerialized {
+            n: 4,
+            max_card: 40,
+            no_set_list: vec![0, 1, 3, 40],
+            remaining_cards_list: vec![41, 45, 80],
+        };
+        let delta = NoSetListDelta::from_serialized(&serialized);
+        assert_eq!(delta.no_set_deltas, vec![0, 1, 2, 37]);
+        assert_eq!(delta.remaining_deltas, vec![1, 4, 35]);
+        let decoded = delta.to_serialized();
+        assert_eq!(decoded.n, 4);
+        assert_eq!(decoded.max_card, 40);
+        assert_eq!(decoded.no_set_list, serialized.no_set_list);
+        assert_eq!(decoded.remaining_cards_list, serialized.remaining_cards_list);
+    }
+
+    #[test]
+    fn test_packed_encoding_round_trip() {
+        let serialized = NoSetListSerialized {
+            n: 4,
+            max_card: 40,
+            no_set_list: vec![0, 1, 3, 40],
+            remaining_cards_list: vec![41, 45, 80],
+        };
+        let packed = NoSetListPacked::from_serialized(&serialized);
+        assert_eq!(packed.0[0], 0b1011);
+        assert_eq!(packed.0[MASK_BYTES + 10], 1); // card 80
+        let decoded = NoSetListPacked::decode(&packed.0).expect("consistent");
+        assert_eq!((decoded.n, decoded.max_card), (4, 40));
+        assert_eq!(decoded.no_set_list, serialized.no_set_list);
+        assert_eq!(decoded.remaining_cards_list, serialized.remaining_cards_list);
+
+        let mut corrupt = packed;
+        corrupt.0[2] = 0xFF; // 8 more cards than n
+        assert!(corrupt.to_serialized().is_none());
+    }
+
     #[test]
     fn test_from_slices() {
         let nsl = NoSetList::from_slices(3, 42, &[10, 20, 30], &[43, 44, 45]);
@@ -311,6 +547,26 @@ mod tests {
         assert_eq!(nsl1.no_set_slice(), nsl2.no_set_slice());
     }
     
+    #[test]
+    fn test_extension_upper_bound_is_sound() {
+        // Greedy extensions of seeds never add more cards than the bound
+        for (i, j, k) in [(0, 1, 3), (5, 17, 40), (2, 30, 61)] {
+            let seed = NoSetList::from_slices(3, k, &[i, j, k], &[]);
+            let forbidden = [next_to_set(i, j), next_to_set(i, k), next_to_set(j, k)];
+            let remaining: Vec<usize> = ((k + 1)..81).filter(|c| !forbidden.contains(c)).collect();
+            let bound = extension_upper_bound(seed.no_set_slice(), &remaining);
+            assert!(bound <= remaining.len());
+            
+            let mut list = vec![i, j, k];
+            for &c in remaining.iter() {
+                if (0..list.len()).all(|a| (a + 1..list.len()).all(|b| next_to_set(list[a], list[b]) != c)) {
+                    list.push(c);
+                }
+            }
+            assert!(list.len() - 3 <= bound, "extension of {} cards > bound {}", list.len() - 3, bound);
+        }
+    }
+    
     #[test]
     fn test_to_string() {
         let nsl = NoSetList::from_slices(3, 20, &[10, 15, 20], &[21, 22, 23]);
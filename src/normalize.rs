@@ -0,0 +1,170 @@
+//! Normalize module: rename the files of a size to the canonical filename scheme
+//!
+//! Directories written by older versions mix 5-digit and 6-digit batch numbers,
+//! which find_input_filename does not match. This module renames them to
+//! nsl_{src:02}_batch_{src_batch:06}_to_{tgt:02}_batch_{tgt_batch:06}[_compacted][_shard_KKofMM].rkyv.
+//!
+//! Key features:
+//! - Every nsl_* list file of the size parsed and renamed if its name differs
+//!   from the canonical one (the _compacted and shard tags are kept)
+//! - Each rename is followed by the matching state update and a flush, so the
+//!   state never lags more than one file behind (--repair heals a crash in between)
+//! - Filenames rewritten in the intermediate count files of the size
+//! - A rename onto an existing file is refused and reported
+//! - Legacy nlist_* files are only reported (they need --migrate)
+//!
+//! Used by --normalize-filenames mode
+
+use std::path::Path;
+
+use crate::file_info::{GlobalFileState, RemovalReason};
+use crate::filenames::{parse_filename, strip_shard_tag};
+use crate::utils::*;
+
+/// Outcome of a normalization
+#[derive(Debug, Clone, Default)]
+pub struct NormalizeReport {
+    pub size: u8,
+    pub renamed: Vec<(String, String)>,    // (old name, new name)
+    pub conflicts: Vec<(String, String)>,  // (name, canonical name already taken)
+    pub legacy: Vec<String>,               // nlist_* files (see --migrate)
+    pub state_entries_updated: u64,
+    pub count_files_updated: u64,
+}
+
+/// Canonical name of a list filename (None if it is not an nsl_* list filename)
+pub fn canonical_filename(name: &str) -> Option<String> {
+    let parsed = parse_filename(name)?;
+    with_target_batch(name, parsed.target_batch)
+}
+
+/// Canonical name of a list filename moved to `target_batch` (None if it is not an nsl_* list filename)
+pub fn with_target_batch(name: &str, target_batch: u32) -> Option<String> {
+    let parsed = parse_filename(name)?;
+    let stem = name.strip_suffix(".rkyv")?;
+    let shard_tag = &stem[strip_shard_tag(stem).len()..];
+    Some(format!("nsl_{:02}_batch_{:06}_to_{:02}_batch_{:06}{}{}.rkyv",
+        parsed.source_size, parsed.source_batch, parsed.target_size, target_batch,
+        if parsed.compacted { "_compacted" } else { "" }, shard_tag))
+}
+
+/// Replace the renamed filenames in the intermediate count files of `size`
+pub fn rewrite_count_files(dir: &str, size: u8, renamed: &[(String, String)]) -> std::io::Result<u64> {
+    let prefix = format!("nsl_{:02}_intermediate_count_from_", size);
+    let mut updated = 0;
+    for entry in std::fs::read_dir(dir)?.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !name.starts_with(&prefix) || !name.ends_with(".txt") {
+            continue;
+        }
+        let text = std::fs::read_to_string(entry.path())?;
+        let mut new_text = String::with_capacity(text.len());
+        for line in text.lines() {
+            let mut line = line.to_string();
+            if let Some(last) = line.split_whitespace().last()
+                && let Some((_, new)) = renamed.iter().find(|(old, _)| old == last) {
+                line = format!("{}{}", &line[..line.len() - last.len()], new);
+            }
+            new_text.push_str(&line);
+            new_text.push('\n');
+        }
+        if new_text != text {
+            let tmp = entry.path().with_extension("txt.tmp");
+            std::fs::write(&tmp, &new_text)?;
+            std::fs::rename(&tmp, entry.path())?;
+            updated += 1;
+        }
+    }
+    Ok(updated)
+}
+
+/// Rename the size `size` files of `dir` to the canonical scheme, updating the state as it goes
+pub fn normalize_size_filenames(dir: &str, size: u8) -> std::io::Result<NormalizeReport> {
+    test_print(&format!("\nNORMALIZE MODE: Renaming the size {:02} files to the canonical scheme...", size));
+    test_print(&format!("   Directory: {}", dir));
+
+    let mut state = GlobalFileState::from_sources(dir, size)?;
+    let mut report = NormalizeReport { size, ..Default::default() };
+    let legacy_prefixes = [format!("nlist_{:02}_", size), format!("nlist_v31_{:02}_", size)];
+
+    let mut names: Vec<String> = std::fs::read_dir(dir)?.flatten()
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    for name in names.iter() {
+        if legacy_prefixes.iter().any(|p| name.starts_with(p.as_str())) {
+            report.legacy.push(name.clone());
+            continue;
+        }
+        let Some(parsed) = parse_filename(name) else { continue };
+        if parsed.target_size != size {
+            continue;
+        }
+        let Some(canonical) = canonical_filename(name) else { continue };
+        if &canonical == name {
+            continue;
+        }
+        let target = Path::new(dir).join(&canonical).to_string_lossy().into_owned();
+        if crate::storage::list_file_exists(&target) {
+            test_print(&format!("   ... {} not renamed: {} already exists", name, canonical));
+            report.conflicts.push((name.clone(), canonical));
+            continue;
+        }
+        let old_path = Path::new(dir).join(name).to_string_lossy().into_owned();
+        crate::storage::rename_list_file(&old_path, &target)?;
+        crate::io_helpers::invalidate_cached_batch(&old_path);
+
+        let recorded = state.entries().values()
+            .find(|e| &e.filename == name)
+            .cloned();
+        if let Some(entry) = recorded {
+            state.remove_file(name, entry.source_batch, entry.target_batch, RemovalReason::Manual);
+            state.register_file(&canonical, entry.source_batch, entry.target_batch, entry.nb_lists_in_file,
+                entry.compacted, entry.file_size_bytes, entry.modified_timestamp);
+            state.flush()?;
+            report.state_entries_updated += 1;
+        }
+        debug_print(&format!("   ... renamed {} -> {}", name, canonical));
+        report.renamed.push((name.clone(), canonical));
+    }
+
+    if !report.renamed.is_empty() {
+        report.count_files_updated = rewrite_count_files(dir, size, &report.renamed)?;
+        state.export_human_readable()?;
+    }
+    for name in report.legacy.iter() {
+        test_print(&format!("   ... legacy file {} left as is (convert it with --migrate)", name));
+    }
+    test_print(&format!("   ... {} files renamed ({} state entries, {} count files updated), {} conflicts, {} legacy files",
+        report.renamed.len(), report.state_entries_updated, report.count_files_updated,
+        report.conflicts.len(), report.legacy.len()));
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_made_canonical_and_state_follows() {
+        assert_eq!(canonical_filename("nsl_13_batch_00042_to_14_batch_01234_compacted.rkyv").as_deref(),
+            Some("nsl_13_batch_000042_to_14_batch_001234_compacted.rkyv"));
+        assert_eq!(canonical_filename("nsl_13_batch_42_to_14_batch_7_shard_01of04.rkyv").as_deref(),
+            Some("nsl_13_batch_000042_to_14_batch_000007_shard_01of04.rkyv"));
+        assert_eq!(canonical_filename("nlist_14_batch_000001.rkyv"), None);
+
+        let dir = crate::test_dir::TestDir::new("normalize");
+        let dir_str = dir.to_string_lossy().into_owned();
+        std::fs::write(dir.join("nsl_03_batch_00000_to_04_batch_00001.rkyv"), b"lists").expect("write");
+        let mut state = GlobalFileState::new(&dir_str, 4);
+        state.register_file("nsl_03_batch_00000_to_04_batch_00001.rkyv", 0, 1, 9, false, None, None);
+        state.flush().expect("flush");
+
+        let report = normalize_size_filenames(&dir_str, 4).expect("normalize");
+        assert_eq!(report.renamed.len(), 1);
+        assert!(dir.join("nsl_03_batch_000000_to_04_batch_000001.rkyv").exists());
+        let state = GlobalFileState::from_sources(&dir_str, 4).expect("state");
+        let entry = state.entries().values().next().expect("entry");
+        assert_eq!((entry.filename.as_str(), entry.nb_lists_in_file), ("nsl_03_batch_000000_to_04_batch_000001.rkyv", 9));
+    }
+}
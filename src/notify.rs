@@ -0,0 +1,255 @@
+//! Notify module: tell someone away that a long run ended
+//!
+//! A cascade or a size runs for days; its end (or its failure) should not wait
+//! for someone to look at the console. With --notify, the run sends a short JSON
+//! summary to webhooks or by mail.
+//!
+//! Key features:
+//! - Targets (--notify, repeatable): http://HOST[:PORT]/PATH or https://... (POST
+//!   through ureq), smtp://HOST[:PORT]/TO (through lettre, to a relay that needs
+//!   no authentication; sender funny@HOST)
+//! - Needs a build with the `notify` feature (cargo build --release --features
+//!   notify); without it the command line refuses --notify
+//! - Events: completed, failed (end of --cascade, --size and --compact, with the
+//!   run summary), low_disk (cascade or compaction short of space, once per
+//!   episode)
+//! - Payload: event, mode, host, time, message, and the run summary if any
+//! - A notification that cannot be sent is reported and never fails the run
+//!
+//! Used by --cascade, --size and --compact modes
+
+use std::sync::Mutex;
+#[cfg(feature = "notify")]
+use std::time::Duration;
+use serde::Serialize;
+
+use crate::findings::RunSummary;
+use crate::utils::*;
+
+/// Time allowed to each network operation of a notification
+#[cfg(feature = "notify")]
+const NOTIFY_TIMEOUT_SECS: u64 = 20;
+
+// Targets and mode of the run (--notify)
+static TARGETS: Mutex<(Vec<String>, String)> = Mutex::new((Vec::new(), String::new()));
+
+/// Send the notifications of the run of `mode` to `targets`
+pub fn configure(targets: &[String], mode: &str) {
+    *TARGETS.lock().unwrap_or_else(|e| e.into_inner()) = (targets.to_vec(), mode.to_string());
+}
+
+/// Check a --notify target
+pub fn parse_target(target: &str) -> Result<(), String> {
+    if is_webhook(target) || parse_smtp_target(target).is_some() {
+        Ok(())
+    } else {
+        Err(format!("Invalid --notify target '{}' (http://HOST/PATH, https://..., smtp://HOST[:PORT]/TO)", target))
+    }
+}
+
+/// What is sent
+#[derive(Debug, Clone, Serialize)]
+pub struct Notification {
+    pub event: String,
+    pub mode: String,
+    pub host: String,
+    pub at: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<RunSummary>,
+}
+
+/// Send `event` with `message` (and the run `summary`) to every target, if any
+pub fn notify(event: &str, message: &str, summary: Option<&RunSummary>) {
+    let (targets, mode) = TARGETS.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    if targets.is_empty() {
+        return;
+    }
+    let notification = Notification {
+        event: event.to_string(),
+        mode,
+        host: crate::file_info::hostname(),
+        at: chrono::Local::now().to_rfc3339(),
+        message: message.to_string(),
+        summary: summary.cloned(),
+    };
+    let Ok(json) = serde_json::to_string(&notification) else { return };
+    for target in &targets {
+        match send(target, &notification, &json) {
+            Ok(()) => test_print(&format!("   Notification '{}' sent to {}", event, target)),
+            Err(e) => test_print(&format!("   Warning: notification '{}' not sent to {}: {}", event, target, e)),
+        }
+    }
+}
+
+#[cfg(feature = "notify")]
+fn send(target: &str, notification: &Notification, json: &str) -> std::io::Result<()> {
+    if is_webhook(target) {
+        return post_json(target, json);
+    }
+    if let Some((host, port, to)) = parse_smtp_target(target) {
+        let subject = format!("[funny] {} {} on {}", notification.mode, notification.event, notification.host);
+        return send_mail(&host, port, &to, &subject, json);
+    }
+    Err(std::io::Error::other("unknown target"))
+}
+
+#[cfg(not(feature = "notify"))]
+fn send(_target: &str, _notification: &Notification, _json: &str) -> std::io::Result<()> {
+    Err(std::io::Error::other("notifications need a build with the `notify` feature (cargo build --release --features notify)"))
+}
+
+/// True for http://HOST[/PATH] and https://HOST[/PATH]
+fn is_webhook(target: &str) -> bool {
+    ["http://", "https://"].iter().any(|scheme| target.strip_prefix(scheme)
+        .is_some_and(|rest| !rest.is_empty() && !rest.starts_with('/')))
+}
+
+/// (host, port, recipient) of smtp://HOST[:PORT]/TO
+fn parse_smtp_target(target: &str) -> Option<(String, u16, String)> {
+    let rest = target.strip_prefix("smtp://")?;
+    let (authority, to) = rest.split_once('/')?;
+    let (host, port) = match authority.split_once(':') {
+        Some((host, port)) => (host, port.parse().ok()?),
+        None => (authority, 25),
+    };
+    (!host.is_empty() && to.contains('@')).then(|| (host.to_string(), port, to.to_string()))
+}
+
+/// POST `body` as JSON to `url`: an error unless the answer is a success (2xx)
+#[cfg(feature = "notify")]
+fn post_json(url: &str, body: &str) -> std::io::Result<()> {
+    let agent = ureq::AgentBuilder::new().timeout(Duration::from_secs(NOTIFY_TIMEOUT_SECS)).build();
+    match agent.post(url).set("Content-Type", "application/json").send_string(body) {
+        Ok(_) => Ok(()),
+        Err(ureq::Error::Status(code, _)) => Err(std::io::Error::other(format!("answered {}", code))),
+        Err(e) => Err(std::io::Error::other(e)),
+    }
+}
+
+/// Send `body` to `to` through the SMTP relay `host` (no TLS, no authentication)
+#[cfg(feature = "notify")]
+fn send_mail(host: &str, port: u16, to: &str, subject: &str, body: &str) -> std::io::Result<()> {
+    use lettre::Transport;
+    let from = format!("funny@{}", crate::file_info::hostname());
+    let address = |address: &str| address.parse::<lettre::message::Mailbox>()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{}: {}", address, e)));
+    let message = lettre::Message::builder()
+        .from(address(&from)?)
+        .to(address(to)?)
+        .subject(subject)
+        .header(lettre::message::header::ContentType::parse("application/json").map_err(std::io::Error::other)?)
+        .body(body.to_string())
+        .map_err(std::io::Error::other)?;
+    let relay = lettre::SmtpTransport::builder_dangerous(host)
+        .port(port)
+        .timeout(Some(Duration::from_secs(NOTIFY_TIMEOUT_SECS)))
+        .build();
+    relay.send(&message).map(|_| ()).map_err(std::io::Error::other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_are_parsed() {
+        assert!(is_webhook("http://nas:8080/hooks/funny") && is_webhook("https://nas"));
+        assert!(!is_webhook("http://") && !is_webhook("https:///x"));
+        assert_eq!(parse_smtp_target("smtp://relay/me@example.org"), Some(("relay".to_string(), 25, "me@example.org".to_string())));
+        assert_eq!(parse_smtp_target("smtp://relay:2525"), None);
+        assert!(parse_target("https://hooks.example.org/x").is_ok());
+        assert!(parse_target("ftp://nas/x").is_err());
+    }
+
+    #[cfg(feature = "notify")]
+    #[test]
+    fn webhook_receives_the_json_payload() {
+        use std::io::{BufRead, BufReader, Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+        let port = listener.local_addr().expect("addr").port();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("accept");
+            let mut reader = BufReader::new(stream.try_clone().expect("clone"));
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).expect("header");
+                if let Some(value) = line.strip_prefix("Content-Length: ") {
+                    length = value.trim().parse().expect("length");
+                }
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0u8; length];
+            reader.read_exact(&mut body).expect("body");
+            let mut stream = stream;
+            stream.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").expect("answer");
+            String::from_utf8(body).expect("utf8")
+        });
+
+        let notification = Notification { event: "completed".to_string(), mode: "cascade".to_string(), host: "h".to_string(),
+            at: "now".to_string(), message: "Cascade mode completed".to_string(), summary: None };
+        let json = serde_json::to_string(&notification).expect("json");
+        send(&format!("http://127.0.0.1:{}/hook", port), &notification, &json).expect("send");
+        let received: serde_json::Value = serde_json::from_str(&server.join().expect("server")).expect("payload");
+        assert_eq!((received["event"].as_str(), received["mode"].as_str()), (Some("completed"), Some("cascade")));
+    }
+
+    #[cfg(feature = "notify")]
+    #[test]
+    fn mail_goes_through_the_relay() {
+        use std::io::{BufRead, BufReader, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+        let port = listener.local_addr().expect("addr").port();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("accept");
+            let mut reader = BufReader::new(stream.try_clone().expect("clone"));
+            let mut stream = stream;
+            let (mut recipient, mut data, mut in_data) = (String::new(), String::new(), false);
+            stream.write_all(b"220 relay ready\r\n").expect("greeting");
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).expect("command") == 0 {
+                    break;
+                }
+                let answer: &[u8] = if in_data {
+                    if line != ".\r\n" {
+                        data.push_str(&line);
+                        continue;
+                    }
+                    in_data = false;
+                    b"250 queued\r\n"
+                } else if line.starts_with("DATA") {
+                    in_data = true;
+                    b"354 go on\r\n"
+                } else if line.starts_with("QUIT") {
+                    stream.write_all(b"221 bye\r\n").expect("bye");
+                    break;
+                } else {
+                    if let Some(to) = line.strip_prefix("RCPT TO:") {
+                        recipient = to.trim().to_string();
+                    }
+                    b"250 ok\r\n"
+                };
+                stream.write_all(answer).expect("answer");
+            }
+            (recipient, data)
+        });
+
+        send_mail("127.0.0.1", port, "me@example.org", "[funny] size completed on h", "{\"event\":\"completed\"}").expect("send");
+        let (recipient, data) = server.join().expect("server");
+        assert_eq!(recipient, "<me@example.org>");
+        assert!(data.contains("Subject: [funny] size completed on h") && data.contains("{\"event\":\"completed\"}"));
+    }
+
+    #[cfg(not(feature = "notify"))]
+    #[test]
+    fn notifications_need_the_notify_feature() {
+        let notification = Notification { event: "failed".to_string(), mode: "size".to_string(), host: "h".to_string(),
+            at: "now".to_string(), message: "disk full".to_string(), summary: None };
+        let error = send("http://127.0.0.1:9/hook", &notification, "{}").unwrap_err();
+        assert!(error.to_string().contains("--features notify"));
+    }
+}
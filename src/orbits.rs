@@ -0,0 +1,359 @@
+//! Orbit analysis module for counting no-set-lists up to symmetry
+//!
+//! The 81 cards are the points of the affine space AG(4,3): three cards form a
+//! set exactly when they are collinear. Every affine bijection of AG(4,3) maps
+//! sets to sets, so the affine group AGL(4,3) acts on no-set-lists (caps) and
+//! splits them into orbits (equivalence classes).
+//!
+//! Key features:
+//! - Canonical form of a list: minimum over the ordered affine bases of its hull
+//!   of the 81-bit mask of its coordinates in that basis
+//! - Stabilizer order (and so full orbit size) derived from the same enumeration
+//! - Streaming over all stored files of a size, one batch at a time
+//! - Report of orbit counts and orbit-size distribution (stdout/log and JSON)
+//!
+//! Used by --orbits mode
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use separator::Separatable;
+use serde::Serialize;
+
+use crate::set::index_to_base3;
+use crate::utils::*;
+
+/// Order of the affine group AGL(4,3) = 81 × |GL(4,3)|
+pub const AGL_4_3_ORDER: u64 = 81 * 80 * 78 * 72 * 54;
+
+/// Canonical form of a list under AGL(4,3), with its stabilizer order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CanonicalForm {
+    pub mask: u128,         // bit i set if card i belongs to the canonical representative
+    pub stabilizer: u64,    // number of affine maps fixing the list (as a set)
+}
+
+impl CanonicalForm {
+    /// Number of lists in the orbit (orbit-stabilizer theorem)
+    pub fn orbit_size(&self) -> u64 {
+        AGL_4_3_ORDER / self.stabilizer
+    }
+}
+
+/// Card index from base-3 digits (inverse of `index_to_base3`)
+fn base3_to_index(b3: &[usize; 4]) -> usize {
+    b3.iter().fold(0, |index, digit| index * 3 + digit)
+}
+
+/// Precomputed card arithmetic: a + b and k.a (coordinate-wise, mod 3)
+struct CardArithmetic {
+    add: [[u8; 81]; 81],
+    double: [u8; 81],
+}
+
+impl CardArithmetic {
+    fn new() -> Self {
+        let mut add = [[0u8; 81]; 81];
+        let mut double = [0u8; 81];
+        for a in 0..81 {
+            let ba = index_to_base3(a);
+            for (b, slot) in add[a].iter_mut().enumerate() {
+                let bb = index_to_base3(b);
+                let sum = [0, 1, 2, 3].map(|j| (ba[j] + bb[j]) % 3);
+                *slot = base3_to_index(&sum) as u8;
+            }
+            double[a] = add[a][a];
+        }
+        CardArithmetic { add, double }
+    }
+
+    /// a - b (coordinate-wise, mod 3)
+    fn sub(&self, a: usize, b: usize) -> usize {
+        // -b = 2.b mod 3
+        self.add[a][self.double[b] as usize] as usize
+    }
+}
+
+/// Number of affine maps of AG(4,3) fixing pointwise a flat of dimension `dim`
+fn pointwise_fixer_order(dim: usize) -> u64 {
+    (dim..4).map(|j| 81 - 3u64.pow(j as u32)).product()
+}
+
+/// Enumerates the ordered affine bases of the hull of one list
+struct CanonicalSearch<'a> {
+    arith: &'a CardArithmetic,
+    cards: &'a [usize],
+    dim: usize,
+    best: u128,
+    best_count: u64,
+}
+
+impl<'a> CanonicalSearch<'a> {
+    /// Extend the partial basis (origin + direction vectors); `flat` holds the
+    /// points of the affine flat spanned so far
+    fn extend(&mut self, origin: usize, vectors: &mut Vec<usize>, flat: u128) {
+        if vectors.len() == self.dim {
+            self.evaluate(origin, vectors);
+            return;
+        }
+        for &card in self.cards {
+            if flat & (1u128 << card) != 0 {
+                continue;
+            }
+            let v = self.arith.sub(card, origin);
+            // New flat = flat ∪ (flat + v) ∪ (flat + 2v)
+            let v2 = self.arith.double[v] as usize;
+            let mut new_flat = flat;
+            for p in 0..81 {
+                if flat & (1u128 << p) != 0 {
+                    new_flat |= 1u128 << self.arith.add[p][v];
+                    new_flat |= 1u128 << self.arith.add[p][v2];
+                }
+            }
+            vectors.push(v);
+            self.extend(origin, vectors, new_flat);
+            vectors.pop();
+        }
+    }
+
+    /// Compute the mask of the list in coordinates of the complete basis
+    fn evaluate(&mut self, origin: usize, vectors: &[usize]) {
+        // Map each point of the hull to its coordinate index
+        let mut coords = [0u8; 81];
+        let nb_points = 3usize.pow(self.dim as u32);
+        for c in 0..nb_points {
+            let mut point = origin;
+            let mut rem = c;
+            let mut index = 0;
+            for (i, &v) in vectors.iter().enumerate() {
+                let digit = rem % 3;
+                rem /= 3;
+                if digit == 1 {
+                    point = self.arith.add[point][v] as usize;
+                } else if digit == 2 {
+                    point = self.arith.add[point][self.arith.double[v] as usize] as usize;
+                }
+                index += digit * 3usize.pow(3 - i as u32);
+            }
+            coords[point] = index as u8;
+        }
+
+        let mask = self.cards.iter().fold(0u128, |m, &card| m | (1u128 << coords[card]));
+        if mask < self.best {
+            self.best = mask;
+            self.best_count = 1;
+        } else if mask == self.best {
+            self.best_count += 1;
+        }
+    }
+}
+
+/// Dimension of the affine hull of a list of cards
+fn hull_dimension(arith: &CardArithmetic, cards: &[usize]) -> usize {
+    let Some(&origin) = cards.first() else { return 0 };
+    let mut flat: u128 = 1u128 << origin;
+    let mut dim = 0;
+    for &card in cards {
+        if flat & (1u128 << card) != 0 {
+            continue;
+        }
+        let v = arith.sub(card, origin);
+        let v2 = arith.double[v] as usize;
+        let mut new_flat = flat;
+        for p in 0..81 {
+            if flat & (1u128 << p) != 0 {
+                new_flat |= 1u128 << arith.add[p][v];
+                new_flat |= 1u128 << arith.add[p][v2];
+            }
+        }
+        flat = new_flat;
+        dim += 1;
+    }
+    dim
+}
+
+/// Canonical form computer (holds the precomputed card arithmetic)
+pub struct Canonicalizer {
+    arith: CardArithmetic,
+}
+
+impl Default for Canonicalizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Canonicalizer {
+    pub fn new() -> Self {
+        Canonicalizer { arith: CardArithmetic::new() }
+    }
+
+    /// Canonical form of a list of cards under AGL(4,3).
+    ///
+    /// For every ordered affine basis (origin + `dim` points) taken from the list,
+    /// the list is rewritten in the coordinates of that basis; the smallest mask wins.
+    /// Equivalent lists give the same minimum, and the number of bases reaching it,
+    /// times the maps fixing the hull pointwise, is the stabilizer order.
+    pub fn canonical_form(&self, cards: &[usize]) -> CanonicalForm {
+        let dim = hull_dimension(&self.arith, cards);
+        let mut search = CanonicalSearch {
+            arith: &self.arith,
+            cards,
+            dim,
+            best: u128::MAX,
+            best_count: 0,
+        };
+        let mut vectors: Vec<usize> = Vec::with_capacity(4);
+        for &origin in cards {
+            search.extend(origin, &mut vectors, 1u128 << origin);
+        }
+        CanonicalForm {
+            mask: search.best,
+            stabilizer: search.best_count * pointwise_fixer_order(dim),
+        }
+    }
+}
+
+/// One orbit found among the stored lists
+#[derive(Debug, Clone, Serialize)]
+pub struct OrbitInfo {
+    pub representative: Vec<usize>,    // cards of the canonical representative
+    pub orbit_size: u64,                // number of lists in the full orbit
+    pub stabilizer: u64,                // order of the stabilizer subgroup
+    pub stored_lists: u64,              // number of stored lists in this orbit
+}
+
+/// Orbit report for one size (written as nsl_{size:02}_orbits.json)
+#[derive(Debug, Clone, Serialize)]
+pub struct OrbitReport {
+    pub size: u8,
+    pub files_scanned: u64,
+    pub lists_scanned: u64,
+    pub nb_orbits: u64,
+    pub orbit_size_distribution: BTreeMap<u64, u64>,    // orbit size -> number of orbits
+    pub orbits: Vec<OrbitInfo>,
+}
+
+/// Group all stored lists of `size` found in `base_path` by canonical form
+pub fn analyze_orbits(base_path: &str, size: u8) -> std::io::Result<OrbitReport> {
+    test_print(&format!("\nOrbit analysis for size {:02}...", size));
+    test_print(&format!("   Input directory: {}", base_path));
+    let start_time = std::time::Instant::now();
+
+    let files = crate::filenames::list_input_files(base_path, size);
+    if files.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound,
+            format!("No size {:02} files found in {}", size, base_path)));
+    }
+
+    let canonicalizer = Canonicalizer::new();
+    let mut orbits: HashMap<u128, (CanonicalForm, u64)> = HashMap::new();
+    let mut lists_scanned: u64 = 0;
+
+    for file in files.iter() {
+        let lists = crate::io_helpers::load_lists_cached(&file.path)?;
+        for list in lists.iter() {
+            let form = canonicalizer.canonical_form(&list.no_set_list);
+            orbits.entry(form.mask).or_insert((form, 0)).1 += 1;
+        }
+        lists_scanned += lists.len() as u64;
+        let name = Path::new(&file.path).file_name().unwrap_or_default().to_string_lossy();
+        test_print(&format!("   ... {:>10} lists from {} ({} orbits so far)",
+            lists.len().separated_string(), name, orbits.len().separated_string()));
+    }
+
+    let mut orbit_list: Vec<OrbitInfo> = orbits.values()
+        .map(|(form, count)| OrbitInfo {
+            representative: (0..81).filter(|i| form.mask & (1u128 << i) != 0).collect(),
+            orbit_size: form.orbit_size(),
+            stabilizer: form.stabilizer,
+            stored_lists: *count,
+        })
+        .collect();
+    orbit_list.sort_by(|a, b| a.representative.cmp(&b.representative));
+
+    let mut distribution: BTreeMap<u64, u64> = BTreeMap::new();
+    for orbit in orbit_list.iter() {
+        *distribution.entry(orbit.orbit_size).or_insert(0) += 1;
+    }
+
+    test_print(&format!("   ... analyzed {} lists in {:.2}s",
+        lists_scanned.separated_string(), start_time.elapsed().as_secs_f64()));
+
+    Ok(OrbitReport {
+        size,
+        files_scanned: files.len() as u64,
+        lists_scanned,
+        nb_orbits: orbit_list.len() as u64,
+        orbit_size_distribution: distribution,
+        orbits: orbit_list,
+    })
+}
+
+/// Print the orbit report and save it as nsl_{size:02}_orbits.json next to the files
+pub fn save_orbit_report(base_path: &str, report: &OrbitReport) -> std::io::Result<()> {
+    test_print(&format!("\nSize {:02}: {} orbits among {} stored lists ({} files)",
+        report.size, report.nb_orbits.separated_string(),
+        report.lists_scanned.separated_string(), report.files_scanned));
+    test_print("   Orbit size distribution (orbit size: number of orbits):");
+    for (orbit_size, nb) in report.orbit_size_distribution.iter() {
+        test_print(&format!("   {:>15}: {}", orbit_size.separated_string(), nb.separated_string()));
+    }
+
+    let path = Path::new(base_path).join(format!("nsl_{:02}_orbits.json", report.size));
+    let json = serde_json::to_string_pretty(report)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    std::fs::write(&path, json)?;
+    test_print(&format!("   Report saved: {}", path.display()));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::set::{is_set, next_to_set};
+
+    /// Apply an affine map: permute coordinates, negate one, then translate
+    fn transform(card: usize) -> usize {
+        let b = index_to_base3(card);
+        let mut t = [b[2], b[0], (3 - b[3]) % 3, b[1]];
+        let shift = [1, 2, 0, 1];
+        for j in 0..4 {
+            t[j] = (t[j] + shift[j]) % 3;
+        }
+        base3_to_index(&t)
+    }
+
+    #[test]
+    fn all_triangles_form_one_orbit() {
+        let canonicalizer = Canonicalizer::new();
+        let reference = canonicalizer.canonical_form(&[0, 1, 3]);
+        assert_eq!(reference.orbit_size(), 85_320 - 1_080, "non-collinear triples");
+        for (a, b) in [(0, 4), (5, 40), (12, 80)] {
+            let mut c = 0;
+            while c == a || c == b || c == next_to_set(a, b) {
+                c += 1;
+            }
+            assert!(!is_set(a, b, c));
+            assert_eq!(canonicalizer.canonical_form(&[a, b, c]).mask, reference.mask);
+        }
+    }
+
+    #[test]
+    fn canonical_form_is_invariant_under_affine_maps() {
+        let canonicalizer = Canonicalizer::new();
+        // A 6-card no-set-list spanning the whole space
+        let cards = vec![0, 1, 3, 9, 27, 40];
+        for i in 0..cards.len() {
+            for j in i + 1..cards.len() {
+                for k in j + 1..cards.len() {
+                    assert!(!is_set(cards[i], cards[j], cards[k]));
+                }
+            }
+        }
+        let image: Vec<usize> = cards.iter().map(|&c| transform(c)).collect();
+        let f1 = canonicalizer.canonical_form(&cards);
+        let f2 = canonicalizer.canonical_form(&image);
+        assert_eq!(f1, f2);
+        assert_eq!(AGL_4_3_ORDER % f1.stabilizer, 0);
+    }
+}
@@ -0,0 +1,303 @@
+//! Overview module: progress of every size of an exploration, in one table
+//!
+//! Each size keeps its own state (nsl_XX_global_info); this module loads them all,
+//! read-only, to give the big picture.
+//!
+//! Key features:
+//! - Cascade directory layout aware (same directories as --cascade); the sizes
+//!   outside of it are looked up in ROOT/{N-1}_to_{N} and in ROOT itself
+//! - Per size: directory, files and lists (from the state; files on disk for the
+//!   sizes without state), disk usage, last activity (files, consumed inputs, state)
+//! - Percent complete: input files consumed (recorded in the state, or source
+//!   batches of the entries for older states) over the previous size's files,
+//!   compacted and regular apart
+//! - Cascade running (or last run) in ROOT: its cascade_status.json, in one line
+//! - Nothing is modified
+//! - Count-all: the count of --count run on every size found (states brought up
+//!   to date with the files on disk), then one grand-total report of the lists,
+//!   files and disk usage per size, with the growth factor from the previous size,
+//!   saved as nsl_grand_total.json and .txt in ROOT
+//!
+//! Used by --overview and --count-all modes
+
+use std::collections::BTreeSet;
+use std::path::Path;
+use separator::Separatable;
+use serde::Serialize;
+
+use crate::dry_run::load_state_readonly;
+use crate::file_info::InputKey;
+use crate::utils::*;
+
+/// Progress of one size
+#[derive(Debug, Clone, Default)]
+pub struct SizeOverview {
+    pub size: u8,
+    pub dir: String,
+    pub files: u64,
+    pub lists: u64,
+    pub bytes: u64,
+    pub last_activity: Option<i64>, // unix seconds
+    pub inputs_consumed: u64,
+    pub inputs_total: u64,          // batches of the previous size (0: unknown, or seeds)
+}
+
+impl SizeOverview {
+    /// Share of the previous size's batches consumed (None if there are none)
+    pub fn percent_complete(&self) -> Option<f64> {
+        (self.inputs_total > 0).then(|| 100.0 * self.inputs_consumed as f64 / self.inputs_total as f64)
+    }
+}
+
+/// True if `dir` holds a state or list files of `size`
+fn holds_size(dir: &Path, size: u8) -> bool {
+    ["rkyv", "json", "sqlite"].iter()
+        .any(|ext| dir.join(format!("nsl_{:02}_global_info.{}", size, ext)).exists())
+        || !crate::filenames::list_input_files(&dir.to_string_lossy(), size).is_empty()
+}
+
+/// Directory of the size `size` files under `root`
+fn size_dir(root: &str, size: u8) -> Option<String> {
+    let mut candidates = Vec::new();
+    if size >= 12 {
+        candidates.push(crate::filenames::get_cascade_directories(root, size).0);
+    }
+    candidates.push(Path::new(root).join(format!("{}_to_{}", size - 1, size)).to_string_lossy().into_owned());
+    candidates.push(root.to_string());
+    candidates.into_iter().find(|dir| holds_size(Path::new(dir), size))
+}
+
+/// Directory of the size `size - 1` files feeding the size `size` files of `dir`:
+/// `dir` itself, or the layout of its parent (as for --overview)
+pub fn input_dir_of(dir: &str, size: u8) -> Option<String> {
+    if size <= 3 {
+        return None;
+    }
+    if holds_size(Path::new(dir), size - 1) {
+        return Some(dir.to_string());
+    }
+    let parent = Path::new(dir).parent()?.to_string_lossy().into_owned();
+    size_dir(if parent.is_empty() { "." } else { &parent }, size - 1)
+}
+
+/// Input files of `size` in `dir`: target batches of its state and files on disk,
+/// compacted and regular apart (their numberings overlap)
+pub fn size_inputs(dir: &str, size: u8) -> std::io::Result<BTreeSet<InputKey>> {
+    let state = load_state_readonly(dir, size)?;
+    let mut inputs: BTreeSet<InputKey> = state.entries().values()
+        .map(|e| InputKey::whole(e.target_batch, e.compacted))
+        .collect();
+    inputs.extend(crate::filenames::list_input_files(dir, size).iter().map(|f| InputKey::whole(f.batch, f.compacted)));
+    Ok(inputs)
+}
+
+/// Progress of `size` in `dir`, the previous size being in `input_dir` (if found)
+fn size_overview(dir: &str, size: u8, input_dir: Option<&str>) -> std::io::Result<SizeOverview> {
+    let state = load_state_readonly(dir, size)?;
+    let mut overview = SizeOverview {
+        size,
+        dir: dir.to_string(),
+        files: state.entries().len() as u64,
+        lists: state.entries().values().map(|e| e.nb_lists_in_file).sum(),
+        ..Default::default()
+    };
+
+    let mut last_activity: Vec<i64> = state.consumed_inputs().values().map(|c| c.completed_at).collect();
+    let files = crate::filenames::list_input_files(dir, size);
+    if overview.files == 0 {
+        overview.files = files.len() as u64; // no state: lists unknown
+    }
+    for file in files {
+        if let Some((bytes, modified)) = crate::storage::file_metadata(&file.path) {
+            overview.bytes += bytes;
+            last_activity.extend(modified);
+        }
+    }
+    if let Some(modified) = std::fs::metadata(state.state_path()).ok()
+        .and_then(|m| m.modified().ok())
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok()) {
+        last_activity.push(modified.as_secs() as i64);
+    }
+    overview.last_activity = last_activity.into_iter().max();
+
+    if let Some(input_dir) = input_dir {
+        let inputs = size_inputs(input_dir, size - 1)?;
+        overview.inputs_total = inputs.len() as u64;
+        overview.inputs_consumed = state.consumed_among(&inputs, &BTreeSet::new()).len() as u64;
+    }
+    Ok(overview)
+}
+
+/// Progress of every size found under `root`
+pub fn overview(root: &str) -> std::io::Result<Vec<SizeOverview>> {
+    test_print(&format!("\nOVERVIEW MODE: progress of every size under {}", root));
+    let mut sizes = Vec::new();
+    for size in 3..=20u8 {
+        let Some(dir) = size_dir(root, size) else {
+            continue;
+        };
+        let input_dir = if size > 3 { size_dir(root, size - 1) } else { None };
+        sizes.push(size_overview(&dir, size, input_dir.as_deref())?);
+    }
+    print_overview(&sizes);
+    if let Some(status) = crate::cascade_status::read(root) {
+        test_print(&format!("\n   Cascade: {}", status.describe()));
+    }
+    Ok(sizes)
+}
+
+/// Counted totals of one size
+#[derive(Debug, Clone, Serialize)]
+pub struct SizeTotal {
+    pub size: u8,
+    pub dir: String,
+    pub files: u64,
+    pub lists: u64,
+    pub bytes: u64,
+    pub growth: Option<f64>, // lists / lists of the previous size (None: previous size not found)
+}
+
+/// Grand total of every size under a root directory
+#[derive(Debug, Clone, Serialize)]
+pub struct GrandTotal {
+    pub root: String,
+    pub generated: String,
+    pub sizes: Vec<SizeTotal>,
+    pub files: u64,
+    pub lists: u64,
+    pub bytes: u64,
+}
+
+impl GrandTotal {
+    /// Human-readable rendering (the .txt report)
+    pub fn to_txt(&self) -> String {
+        let mut txt = String::new();
+        txt.push_str(&format!("# Grand total of the no-set-lists under {}\n", self.root));
+        txt.push_str(&format!("# Generated: {}\n#\n", self.generated));
+        txt.push_str(&format!("{:>4} | {:>9} | {:>22} | {:>10} | {:>8} | {}\n",
+            "size", "files", "lists", "disk (GB)", "growth", "directory"));
+        for s in self.sizes.iter() {
+            txt.push_str(&format!("{:>4} | {:>9} | {:>22} | {:>10.2} | {:>8} | {}\n",
+                s.size, s.files.separated_string(), s.lists.separated_string(), s.bytes as f64 / 1_073_741_824.0,
+                s.growth.map_or_else(|| "-".to_string(), |g| format!("x{:.2}", g)), s.dir));
+        }
+        txt.push_str(&format!("{:>4} | {:>9} | {:>22} | {:>10.2} |\n",
+            "all", self.files.separated_string(), self.lists.separated_string(), self.bytes as f64 / 1_073_741_824.0));
+        txt
+    }
+}
+
+/// Run the count of every size found under `root` (`force`: states rebuilt from the
+/// files), then save the grand total in `root`
+pub fn count_all(root: &str, force: bool) -> std::io::Result<GrandTotal> {
+    test_print(&format!("\nCOUNT-ALL MODE: counting every size under {}", root));
+    let mut sizes: Vec<SizeTotal> = Vec::new();
+    for size in 3..=20u8 {
+        let Some(dir) = size_dir(root, size) else {
+            continue;
+        };
+        crate::list_of_nsl::count_size_files(&dir, size, force, false)?;
+        let counted = size_overview(&dir, size, None)?;
+        let growth = sizes.last()
+            .filter(|previous| previous.size + 1 == size && previous.lists > 0)
+            .map(|previous| counted.lists as f64 / previous.lists as f64);
+        sizes.push(SizeTotal { size, dir, files: counted.files, lists: counted.lists, bytes: counted.bytes, growth });
+    }
+    let total = GrandTotal {
+        root: root.to_string(),
+        generated: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        files: sizes.iter().map(|s| s.files).sum(),
+        lists: sizes.iter().map(|s| s.lists).sum(),
+        bytes: sizes.iter().map(|s| s.bytes).sum(),
+        sizes,
+    };
+
+    let txt = total.to_txt();
+    test_print("");
+    for line in txt.lines().filter(|l| !l.starts_with('#')) {
+        test_print(&format!("   {}", line));
+    }
+    let json = serde_json::to_string_pretty(&total).map_err(std::io::Error::other)?;
+    let json_path = Path::new(root).join("nsl_grand_total.json");
+    crate::io_helpers::write_file_atomic(&json_path.to_string_lossy(), json.as_bytes())?;
+    crate::io_helpers::write_file_atomic(&Path::new(root).join("nsl_grand_total.txt").to_string_lossy(), txt.as_bytes())?;
+    test_print(&format!("   Report saved: {} (and .txt)", json_path.display()));
+    Ok(total)
+}
+
+fn print_overview(sizes: &[SizeOverview]) {
+    test_print(&format!("\n   {:>4} | {:>7} | {:>18} | {:>10} | {:<16} | {:>8} | {}",
+        "size", "files", "lists", "disk (GB)", "last activity", "complete", "directory"));
+    for s in sizes {
+        let last = s.last_activity
+            .and_then(|t| chrono::DateTime::from_timestamp(t, 0))
+            .map(|t| t.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_else(|| "-".to_string());
+        let complete = s.percent_complete()
+            .map(|p| format!("{:.1}%", p))
+            .unwrap_or_else(|| "-".to_string());
+        test_print(&format!("   {:>4} | {:>7} | {:>18} | {:>10.2} | {:<16} | {:>8} | {}",
+            s.size, s.files.separated_string(), s.lists.separated_string(), s.bytes as f64 / 1_073_741_824.0,
+            last, complete, s.dir));
+    }
+    if sizes.is_empty() {
+        test_print("   ... no size found");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_info::InputKey;
+    use crate::file_info::GlobalFileState;
+
+    #[test]
+    fn cascade_sizes_are_summed_up_with_their_progress() {
+        let root = crate::test_dir::TestDir::new("overview");
+        let root_str = root.to_string_lossy().into_owned();
+        let (dir_13, dir_14) = crate::filenames::get_cascade_directories(&root_str, 13);
+        std::fs::create_dir_all(&dir_13).expect("create size 13 dir");
+        std::fs::create_dir_all(&dir_14).expect("create size 14 dir");
+
+        let mut inputs = GlobalFileState::new(&dir_13, 13);
+        for batch in 0..4 {
+            inputs.register_file(&format!("nsl_12_batch_000000_to_13_batch_{:06}.rkyv", batch), 0, batch, 10, false, None, None);
+        }
+        inputs.flush().expect("flush size 13");
+        let mut outputs = GlobalFileState::new(&dir_14, 14);
+        outputs.register_file("nsl_13_batch_000000_to_14_batch_000000.rkyv", 0, 0, 25, false, None, None);
+        outputs.record_consumed_input(13, InputKey::whole(0, false), 10);
+        outputs.record_consumed_input(13, InputKey::whole(1, false), 10); // no output
+        outputs.flush().expect("flush size 14");
+
+        let sizes = overview(&root_str).expect("overview");
+        let found: Vec<(u8, u64, u64)> = sizes.iter().map(|s| (s.size, s.files, s.lists)).collect();
+        assert_eq!(found, vec![(13, 4, 40), (14, 1, 25)]);
+        assert_eq!(sizes[1].percent_complete(), Some(50.0));
+        assert!(sizes[1].last_activity.is_some());
+    }
+
+    #[test]
+    fn count_all_totals_every_size_with_its_growth() {
+        use crate::no_set_list::NoSetListSerialized;
+        let root = crate::test_dir::TestDir::new("count_all");
+        let root_str = root.to_string_lossy().into_owned();
+        let (dir_13, dir_14) = crate::filenames::get_cascade_directories(&root_str, 13);
+        std::fs::create_dir_all(&dir_13).expect("create size 13 dir");
+        std::fs::create_dir_all(&dir_14).expect("create size 14 dir");
+
+        // Files on disk only: the count registers them
+        let list = |n: u8| NoSetListSerialized { n, max_card: 9, no_set_list: vec![9; n as usize], remaining_cards_list: vec![] };
+        for (dir, size, nb_lists) in [(&dir_13, 13u8, 2usize), (&dir_14, 14, 3)] {
+            let path = crate::filenames::output_filename(dir, size - 1, 0, size, 0);
+            assert!(crate::io_helpers::save_to_file_serialized(&vec![list(size); nb_lists], &path));
+        }
+
+        let total = count_all(&root_str, false).expect("count all");
+        let found: Vec<(u8, u64, u64, Option<f64>)> = total.sizes.iter().map(|s| (s.size, s.files, s.lists, s.growth)).collect();
+        assert_eq!(found, vec![(13, 1, 2, None), (14, 1, 3, Some(1.5))]);
+        assert_eq!(total.lists, 5);
+        assert!(total.bytes > 0);
+        assert!(root.join("nsl_grand_total.json").exists());
+    }
+}
@@ -0,0 +1,315 @@
+//! Prune module: delete (or move away) input files fully consumed by the next size
+//!
+//! Once size N+1 is computed, the size-N files are only needed to recompute it.
+//! This module frees their space, but only after checking that the outputs they
+//! produced are all there.
+//!
+//! Key features:
+//! - Consumed input files read from the size N+1 state, which records them by batch
+//!   and kind (compacted and regular files share batch numbers): a file is pruned
+//!   only if its own input is recorded as consumed, or if it is below the first
+//!   input recorded of its kind (processed before the consumption was recorded)
+//! - States recording no input (schema version 3 and older): from the source
+//!   batches of the state and history: inputs are processed in batch order, so
+//!   every batch up to the highest source batch is consumed - the highest one only
+//!   once no later input is left (before that, it may be the batch an interrupted
+//!   run was working on)
+//! - Every output recorded in the size N+1 state must exist and hold its recorded
+//!   count; otherwise nothing is pruned
+//! - Consumed inputs deleted, or moved to an archive directory (--archive-dir)
+//! - Pruned files appended to nsl_{N:02}_pruned.json in the input directory; the
+//!   size N state is left as is, so its totals still validate
+//! - With --dry-run, the files that would be deleted or moved are only listed
+//!
+//! Used by --prune mode
+
+use std::collections::BTreeSet;
+use std::path::Path;
+use separator::Separatable;
+use serde::{Deserialize, Serialize};
+
+use crate::dry_run::{DryRunPlan, Operation};
+use crate::file_info::{GlobalFileState, InputKey};
+use crate::utils::*;
+
+/// Record of one pruned input file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrunedFile {
+    pub filename: String,
+    pub batch: u32,
+    pub nb_lists: u64,
+    pub bytes: u64,
+    pub moved_to: Option<String>,
+    pub pruned_at: String,
+}
+
+/// Result of pruning one size
+#[derive(Debug, Clone)]
+pub struct PruneReport {
+    pub size: u8,
+    pub consumed_up_to: u32,
+    pub files_pruned: u64,
+    pub lists_pruned: u64,
+    pub bytes_freed: u64,
+}
+
+/// Input batches of `size` - 1 recorded in `dir`: (source batches of the state and
+/// the history, and the state itself, which records the consumed inputs)
+fn consumed_source_batches(dir: &str, size: u8) -> std::io::Result<(BTreeSet<u32>, GlobalFileState)> {
+    let state = GlobalFileState::from_sources(dir, size)?;
+    let mut batches: BTreeSet<u32> = state.entries().values().map(|e| e.source_batch).collect();
+    if let Ok(history) = GlobalFileState::from_history_file(dir, size, "rkyv") {
+        batches.extend(history.entries().values().map(|e| e.source_batch));
+    }
+    batches.extend(state.consumed_inputs().keys().map(|k| k.batch));
+    Ok((batches, state))
+}
+
+/// True if the input `batch` (`compacted` or not) is consumed according to a state
+/// recording the consumed inputs: recorded itself, or below the first input
+/// recorded of its kind; a kind none of whose inputs is recorded is kept
+fn recorded_as_consumed(state: &GlobalFileState, batch: u32, compacted: bool) -> bool {
+    let mut of_kind = state.consumed_inputs().keys().filter(|k| k.compacted == compacted).map(|k| k.batch);
+    let Some(first) = of_kind.clone().min() else { return false };
+    batch < first || (of_kind.any(|b| b == batch) && state.is_input_consumed(InputKey::whole(batch, compacted)))
+}
+
+/// Check that every output recorded in the state of `size` exists with its recorded count
+fn verify_outputs(dir: &str, size: u8) -> std::io::Result<()> {
+    let state = GlobalFileState::from_sources(dir, size)?;
+    let mut problems = Vec::new();
+    for entry in state.entries().values() {
+        let path = Path::new(dir).join(&entry.filename);
+        match crate::io_helpers::count_lists_in_file(&path.to_string_lossy()) {
+            Ok(count) if count == entry.nb_lists_in_file => {}
+            Ok(count) => problems.push(format!("{} holds {} lists, {} recorded",
+                entry.filename, count.separated_string(), entry.nb_lists_in_file.separated_string())),
+            Err(e) => problems.push(format!("{}: {}", entry.filename, e)),
+        }
+    }
+    if problems.is_empty() {
+        test_print(&format!("   ... {} size {:02} outputs verified", state.entries().len(), size));
+        return Ok(());
+    }
+    for problem in problems.iter().take(10) {
+        test_print(&format!("   ... output problem: {}", problem));
+    }
+    Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+        format!("{} size {:02} outputs missing or with a wrong count: nothing pruned (run --check {})",
+            problems.len(), size, size)))
+}
+
+/// Move a list file, copying it when a rename is not possible (other file system)
+fn move_file(from: &str, to: &Path) -> std::io::Result<()> {
+    let target = to.to_string_lossy();
+    if crate::storage::rename_list_file(from, &target).is_ok() {
+        return Ok(());
+    }
+    let resolved = crate::storage::resolve_path(from);
+    crate::io_helpers::with_retry("write", &target, || std::fs::copy(&resolved, to))?;
+    crate::storage::remove_list_file(from)
+}
+
+/// Prune the size `size` files of `input_dir` consumed by the size + 1 outputs of `output_dir`
+pub fn prune_consumed_inputs(input_dir: &str, output_dir: &str, size: u8, archive_dir: Option<&str>, dry_run: bool) -> std::io::Result<PruneReport> {
+    let next = size + 1;
+    test_print(&format!("\nPRUNE MODE: Removing size {:02} inputs consumed by size {:02}...", size, next));
+    test_print(&format!("   Input directory:  {}", input_dir));
+    test_print(&format!("   Output directory: {}", output_dir));
+
+    let (consumed, state) = consumed_source_batches(output_dir, next)?;
+    let recorded = state.consumed_batches();
+    let last_done = *consumed.iter().next_back().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound,
+        format!("No size {:02} output recorded in {}: no input consumed", next, output_dir)))?;
+    verify_outputs(output_dir, next)?;
+
+    let inputs = crate::filenames::list_input_files(input_dir, size);
+    let complete = inputs.iter().all(|f| f.batch <= last_done);
+    match recorded.iter().next_back() {
+        Some(last) => test_print(&format!("   ... {} input batches recorded as consumed, up to {:06}", recorded.len(), last)),
+        None => test_print(&format!("   ... input batches up to {:06} consumed ({})", last_done,
+            if complete { "size complete" } else { "size in progress: last consumed batch kept" })),
+    }
+
+    if let Some(dir) = archive_dir
+        && !dry_run {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut plan = DryRunPlan::new(&format!("prune of size {:02} in {}", size, input_dir));
+    let mut report = PruneReport { size, consumed_up_to: last_done, files_pruned: 0, lists_pruned: 0, bytes_freed: 0 };
+    let mut pruned = Vec::new();
+    // Recorded consumed inputs are exact (by batch and kind); the inputs below the
+    // first one of their kind were processed before they were recorded
+    let is_consumed = |batch: u32, compacted: bool| if state.consumed_inputs().is_empty() {
+        batch < last_done || (complete && batch == last_done)
+    } else {
+        recorded_as_consumed(&state, batch, compacted)
+    };
+    for file in inputs.iter().filter(|f| is_consumed(f.batch, f.compacted)) {
+        let resolved = crate::storage::resolve_path(&file.path);
+        let path = Path::new(&resolved);
+        let filename = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let nb_lists = crate::io_helpers::count_lists_in_file(&file.path).unwrap_or(0);
+        let bytes = crate::storage::file_metadata(&file.path).map(|(bytes, _)| bytes).ok_or_else(|| std::io::Error::new(
+            std::io::ErrorKind::NotFound, format!("{} not found", file.path)))?;
+        let moved_to = match archive_dir {
+            Some(dir) => {
+                let target = Path::new(dir).join(&filename);
+                if dry_run {
+                    plan.add(Operation::Rename, path, format!("moved to {}", target.display()));
+                } else {
+                    move_file(&file.path, &target)?;
+                }
+                Some(target.to_string_lossy().into_owned())
+            }
+            None => {
+                if dry_run {
+                    plan.add(Operation::Delete, path, format!("{} lists consumed", nb_lists.separated_string()));
+                } else {
+                    crate::storage::remove_list_file(&file.path)?;
+                }
+                None
+            }
+        };
+        if !dry_run {
+            crate::io_helpers::invalidate_cached_batch(&file.path);
+            let action = if moved_to.is_some() { crate::events::FileAction::Archived } else { crate::events::FileAction::Deleted };
+            crate::events::file_processed(crate::events::FileProcessed::new("prune", size, action, filename.as_str(), Some(nb_lists)));
+        }
+        debug_print(&format!("   ... pruned {} ({} lists)", filename, nb_lists.separated_string()));
+        report.files_pruned += 1;
+        report.lists_pruned += nb_lists;
+        report.bytes_freed += bytes;
+        pruned.push(PrunedFile { filename, batch: file.batch, nb_lists, bytes, moved_to, pruned_at: chrono::Local::now().to_rfc3339() });
+    }
+
+    if dry_run {
+        if !pruned.is_empty() {
+            plan.add(Operation::Write, Path::new(input_dir).join(format!("nsl_{:02}_pruned.json", size)), "pruned files appended");
+        }
+        plan.print();
+        return Ok(report);
+    }
+    if !pruned.is_empty() {
+        let log_path = Path::new(input_dir).join(format!("nsl_{:02}_pruned.json", size));
+        let mut log: Vec<PrunedFile> = std::fs::read_to_string(&log_path).ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+        log.extend(pruned);
+        let json = serde_json::to_string_pretty(&log)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        std::fs::write(&log_path, json)?;
+        test_print(&format!("   ... pruned files recorded in {}", log_path.display()));
+    }
+
+    test_print(&format!("   ... {} files {} ({} lists, {:.2} GB freed)", report.files_pruned,
+        if archive_dir.is_some() { "moved" } else { "deleted" },
+        report.lists_pruned.separated_string(), report.bytes_freed as f64 / 1_073_741_824.0));
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::no_set_list::NoSetListSerialized;
+
+    #[test]
+    fn inputs_are_pruned_only_when_outputs_check_out() {
+        let dir = crate::test_dir::TestDir::new("prune");
+        let dir_str = dir.to_string_lossy().into_owned();
+
+        let list = NoSetListSerialized { n: 3, max_card: 3, no_set_list: vec![0, 1, 3], remaining_cards_list: vec![4] };
+        let input = crate::filenames::output_filename(&dir_str, 0, 0, 3, 0);
+        let output = crate::filenames::output_filename(&dir_str, 3, 0, 4, 0);
+        assert!(crate::io_helpers::save_to_file_serialized(&vec![list.clone()], &input));
+        assert!(crate::io_helpers::save_to_file_serialized(&vec![list.clone(), list], &output));
+        let output_name = Path::new(&output).file_name().unwrap().to_string_lossy().into_owned();
+
+        // Recorded count differs from the file: nothing pruned
+        let mut state = GlobalFileState::from_sources(&dir_str, 4).expect("state");
+        state.register_file(&output_name, 0, 0, 3, false, None, None);
+        state.flush().expect("flush");
+        assert!(prune_consumed_inputs(&dir_str, &dir_str, 3, None, false).is_err());
+        assert!(Path::new(&input).exists());
+
+        state.update_count(&output_name, 0, 0, 2);
+        state.flush().expect("flush");
+        let report = prune_consumed_inputs(&dir_str, &dir_str, 3, None, false).expect("prune");
+        assert_eq!(report.files_pruned, 1);
+        assert!(!Path::new(&input).exists());
+    }
+
+    #[test]
+    fn nothing_is_pruned_without_outputs_or_when_one_is_missing() {
+        let dir = crate::test_dir::TestDir::new("prune_errors");
+        let dir_str = dir.to_string_lossy().into_owned();
+
+        let list = NoSetListSerialized { n: 3, max_card: 3, no_set_list: vec![0, 1, 3], remaining_cards_list: vec![4] };
+        let input = crate::filenames::output_filename(&dir_str, 0, 0, 3, 0);
+        assert!(crate::io_helpers::save_to_file_serialized(&vec![list], &input));
+
+        // No size 4 output recorded
+        let error = prune_consumed_inputs(&dir_str, &dir_str, 3, None, false).expect_err("no outputs");
+        assert_eq!(error.kind(), std::io::ErrorKind::NotFound);
+
+        // An output recorded but not on disk
+        let mut state = GlobalFileState::new(&dir_str, 4);
+        state.register_file("nsl_03_batch_000000_to_04_batch_000000.rkyv", 0, 0, 1, false, None, None);
+        state.flush().expect("flush");
+        let error = prune_consumed_inputs(&dir_str, &dir_str, 3, None, false).expect_err("missing output");
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(Path::new(&input).exists());
+    }
+
+    #[test]
+    fn consumed_inputs_are_listed_by_a_dry_run_and_moved_to_the_archive() {
+        let dir = crate::test_dir::TestDir::new("prune_archive");
+        let dir_str = dir.to_string_lossy().into_owned();
+        let archive = dir.join("archive").to_string_lossy().into_owned();
+
+        let list = NoSetListSerialized { n: 3, max_card: 3, no_set_list: vec![0, 1, 3], remaining_cards_list: vec![4] };
+        let input = crate::filenames::output_filename(&dir_str, 0, 0, 3, 0);
+        let output = crate::filenames::output_filename(&dir_str, 3, 0, 4, 0);
+        assert!(crate::io_helpers::save_to_file_serialized(&vec![list.clone()], &input));
+        assert!(crate::io_helpers::save_to_file_serialized(&vec![list], &output));
+        let output_name = Path::new(&output).file_name().unwrap().to_string_lossy().into_owned();
+        let mut state = GlobalFileState::new(&dir_str, 4);
+        state.register_file(&output_name, 0, 0, 1, false, None, None);
+        state.flush().expect("flush");
+
+        let report = prune_consumed_inputs(&dir_str, &dir_str, 3, Some(&archive), true).expect("dry run");
+        assert_eq!(report.files_pruned, 1);
+        assert!(Path::new(&input).exists());
+        assert!(!Path::new(&archive).exists());
+
+        prune_consumed_inputs(&dir_str, &dir_str, 3, Some(&archive), false).expect("prune");
+        let input_name = Path::new(&input).file_name().unwrap();
+        assert!(!Path::new(&input).exists());
+        assert!(Path::new(&archive).join(input_name).exists());
+        assert!(dir.join("nsl_03_pruned.json").exists());
+    }
+
+    #[test]
+    fn a_regular_input_sharing_the_batch_of_a_consumed_compacted_one_is_kept() {
+        let dir = crate::test_dir::TestDir::new("prune_kinds");
+        let dir_str = dir.str();
+
+        let list = NoSetListSerialized { n: 3, max_card: 3, no_set_list: vec![0, 1, 3], remaining_cards_list: vec![4] };
+        let compacted = dir.join("nsl_02_batch_000000_to_03_batch_000002_compacted.rkyv");
+        let regular = crate::filenames::output_filename(&dir_str, 2, 5, 3, 2);
+        let output = crate::filenames::output_filename(&dir_str, 3, 2, 4, 0);
+        assert!(crate::io_helpers::save_to_file_serialized(&vec![list.clone()], &compacted.to_string_lossy()));
+        assert!(crate::io_helpers::save_to_file_serialized(&vec![list.clone()], &regular));
+        assert!(crate::io_helpers::save_to_file_serialized(&vec![list], &output));
+        let output_name = Path::new(&output).file_name().unwrap().to_string_lossy().into_owned();
+        let mut state = GlobalFileState::new(&dir_str, 4);
+        state.register_file(&output_name, 2, 0, 1, false, None, None);
+        state.record_consumed_input(3, InputKey::whole(2, true), 1);
+        state.flush().expect("flush");
+
+        let report = prune_consumed_inputs(&dir_str, &dir_str, 3, None, false).expect("prune");
+        assert_eq!(report.files_pruned, 1);
+        assert!(!compacted.exists());
+        assert!(Path::new(&regular).exists(), "regular batch 2 not consumed yet");
+    }
+}
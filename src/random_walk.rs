@@ -0,0 +1,222 @@
+//! Monte-Carlo exploration module: random greedy growth of no-set-lists
+//!
+//! Exhaustive enumeration becomes infeasible for large sizes; this module samples
+//! instead. Each walk starts from a random seed (3 cards not forming a set) and
+//! adds uniformly chosen compatible cards until none is left, ending on a
+//! complete (non-extendable) no-set-list.
+//!
+//! Key features:
+//! - Reproducible runs: a small SplitMix64 generator driven by a user seed
+//! - Statistics on the final sizes reached (histogram, best size)
+//! - Lists of size 19/20 recorded in the discovery registry
+//!   (nsl_{size:02}_discoveries.json, deduplicated, read by --final-report)
+//! - Report saved as random_walk_report.json
+//!
+//! Used by --random-walk mode
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use separator::Separatable;
+use serde::{Deserialize, Serialize};
+
+use crate::set::next_to_set;
+use crate::utils::*;
+
+/// Lists of at least this size are recorded in the discovery registry
+pub const DISCOVERY_MIN_SIZE: usize = 19;
+
+/// SplitMix64 pseudo-random generator (tiny, fast and reproducible)
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        SplitMix64 { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform value in 0..bound (bound > 0)
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+}
+
+/// Grow one random complete no-set-list, returned sorted
+pub fn random_complete_list(rng: &mut SplitMix64) -> Vec<usize> {
+    let mut cards: Vec<usize> = Vec::with_capacity(20);
+    // Cards already used or completing a set with two cards of the list
+    let mut blocked: u128 = 0;
+
+    loop {
+        let candidates: Vec<usize> = (0..81).filter(|&c| blocked & (1u128 << c) == 0).collect();
+        if candidates.is_empty() {
+            break;
+        }
+        let card = candidates[rng.below(candidates.len())];
+        for &other in cards.iter() {
+            blocked |= 1u128 << next_to_set(card, other);
+        }
+        blocked |= 1u128 << card;
+        cards.push(card);
+    }
+
+    cards.sort_unstable();
+    cards
+}
+
+/// One large list found by a walk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Discovery {
+    pub cards: Vec<usize>,
+    pub found_at: String,
+    pub method: String,     // e.g. "random-walk seed=42 iteration=1234"
+}
+
+/// Statistics of a random-walk run
+#[derive(Debug, Clone, Serialize)]
+pub struct RandomWalkReport {
+    pub rng_seed: u64,
+    pub iterations: u64,
+    pub elapsed_secs: f64,
+    pub size_histogram: BTreeMap<usize, u64>,   // final size -> number of walks
+    pub best_size: usize,
+    pub best_list: Vec<usize>,
+    pub new_discoveries: u64,
+}
+
+/// Run `iterations` random walks and record the large lists found in `output_dir`
+pub fn run_random_walk(output_dir: &str, iterations: u64, rng_seed: u64) -> std::io::Result<RandomWalkReport> {
+    test_print(&format!("\nRandom walk: {} iterations, RNG seed {}", iterations.separated_string(), rng_seed));
+    test_print(&format!("   Output directory: {}", output_dir));
+    let start_time = std::time::Instant::now();
+
+    let mut rng = SplitMix64::new(rng_seed);
+    let mut report = RandomWalkReport {
+        rng_seed,
+        iterations,
+        elapsed_secs: 0.0,
+        size_histogram: BTreeMap::new(),
+        best_size: 0,
+        best_list: Vec::new(),
+        new_discoveries: 0,
+    };
+    let mut found: Vec<(u64, Vec<usize>)> = Vec::new();
+    let progress_step = (iterations / 10).max(1);
+
+    for iteration in 0..iterations {
+        let cards = random_complete_list(&mut rng);
+        *report.size_histogram.entry(cards.len()).or_insert(0) += 1;
+        if cards.len() > report.best_size {
+            report.best_size = cards.len();
+            report.best_list = cards.clone();
+        }
+        if cards.len() >= DISCOVERY_MIN_SIZE {
+            test_print(&format!("   ... iteration {}: found a {}-card list {:?}", iteration, cards.len(), cards));
+            found.push((iteration, cards));
+        }
+        if (iteration + 1) % progress_step == 0 {
+            progress_print(&format!("   ... {} walks done, best size so far {}",
+                (iteration + 1).separated_string(), report.best_size));
+        }
+    }
+
+    // Record the large lists in the discovery registry of their size
+    for size in DISCOVERY_MIN_SIZE..=20 {
+        let new: Vec<Discovery> = found.iter()
+            .filter(|(_, cards)| cards.len() == size)
+            .map(|(iteration, cards)| Discovery {
+                cards: cards.clone(),
+                found_at: chrono::Local::now().to_rfc3339(),
+                method: format!("random-walk seed={} iteration={}", rng_seed, iteration),
+            })
+            .collect();
+        if !new.is_empty() {
+            report.new_discoveries += register_discoveries(output_dir, size as u8, new)?;
+        }
+    }
+
+    report.elapsed_secs = start_time.elapsed().as_secs_f64();
+    Ok(report)
+}
+
+/// Add discoveries to nsl_{size:02}_discoveries.json (lists already recorded are skipped).
+/// Returns the number of lists actually added.
+pub fn register_discoveries(base_dir: &str, size: u8, discoveries: Vec<Discovery>) -> std::io::Result<u64> {
+    let path = Path::new(base_dir).join(format!("nsl_{:02}_discoveries.json", size));
+    let mut registry: Vec<Discovery> = match std::fs::read_to_string(&path) {
+        Ok(text) => serde_json::from_str(&text)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?,
+        Err(_) => Vec::new(),
+    };
+
+    let mut added = 0;
+    for discovery in discoveries {
+        if !registry.iter().any(|d| d.cards == discovery.cards) {
+            registry.push(discovery);
+            added += 1;
+        }
+    }
+
+    let json = serde_json::to_string_pretty(&registry)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, json)?;
+    std::fs::rename(&tmp, &path)?;
+    test_print(&format!("   Discovery registry {}: {} new, {} total", path.display(), added, registry.len()));
+    Ok(added)
+}
+
+/// Print the run statistics and save them as random_walk_report.json
+pub fn save_random_walk_report(output_dir: &str, report: &RandomWalkReport) -> std::io::Result<()> {
+    test_print(&format!("\nRandom walk: {} walks in {:.2}s, best size {}",
+        report.iterations.separated_string(), report.elapsed_secs, report.best_size));
+    test_print("   Final size distribution (size: walks):");
+    for (size, nb) in report.size_histogram.iter() {
+        test_print(&format!("   {:>4}: {}", size, nb.separated_string()));
+    }
+
+    let path = Path::new(output_dir).join("random_walk_report.json");
+    let json = serde_json::to_string_pretty(report)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    std::fs::write(&path, json)?;
+    test_print(&format!("   Report saved: {}", path.display()));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::set::is_set;
+
+    #[test]
+    fn walks_are_reproducible_and_end_on_complete_lists() {
+        let mut rng1 = SplitMix64::new(42);
+        let mut rng2 = SplitMix64::new(42);
+        for _ in 0..20 {
+            let cards = random_complete_list(&mut rng1);
+            assert_eq!(cards, random_complete_list(&mut rng2));
+            assert!(cards.len() >= 3 && cards.len() <= 20);
+
+            // No set inside, and every other card completes a set (complete list)
+            for i in 0..cards.len() {
+                for j in (i + 1)..cards.len() {
+                    for k in (j + 1)..cards.len() {
+                        assert!(!is_set(cards[i], cards[j], cards[k]));
+                    }
+                }
+            }
+            for c in (0..81).filter(|c| !cards.contains(c)) {
+                let blocked = (0..cards.len()).any(|i| (i + 1..cards.len()).any(|j| next_to_set(cards[i], cards[j]) == c));
+                assert!(blocked, "card {} could still be added to {:?}", c, cards);
+            }
+        }
+    }
+}
@@ -0,0 +1,154 @@
+//! Re-encode module: rewrite the files of a size in another on-disk encoding
+//!
+//! Files keep their name, their batch numbers and their lists; only the encoding
+//! changes (plain, delta or packed, zstd-compressed or not). Converting the files
+//! of a finished size to the packed encoding typically divides its disk space by 4.
+//!
+//! Key features:
+//! - Target encoding and compression taken from --encoding and --compress
+//! - Files already in the target encoding and compression are left untouched,
+//!   unless they were written in an older format version (see io_helpers)
+//! - Each file streamed frame by frame into a .tmp file (ListFileWriter), its
+//!   list count checked against the original, then renamed over the original
+//! - State entries updated (file size, mtime, compressed flag) and flushed once
+//!
+//! Used by --reencode mode
+
+use std::path::Path;
+use separator::Separatable;
+
+use crate::file_info::GlobalFileState;
+use crate::io_helpers::{ListEncoding, ListFileWriter, FRAME_LISTS};
+use crate::utils::*;
+
+/// Result of re-encoding the files of one size
+#[derive(Debug, Clone)]
+pub struct ReencodeReport {
+    pub size: u8,
+    pub encoding: ListEncoding,
+    pub compressed: bool,
+    pub files_checked: u64,
+    pub files_rewritten: u64,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+/// Rewrite `path` in the current output encoding and compression; returns its list count
+fn reencode_file(path: &str) -> std::io::Result<u64> {
+    let tmp = format!("{}.tmp", path);
+    let mut writer = ListFileWriter::create(&tmp)?;
+    let read = crate::io_helpers::load_lists_in_chunks(path, FRAME_LISTS, |chunk| writer.write_frame(&chunk))?;
+    let written = writer.finish()?;
+    if written != read || crate::io_helpers::count_lists_in_file(&tmp)? != read {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+            format!("{} lists read from {} but {} written", read, path, written)));
+    }
+    crate::io_helpers::commit_atomic_write(&tmp, path)?;
+    crate::io_helpers::invalidate_cached_batch(path);
+    Ok(read)
+}
+
+/// Rewrite every file of `size` in `base_path` in the current output encoding and compression
+pub fn reencode_size_files(base_path: &str, size: u8) -> std::io::Result<ReencodeReport> {
+    let encoding = crate::io_helpers::output_encoding();
+    let compressed = crate::io_helpers::output_compression().is_some();
+    test_print(&format!("\nREENCODE MODE: Rewriting size {:02} files as {}{}...", size, encoding.name(),
+        if compressed { " + zstd" } else { "" }));
+    test_print(&format!("   Directory: {}", base_path));
+    let start_time = std::time::Instant::now();
+
+    let files = crate::filenames::list_input_files(base_path, size);
+    if files.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound,
+            format!("No size {:02} files found in {}", size, base_path)));
+    }
+    let mut state = GlobalFileState::from_sources(base_path, size)?;
+
+    let mut report = ReencodeReport {
+        size,
+        encoding,
+        compressed,
+        files_checked: 0,
+        files_rewritten: 0,
+        bytes_before: 0,
+        bytes_after: 0,
+    };
+
+    for file in files.iter() {
+        report.files_checked += 1;
+        let name = Path::new(&file.path).file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let format = crate::io_helpers::file_format(&file.path)?;
+        if format.encoding == encoding && format.compressed == compressed
+            && format.version == crate::io_helpers::FORMAT_VERSION {
+            debug_print(&format!("   ... {} already {} (unchanged)", name, encoding.name()));
+            continue;
+        }
+        let before = std::fs::metadata(crate::storage::resolve_path(&file.path))?.len();
+        let nb_lists = reencode_file(&file.path)?;
+        let (file_size, mtime) = crate::storage::file_metadata(&file.path)
+            .map(|(bytes, mtime)| (Some(bytes), mtime)).unwrap_or((None, None));
+
+        let keys: Vec<(u32, u32, String)> = state.entries().keys()
+            .filter(|(_, _, filename)| *filename == name)
+            .cloned()
+            .collect();
+        if keys.is_empty() {
+            test_print(&format!("   ... WARNING: {} is not recorded in the global state (run --count {})", name, size));
+        }
+        for (src, tgt, filename) in keys {
+            state.update_entry(&filename, src, tgt, nb_lists, file.compacted, file_size, mtime);
+        }
+
+        report.files_rewritten += 1;
+        report.bytes_before += before;
+        report.bytes_after += file_size.unwrap_or(0);
+        test_print(&format!("   ... {:>10} lists in {}: {} -> {} bytes", nb_lists.separated_string(), name,
+            before.separated_string(), file_size.unwrap_or(0).separated_string()));
+    }
+
+    if report.files_rewritten > 0 {
+        state.flush()?;
+        state.export_human_readable()?;
+    }
+
+    test_print(&format!("   ... {} of {} files rewritten ({} -> {} bytes) in {:.2}s",
+        report.files_rewritten, report.files_checked, report.bytes_before.separated_string(),
+        report.bytes_after.separated_string(), start_time.elapsed().as_secs_f64()));
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::no_set_list::NoSetListSerialized;
+
+    #[test]
+    fn files_are_rewritten_in_the_output_encoding_with_their_lists() {
+        let dir = crate::test_dir::TestDir::new("reencode");
+        let dir_str = dir.to_string_lossy().into_owned();
+        assert!(reencode_size_files(&dir_str, 4).is_err_and(|e| e.kind() == std::io::ErrorKind::NotFound));
+
+        let lists: Vec<NoSetListSerialized> = (4..40)
+            .map(|c| NoSetListSerialized { n: 4, max_card: c, no_set_list: vec![0, 1, 3, c], remaining_cards_list: vec![c + 1, 80] })
+            .collect();
+        let path = crate::filenames::output_filename(&dir_str, 3, 0, 4, 0);
+        std::fs::write(&path, crate::io_helpers::encode_lists(&lists, ListEncoding::Packed).unwrap()).expect("write");
+        let name = Path::new(&path).file_name().unwrap().to_string_lossy().into_owned();
+        let mut state = GlobalFileState::new(&dir_str, 4);
+        state.register_file(&name, 0, 0, lists.len() as u64, false, None, None);
+        state.flush().expect("flush");
+
+        let report = reencode_size_files(&dir_str, 4).expect("reencode");
+        assert_eq!((report.files_checked, report.files_rewritten), (1, 1));
+        assert_eq!(crate::io_helpers::file_format(&path).unwrap().encoding, crate::io_helpers::output_encoding());
+        let loaded = crate::io_helpers::load_lists_from_file(&path).expect("load");
+        assert_eq!(loaded.iter().map(|l| l.max_card).collect::<Vec<_>>(), lists.iter().map(|l| l.max_card).collect::<Vec<_>>());
+        let state = GlobalFileState::from_sources(&dir_str, 4).expect("state");
+        let entry = state.entries().values().next().expect("entry");
+        assert_eq!(entry.file_size_bytes, Some(std::fs::metadata(&path).unwrap().len()));
+
+        // Already in the output encoding: left untouched
+        assert_eq!(reencode_size_files(&dir_str, 4).expect("reencode").files_rewritten, 0);
+    }
+}
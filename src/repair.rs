@@ -0,0 +1,395 @@
+//! Repair module: reconcile the global state of a size with the files on disk
+//!
+//! After a crash the state can drift: files listed but gone, files on disk but
+//! unlisted, counts no longer matching a rewritten file.
+//!
+//! Key features:
+//! - Stale entries (file missing) removed
+//! - Unknown files (on disk, not in the state) counted and registered
+//! - Listed files whose size or mtime changed since they were recorded are
+//!   recounted, and their entries fixed if the count differs
+//! - Unreadable files reported and left as they are
+//! - Cumulative totals recomputed, state flushed and exported
+//! - Reconciliation report written as nsl_{size:02}_repair_report.txt
+//! - With --dry-run, the reconciliation is computed and reported but nothing is written
+//! - Check --fix: the same reconciliation, then the spurious gaps in the batch
+//!   numbering (batches no file ever had: no tombstone, no history entry) closed
+//!   by renaming the files above them, when safe: no compaction or rebalance in
+//!   progress, no compacted file to move, no batch consumed by the next size in
+//!   this directory (a next size in another directory, as with --cascade, is not
+//!   seen: fix before computing it). The consolidated count file is rewritten, and
+//!   every fix appended to nsl_{size:02}_repair.log
+//!
+//! Used by --repair mode and --check --fix
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::path::Path;
+use separator::Separatable;
+
+use crate::dry_run::{DryRunPlan, Operation};
+use crate::file_info::{GlobalFileState, InputKey, RemovalReason};
+use crate::filenames::parse_filename;
+use crate::utils::*;
+
+/// Outcome of a reconciliation
+#[derive(Debug, Clone, Default)]
+pub struct RepairReport {
+    pub size: u8,
+    pub lists_before: u64,
+    pub lists_after: u64,
+    pub removed: Vec<String>,              // stale entries (file missing)
+    pub registered: Vec<(String, u64)>,    // unknown files and their counts
+    pub fixed: Vec<(String, u64, u64)>,    // recounted files: recorded count, actual count
+    pub unreadable: Vec<(String, String)>, // files that could not be counted
+}
+
+impl RepairReport {
+    pub fn changes(&self) -> usize {
+        self.removed.len() + self.registered.len() + self.fixed.len()
+    }
+
+    /// Human-readable rendering (the report file)
+    pub fn to_txt(&self) -> String {
+        let mut txt = String::new();
+        txt.push_str(&format!("# Repair report for no-set-{:02} lists\n", self.size));
+        txt.push_str(&format!("# Generated: {}\n", chrono::Local::now().format("%Y-%m-%d %H:%M:%S")));
+        txt.push_str(&format!("# Total lists: {} before, {} after\n#\n",
+            self.lists_before.separated_string(), self.lists_after.separated_string()));
+        txt.push_str(&format!("# Stale entries removed (file missing): {}\n", self.removed.len()));
+        for filename in self.removed.iter() {
+            txt.push_str(&format!("removed    | {}\n", filename));
+        }
+        txt.push_str(&format!("# Unknown files registered: {}\n", self.registered.len()));
+        for (filename, count) in self.registered.iter() {
+            txt.push_str(&format!("registered | {:>15} | {}\n", count.separated_string(), filename));
+        }
+        txt.push_str(&format!("# Counts fixed: {}\n", self.fixed.len()));
+        for (filename, recorded, actual) in self.fixed.iter() {
+            txt.push_str(&format!("fixed      | {:>15} -> {:>15} | {}\n",
+                recorded.separated_string(), actual.separated_string(), filename));
+        }
+        txt.push_str(&format!("# Unreadable files (left as they are): {}\n", self.unreadable.len()));
+        for (filename, error) in self.unreadable.iter() {
+            txt.push_str(&format!("unreadable | {} | {}\n", filename, error));
+        }
+        txt
+    }
+}
+
+/// Outcome of check --fix
+#[derive(Debug, Clone, Default)]
+pub struct FixReport {
+    pub repair: RepairReport,
+    pub spurious_gaps: Vec<u32>,           // batches no file ever had
+    pub real_gaps: Vec<u32>,               // batches of files gone (left as they are)
+    pub renumbered: Vec<(String, String)>, // (old name, new name)
+    pub not_renumbered: Option<String>,    // why the spurious gaps were left open
+}
+
+impl FixReport {
+    pub fn changes(&self) -> usize {
+        self.repair.changes() + self.renumbered.len()
+    }
+
+    /// One line per fix, for the repair log
+    fn log_lines(&self) -> Vec<String> {
+        let mut lines: Vec<String> = Vec::new();
+        lines.extend(self.repair.removed.iter().map(|f| format!("removed    | {} (file missing)", f)));
+        lines.extend(self.repair.registered.iter().map(|(f, count)| format!("registered | {} ({} lists)", f, count)));
+        lines.extend(self.repair.fixed.iter().map(|(f, recorded, actual)| format!("recounted  | {} ({} -> {} lists)", f, recorded, actual)));
+        lines.extend(self.renumbered.iter().map(|(old, new)| format!("renumbered | {} -> {}", old, new)));
+        if let Some(reason) = &self.not_renumbered {
+            lines.push(format!("gaps left  | {:?}: {}", self.spurious_gaps, reason));
+        }
+        lines
+    }
+}
+
+/// Size in bytes and mtime (unix seconds) of a file
+fn file_metadata(path: &Path) -> (Option<u64>, Option<i64>) {
+    let metadata = std::fs::metadata(path).ok();
+    let file_size = metadata.as_ref().map(|m| m.len());
+    let mtime = metadata.as_ref()
+        .and_then(|m| m.modified().ok())
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64);
+    (file_size, mtime)
+}
+
+/// Reconcile the state of `size` in `base_path` with the files on disk
+pub fn repair_size_state(base_path: &str, size: u8, dry_run: bool) -> std::io::Result<RepairReport> {
+    test_print(&format!("\nREPAIR MODE: Reconciling the size {:02} state with the disk...", size));
+    test_print(&format!("   Directory: {}", base_path));
+
+    let mut state = match GlobalFileState::from_sources(base_path, size) {
+        Ok(state) => state,
+        Err(e) if crate::file_info::is_state_locked(&e) => return Err(e),
+        Err(e) => {
+            test_print(&format!("   ... could not load the state ({}): rebuilding it from disk", e));
+            GlobalFileState::new(base_path, size)
+        }
+    };
+    let mut report = RepairReport {
+        size,
+        lists_before: state.entries().values().map(|e| e.nb_lists_in_file).sum(),
+        ..Default::default()
+    };
+
+    // Files of this size on disk, by filename
+    let mut on_disk: BTreeMap<String, crate::filenames::ParsedFilename> = BTreeMap::new();
+    for entry in std::fs::read_dir(base_path)?.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if let Some(parsed) = parse_filename(&name)
+            && parsed.target_size == size {
+            on_disk.insert(name, parsed);
+        }
+    }
+
+    // Listed entries: stale ones removed, changed ones recounted
+    let listed: Vec<_> = state.entries().values().cloned().collect();
+    for entry in listed.iter() {
+        let path = Path::new(base_path).join(&entry.filename);
+        if !on_disk.contains_key(&entry.filename) {
+            state.remove_file(&entry.filename, entry.source_batch, entry.target_batch, RemovalReason::Pruned);
+            report.removed.push(entry.filename.clone());
+            continue;
+        }
+        let (file_size, mtime) = file_metadata(&path);
+        let unchanged = entry.file_size_bytes.is_some() && entry.file_size_bytes == file_size
+            && entry.modified_timestamp == mtime;
+        if unchanged {
+            continue;
+        }
+        match crate::io_helpers::count_lists_in_file(&path.to_string_lossy()) {
+            Ok(count) => {
+                if count != entry.nb_lists_in_file {
+                    report.fixed.push((entry.filename.clone(), entry.nb_lists_in_file, count));
+                }
+                state.update_entry(&entry.filename, entry.source_batch, entry.target_batch,
+                    count, entry.compacted, file_size, mtime);
+            }
+            Err(e) => report.unreadable.push((entry.filename.clone(), e.to_string())),
+        }
+    }
+
+    // Unknown files: counted and registered
+    for (filename, parsed) in on_disk.iter() {
+        if listed.iter().any(|e| &e.filename == filename) {
+            continue;
+        }
+        let path = Path::new(base_path).join(filename);
+        match crate::io_helpers::count_lists_in_file(&path.to_string_lossy()) {
+            Ok(count) => {
+                let (file_size, mtime) = file_metadata(&path);
+                state.register_file(filename, parsed.source_batch, parsed.target_batch,
+                    count, parsed.compacted, file_size, mtime);
+                report.registered.push((filename.clone(), count));
+            }
+            Err(e) => report.unreadable.push((filename.clone(), e.to_string())),
+        }
+    }
+
+    report.lists_after = state.entries().values().map(|e| e.nb_lists_in_file).sum();
+    let report_path = Path::new(base_path).join(format!("nsl_{:02}_repair_report.txt", size));
+    if dry_run {
+        let mut plan = DryRunPlan::new(&format!("repair of size {:02} in {}", size, base_path));
+        for line in report.to_txt().lines().filter(|l| !l.starts_with('#')) {
+            plan.note(line.to_string());
+        }
+        crate::dry_run::add_state_writes(&mut plan, base_path, size, false);
+        plan.add(Operation::Write, &report_path, "reconciliation report");
+        plan.print();
+    } else {
+        state.flush()?;
+        state.export_human_readable()?;
+        std::fs::write(&report_path, report.to_txt())?;
+    }
+    test_print(&format!("   ... {} stale entries removed, {} files registered, {} counts fixed, {} unreadable",
+        report.removed.len(), report.registered.len(), report.fixed.len(), report.unreadable.len()));
+    test_print(&format!("   ... total lists: {} before, {} after",
+        report.lists_before.separated_string(), report.lists_after.separated_string()));
+    if !dry_run {
+        test_print(&format!("   Reconciliation report: {}", report_path.display()));
+    }
+    Ok(report)
+}
+
+/// Gaps in the target batch numbering of `state`: (spurious, real), a real gap being
+/// a batch with a tombstone or a history entry
+fn classify_gaps(base_path: &str, size: u8, state: &GlobalFileState) -> (Vec<u32>, Vec<u32>) {
+    let batches: BTreeSet<u32> = state.entries().values().map(|e| e.target_batch).collect();
+    let (Some(&first), Some(&last)) = (batches.first(), batches.last()) else {
+        return (Vec::new(), Vec::new());
+    };
+    let mut traced: BTreeSet<u32> = state.tombstones().values().map(|t| t.target_batch).collect();
+    if let Ok(history) = GlobalFileState::from_history_file(base_path, size, "rkyv") {
+        traced.extend(history.entries().values().map(|e| e.target_batch));
+    }
+    (first..=last).filter(|b| !batches.contains(b)).partition(|b| !traced.contains(b))
+}
+
+/// Why the files above `first_gap` cannot be renumbered (None if they can)
+fn renumber_blocker(base_path: &str, size: u8, state: &GlobalFileState, first_gap: u32, real_gaps: &[u32]) -> std::io::Result<Option<String>> {
+    if let Some(b) = real_gaps.iter().find(|b| **b > first_gap) {
+        return Ok(Some(format!("batch {:06} above is the gap of a file gone, renumbering would hide it", b)));
+    }
+    for journal in [crate::compaction::compaction_journal_path(base_path, size), crate::compaction::rebalance_journal_path(base_path, size)] {
+        if journal.exists() {
+            return Ok(Some(format!("{} in progress (finish it first)", journal.display())));
+        }
+    }
+    let moved: Vec<_> = state.entries().values().filter(|e| e.target_batch > first_gap).collect();
+    if let Some(e) = moved.iter().find(|e| e.compacted) {
+        return Ok(Some(format!("compacted file {} would move", e.filename)));
+    }
+    if let Some(e) = moved.iter().find(|e| state.is_leased(&e.filename)) {
+        return Ok(Some(format!("{} is in use by a running compaction", e.filename)));
+    }
+    let next = crate::dry_run::load_state_readonly(base_path, size + 1)?;
+    // Only regular files move: a compacted input of the same number does not block
+    let moved_inputs: BTreeSet<InputKey> = moved.iter().map(|e| InputKey::whole(e.target_batch, false)).collect();
+    if let Some(input) = next.consumed_among(&moved_inputs, &BTreeSet::new()).first() {
+        return Ok(Some(format!("batch {:06} already consumed by size {:02}", input.batch, size + 1)));
+    }
+    Ok(None)
+}
+
+/// Rename the files above the spurious gaps down to close them, updating the state as it goes
+fn renumber_files(base_path: &str, state: &mut GlobalFileState, gaps: &[u32]) -> std::io::Result<Vec<(String, String)>> {
+    let mut moved: Vec<_> = state.entries().values()
+        .filter(|e| e.target_batch > gaps[0])
+        .cloned()
+        .collect();
+    moved.sort_by_key(|e| e.target_batch);
+    let mut renamed = Vec::new();
+    for entry in moved.iter() {
+        let new_batch = entry.target_batch - gaps.iter().filter(|g| **g < entry.target_batch).count() as u32;
+        let Some(new_name) = crate::normalize::with_target_batch(&entry.filename, new_batch) else { continue };
+        let (old_path, new_path) = (Path::new(base_path).join(&entry.filename), Path::new(base_path).join(&new_name));
+        if new_path.exists() {
+            return Err(std::io::Error::other(format!("cannot renumber {}: {} already exists", entry.filename, new_name)));
+        }
+        std::fs::rename(&old_path, &new_path)?;
+        crate::io_helpers::invalidate_cached_batch(&old_path.to_string_lossy());
+        state.remove_file(&entry.filename, entry.source_batch, entry.target_batch, RemovalReason::Manual);
+        state.register_file(&new_name, entry.source_batch, new_batch, entry.nb_lists_in_file,
+            entry.compacted, entry.file_size_bytes, entry.modified_timestamp);
+        if let Some(provenance) = entry.provenance.clone() {
+            state.set_provenance(&new_name, entry.source_batch, new_batch, provenance);
+        }
+        state.flush()?;
+        debug_print(&format!("   ... renumbered {} -> {}", entry.filename, new_name));
+        renamed.push((entry.filename.clone(), new_name));
+    }
+    Ok(renamed)
+}
+
+/// Check --fix: reconcile the state of `size` in `base_path` with the disk, then close
+/// the spurious gaps of the batch numbering when safe, logging every fix
+pub fn fix_size_files(base_path: &str, size: u8) -> std::io::Result<FixReport> {
+    let mut report = FixReport { repair: repair_size_state(base_path, size, false)?, ..Default::default() };
+    let mut state = GlobalFileState::from_sources(base_path, size)?;
+    (report.spurious_gaps, report.real_gaps) = classify_gaps(base_path, size, &state);
+    if !report.real_gaps.is_empty() {
+        test_print(&format!("   ... {} gaps left open (their files existed): {:?}", report.real_gaps.len(), report.real_gaps));
+    }
+    if let Some(&first_gap) = report.spurious_gaps.first() {
+        match renumber_blocker(base_path, size, &state, first_gap, &report.real_gaps)? {
+            Some(reason) => {
+                test_print(&format!("   ... {} spurious gaps left open: {}", report.spurious_gaps.len(), reason));
+                report.not_renumbered = Some(reason);
+            }
+            None => {
+                report.renumbered = renumber_files(base_path, &mut state, &report.spurious_gaps)?;
+                crate::normalize::rewrite_count_files(base_path, size, &report.renumbered)?;
+                state.export_human_readable()?;
+                test_print(&format!("   ... {} spurious gaps closed, {} files renumbered",
+                    report.spurious_gaps.len(), report.renumbered.len()));
+            }
+        }
+    }
+
+    // The consolidated count file names the files: rewritten from the fixed state
+    let count_path = Path::new(base_path).join(format!("nsl_{:02}_global_count.txt", size));
+    if report.changes() > 0 && count_path.exists() {
+        let text = crate::file_info::render_global_count(&state.to_vec(), size, base_path);
+        crate::io_helpers::write_file_atomic(&count_path.to_string_lossy(), text.as_bytes())?;
+    }
+
+    let lines = report.log_lines();
+    if !lines.is_empty() {
+        let log_path = Path::new(base_path).join(format!("nsl_{:02}_repair.log", size));
+        let mut log = std::fs::OpenOptions::new().create(true).append(true).open(&log_path)?;
+        let now = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
+        for line in lines.iter() {
+            writeln!(log, "{} | check --fix | {}", now, line)?;
+        }
+        test_print(&format!("   Repair log: {} ({} fixes)", log_path.display(), lines.len()));
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::no_set_list::NoSetListSerialized;
+
+    #[test]
+    fn stale_entries_removed_and_unknown_files_registered() {
+        let dir = crate::test_dir::TestDir::new("repair");
+        let dir_str = dir.to_string_lossy().into_owned();
+
+        let list = NoSetListSerialized { n: 4, max_card: 5, no_set_list: vec![0, 1, 3, 5], remaining_cards_list: vec![6] };
+        let unknown = crate::filenames::output_filename(&dir_str, 3, 0, 4, 1);
+        assert!(crate::io_helpers::save_to_file_serialized(&vec![list.clone(), list], &unknown));
+
+        let mut state = GlobalFileState::new(&dir_str, 4);
+        state.register_file("nsl_03_batch_000000_to_04_batch_000000.rkyv", 0, 0, 7, false, None, None);
+        state.flush().expect("flush");
+
+        let report = repair_size_state(&dir_str, 4, false).expect("repair");
+        assert_eq!(report.removed, vec!["nsl_03_batch_000000_to_04_batch_000000.rkyv".to_string()]);
+        assert_eq!(report.registered.len(), 1);
+        assert_eq!((report.lists_before, report.lists_after), (7, 2));
+
+        let state = GlobalFileState::from_sources(&dir_str, 4).expect("state");
+        assert_eq!(state.entries().len(), 1);
+        let tombstones: Vec<_> = state.tombstones().values().map(|t| (t.filename.as_str(), t.reason)).collect();
+        assert_eq!(tombstones, vec![("nsl_03_batch_000000_to_04_batch_000000.rkyv", RemovalReason::Pruned)]);
+    }
+
+    #[test]
+    fn fix_closes_spurious_gaps_and_logs_every_fix() {
+        let dir = crate::test_dir::TestDir::new("check_fix");
+        let dir_str = dir.to_string_lossy().into_owned();
+
+        let list = NoSetListSerialized { n: 4, max_card: 5, no_set_list: vec![0, 1, 3, 5], remaining_cards_list: vec![6] };
+        let mut state = GlobalFileState::new(&dir_str, 4);
+        // Batches 0, 1, 3 recorded, 4 on disk only, 6 recorded but gone: batch 2 never existed
+        for batch in [0, 1, 3, 4] {
+            let path = crate::filenames::output_filename(&dir_str, 3, 0, 4, batch);
+            assert!(crate::io_helpers::save_to_file_serialized(&vec![list.clone()], &path));
+            if batch != 4 {
+                state.register_file(&format!("nsl_03_batch_000000_to_04_batch_{:06}.rkyv", batch), 0, batch, 1, false, None, None);
+            }
+        }
+        state.register_file("nsl_03_batch_000000_to_04_batch_000006.rkyv", 0, 6, 1, false, None, None);
+        state.flush().expect("flush");
+
+        let report = fix_size_files(&dir_str, 4).expect("fix");
+        assert_eq!((report.repair.removed.len(), report.repair.registered.len()), (1, 1));
+        assert_eq!(report.spurious_gaps, vec![2]);
+        assert_eq!(report.renumbered, vec![
+            ("nsl_03_batch_000000_to_04_batch_000003.rkyv".to_string(), "nsl_03_batch_000000_to_04_batch_000002.rkyv".to_string()),
+            ("nsl_03_batch_000000_to_04_batch_000004.rkyv".to_string(), "nsl_03_batch_000000_to_04_batch_000003.rkyv".to_string()),
+        ]);
+        assert!(!dir.join("nsl_03_batch_000000_to_04_batch_000004.rkyv").exists());
+
+        let state = GlobalFileState::from_sources(&dir_str, 4).expect("state");
+        let batches: Vec<u32> = state.entries().values().map(|e| e.target_batch).collect();
+        assert_eq!(batches, vec![0, 1, 2, 3]);
+        let log = std::fs::read_to_string(dir.join("nsl_04_repair.log")).expect("log");
+        assert_eq!(log.lines().count(), 4);
+    }
+}
@@ -0,0 +1,132 @@
+//! Restore-state module: roll the state of a size back to one of its backups
+//!
+//! Each flush keeps the previous state as nsl_XX_global_info.rkyv.old, and with
+//! --keep-backups N the N-1 before it as nsl_XX_global_info.rkyv.<timestamp>.old.
+//! This module lists them and puts the chosen one back in place.
+//!
+//! Key features:
+//! - Backup chosen by timestamp (%Y%m%d_%H%M%S, any unambiguous prefix), the most
+//!   recent one by default
+//! - The backup is loaded (validated) before anything is touched
+//! - The replaced state becomes the last backup (.rkyv.old): a restore can be undone
+//!   by restoring again
+//! - The journal is deleted (its changes belong to the replaced state) and the
+//!   JSON/TXT exports are rewritten from the restored state
+//! - rkyv backend only (the sqlite backend keeps no backups)
+//!
+//! Used by --restore-state mode
+
+use std::path::Path;
+use separator::Separatable;
+
+use crate::file_info::{acquire_state_lock, journal_path, keep_backups, rotate_backups, state_backups,
+    GlobalFileInfo, GlobalFileState, StateBackup};
+use crate::utils::*;
+
+/// Result of restoring the state of one size
+#[derive(Debug, Clone)]
+pub struct RestoreStateReport {
+    pub size: u8,
+    pub timestamp: String,
+    pub entries: u64,
+}
+
+/// Backup matching `timestamp` (a prefix of it), the most recent one if None
+fn choose_backup<'a>(backups: &'a [StateBackup], timestamp: Option<&str>) -> std::io::Result<&'a StateBackup> {
+    let Some(wanted) = timestamp else {
+        return backups.last().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "No backup found"));
+    };
+    let matching: Vec<&StateBackup> = backups.iter().filter(|b| b.timestamp.starts_with(wanted)).collect();
+    let available = || backups.iter().map(|b| b.timestamp.as_str()).collect::<Vec<_>>().join(", ");
+    match matching.last() {
+        None => Err(std::io::Error::new(std::io::ErrorKind::NotFound,
+            format!("No backup matches {} (available: {})", wanted, available()))),
+        Some(chosen) if matching.iter().all(|b| b.timestamp == chosen.timestamp) => Ok(chosen),
+        Some(_) => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+            format!("{} matches several backups (available: {})", wanted, available()))),
+    }
+}
+
+/// Restore the state of `size` in `base_path` from the backup matching `timestamp`
+/// (the most recent one if None)
+pub fn restore_state(base_path: &str, size: u8, timestamp: Option<&str>) -> std::io::Result<RestoreStateReport> {
+    test_print(&format!("\nRESTORE STATE MODE: Rolling the size {:02} state back to a backup...", size));
+    test_print(&format!("   Directory: {}", base_path));
+
+    let path = Path::new(base_path).join(format!("nsl_{:02}_global_info.rkyv", size));
+    let backups = state_backups(&path);
+    if backups.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound,
+            format!("No backup of {} found", path.display())));
+    }
+    test_print("   Backups (oldest first):");
+    for backup in backups.iter() {
+        let bytes = std::fs::metadata(&backup.path).map(|m| m.len()).unwrap_or(0);
+        test_print(&format!("   ... {}  {:>12} bytes  {}", backup.timestamp, bytes.separated_string(),
+            backup.path.file_name().unwrap_or_default().to_string_lossy()));
+    }
+    let chosen = choose_backup(&backups, timestamp)?.clone();
+    let gfi = GlobalFileInfo::load_rkyv(&chosen.path)?;
+    acquire_state_lock(base_path, size)?;
+
+    // Copy first: the chosen backup may be the .rkyv.old the rotation replaces
+    let tmp = path.with_extension("rkyv.tmp");
+    std::fs::copy(&chosen.path, &tmp)?;
+    rotate_backups(&path, keep_backups())?;
+    std::fs::rename(&tmp, &path)?;
+
+    let journal = journal_path(base_path, size);
+    if journal.exists() {
+        test_print(&format!("   ... deleting the journal {} (changes of the replaced state)", journal.display()));
+        std::fs::remove_file(&journal)?;
+    }
+    GlobalFileState::from_sources(base_path, size)?.export_human_readable()?;
+
+    let report = RestoreStateReport { size, timestamp: chosen.timestamp, entries: gfi.entries.len() as u64 };
+    test_print(&format!("   ... restored the backup of {} ({} entries); the replaced state is now the last backup",
+        report.timestamp, report.entries.separated_string()));
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_info::FileInfo;
+    use chrono::TimeZone;
+
+    fn state_with(entries: u32) -> GlobalFileInfo {
+        GlobalFileInfo::new((0..entries).map(|batch| FileInfo {
+            source_batch: 0, target_batch: batch, cumulative_nb_lists: 0, nb_lists_in_file: 1,
+            filename: format!("nsl_04_batch_000000_to_05_batch_{:06}.rkyv", batch), compacted: false,
+            compressed: false, exists: None, file_size_bytes: None, modified_timestamp: None, provenance: None,
+        }).collect())
+    }
+
+    #[test]
+    fn backups_are_rotated_and_restored_by_timestamp() {
+        let dir = crate::test_dir::TestDir::new("restore_state");
+        let dir_str = dir.to_string_lossy().into_owned();
+        let path = dir.join("nsl_05_global_info.rkyv");
+
+        // Five flushes, one day apart, keeping 3 backups
+        for day in 1..=5 {
+            let tmp = path.with_extension("rkyv.tmp");
+            state_with(day).save_rkyv(&tmp).expect("save");
+            let written = chrono::Local.with_ymd_and_hms(2026, 1, day, 12, 0, 0).single().expect("date");
+            std::fs::File::options().write(true).open(&tmp).expect("open")
+                .set_modified(written.into()).expect("set mtime");
+            rotate_backups(&path, 3).expect("rotate");
+            std::fs::rename(&tmp, &path).expect("rename");
+        }
+        let stamps: Vec<String> = state_backups(&path).into_iter().map(|b| b.timestamp).collect();
+        assert_eq!(stamps, vec!["20260102_120000", "20260103_120000", "20260104_120000"]);
+        assert!(choose_backup(&state_backups(&path), Some("2026010")).is_err(), "ambiguous prefix");
+
+        let report = restore_state(&dir_str, 5, Some("20260102")).expect("restore");
+        assert_eq!((report.timestamp.as_str(), report.entries), ("20260102_120000", 2));
+        assert_eq!(GlobalFileInfo::load_rkyv(&path).expect("load").entries.len(), 2);
+
+        let undo = restore_state(&dir_str, 5, None).expect("undo");
+        assert_eq!(undo.entries, 5, "the replaced state was kept as the last backup");
+    }
+}
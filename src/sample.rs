@@ -0,0 +1,148 @@
+//! Sampling module: export a uniform random subset of the stored lists of a size
+//!
+//! Every stored list has the same probability of being picked, whatever the
+//! size of the file holding it.
+//!
+//! Key features:
+//! - N distinct global indices drawn uniformly among all lists (Floyd's algorithm),
+//!   mapped to (file, offset) with the per-file counts of the global state
+//! - Only the files holding sampled lists are read, one at a time, and within
+//!   them only the ranges of lists around the sampled offsets
+//! - Reproducible with --seed (SplitMix64, as --random-walk)
+//! - Saved as nsl_{size:02}_sample_{N}.rkyv and .json
+//!
+//! Used by --sample mode
+
+use std::collections::BTreeSet;
+use std::path::Path;
+use separator::Separatable;
+
+use crate::file_info::GlobalFileState;
+use crate::no_set_list::NoSetListSerialized;
+use crate::random_walk::SplitMix64;
+use crate::utils::*;
+
+/// Draw `n` distinct indices uniformly in 0..total (all of them if n >= total), sorted
+pub fn sample_indices(total: u64, n: u64, rng: &mut SplitMix64) -> BTreeSet<u64> {
+    let n = n.min(total);
+    let mut chosen = BTreeSet::new();
+    for j in (total - n)..total {
+        let t = rng.next_u64() % (j + 1);
+        if !chosen.insert(t) {
+            chosen.insert(j);
+        }
+    }
+    chosen
+}
+
+/// Group sorted offsets into runs read with one range load: an offset joins the
+/// run of the previous one unless a whole frame of lists lies between them
+fn offset_ranges(offsets: &[u64]) -> Vec<&[u64]> {
+    let gap = crate::io_helpers::FRAME_LISTS as u64;
+    offsets.chunk_by(|a, b| b - a <= gap).collect()
+}
+
+/// Sample `n` lists of `size` from `input_dir`
+pub fn sample_size_lists(input_dir: &str, size: u8, n: u64, rng_seed: u64) -> std::io::Result<Vec<NoSetListSerialized>> {
+    test_print(&format!("\nSAMPLE MODE: {} random lists of size {:02} (RNG seed {})", n.separated_string(), size, rng_seed));
+    test_print(&format!("   Directory: {}", input_dir));
+
+    // Per-file counts, in state order (source batch, target batch, filename)
+    let state = GlobalFileState::from_sources(input_dir, size)?;
+    let files: Vec<(String, u64)> = state.entries().values()
+        .filter(|e| e.nb_lists_in_file > 0)
+        .map(|e| (e.filename.clone(), e.nb_lists_in_file))
+        .collect();
+    let total: u64 = files.iter().map(|(_, count)| count).sum();
+    if total == 0 {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound,
+            format!("No size {:02} lists recorded in the state of {} (run --count {} first)", size, input_dir, size)));
+    }
+    test_print(&format!("   ... {} lists in {} files", total.separated_string(), files.len()));
+
+    let mut rng = SplitMix64::new(rng_seed);
+    let indices = sample_indices(total, n, &mut rng);
+
+    // Walk the files in cumulative order, reading only those holding sampled lists
+    let mut sample = Vec::with_capacity(indices.len());
+    let mut picked = indices.iter().peekable();
+    let mut first_index = 0u64;
+    for (filename, count) in files.iter() {
+        let end = first_index + count;
+        let offsets: Vec<u64> = std::iter::from_fn(|| picked.next_if(|&&i| i < end).map(|&i| i - first_index)).collect();
+        if !offsets.is_empty() {
+            let path = Path::new(input_dir).join(filename);
+            for range in offset_ranges(&offsets) {
+                let (start, len) = (range[0], range[range.len() - 1] - range[0] + 1);
+                let lists = crate::io_helpers::load_lists_range(&path.to_string_lossy(), start as usize, len as usize)?;
+                if (lists.len() as u64) < len {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+                        format!("{} holds {} lists but the state records {} (run --count {} --force)",
+                            filename, start + lists.len() as u64, count, size)));
+                }
+                sample.extend(range.iter().map(|offset| lists[(offset - start) as usize].clone()));
+            }
+            debug_print(&format!("   ... {} lists sampled from {}", offsets.len(), filename));
+        }
+        first_index = end;
+    }
+
+    test_print(&format!("   ... {} lists sampled", sample.len().separated_string()));
+    Ok(sample)
+}
+
+/// Save the sample as nsl_{size:02}_sample_{N}.rkyv and .json in `output_dir`
+pub fn save_sample(output_dir: &str, size: u8, sample: &Vec<NoSetListSerialized>) -> std::io::Result<()> {
+    let rkyv_path = Path::new(output_dir).join(format!("nsl_{:02}_sample_{}.rkyv", size, sample.len()));
+    if !crate::io_helpers::save_to_file_serialized(sample, &rkyv_path.to_string_lossy()) {
+        return Err(std::io::Error::other(format!("Cannot write {}", rkyv_path.display())));
+    }
+    let json = serde_json::to_string_pretty(sample)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    std::fs::write(rkyv_path.with_extension("json"), json)?;
+    test_print(&format!("   Sample saved: {} and .json", rkyv_path.display()));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sampled_indices_are_distinct_and_in_range() {
+        let mut rng = SplitMix64::new(7);
+        let indices = sample_indices(1_000, 100, &mut rng);
+        assert_eq!(indices.len(), 100);
+        assert!(indices.iter().all(|&i| i < 1_000));
+
+        let all = sample_indices(10, 50, &mut rng);
+        assert_eq!(all, (0..10).collect::<BTreeSet<u64>>());
+    }
+
+    #[test]
+    fn close_offsets_share_a_range_load() {
+        let frame = crate::io_helpers::FRAME_LISTS as u64;
+        let offsets = [3, 5, 5 + frame, 7 + 3 * frame, 8 + 3 * frame];
+        assert_eq!(offset_ranges(&offsets), vec![&offsets[..3], &offsets[3..]]);
+        assert!(offset_ranges(&[]).is_empty());
+    }
+
+    #[test]
+    fn only_the_sampled_lists_are_read_and_kept_in_order() {
+        let dir = crate::test_dir::TestDir::new("sample_ranges");
+        let lists: Vec<NoSetListSerialized> = (0..40)
+            .map(|i| NoSetListSerialized { n: 3, max_card: i, no_set_list: vec![0, 1, i], remaining_cards_list: vec![] })
+            .collect();
+        for (batch, chunk) in lists.chunks(20).enumerate() {
+            let path = dir.join(format!("nsl_02_batch_000000_to_03_batch_{:06}.rkyv", batch));
+            assert!(crate::io_helpers::save_to_file_serialized(&chunk.to_vec(), &path.to_string_lossy()));
+        }
+        crate::list_of_nsl::count_size_files(&dir.str(), 3, false, false).expect("count");
+
+        let sample = sample_size_lists(&dir.str(), 3, 40, 1).expect("sample");
+        assert_eq!(sample.iter().map(|l| l.max_card).collect::<Vec<_>>(), (0..40).collect::<Vec<_>>());
+        let sample = sample_size_lists(&dir.str(), 3, 7, 1).expect("sample");
+        assert_eq!(sample.len(), 7);
+        assert!(sample.windows(2).all(|w| w[0].max_card < w[1].max_card), "distinct, in file order");
+    }
+}
@@ -0,0 +1,243 @@
+//! Scan module: full validation of every list file of a size, quarantining the corrupted ones
+//!
+//! --checksum reads the footers only and --check counts the lists: a file can pass
+//! both (its archive validating when mapped) and still fail partway through
+//! decoding. This scan decodes every list of every file and checks its fields.
+//!
+//! Key features:
+//! - Files scanned in parallel (WORKERS threads, default one per core)
+//! - Footer checksum (files that have one), every archive validated, every list decoded
+//! - Fields of each list: n = size, n cards below 81 and ascending, max_card = last
+//!   card, remaining cards below 81, ascending and above max_card (the set checks
+//!   are left to --verify)
+//! - List count compared with the footer
+//! - Corrupted files moved to the quarantine/ subdirectory of their directory and
+//!   removed from the state (tombstone reason: quarantined)
+//! - Report saved as nsl_{size:02}_scan_report.json, with one finding (see
+//!   findings) per corrupted file
+//!
+//! Used by --scan mode
+
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use separator::Separatable;
+use serde::Serialize;
+
+use crate::file_info::{GlobalFileState, RemovalReason};
+use crate::findings::{Finding, FindingsReport};
+use crate::no_set_list::NoSetListSerialized;
+use crate::utils::*;
+
+/// Lists decoded at once per worker
+const SCAN_CHUNK_LISTS: usize = 1_000_000;
+
+/// Subdirectory receiving the corrupted files
+pub const QUARANTINE_DIR: &str = "quarantine";
+
+/// Result of the scan of one size
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScanReport {
+    pub size: u8,
+    pub files_scanned: u64,
+    pub lists_scanned: u64,
+    pub bytes: u64,
+    pub corrupted: Vec<(String, String)>,  // (filename, problem)
+    pub quarantined: Vec<String>,          // paths the corrupted files were moved to
+    pub state_entries_removed: u64,
+}
+
+/// First problem found in the fields of a list (None if they are sound)
+fn field_problem(list: &NoSetListSerialized, size: u8) -> Option<String> {
+    let cards = &list.no_set_list;
+    let remaining = &list.remaining_cards_list;
+    if list.n != size {
+        return Some(format!("n = {} (expected {})", list.n, size));
+    }
+    if cards.len() != list.n as usize {
+        return Some(format!("{} cards stored for n = {}", cards.len(), list.n));
+    }
+    if cards.iter().chain(remaining.iter()).any(|&c| c >= 81) {
+        return Some("card index out of range (>= 81)".to_string());
+    }
+    if !cards.windows(2).all(|w| w[0] < w[1]) || !remaining.windows(2).all(|w| w[0] < w[1]) {
+        return Some("cards not strictly ascending".to_string());
+    }
+    if cards.last() != Some(&list.max_card) {
+        return Some(format!("max_card = {} but last card is {:?}", list.max_card, cards.last()));
+    }
+    if remaining.first().is_some_and(|&r| r <= list.max_card) {
+        return Some(format!("remaining card {:?} <= max_card {}", remaining.first(), list.max_card));
+    }
+    None
+}
+
+/// Decode every list of `path` and check it, returns the number of lists
+fn scan_file(path: &str, size: u8) -> Result<u64, String> {
+    let footer = crate::io_helpers::verify_file_checksum(path).map_err(|e| e.to_string())?;
+    let mut index = 0u64;
+    crate::io_helpers::load_lists_in_chunks(path, SCAN_CHUNK_LISTS, |chunk| {
+        for list in chunk.iter() {
+            if let Some(problem) = field_problem(list, size) {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("list {}: {}", index, problem)));
+            }
+            index += 1;
+        }
+        Ok(())
+    }).map_err(|e| e.to_string())?;
+    match footer {
+        Some(footer) if footer.nb_lists != index => Err(format!("footer records {} lists, {} decoded", footer.nb_lists, index)),
+        _ => Ok(index),
+    }
+}
+
+/// Move a corrupted file to the quarantine subdirectory of its directory, returns its new path
+fn quarantine(path: &str) -> std::io::Result<String> {
+    let resolved = crate::storage::resolve_path(path);
+    let source = Path::new(&resolved);
+    let dir = source.parent().unwrap_or(Path::new(".")).join(QUARANTINE_DIR);
+    std::fs::create_dir_all(&dir)?;
+    let name = source.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let mut target = dir.join(&name);
+    if target.exists() {
+        target = dir.join(format!("{}.{}", name, crate::file_info::unix_now()));
+    }
+    crate::storage::rename_list_file(path, &target.to_string_lossy())?;
+    crate::io_helpers::invalidate_cached_batch(path);
+    Ok(target.to_string_lossy().into_owned())
+}
+
+/// Scan every file of `size` in `base_path` on `workers` threads (0: one per core),
+/// quarantine the corrupted ones and drop them from the state
+pub fn scan_size_files(base_path: &str, size: u8, workers: usize) -> std::io::Result<ScanReport> {
+    test_print(&format!("\nSCAN MODE: Decoding every list of size {:02}...", size));
+    test_print(&format!("   Directory: {}", base_path));
+    let start_time = std::time::Instant::now();
+
+    let files = crate::filenames::list_input_files(base_path, size);
+    if files.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound,
+            format!("No size {:02} files found in {}", size, base_path)));
+    }
+    let workers = match workers {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    }.min(files.len());
+    test_print(&format!("   ... {} files on {} threads", files.len(), workers));
+
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, Result<u64, String>)> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers).map(|_| scope.spawn(|| {
+            let mut scanned = Vec::new();
+            loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                if i >= files.len() {
+                    break;
+                }
+                // A decoder panic on a corrupted archive is a corrupted file, not a failed scan
+                let result = std::panic::catch_unwind(|| scan_file(&files[i].path, size))
+                    .unwrap_or_else(|_| Err("decoding panicked".to_string()));
+                scanned.push((i, result));
+                if (i + 1).is_multiple_of(100) {
+                    progress_print(&format!("   ... {} of {} files scanned", i + 1, files.len()));
+                }
+            }
+            scanned
+        })).collect();
+        handles.into_iter().flat_map(|h| h.join().unwrap_or_default()).collect()
+    });
+    results.sort_by_key(|(i, _)| *i);
+
+    let mut report = ScanReport { size, ..Default::default() };
+    let mut corrupted_paths = Vec::new();
+    for (i, result) in results {
+        let file = &files[i];
+        let name = Path::new(&file.path).file_name().unwrap_or_default().to_string_lossy().into_owned();
+        report.files_scanned += 1;
+        report.bytes += std::fs::metadata(crate::storage::resolve_path(&file.path)).map(|m| m.len()).unwrap_or(0);
+        crate::events::file_processed(crate::events::FileProcessed::new("scan", size,
+            crate::events::FileAction::Scanned, name.as_str(), result.as_ref().ok().copied()));
+        match result {
+            Ok(lists) => report.lists_scanned += lists,
+            Err(problem) => {
+                test_print(&format!("   [!!] {}: {}", name, problem));
+                report.corrupted.push((name, problem));
+                corrupted_paths.push(file.path.clone());
+            }
+        }
+    }
+
+    if !report.corrupted.is_empty() {
+        let mut state = match GlobalFileState::from_sources(base_path, size) {
+            Ok(state) => Some(state),
+            Err(e) if crate::file_info::is_state_locked(&e) => return Err(e),
+            Err(_) => None,
+        };
+        for (path, (name, _)) in corrupted_paths.iter().zip(report.corrupted.iter()) {
+            let moved = quarantine(path)?;
+            test_print(&format!("   ... {} quarantined: {}", name, moved));
+            report.quarantined.push(moved);
+            if let Some(state) = state.as_mut() {
+                let entries: Vec<_> = state.entries().values().filter(|e| &e.filename == name).cloned().collect();
+                for e in entries {
+                    state.remove_file(&e.filename, e.source_batch, e.target_batch, RemovalReason::Quarantined);
+                    report.state_entries_removed += 1;
+                }
+            }
+        }
+        if let Some(mut state) = state {
+            state.flush()?;
+            state.export_human_readable()?;
+        }
+    }
+
+    test_print(&format!("   ... {} files scanned ({} lists, {} bytes) in {:.2}s: {} corrupted, {} state entries removed",
+        report.files_scanned, report.lists_scanned.separated_string(), report.bytes.separated_string(),
+        start_time.elapsed().as_secs_f64(), report.corrupted.len(), report.state_entries_removed));
+    Ok(report)
+}
+
+/// Save the scan report, with its findings, as nsl_{size:02}_scan_report.json
+pub fn save_scan_report(base_path: &str, report: &ScanReport) -> std::io::Result<FindingsReport<ScanReport>> {
+    let findings = report.corrupted.iter()
+        .map(|(name, problem)| Finding::error("corrupted_file", problem.as_str()).with_file(name.as_str()))
+        .collect();
+    let findings = FindingsReport::new("scan", findings, report.clone());
+    findings.save(base_path, &format!("nsl_{:02}_scan_report.json", report.size))?;
+    Ok(findings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn corrupted_files_are_quarantined_and_dropped_from_the_state() {
+        let dir = crate::test_dir::TestDir::new("scan");
+        let dir_str = dir.to_string_lossy().into_owned();
+
+        let sound = vec![NoSetListSerialized { n: 3, max_card: 3, no_set_list: vec![0, 1, 3], remaining_cards_list: vec![4, 80] }];
+        // Valid archive, valid checksum, but a list whose max_card is not its last card
+        let bad_fields = vec![NoSetListSerialized { n: 3, max_card: 9, no_set_list: vec![0, 1, 3], remaining_cards_list: vec![] }];
+        let mut state = GlobalFileState::new(&dir_str, 3);
+        for (batch, lists) in [(0, &sound), (1, &bad_fields)] {
+            let path = crate::filenames::output_filename(&dir_str, 2, 0, 3, batch);
+            assert!(crate::io_helpers::save_to_file_serialized(lists, &path));
+            state.register_file(&format!("nsl_02_batch_000000_to_03_batch_{:06}.rkyv", batch), 0, batch, 1, false, None, None);
+        }
+        state.flush().expect("flush");
+
+        let report = scan_size_files(&dir_str, 3, 2).expect("scan");
+        assert_eq!((report.files_scanned, report.lists_scanned), (2, 1));
+        assert_eq!(report.corrupted.len(), 1);
+        assert!(report.corrupted[0].1.contains("max_card"), "{:?}", report.corrupted);
+        assert!(dir.join(QUARANTINE_DIR).join("nsl_02_batch_000000_to_03_batch_000001.rkyv").exists());
+        assert!(!dir.join("nsl_02_batch_000000_to_03_batch_000001.rkyv").exists());
+        assert_eq!(report.state_entries_removed, 1);
+
+        let state = GlobalFileState::from_sources(&dir_str, 3).expect("state");
+        assert_eq!(state.entries().len(), 1);
+        assert!(state.tombstones().values().any(|t| t.reason == RemovalReason::Quarantined));
+        let saved = save_scan_report(&dir_str, &report).expect("save");
+        assert_eq!(saved.status, crate::findings::Status::Errors);
+    }
+}
@@ -1,8 +1,9 @@
-/// Various helpers when manipulating Set cards
-///
-/// This module exposes helpers to test whether three indices form a Set,
-/// compute the index that completes a set for two cards, and test whether a
-/// slice of indices contains any set.
+//! Various helpers when manipulating Set cards
+//!
+//! This module exposes helpers to test whether three indices form a Set,
+//! compute the index that completes a set for two cards, and test whether a
+//! slice of indices contains any set. Card decodes an index into the
+//! attributes printed on the card (number, color, shape, shading).
 
 pub fn index_to_base3(i: usize) -> [usize; 4] {
     // converts a card index (0..80) to its base-3 representation
@@ -11,9 +12,67 @@ pub fn index_to_base3(i: usize) -> [usize; 4] {
     let mut base3 = [0; 4];
     for j in (0..4).rev() {
         base3[j] = rem % 3;
-        rem = rem / 3;
+        rem /= 3;
+    }
+    base3
+}
+
+/// Attribute values, in the order of the base-3 digits of a card index
+/// (most significant digit first): number, color, shape, shading
+pub const ATTRIBUTE_VALUES: [[&str; 3]; 4] = [
+    ["1", "2", "3"],
+    ["red", "green", "purple"],
+    ["diamond", "oval", "squiggle"],
+    ["solid", "striped", "open"],
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Number { One, Two, Three }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color { Red, Green, Purple }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shape { Diamond, Oval, Squiggle }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shading { Solid, Striped, Open }
+
+/// A SET card, i.e. a card index (0..80) decoded into its 4 attributes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Card {
+    pub number: Number,
+    pub color: Color,
+    pub shape: Shape,
+    pub shading: Shading,
+}
+
+impl Card {
+    /// decode a card index (0..80): its base-3 digits are the attribute values
+    pub fn decode(i: usize) -> Card {
+        let base3 = index_to_base3(i);
+        Card {
+            number: [Number::One, Number::Two, Number::Three][base3[0]],
+            color: [Color::Red, Color::Green, Color::Purple][base3[1]],
+            shape: [Shape::Diamond, Shape::Oval, Shape::Squiggle][base3[2]],
+            shading: [Shading::Solid, Shading::Striped, Shading::Open][base3[3]],
+        }
+    }
+
+    /// attribute names: number, color, shape, shading
+    pub fn attributes(&self) -> [&'static str; 4] {
+        let digits = [self.number as usize, self.color as usize, self.shape as usize, self.shading as usize];
+        [0, 1, 2, 3].map(|j| ATTRIBUTE_VALUES[j][digits[j]])
+    }
+}
+
+/// Reads like the card: "1 red solid diamond", "3 purple open squiggles"
+impl std::fmt::Display for Card {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let [number, color, shape, shading] = self.attributes();
+        let plural = if self.number == Number::One { "" } else { "s" };
+        write!(f, "{} {} {} {}{}", number, color, shading, shape, plural)
     }
-    return base3;
 }
 
 /// check whether the three given card form a valid Set
@@ -25,17 +84,16 @@ pub fn is_set(i0: usize, i1: usize, i2: usize) -> bool {
     ];
     // sum each properties (= digit of same rank) across the 3 cards
     let mut sum_base3 = [0; 4];
-    for i in 0..3 {
-        let b3 = base3[i];
+    for b3 in base3 {
         for j in 0..4 {
             sum_base3[j] += b3[j];
         }
     }
     // For each attribute, the sum modulo 3 must be 0 for a valid SET
-    return (sum_base3[0] % 3 == 0)
+    (sum_base3[0] % 3 == 0)
         && (sum_base3[1] % 3 == 0)
         && (sum_base3[2] % 3 == 0)
-        && (sum_base3[3] % 3 == 0);
+        && (sum_base3[3] % 3 == 0)
 }
 
 /// Compute the card that completes the two given cards to form a valid set
@@ -48,10 +106,23 @@ pub fn next_to_set(i0: usize, i1: usize) -> usize {
     }
     // convert back to index
     let mut index = 0;
-    for j in 0..4 {
-        index = index * 3 + b3_2[j];
+    for digit in b3_2 {
+        index = index * 3 + digit;
     }
-    return index;
+    index
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    #[test]
+    fn cards_are_decoded_from_their_base3_digits() {
+        assert_eq!(Card::decode(0).to_string(), "1 red solid diamond");
+        assert_eq!(Card::decode(80).to_string(), "3 purple open squiggles");
+        let card = Card::decode(41); // base 3: 1112
+        assert_eq!((card.number, card.color, card.shape, card.shading),
+            (Number::Two, Color::Green, Shape::Oval, Shading::Open));
+        assert_eq!(card.attributes().join("/"), "2/green/oval/open");
+    }
+}
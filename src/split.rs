@@ -0,0 +1,175 @@
+//! Split module: break oversized files of a size into smaller batches
+//!
+//! Compaction and long unitary runs can leave files far above the usual batch
+//! size, which then dominate the memory of the next expansion step.
+//!
+//! Key features:
+//! - Every file holding more than MAX_LISTS lists is rewritten into parts of at
+//!   most MAX_LISTS lists, streamed chunk by chunk (one part in memory at a time)
+//! - The first part keeps the original filename and target batch; the other parts
+//!   get new target batches after the highest one on disk or in the state, with the
+//!   same source size and batch (and the _compacted suffix if the original had it)
+//! - All parts are written as .tmp files first, then renamed; the global state is
+//!   updated and flushed once every file of the size is done
+//!
+//! Note: split a size before computing the next one - the moved lists get new
+//! batch numbers, so outputs already computed from this size no longer line up
+//! with their input batches.
+//!
+//! Used by --split mode
+
+use std::path::Path;
+use separator::Separatable;
+
+use crate::file_info::GlobalFileState;
+use crate::filenames::output_filename;
+use crate::utils::*;
+
+/// Result of splitting the files of one size
+#[derive(Debug, Clone)]
+pub struct SplitReport {
+    pub size: u8,
+    pub max_lists: u64,
+    pub files_checked: u64,
+    pub files_split: u64,
+    pub files_created: u64,
+    pub lists_moved: u64,
+}
+
+/// Number of parts needed to hold `nb_lists` lists with at most `max_lists` per part
+pub fn nb_parts(nb_lists: u64, max_lists: u64) -> u64 {
+    nb_lists.div_ceil(max_lists.max(1)).max(1)
+}
+
+/// Source size and batch of a file named nsl_{src:02}_batch_{src_batch:06}_to_...
+fn parse_source(filename: &str) -> Option<(u8, u32)> {
+    let rest = filename.strip_prefix("nsl_")?;
+    let (src_size, rest) = rest.split_once("_batch_")?;
+    let (src_batch, _) = rest.split_once("_to_")?;
+    Some((src_size.parse().ok()?, src_batch.parse().ok()?))
+}
+
+/// Rewrite every file of `size` in `base_path` holding more than `max_lists` lists
+pub fn split_size_files(base_path: &str, size: u8, max_lists: u64) -> std::io::Result<SplitReport> {
+    test_print(&format!("\nSPLIT MODE: Splitting size {:02} files above {} lists...", size, max_lists.separated_string()));
+    test_print(&format!("   Directory: {}", base_path));
+    let start_time = std::time::Instant::now();
+
+    if max_lists == 0 {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "MAX_LISTS must be at least 1"));
+    }
+    let files = crate::filenames::list_input_files(base_path, size);
+    if files.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound,
+            format!("No size {:02} files found in {}", size, base_path)));
+    }
+    let mut state = GlobalFileState::from_sources(base_path, size)?;
+
+    // New target batches come after every batch number already used, on disk or in the state
+    let max_state_batch = state.entries().keys().map(|(_, tgt, _)| *tgt + 1).max().unwrap_or(0);
+    let mut next_batch = crate::filenames::get_next_output_batch_from_files(base_path, size, u32::MAX)
+        .max(max_state_batch);
+
+    let mut report = SplitReport {
+        size,
+        max_lists,
+        files_checked: 0,
+        files_split: 0,
+        files_created: 0,
+        lists_moved: 0,
+    };
+
+    for file in files.iter() {
+        report.files_checked += 1;
+        let name = Path::new(&file.path).file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let nb_lists = crate::io_helpers::count_lists_in_file(&file.path)?;
+        if nb_lists <= max_lists {
+            debug_print(&format!("   ... {:>10} lists in {} (unchanged)", nb_lists.separated_string(), name));
+            continue;
+        }
+        let (src_size, src_batch) = parse_source(&name).ok_or_else(|| std::io::Error::new(
+            std::io::ErrorKind::InvalidData, format!("Cannot parse the source batch of {}", name)))?;
+
+        // Part 0 replaces the original file, the other parts take fresh target batches
+        let parts = nb_parts(nb_lists, max_lists);
+        let mut targets: Vec<(String, u32)> = vec![(file.path.clone(), file.batch)];
+        for _ in 1..parts {
+            let mut path = output_filename(base_path, src_size, src_batch, size, next_batch);
+            if file.compacted {
+                path = path.replace(".rkyv", "_compacted.rkyv");
+            }
+            targets.push((path, next_batch));
+            next_batch += 1;
+        }
+
+        // Write every part to a temporary file first, so an interrupted run leaves the original intact
+        let mut part_counts: Vec<u64> = Vec::with_capacity(targets.len());
+        let mut part = 0usize;
+        crate::io_helpers::load_lists_in_chunks(&file.path, max_lists as usize, |chunk| {
+            let tmp = format!("{}.tmp", targets[part].0);
+            if !crate::io_helpers::save_to_file_serialized(&chunk, &tmp) {
+                return Err(std::io::Error::other(format!("Cannot write {}", tmp)));
+            }
+            part_counts.push(chunk.len() as u64);
+            part += 1;
+            Ok(())
+        })?;
+
+        // New parts first, then the original: a crash in between duplicates lists rather than losing them
+        for (path, _) in targets.iter().skip(1) {
+            crate::io_helpers::commit_atomic_write(&format!("{}.tmp", path), path)?;
+        }
+        crate::io_helpers::commit_atomic_write(&format!("{}.tmp", file.path), &file.path)?;
+        crate::io_helpers::invalidate_cached_batch(&file.path);
+
+        // Update the state: the original entry shrinks, the new parts are registered
+        let keys: Vec<(u32, u32, String)> = state.entries().keys()
+            .filter(|(_, _, filename)| *filename == name)
+            .cloned()
+            .collect();
+        if keys.is_empty() {
+            test_print(&format!("   ... WARNING: {} is not recorded in the global state (run --count {})", name, size));
+        }
+        let (file_size, mtime) = crate::storage::file_metadata(&file.path)
+            .map(|(bytes, mtime)| (Some(bytes), mtime)).unwrap_or((None, None));
+        for (src, tgt, filename) in keys {
+            state.update_entry(&filename, src, tgt, part_counts[0], file.compacted, file_size, mtime);
+        }
+        for ((path, tgt_batch), count) in targets.iter().zip(part_counts.iter()).skip(1) {
+            let (file_size, mtime) = crate::storage::file_metadata(path)
+                .map(|(bytes, mtime)| (Some(bytes), mtime)).unwrap_or((None, None));
+            let filename = Path::new(path).file_name().unwrap_or_default().to_string_lossy().into_owned();
+            state.register_file(&filename, src_batch, *tgt_batch, *count, file.compacted, file_size, mtime);
+        }
+
+        report.files_split += 1;
+        report.files_created += parts - 1;
+        report.lists_moved += nb_lists - part_counts[0];
+        test_print(&format!("   ... {:>10} lists in {} split into {} files (new batches {:06}-{:06})",
+            nb_lists.separated_string(), name, parts, targets[1].1, targets[targets.len() - 1].1));
+    }
+
+    if report.files_split > 0 {
+        state.flush()?;
+        state.export_human_readable()?;
+    }
+
+    test_print(&format!("   ... {} files split into {} new files ({} lists moved) in {:.2}s",
+        report.files_split, report.files_created, report.lists_moved.separated_string(),
+        start_time.elapsed().as_secs_f64()));
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parts_and_source_batches() {
+        assert_eq!(nb_parts(1_000, 300), 4);
+        assert_eq!(nb_parts(900, 300), 3);
+        assert_eq!(nb_parts(0, 300), 1);
+        assert_eq!(parse_source("nsl_03_batch_000012_to_04_batch_000034_compacted.rkyv"), Some((3, 12)));
+        assert_eq!(parse_source("nsl_04_global_info.rkyv"), None);
+    }
+}
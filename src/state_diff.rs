@@ -0,0 +1,318 @@
+//! State diff module: compare the global states of a size in two directories
+//!
+//! Typically last week's copy of a state against today's, or a backup against
+//! the live directory.
+//!
+//! Key features:
+//! - Entries matched by (source batch, target batch, filename)
+//! - Added / removed entries, and modified ones (count, compacted flag, file size)
+//! - Count deltas per modified entry and total list difference
+//! - Printed in human-readable form (log) and emitted as JSON (printed on stdout by
+//!   the command line)
+//! - History diff: the history of a size (nsl_XX_global_info_history) against its
+//!   current state, in one directory; the entries found only in the history are
+//!   the files deleted or consumed since, each with its tombstone reason (or
+//!   unrecorded) and whether it is still on disk, totalled per reason
+//!
+//! Used by --diff and --history-diff modes
+
+use std::collections::BTreeMap;
+use separator::Separatable;
+use serde::Serialize;
+
+use crate::file_info::{FileInfo, GlobalFileState};
+use crate::utils::*;
+
+/// Entry present in one state only
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffEntry {
+    pub filename: String,
+    pub source_batch: u32,
+    pub target_batch: u32,
+    pub nb_lists: u64,
+}
+
+/// Entry present in both states with different values
+#[derive(Debug, Clone, Serialize)]
+pub struct ModifiedEntry {
+    pub filename: String,
+    pub source_batch: u32,
+    pub target_batch: u32,
+    pub nb_lists_a: u64,
+    pub nb_lists_b: u64,
+    pub delta: i64,
+    pub compacted_a: bool,
+    pub compacted_b: bool,
+    pub file_size_a: Option<u64>,
+    pub file_size_b: Option<u64>,
+}
+
+/// Differences between state A and state B of one size
+#[derive(Debug, Clone, Serialize)]
+pub struct StateDiff {
+    pub size: u8,
+    pub dir_a: String,
+    pub dir_b: String,
+    pub entries_a: usize,
+    pub entries_b: usize,
+    pub total_lists_a: u64,
+    pub total_lists_b: u64,
+    pub total_delta: i64,
+    pub added: Vec<DiffEntry>,
+    pub removed: Vec<DiffEntry>,
+    pub modified: Vec<ModifiedEntry>,
+}
+
+impl StateDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+fn diff_entry(e: &FileInfo) -> DiffEntry {
+    DiffEntry {
+        filename: e.filename.clone(),
+        source_batch: e.source_batch,
+        target_batch: e.target_batch,
+        nb_lists: e.nb_lists_in_file,
+    }
+}
+
+/// Compare two loaded states
+pub fn diff_states(size: u8, dir_a: &str, a: &GlobalFileState, dir_b: &str, b: &GlobalFileState) -> StateDiff {
+    let total_a: u64 = a.entries().values().map(|e| e.nb_lists_in_file).sum();
+    let total_b: u64 = b.entries().values().map(|e| e.nb_lists_in_file).sum();
+    let mut diff = StateDiff {
+        size,
+        dir_a: dir_a.to_string(),
+        dir_b: dir_b.to_string(),
+        entries_a: a.entries().len(),
+        entries_b: b.entries().len(),
+        total_lists_a: total_a,
+        total_lists_b: total_b,
+        total_delta: total_b as i64 - total_a as i64,
+        added: Vec::new(),
+        removed: Vec::new(),
+        modified: Vec::new(),
+    };
+
+    for (key, ea) in a.entries().iter() {
+        match b.entries().get(key) {
+            None => diff.removed.push(diff_entry(ea)),
+            Some(eb) => {
+                if ea.nb_lists_in_file != eb.nb_lists_in_file || ea.compacted != eb.compacted
+                    || ea.file_size_bytes != eb.file_size_bytes {
+                    diff.modified.push(ModifiedEntry {
+                        filename: ea.filename.clone(),
+                        source_batch: ea.source_batch,
+                        target_batch: ea.target_batch,
+                        nb_lists_a: ea.nb_lists_in_file,
+                        nb_lists_b: eb.nb_lists_in_file,
+                        delta: eb.nb_lists_in_file as i64 - ea.nb_lists_in_file as i64,
+                        compacted_a: ea.compacted,
+                        compacted_b: eb.compacted,
+                        file_size_a: ea.file_size_bytes,
+                        file_size_b: eb.file_size_bytes,
+                    });
+                }
+            }
+        }
+    }
+    for (key, eb) in b.entries().iter() {
+        if !a.entries().contains_key(key) {
+            diff.added.push(diff_entry(eb));
+        }
+    }
+    diff
+}
+
+/// Load the states of `size` from both directories and compare them
+pub fn diff_size_states(dir_a: &str, dir_b: &str, size: u8) -> std::io::Result<StateDiff> {
+    test_print(&format!("\nDIFF MODE: Comparing the size {:02} states", size));
+    test_print(&format!("   A: {}", dir_a));
+    test_print(&format!("   B: {}", dir_b));
+    let a = GlobalFileState::from_sources(dir_a, size)?;
+    let b = GlobalFileState::from_sources(dir_b, size)?;
+    Ok(diff_states(size, dir_a, &a, dir_b, &b))
+}
+
+/// Entry of the history missing from the current state, with what became of it
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryOnlyEntry {
+    #[serde(flatten)]
+    pub entry: DiffEntry,
+    pub reason: String, // reason of its tombstone (compacted_away, pruned...), or "unrecorded"
+    pub on_disk: bool,
+}
+
+/// Files and lists of the history-only entries sharing a reason
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReasonTotal {
+    pub files: u64,
+    pub lists: u64,
+}
+
+/// Differences between the history and the current state of one size
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryDiff {
+    pub size: u8,
+    pub dir: String,
+    pub entries_history: usize,
+    pub entries_current: usize,
+    pub total_lists_history: u64,
+    pub total_lists_current: u64,
+    pub history_only: Vec<HistoryOnlyEntry>,
+    pub history_only_by_reason: BTreeMap<String, ReasonTotal>,
+    pub current_only: Vec<DiffEntry>,
+    pub differing: Vec<ModifiedEntry>,
+}
+
+impl HistoryDiff {
+    pub fn is_empty(&self) -> bool {
+        self.history_only.is_empty() && self.current_only.is_empty() && self.differing.is_empty()
+    }
+}
+
+/// Compare the history of a size with its current state: the tombstone reasons are
+/// looked up in the current state first, then in the history
+pub fn diff_history(size: u8, dir: &str, history: &GlobalFileState, current: &GlobalFileState) -> HistoryDiff {
+    let diff = diff_states(size, dir, history, dir, current);
+    let mut by_reason: BTreeMap<String, ReasonTotal> = BTreeMap::new();
+    let history_only: Vec<HistoryOnlyEntry> = diff.removed.into_iter().map(|entry| {
+        let key = (entry.source_batch, entry.target_batch, entry.filename.clone());
+        let reason = current.tombstones().get(&key).or_else(|| history.tombstones().get(&key))
+            .map_or("unrecorded", |t| t.reason.as_str()).to_string();
+        let on_disk = crate::storage::file_metadata(&format!("{}/{}", dir, entry.filename)).is_some();
+        let total = by_reason.entry(reason.clone()).or_default();
+        total.files += 1;
+        total.lists += entry.nb_lists;
+        HistoryOnlyEntry { entry, reason, on_disk }
+    }).collect();
+    HistoryDiff {
+        size,
+        dir: dir.to_string(),
+        entries_history: diff.entries_a,
+        entries_current: diff.entries_b,
+        total_lists_history: diff.total_lists_a,
+        total_lists_current: diff.total_lists_b,
+        history_only,
+        history_only_by_reason: by_reason,
+        current_only: diff.added,
+        differing: diff.modified,
+    }
+}
+
+/// Load the history (rkyv, else JSON) and the current state of `size` in `dir` and compare them
+pub fn diff_size_history(dir: &str, size: u8) -> std::io::Result<HistoryDiff> {
+    test_print(&format!("\nHISTORY DIFF MODE: Comparing the size {:02} history with the current state", size));
+    test_print(&format!("   Directory: {}", dir));
+    let history = GlobalFileState::from_history_file(dir, size, "rkyv")
+        .or_else(|_| GlobalFileState::from_history_file(dir, size, "json"))
+        .map_err(|e| std::io::Error::new(e.kind(), format!("No readable history for size {:02} in {}: {}", size, dir, e)))?;
+    let current = crate::dry_run::load_state_readonly(dir, size)?;
+    Ok(diff_history(size, dir, &history, &current))
+}
+
+/// Print the history diff (human-readable in the log) and emit it as JSON (see events::result)
+pub fn print_history_diff(diff: &HistoryDiff) -> std::io::Result<()> {
+    test_print(&format!("   ... history: {} entries, {} lists", diff.entries_history, diff.total_lists_history.separated_string()));
+    test_print(&format!("   ... current: {} entries, {} lists", diff.entries_current, diff.total_lists_current.separated_string()));
+    for e in diff.history_only.iter() {
+        test_print(&format!("   - {:06} {:06} | {:>15} | {} ({}{})", e.entry.source_batch, e.entry.target_batch,
+            e.entry.nb_lists.separated_string(), e.entry.filename, e.reason, if e.on_disk { ", still on disk" } else { "" }));
+    }
+    for e in diff.current_only.iter() {
+        test_print(&format!("   + {:06} {:06} | {:>15} | {}", e.source_batch, e.target_batch, e.nb_lists.separated_string(), e.filename));
+    }
+    for e in diff.differing.iter() {
+        test_print(&format!("   ~ {:06} {:06} | {:>15} -> {:>15} ({:+}) | {}", e.source_batch, e.target_batch,
+            e.nb_lists_a.separated_string(), e.nb_lists_b.separated_string(), e.delta, e.filename));
+    }
+    if diff.is_empty() {
+        test_print("   ... the history matches the current state");
+    } else {
+        for (reason, total) in diff.history_only_by_reason.iter() {
+            test_print(&format!("   ... history only, {}: {} files, {} lists", reason, total.files, total.lists.separated_string()));
+        }
+        test_print(&format!("   ... {} in the history only, {} in the current state only, {} with differing counts",
+            diff.history_only.len(), diff.current_only.len(), diff.differing.len()));
+    }
+
+    let json = serde_json::to_string_pretty(diff)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    crate::events::result(crate::events::ResultReady { mode: "history_diff".to_string(), json });
+    Ok(())
+}
+
+/// Print the differences (human-readable in the log) and emit them as JSON (see events::result)
+pub fn print_state_diff(diff: &StateDiff) -> std::io::Result<()> {
+    test_print(&format!("   ... A: {} entries, {} lists", diff.entries_a, diff.total_lists_a.separated_string()));
+    test_print(&format!("   ... B: {} entries, {} lists", diff.entries_b, diff.total_lists_b.separated_string()));
+    for e in diff.added.iter() {
+        test_print(&format!("   + {:06} {:06} | {:>15} | {}", e.source_batch, e.target_batch, e.nb_lists.separated_string(), e.filename));
+    }
+    for e in diff.removed.iter() {
+        test_print(&format!("   - {:06} {:06} | {:>15} | {}", e.source_batch, e.target_batch, e.nb_lists.separated_string(), e.filename));
+    }
+    for e in diff.modified.iter() {
+        test_print(&format!("   ~ {:06} {:06} | {:>15} -> {:>15} ({:+}){} | {}", e.source_batch, e.target_batch,
+            e.nb_lists_a.separated_string(), e.nb_lists_b.separated_string(), e.delta,
+            if e.compacted_a != e.compacted_b { ", compacted flag changed" } else { "" }, e.filename));
+    }
+    if diff.is_empty() {
+        test_print("   ... the states are identical");
+    } else {
+        test_print(&format!("   ... {} added, {} removed, {} modified, total lists {:+}",
+            diff.added.len(), diff.removed.len(), diff.modified.len(), diff.total_delta));
+    }
+
+    let json = serde_json::to_string_pretty(diff)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    crate::events::result(crate::events::ResultReady { mode: "diff".to_string(), json });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn added_removed_and_modified_entries() {
+        let mut a = GlobalFileState::new("a", 5);
+        a.register_file("f0.rkyv", 0, 0, 10, false, None, None);
+        a.register_file("f1.rkyv", 0, 1, 20, false, None, None);
+        let mut b = GlobalFileState::new("b", 5);
+        b.register_file("f1.rkyv", 0, 1, 25, false, None, None);
+        b.register_file("f2.rkyv", 1, 2, 5, false, None, None);
+
+        let diff = diff_states(5, "a", &a, "b", &b);
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.removed[0].filename, "f0.rkyv");
+        assert_eq!(diff.modified[0].delta, 5);
+        assert_eq!(diff.total_delta, 0);
+        assert!(!diff.is_empty());
+    }
+
+    #[test]
+    fn history_only_entries_carry_their_tombstone_reason() {
+        use crate::file_info::RemovalReason;
+        let mut history = GlobalFileState::new("h", 5);
+        history.register_file("f0.rkyv", 0, 0, 10, false, None, None);
+        history.register_file("f1.rkyv", 1, 1, 20, false, None, None);
+        history.register_file("f2.rkyv", 2, 2, 30, false, None, None);
+        let mut current = history.clone();
+        current.remove_file("f0.rkyv", 0, 0, RemovalReason::CompactedAway);
+        current.remove_file("f1.rkyv", 1, 1, RemovalReason::CompactedAway);
+        current.register_file("f2.rkyv", 2, 2, 31, false, None, None);
+        current.register_file("c0_compacted.rkyv", 0, 0, 30, true, None, None);
+        history.register_file("f3.rkyv", 3, 3, 5, false, None, None);
+
+        let diff = diff_history(5, "h", &history, &current);
+        let reasons: Vec<(&str, &str)> = diff.history_only.iter().map(|e| (e.entry.filename.as_str(), e.reason.as_str())).collect();
+        assert_eq!(reasons, vec![("f0.rkyv", "compacted_away"), ("f1.rkyv", "compacted_away"), ("f3.rkyv", "unrecorded")]);
+        assert_eq!((diff.history_only_by_reason["compacted_away"].files, diff.history_only_by_reason["compacted_away"].lists), (2, 30));
+        assert_eq!(diff.current_only[0].filename, "c0_compacted.rkyv");
+        assert_eq!(diff.differing[0].delta, 1);
+        assert!(diff.history_only.iter().all(|e| !e.on_disk));
+    }
+}
@@ -0,0 +1,140 @@
+//! Card-composition statistics module for the stored no-set-lists of a size
+//!
+//! Streams every file of a size (one file in memory at a time) and gathers
+//! distributions over all stored lists.
+//!
+//! Key features:
+//! - Per-card frequencies: how many stored lists contain each of the 81 cards
+//! - max_card histogram
+//! - Remaining-length histogram (number of remaining cards per list)
+//! - Saved as nsl_{size:02}_stats.json and nsl_{size:02}_stats.txt
+//!
+//! Used by --stats mode
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use separator::Separatable;
+use serde::Serialize;
+
+use crate::no_set_list::NoSetListSerialized;
+use crate::utils::*;
+
+/// Distributions over all stored lists of one size
+#[derive(Debug, Clone, Serialize)]
+pub struct SizeStats {
+    pub size: u8,
+    pub files: u64,
+    pub lists: u64,
+    pub card_frequencies: Vec<u64>,                 // index = card (0-80)
+    pub max_card_histogram: BTreeMap<usize, u64>,   // max_card -> number of lists
+    pub remaining_length_histogram: BTreeMap<usize, u64>, // remaining cards -> number of lists
+}
+
+impl SizeStats {
+    pub fn new(size: u8) -> Self {
+        SizeStats {
+            size,
+            files: 0,
+            lists: 0,
+            card_frequencies: vec![0; 81],
+            max_card_histogram: BTreeMap::new(),
+            remaining_length_histogram: BTreeMap::new(),
+        }
+    }
+
+    /// Account for one list
+    pub fn add(&mut self, list: &NoSetListSerialized) {
+        self.lists += 1;
+        for &card in list.no_set_list.iter() {
+            if let Some(freq) = self.card_frequencies.get_mut(card) {
+                *freq += 1;
+            }
+        }
+        *self.max_card_histogram.entry(list.max_card).or_insert(0) += 1;
+        *self.remaining_length_histogram.entry(list.remaining_cards_list.len()).or_insert(0) += 1;
+    }
+
+    /// Human-readable rendering (the TXT export)
+    pub fn to_txt(&self) -> String {
+        let mut txt = String::new();
+        txt.push_str(&format!("# Statistics of size {:02}: {} lists in {} files\n",
+            self.size, self.lists.separated_string(), self.files));
+        txt.push_str("#\n# Card frequencies (card: lists containing it, share)\n");
+        for (card, freq) in self.card_frequencies.iter().enumerate() {
+            txt.push_str(&format!("{:>4}: {:>18} {:>8.4}%\n", card, freq.separated_string(), self.percent(*freq)));
+        }
+        txt.push_str("#\n# max_card histogram (max_card: lists)\n");
+        for (max_card, nb) in self.max_card_histogram.iter() {
+            txt.push_str(&format!("{:>4}: {:>18} {:>8.4}%\n", max_card, nb.separated_string(), self.percent(*nb)));
+        }
+        txt.push_str("#\n# Remaining-length histogram (remaining cards: lists)\n");
+        for (len, nb) in self.remaining_length_histogram.iter() {
+            txt.push_str(&format!("{:>4}: {:>18} {:>8.4}%\n", len, nb.separated_string(), self.percent(*nb)));
+        }
+        txt
+    }
+
+    fn percent(&self, nb: u64) -> f64 {
+        if self.lists == 0 { 0.0 } else { 100.0 * nb as f64 / self.lists as f64 }
+    }
+}
+
+/// Stream every stored list of `size` in `base_path` and gather the statistics
+pub fn compute_size_stats(base_path: &str, size: u8) -> std::io::Result<SizeStats> {
+    test_print(&format!("\nSTATS MODE: Card composition of size {:02} lists...", size));
+    test_print(&format!("   Directory: {}", base_path));
+    let start_time = std::time::Instant::now();
+
+    let files = crate::filenames::list_input_files(base_path, size);
+    if files.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound,
+            format!("No size {:02} files found in {}", size, base_path)));
+    }
+
+    let mut stats = SizeStats::new(size);
+    for file in files.iter() {
+        let lists = crate::io_helpers::load_lists_cached(&file.path)?;
+        for list in lists.iter() {
+            stats.add(list);
+        }
+        stats.files += 1;
+        test_print(&format!("   ... {:>10} lists in {}", lists.len().separated_string(),
+            Path::new(&file.path).file_name().unwrap_or_default().to_string_lossy()));
+    }
+
+    test_print(&format!("   ... {} lists analysed in {:.2}s",
+        stats.lists.separated_string(), start_time.elapsed().as_secs_f64()));
+    Ok(stats)
+}
+
+/// Save the statistics as nsl_{size:02}_stats.json and nsl_{size:02}_stats.txt
+pub fn save_size_stats(base_path: &str, stats: &SizeStats) -> std::io::Result<()> {
+    let json_path = Path::new(base_path).join(format!("nsl_{:02}_stats.json", stats.size));
+    let json = serde_json::to_string_pretty(stats)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    std::fs::write(&json_path, json)?;
+
+    let txt_path = json_path.with_extension("txt");
+    std::fs::write(&txt_path, stats.to_txt())?;
+    test_print(&format!("   Statistics saved: {} and .txt", json_path.display()));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distributions_count_every_list() {
+        let mut stats = SizeStats::new(3);
+        stats.add(&NoSetListSerialized { n: 3, max_card: 3, no_set_list: vec![0, 1, 3], remaining_cards_list: vec![4, 5] });
+        stats.add(&NoSetListSerialized { n: 3, max_card: 4, no_set_list: vec![0, 1, 4], remaining_cards_list: vec![5, 6] });
+
+        assert_eq!(stats.lists, 2);
+        assert_eq!(stats.card_frequencies[0], 2);
+        assert_eq!(stats.card_frequencies[3], 1);
+        assert_eq!(stats.card_frequencies.iter().sum::<u64>(), 6);
+        assert_eq!(stats.max_card_histogram.get(&3), Some(&1));
+        assert_eq!(stats.remaining_length_histogram.get(&2), Some(&2));
+    }
+}
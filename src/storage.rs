@@ -0,0 +1,895 @@
+//! Storage module: where the list files live (one file per batch, or SQLite databases)
+//!
+//! The rest of the program names list files as always (nsl_*.rkyv paths) and goes
+//! through this module to list them, get their size and date, read and write them.
+//! With the sqlite backend, a "file" is a row of the database of its size,
+//! nsl_{size:02}_lists.sqlite in the same directory, and its lists are stored as the
+//! frames of a framed file (one row per frame).
+//!
+//! Key features:
+//! - files backend (default): one nsl_*.rkyv file per batch
+//! - sqlite backend: frames appended to the database of the size in one transaction
+//!   per file (WAL journal, so readers never block the writer); a file written again
+//!   replaces its previous rows
+//! - Reads look in the database first, then on disk: the seed file and the sizes
+//!   computed before switching backend are read as usual
+//! - Size, count, check, stats and top work identically against either backend
+//! - Multi-volume directories (-o "D:\a;E:\b"): the first root holds the state and
+//!   reports and names the directory; new list files are placed on any root
+//!   (round-robin or most free space), and listing, reading, rewriting and deleting
+//!   a file resolve it on whichever root holds it. Subdirectories (cascade) follow.
+//! - ListStore trait (list, metadata, open or read frame by frame, create a frame
+//!   writer, delete, rename): the readers and writers only see the store;
+//!   LocalStore (files, multi-volume) and SqliteStore implement it, and
+//!   set_list_store installs another one (object store, in-memory store for tests...)
+//!
+//! Used by --storage (sqlite needs the `sqlite` feature), and by -i / -o lists of roots
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU8, Ordering};
+use rkyv::AlignedVec;
+
+/// Where list files are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageBackend {
+    Files,
+    Sqlite,
+}
+
+impl StorageBackend {
+    /// Parse a --storage value
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "files" => Ok(StorageBackend::Files),
+            "sqlite" => Ok(StorageBackend::Sqlite),
+            _ => Err(format!("Unknown storage backend '{}' (expected files or sqlite)", text)),
+        }
+    }
+}
+
+// Storage backend of the list files (0 = files, 1 = sqlite)
+static STORAGE_BACKEND: AtomicU8 = AtomicU8::new(0);
+
+/// Select the storage backend of the list files written and read from now on
+pub fn set_storage_backend(backend: StorageBackend) {
+    STORAGE_BACKEND.store(backend as u8, Ordering::Relaxed);
+}
+
+/// Storage backend currently selected
+pub fn storage_backend() -> StorageBackend {
+    match STORAGE_BACKEND.load(Ordering::Relaxed) {
+        1 => StorageBackend::Sqlite,
+        _ => StorageBackend::Files,
+    }
+}
+
+// ============================================================================
+// Multi-volume directories
+// ============================================================================
+
+/// How new list files are spread over the roots of a multi-volume directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placement {
+    RoundRobin,
+    FreeSpace,
+}
+
+impl Placement {
+    /// Parse a --placement value
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "round-robin" => Ok(Placement::RoundRobin),
+            "free-space" => Ok(Placement::FreeSpace),
+            _ => Err(format!("Unknown placement '{}' (expected round-robin or free-space)", text)),
+        }
+    }
+}
+
+/// Roots of one multi-volume directory (the first one names the directory)
+struct VolumeSet {
+    roots: Vec<PathBuf>,
+    next: usize,    // next root of round-robin placement
+}
+
+// Placement of new list files (0 = round-robin, 1 = free space)
+static PLACEMENT: AtomicU8 = AtomicU8::new(0);
+
+// Multi-volume directories registered by -i / -o
+static VOLUMES: Mutex<Vec<VolumeSet>> = Mutex::new(Vec::new());
+
+/// Select how new list files are spread over the roots of multi-volume directories
+pub fn set_placement(placement: Placement) {
+    PLACEMENT.store(placement as u8, Ordering::Relaxed);
+}
+
+/// Placement currently selected
+pub fn placement() -> Placement {
+    match PLACEMENT.load(Ordering::Relaxed) {
+        1 => Placement::FreeSpace,
+        _ => Placement::RoundRobin,
+    }
+}
+
+/// Register a directory given as a ';'-separated list of roots ("D:\a;E:\b") and
+/// return its first root, which names the directory from then on (a single root is
+/// returned unchanged)
+pub fn register_volumes(spec: &str) -> String {
+    let roots: Vec<PathBuf> = spec.split(';').map(str::trim).filter(|r| !r.is_empty()).map(PathBuf::from).collect();
+    let Some(primary) = roots.first().map(|r| r.to_string_lossy().into_owned()) else { return spec.to_string() };
+    if roots.len() > 1 {
+        let mut volumes = VOLUMES.lock().unwrap();
+        volumes.retain(|set| set.roots[0] != roots[0]);
+        volumes.push(VolumeSet { roots, next: 0 });
+    }
+    primary
+}
+
+/// Roots of the multi-volume directory `dir` (or of the one it is a subdirectory
+/// of), each joined with the subdirectory; None if `dir` is a plain directory
+pub fn volume_dirs(dir: &Path) -> Option<Vec<PathBuf>> {
+    let volumes = VOLUMES.lock().unwrap();
+    volumes.iter().find_map(|set| {
+        let relative = dir.strip_prefix(&set.roots[0]).ok()?;
+        Some(set.roots.iter()
+            .map(|root| if relative.as_os_str().is_empty() { root.clone() } else { root.join(relative) })
+            .collect())
+    })
+}
+
+/// Bytes available to the user on the file system holding `dir`
+#[cfg(unix)]
+pub fn free_space(dir: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = std::ffi::CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stats) } != 0 {
+        return None;
+    }
+    Some(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
+
+/// Bytes available to the user on the volume holding `dir`
+#[cfg(windows)]
+pub fn free_space(dir: &Path) -> Option<u64> {
+    use std::os::windows::ffi::OsStrExt;
+    unsafe extern "system" {
+        fn GetDiskFreeSpaceExW(path: *const u16, available: *mut u64, total: *mut u64, free: *mut u64) -> i32;
+    }
+    let path: Vec<u16> = dir.as_os_str().encode_wide().chain(std::iter::once(0)).collect();
+    let mut available = 0u64;
+    let ok = unsafe { GetDiskFreeSpaceExW(path.as_ptr(), &mut available, std::ptr::null_mut(), std::ptr::null_mut()) };
+    if ok != 0 { Some(available) } else { None }
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn free_space(_dir: &Path) -> Option<u64> {
+    None
+}
+
+/// Path of the file `path` on the root that holds it, if its directory spans
+/// several volumes; `path` itself otherwise (or if no root holds it)
+pub fn resolve_path(path: &str) -> String {
+    let p = Path::new(path);
+    if p.exists() {
+        return path.to_string();
+    }
+    let (Some(dir), Some(name)) = (p.parent(), p.file_name()) else { return path.to_string() };
+    volume_dirs(dir).and_then(|dirs| dirs.into_iter().map(|d| d.join(name)).find(|candidate| candidate.exists()))
+        .map(|found| found.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_string())
+}
+
+/// Path the file `path` is to be written to: where it already is (a rewrite stays
+/// on its root, and a "x.tmp" goes next to "x"), else the root picked by the placement
+pub fn placement_path(path: &str) -> String {
+    let p = Path::new(path);
+    let (Some(dir), Some(name)) = (p.parent(), p.file_name().and_then(|n| n.to_str())) else { return path.to_string() };
+    let Some(dirs) = volume_dirs(dir) else { return path.to_string() };
+    let stem = name.find(".tmp").map(|pos| &name[..pos]).unwrap_or(name);
+    if let Some(existing) = dirs.iter().find(|d| d.join(name).exists() || d.join(stem).exists()) {
+        return existing.join(name).to_string_lossy().into_owned();
+    }
+    let chosen = match placement() {
+        // First root on ties (max_by_key keeps the last maximum)
+        Placement::FreeSpace => dirs.iter().rev()
+            .max_by_key(|d| free_space(d).or_else(|| d.parent().and_then(free_space)).unwrap_or(0))
+            .cloned()
+            .unwrap_or_else(|| dir.to_path_buf()),
+        Placement::RoundRobin => {
+            let mut volumes = VOLUMES.lock().unwrap();
+            match volumes.iter_mut().find(|set| dir.starts_with(&set.roots[0])) {
+                Some(set) => {
+                    let index = set.next % dirs.len();
+                    set.next = index + 1;
+                    dirs[index].clone()
+                }
+                None => dir.to_path_buf(),
+            }
+        }
+    };
+    let _ = std::fs::create_dir_all(&chosen);
+    chosen.join(name).to_string_lossy().into_owned()
+}
+
+/// Paths to rename `tmp` to `path` with: the tmp file where it was written and the
+/// final name next to it (both unchanged outside multi-volume directories)
+pub fn resolve_rename(tmp: &str, path: &str) -> (String, String) {
+    let resolved = resolve_path(tmp);
+    if resolved == tmp {
+        return (resolved, path.to_string());
+    }
+    let target = match (Path::new(&resolved).parent(), Path::new(path).file_name()) {
+        (Some(dir), Some(name)) => dir.join(name).to_string_lossy().into_owned(),
+        _ => path.to_string(),
+    };
+    (resolved, target)
+}
+
+/// Database holding the list files of `size` in `dir`
+pub fn database_path(dir: &str, size: u8) -> PathBuf {
+    Path::new(dir).join(format!("nsl_{:02}_lists.sqlite", size))
+}
+
+/// Database and name of the list file `path`, when it is a list filename
+fn database_entry(path: &str) -> Option<(PathBuf, String)> {
+    let path = Path::new(path);
+    let name = path.file_name()?.to_str()?.to_string();
+    let parsed = crate::filenames::parse_filename(&name)?;
+    let dir = path.parent().map(|p| p.to_string_lossy().into_owned()).filter(|d| !d.is_empty());
+    Some((database_path(dir.as_deref().unwrap_or("."), parsed.target_size), name))
+}
+
+// ============================================================================
+// List stores
+// ============================================================================
+
+/// Frames of a list file written to a store that keeps them itself (see ListStore::create)
+pub trait FrameWriter {
+    /// Append one frame (frame header, payload and padding) holding `nb_lists` lists
+    fn write_frame(&mut self, nb_lists: u64, frame: &[u8]) -> std::io::Result<()>;
+
+    /// Record the file (`nb_lists` lists, `bytes` bytes) and make its frames visible
+    fn finish(self: Box<Self>, nb_lists: u64, bytes: u64) -> std::io::Result<()>;
+}
+
+/// Frames of a list file read one at a time from a store that keeps them itself
+/// (see ListStore::frames)
+pub trait FrameReader {
+    /// Next frame (frame header, payload and padding), None after the last one
+    fn next_frame(&mut self) -> std::io::Result<Option<AlignedVec>>;
+}
+
+/// Where the list files live. Every module lists, reads, writes, renames and
+/// deletes list files through the store of the run (see `list_store`); a list
+/// file is named by its nsl_*.rkyv path whatever the store.
+pub trait ListStore: Send + Sync {
+    /// Name of the store, as given to --storage
+    fn name(&self) -> &'static str;
+
+    /// Names of the list files of `dir` (nsl_*.rkyv), sorted, without duplicates
+    fn list(&self, dir: &str) -> std::io::Result<Vec<String>>;
+
+    /// Size in bytes and modification time (Unix seconds) of the list file `path`
+    /// (None if the store does not hold it)
+    fn metadata(&self, path: &str) -> Option<(u64, Option<i64>)>;
+
+    fn exists(&self, path: &str) -> bool {
+        self.metadata(path).is_some()
+    }
+
+    /// Content of the list file `path` if the store holds it itself: the image of
+    /// a framed file (None: the file on disk is read in place)
+    fn open(&self, path: &str) -> std::io::Result<Option<AlignedVec>>;
+
+    /// Frames of the list file `path`, read one at a time, if the store holds it
+    /// itself (None: read it with `open`, or in place): a large file is then never
+    /// held whole in memory
+    fn frames(&self, _path: &str) -> std::io::Result<Option<Box<dyn FrameReader>>> {
+        Ok(None)
+    }
+
+    /// List count of the list file `path` if the store records it
+    fn count(&self, _path: &str) -> std::io::Result<Option<u64>> {
+        Ok(None)
+    }
+
+    /// True if the store keeps the frames of `path` itself (see `create`)
+    fn keeps_frames(&self, _path: &str) -> bool {
+        false
+    }
+
+    /// Writer of the frames of `path` if the store keeps them itself (None: `path`
+    /// is written as a file, atomically, see io_helpers)
+    fn create(&self, path: &str) -> std::io::Result<Option<Box<dyn FrameWriter>>>;
+
+    /// Delete the list file `path`
+    fn delete(&self, path: &str) -> std::io::Result<()>;
+
+    /// Rename the list file `from` to `to` (a list file of the same directory, or
+    /// of another one: a quarantine or an archive directory)
+    fn rename(&self, from: &str, to: &str) -> std::io::Result<()>;
+}
+
+/// One file per batch, on the local file systems (multi-volume directories included)
+pub struct LocalStore;
+
+impl ListStore for LocalStore {
+    fn name(&self) -> &'static str {
+        "files"
+    }
+
+    fn list(&self, dir: &str) -> std::io::Result<Vec<String>> {
+        let mut names: Vec<String> = local_entries(dir)?.into_iter()
+            .filter_map(|entry| entry.file_name().to_str().map(|n| n.to_string()))
+            .filter(|name| name.starts_with("nsl_") && name.ends_with(".rkyv"))
+            .collect();
+        names.sort();
+        names.dedup();
+        Ok(names)
+    }
+
+    fn metadata(&self, path: &str) -> Option<(u64, Option<i64>)> {
+        let metadata = std::fs::metadata(resolve_path(path)).ok()?;
+        let modified = metadata.modified().ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64);
+        Some((metadata.len(), modified))
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        Path::new(&resolve_path(path)).exists()
+    }
+
+    fn open(&self, _path: &str) -> std::io::Result<Option<AlignedVec>> {
+        Ok(None)
+    }
+
+    fn create(&self, _path: &str) -> std::io::Result<Option<Box<dyn FrameWriter>>> {
+        Ok(None)
+    }
+
+    /// Deleted on whichever root holds it
+    fn delete(&self, path: &str) -> std::io::Result<()> {
+        let resolved = resolve_path(path);
+        crate::io_helpers::with_retry("delete", &resolved, || std::fs::remove_file(&resolved))
+    }
+
+    /// Renamed on the root that holds it when `to` is in the same directory
+    fn rename(&self, from: &str, to: &str) -> std::io::Result<()> {
+        let (source, target) = if Path::new(from).parent() == Path::new(to).parent() {
+            resolve_rename(from, to)
+        } else {
+            (resolve_path(from), to.to_string())
+        };
+        crate::io_helpers::with_retry("rename", &source, || std::fs::rename(&source, &target))
+    }
+}
+
+/// Entries of `dir` and of the other roots of its multi-volume directory, if any
+fn local_entries(dir: &str) -> std::io::Result<Vec<std::fs::DirEntry>> {
+    let mut entries: Vec<std::fs::DirEntry> = std::fs::read_dir(dir)?.flatten().collect();
+    // The other roots of a multi-volume directory may not hold this directory yet
+    for other in volume_dirs(Path::new(dir)).unwrap_or_default().iter().skip(1) {
+        entries.extend(std::fs::read_dir(other).into_iter().flatten().flatten());
+    }
+    Ok(entries)
+}
+
+/// The list files of each size in a SQLite database next to them (nsl_{size}_lists.sqlite);
+/// the files not in a database (seeds, sizes computed before) are read on disk
+pub struct SqliteStore;
+
+impl ListStore for SqliteStore {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    fn list(&self, dir: &str) -> std::io::Result<Vec<String>> {
+        let mut names = LocalStore.list(dir)?;
+        for entry in local_entries(dir)? {
+            let is_database = entry.file_name().to_str().is_some_and(|n| n.starts_with("nsl_") && n.ends_with("_lists.sqlite"));
+            if is_database {
+                names.extend(database_names(&entry.path())?);
+            }
+        }
+        names.sort();
+        names.dedup();
+        Ok(names)
+    }
+
+    fn metadata(&self, path: &str) -> Option<(u64, Option<i64>)> {
+        if let Some((database, name)) = database_entry(path)
+            && let Ok(Some((bytes, modified))) = database_metadata(&database, &name) {
+            return Some((bytes, Some(modified)));
+        }
+        LocalStore.metadata(path)
+    }
+
+    fn open(&self, path: &str) -> std::io::Result<Option<AlignedVec>> {
+        match database_entry(path) {
+            Some((database, name)) => database_read(&database, &name),
+            None => Ok(None),
+        }
+    }
+
+    fn frames(&self, path: &str) -> std::io::Result<Option<Box<dyn FrameReader>>> {
+        match database_entry(path) {
+            Some((database, name)) => Ok(DatabaseReader::open(&database, &name)?
+                .map(|reader| Box::new(reader) as Box<dyn FrameReader>)),
+            None => Ok(None),
+        }
+    }
+
+    fn count(&self, path: &str) -> std::io::Result<Option<u64>> {
+        match database_entry(path) {
+            Some((database, name)) => database_count(&database, &name),
+            None => Ok(None),
+        }
+    }
+
+    fn keeps_frames(&self, path: &str) -> bool {
+        database_entry(path).is_some()
+    }
+
+    fn create(&self, path: &str) -> std::io::Result<Option<Box<dyn FrameWriter>>> {
+        match database_entry(path) {
+            Some((database, name)) => Ok(Some(Box::new(DatabaseWriter::create(&database, &name)?))),
+            None => Ok(None),
+        }
+    }
+
+    /// Deleted from its database, or else on disk
+    fn delete(&self, path: &str) -> std::io::Result<()> {
+        if let Some((database, name)) = database_entry(path)
+            && database_delete(&database, &name)? {
+            return Ok(());
+        }
+        LocalStore.delete(path)
+    }
+
+    /// Renamed in its database, or moved into the database of `to` when `to` is
+    /// in another directory; a file on disk is renamed on disk
+    fn rename(&self, from: &str, to: &str) -> std::io::Result<()> {
+        if let (Some((database, name)), Some((to_database, to_name))) = (database_entry(from), database_entry(to))
+            && database_rename(&database, &name, &to_database, &to_name)? {
+            return Ok(());
+        }
+        LocalStore.rename(from, to)
+    }
+}
+
+// Store set by a library user (set_list_store), used instead of the --storage one
+static CUSTOM_STORE: Mutex<Option<&'static dyn ListStore>> = Mutex::new(None);
+
+/// Use `store` for the list files from now on, whatever the storage backend
+pub fn set_list_store(store: Box<dyn ListStore>) {
+    *CUSTOM_STORE.lock().unwrap_or_else(|e| e.into_inner()) = Some(Box::leak(store));
+}
+
+/// Store of the list files: the one set by set_list_store, else the one of the
+/// storage backend
+pub fn list_store() -> &'static dyn ListStore {
+    if let Some(store) = *CUSTOM_STORE.lock().unwrap_or_else(|e| e.into_inner()) {
+        return store;
+    }
+    match storage_backend() {
+        StorageBackend::Files => &LocalStore,
+        StorageBackend::Sqlite => &SqliteStore,
+    }
+}
+
+/// True if the list file `path` exists in the store (on any root of its directory)
+pub fn list_file_exists(path: &str) -> bool {
+    list_store().exists(path)
+}
+
+/// Delete the list file `path` from the store
+pub fn remove_list_file(path: &str) -> std::io::Result<()> {
+    list_store().delete(path)
+}
+
+/// Rename the list file `from` to `to` in the store (see ListStore::rename)
+pub fn rename_list_file(from: &str, to: &str) -> std::io::Result<()> {
+    list_store().rename(from, to)
+}
+
+/// Names of the list files of `dir` in the store (see ListStore::list)
+pub fn list_file_names(dir: &str) -> std::io::Result<Vec<String>> {
+    list_store().list(dir)
+}
+
+/// Size in bytes and modification time (Unix seconds) of the list file `path`
+/// (None if the store does not hold it)
+pub fn file_metadata(path: &str) -> Option<(u64, Option<i64>)> {
+    list_store().metadata(path)
+}
+
+/// Transactional writer of a list file into its database: the frames become
+/// visible together when finish() commits (dropping the writer rolls them back)
+struct DatabaseWriter {
+    #[cfg(feature = "sqlite")]
+    inner: sqlite_backend::FrameWriter,
+}
+
+impl DatabaseWriter {
+    /// Start writing the list file `name` into `database`
+    #[cfg(feature = "sqlite")]
+    fn create(database: &Path, name: &str) -> std::io::Result<Self> {
+        Ok(DatabaseWriter { inner: sqlite_backend::FrameWriter::create(database, name)? })
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    fn create(_database: &Path, _name: &str) -> std::io::Result<Self> {
+        Err(unsupported())
+    }
+}
+
+impl FrameWriter for DatabaseWriter {
+    #[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+    fn write_frame(&mut self, nb_lists: u64, frame: &[u8]) -> std::io::Result<()> {
+        #[cfg(feature = "sqlite")]
+        self.inner.write_frame(nb_lists, frame)?;
+        Ok(())
+    }
+
+    #[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+    fn finish(self: Box<Self>, nb_lists: u64, bytes: u64) -> std::io::Result<()> {
+        #[cfg(feature = "sqlite")]
+        self.inner.finish(nb_lists, bytes)?;
+        Ok(())
+    }
+}
+
+/// Reader of the frames of a list file of a database, in order
+struct DatabaseReader {
+    #[cfg(feature = "sqlite")]
+    inner: sqlite_backend::FrameReader,
+}
+
+impl DatabaseReader {
+    /// Reader of the list file `name` of `database` (None if it does not hold it)
+    #[cfg(feature = "sqlite")]
+    fn open(database: &Path, name: &str) -> std::io::Result<Option<Self>> {
+        Ok(sqlite_backend::FrameReader::open(database, name)?.map(|inner| DatabaseReader { inner }))
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    fn open(_database: &Path, _name: &str) -> std::io::Result<Option<Self>> {
+        Err(unsupported())
+    }
+}
+
+impl FrameReader for DatabaseReader {
+    fn next_frame(&mut self) -> std::io::Result<Option<AlignedVec>> {
+        #[cfg(feature = "sqlite")]
+        return self.inner.next_frame();
+        #[cfg(not(feature = "sqlite"))]
+        Ok(None)
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn unsupported() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Unsupported,
+        "The sqlite storage backend needs a build with the `sqlite` feature (cargo build --release --features sqlite)")
+}
+
+#[cfg(feature = "sqlite")]
+use sqlite_backend::{database_count, database_delete, database_metadata, database_names, database_read, database_rename};
+
+#[cfg(not(feature = "sqlite"))]
+fn database_names(_database: &Path) -> std::io::Result<Vec<String>> {
+    Err(unsupported())
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn database_metadata(_database: &Path, _name: &str) -> std::io::Result<Option<(u64, i64)>> {
+    Err(unsupported())
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn database_read(_database: &Path, _name: &str) -> std::io::Result<Option<AlignedVec>> {
+    Err(unsupported())
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn database_count(_database: &Path, _name: &str) -> std::io::Result<Option<u64>> {
+    Err(unsupported())
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn database_delete(_database: &Path, _name: &str) -> std::io::Result<bool> {
+    Err(unsupported())
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn database_rename(_database: &Path, _name: &str, _to_database: &Path, _to_name: &str) -> std::io::Result<bool> {
+    Err(unsupported())
+}
+
+/// SQLite databases of list files: tables `files` (name, list count, bytes, date)
+/// and `frames` (name, sequence number, list count, frame bytes)
+#[cfg(feature = "sqlite")]
+mod sqlite_backend {
+    use std::path::Path;
+    use rkyv::AlignedVec;
+    use rusqlite::{params, Connection, OptionalExtension};
+
+    use crate::io_helpers::{FILE_HEADER_LEN, FRAMED_MAGIC};
+
+    fn sql_error(e: rusqlite::Error) -> std::io::Error {
+        std::io::Error::other(e.to_string())
+    }
+
+    /// Open (or create) a database in WAL mode
+    fn open(database: &Path) -> std::io::Result<Connection> {
+        let conn = Connection::open(database).map_err(sql_error)?;
+        conn.busy_timeout(std::time::Duration::from_secs(60)).map_err(sql_error)?;
+        conn.query_row("PRAGMA journal_mode=WAL", [], |_| Ok(())).map_err(sql_error)?;
+        conn.execute_batch(
+            "PRAGMA synchronous=NORMAL;
+             CREATE TABLE IF NOT EXISTS files (
+                 name TEXT PRIMARY KEY, nb_lists INTEGER NOT NULL,
+                 bytes INTEGER NOT NULL, modified INTEGER NOT NULL);
+             CREATE TABLE IF NOT EXISTS frames (
+                 name TEXT NOT NULL, seq INTEGER NOT NULL, nb_lists INTEGER NOT NULL,
+                 frame BLOB NOT NULL, PRIMARY KEY (name, seq));").map_err(sql_error)?;
+        Ok(conn)
+    }
+
+    /// Open an existing database (None if there is none: nothing is stored in it)
+    fn open_existing(database: &Path) -> std::io::Result<Option<Connection>> {
+        if database.exists() { open(database).map(Some) } else { Ok(None) }
+    }
+
+    pub fn database_names(database: &Path) -> std::io::Result<Vec<String>> {
+        let conn = open(database)?;
+        let mut statement = conn.prepare("SELECT name FROM files").map_err(sql_error)?;
+        let names = statement.query_map([], |row| row.get::<_, String>(0)).map_err(sql_error)?;
+        names.collect::<Result<Vec<String>, _>>().map_err(sql_error)
+    }
+
+    pub fn database_metadata(database: &Path, name: &str) -> std::io::Result<Option<(u64, i64)>> {
+        let Some(conn) = open_existing(database)? else { return Ok(None) };
+        conn.query_row("SELECT bytes, modified FROM files WHERE name = ?1", [name],
+            |row| Ok((row.get::<_, i64>(0)? as u64, row.get::<_, i64>(1)?)))
+            .optional().map_err(sql_error)
+    }
+
+    pub fn database_count(database: &Path, name: &str) -> std::io::Result<Option<u64>> {
+        let Some(conn) = open_existing(database)? else { return Ok(None) };
+        conn.query_row("SELECT nb_lists FROM files WHERE name = ?1", [name], |row| row.get::<_, i64>(0))
+            .optional().map(|n| n.map(|n| n as u64)).map_err(sql_error)
+    }
+
+    /// Delete the list file `name` (false if the database does not hold it)
+    pub fn database_delete(database: &Path, name: &str) -> std::io::Result<bool> {
+        let Some(conn) = open_existing(database)? else { return Ok(false) };
+        conn.execute_batch("BEGIN IMMEDIATE").map_err(sql_error)?;
+        conn.execute("DELETE FROM frames WHERE name = ?1", [name]).map_err(sql_error)?;
+        let deleted = conn.execute("DELETE FROM files WHERE name = ?1", [name]).map_err(sql_error)?;
+        conn.execute_batch("COMMIT").map_err(sql_error)?;
+        Ok(deleted > 0)
+    }
+
+    pub fn database_read(database: &Path, name: &str) -> std::io::Result<Option<AlignedVec>> {
+        let Some(conn) = open_existing(database)? else { return Ok(None) };
+        let Some(nb_lists) = conn.query_row("SELECT nb_lists FROM files WHERE name = ?1", [name],
+            |row| row.get::<_, i64>(0)).optional().map_err(sql_error)? else { return Ok(None) };
+        // Framed file without index: header, then the frames in order
+        let mut bytes = AlignedVec::new();
+        bytes.extend_from_slice(FRAMED_MAGIC);
+        bytes.extend_from_slice(&(nb_lists as u64).to_le_bytes());
+        bytes.extend_from_slice(&[0u8; FILE_HEADER_LEN - 16]);
+        let mut statement = conn.prepare("SELECT frame FROM frames WHERE name = ?1 ORDER BY seq").map_err(sql_error)?;
+        let mut rows = statement.query([name]).map_err(sql_error)?;
+        while let Some(row) = rows.next().map_err(sql_error)? {
+            bytes.extend_from_slice(row.get_ref(0).map_err(sql_error)?.as_blob().map_err(|e| std::io::Error::other(e.to_string()))?);
+        }
+        Ok(Some(bytes))
+    }
+
+    /// Rename the list file `name` to `to_name` of `to_database` (false if `database`
+    /// does not hold it): renamed in place within a database, else its frames are
+    /// copied into `to_database` before it is deleted
+    pub fn database_rename(database: &Path, name: &str, to_database: &Path, to_name: &str) -> std::io::Result<bool> {
+        let Some(conn) = open_existing(database)? else { return Ok(false) };
+        let Some((nb_lists, bytes)) = conn.query_row("SELECT nb_lists, bytes FROM files WHERE name = ?1", [name],
+            |row| Ok((row.get::<_, i64>(0)? as u64, row.get::<_, i64>(1)? as u64))).optional().map_err(sql_error)? else {
+            return Ok(false);
+        };
+        if database == to_database {
+            conn.execute_batch("BEGIN IMMEDIATE").map_err(sql_error)?;
+            conn.execute("DELETE FROM frames WHERE name = ?1", [to_name]).map_err(sql_error)?;
+            conn.execute("DELETE FROM files WHERE name = ?1", [to_name]).map_err(sql_error)?;
+            conn.execute("UPDATE frames SET name = ?2 WHERE name = ?1", [name, to_name]).map_err(sql_error)?;
+            conn.execute("UPDATE files SET name = ?2 WHERE name = ?1", [name, to_name]).map_err(sql_error)?;
+            conn.execute_batch("COMMIT").map_err(sql_error)?;
+            return Ok(true);
+        }
+        let mut writer = FrameWriter::create(to_database, to_name)?;
+        let mut statement = conn.prepare("SELECT nb_lists, frame FROM frames WHERE name = ?1 ORDER BY seq").map_err(sql_error)?;
+        let mut rows = statement.query([name]).map_err(sql_error)?;
+        while let Some(row) = rows.next().map_err(sql_error)? {
+            let frame = row.get_ref(1).map_err(sql_error)?.as_blob().map_err(|e| std::io::Error::other(e.to_string()))?;
+            writer.write_frame(row.get::<_, i64>(0).map_err(sql_error)? as u64, frame)?;
+        }
+        writer.finish(nb_lists, bytes)?;
+        drop(rows);
+        drop(statement);
+        drop(conn);
+        database_delete(database, name)
+    }
+
+    /// The frames of one list file, read one query at a time
+    pub struct FrameReader {
+        conn: Connection,
+        name: String,
+        seq: i64,
+    }
+
+    impl FrameReader {
+        pub fn open(database: &Path, name: &str) -> std::io::Result<Option<Self>> {
+            let Some(conn) = open_existing(database)? else { return Ok(None) };
+            let stored = conn.query_row("SELECT 1 FROM files WHERE name = ?1", [name], |_| Ok(()))
+                .optional().map_err(sql_error)?.is_some();
+            Ok(stored.then(|| FrameReader { conn, name: name.to_string(), seq: 0 }))
+        }
+
+        pub fn next_frame(&mut self) -> std::io::Result<Option<AlignedVec>> {
+            let frame = self.conn.query_row("SELECT frame FROM frames WHERE name = ?1 AND seq = ?2",
+                params![self.name, self.seq], |row| {
+                    let mut bytes = AlignedVec::new();
+                    bytes.extend_from_slice(row.get_ref(0)?.as_blob()?);
+                    Ok(bytes)
+                }).optional().map_err(sql_error)?;
+            self.seq += 1;
+            Ok(frame)
+        }
+    }
+
+    pub struct FrameWriter {
+        conn: Connection,
+        name: String,
+        seq: i64,
+    }
+
+    impl FrameWriter {
+        pub fn create(database: &Path, name: &str) -> std::io::Result<Self> {
+            let conn = open(database)?;
+            conn.execute_batch("BEGIN IMMEDIATE").map_err(sql_error)?;
+            conn.execute("DELETE FROM frames WHERE name = ?1", [name]).map_err(sql_error)?;
+            conn.execute("DELETE FROM files WHERE name = ?1", [name]).map_err(sql_error)?;
+            Ok(FrameWriter { conn, name: name.to_string(), seq: 0 })
+        }
+
+        pub fn write_frame(&mut self, nb_lists: u64, frame: &[u8]) -> std::io::Result<()> {
+            self.conn.execute("INSERT INTO frames (name, seq, nb_lists, frame) VALUES (?1, ?2, ?3, ?4)",
+                params![self.name, self.seq, nb_lists as i64, frame]).map_err(sql_error)?;
+            self.seq += 1;
+            Ok(())
+        }
+
+        pub fn finish(self, nb_lists: u64, bytes: u64) -> std::io::Result<()> {
+            let modified = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64).unwrap_or(0);
+            self.conn.execute("INSERT INTO files (name, nb_lists, bytes, modified) VALUES (?1, ?2, ?3, ?4)",
+                params![self.name, nb_lists as i64, bytes as i64, modified]).map_err(sql_error)?;
+            self.conn.execute_batch("COMMIT").map_err(sql_error)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn list_files_are_listed_and_stored_frame_by_frame() {
+        use crate::no_set_list::NoSetListSerialized;
+
+        let dir = crate::test_dir::TestDir::new("storage");
+        let dir_str = dir.to_string_lossy().into_owned();
+
+        let lists = vec![
+            NoSetListSerialized { n: 3, max_card: 3, no_set_list: vec![0, 1, 3], remaining_cards_list: vec![4, 80] },
+            NoSetListSerialized { n: 3, max_card: 9, no_set_list: vec![0, 1, 9], remaining_cards_list: vec![] },
+        ];
+        let on_disk = crate::filenames::output_filename(&dir_str, 2, 0, 3, 0);
+        assert!(crate::io_helpers::save_to_file_serialized(&lists, &on_disk));
+        std::fs::write(dir.join("nsl_03_global_info.json"), "{}").expect("write");
+        assert_eq!(list_file_names(&dir_str).expect("names"), vec!["nsl_02_batch_000000_to_03_batch_000000.rkyv"]);
+        assert!(file_metadata(&on_disk).is_some_and(|(bytes, _)| bytes > 0));
+        let renamed = crate::filenames::output_filename(&dir_str, 2, 0, 3, 5);
+        LocalStore.rename(&on_disk, &renamed).expect("rename");
+        assert!(!LocalStore.exists(&on_disk) && LocalStore.exists(&renamed));
+        assert!(LocalStore.frames(&renamed).expect("frames").is_none(), "files are read in place");
+        LocalStore.rename(&renamed, &on_disk).expect("rename back");
+
+        // The backend switch is global: the database is exercised directly here
+        #[cfg(feature = "sqlite")]
+        {
+            let database = database_path(&dir_str, 3);
+            let name = "nsl_02_batch_000001_to_03_batch_000001.rkyv";
+            let frames: Vec<Vec<u8>> = lists.iter()
+                .map(|l| crate::io_helpers::encode_frame(&vec![l.clone()], crate::io_helpers::ListEncoding::Packed).expect("frame"))
+                .collect();
+            let mut writer = sqlite_backend::FrameWriter::create(&database, name).expect("writer");
+            for frame in frames.iter() {
+                writer.write_frame(1, frame).expect("write frame");
+            }
+            writer.finish(2, 1234).expect("commit");
+            assert_eq!(database_names(&database).expect("names"), vec![name]);
+            assert_eq!(database_count(&database, name).expect("count"), Some(2));
+            assert_eq!(database_metadata(&database, name).expect("metadata").map(|(bytes, _)| bytes), Some(1234));
+            let image = database_read(&database, name).expect("read").expect("stored");
+            let decoded = crate::io_helpers::decode_lists(&image).expect("decode");
+            let cards: Vec<Vec<usize>> = decoded.iter().map(|l| l.no_set_list.clone()).collect();
+            assert_eq!(cards, vec![vec![0, 1, 3], vec![0, 1, 9]]);
+            assert_eq!(decoded[0].remaining_cards_list, vec![4, 80]);
+
+            // A writer dropped before finish() leaves the previous version in place
+            let mut unfinished = sqlite_backend::FrameWriter::create(&database, name).expect("writer");
+            unfinished.write_frame(1, &frames[0]).expect("write frame");
+            drop(unfinished);
+            assert_eq!(database_count(&database, name).expect("count"), Some(2));
+            assert!(database_read(&database, "nsl_02_batch_000002_to_03_batch_000002.rkyv").expect("read").is_none());
+
+            // Read frame by frame, renamed within the database, then moved to the
+            // database of another directory
+            let path = dir.join(name).to_string_lossy().into_owned();
+            let mut reader = SqliteStore.frames(&path).expect("frames").expect("stored");
+            let mut read = Vec::new();
+            while let Some(frame) = reader.next_frame().expect("frame") {
+                read.push(frame.to_vec());
+            }
+            assert_eq!(read, frames);
+            let renamed_name = "nsl_02_batch_000003_to_03_batch_000003.rkyv";
+            let renamed = dir.join(renamed_name).to_string_lossy().into_owned();
+            SqliteStore.rename(&path, &renamed).expect("rename");
+            assert_eq!(database_names(&database).expect("names"), vec![renamed_name]);
+            let archive = dir.join("archive");
+            std::fs::create_dir_all(&archive).expect("create dir");
+            SqliteStore.rename(&renamed, &archive.join(renamed_name).to_string_lossy()).expect("move");
+            assert!(database_names(&database).expect("names").is_empty());
+            let archived = database_path(&archive.to_string_lossy(), 3);
+            assert_eq!(database_count(&archived, renamed_name).expect("count"), Some(2));
+            assert_eq!(database_read(&archived, renamed_name).expect("read").expect("stored").to_vec(), image.to_vec());
+        }
+    }
+
+    #[test]
+    fn multi_volume_directories_resolve_files_on_every_root() {
+        use crate::no_set_list::NoSetListSerialized;
+
+        let base = crate::test_dir::TestDir::new("volumes");
+        let (first, second) = (base.join("a"), base.join("b"));
+        std::fs::create_dir_all(&first).expect("create dir");
+        let spec = format!("{};{}", first.display(), second.display());
+        let dir = register_volumes(&spec);
+        assert_eq!(dir, first.to_string_lossy());
+
+        let list = NoSetListSerialized { n: 3, max_card: 3, no_set_list: vec![0, 1, 3], remaining_cards_list: vec![4, 80] };
+        let files: Vec<String> = (0..4).map(|b| crate::filenames::output_filename(&dir, 2, b, 3, b)).collect();
+        for file in files.iter() {
+            assert!(crate::io_helpers::save_to_file_serialized(&vec![list.clone()], file));
+        }
+        let on_second = std::fs::read_dir(&second).expect("second root").count();
+        assert!(on_second > 0 && on_second < 4, "files are spread over both roots");
+        assert_eq!(list_file_names(&dir).expect("names").len(), 4);
+
+        // Found, read, rewritten in place and deleted wherever they are
+        let found = crate::filenames::find_input_filename(&dir, 3, 1).expect("found");
+        assert_eq!(crate::io_helpers::count_lists_in_file(&found).expect("count"), 1);
+        for file in files.iter() {
+            assert!(crate::io_helpers::save_to_file_serialized(&vec![list.clone(), list.clone()], file));
+            assert_eq!(crate::io_helpers::count_lists_in_file(file).expect("count"), 2);
+        }
+        assert_eq!(std::fs::read_dir(&second).expect("second root").count(), on_second, "rewrites stay on their root");
+        for file in files.iter() {
+            remove_list_file(file).expect("remove");
+        }
+        assert!(list_file_names(&dir).expect("names").is_empty());
+    }
+}
@@ -0,0 +1,53 @@
+//! Test dir module: the temporary directories of the unit tests
+//!
+//! Key features:
+//! - TestDir::new("name"): an empty directory funny_test_<name>_<pid> in the
+//!   temporary directory of the system (emptied first if a run left it behind)
+//! - Removed when the TestDir is dropped, including when the test panics
+//! - Derefs to its Path (join, to_string_lossy, ...); str() gives it as a String
+//!
+//! Used by the tests of every module reading or writing files
+
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// An empty temporary directory, removed when dropped
+pub struct TestDir {
+    path: PathBuf,
+}
+
+impl TestDir {
+    /// Create the empty directory of the test `name`
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("funny_test_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).expect("create test dir");
+        Self { path }
+    }
+
+    /// The directory, as the modes take it
+    pub fn str(&self) -> String {
+        self.path.to_string_lossy().into_owned()
+    }
+}
+
+impl Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for TestDir {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
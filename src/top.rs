@@ -0,0 +1,199 @@
+//! Top module: leaderboard of the stored lists with the most headroom
+//!
+//! Among the lists of a size, the ones with the longest remaining-cards list
+//! (or the highest reachable bound) are the best candidates for large caps.
+//!
+//! Key features:
+//! - Ranking by remaining cards, or by reachable bound: n + the extension upper
+//!   bound of the list (remaining cards minus the pairs that exclude each other)
+//! - Files read from disk; state entries whose file is missing are reported
+//! - Ties broken by position (file order, then index in the file)
+//! - Printed with decoded cards, and saved as nsl_{size:02}_top.json
+//!
+//! Used by --top mode
+
+use std::path::Path;
+use separator::Separatable;
+use serde::Serialize;
+
+use crate::no_set_list::{extension_upper_bound, NoSetListSerialized};
+use crate::set::Card;
+use crate::utils::*;
+
+/// Ranking criterion
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TopRanking {
+    Remaining,
+    Bound,
+}
+
+impl TopRanking {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "remaining" => Ok(TopRanking::Remaining),
+            "bound" => Ok(TopRanking::Bound),
+            other => Err(format!("Unknown ranking '{}' (expected remaining or bound)", other)),
+        }
+    }
+
+    fn score(&self, list: &NoSetListSerialized) -> usize {
+        match self {
+            TopRanking::Remaining => list.remaining_cards_list.len(),
+            TopRanking::Bound => list.n as usize + extension_upper_bound(&list.no_set_list, &list.remaining_cards_list),
+        }
+    }
+}
+
+/// One list of the leaderboard
+#[derive(Debug, Clone, Serialize)]
+pub struct TopEntry {
+    pub score: usize,
+    pub file: String,
+    pub index: u64,
+    pub max_card: usize,
+    pub cards: Vec<usize>,
+    pub remaining: Vec<usize>,
+    pub reachable_bound: usize,
+}
+
+/// Leaderboard of one size
+#[derive(Debug, Clone, Serialize)]
+pub struct TopReport {
+    pub size: u8,
+    pub ranking: String,
+    pub files_scanned: u64,
+    pub lists_scanned: u64,
+    pub missing_files: Vec<String>,
+    pub entries: Vec<TopEntry>,
+}
+
+/// Scan the size `size` files of `dir` and keep the `count` best lists
+pub fn top_lists(dir: &str, size: u8, count: usize, ranking: TopRanking) -> std::io::Result<TopReport> {
+    test_print(&format!("\nTOP MODE: Best {} lists of size {:02} by {}", count,
+        size, if ranking == TopRanking::Bound { "reachable bound" } else { "remaining cards" }));
+    test_print(&format!("   Directory: {}", dir));
+
+    let files = crate::filenames::list_input_files(dir, size);
+    if files.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound,
+            format!("No size {:02} files found in {}", size, dir)));
+    }
+    let missing_files: Vec<String> = match crate::file_info::GlobalFileState::from_sources(dir, size) {
+        Ok(state) => state.entries().values()
+            .filter(|e| crate::storage::file_metadata(&Path::new(dir).join(&e.filename).to_string_lossy()).is_none())
+            .map(|e| e.filename.clone())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    let mut report = TopReport {
+        size,
+        ranking: if ranking == TopRanking::Bound { "bound".to_string() } else { "remaining".to_string() },
+        files_scanned: files.len() as u64,
+        lists_scanned: 0,
+        missing_files,
+        entries: Vec::new(),
+    };
+    for file in files.iter() {
+        let filename = Path::new(&file.path).file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let mut index = 0u64;
+        let entries = &mut report.entries;
+        crate::io_helpers::load_lists_in_chunks(&file.path, 1_000_000, |chunk| {
+            for list in chunk.iter() {
+                let score = ranking.score(list);
+                // Sorted best first: a list only enters if it beats the last one
+                if entries.len() < count || entries.last().is_some_and(|last| score > last.score) {
+                    let position = entries.iter().position(|e| score > e.score).unwrap_or(entries.len());
+                    entries.insert(position, TopEntry {
+                        score,
+                        file: filename.clone(),
+                        index,
+                        max_card: list.max_card,
+                        cards: list.no_set_list.clone(),
+                        remaining: list.remaining_cards_list.clone(),
+                        reachable_bound: list.n as usize + extension_upper_bound(&list.no_set_list, &list.remaining_cards_list),
+                    });
+                    entries.truncate(count);
+                }
+                index += 1;
+            }
+            Ok(())
+        })?;
+        report.lists_scanned += index;
+    }
+    Ok(report)
+}
+
+/// Print the leaderboard and save it as nsl_{size:02}_top.json in `dir`
+pub fn print_and_save_top(report: &TopReport, dir: &str) -> std::io::Result<()> {
+    test_print(&format!("   ... {} lists scanned in {} files", report.lists_scanned.separated_string(), report.files_scanned));
+    for filename in report.missing_files.iter() {
+        test_print(&format!("   ... warning: {} is in the state but not on disk", filename));
+    }
+    for (rank, entry) in report.entries.iter().enumerate() {
+        test_print(&format!("\n   #{:<3} {} remaining cards, reachable bound {} ({} #{})", rank + 1,
+            entry.remaining.len(), entry.reachable_bound, entry.file, entry.index));
+        test_print(&format!("        cards:     {:?}", entry.cards));
+        test_print(&format!("        remaining: {:?}", entry.remaining));
+        for &card in entry.cards.iter() {
+            test_print(&format!("        {:>5}  {}", card, Card::decode(card)));
+        }
+    }
+
+    let path = Path::new(dir).join(format!("nsl_{:02}_top.json", report.size));
+    let json = serde_json::to_string_pretty(report)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    std::fs::write(&path, json)?;
+    test_print(&format!("\n   Leaderboard saved: {}", path.display()));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn best_lists_are_kept_in_order() {
+        let dir = crate::test_dir::TestDir::new("top");
+        let dir_str = dir.to_string_lossy().into_owned();
+
+        let list = |max_card: usize, remaining: Vec<usize>| NoSetListSerialized {
+            n: 4, max_card, no_set_list: vec![0, 1, 3, max_card], remaining_cards_list: remaining };
+        let lists = vec![list(5, vec![6]), list(6, vec![7, 8, 9]), list(7, vec![8, 9]), list(8, vec![9, 10, 11])];
+        let file = crate::filenames::output_filename(&dir_str, 3, 0, 4, 0);
+        assert!(crate::io_helpers::save_to_file_serialized(&lists, &file));
+
+        let report = top_lists(&dir_str, 4, 2, TopRanking::Remaining).expect("top");
+        assert_eq!(report.lists_scanned, 4);
+        // Equal scores: the first list in the file ranks first
+        assert_eq!(report.entries.iter().map(|e| e.index).collect::<Vec<_>>(), vec![1, 3]);
+    }
+
+    #[test]
+    fn unknown_rankings_and_empty_sizes_are_refused() {
+        assert_eq!(TopRanking::parse("bound"), Ok(TopRanking::Bound));
+        assert!(TopRanking::parse("best").is_err());
+
+        let dir = crate::test_dir::TestDir::new("top_empty");
+        let error = top_lists(&dir.to_string_lossy(), 4, 2, TopRanking::Remaining).expect_err("no files");
+        assert_eq!(error.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn files_recorded_but_missing_are_reported() {
+        let dir = crate::test_dir::TestDir::new("top_missing");
+        let dir_str = dir.to_string_lossy().into_owned();
+
+        let lists = vec![NoSetListSerialized { n: 4, max_card: 5, no_set_list: vec![0, 1, 3, 5], remaining_cards_list: vec![6] }];
+        let file = crate::filenames::output_filename(&dir_str, 3, 0, 4, 0);
+        assert!(crate::io_helpers::save_to_file_serialized(&lists, &file));
+        let mut state = crate::file_info::GlobalFileState::new(&dir_str, 4);
+        state.register_file("nsl_03_batch_000001_to_04_batch_000001.rkyv", 1, 1, 1, false, None, None);
+        state.flush().expect("flush");
+
+        let report = top_lists(&dir_str, 4, 2, TopRanking::Bound).expect("top");
+        assert_eq!(report.missing_files, vec!["nsl_03_batch_000001_to_04_batch_000001.rkyv".to_string()]);
+        print_and_save_top(&report, &dir_str).expect("save");
+        assert!(dir.join("nsl_04_top.json").exists());
+    }
+}
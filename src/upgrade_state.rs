@@ -0,0 +1,150 @@
+//! Upgrade-state module: rewrite the global state of a size in the current schema
+//!
+//! The rkyv state files are validated against the exact layout of GlobalFileInfo, so
+//! each change of FileInfo or GlobalFileInfo bumps STATE_SCHEMA_VERSION. Older states
+//! are still loaded (migrated step by step in memory), but only rewriting them makes
+//! the migration permanent and lets the read-only layouts be dropped one day.
+//!
+//! Key features:
+//! - Upgrades nsl_XX_global_info.rkyv and nsl_XX_global_info_history.rkyv, and the
+//!   JSON exports next to them
+//! - Files already in the current schema version are left untouched
+//! - The upgraded state is written into a .tmp file, then renamed over the
+//!   original, which is kept as .rkyv.old
+//! - States of an unknown (newer) schema version are refused
+//!
+//! Used by --upgrade-state mode
+
+use std::path::{Path, PathBuf};
+
+use crate::file_info::{acquire_state_lock, keep_backups, rotate_backups, state_schema_version, GlobalFileInfo, STATE_SCHEMA_VERSION};
+use crate::utils::*;
+
+/// Result of upgrading the state files of one size
+#[derive(Debug, Clone)]
+pub struct UpgradeStateReport {
+    pub size: u8,
+    pub files_checked: u64,
+    pub files_upgraded: u64,
+}
+
+/// Schema version recorded in a JSON state (0 if written before versioning)
+fn json_schema_version(path: &Path) -> std::io::Result<u32> {
+    let text = std::fs::read_to_string(path)?;
+    let value: serde_json::Value = serde_json::from_str(&text)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    Ok(value.get("schema_version").and_then(|v| v.as_u64()).unwrap_or(0) as u32)
+}
+
+/// Rewrite the rkyv state at `path` in the current schema, keeping the original as .rkyv.old
+/// (rotated like the backups of a flush)
+fn upgrade_rkyv(path: &Path) -> std::io::Result<()> {
+    let gfi = GlobalFileInfo::load_rkyv(path)?;
+    let tmp = path.with_extension("rkyv.tmp");
+    gfi.save_rkyv(&tmp)?;
+    rotate_backups(path, keep_backups())?;
+    std::fs::rename(&tmp, path)
+}
+
+/// Upgrade the state files of `size` in `base_path` to the current schema version
+pub fn upgrade_state_files(base_path: &str, size: u8) -> std::io::Result<UpgradeStateReport> {
+    test_print(&format!("\nUPGRADE STATE MODE: Upgrading the size {:02} state to schema version {}...", size, STATE_SCHEMA_VERSION));
+    test_print(&format!("   Directory: {}", base_path));
+
+    let names = [format!("nsl_{:02}_global_info", size), format!("nsl_{:02}_global_info_history", size)];
+    let rkyv_paths: Vec<PathBuf> = names.iter().map(|n| Path::new(base_path).join(format!("{}.rkyv", n))).collect();
+    let json_paths: Vec<PathBuf> = names.iter().map(|n| Path::new(base_path).join(format!("{}.json", n))).collect();
+    if !rkyv_paths.iter().chain(json_paths.iter()).any(|p| p.exists()) {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound,
+            format!("No size {:02} state found in {}", size, base_path)));
+    }
+    acquire_state_lock(base_path, size)?;
+
+    let mut report = UpgradeStateReport { size, files_checked: 0, files_upgraded: 0 };
+    for (path, is_json) in rkyv_paths.iter().map(|p| (p, false)).chain(json_paths.iter().map(|p| (p, true))) {
+        if !path.exists() {
+            continue;
+        }
+        report.files_checked += 1;
+        let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let version = if is_json { json_schema_version(path)? } else { state_schema_version(path)? };
+        if version == STATE_SCHEMA_VERSION {
+            debug_print(&format!("   ... {} already in schema version {} (unchanged)", name, STATE_SCHEMA_VERSION));
+            continue;
+        }
+        if is_json {
+            // load_json refuses newer versions and fills the fields added since with their defaults
+            GlobalFileInfo::load_json(path)?.save_json(path)?;
+        } else {
+            upgrade_rkyv(path)?;
+        }
+        report.files_upgraded += 1;
+        test_print(&format!("   ... {}: schema version {} -> {}", name, version, STATE_SCHEMA_VERSION));
+    }
+
+    test_print(&format!("   ... {} of {} state files upgraded", report.files_upgraded, report.files_checked));
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_info::FileInfo;
+    use rkyv::{Archive, Serialize as RkyvSerialize};
+
+    /// Layout of schema version 3 (NSLSTAT3), as written by older versions
+    #[derive(Archive, RkyvSerialize)]
+    struct StateV3 {
+        entries: Vec<EntryV3>,
+        max_lists_per_file: Option<u64>,
+    }
+
+    #[derive(Archive, RkyvSerialize)]
+    struct EntryV3 {
+        source_batch: u32,
+        target_batch: u32,
+        cumulative_nb_lists: u64,
+        nb_lists_in_file: u64,
+        filename: String,
+        compacted: bool,
+        compressed: bool,
+        exists: Option<bool>,
+        file_size_bytes: Option<u64>,
+        modified_timestamp: Option<i64>,
+    }
+
+    #[test]
+    fn older_state_is_rewritten_in_current_schema() {
+        let dir = crate::test_dir::TestDir::new("upgrade_state");
+        let dir_str = dir.to_string_lossy().into_owned();
+
+        let filename = "nsl_04_batch_000000_to_04_batch_000001.rkyv".to_string();
+        let old_entry = EntryV3 {
+            source_batch: 0, target_batch: 1, cumulative_nb_lists: 7, nb_lists_in_file: 7,
+            filename: filename.clone(), compacted: false,
+            compressed: true, exists: Some(true), file_size_bytes: Some(512), modified_timestamp: None,
+        };
+        let entry = FileInfo {
+            source_batch: 0, target_batch: 1, cumulative_nb_lists: 7, nb_lists_in_file: 7,
+            filename, compacted: false,
+            compressed: true, exists: Some(true), file_size_bytes: Some(512), modified_timestamp: None,
+            provenance: None,
+        };
+        let old = StateV3 { entries: vec![old_entry], max_lists_per_file: Some(1000) };
+        let path = dir.join("nsl_04_global_info.rkyv");
+        let mut bytes = b"NSLSTAT3".to_vec();
+        bytes.extend_from_slice(&rkyv::to_bytes::<_, 256>(&old).expect("serialize"));
+        std::fs::write(&path, bytes).expect("write");
+        assert_eq!(state_schema_version(&path).expect("version"), 3);
+
+        let report = upgrade_state_files(&dir_str, 4).expect("upgrade");
+        assert_eq!((report.files_checked, report.files_upgraded), (1, 1));
+        assert_eq!(state_schema_version(&path).expect("version"), STATE_SCHEMA_VERSION);
+        assert_eq!(state_schema_version(path.with_extension("rkyv.old")).expect("version"), 3);
+        let upgraded = GlobalFileInfo::load_rkyv(&path).expect("load");
+        assert_eq!((upgraded.entries, upgraded.max_lists_per_file), (vec![entry], Some(1000)));
+
+        let again = upgrade_state_files(&dir_str, 4).expect("upgrade");
+        assert_eq!(again.files_upgraded, 0, "current state left untouched");
+    }
+}
@@ -1,4 +1,4 @@
-/// Few tools for debug
+//! Few tools for debug
 
 // This very stupid 'debug_print' function raises a problem:
 //		- it is used by virtually all other modules, so it has a global scope
@@ -51,10 +51,9 @@ pub fn init_log_file() {
 
 /// Write to log file if it's open
 fn write_to_log(msg: &str) {
-	if let Ok(mut log_guard) = LOG_FILE.lock() {
-		if let Some(ref mut file) = *log_guard {
-			let _ = writeln!(file, "{}", msg);
-		}
+	if let Ok(mut log_guard) = LOG_FILE.lock()
+		&& let Some(ref mut file) = *log_guard {
+		let _ = writeln!(file, "{}", msg);
 	}
 }
 
@@ -76,31 +75,36 @@ pub fn test_print_off() {
 
 pub fn debug_print_noln(msg:&str) {
 	if DEBUG_FLAG.load(Ordering::Relaxed) {
-		eprint!("{}", format!("debug: {}", msg.to_string()));
+		eprint!("debug: {}", msg);
 	}
 }
 
 
 pub fn debug_print(msg:&str) {
 	if DEBUG_FLAG.load(Ordering::Relaxed) {
-		eprintln!("{}", format!("debug: {}", msg.to_string()));
+		eprintln!("debug: {}", msg);
 	}
 }
 
+/// Console line of the run: printed on stderr unless test printing is off, written
+/// to the log file if open, and passed to the observers (events::progress)
 pub fn test_print(msg:&str) {
 	if TEST_FLAG.load(Ordering::Relaxed) {
-		eprintln!("{}", msg.to_string());
+		eprintln!("{}", msg);
 	}
 	// Always write to log file if it's open
 	write_to_log(msg);
+	crate::events::progress(msg);
 }
 
 /// Progress output intended for interactive display during long-running operations.
 /// Prints to stdout and flushes so progress is visible even if stderr/stdout is redirected.
+/// Also passed to the observers (events::progress).
 pub fn progress_print(msg: &str) {
 	println!("{}", msg);
 	let _ = stdout().flush();
 	write_to_log(msg);
+	crate::events::progress(msg);
 }
 
 pub fn banner(msg:&str) {
@@ -111,7 +115,7 @@ pub fn banner(msg:&str) {
 	let titre = if msg_len > BANNER_WIDTH {
 		&msg[..BANNER_WIDTH]
 	} else {
-		&msg
+		msg
 	};
 	// compute the required spaces before and after the message
 	let total_padding = BANNER_WIDTH - msg_len;
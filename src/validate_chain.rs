@@ -0,0 +1,280 @@
+//! Validate-chain module: cross-size consistency of a cascade directory tree
+//!
+//! Each size is computed from all the batches of the previous size. This module
+//! checks, for each consecutive pair of sizes (N, N+1), that every input batch of
+//! size N was consumed by size N+1 and that every size N+1 output comes from a
+//! known size N batch.
+//!
+//! Key features:
+//! - Cascade directory layout (same directories as --cascade)
+//! - Input batches: size N state entries and size N files on disk (target batches),
+//!   compacted and regular files apart (their numberings overlap)
+//! - Consumed batches: consumed inputs recorded by the size N+1 state, and source
+//!   batches of its entries, history and compacted files (every source batch merged
+//!   into a compacted file, not only the one its filename names)
+//! - Reports gaps in the input batch numbering, unconsumed input batches, consumed
+//!   inputs whose list count differs from the size N state, and outputs (state
+//!   entries or files) whose source batch is unknown
+//! - Nothing is modified (the legacy state rebuild paths are not used), except the
+//!   report nsl_{from:02}_to_{to:02}_chain_report.json written in the root: gaps are
+//!   warnings (see findings), every other inconsistency an integrity error
+//!
+//! Used by --validate-chain mode and --check --against-input (one pair of sizes)
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use serde::Serialize;
+
+use crate::dry_run::load_state_readonly;
+use crate::file_info::{GlobalFileState, InputKey};
+use crate::findings::{Finding, FindingsReport};
+use crate::utils::*;
+
+/// Consistency of one pair of sizes
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ChainLink {
+    pub input_size: u8,
+    pub input_dir: String,
+    pub output_dir: String,
+    pub missing_dirs: Vec<String>,
+    pub input_batches: u64,
+    pub consumed_batches: u64,
+    pub gaps: Vec<u32>,                         // input batches missing from the numbering
+    pub unconsumed: Vec<u32>,                   // input batches not used by size N+1
+    pub unconsumed_compacted: Vec<u32>,         // compacted input batches not used by size N+1
+    pub count_mismatches: Vec<(u32, u64, u64)>, // (input batch, lists recorded, lists read)
+    pub unknown_sources: Vec<(String, u32)>,    // (output file, source batch)
+    pub traced_compacted: u64,                  // compacted outputs with recorded source batches
+}
+
+impl ChainLink {
+    pub fn is_clean(&self) -> bool {
+        self.missing_dirs.is_empty() && self.gaps.is_empty()
+            && self.unconsumed.is_empty() && self.unconsumed_compacted.is_empty()
+            && self.count_mismatches.is_empty() && self.unknown_sources.is_empty()
+    }
+}
+
+/// Consistency of a range of sizes
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ChainReport {
+    pub links: Vec<ChainLink>,
+}
+
+impl ChainReport {
+    pub fn is_clean(&self) -> bool {
+        self.links.iter().all(|l| l.is_clean())
+    }
+}
+
+/// Input files of `size` in `dir`: the state entries and the files on disk, with
+/// the lists the state records for each (None for the files not in the state);
+/// compacted and regular files apart, their numberings overlap
+fn input_batches(dir: &str, size: u8) -> std::io::Result<BTreeMap<InputKey, Option<u64>>> {
+    let state = load_state_readonly(dir, size)?;
+    let mut batches: BTreeMap<InputKey, Option<u64>> = BTreeMap::new();
+    for e in state.entries().values() {
+        *batches.entry(InputKey::whole(e.target_batch, e.compacted)).or_default().get_or_insert(0) += e.nb_lists_in_file;
+    }
+    for file in crate::filenames::list_input_files(dir, size) {
+        batches.entry(InputKey::whole(file.batch, file.compacted)).or_default();
+    }
+    Ok(batches)
+}
+
+/// Check one pair of sizes: `input_size` in `input_dir`, `input_size + 1` in `output_dir`
+pub fn validate_link(input_dir: &str, output_dir: &str, input_size: u8) -> std::io::Result<ChainLink> {
+    let output_size = input_size + 1;
+    let mut link = ChainLink {
+        input_size,
+        input_dir: input_dir.to_string(),
+        output_dir: output_dir.to_string(),
+        ..Default::default()
+    };
+    for dir in [input_dir, output_dir] {
+        if !Path::new(dir).is_dir() {
+            link.missing_dirs.push(dir.to_string());
+        }
+    }
+    if !link.missing_dirs.is_empty() {
+        return Ok(link);
+    }
+
+    let recorded_counts = input_batches(input_dir, input_size)?;
+    let inputs: BTreeSet<InputKey> = recorded_counts.keys().copied().collect();
+    let input_numbers: BTreeSet<u32> = inputs.iter().map(|k| k.batch).collect();
+    if let Some(&last) = input_numbers.iter().next_back() {
+        link.gaps = (0..=last).filter(|b| !input_numbers.contains(b)).collect();
+    }
+
+    // Outputs: state and history entries, plus files on disk not recorded
+    let state = load_state_readonly(output_dir, output_size)?;
+    let mut outputs: Vec<(String, u32)> = state.entries().values()
+        .map(|e| (e.filename.clone(), e.source_batch))
+        .collect();
+    if let Ok(history) = GlobalFileState::from_history_file(output_dir, output_size, "rkyv") {
+        outputs.extend(history.entries().values().map(|e| (e.filename.clone(), e.source_batch)));
+    }
+    for file in crate::filenames::list_input_files(output_dir, output_size) {
+        let name = Path::new(&file.path).file_name().unwrap_or_default().to_string_lossy().into_owned();
+        if let Some(parsed) = crate::filenames::parse_filename(&name)
+            && !outputs.iter().any(|(f, _)| *f == name) {
+            outputs.push((name, parsed.source_batch));
+        }
+    }
+    for (filename, sources) in state.compacted_sources().iter() {
+        outputs.extend(sources.iter().map(|s| (filename.clone(), s.source_batch)));
+    }
+    link.traced_compacted = state.compacted_sources().len() as u64;
+    outputs.sort();
+    outputs.dedup();
+
+    // Outputs name their source batch, not its kind: they only tell for the batch
+    // numbers the state records no consumed input of
+    let sources: BTreeSet<u32> = outputs.iter().map(|(_, b)| *b).collect();
+    let consumed = state.consumed_among(&inputs, &sources);
+    for (key, input) in state.consumed_inputs().iter() {
+        if let Some(Some(recorded)) = recorded_counts.get(&InputKey::whole(key.batch, key.compacted))
+            && *recorded != input.nb_lists {
+            link.count_mismatches.push((input.batch, *recorded, input.nb_lists));
+        }
+    }
+    link.input_batches = inputs.len() as u64;
+    link.consumed_batches = consumed.len() as u64;
+    let (compacted, regular): (Vec<InputKey>, Vec<InputKey>) = inputs.difference(&consumed).partition(|k| k.compacted);
+    link.unconsumed = regular.into_iter().map(|k| k.batch).collect();
+    link.unconsumed_compacted = compacted.into_iter().map(|k| k.batch).collect();
+    link.unknown_sources = outputs.into_iter().filter(|(_, b)| !input_numbers.contains(b)).collect();
+    Ok(link)
+}
+
+/// Check every pair of sizes from `from_size` to `to_size` under the cascade root `root`
+pub fn validate_chain(root: &str, from_size: u8, to_size: u8) -> std::io::Result<ChainReport> {
+    test_print(&format!("\nVALIDATE-CHAIN MODE: sizes {:02} to {:02}", from_size, to_size));
+    test_print(&format!("   Root directory: {}", root));
+    let mut report = ChainReport::default();
+    for input_size in from_size..to_size {
+        let (input_dir, output_dir) = crate::filenames::get_cascade_directories(root, input_size);
+        let link = validate_link(&input_dir, &output_dir, input_size)?;
+        print_link(&link);
+        report.links.push(link);
+    }
+    Ok(report)
+}
+
+/// Findings of a chain report: gaps are warnings, the other inconsistencies errors
+pub fn chain_findings(report: &ChainReport) -> Vec<Finding> {
+    report.links.iter().flat_map(link_findings).collect()
+}
+
+/// Findings of one pair of sizes (see chain_findings)
+pub fn link_findings(link: &ChainLink) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let pair = format!("size {:02} -> {:02}", link.input_size, link.input_size + 1);
+    for dir in link.missing_dirs.iter() {
+        findings.push(Finding::error("missing_directory", format!("{}: directory {} not found", pair, dir)));
+    }
+    for batch in link.gaps.iter() {
+        findings.push(Finding::warning("gap", format!("{}: input batch missing from the numbering", pair))
+            .with_batch(*batch));
+    }
+    for batch in link.unconsumed.iter() {
+        findings.push(Finding::error("unconsumed_input", format!("{}: input batch not used by size {:02}", pair, link.input_size + 1))
+            .with_batch(*batch));
+    }
+    for batch in link.unconsumed_compacted.iter() {
+        findings.push(Finding::error("unconsumed_input", format!("{}: compacted input batch not used by size {:02}", pair, link.input_size + 1))
+            .with_batch(*batch));
+    }
+    for (batch, recorded, read) in link.count_mismatches.iter() {
+        findings.push(Finding::error("count_mismatch", format!("{}: {} lists recorded, {} read", pair, recorded, read))
+            .with_batch(*batch));
+    }
+    for (filename, batch) in link.unknown_sources.iter() {
+        findings.push(Finding::error("unknown_source", format!("{}: output from an unknown input batch", pair))
+            .with_file(filename.as_str()).with_batch(*batch));
+    }
+    findings
+}
+
+/// Save the chain report, with its findings, as nsl_{from:02}_to_{to:02}_chain_report.json in `root`
+pub fn save_chain_report(root: &str, from_size: u8, to_size: u8, report: &ChainReport) -> std::io::Result<FindingsReport<ChainReport>> {
+    let findings = FindingsReport::new("validate-chain", chain_findings(report), report.clone());
+    findings.save(root, &format!("nsl_{:02}_to_{:02}_chain_report.json", from_size, to_size))?;
+    Ok(findings)
+}
+
+/// Print the consistency of one pair of sizes
+pub fn print_link(link: &ChainLink) {
+    test_print(&format!("\n   Size {:02} -> {:02} ({} -> {})", link.input_size, link.input_size + 1,
+        link.input_dir, link.output_dir));
+    for dir in link.missing_dirs.iter() {
+        test_print(&format!("      [MISSING] directory {} not found", dir));
+    }
+    if !link.missing_dirs.is_empty() {
+        return;
+    }
+    test_print(&format!("      {} input batches, {} consumed", link.input_batches, link.consumed_batches));
+    if link.traced_compacted > 0 {
+        test_print(&format!("      {} compacted outputs traced to their source batches", link.traced_compacted));
+    }
+    if !link.gaps.is_empty() {
+        test_print(&format!("      [GAP] {} input batches missing: {:?}", link.gaps.len(), link.gaps));
+    }
+    if !link.unconsumed.is_empty() {
+        test_print(&format!("      [UNCONSUMED] {} input batches: {:?}", link.unconsumed.len(), link.unconsumed));
+    }
+    if !link.unconsumed_compacted.is_empty() {
+        test_print(&format!("      [UNCONSUMED] {} compacted input batches: {:?}", link.unconsumed_compacted.len(), link.unconsumed_compacted));
+    }
+    for (batch, recorded, read) in link.count_mismatches.iter() {
+        test_print(&format!("      [COUNT] input batch {:06}: {} lists recorded by size {:02}, {} read by size {:02}",
+            batch, recorded, link.input_size, read, link.input_size + 1));
+    }
+    for (filename, batch) in link.unknown_sources.iter() {
+        test_print(&format!("      [UNKNOWN SOURCE] {} (source batch {:06})", filename, batch));
+    }
+    if link.is_clean() {
+        test_print("      [OK] every input batch consumed, every output from a known input");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unconsumed_inputs_and_unknown_sources_are_reported() {
+        let root = crate::test_dir::TestDir::new("validate_chain");
+        let (input_dir, output_dir) = crate::filenames::get_cascade_directories(&root.to_string_lossy(), 13);
+        std::fs::create_dir_all(&input_dir).expect("create input dir");
+        std::fs::create_dir_all(&output_dir).expect("create output dir");
+
+        let mut inputs = GlobalFileState::new(&input_dir, 13);
+        for batch in [0, 1, 3, 4] {
+            inputs.register_file(&format!("nsl_12_batch_000000_to_13_batch_{:06}.rkyv", batch), 0, batch, 10, false, None, None);
+        }
+        inputs.flush().expect("flush inputs");
+        let mut outputs = GlobalFileState::new(&output_dir, 14);
+        for (src, tgt) in [(0, 0), (1, 1), (7, 2)] {
+            outputs.register_file(&format!("nsl_13_batch_{:06}_to_14_batch_{:06}.rkyv", src, tgt), src, tgt, 10, false, None, None);
+        }
+        outputs.record_consumed_input(13, InputKey::whole(1, false), 9); // size 13 state records 10 lists
+        outputs.record_consumed_input(13, InputKey::whole(4, false), 10); // consumed, no output
+        outputs.flush().expect("flush outputs");
+
+        let report = validate_chain(&root.to_string_lossy(), 13, 15).expect("validate");
+        let link = &report.links[0];
+        assert_eq!(link.gaps, vec![2]);
+        assert_eq!(link.unconsumed, vec![3]);
+        assert_eq!(link.count_mismatches, vec![(1, 10, 9)]);
+        assert_eq!(link.unknown_sources, vec![("nsl_13_batch_000007_to_14_batch_000002.rkyv".to_string(), 7)]);
+        assert_eq!(report.links[1].missing_dirs.len(), 1);
+        assert!(!report.is_clean());
+        let saved = save_chain_report(&root.to_string_lossy(), 13, 15, &report).expect("save");
+        assert_eq!(saved.status, crate::findings::Status::Errors);
+        let gap = saved.findings.iter().find(|f| f.kind == "gap").expect("gap finding");
+        assert_eq!((gap.severity, gap.batch), (crate::findings::Severity::Warning, Some(2)));
+        assert!(root.join("nsl_13_to_15_chain_report.json").exists());
+    }
+}
@@ -0,0 +1,142 @@
+//! Count validation module: compare produced totals with known reference values
+//!
+//! The number of stored no-set-lists of each size is deterministic, so a total
+//! differing from a known value means lost, duplicated or corrupted output.
+//!
+//! Key features:
+//! - Built-in reference table for sizes 3-10 (the values of the default-mode banner)
+//! - Optional user-supplied JSON of expected values ({"11": 123, ...}) overriding
+//!   or extending the built-in table
+//! - Totals read from the global state of the size (nsl_{size:02}_global_info.*)
+//!
+//! Used by --validate-counts (size, default and cascade modes) and --final-report
+
+use std::collections::BTreeMap;
+use separator::Separatable;
+
+use crate::file_info::GlobalFileState;
+use crate::utils::*;
+
+/// Reference counts of stored no-set-lists per size
+pub const REFERENCE_COUNTS: [(u8, u64); 8] = [
+    (3, 58_896),
+    (4, 1_004_589),
+    (5, 13_394_538),
+    (6, 141_370_218),
+    (7, 1_180_345_041),
+    (8, 7_920_450_378),
+    (9, 43_126_538_805),
+    (10, 193_375_848_191),
+];
+
+/// Built-in reference count of a size, if known
+pub fn reference_count(size: u8) -> Option<u64> {
+    REFERENCE_COUNTS.iter().find(|(s, _)| *s == size).map(|(_, c)| *c)
+}
+
+/// Expected counts: the built-in table, overridden/extended by `user_file` if given
+pub fn load_expected_counts(user_file: Option<&str>) -> std::io::Result<BTreeMap<u8, u64>> {
+    let mut expected: BTreeMap<u8, u64> = REFERENCE_COUNTS.iter().copied().collect();
+    if let Some(path) = user_file {
+        let text = std::fs::read_to_string(path)?;
+        let user: BTreeMap<String, u64> = serde_json::from_str(&text)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path, e)))?;
+        for (size, count) in user {
+            let size: u8 = size.trim().parse()
+                .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData,
+                    format!("{}: invalid size key '{}'", path, size)))?;
+            expected.insert(size, count);
+        }
+    }
+    Ok(expected)
+}
+
+/// Outcome of the validation of one size
+#[derive(Debug, Clone)]
+pub struct CountCheck {
+    pub size: u8,
+    pub computed: u64,
+    pub expected: Option<u64>,
+}
+
+impl CountCheck {
+    pub fn is_mismatch(&self) -> bool {
+        self.expected.is_some_and(|e| e != self.computed)
+    }
+}
+
+/// Compare the total recorded in the global state of `size` with the expected value
+pub fn validate_size_count(base_dir: &str, size: u8, expected: &BTreeMap<u8, u64>) -> std::io::Result<CountCheck> {
+    let state = GlobalFileState::from_sources(base_dir, size)?;
+    if state.isomorph_reduced() {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+            format!("the size {:02} lists are reduced by --isomorph-cache: not comparable with exhaustive counts", size)));
+    }
+    if state.strong_pruned() {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+            format!("the size {:02} lists are pruned by --strong-prune: not comparable with exhaustive counts", size)));
+    }
+    let computed: u64 = state.entries().values().map(|e| e.nb_lists_in_file).sum();
+    let check = CountCheck { size, computed, expected: expected.get(&size).copied() };
+
+    match check.expected {
+        None => test_print(&format!("   Count validation: no expected value for size {:02} ({} lists), skipped",
+            size, computed.separated_string())),
+        Some(e) if e == computed => test_print(&format!("   Count validation: size {:02} OK ({} lists)",
+            size, computed.separated_string())),
+        Some(e) => {
+            test_print("\n   ##################################################");
+            test_print(&format!("   COUNT MISMATCH for size {:02}: {} lists produced, {} expected",
+                size, computed.separated_string(), e.separated_string()));
+            test_print("   ##################################################\n");
+        }
+    }
+    Ok(check)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_values_override_and_extend_reference_table() {
+        let dir = crate::test_dir::TestDir::new("expected_counts");
+        let path = dir.join("expected.json");
+        std::fs::write(&path, r#"{"4": 12, "11": 34}"#).expect("write json");
+
+        let expected = load_expected_counts(Some(&path.to_string_lossy())).expect("load");
+        assert_eq!(expected.get(&3), Some(&58_896));
+        assert_eq!(expected.get(&4), Some(&12));
+        assert_eq!(expected.get(&11), Some(&34));
+
+        let check = CountCheck { size: 4, computed: 13, expected: expected.get(&4).copied() };
+        assert!(check.is_mismatch());
+    }
+
+    #[test]
+    fn reduced_states_are_not_validated() {
+        let dir = crate::test_dir::TestDir::new("validate_reduced");
+        let dir_str = dir.to_string_lossy().into_owned();
+
+        let mut state = GlobalFileState::new(&dir_str, 5);
+        state.set_isomorph_reduced(true);
+        state.flush().expect("flush");
+
+        let error = validate_size_count(&dir_str, 5, &BTreeMap::new()).expect_err("reduced state");
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn pruned_states_are_not_validated() {
+        let dir = crate::test_dir::TestDir::new("validate_pruned");
+        let dir_str = dir.to_string_lossy().into_owned();
+
+        let mut state = GlobalFileState::new(&dir_str, 4);
+        state.register_file("nsl_03_batch_000000_to_04_batch_000000.rkyv", 0, 0, 935_109, false, None, None);
+        state.set_strong_pruned(true);
+        state.flush().expect("flush");
+
+        let error = validate_size_count(&dir_str, 4, &load_expected_counts(None).expect("reference")).expect_err("pruned state");
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
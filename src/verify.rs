@@ -0,0 +1,244 @@
+//! Deep verification module re-checking the content of stored no-set-lists
+//!
+//! Unlike --check (which looks at files and batch numbering), this module opens
+//! every stored list of a size and re-checks the invariants the algorithm relies on.
+//!
+//! Key features:
+//! - No set among the cards of a list (all triples re-checked with is_set)
+//! - Header consistency: n = number of cards, max_card = last card, cards ascending
+//! - Remaining cards: ascending, all > max_card, none completing a set with a pair
+//!   of the list, and no compatible card > max_card missing
+//! - Violations reported with file name and offset (index of the list in the file)
+//! - Report saved as nsl_{size:02}_verify_report.json next to the files, with one
+//!   finding (see findings) per unreadable file and invalid list
+//!
+//! Used by --verify mode
+
+use std::path::Path;
+use separator::Separatable;
+use serde::Serialize;
+
+use crate::findings::{Finding, FindingsReport};
+use crate::no_set_list::NoSetListSerialized;
+use crate::set::{is_set, next_to_set};
+use crate::utils::*;
+
+/// Max number of violations kept in the report (all are counted)
+const MAX_REPORTED_VIOLATIONS: usize = 10_000;
+
+/// One invalid list found in a file
+#[derive(Debug, Clone, Serialize)]
+pub struct Violation {
+    pub file: String,
+    pub offset: u64,            // index of the list in the file
+    pub cards: Vec<usize>,
+    pub problems: Vec<String>,
+}
+
+/// Verification report for one size
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifyReport {
+    pub size: u8,
+    pub files_checked: u64,
+    pub lists_checked: u64,
+    pub invalid_lists: u64,
+    pub unreadable_files: Vec<String>,
+    pub violations: Vec<Violation>,     // first MAX_REPORTED_VIOLATIONS only
+}
+
+impl VerifyReport {
+    pub fn is_clean(&self) -> bool {
+        self.invalid_lists == 0 && self.unreadable_files.is_empty()
+    }
+}
+
+/// Check one list against all invariants, returns the problems found (empty if valid)
+pub fn check_list(list: &NoSetListSerialized, expected_size: u8) -> Vec<String> {
+    let mut problems = Vec::new();
+    let cards = &list.no_set_list;
+
+    // Header consistency
+    if list.n != expected_size {
+        problems.push(format!("n = {} (expected {})", list.n, expected_size));
+    }
+    if cards.len() != list.n as usize {
+        problems.push(format!("{} cards stored for n = {}", cards.len(), list.n));
+    }
+    if cards.iter().any(|&c| c >= 81) {
+        problems.push("card index out of range (>= 81)".to_string());
+        return problems;
+    }
+    if !cards.windows(2).all(|w| w[0] < w[1]) {
+        problems.push("cards not strictly ascending".to_string());
+    }
+    if cards.last() != Some(&list.max_card) {
+        problems.push(format!("max_card = {} but last card is {:?}", list.max_card, cards.last()));
+    }
+
+    // No set among the cards
+    let mut forbidden = [false; 81];
+    for i in 0..cards.len() {
+        for j in (i + 1)..cards.len() {
+            forbidden[next_to_set(cards[i], cards[j])] = true;
+            for k in (j + 1)..cards.len() {
+                if is_set(cards[i], cards[j], cards[k]) {
+                    problems.push(format!("set found: ({}, {}, {})", cards[i], cards[j], cards[k]));
+                }
+            }
+        }
+    }
+
+    // Remaining cards: exactly the compatible cards above max_card
+    let remaining = &list.remaining_cards_list;
+    if remaining.iter().any(|&c| c >= 81) {
+        problems.push("remaining card index out of range (>= 81)".to_string());
+        return problems;
+    }
+    if !remaining.windows(2).all(|w| w[0] < w[1]) {
+        problems.push("remaining cards not strictly ascending".to_string());
+    }
+    for &r in remaining.iter() {
+        if r <= list.max_card {
+            problems.push(format!("remaining card {} <= max_card {}", r, list.max_card));
+        } else if forbidden[r] {
+            problems.push(format!("remaining card {} completes a set with the list", r));
+        }
+    }
+    let missing: Vec<usize> = ((list.max_card + 1)..81)
+        .filter(|&c| !forbidden[c] && !remaining.contains(&c))
+        .collect();
+    if !missing.is_empty() {
+        problems.push(format!("compatible cards missing from remaining: {:?}", missing));
+    }
+
+    problems
+}
+
+/// Stream every stored list of `size` in `base_path` and check it
+pub fn verify_size_files(base_path: &str, size: u8) -> std::io::Result<VerifyReport> {
+    test_print(&format!("\nVERIFY MODE: Re-checking every list of size {:02}...", size));
+    test_print(&format!("   Directory: {}", base_path));
+    let start_time = std::time::Instant::now();
+
+    let files = crate::filenames::list_input_files(base_path, size);
+    if files.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound,
+            format!("No size {:02} files found in {}", size, base_path)));
+    }
+
+    let mut report = VerifyReport {
+        size,
+        files_checked: 0,
+        lists_checked: 0,
+        invalid_lists: 0,
+        unreadable_files: Vec::new(),
+        violations: Vec::new(),
+    };
+
+    for file in files.iter() {
+        let name = Path::new(&file.path).file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let lists = match crate::io_helpers::load_lists_cached(&file.path) {
+            Ok(lists) => lists,
+            Err(e) => {
+                test_print(&format!("   ... ERROR: cannot read {}: {}", name, e));
+                report.unreadable_files.push(name);
+                continue;
+            }
+        };
+
+        let mut invalid_in_file = 0u64;
+        for (offset, list) in lists.iter().enumerate() {
+            let problems = check_list(list, size);
+            if !problems.is_empty() {
+                invalid_in_file += 1;
+                if report.violations.len() < MAX_REPORTED_VIOLATIONS {
+                    report.violations.push(Violation {
+                        file: name.clone(),
+                        offset: offset as u64,
+                        cards: list.no_set_list.clone(),
+                        problems,
+                    });
+                }
+            }
+        }
+        report.files_checked += 1;
+        report.lists_checked += lists.len() as u64;
+        report.invalid_lists += invalid_in_file;
+        crate::events::file_processed(crate::events::FileProcessed::new("verify", size,
+            crate::events::FileAction::Verified, name.as_str(), Some(lists.len() as u64)));
+        test_print(&format!("   ... {:>10} lists checked in {} ({} invalid)",
+            lists.len().separated_string(), name, invalid_in_file.separated_string()));
+    }
+
+    test_print(&format!("   ... verified {} lists in {:.2}s",
+        report.lists_checked.separated_string(), start_time.elapsed().as_secs_f64()));
+    Ok(report)
+}
+
+/// Findings of a verification: one error per unreadable file and per invalid list reported
+pub fn verify_findings(report: &VerifyReport) -> Vec<Finding> {
+    let mut findings: Vec<Finding> = report.unreadable_files.iter()
+        .map(|file| Finding::error("unreadable_file", "file could not be read").with_file(file.as_str()))
+        .collect();
+    findings.extend(report.violations.iter().map(|v| Finding::error("invalid_list",
+        format!("list {} {:?}: {}", v.offset, v.cards, v.problems.join("; "))).with_file(v.file.as_str())));
+    let unreported = report.invalid_lists.saturating_sub(report.violations.len() as u64);
+    if unreported > 0 {
+        findings.push(Finding::error("invalid_list", format!("{} more invalid lists not reported", unreported)));
+    }
+    findings
+}
+
+/// Print the verification summary and save it, with its findings, as nsl_{size:02}_verify_report.json
+pub fn save_verify_report(base_path: &str, report: &VerifyReport) -> std::io::Result<FindingsReport<VerifyReport>> {
+    test_print(&format!("\nSize {:02}: {} lists in {} files, {} invalid, {} unreadable files",
+        report.size, report.lists_checked.separated_string(), report.files_checked,
+        report.invalid_lists.separated_string(), report.unreadable_files.len()));
+    for violation in report.violations.iter().take(20) {
+        test_print(&format!("   {} @ {}: {:?} -> {}",
+            violation.file, violation.offset, violation.cards, violation.problems.join("; ")));
+    }
+    if report.violations.len() > 20 {
+        test_print(&format!("   ... and {} more (see report)", (report.invalid_lists - 20).separated_string()));
+    }
+
+    let findings = FindingsReport::new("verify", verify_findings(report), report.clone());
+    findings.save(base_path, &format!("nsl_{:02}_verify_report.json", report.size))?;
+    Ok(findings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seed(i: usize, j: usize, k: usize) -> NoSetListSerialized {
+        let forbidden = [next_to_set(i, j), next_to_set(i, k), next_to_set(j, k)];
+        NoSetListSerialized {
+            n: 3,
+            max_card: k,
+            no_set_list: vec![i, j, k],
+            remaining_cards_list: ((k + 1)..81).filter(|c| !forbidden.contains(c)).collect(),
+        }
+    }
+
+    #[test]
+    fn valid_seed_has_no_problem() {
+        assert!(check_list(&seed(0, 1, 3), 3).is_empty());
+    }
+
+    #[test]
+    fn corrupted_lists_are_reported() {
+        // (0, 1, 2) is a set
+        let set_list = seed(0, 1, 2);
+        assert!(check_list(&set_list, 3).iter().any(|p| p.starts_with("set found")));
+
+        // A forbidden card in the remaining cards, and a compatible one dropped
+        let mut list = seed(0, 1, 3);
+        let dropped = list.remaining_cards_list.remove(0);
+        list.remaining_cards_list.push(next_to_set(0, 3).max(next_to_set(1, 3)));
+        list.remaining_cards_list.sort();
+        let problems = check_list(&list, 3);
+        assert!(problems.iter().any(|p| p.contains("completes a set")), "{:?}", problems);
+        assert!(problems.iter().any(|p| p.contains(&format!("[{}]", dropped))), "{:?}", problems);
+    }
+}
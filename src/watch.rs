@@ -0,0 +1,173 @@
+//! Watch module: detect input batches as they appear in a directory
+//!
+//! Input batches are often copied in over the network while the next size is
+//! being computed. The watcher polls the input directory and hands out the
+//! files that are complete and not processed yet; --watch processes each one
+//! in unitary style.
+//!
+//! Key features:
+//! - Processed files read from the output state (consumed inputs, compacted and
+//!   regular apart) and, for the batch numbers it records no input of, from the
+//!   source batches of its entries and history
+//! - A file is ready once its size and mtime are unchanged between two polls and
+//!   its header is readable (a file still being copied is left for later)
+//! - Files handed out in batch order, each by path (a regular file is not shadowed
+//!   by a compacted file of the same batch number)
+//! - Stopped cleanly by creating funny_watch.stop in the output directory
+//!
+//! Used by --watch mode
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::file_info::{GlobalFileState, InputKey};
+use crate::filenames::InputFile;
+use crate::utils::*;
+
+/// File whose presence in the output directory stops the watch
+pub const WATCH_STOP_FILE: &str = "funny_watch.stop";
+
+/// Input files of a directory, tracked between polls: compacted and regular files
+/// are numbered apart, so they are told apart by their InputKey
+pub struct WatchTracker {
+    input_dir: String,
+    input_size: u8,
+    state: GlobalFileState,         // output state: the inputs consumed before the watch
+    history_sources: BTreeSet<u32>, // source batches of the history entries
+    seen: BTreeMap<InputKey, (u64, Option<SystemTime>)>, // input -> (bytes, mtime) at the last poll
+    done: BTreeSet<InputKey>,
+    failed: BTreeSet<InputKey>,
+}
+
+fn key_of(file: &InputFile) -> InputKey {
+    InputKey::whole(file.batch, file.compacted)
+}
+
+impl WatchTracker {
+    /// Track the `input_size` files of `input_dir`, skipping those recorded in the state of `output_dir`
+    pub fn new(input_dir: &str, output_dir: &str, input_size: u8) -> std::io::Result<Self> {
+        let state = GlobalFileState::from_sources(output_dir, input_size + 1)?;
+        let history_sources = GlobalFileState::from_history_file(output_dir, input_size + 1, "rkyv")
+            .map(|history| history.entries().values().map(|e| e.source_batch).collect())
+            .unwrap_or_default();
+        Ok(Self {
+            input_dir: input_dir.to_string(),
+            input_size,
+            state,
+            history_sources,
+            seen: BTreeMap::new(),
+            done: BTreeSet::new(),
+            failed: BTreeSet::new(),
+        })
+    }
+
+    /// True if `key` was processed: during the watch, or before it (consumed inputs
+    /// of the state, source batches of its entries and history)
+    fn is_done(&self, key: InputKey) -> bool {
+        self.done.contains(&key)
+            || !self.state.consumed_among(&BTreeSet::from([key]), &self.history_sources).is_empty()
+    }
+
+    /// Number of input files on disk already processed
+    pub fn nb_done(&self) -> usize {
+        crate::filenames::list_input_files(&self.input_dir, self.input_size).iter()
+            .filter(|f| self.is_done(key_of(f)))
+            .count()
+    }
+
+    /// Input files complete on disk and not processed yet, in batch order
+    pub fn ready_inputs(&mut self) -> Vec<InputFile> {
+        let mut ready: Vec<InputFile> = Vec::new();
+        for file in crate::filenames::list_input_files(&self.input_dir, self.input_size) {
+            let key = key_of(&file);
+            if self.is_done(key) || self.failed.contains(&key) {
+                continue;
+            }
+            let metadata = match std::fs::metadata(crate::storage::resolve_path(&file.path)) {
+                Ok(m) => m,
+                Err(_) => continue,
+            };
+            let current = (metadata.len(), metadata.modified().ok());
+            let stable = self.seen.get(&key) == Some(&current);
+            self.seen.insert(key, current);
+            if stable && crate::io_helpers::count_lists_in_file(&file.path).is_ok() && !ready.iter().any(|f| key_of(f) == key) {
+                ready.push(file);
+            }
+        }
+        ready.sort_by_key(|f| (f.batch, !f.compacted));
+        ready
+    }
+
+    pub fn mark_done(&mut self, input: &InputFile) {
+        self.done.insert(key_of(input));
+    }
+
+    /// Input whose processing failed: not handed out again
+    pub fn mark_failed(&mut self, input: &InputFile) {
+        self.failed.insert(key_of(input));
+    }
+}
+
+/// True if the stop file exists in `output_dir` (it is removed)
+pub fn stop_requested(output_dir: &str) -> bool {
+    let path = Path::new(output_dir).join(WATCH_STOP_FILE);
+    if path.exists() {
+        test_print(&format!("   ... {} found: stopping the watch", path.display()));
+        let _ = std::fs::remove_file(&path);
+        return true;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::no_set_list::NoSetListSerialized;
+
+    #[test]
+    fn batches_are_ready_once_stable_and_handed_out_once() {
+        let dir = crate::test_dir::TestDir::new("watch");
+        let dir_str = dir.to_string_lossy().into_owned();
+
+        let list = NoSetListSerialized { n: 4, max_card: 5, no_set_list: vec![0, 1, 3, 5], remaining_cards_list: vec![6] };
+        let file = crate::filenames::output_filename(&dir_str, 3, 0, 4, 2);
+        assert!(crate::io_helpers::save_to_file_serialized(&vec![list], &file));
+
+        let mut tracker = WatchTracker::new(&dir_str, &dir_str, 4).expect("tracker");
+        assert!(tracker.ready_inputs().is_empty()); // first sighting
+        let ready = tracker.ready_inputs();
+        assert_eq!(ready.iter().map(|f| f.batch).collect::<Vec<_>>(), vec![2]);
+        tracker.mark_done(&ready[0]);
+        assert!(tracker.ready_inputs().is_empty());
+
+        std::fs::write(dir.join(WATCH_STOP_FILE), "").expect("stop file");
+        assert!(stop_requested(&dir_str));
+        assert!(!stop_requested(&dir_str));
+    }
+
+    #[test]
+    fn a_regular_input_sharing_the_batch_of_a_consumed_compacted_one_is_handed_out() {
+        let input = crate::test_dir::TestDir::new("watch_kinds_in");
+        let output = crate::test_dir::TestDir::new("watch_kinds_out");
+        let (input_str, output_str) = (input.to_string_lossy().into_owned(), output.to_string_lossy().into_owned());
+
+        let list = NoSetListSerialized { n: 4, max_card: 5, no_set_list: vec![0, 1, 3, 5], remaining_cards_list: vec![6] };
+        let compacted = input.join("nsl_03_batch_000000_to_04_batch_000002_compacted.rkyv");
+        assert!(crate::io_helpers::save_to_file_serialized(&vec![list.clone()], &compacted.to_string_lossy()));
+        let regular = crate::filenames::output_filename(&input_str, 3, 5, 4, 2);
+        assert!(crate::io_helpers::save_to_file_serialized(&vec![list], &regular));
+
+        let mut state = GlobalFileState::new(&output_str, 5);
+        state.register_file("nsl_04_batch_000002_to_05_batch_000000.rkyv", 2, 0, 1, false, None, None);
+        state.record_consumed_input(4, InputKey::whole(2, true), 1);
+        state.flush().expect("flush");
+
+        let mut tracker = WatchTracker::new(&input_str, &output_str, 4).expect("tracker");
+        assert_eq!(tracker.nb_done(), 1);
+        assert!(tracker.ready_inputs().is_empty()); // first sighting
+        let ready = tracker.ready_inputs();
+        assert_eq!(ready.len(), 1);
+        assert_eq!((ready[0].batch, ready[0].compacted, ready[0].path.as_str()), (2, false, regular.as_str()));
+    }
+}